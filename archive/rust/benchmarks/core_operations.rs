@@ -1,8 +1,9 @@
 use base::core::{
     file_system::{delete_files_by_extensions_core, get_files_by_extension_core},
-    image_converter::convert_image_batch_core,
+    image_converter::{convert_image_batch_core, resize_options},
     image_merger::{
-        merge_images_grid_core, merge_images_horizontal_core, merge_images_vertical_core,
+        merge_images_grid_core, merge_images_horizontal_core, merge_images_vertical_core, CellMode,
+        MergeOutput,
     },
 };
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
@@ -69,6 +70,7 @@ fn bench_file_scanning(c: &mut Criterion) {
 /// Benchmark image conversion (single and batch)
 fn bench_image_conversion(c: &mut Criterion) {
     let mut group = c.benchmark_group("image_conversion");
+    let resize = resize_options(None).unwrap();
     group.sample_size(50);
 
     let dir = tempdir().unwrap();
@@ -100,6 +102,10 @@ fn bench_image_conversion(c: &mut Criterion) {
                         black_box(false),
                         black_box(None),
                         black_box("crop"),
+                        black_box(true),
+                        black_box(&resize),
+                        black_box(None),
+                        &[],
                     )
                 });
             },
@@ -126,6 +132,10 @@ fn bench_image_conversion(c: &mut Criterion) {
                 black_box(false),
                 black_box(None),
                 black_box("crop"),
+                black_box(true),
+                black_box(&resize),
+                black_box(None),
+                &[],
             )
         });
     });
@@ -136,6 +146,7 @@ fn bench_image_conversion(c: &mut Criterion) {
 /// Benchmark image merge operations
 fn bench_image_merge(c: &mut Criterion) {
     let mut group = c.benchmark_group("image_merge");
+    let resize = resize_options(None).unwrap();
     group.sample_size(50);
 
     let dir = tempdir().unwrap();
@@ -158,6 +169,8 @@ fn bench_image_merge(c: &mut Criterion) {
                     black_box(output_path.to_str().unwrap()),
                     black_box(0),
                     black_box("center"),
+                    black_box(&resize),
+                    black_box(&MergeOutput::default()),
                 )
             });
         });
@@ -169,6 +182,7 @@ fn bench_image_merge(c: &mut Criterion) {
                     black_box(output_path.to_str().unwrap()),
                     black_box(0),
                     black_box("center"),
+                    black_box(&MergeOutput::default()),
                 )
             });
         });
@@ -182,6 +196,10 @@ fn bench_image_merge(c: &mut Criterion) {
                     black_box(side),
                     black_box(side),
                     black_box(0),
+                    black_box(CellMode::Fit),
+                    black_box(&resize),
+                    black_box(None),
+                    black_box(&MergeOutput::default()),
                 )
             });
         });
@@ -1,10 +1,64 @@
+use crate::web::clients::downloader::unique_path_by;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Local};
 #[cfg(feature = "python")]
+use pyo3::exceptions::PyValueError;
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
 use rayon::prelude::*;
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
-use walkdir::WalkDir;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use walkdir::{DirEntry, WalkDir};
+
+/// Placeholders `organize_files_core` fills in
+const ORGANIZE_PLACEHOLDERS: [&str; 7] = ["year", "month", "day", "stem", "ext", "width", "height"];
+
+/// Size and modification time bounds for a file scan, and how many files it
+/// may return. Times are Unix timestamps in seconds; every bound is inclusive.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanFilter {
+    pub min_size_bytes: Option<u64>,
+    pub max_size_bytes: Option<u64>,
+    pub modified_after: Option<f64>,
+    pub modified_before: Option<f64>,
+    pub max_results: Option<usize>,
+}
+
+impl ScanFilter {
+    /// Whether a file with `metadata` is within the size and time bounds
+    pub fn matches(&self, metadata: &fs::Metadata) -> bool {
+        let size = metadata.len();
+        if self.min_size_bytes.is_some_and(|min| size < min)
+            || self.max_size_bytes.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+        if self.modified_after.is_none() && self.modified_before.is_none() {
+            return true;
+        }
+        let Ok(modified) = metadata.modified() else {
+            return false;
+        };
+        let modified = match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+        !(self.modified_after.is_some_and(|after| modified < after)
+            || self.modified_before.is_some_and(|before| modified > before))
+    }
+
+    /// `matches` for a walked entry, reading its metadata only if a bound
+    /// needs it
+    pub fn matches_entry(&self, entry: &DirEntry) -> bool {
+        let unbounded = self.min_size_bytes.is_none()
+            && self.max_size_bytes.is_none()
+            && self.modified_after.is_none()
+            && self.modified_before.is_none();
+        unbounded || entry.metadata().is_ok_and(|m| self.matches(&m))
+    }
+}
 
 // Core (non-Python) helper for reuse by Tauri and other Rust callers.
 pub fn get_files_by_extension_core(
@@ -74,6 +128,120 @@ pub fn delete_path_core(path: &str) -> bool {
     }
 }
 
+/// The placeholder names in `pattern`, or an error for an unknown or
+/// unclosed one
+fn pattern_placeholders(pattern: &str) -> Result<Vec<&str>> {
+    let mut names = Vec::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let len = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed placeholder in pattern: {}", pattern))?;
+        let name = &rest[start + 1..start + len];
+        if !ORGANIZE_PLACEHOLDERS.contains(&name) {
+            return Err(anyhow!("Unknown placeholder {{{}}} in pattern", name));
+        }
+        names.push(name);
+        rest = &rest[start + len + 1..];
+    }
+    Ok(names)
+}
+
+/// `pattern` filled in for the file at `path`
+fn render_pattern(pattern: &str, path: &Path) -> Result<String> {
+    let names = pattern_placeholders(pattern)?;
+    let modified: DateTime<Local> = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("Failed to read modification time of {}", path.display()))?
+        .into();
+    // Only the header is read, and only if the pattern needs it
+    let dims = if names.contains(&"width") || names.contains(&"height") {
+        Some(
+            image::image_dimensions(path)
+                .with_context(|| format!("Failed to read dimensions of {}", path.display()))?,
+        )
+    } else {
+        None
+    };
+    let part = |part: Option<&std::ffi::OsStr>| {
+        part.map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    let mut rendered = pattern.to_string();
+    for name in names {
+        let value = match name {
+            "year" => modified.year().to_string(),
+            "month" => format!("{:02}", modified.month()),
+            "day" => format!("{:02}", modified.day()),
+            "stem" => part(path.file_stem()),
+            "ext" => part(path.extension()),
+            "width" => dims.unwrap_or_default().0.to_string(),
+            _ => dims.unwrap_or_default().1.to_string(),
+        };
+        rendered = rendered.replacen(&format!("{{{}}}", name), &value, 1);
+    }
+    Ok(rendered)
+}
+
+/// Rename, or copy and delete when `dst` is on another file system
+fn move_file(src: &Path, dst: &Path) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    if fs::rename(src, dst).is_err() {
+        fs::copy(src, dst).with_context(|| format!("Failed to copy to {}", dst.display()))?;
+        fs::remove_file(src).with_context(|| format!("Failed to remove {}", src.display()))?;
+    }
+    Ok(())
+}
+
+/// Move each of `paths` to `dest_dir/pattern`, where `pattern` (such as
+/// `{year}/{month}/{stem}_{width}x{height}.{ext}`) is filled in from the
+/// file's modification time, name and image dimensions. Taken destinations
+/// get a " (n)" suffix as downloads do. Returns the `(src, dst)` moves made,
+/// or with `dry_run` the ones that would be, without touching the disk.
+/// Files whose placeholders can't be filled, or that fail to move, are left
+/// where they are.
+pub fn organize_files_core(
+    paths: &[String],
+    dest_dir: &str,
+    pattern: &str,
+    dry_run: bool,
+) -> Result<Vec<(String, String)>> {
+    pattern_placeholders(pattern)?;
+    let dest_dir = Path::new(dest_dir);
+    let rendered: Vec<Option<PathBuf>> = paths
+        .par_iter()
+        .map(|path| render_pattern(pattern, Path::new(path)).ok())
+        .map(|name| name.map(|name| dest_dir.join(name)))
+        .collect();
+
+    // Sequentially, so two files can't be given the same destination
+    let mut planned = HashSet::new();
+    let mut moves = Vec::new();
+    for (src, dst) in paths.iter().zip(rendered) {
+        let Some(dst) = dst else { continue };
+        let src_path = Path::new(src);
+        if dst == src_path {
+            continue;
+        }
+        let (Some(dir), Some(name)) = (dst.parent(), dst.file_name()) else {
+            continue;
+        };
+        let dst = unique_path_by(dir, &name.to_string_lossy(), |candidate| {
+            planned.contains(candidate) || (candidate.exists() && candidate != src_path)
+        });
+        if dst == src_path || (!dry_run && move_file(src_path, &dst).is_err()) {
+            continue;
+        }
+        moves.push((src.clone(), dst.to_string_lossy().to_string()));
+        planned.insert(dst);
+    }
+    Ok(moves)
+}
+
 #[cfg(feature = "python")]
 #[pyfunction]
 pub fn get_files_by_extension(
@@ -105,6 +273,23 @@ pub fn delete_path(py: Python, path: String) -> PyResult<bool> {
     Ok(res)
 }
 
+/// Move `paths` into `dest_dir` following `pattern`; see
+/// `organize_files_core`. Returns the `(src, dst)` moves made, or planned
+/// with `dry_run`.
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (paths, dest_dir, pattern, dry_run=false))]
+pub fn organize_files(
+    py: Python,
+    paths: Vec<String>,
+    dest_dir: String,
+    pattern: String,
+    dry_run: bool,
+) -> PyResult<Vec<(String, String)>> {
+    py.detach(|| organize_files_core(&paths, &dest_dir, &pattern, dry_run))
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
 #[cfg(all(test, feature = "python"))]
 mod tests {
     use super::*;
@@ -192,4 +377,87 @@ mod tests {
             assert!(f3.exists());
         });
     }
+
+    #[test]
+    fn test_organize_files() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        let dest = dir.path().join("dest");
+        fs::create_dir_all(src.join("other")).unwrap();
+        // Mid-month and midday, so the local date is the same in any time zone
+        let june = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_623_758_400);
+        let mut paths = Vec::new();
+        for (name, w, h) in [("a.png", 4, 2), ("other/a.png", 4, 2), ("b.png", 3, 5)] {
+            let path = src.join(name);
+            image::RgbImage::new(w, h).save(&path).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(june)
+                .unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        }
+        let note = src.join("notes.txt");
+        fs::write(&note, "not an image").unwrap();
+        paths.push(note.to_string_lossy().to_string());
+        // Already taken before organizing
+        fs::create_dir_all(dest.join("2021/06")).unwrap();
+        fs::write(dest.join("2021/06/b_3x5.png"), "taken").unwrap();
+
+        let pattern = "{year}/{month}/{stem}_{width}x{height}.{ext}";
+        let dest_str = dest.to_str().unwrap();
+        let expected: Vec<(String, String)> = [
+            (&paths[0], "2021/06/a_4x2.png"),
+            (&paths[1], "2021/06/a_4x2 (1).png"),
+            (&paths[2], "2021/06/b_3x5 (1).png"),
+        ]
+        .iter()
+        .map(|(src, dst)| {
+            (
+                src.to_string(),
+                dest.join(dst).to_string_lossy().to_string(),
+            )
+        })
+        .collect();
+
+        let planned = organize_files_core(&paths, dest_str, pattern, true).unwrap();
+        assert_eq!(planned, expected);
+        assert!(paths.iter().all(|p| Path::new(p).exists()));
+        assert!(!dest.join("2021/06/a_4x2.png").exists());
+
+        let moved = organize_files_core(&paths, dest_str, pattern, false).unwrap();
+        assert_eq!(moved, expected);
+        for (src, dst) in &moved {
+            assert!(!Path::new(src).exists());
+            assert!(Path::new(dst).exists());
+        }
+        assert!(note.exists());
+
+        // Files already where the pattern puts them stay; bad patterns are
+        // refused up front
+        let organized: Vec<String> = moved.into_iter().map(|(_, dst)| dst).collect();
+        let month_dir = dest.join("2021/06");
+        assert!(organize_files_core(
+            &organized,
+            month_dir.to_str().unwrap(),
+            "{stem}.{ext}",
+            false
+        )
+        .unwrap()
+        .is_empty());
+        Python::initialize();
+        Python::attach(|py| {
+            for bad in ["{stem}_{camera}.{ext}", "{stem.{ext}"] {
+                let res = organize_files(
+                    py,
+                    organized.clone(),
+                    dest_str.to_string(),
+                    bad.to_string(),
+                    true,
+                );
+                assert!(res.is_err(), "{}", bad);
+            }
+        });
+    }
 }
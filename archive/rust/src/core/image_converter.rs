@@ -1,6 +1,13 @@
+use super::image_merger::parse_background;
+use super::text;
 use anyhow::{anyhow, Result};
 use fast_image_resize as fr;
-use image::{DynamicImage, ImageFormat, ImageReader};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPDecoder;
+use image::metadata::LoopCount;
+use image::{AnimationDecoder, DynamicImage, Frame, ImageDecoder, ImageFormat, ImageReader};
 #[cfg(feature = "python")]
 use pyo3::exceptions::PyValueError;
 #[cfg(feature = "python")]
@@ -8,8 +15,14 @@ use pyo3::prelude::*;
 use rayon::prelude::*;
 use std::fs;
 
-// Helper function to load image
-fn load_image(path: &str) -> Result<DynamicImage> {
+/// Load the image at `path`, turned upright as its EXIF orientation says
+/// unless `auto_orient` is false
+pub fn load_image(path: &str, auto_orient: bool) -> Result<DynamicImage> {
+    // The image crate can't read HEIC, which iPhones save by default
+    if is_heic(&read_header(path)) {
+        return load_heic(path, auto_orient);
+    }
+
     let reader = ImageReader::open(path)
         .map_err(|e| anyhow!("Failed to open file [{}]: {}", path, e))?;
     
@@ -17,43 +30,359 @@ fn load_image(path: &str) -> Result<DynamicImage> {
     let reader = reader.with_guessed_format()
         .map_err(|e| anyhow!("Failed to identify format for [{}]: {}", path, e))?;
 
-    reader.decode().map_err(|e| {
-        // Diagnostic: Read first 12 bytes to see RIFF/WEBP signature
-        let mut header = vec![0u8; 12];
-        let diag_info = if let Ok(mut f) = std::fs::File::open(path) {
-            use std::io::Read;
-            if f.read_exact(&mut header).is_ok() {
-                format!("Header bytes: {:?}", header)
-            } else {
-                "Could not read header".to_string()
-            }
+    let mut decoder = reader.into_decoder().map_err(|e| decode_error(path, e))?;
+    // Decoders don't rotate on their own; phone photos are often stored sideways
+    let orientation = if auto_orient {
+        decoder.orientation().ok()
+    } else {
+        None
+    };
+    let mut img = DynamicImage::from_decoder(decoder).map_err(|e| decode_error(path, e))?;
+    if let Some(orientation) = orientation {
+        img.apply_orientation(orientation);
+    }
+    Ok(img)
+}
+
+fn decode_error(path: &str, e: image::ImageError) -> anyhow::Error {
+    // Diagnostic: Read first 12 bytes to see RIFF/WEBP signature
+    let mut header = vec![0u8; 12];
+    let diag_info = if let Ok(mut f) = std::fs::File::open(path) {
+        use std::io::Read;
+        if f.read_exact(&mut header).is_ok() {
+            format!("Header bytes: {:?}", header)
         } else {
-            "Could not open for diagnostic".to_string()
-        };
-        
-        anyhow!("Failed to decode image [{}]: {}. Diag: {}", path, e, diag_info)
-    })
-}
-
-// Helper to save image
-fn save_image(img: &DynamicImage, output_path: &str, format: &str) -> Result<()> {
-    let fmt = match format.to_lowercase().as_str() {
-        "png" => ImageFormat::Png,
-        "jpg" | "jpeg" => ImageFormat::Jpeg,
-        "webp" => ImageFormat::WebP,
-        "bmp" => ImageFormat::Bmp,
-        "ico" => ImageFormat::Ico,
-        "tiff" => ImageFormat::Tiff,
-        _ => {
-            return Err(anyhow!("Unsupported format: {}", format));
+            "Could not read header".to_string()
         }
+    } else {
+        "Could not open for diagnostic".to_string()
     };
+    
+    anyhow!("Failed to decode image [{}]: {}. Diag: {}", path, e, diag_info)
+}
+
+/// Brands in an ISO media `ftyp` box that mark HEVC-coded HEIF images
+const HEIC_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"hevm", b"hevs",
+];
+
+fn read_header(path: &str) -> Vec<u8> {
+    use std::io::Read;
+    let mut header = Vec::with_capacity(64);
+    if let Ok(f) = fs::File::open(path) {
+        let _ = f.take(64).read_to_end(&mut header);
+    }
+    header
+}
+
+/// Whether `header`, the start of a file, is HEIC: an `ftyp` box whose major
+/// or compatible brands include a HEVC one
+fn is_heic(header: &[u8]) -> bool {
+    if header.len() < 12 || &header[4..8] != b"ftyp" {
+        return false;
+    }
+    let box_end = (u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize)
+        .min(header.len());
+    // Major brand, then a minor version, then the compatible brands
+    let compatible = header.get(16..box_end).unwrap_or_default();
+    std::iter::once(&header[8..12])
+        .chain(compatible.chunks_exact(4))
+        .any(|brand| HEIC_BRANDS.iter().any(|heic| &heic[..] == brand))
+}
+
+#[cfg(feature = "heic")]
+fn load_heic(path: &str, auto_orient: bool) -> Result<DynamicImage> {
+    use image::RgbaImage;
+    use libheif_rs::{ColorSpace, DecodingOptions, HeifContext, LibHeif, RgbChroma};
+
+    let error = |e: libheif_rs::HeifError| anyhow!("Failed to decode HEIC image [{}]: {}", path, e);
+    let ctx = HeifContext::read_from_file(path).map_err(error)?;
+    let handle = ctx.primary_image_handle().map_err(error)?;
+    // libheif applies the rotation and mirroring stored in the file itself
+    let mut options = DecodingOptions::new();
+    if let Some(options) = options.as_mut() {
+        options.set_ignore_transformations(!auto_orient);
+    }
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), options)
+        .map_err(error)?;
+
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or_else(|| anyhow!("Failed to decode HEIC image [{}]: no RGBA plane", path))?;
+    let row = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row * plane.height as usize);
+    for y in 0..plane.height as usize {
+        pixels.extend_from_slice(&plane.data[y * plane.stride..][..row]);
+    }
+    RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| anyhow!("Failed to decode HEIC image [{}]: bad plane size", path))
+}
+
+#[cfg(not(feature = "heic"))]
+fn load_heic(path: &str, _auto_orient: bool) -> Result<DynamicImage> {
+    Err(anyhow!(
+        "HEIC support not enabled: cannot open [{}] without the `heic` feature",
+        path
+    ))
+}
+
+/// Encoder speed for AVIF output with a quality, as the image crate's default
+const AVIF_SPEED: u8 = 4;
+
+/// The format written for a name such as `png` or `jpeg`, in any case
+pub fn image_format(name: &str) -> Result<ImageFormat> {
+    match name.to_lowercase().as_str() {
+        "png" => Ok(ImageFormat::Png),
+        "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+        "webp" => Ok(ImageFormat::WebP),
+        "bmp" => Ok(ImageFormat::Bmp),
+        "ico" => Ok(ImageFormat::Ico),
+        "tiff" | "tif" => Ok(ImageFormat::Tiff),
+        "avif" => Ok(ImageFormat::Avif),
+        "gif" => Ok(ImageFormat::Gif),
+        _ => Err(anyhow!("Unsupported format: {}", name)),
+    }
+}
+
+/// Save `img` as `format` (see `image_format`); `quality` (1-100) applies to
+/// JPEG and AVIF and makes WebP lossy. JPEG and BMP have no alpha channel, so
+/// it is dropped for them.
+pub fn save_image(
+    img: &DynamicImage,
+    output_path: &str,
+    format: &str,
+    quality: Option<u8>,
+) -> Result<()> {
+    let fmt = image_format(format)?;
+    let flattened;
+    let img = if matches!(fmt, ImageFormat::Jpeg | ImageFormat::Bmp) && img.color().has_alpha() {
+        flattened = DynamicImage::ImageRgb8(img.to_rgb8());
+        &flattened
+    } else {
+        img
+    };
+
+    match (fmt, quality.map(|q| q.clamp(1, 100))) {
+        (ImageFormat::Jpeg, Some(q)) => {
+            let file = fs::File::create(output_path)
+                .map_err(|e| anyhow!("Failed to save image: {}", e))?;
+            let encoder = JpegEncoder::new_with_quality(std::io::BufWriter::new(file), q);
+            img.write_with_encoder(encoder)
+                .map_err(|e| anyhow!("Failed to save image: {}", e))
+        }
+        (ImageFormat::Avif, Some(q)) => {
+            let file = fs::File::create(output_path)
+                .map_err(|e| anyhow!("Failed to save image: {}", e))?;
+            let encoder =
+                AvifEncoder::new_with_speed_quality(std::io::BufWriter::new(file), AVIF_SPEED, q);
+            img.write_with_encoder(encoder)
+                .map_err(|e| anyhow!("Failed to save image: {}", e))
+        }
+        (ImageFormat::WebP, Some(q)) => {
+            // The image crate only writes lossless WebP
+            let rgba = img.to_rgba8();
+            let encoded =
+                webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(q as f32);
+            fs::write(output_path, &*encoded).map_err(|e| anyhow!("Failed to save image: {}", e))
+        }
+        _ => img
+            .save_with_format(output_path, fmt)
+            .map_err(|e| anyhow!("Failed to save image: {}", e)),
+    }
+}
+
+/// The frames of an animated GIF or WebP, each composited to full size
+struct Animation {
+    frames: Vec<Frame>,
+    loop_count: LoopCount,
+}
+
+impl Animation {
+    /// Run `f` over every frame's image, keeping the delays
+    fn map_frames(self, f: impl Fn(DynamicImage) -> Result<DynamicImage>) -> Result<Animation> {
+        let frames = self
+            .frames
+            .into_iter()
+            .map(|frame| {
+                let delay = frame.delay();
+                let img = f(DynamicImage::ImageRgba8(frame.into_buffer()))?;
+                Ok(Frame::from_parts(img.into_rgba8(), 0, 0, delay))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Animation {
+            frames,
+            loop_count: self.loop_count,
+        })
+    }
+}
+
+/// `path`'s frames if it is an animated GIF or WebP, `None` for anything
+/// else, single-frame GIFs included
+fn load_animation(path: &str) -> Result<Option<Animation>> {
+    let reader = ImageReader::open(path)
+        .map_err(|e| anyhow!("Failed to open file [{}]: {}", path, e))?
+        .with_guessed_format()
+        .map_err(|e| anyhow!("Failed to identify format for [{}]: {}", path, e))?;
+    let (frames, loop_count) = match reader.format() {
+        Some(ImageFormat::Gif) => {
+            let decoder =
+                GifDecoder::new(reader.into_inner()).map_err(|e| decode_error(path, e))?;
+            let loop_count = decoder.loop_count();
+            (decoder.into_frames().collect_frames(), loop_count)
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder =
+                WebPDecoder::new(reader.into_inner()).map_err(|e| decode_error(path, e))?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            let loop_count = decoder.loop_count();
+            (decoder.into_frames().collect_frames(), loop_count)
+        }
+        _ => return Ok(None),
+    };
+    let frames = frames.map_err(|e| decode_error(path, e))?;
+    Ok((frames.len() > 1).then_some(Animation { frames, loop_count }))
+}
+
+/// Frame `index` of `path`; a still image only has frame 0
+fn load_frame(path: &str, index: usize, auto_orient: bool) -> Result<DynamicImage> {
+    let Some(animation) = load_animation(path)? else {
+        if index == 0 {
+            return load_image(path, auto_orient);
+        }
+        return Err(anyhow!(
+            "Frame {} out of range: [{}] has 1 frame",
+            index,
+            path
+        ));
+    };
+    let count = animation.frames.len();
+    animation
+        .frames
+        .into_iter()
+        .nth(index)
+        .map(|frame| DynamicImage::ImageRgba8(frame.into_buffer()))
+        .ok_or_else(|| {
+            anyhow!(
+                "Frame {} out of range: [{}] has {} frames",
+                index,
+                path,
+                count
+            )
+        })
+}
+
+fn supports_animation(format: &str) -> bool {
+    matches!(format.to_lowercase().as_str(), "gif" | "webp")
+}
+
+// Helper to save every frame of `animation` as an animated GIF or WebP;
+// `quality` makes WebP lossy as in `save_image`
+fn save_animation(
+    animation: Animation,
+    output_path: &str,
+    format: &str,
+    quality: Option<u8>,
+) -> Result<()> {
+    let save_error = |e: &dyn std::fmt::Display| anyhow!("Failed to save image: {}", e);
+    match format.to_lowercase().as_str() {
+        "gif" => {
+            let file = fs::File::create(output_path).map_err(|e| save_error(&e))?;
+            let mut encoder = GifEncoder::new(std::io::BufWriter::new(file));
+            let repeat = match animation.loop_count {
+                LoopCount::Infinite => Repeat::Infinite,
+                LoopCount::Finite(n) => Repeat::Finite(n.get().min(u16::MAX as u32) as u16),
+            };
+            encoder.set_repeat(repeat).map_err(|e| save_error(&e))?;
+            encoder
+                .encode_frames(animation.frames)
+                .map_err(|e| save_error(&e))
+        }
+        "webp" => {
+            let mut config = webp::WebPConfig::new()
+                .map_err(|_| anyhow!("Failed to save image: no WebP encoder config"))?;
+            match quality {
+                Some(q) => config.quality = q.clamp(1, 100) as f32,
+                None => config.lossless = 1,
+            }
+            let (width, height) = animation.frames[0].buffer().dimensions();
+            let mut encoder = webp::AnimEncoder::new(width, height, &config);
+            encoder.set_loop_count(match animation.loop_count {
+                LoopCount::Infinite => 0,
+                LoopCount::Finite(n) => n.get().min(i32::MAX as u32) as i32,
+            });
+            // Frames are placed by start time rather than delay
+            let mut timestamp = 0;
+            for frame in &animation.frames {
+                encoder.add_frame(webp::AnimFrame::from_rgba(
+                    frame.buffer(),
+                    width,
+                    height,
+                    timestamp,
+                ));
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                timestamp += (numer / denom.max(1)) as i32;
+            }
+            let encoded = encoder
+                .try_encode()
+                .map_err(|e| anyhow!("Failed to save image: {:?}", e))?;
+            let mut bytes = encoded.to_vec();
+            end_animation_at(&mut bytes, timestamp as u32);
+            fs::write(output_path, &bytes).map_err(|e| save_error(&e))
+        }
+        _ => Err(anyhow!("Format {} cannot hold an animation", format)),
+    }
+}
 
-    img.save_with_format(output_path, fmt)
-        .map_err(|e| anyhow!("Failed to save image: {}", e))
+/// Stretch the last frame of an encoded animated WebP to end at `end_ms`.
+/// libwebp is never told when the animation ends, so it gives the last frame
+/// the average delay.
+fn end_animation_at(webp: &mut [u8], end_ms: u32) {
+    // RIFF header, then chunks: fourcc, little-endian size, even-padded data
+    let mut offset = 12;
+    let mut elapsed = 0;
+    let mut last = None;
+    while let Some(header) = webp.get(offset..offset + 8) {
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        // ANMF data: x, y, width - 1, height - 1, then the 24-bit duration
+        if &header[..4] == b"ANMF" && webp.len() >= offset + 23 {
+            let duration = offset + 20;
+            if let Some(previous) = last.replace(duration) {
+                elapsed +=
+                    u32::from_le_bytes([webp[previous], webp[previous + 1], webp[previous + 2], 0]);
+            }
+        }
+        offset += 8 + size + size % 2;
+    }
+    if let Some(duration) = last {
+        let remaining = end_ms.saturating_sub(elapsed).min(0xFF_FFFF).to_le_bytes();
+        webp[duration..duration + 3].copy_from_slice(&remaining[..3]);
+    }
 }
 
-fn resize_image(img: &DynamicImage, new_w: u32, new_h: u32) -> Result<DynamicImage> {
+/// Resize options for a filter name: `nearest`, `bilinear`, `lanczos3` or
+/// `catmullrom`. Without one, Lanczos3 as before.
+pub fn resize_options(filter: Option<&str>) -> Result<fr::ResizeOptions> {
+    let alg = match filter.map(str::to_lowercase).as_deref() {
+        None | Some("lanczos3") => fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3),
+        Some("nearest") => fr::ResizeAlg::Nearest,
+        Some("bilinear") => fr::ResizeAlg::Convolution(fr::FilterType::Bilinear),
+        Some("catmullrom") => fr::ResizeAlg::Convolution(fr::FilterType::CatmullRom),
+        Some(other) => return Err(anyhow!("Unsupported resize filter: {}", other)),
+    };
+    Ok(fr::ResizeOptions::new().resize_alg(alg))
+}
+
+fn resize_image(
+    img: &DynamicImage,
+    new_w: u32,
+    new_h: u32,
+    options: &fr::ResizeOptions,
+) -> Result<DynamicImage> {
     let width = img.width();
     let height = img.height();
 
@@ -70,7 +399,7 @@ fn resize_image(img: &DynamicImage, new_w: u32, new_h: u32) -> Result<DynamicIma
     let mut resizer = fr::Resizer::new();
 
     resizer
-        .resize(&src_image, &mut dst_image, None)
+        .resize(&src_image, &mut dst_image, options)
         .map_err(|e| anyhow!("Failed to resize: {}", e))?;
 
     // Convert back to DynamicImage
@@ -126,7 +455,11 @@ fn pad_image(img: &DynamicImage, target_ratio: f32) -> Result<DynamicImage> {
     Ok(DynamicImage::ImageRgba8(new_img))
 }
 
-fn stretch_image(img: &DynamicImage, target_ratio: f32) -> Result<DynamicImage> {
+fn stretch_image(
+    img: &DynamicImage,
+    target_ratio: f32,
+    options: &fr::ResizeOptions,
+) -> Result<DynamicImage> {
     let w = img.width();
     let h = img.height();
     let current_ratio = w as f32 / h as f32;
@@ -142,14 +475,19 @@ fn stretch_image(img: &DynamicImage, target_ratio: f32) -> Result<DynamicImage>
     };
 
     // Stretch using fast_image_resize
-    resize_image(img, new_w, new_h)
+    resize_image(img, new_w, new_h, options)
 }
 
-fn apply_ar_transform(img: DynamicImage, ratio: Option<f32>, mode: &str) -> Result<DynamicImage> {
+fn apply_ar_transform(
+    img: DynamicImage,
+    ratio: Option<f32>,
+    mode: &str,
+    options: &fr::ResizeOptions,
+) -> Result<DynamicImage> {
     if let Some(r) = ratio {
         match mode {
             "pad" => pad_image(&img, r),
-            "stretch" => stretch_image(&img, r),
+            "stretch" => stretch_image(&img, r, options),
             _ => crop_center(&img, r),
         }
     } else {
@@ -157,36 +495,395 @@ fn apply_ar_transform(img: DynamicImage, ratio: Option<f32>, mode: &str) -> Resu
     }
 }
 
+/// A rotation or mirror, applied before the aspect-ratio fix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    /// Clockwise by a quarter turn
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirrored left to right
+    FlipH,
+    /// Mirrored top to bottom
+    FlipV,
+    /// Clockwise by any angle in degrees, on a canvas grown to fit the
+    /// turned image with its corners filled with `fill`
+    Rotate {
+        degrees: f32,
+        fill: image::Rgba<u8>,
+    },
+}
+
+impl Transform {
+    /// `rotate90`, `rotate180`, `rotate270`, `flip_h` or `flip_v`; or
+    /// `rotate<degrees>`, such as `rotate-2.5`, optionally followed by
+    /// `:<fill>` (`#RRGGBB`, `#RRGGBBAA` or `transparent`, the default)
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim().to_lowercase();
+        match spec.as_str() {
+            "flip_h" | "fliph" => return Ok(Transform::FlipH),
+            "flip_v" | "flipv" => return Ok(Transform::FlipV),
+            _ => {}
+        }
+        let unknown = || {
+            anyhow!(
+                "Unknown transform: {} (expected rotate90, rotate180, rotate270, flip_h, flip_v or rotate<degrees>[:<fill>])",
+                spec
+            )
+        };
+        let rotation = spec.strip_prefix("rotate").ok_or_else(unknown)?;
+        let (degrees, fill) = match rotation.split_once(':') {
+            Some((degrees, fill)) => (degrees, parse_background(fill)?),
+            None => (rotation, image::Rgba([0, 0, 0, 0])),
+        };
+        let degrees: f32 = degrees.trim().parse().map_err(|_| unknown())?;
+        if !degrees.is_finite() {
+            return Err(unknown());
+        }
+        Ok(match degrees.rem_euclid(360.0) {
+            90.0 => Transform::Rotate90,
+            180.0 => Transform::Rotate180,
+            270.0 => Transform::Rotate270,
+            degrees => Transform::Rotate { degrees, fill },
+        })
+    }
+
+    pub fn apply(self, img: DynamicImage) -> DynamicImage {
+        match self {
+            Transform::Rotate90 => img.rotate90(),
+            Transform::Rotate180 => img.rotate180(),
+            Transform::Rotate270 => img.rotate270(),
+            Transform::FlipH => img.fliph(),
+            Transform::FlipV => img.flipv(),
+            Transform::Rotate { degrees: 0.0, .. } => img,
+            Transform::Rotate { degrees, fill } => rotate_by(&img, degrees, fill),
+        }
+    }
+}
+
+/// Parse `specs` as `Transform::parse` does, keeping their order
+pub fn parse_transforms(specs: &[String]) -> Result<Vec<Transform>> {
+    specs.iter().map(|spec| Transform::parse(spec)).collect()
+}
+
+fn rotate_by(img: &DynamicImage, degrees: f32, fill: image::Rgba<u8>) -> DynamicImage {
+    let theta = degrees.to_radians();
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let (sin, cos) = (theta.sin().abs(), theta.cos().abs());
+    let new_w = (w * cos + h * sin).round().max(1.0) as u32;
+    let new_h = (w * sin + h * cos).round().max(1.0) as u32;
+
+    // Center on a canvas the turned image fits, then turn that in place
+    let mut canvas = image::RgbaImage::from_pixel(new_w, new_h, fill);
+    let x = (new_w as i64 - img.width() as i64) / 2;
+    let y = (new_h as i64 - img.height() as i64) / 2;
+    image::imageops::replace(&mut canvas, &img.to_rgba8(), x, y);
+    DynamicImage::ImageRgba8(imageproc::geometric_transformations::rotate_about_center(
+        &canvas,
+        theta,
+        imageproc::geometric_transformations::Interpolation::Bilinear,
+        fill,
+    ))
+}
+
+/// Where a watermark goes on an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+    /// Repeated across the whole image
+    Tiled,
+}
+
+impl WatermarkPosition {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().replace('_', "-").as_str() {
+            "top-left" => Ok(WatermarkPosition::TopLeft),
+            "top-right" => Ok(WatermarkPosition::TopRight),
+            "bottom-left" => Ok(WatermarkPosition::BottomLeft),
+            "bottom-right" => Ok(WatermarkPosition::BottomRight),
+            "center" => Ok(WatermarkPosition::Center),
+            "tiled" => Ok(WatermarkPosition::Tiled),
+            other => Err(anyhow!(
+                "Unknown watermark position: {} (expected top-left, top-right, bottom-left, bottom-right, center or tiled)",
+                other
+            )),
+        }
+    }
+}
+
+/// Pixel height text watermarks are drawn at before being scaled
+const WATERMARK_TEXT_SIZE: f32 = 64.0;
+
+/// A logo or line of text stamped onto images as they're converted
+pub struct Watermark {
+    /// The overlay at its own size, with the opacity already applied
+    mark: DynamicImage,
+    position: WatermarkPosition,
+    /// Width of the overlay as a fraction of the image's
+    scale: f32,
+}
+
+impl Watermark {
+    /// `source` is an image file to overlay or, if there's no such file, the
+    /// text to write (white, with a dark shadow to stand out on light
+    /// images). `opacity` (0.0-1.0) scales the overlay's alpha and `scale`
+    /// (above 0.0, up to 1.0) is its width as a fraction of the image's.
+    pub fn new(source: &str, position: &str, opacity: f32, scale: f32) -> Result<Self> {
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(anyhow!(
+                "Watermark opacity must be between 0 and 1, got {}",
+                opacity
+            ));
+        }
+        if !(scale > 0.0 && scale <= 1.0) {
+            return Err(anyhow!(
+                "Watermark scale must be above 0 and at most 1, got {}",
+                scale
+            ));
+        }
+        let mut mark = if std::path::Path::new(source).is_file() {
+            load_image(source, true)?.into_rgba8()
+        } else {
+            text_watermark(source)?
+        };
+        for pixel in mark.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+        }
+        Ok(Watermark {
+            mark: DynamicImage::ImageRgba8(mark),
+            position: WatermarkPosition::parse(position)?,
+            scale,
+        })
+    }
+
+    /// `img` with the watermark drawn over it
+    pub fn apply(&self, mut img: DynamicImage) -> Result<DynamicImage> {
+        let (w, h) = (img.width(), img.height());
+        let mark_w = ((w as f32 * self.scale).round() as u32).max(1);
+        let mark_h =
+            ((self.mark.height() as f32 * mark_w as f32 / self.mark.width() as f32).round() as u32)
+                .max(1);
+        let mark = resize_image(&self.mark, mark_w, mark_h, &fr::ResizeOptions::new())?;
+        let (w, h, mark_w, mark_h) = (w as i64, h as i64, mark_w as i64, mark_h as i64);
+        // Corners keep a 2% margin
+        let margin = (w.min(h) as f32 * 0.02).round() as i64;
+
+        let (left, right) = (margin, w - mark_w - margin);
+        let (top, bottom) = (margin, h - mark_h - margin);
+        let (x, y) = match self.position {
+            WatermarkPosition::TopLeft => (left, top),
+            WatermarkPosition::TopRight => (right, top),
+            WatermarkPosition::BottomLeft => (left, bottom),
+            WatermarkPosition::BottomRight => (right, bottom),
+            WatermarkPosition::Center => ((w - mark_w) / 2, (h - mark_h) / 2),
+            WatermarkPosition::Tiled => {
+                // Half the overlay's size between copies
+                let (step_x, step_y) = (mark_w + mark_w / 2, mark_h + mark_h / 2);
+                for y in (0..h).step_by(step_y as usize) {
+                    for x in (0..w).step_by(step_x as usize) {
+                        image::imageops::overlay(&mut img, &mark, x, y);
+                    }
+                }
+                return Ok(img);
+            }
+        };
+        image::imageops::overlay(&mut img, &mark, x, y);
+        Ok(img)
+    }
+}
+
+fn text_watermark(text: &str) -> Result<image::RgbaImage> {
+    let white = image::Rgba([255, 255, 255, 255]);
+    let shadow = image::Rgba([0, 0, 0, 160]);
+    let (Some(fill), Some(shade)) = (
+        text::render_text(text, WATERMARK_TEXT_SIZE, white),
+        text::render_text(text, WATERMARK_TEXT_SIZE, shadow),
+    ) else {
+        return Err(anyhow!("Watermark text is empty"));
+    };
+    let offset = (WATERMARK_TEXT_SIZE / 32.0) as u32;
+    let mut mark = image::RgbaImage::new(fill.width() + offset, fill.height() + offset);
+    image::imageops::overlay(&mut mark, &shade, offset as i64, offset as i64);
+    image::imageops::overlay(&mut mark, &fill, 0, 0);
+    Ok(mark)
+}
+
+/// Convert one file. Animated GIFs and WebPs keep every frame when written
+/// as GIF or WebP and no `frame_index` is asked for; otherwise frame
+/// `frame_index` (default 0) is converted. `transforms` are applied in order
+/// before the aspect-ratio fix and a `watermark` goes on after it, on every
+/// frame kept.
+#[allow(clippy::too_many_arguments)]
+pub fn convert_file(
+    path: &str,
+    out_path: &str,
+    output_format: &str,
+    aspect_ratio: Option<f32>,
+    ar_mode: &str,
+    auto_orient: bool,
+    resize: &fr::ResizeOptions,
+    quality: Option<u8>,
+    frame_index: Option<usize>,
+    transforms: &[Transform],
+    watermark: Option<&Watermark>,
+) -> Result<()> {
+    let transform = |img| {
+        let img = transforms.iter().fold(img, |img, t| t.apply(img));
+        let img = apply_ar_transform(img, aspect_ratio, ar_mode, resize)?;
+        match watermark {
+            Some(watermark) => watermark.apply(img),
+            None => Ok(img),
+        }
+    };
+    if frame_index.is_none() && supports_animation(output_format) {
+        if let Some(animation) = load_animation(path)? {
+            let animation = animation.map_frames(transform)?;
+            return save_animation(animation, out_path, output_format, quality);
+        }
+    }
+    let img = match frame_index {
+        Some(index) => load_frame(path, index, auto_orient)?,
+        None => load_image(path, auto_orient)?,
+    };
+    save_image(&transform(img)?, out_path, output_format, quality)
+}
+
 // Core (non-Python) batch conversion for reuse by Tauri
+#[allow(clippy::too_many_arguments)]
 pub fn convert_image_batch_core(
     image_pairs: &[(String, String)], // (input_path, output_path)
     output_format: &str,
     delete_original: bool,
     aspect_ratio: Option<f32>,
     ar_mode: &str,
+    auto_orient: bool,
+    resize: &fr::ResizeOptions,
+    quality: Option<u8>,
+    transforms: &[Transform],
 ) -> Vec<String> {
-    image_pairs
+    convert_image_batch_detailed_core(
+        image_pairs,
+        output_format,
+        delete_original,
+        aspect_ratio,
+        ar_mode,
+        auto_orient,
+        resize,
+        quality,
+        transforms,
+    )
+    .0
+}
+
+/// `(input, error)` pairs for what a batch conversion couldn't convert
+pub type ConvertFailures = Vec<(String, String)>;
+
+/// `convert_image_batch_core`, also returning `(input, error)` for each
+/// pair that failed, in input order
+#[allow(clippy::too_many_arguments)]
+pub fn convert_image_batch_detailed_core(
+    image_pairs: &[(String, String)],
+    output_format: &str,
+    delete_original: bool,
+    aspect_ratio: Option<f32>,
+    ar_mode: &str,
+    auto_orient: bool,
+    resize: &fr::ResizeOptions,
+    quality: Option<u8>,
+    transforms: &[Transform],
+) -> (Vec<String>, ConvertFailures) {
+    let results: Vec<std::result::Result<String, (String, String)>> = image_pairs
         .par_iter()
-        .filter_map(|(path, out_path)| {
-            match load_image(path).and_then(|img| apply_ar_transform(img, aspect_ratio, ar_mode)) {
-                Ok(proc_img) => match save_image(&proc_img, out_path, output_format) {
-                    Ok(_) => {
-                        if delete_original {
-                            let _ = fs::remove_file(path);
-                        }
-                        Some(out_path.clone())
+        .map(|(path, out_path)| {
+            match convert_file(
+                path,
+                out_path,
+                output_format,
+                aspect_ratio,
+                ar_mode,
+                auto_orient,
+                resize,
+                quality,
+                None,
+                transforms,
+                None,
+            ) {
+                Ok(_) => {
+                    if delete_original {
+                        let _ = fs::remove_file(path);
                     }
-                    Err(_) => None,
-                },
-                Err(_) => None,
+                    Ok(out_path.clone())
+                }
+                Err(e) => Err((path.clone(), e.to_string())),
             }
         })
+        .collect();
+
+    let mut converted = Vec::new();
+    let mut failed = ConvertFailures::new();
+    for result in results {
+        match result {
+            Ok(out_path) => converted.push(out_path),
+            Err(failure) => failed.push(failure),
+        }
+    }
+    (converted, failed)
+}
+
+/// Convert each pair with `watermark` stamped on, as `output_format` or,
+/// without one, the format each output's extension names; returns the
+/// outputs written
+#[allow(clippy::too_many_arguments)]
+pub fn apply_watermark_batch_core(
+    image_pairs: &[(String, String)],
+    watermark: &Watermark,
+    output_format: Option<&str>,
+    aspect_ratio: Option<f32>,
+    ar_mode: &str,
+    auto_orient: bool,
+    resize: &fr::ResizeOptions,
+    quality: Option<u8>,
+) -> Vec<String> {
+    image_pairs
+        .par_iter()
+        .filter_map(|(path, out_path)| {
+            let format = output_format.or_else(|| {
+                std::path::Path::new(out_path)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+            })?;
+            convert_file(
+                path,
+                out_path,
+                format,
+                aspect_ratio,
+                ar_mode,
+                auto_orient,
+                resize,
+                quality,
+                None,
+                &[],
+                Some(watermark),
+            )
+            .ok()
+            .map(|_| out_path.clone())
+        })
         .collect()
 }
 
+/// Convert one image. An animated GIF or WebP stays animated when written as
+/// GIF or WebP; `frame_index` picks a single frame instead, and is the frame
+/// used for still formats (default: the first). `transform` lists rotations
+/// and flips such as `["rotate90", "flip_h"]`, applied in order before the
+/// aspect-ratio fix (see `Transform::parse`).
 #[cfg(feature = "python")]
 #[pyfunction]
-#[pyo3(signature = (input_path, output_path, output_format, delete_original, aspect_ratio=None, ar_mode=None))]
+#[pyo3(signature = (input_path, output_path, output_format, delete_original, aspect_ratio=None, ar_mode=None, auto_orient=true, filter=None, quality=None, frame_index=None, transform=None))]
+#[allow(clippy::too_many_arguments)]
 pub fn convert_single_image(
     input_path: String,
     output_path: String,
@@ -194,15 +891,32 @@ pub fn convert_single_image(
     delete_original: bool,
     aspect_ratio: Option<f32>,
     ar_mode: Option<String>,
+    auto_orient: bool,
+    filter: Option<String>,
+    quality: Option<u8>,
+    frame_index: Option<usize>,
+    transform: Option<Vec<String>>,
 ) -> PyResult<bool> {
     let mode = ar_mode.unwrap_or_else(|| "crop".to_string());
-
-    let img = load_image(&input_path).map_err(|e| PyValueError::new_err(e.to_string()))?;
-    let processed_img = apply_ar_transform(img, aspect_ratio, &mode)
+    let resize =
+        resize_options(filter.as_deref()).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let transforms = parse_transforms(&transform.unwrap_or_default())
         .map_err(|e| PyValueError::new_err(e.to_string()))?;
 
-    save_image(&processed_img, &output_path, &output_format)
-        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    convert_file(
+        &input_path,
+        &output_path,
+        &output_format,
+        aspect_ratio,
+        &mode,
+        auto_orient,
+        &resize,
+        quality,
+        frame_index,
+        &transforms,
+        None,
+    )
+    .map_err(|e| PyValueError::new_err(e.to_string()))?;
 
     if delete_original {
         let _ = fs::remove_file(input_path);
@@ -211,9 +925,12 @@ pub fn convert_single_image(
     Ok(true)
 }
 
+/// `transform` as in `convert_single_image`; returns the outputs written.
+/// Pairs that fail are left out; `convert_image_batch_detailed` says why.
 #[cfg(feature = "python")]
 #[pyfunction]
-#[pyo3(signature = (image_pairs, output_format, delete_original, aspect_ratio=None, ar_mode=None))]
+#[pyo3(signature = (image_pairs, output_format, delete_original, aspect_ratio=None, ar_mode=None, auto_orient=true, filter=None, quality=None, transform=None))]
+#[allow(clippy::too_many_arguments)]
 pub fn convert_image_batch(
     py: Python,
     image_pairs: Vec<(String, String)>, // (input_path, output_path)
@@ -221,20 +938,108 @@ pub fn convert_image_batch(
     delete_original: bool,
     aspect_ratio: Option<f32>,
     ar_mode: Option<String>,
+    auto_orient: bool,
+    filter: Option<String>,
+    quality: Option<u8>,
+    transform: Option<Vec<String>>,
 ) -> PyResult<Vec<String>> {
+    let (converted, _) = convert_image_batch_detailed(
+        py,
+        image_pairs,
+        output_format,
+        delete_original,
+        aspect_ratio,
+        ar_mode,
+        auto_orient,
+        filter,
+        quality,
+        transform,
+    )?;
+    Ok(converted)
+}
+
+/// `convert_image_batch`, returning the outputs written and the
+/// `(input, error)` of every pair that couldn't be converted
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (image_pairs, output_format, delete_original, aspect_ratio=None, ar_mode=None, auto_orient=true, filter=None, quality=None, transform=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn convert_image_batch_detailed(
+    py: Python,
+    image_pairs: Vec<(String, String)>,
+    output_format: String,
+    delete_original: bool,
+    aspect_ratio: Option<f32>,
+    ar_mode: Option<String>,
+    auto_orient: bool,
+    filter: Option<String>,
+    quality: Option<u8>,
+    transform: Option<Vec<String>>,
+) -> PyResult<(Vec<String>, ConvertFailures)> {
     let mode = ar_mode.unwrap_or_else(|| "crop".to_string());
+    let resize =
+        resize_options(filter.as_deref()).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let transforms = parse_transforms(&transform.unwrap_or_default())
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
 
-    let results: Vec<String> = py.detach(|| {
-        convert_image_batch_core(
+    Ok(py.detach(|| {
+        convert_image_batch_detailed_core(
             &image_pairs,
             &output_format,
             delete_original,
             aspect_ratio,
             &mode,
+            auto_orient,
+            &resize,
+            quality,
+            &transforms,
         )
-    });
+    }))
+}
+
+/// Convert each `(input, output)` pair with a watermark stamped on in the
+/// same pass. `watermark` is a logo file or, if no such file exists, text;
+/// `position` is `top-left`, `top-right`, `bottom-left`, `bottom-right`,
+/// `center` or `tiled`; `opacity` (0.0-1.0) scales the overlay's alpha and
+/// `scale` is its width as a fraction of the image's. Without an
+/// `output_format`, each output's extension picks it; the other options are
+/// as in `convert_image_batch`.
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (image_pairs, watermark, position="bottom-right", opacity=0.5, scale=0.2, output_format=None, aspect_ratio=None, ar_mode=None, auto_orient=true, filter=None, quality=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn apply_watermark_batch(
+    py: Python,
+    image_pairs: Vec<(String, String)>,
+    watermark: String,
+    position: &str,
+    opacity: f32,
+    scale: f32,
+    output_format: Option<String>,
+    aspect_ratio: Option<f32>,
+    ar_mode: Option<String>,
+    auto_orient: bool,
+    filter: Option<String>,
+    quality: Option<u8>,
+) -> PyResult<Vec<String>> {
+    let mode = ar_mode.unwrap_or_else(|| "crop".to_string());
+    let resize =
+        resize_options(filter.as_deref()).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let watermark = Watermark::new(&watermark, position, opacity, scale)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
 
-    Ok(results)
+    Ok(py.detach(|| {
+        apply_watermark_batch_core(
+            &image_pairs,
+            &watermark,
+            output_format.as_deref(),
+            aspect_ratio,
+            &mode,
+            auto_orient,
+            &resize,
+            quality,
+        )
+    }))
 }
 
 #[cfg(all(test, feature = "python"))]
@@ -270,6 +1075,11 @@ mod tests {
             false,
             Some(1.0),
             Some("crop".to_string()),
+            true,
+            None,
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -295,6 +1105,11 @@ mod tests {
             false,
             Some(1.0),
             Some("pad".to_string()),
+            true,
+            None,
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -323,6 +1138,11 @@ mod tests {
             false,
             Some(2.0),
             Some("stretch".to_string()),
+            true,
+            None,
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -355,11 +1175,499 @@ mod tests {
                 ),
             ];
 
-            let res = convert_image_batch(py, pairs, "png".to_string(), false, None, None).unwrap();
+            let res = convert_image_batch(
+                py,
+                pairs,
+                "png".to_string(),
+                false,
+                None,
+                None,
+                true,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
 
             assert_eq!(res.len(), 2);
             assert!(o1.exists());
             assert!(o2.exists());
         });
     }
+
+    #[test]
+    fn test_convert_batch_detailed() {
+        let dir = tempdir().unwrap();
+        let good = dir.path().join("good.png");
+        let broken = dir.path().join("broken.png");
+        create_test_image(good.to_str().unwrap(), 20, 20);
+        std::fs::write(&broken, "not an image").unwrap();
+        let pair = |input: &std::path::Path, output: &str| {
+            (
+                input.to_str().unwrap().to_string(),
+                dir.path().join(output).to_str().unwrap().to_string(),
+            )
+        };
+        let pairs = vec![
+            pair(&broken, "broken.jpg"),
+            pair(&good, "good.jpg"),
+            pair(&dir.path().join("missing.png"), "missing.jpg"),
+        ];
+
+        Python::initialize();
+        Python::attach(|py| {
+            let (converted, failed) = convert_image_batch_detailed(
+                py,
+                pairs.clone(),
+                "jpg".to_string(),
+                false,
+                None,
+                None,
+                true,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(converted, [pairs[1].1.clone()]);
+            let inputs: Vec<&str> = failed.iter().map(|(input, _)| input.as_str()).collect();
+            assert_eq!(inputs, [pairs[0].0.as_str(), pairs[2].0.as_str()]);
+            assert!(
+                failed[0].1.starts_with("Failed to decode image"),
+                "{}",
+                failed[0].1
+            );
+            assert!(
+                failed[1].1.starts_with("Failed to open file"),
+                "{}",
+                failed[1].1
+            );
+
+            // The plain batch still just leaves failures out
+            let converted = convert_image_batch(
+                py,
+                pairs.clone(),
+                "jpg".to_string(),
+                false,
+                None,
+                None,
+                true,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(converted, [pairs[1].1.clone()]);
+        });
+    }
+
+    #[test]
+    fn test_exif_orientation() {
+        let dir = tempdir().unwrap();
+        let fixture = |n: u32| {
+            format!(
+                "{}/tests/fixtures/orientation/orientation_{}.jpg",
+                env!("CARGO_MANIFEST_DIR"),
+                n
+            )
+        };
+        let is_red = |p: &image::Rgb<u8>| p[0] > 200 && p[1] < 60 && p[2] < 60;
+
+        // Every orientation comes out as the same upright 24x16 image with a
+        // red top-left corner
+        for n in 1..=8 {
+            let out = dir.path().join(format!("upright_{}.png", n));
+            convert_single_image(
+                fixture(n),
+                out.to_str().unwrap().to_string(),
+                "png".to_string(),
+                false,
+                None,
+                None,
+                true,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+            let img = image::open(&out).unwrap().to_rgb8();
+            assert_eq!(img.dimensions(), (24, 16), "orientation {}", n);
+            assert!(is_red(img.get_pixel(2, 2)), "orientation {}", n);
+            assert!(!is_red(img.get_pixel(21, 2)), "orientation {}", n);
+            assert!(!is_red(img.get_pixel(2, 13)), "orientation {}", n);
+        }
+
+        // Rotation happens before the aspect-ratio transform, and can be off
+        let pairs: Vec<(String, String)> = [true, false]
+            .iter()
+            .map(|orient| {
+                let out = dir.path().join(format!("cropped_{}.png", orient));
+                (fixture(6), out.to_str().unwrap().to_string())
+            })
+            .collect();
+        let resize = fr::ResizeOptions::new();
+        let upright = convert_image_batch_core(
+            &pairs[..1],
+            "png",
+            false,
+            Some(1.0),
+            "crop",
+            true,
+            &resize,
+            None,
+            &[],
+        );
+        let raw = convert_image_batch_core(
+            &pairs[1..],
+            "png",
+            false,
+            None,
+            "crop",
+            false,
+            &resize,
+            None,
+            &[],
+        );
+        assert_eq!(upright.len() + raw.len(), 2);
+        let cropped = image::open(&pairs[0].1).unwrap().to_rgb8();
+        assert_eq!(cropped.dimensions(), (16, 16));
+        assert!(is_red(cropped.get_pixel(1, 1)));
+        assert!(!is_red(cropped.get_pixel(14, 1)));
+        assert_eq!(image::image_dimensions(&pairs[1].1).unwrap(), (16, 24));
+    }
+
+    #[test]
+    fn test_resize_filter_and_quality() {
+        let dir = tempdir().unwrap();
+        assert!(resize_options(Some("bicubic")).is_err());
+        for name in ["nearest", "Bilinear", "lanczos3", "catmullrom"] {
+            assert!(resize_options(Some(name)).is_ok());
+        }
+
+        // A black and white checkerboard stretched with nearest-neighbour
+        // stays black and white; Lanczos3 blends the edges
+        let board = dir.path().join("board.png");
+        RgbImage::from_fn(10, 10, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })
+        .save(&board)
+        .unwrap();
+        let blended = |filter: &str| {
+            let out = dir.path().join(format!("board_{}.png", filter));
+            convert_single_image(
+                board.to_str().unwrap().to_string(),
+                out.to_str().unwrap().to_string(),
+                "png".to_string(),
+                false,
+                Some(2.0),
+                Some("stretch".to_string()),
+                true,
+                Some(filter.to_string()),
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+            let img = image::open(&out).unwrap().to_rgb8();
+            assert_eq!(img.dimensions(), (20, 10));
+            img.pixels().any(|p| p[0] != 0 && p[0] != 255)
+        };
+        assert!(!blended("nearest"));
+        assert!(blended("lanczos3"));
+
+        // Quality controls the size of JPEG and WebP output
+        let noisy = dir.path().join("noisy.png");
+        RgbImage::from_fn(64, 64, |x, y| {
+            Rgb([(x * 37 + y * 91) as u8, (x * y) as u8, (x ^ y) as u8 * 4])
+        })
+        .save(&noisy)
+        .unwrap();
+        let size = |format: &str, quality: Option<u8>| {
+            let out = dir.path().join(format!("noisy_{:?}.{}", quality, format));
+            convert_single_image(
+                noisy.to_str().unwrap().to_string(),
+                out.to_str().unwrap().to_string(),
+                format.to_string(),
+                false,
+                None,
+                None,
+                true,
+                None,
+                quality,
+                None,
+                None,
+            )
+            .unwrap();
+            assert!(image::open(&out).is_ok());
+            fs::metadata(&out).unwrap().len()
+        };
+        assert!(size("jpg", Some(20)) < size("jpg", Some(95)));
+        assert!(size("webp", Some(20)) < size("webp", Some(95)));
+        assert!(size("webp", Some(95)) < size("webp", None));
+    }
+
+    #[test]
+    fn test_avif_output_and_heic_detection() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("in.png");
+        create_test_image(input.to_str().unwrap(), 16, 16);
+        for quality in [None, Some(60)] {
+            let out = dir.path().join(format!("out_{:?}.avif", quality));
+            convert_single_image(
+                input.to_str().unwrap().to_string(),
+                out.to_str().unwrap().to_string(),
+                "avif".to_string(),
+                false,
+                None,
+                None,
+                true,
+                None,
+                quality,
+                None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(&fs::read(&out).unwrap()[4..12], b"ftypavif");
+        }
+
+        // As an iPhone writes them, with a HEVC brand among the compatible ones
+        let heic_header = b"\0\0\0\x1cftypmif1\0\0\0\0mif1heicmiaf".to_vec();
+        assert!(is_heic(&heic_header));
+        assert!(is_heic(b"\0\0\0\x18ftypheic\0\0\0\0mif1heic"));
+        assert!(!is_heic(b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf"));
+        assert!(!is_heic(&fs::read(&input).unwrap()));
+
+        #[cfg(not(feature = "heic"))]
+        {
+            let photo = dir.path().join("photo.heic");
+            fs::write(&photo, [heic_header, vec![0; 32]].concat()).unwrap();
+            let err = load_image(photo.to_str().unwrap(), true).unwrap_err();
+            assert!(err.to_string().contains("HEIC support not enabled"));
+        }
+    }
+
+    fn gif_frames(path: &std::path::Path) -> (Vec<Frame>, LoopCount) {
+        let file = std::io::BufReader::new(fs::File::open(path).unwrap());
+        let decoder = GifDecoder::new(file).unwrap();
+        let loop_count = decoder.loop_count();
+        (decoder.into_frames().collect_frames().unwrap(), loop_count)
+    }
+
+    #[test]
+    fn test_animated_gif() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("in.gif");
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        {
+            let mut encoder = GifEncoder::new(fs::File::create(&input).unwrap());
+            encoder.set_repeat(Repeat::Finite(3)).unwrap();
+            let frames = colors.iter().zip([100, 200, 300]).map(|(color, ms)| {
+                let buffer = image::RgbaImage::from_pixel(8, 8, image::Rgba(*color));
+                Frame::from_parts(buffer, 0, 0, image::Delay::from_numer_denom_ms(ms, 1))
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+        let convert = |out: &str, format: &str, frame_index: Option<usize>| {
+            convert_single_image(
+                input.to_str().unwrap().to_string(),
+                dir.path().join(out).to_str().unwrap().to_string(),
+                format.to_string(),
+                false,
+                Some(2.0),
+                Some("pad".to_string()),
+                true,
+                None,
+                None,
+                frame_index,
+                None,
+            )
+        };
+
+        // Every frame, delay and the loop count survive, each frame padded
+        convert("out.gif", "gif", None).unwrap();
+        let (frames, loop_count) = gif_frames(&dir.path().join("out.gif"));
+        assert_eq!(frames.len(), 3);
+        let delays: Vec<_> = frames.iter().map(|f| f.delay().numer_denom_ms()).collect();
+        assert_eq!(delays, [(100, 1), (200, 1), (300, 1)]);
+        assert!(matches!(loop_count, LoopCount::Finite(n) if n.get() == 3));
+        for (frame, color) in frames.iter().zip(colors) {
+            assert_eq!(frame.buffer().dimensions(), (16, 8));
+            assert_eq!(frame.buffer().get_pixel(8, 4).0, color);
+        }
+
+        convert("out.webp", "webp", None).unwrap();
+        let file = std::io::BufReader::new(fs::File::open(dir.path().join("out.webp")).unwrap());
+        let decoder = WebPDecoder::new(file).unwrap();
+        assert!(decoder.has_animation());
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        let delays: Vec<_> = frames.iter().map(|f| f.delay().numer_denom_ms()).collect();
+        assert_eq!(delays, [(100, 1), (200, 1), (300, 1)]);
+
+        // One frame for still formats, or when asked for
+        convert("second.png", "png", Some(1)).unwrap();
+        let second = image::open(dir.path().join("second.png"))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(second.get_pixel(8, 4).0, colors[1]);
+        convert("first.png", "png", None).unwrap();
+        let first = image::open(dir.path().join("first.png"))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(first.get_pixel(8, 4).0, colors[0]);
+        convert("third.gif", "gif", Some(2)).unwrap();
+        assert_eq!(gif_frames(&dir.path().join("third.gif")).0.len(), 1);
+        assert!(convert("none.png", "png", Some(3)).is_err());
+    }
+    #[test]
+    fn test_watermark() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("in.png");
+        create_test_image(input.to_str().unwrap(), 200, 100);
+        let logo = dir.path().join("logo.png");
+        RgbImage::from_pixel(10, 10, Rgb([0, 0, 255]))
+            .save(&logo)
+            .unwrap();
+        let logo = logo.to_str().unwrap();
+        let red = DynamicImage::ImageRgb8(RgbImage::from_pixel(200, 100, Rgb([255, 0, 0])));
+        let stamp = |source: &str, position: &str, opacity: f32, scale: f32| {
+            Watermark::new(source, position, opacity, scale)
+                .unwrap()
+                .apply(red.clone())
+                .unwrap()
+                .to_rgb8()
+        };
+
+        // A 20px logo two pixels in from the corner, at half opacity
+        let img = stamp(logo, "top-left", 0.5, 0.1);
+        assert_eq!(img.get_pixel(0, 0).0, [255, 0, 0]);
+        let [r, g, b] = img.get_pixel(10, 10).0;
+        assert!(r.abs_diff(128) <= 2 && g == 0 && b.abs_diff(128) <= 2);
+        assert_eq!(img.get_pixel(24, 10).0, [255, 0, 0]);
+        let img = stamp(logo, "bottom-right", 1.0, 0.1);
+        assert_eq!(img.get_pixel(190, 90).0, [0, 0, 255]);
+        assert_eq!(img.get_pixel(10, 10).0, [255, 0, 0]);
+        // Tiled copies 20px wide with 10px between them
+        let img = stamp(logo, "tiled", 1.0, 0.1);
+        for x in [5, 35, 185] {
+            assert_eq!(img.get_pixel(x, 5).0, [0, 0, 255], "{}", x);
+        }
+        assert_eq!(img.get_pixel(25, 5).0, [255, 0, 0]);
+        assert_eq!(img.get_pixel(5, 25).0, [255, 0, 0]);
+
+        // Anything that isn't a file is text: white in the chosen corner
+        let img = stamp("(c) 2024", "bottom-right", 1.0, 0.5);
+        let white = |x0: u32, y0: u32| {
+            (x0..x0 + 100)
+                .flat_map(|x| (y0..y0 + 50).map(move |y| (x, y)))
+                .filter(|&(x, y)| img.get_pixel(x, y).0.iter().all(|&c| c > 200))
+                .count()
+        };
+        assert!(white(100, 50) > 50);
+        assert_eq!(white(0, 0), 0);
+
+        // In the same pass as the aspect-ratio fix, by the outputs' extensions
+        let pairs = vec![
+            (
+                input.to_str().unwrap().to_string(),
+                dir.path().join("out.jpg").to_str().unwrap().to_string(),
+            ),
+            (
+                input.to_str().unwrap().to_string(),
+                dir.path().join("no_ext").to_str().unwrap().to_string(),
+            ),
+        ];
+        let watermark = Watermark::new(logo, "center", 1.0, 0.2).unwrap();
+        let written = apply_watermark_batch_core(
+            &pairs,
+            &watermark,
+            None,
+            Some(1.0),
+            "crop",
+            true,
+            &fr::ResizeOptions::new(),
+            Some(95),
+        );
+        assert_eq!(written, [pairs[0].1.clone()]);
+        let out = image::open(&pairs[0].1).unwrap().to_rgb8();
+        assert_eq!(out.dimensions(), (100, 100));
+        let [r, _, b] = out.get_pixel(50, 50).0;
+        assert!(r < 30 && b > 220, "{:?}", out.get_pixel(50, 50));
+
+        assert!(Watermark::new(logo, "middle", 0.5, 0.1).is_err());
+        assert!(Watermark::new(logo, "center", 1.5, 0.1).is_err());
+        assert!(Watermark::new(logo, "center", 0.5, 0.0).is_err());
+        assert!(Watermark::new("", "center", 0.5, 0.1).is_err());
+    }
+    #[test]
+    fn test_rotate_and_flip() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("scan.png");
+        // Red, with a blue top-left corner
+        let mut img = RgbImage::from_pixel(200, 100, Rgb([255, 0, 0]));
+        for y in 0..10 {
+            for x in 0..10 {
+                img.put_pixel(x, y, Rgb([0, 0, 255]));
+            }
+        }
+        img.save(&input).unwrap();
+        let convert = |transform: &[&str], aspect_ratio: Option<f32>| {
+            let out = dir.path().join("out.png");
+            convert_single_image(
+                input.to_str().unwrap().to_string(),
+                out.to_str().unwrap().to_string(),
+                "png".to_string(),
+                false,
+                aspect_ratio,
+                None,
+                true,
+                None,
+                None,
+                None,
+                Some(transform.iter().map(|t| t.to_string()).collect()),
+            )
+            .map(|_| image::open(&out).unwrap().to_rgba8())
+        };
+
+        // A quarter turn puts the corner top-right, and the flip brings it
+        // back left
+        let turned = convert(&["rotate90"], None).unwrap();
+        assert_eq!(turned.dimensions(), (100, 200));
+        assert_eq!(turned.get_pixel(95, 5).0, [0, 0, 255, 255]);
+        let flipped = convert(&["rotate90", "flip_h"], None).unwrap();
+        assert_eq!(flipped.get_pixel(5, 5).0, [0, 0, 255, 255]);
+        assert_eq!(flipped.get_pixel(95, 5).0, [255, 0, 0, 255]);
+        let flipped = convert(&["FLIP_V"], None).unwrap();
+        assert_eq!(flipped.get_pixel(5, 95).0, [0, 0, 255, 255]);
+        // Before the aspect-ratio fix: the turned 100x200 is cropped to 2:1
+        assert_eq!(
+            convert(&["rotate270"], Some(2.0)).unwrap().dimensions(),
+            (100, 50)
+        );
+
+        // Any other angle grows the canvas and fills the corners
+        let tilted = convert(&["rotate45:#00ff00"], None).unwrap();
+        assert_eq!(tilted.dimensions(), (212, 212));
+        assert_eq!(tilted.get_pixel(2, 2).0, [0, 255, 0, 255]);
+        assert_eq!(tilted.get_pixel(106, 106).0, [255, 0, 0, 255]);
+        assert_eq!(
+            convert(&["rotate10"], None).unwrap().get_pixel(0, 0).0[3],
+            0
+        );
+
+        assert_eq!(Transform::parse("rotate-90").unwrap(), Transform::Rotate270);
+        assert_eq!(Transform::parse("rotate450").unwrap(), Transform::Rotate90);
+        assert!(matches!(
+            Transform::parse("rotate-2.5").unwrap(),
+            Transform::Rotate { degrees, .. } if degrees == 357.5
+        ));
+        for bad in ["spin90", "rotate", "rotatex", "rotate10:mauve", "flip"] {
+            assert!(Transform::parse(bad).is_err(), "{}", bad);
+        }
+        assert!(convert(&["rotate90", "mirror"], None).is_err());
+    }
 }
@@ -1,23 +1,25 @@
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageReader};
 #[cfg(feature = "python")]
-use image::ImageReader;
+use pyo3::exceptions::{PyIOError, PyValueError};
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
-#[cfg(feature = "python")]
 use rayon::prelude::*;
-#[cfg(feature = "python")]
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-#[cfg(feature = "python")]
 use std::collections::HashMap;
 #[cfg(feature = "python")]
-use std::fs::File;
-#[cfg(feature = "python")]
-use std::io::Read;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
 #[cfg(feature = "python")]
 use walkdir::WalkDir;
 
 // --- Helper Functions ---
 
-#[cfg(feature = "python")]
 fn compute_sha256(path: &str) -> Option<String> {
     let mut file = match File::open(path) {
         Ok(f) => f,
@@ -37,108 +39,823 @@ fn compute_sha256(path: &str) -> Option<String> {
     Some(hex::encode(hasher.finalize()))
 }
 
-#[cfg(feature = "python")]
-fn compute_phash(path: &str) -> Option<(String, u64)> {
-    // 1. Open
-    let img = match ImageReader::open(path) {
-        Ok(reader) => match reader.decode() {
-            Ok(i) => i,
-            Err(_) => return None,
-        },
-        Err(_) => return None,
-    };
+/// How `find_similar_images_phash` fingerprints an image as 64 bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// `ahash`: 8x8 greyscale, one bit per pixel brighter than the mean.
+    /// Fast, but flat images and small crops throw it off.
+    Average,
+    /// `phash`: the lowest 8x8 frequencies of a 32x32 greyscale DCT, one
+    /// bit per coefficient above their median
+    Perceptual,
+    /// `dhash`: 9x8 greyscale, one bit per pixel darker than its right
+    /// neighbour
+    Difference,
+}
 
-    // 2. Resize to 8x8 and Grayscale
+impl HashAlgorithm {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "ahash" => Ok(HashAlgorithm::Average),
+            "phash" => Ok(HashAlgorithm::Perceptual),
+            "dhash" => Ok(HashAlgorithm::Difference),
+            other => bail!(
+                "Unknown hash algorithm: {} (expected ahash, phash or dhash)",
+                other
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Average => "ahash",
+            HashAlgorithm::Perceptual => "phash",
+            HashAlgorithm::Difference => "dhash",
+        }
+    }
+
+    pub fn hash(self, img: &DynamicImage) -> u64 {
+        match self {
+            HashAlgorithm::Average => average_hash(img),
+            HashAlgorithm::Perceptual => perceptual_hash(img),
+            HashAlgorithm::Difference => difference_hash(img),
+        }
+    }
+}
+
+fn average_hash(img: &DynamicImage) -> u64 {
     // resize_exact gives exactly 8x8. FilterType::Triangle (Bilinear) is fast and good enough.
-    let small = img
-        .resize_exact(8, 8, image::imageops::FilterType::Triangle)
-        .to_luma8();
+    let small = img.resize_exact(8, 8, FilterType::Triangle).to_luma8();
 
-    // 3. Compute Mean
     let mut sum: u32 = 0;
     for p in small.pixels() {
         sum += p[0] as u32;
     }
     let mean = sum / 64;
 
-    // 4. Compute Hash
     let mut hash: u64 = 0;
     for (i, p) in small.pixels().enumerate() {
         if p[0] as u32 > mean {
             hash |= 1 << i;
         }
     }
+    hash
+}
+
+/// Side of the greyscale image `perceptual_hash` transforms
+const DCT_SIZE: usize = 32;
+
+fn perceptual_hash(img: &DynamicImage) -> u64 {
+    let n = DCT_SIZE;
+    let small = img
+        .resize_exact(n as u32, n as u32, FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = small.pixels().map(|p| p[0] as f64).collect();
+
+    // cos[k][x] for the 8 lowest frequencies k of a DCT-II over n samples
+    let cos: Vec<Vec<f64>> = (0..8)
+        .map(|k| {
+            (0..n)
+                .map(|x| {
+                    ((2 * x + 1) as f64 * k as f64 * std::f64::consts::PI / (2 * n) as f64).cos()
+                })
+                .collect()
+        })
+        .collect();
+    // Rows first, then columns, keeping only the top-left 8x8 block
+    let rows: Vec<[f64; 8]> = (0..n)
+        .map(|y| {
+            let row = &pixels[y * n..(y + 1) * n];
+            std::array::from_fn(|u| row.iter().zip(&cos[u]).map(|(p, c)| p * c).sum())
+        })
+        .collect();
+    let mut coefficients = [0.0; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            coefficients[v * 8 + u] = rows.iter().zip(&cos[v]).map(|(row, c)| row[u] * c).sum();
+        }
+    }
 
-    Some((path.to_string(), hash))
+    // The DC term is only the overall brightness; leave its bit unset
+    let mut ac = coefficients[1..].to_vec();
+    ac.sort_by(|a, b| a.total_cmp(b));
+    let median = ac[ac.len() / 2];
+    coefficients
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, c)| **c > median)
+        .fold(0u64, |hash, (i, _)| hash | 1 << i)
+}
+
+fn difference_hash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1 << (y * 8 + x);
+            }
+        }
+    }
+    hash
+}
+
+fn compute_image_hash(path: &str, algorithm: HashAlgorithm) -> Option<u64> {
+    let img = ImageReader::open(path).ok()?.decode().ok()?;
+    Some(algorithm.hash(&img))
 }
 
-#[cfg(feature = "python")]
 fn hamming_distance(h1: u64, h2: u64) -> u32 {
     (h1 ^ h2).count_ones()
 }
 
+/// A BK-tree over 64-bit hashes under Hamming distance. Each node's children
+/// are keyed by their distance to it, so by the triangle inequality a search
+/// within `threshold` of a hash only descends into children whose key is
+/// within `threshold` of the node's own distance to that hash.
+#[derive(Default)]
+pub struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    /// Indices of every inserted item with exactly this hash
+    items: Vec<usize>,
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, hash: u64, item: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(BkNode {
+                hash,
+                items: vec![item],
+                children: Vec::new(),
+            });
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = hamming_distance(self.nodes[current].hash, hash);
+            if distance == 0 {
+                self.nodes[current].items.push(item);
+                return;
+            }
+            match self.nodes[current]
+                .children
+                .iter()
+                .find(|(d, _)| *d == distance)
+            {
+                Some(&(_, child)) => current = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(BkNode {
+                        hash,
+                        items: vec![item],
+                        children: Vec::new(),
+                    });
+                    self.nodes[current].children.push((distance, child));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Items whose hash is within `threshold` bits of `hash`, in no
+    /// particular order
+    pub fn within(&self, hash: u64, threshold: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+        let mut pending = vec![0];
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = hamming_distance(node.hash, hash);
+            if distance <= threshold {
+                found.extend_from_slice(&node.items);
+            }
+            let range = distance.saturating_sub(threshold)..=distance + threshold;
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| range.contains(d))
+                    .map(|&(_, child)| child),
+            );
+        }
+        found
+    }
+}
+
+/// Group `hashes` by index: taking each hash not yet grouped in order, it
+/// and every later ungrouped hash within `threshold` bits of it form a
+/// group. Only groups of two or more are returned.
+pub fn group_similar(hashes: &[u64], threshold: u32) -> Vec<Vec<usize>> {
+    let mut tree = BkTree::new();
+    for (i, &hash) in hashes.iter().enumerate() {
+        tree.insert(hash, i);
+    }
+
+    let mut grouped = vec![false; hashes.len()];
+    let mut groups = Vec::new();
+    for i in 0..hashes.len() {
+        if grouped[i] {
+            continue;
+        }
+        grouped[i] = true;
+        let mut matches: Vec<usize> = tree
+            .within(hashes[i], threshold)
+            .into_iter()
+            .filter(|&j| !grouped[j])
+            .collect();
+        if matches.is_empty() {
+            continue;
+        }
+        matches.sort_unstable();
+        for &j in &matches {
+            grouped[j] = true;
+        }
+        let mut group = Vec::with_capacity(matches.len() + 1);
+        group.push(i);
+        group.extend(matches);
+        groups.push(group);
+    }
+    groups
+}
+
+// --- Hash Cache ---
+
+/// What a file looked like when it was hashed; a cached hash is reused only
+/// while the file still matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    /// Modification time, in nanoseconds since the Unix epoch
+    pub modified_ns: i64,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?;
+        let modified_ns = match modified.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_nanos() as i64,
+            Err(before) => -(before.duration().as_nanos() as i64),
+        };
+        Some(FileStamp {
+            size: metadata.len(),
+            modified_ns,
+        })
+    }
+}
+
+/// The hashes known for one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedHashes {
+    #[serde(flatten)]
+    pub stamp: FileStamp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Image hashes by algorithm name (`ahash`, `phash`, `dhash`)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub image_hashes: HashMap<String, u64>,
+}
+
+/// Hashes from earlier scans, keyed by path, so repeat scans only hash new
+/// and changed files. Kept as a JSON sidecar file between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    entries: HashMap<String, CachedHashes>,
+    /// Hashes computed rather than reused since loading
+    #[serde(skip)]
+    computed: usize,
+}
+
+impl HashCache {
+    /// Loads the cache at `path`, or returns an empty one if the file is missing
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read hash cache {:?}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse hash cache {:?}", path))
+    }
+
+    /// Writes the cache via a temp file + rename so a crash never leaves a
+    /// truncated file behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, path).context("Failed to move hash cache into place")?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, path: &str) -> Option<&CachedHashes> {
+        self.entries.get(path)
+    }
+
+    /// How many hashes were computed, rather than reused, since loading
+    pub fn computed(&self) -> usize {
+        self.computed
+    }
+
+    /// Drops entries for files that no longer exist; returns how many
+    pub fn prune(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| Path::new(path).is_file());
+        before - self.entries.len()
+    }
+
+    /// Adds every entry of `other`, replacing entries for the same path;
+    /// returns how many were added or replaced
+    pub fn merge(&mut self, other: HashCache) -> usize {
+        let count = other.entries.len();
+        self.entries.extend(other.entries);
+        count
+    }
+
+    /// SHA-256 of each of `paths` that can be read, in order, reusing
+    /// cached hashes of unchanged files
+    pub fn sha256_all(&mut self, paths: &[String]) -> Vec<(String, String)> {
+        self.hash_all(
+            paths,
+            |cached| cached.sha256.clone(),
+            compute_sha256,
+            |cached, hash| cached.sha256 = Some(hash),
+        )
+    }
+
+    /// `algorithm`'s hash of each of `paths` that decodes, in order, reusing
+    /// cached hashes of unchanged files
+    pub fn image_hash_all(
+        &mut self,
+        paths: &[String],
+        algorithm: HashAlgorithm,
+    ) -> Vec<(String, u64)> {
+        self.hash_all(
+            paths,
+            |cached| cached.image_hashes.get(algorithm.name()).copied(),
+            |path| compute_image_hash(path, algorithm),
+            |cached, hash| {
+                cached
+                    .image_hashes
+                    .insert(algorithm.name().to_string(), hash);
+            },
+        )
+    }
+
+    fn hash_all<T: Clone + Send>(
+        &mut self,
+        paths: &[String],
+        cached: impl Fn(&CachedHashes) -> Option<T> + Sync,
+        compute: impl Fn(&str) -> Option<T> + Sync,
+        store: impl Fn(&mut CachedHashes, T),
+    ) -> Vec<(String, T)> {
+        let entries = &self.entries;
+        // A stamp alongside a hash marks it as newly computed
+        let hashed: Vec<(String, T, Option<FileStamp>)> = paths
+            .par_iter()
+            .filter_map(|path| {
+                let stamp = FileStamp::of(Path::new(path));
+                let hit = entries
+                    .get(path)
+                    .filter(|entry| Some(entry.stamp) == stamp)
+                    .and_then(&cached);
+                match hit {
+                    Some(hash) => Some((path.clone(), hash, None)),
+                    None => compute(path).map(|hash| (path.clone(), hash, stamp)),
+                }
+            })
+            .collect();
+
+        for (path, hash, stamp) in &hashed {
+            let Some(stamp) = *stamp else { continue };
+            self.computed += 1;
+            let entry = self
+                .entries
+                .entry(path.clone())
+                .or_insert_with(|| CachedHashes {
+                    stamp,
+                    sha256: None,
+                    image_hashes: HashMap::new(),
+                });
+            if entry.stamp != stamp {
+                // Changed since: whatever else was cached for it is stale
+                *entry = CachedHashes {
+                    stamp,
+                    sha256: None,
+                    image_hashes: HashMap::new(),
+                };
+            }
+            store(entry, hash.clone());
+        }
+        hashed
+            .into_iter()
+            .map(|(path, hash, _)| (path, hash))
+            .collect()
+    }
+}
+
+// --- Duplicate Detection ---
+
+/// Files larger than this are first told apart by a fingerprint of their
+/// ends, and only hashed whole if that collides
+pub const DEFAULT_PARTIAL_HASH_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How much of each end of a large file its fingerprint covers
+const PARTIAL_HASH_CHUNK: u64 = 1024 * 1024;
+
+/// SHA-256 of a file's size, first megabyte and last megabyte
+fn compute_partial_sha256(path: &str, size: u64) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    hasher.update(size.to_le_bytes());
+    let mut buffer = vec![0; PARTIAL_HASH_CHUNK.min(size) as usize];
+    file.read_exact(&mut buffer).ok()?;
+    hasher.update(&buffer);
+    if size > PARTIAL_HASH_CHUNK {
+        file.seek(SeekFrom::Start(size - buffer.len() as u64))
+            .ok()?;
+        file.read_exact(&mut buffer).ok()?;
+        hasher.update(&buffer);
+    }
+    Some(hex::encode(hasher.finalize()))
+}
+
+/// How much reading `group_duplicates` did
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DuplicateScanStats {
+    /// Files fingerprinted by their ends
+    pub partial_hashes: usize,
+    /// Files hashed whole, not counting hashes reused from the cache
+    pub full_hashes: usize,
+}
+
+/// Groups of `paths` with identical contents, keyed by their SHA-256, with
+/// paths in the order given. Only files sharing their size with another are
+/// hashed, and those over `partial_min_size` bytes only once their
+/// fingerprints collide too.
+pub fn group_duplicates(
+    paths: &[String],
+    cache: &mut HashCache,
+    partial_min_size: u64,
+) -> (HashMap<String, Vec<String>>, DuplicateScanStats) {
+    let mut stats = DuplicateScanStats::default();
+    let sizes: Vec<(usize, u64)> = paths
+        .par_iter()
+        .enumerate()
+        .filter_map(|(i, path)| Some((i, fs::metadata(path).ok()?.len())))
+        .collect();
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, size) in sizes {
+        by_size.entry(size).or_default().push(i);
+    }
+
+    let mut candidates = Vec::new();
+    let mut large = Vec::new();
+    for (size, group) in by_size.into_iter().filter(|(_, group)| group.len() > 1) {
+        if size > partial_min_size {
+            large.extend(group.into_iter().map(|i| (i, size)));
+        } else {
+            candidates.extend(group);
+        }
+    }
+    let fingerprints: Vec<(usize, String)> = large
+        .par_iter()
+        .filter_map(|&(i, size)| Some((i, compute_partial_sha256(&paths[i], size)?)))
+        .collect();
+    stats.partial_hashes = fingerprints.len();
+    let mut by_fingerprint: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, fingerprint) in fingerprints {
+        by_fingerprint.entry(fingerprint).or_default().push(i);
+    }
+    candidates.extend(
+        by_fingerprint
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .flatten(),
+    );
+
+    candidates.sort_unstable();
+    let candidates: Vec<String> = candidates.into_iter().map(|i| paths[i].clone()).collect();
+    let computed_before = cache.computed();
+    let hashes = cache.sha256_all(&candidates);
+    stats.full_hashes = cache.computed() - computed_before;
+
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, hash) in hashes {
+        groups.entry(hash).or_default().push(path);
+    }
+    groups.retain(|_, paths| paths.len() > 1);
+    (groups, stats)
+}
+
+// --- Duplicate Removal ---
+
+/// Which file of a duplicate group to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepStrategy {
+    Oldest,
+    Newest,
+    LargestResolution,
+    ShortestPath,
+}
+
+impl KeepStrategy {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "oldest" => Ok(KeepStrategy::Oldest),
+            "newest" => Ok(KeepStrategy::Newest),
+            "largest_resolution" => Ok(KeepStrategy::LargestResolution),
+            "shortest_path" => Ok(KeepStrategy::ShortestPath),
+            other => bail!(
+                "Unknown keep strategy: {} (expected oldest, newest, largest_resolution or shortest_path)",
+                other
+            ),
+        }
+    }
+
+    /// Index of the path in `paths` to keep. Files that can't be read lose
+    /// to any that can; ties go to the earlier path.
+    pub fn choose(self, paths: &[String]) -> usize {
+        // Lower is better
+        let rank = |path: &String| -> Option<i64> {
+            match self {
+                KeepStrategy::Oldest => FileStamp::of(Path::new(path)).map(|s| s.modified_ns),
+                KeepStrategy::Newest => FileStamp::of(Path::new(path)).map(|s| -s.modified_ns),
+                KeepStrategy::LargestResolution => image::image_dimensions(path)
+                    .ok()
+                    .map(|(w, h)| -(w as i64 * h as i64)),
+                KeepStrategy::ShortestPath => Some(path.chars().count() as i64),
+            }
+        };
+        let ranks: Vec<Option<i64>> = paths.iter().map(rank).collect();
+        (0..paths.len())
+            .min_by_key(|&i| (ranks[i].is_none(), ranks[i], i))
+            .unwrap_or(0)
+    }
+}
+
+/// A group of identical files and which of them to keep
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "python", derive(IntoPyObject))]
+pub struct DuplicateGroup {
+    pub keep: String,
+    pub remove: Vec<String>,
+}
+
+impl DuplicateGroup {
+    pub fn suggest(mut paths: Vec<String>, strategy: KeepStrategy) -> Self {
+        paths.sort();
+        let keep = paths.remove(strategy.choose(&paths));
+        DuplicateGroup {
+            keep,
+            remove: paths,
+        }
+    }
+}
+
+/// `(path, reason)` of each file `delete_duplicates_core` left alone
+pub type SkippedFiles = Vec<(String, String)>;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DeletionReport {
+    /// Deleted, or for a dry run, would be deleted
+    pub removed: Vec<String>,
+    pub skipped: SkippedFiles,
+}
+
+/// Deletes each group's `remove` files that still have the same contents as
+/// its `keep` file; a group whose `keep` file is gone or unreadable is left
+/// alone entirely
+pub fn delete_duplicates_core(
+    groups: &HashMap<String, DuplicateGroup>,
+    dry_run: bool,
+) -> DeletionReport {
+    let mut report = DeletionReport::default();
+    let mut keys: Vec<&String> = groups.keys().collect();
+    keys.sort();
+    for key in keys {
+        let group = &groups[key];
+        let Some(keep_hash) = compute_sha256(&group.keep) else {
+            for path in &group.remove {
+                let reason = format!("The file to keep, {}, can't be read", group.keep);
+                report.skipped.push((path.clone(), reason));
+            }
+            continue;
+        };
+        let keep_path = fs::canonicalize(&group.keep).ok();
+        for path in &group.remove {
+            let reason = if fs::canonicalize(path).ok() == keep_path {
+                Some("It is the file to keep".to_string())
+            } else {
+                match compute_sha256(path) {
+                    None => Some("It can't be read".to_string()),
+                    Some(hash) if hash != keep_hash => {
+                        Some(format!("Its contents no longer match {}", group.keep))
+                    }
+                    Some(_) if dry_run => None,
+                    Some(_) => fs::remove_file(path).err().map(|e| e.to_string()),
+                }
+            };
+            match reason {
+                Some(reason) => report.skipped.push((path.clone(), reason)),
+                None => report.removed.push(path.clone()),
+            }
+        }
+    }
+    report
+}
+
 // --- PyFunctions ---
 
+#[cfg(feature = "python")]
+fn load_cache(cache_path: Option<&str>) -> PyResult<HashCache> {
+    match cache_path {
+        Some(path) => {
+            HashCache::load(Path::new(path)).map_err(|e| PyIOError::new_err(format!("{:#}", e)))
+        }
+        None => Ok(HashCache::default()),
+    }
+}
+
+#[cfg(feature = "python")]
+fn save_cache(cache: &HashCache, cache_path: Option<&str>) -> PyResult<()> {
+    match cache_path {
+        Some(path) => cache
+            .save(Path::new(path))
+            .map_err(|e| PyIOError::new_err(format!("{:#}", e))),
+        None => Ok(()),
+    }
+}
+
+/// One or several directories, so Python can pass a single path or a list
+#[cfg(feature = "python")]
+#[derive(FromPyObject)]
+pub enum Directories {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[cfg(feature = "python")]
+impl Directories {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Directories::One(directory) => vec![directory],
+            Directories::Many(directories) => directories,
+        }
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<String> for Directories {
+    fn from(directory: String) -> Self {
+        Directories::One(directory)
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<Vec<String>> for Directories {
+    fn from(directories: Vec<String>) -> Self {
+        Directories::Many(directories)
+    }
+}
+
+/// `find_duplicate_images`' result: the paths of each group, or with a keep
+/// strategy, which of them to keep
+#[cfg(feature = "python")]
+#[derive(Debug, IntoPyObject)]
+pub enum DuplicateGroups {
+    Paths(HashMap<String, Vec<String>>),
+    Suggested(HashMap<String, DuplicateGroup>),
+}
+
+/// Groups of files under `directories` with identical contents, keyed by
+/// their SHA-256. A file reached through more than one of the directories
+/// is only counted once. With `keep_strategy` (`oldest`, `newest`,
+/// `largest_resolution` or `shortest_path`) each group is a `{"keep": path,
+/// "remove": [paths]}` dict instead of a list. With `cache_path`, hashes of
+/// files unchanged since an earlier scan are read from that cache file,
+/// which is then updated.
 #[cfg(feature = "python")]
 #[pyfunction]
+#[pyo3(signature = (directories, extensions, recursive, cache_path=None, keep_strategy=None))]
 pub fn find_duplicate_images(
     py: Python,
-    directory: String,
+    directories: Directories,
     extensions: Vec<String>,
     recursive: bool,
-) -> PyResult<HashMap<String, Vec<String>>> {
+    cache_path: Option<String>,
+    keep_strategy: Option<&str>,
+) -> PyResult<DuplicateGroups> {
+    let keep_strategy = keep_strategy
+        .map(KeepStrategy::parse)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let mut cache = load_cache(cache_path.as_deref())?;
     let exts: Vec<String> = extensions
         .iter()
         .map(|e| e.trim_start_matches('.').to_lowercase())
         .collect();
 
-    let duplicates: HashMap<String, Vec<String>> = py.detach(|| {
-        let walker = if recursive {
-            WalkDir::new(&directory).into_iter()
-        } else {
-            WalkDir::new(&directory).max_depth(1).into_iter()
-        };
+    let duplicates = py.detach(|| {
+        let mut seen = HashSet::new();
+        let mut paths = Vec::new();
+        for directory in directories.into_vec() {
+            let walker = if recursive {
+                WalkDir::new(&directory).into_iter()
+            } else {
+                WalkDir::new(&directory).max_depth(1).into_iter()
+            };
 
-        let paths: Vec<String> = walker
-            .filter_map(|e: Result<walkdir::DirEntry, walkdir::Error>| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .filter(|e| {
-                e.path()
-                    .extension()
-                    .and_then(|s| s.to_str())
-                    .map(|s| exts.contains(&s.to_lowercase()))
-                    .unwrap_or(false)
-            })
-            .map(|e| e.path().to_string_lossy().to_string())
-            .collect();
+            let found = walker
+                .filter_map(|e: Result<walkdir::DirEntry, walkdir::Error>| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .filter(|e| {
+                    e.path()
+                        .extension()
+                        .and_then(|s| s.to_str())
+                        .map(|s| exts.contains(&s.to_lowercase()))
+                        .unwrap_or(false)
+                })
+                // Overlapping directories would otherwise make a file its
+                // own duplicate
+                .filter(|e| seen.insert(fs::canonicalize(e.path()).unwrap_or(e.path().into())))
+                .map(|e| e.path().to_string_lossy().to_string());
+            paths.extend(found);
+        }
 
-        let hashes: Vec<(String, String)> = paths
-            .par_iter()
-            .filter_map(|p| compute_sha256(p).map(|h| (h, p.clone())))
-            .collect();
+        let (groups, _) = group_duplicates(&paths, &mut cache, DEFAULT_PARTIAL_HASH_MIN_SIZE);
 
-        let mut groups: HashMap<String, Vec<String>> = HashMap::new();
-        for (hash, path) in hashes {
-            groups.entry(hash).or_default().push(path);
+        match keep_strategy {
+            None => DuplicateGroups::Paths(groups),
+            Some(strategy) => DuplicateGroups::Suggested(
+                groups
+                    .into_iter()
+                    .map(|(hash, paths)| (hash, DuplicateGroup::suggest(paths, strategy)))
+                    .collect(),
+            ),
         }
-
-        groups
-            .into_iter()
-            .filter(|(_, v): &(String, Vec<String>)| v.len() > 1)
-            .collect()
     });
 
+    save_cache(&cache, cache_path.as_deref())?;
     Ok(duplicates)
 }
 
+/// Deletes the `remove` files of the groups in `groups_json`, as returned by
+/// `find_duplicate_images` with a keep strategy. A file is only deleted
+/// while it still has the same contents as its group's `keep` file. Returns
+/// the deleted paths, or with `dry_run` the ones that would be, and the
+/// `(path, reason)` of every file left alone.
 #[cfg(feature = "python")]
 #[pyfunction]
+pub fn delete_duplicates(
+    py: Python,
+    groups_json: &str,
+    dry_run: bool,
+) -> PyResult<(Vec<String>, SkippedFiles)> {
+    let groups: HashMap<String, DuplicateGroup> = serde_json::from_str(groups_json)
+        .map_err(|e| PyValueError::new_err(format!("Invalid duplicate groups: {}", e)))?;
+    let report = py.detach(|| delete_duplicates_core(&groups, dry_run));
+    Ok((report.removed, report.skipped))
+}
+
+/// Groups of images under `directory` whose hashes differ in at most
+/// `threshold` of 64 bits, hashed with `algorithm`: `ahash` (the default),
+/// `phash` or `dhash`. `cache_path` works as for `find_duplicate_images`.
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (directory, extensions, threshold, algorithm="ahash", cache_path=None))]
 pub fn find_similar_images_phash(
     py: Python,
     directory: String,
     extensions: Vec<String>,
     threshold: u32,
+    algorithm: &str,
+    cache_path: Option<String>,
 ) -> PyResult<HashMap<String, Vec<String>>> {
+    let algorithm =
+        HashAlgorithm::parse(algorithm).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let mut cache = load_cache(cache_path.as_deref())?;
     let exts: Vec<String> = extensions
         .iter()
         .map(|e| e.trim_start_matches('.').to_lowercase())
@@ -159,54 +876,78 @@ pub fn find_similar_images_phash(
             .map(|e| e.path().to_string_lossy().to_string())
             .collect();
 
-        let path_hashes: Vec<(String, u64)> =
-            paths.par_iter().filter_map(|p| compute_phash(p)).collect();
-
-        // Grouping
-        let mut results = HashMap::new();
-        let mut visited = vec![false; path_hashes.len()];
-        let mut group_id = 0;
-
-        for i in 0..path_hashes.len() {
-            if visited[i] {
-                continue;
-            }
-
-            let mut group = vec![path_hashes[i].0.clone()];
-            visited[i] = true;
-            let hash_a = path_hashes[i].1;
-
-            for j in (i + 1)..path_hashes.len() {
-                if visited[j] {
-                    continue;
-                }
+        let path_hashes = cache.image_hash_all(&paths, algorithm);
 
-                let hash_b = path_hashes[j].1;
+        let hashes: Vec<u64> = path_hashes.iter().map(|(_, hash)| *hash).collect();
+        group_similar(&hashes, threshold)
+            .into_iter()
+            .enumerate()
+            .map(|(group_id, group)| {
+                let paths = group.into_iter().map(|i| path_hashes[i].0.clone());
+                (format!("group_{}", group_id), paths.collect())
+            })
+            .collect()
+    });
 
-                if hamming_distance(hash_a, hash_b) <= threshold {
-                    group.push(path_hashes[j].0.clone());
-                    visited[j] = true;
-                }
-            }
+    save_cache(&cache, cache_path.as_deref())?;
+    Ok(groups)
+}
 
-            if group.len() > 1 {
-                results.insert(format!("group_{}", group_id), group);
-                group_id += 1;
-            }
-        }
+/// Drops entries for files that no longer exist from the hash cache at
+/// `cache_path`; returns how many were dropped
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn prune_hash_cache(py: Python, cache_path: String) -> PyResult<usize> {
+    py.detach(|| {
+        let mut cache = load_cache(Some(&cache_path))?;
+        let pruned = cache.prune();
+        save_cache(&cache, Some(&cache_path))?;
+        Ok(pruned)
+    })
+}
 
-        results
-    });
+/// Writes a copy of the hash cache at `cache_path` to `destination`;
+/// returns how many entries it holds
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn export_hash_cache(py: Python, cache_path: String, destination: String) -> PyResult<usize> {
+    py.detach(|| {
+        let cache = load_cache(Some(&cache_path))?;
+        save_cache(&cache, Some(&destination))?;
+        Ok(cache.len())
+    })
+}
 
-    Ok(groups)
+/// Merges the hash cache exported to `source` into the one at `cache_path`,
+/// its entries replacing any for the same paths; returns how many it held
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn import_hash_cache(py: Python, cache_path: String, source: String) -> PyResult<usize> {
+    py.detach(|| {
+        let imported = load_cache(Some(&source))?;
+        let mut cache = load_cache(Some(&cache_path))?;
+        let count = cache.merge(imported);
+        save_cache(&cache, Some(&cache_path))?;
+        Ok(count)
+    })
 }
 
 #[cfg(all(test, feature = "python"))]
 mod tests {
     use super::*;
     use image::{Rgb, RgbImage};
+    use pyo3::types::PyDict;
     use tempfile::tempdir;
 
+    fn plain(groups: PyResult<DuplicateGroups>) -> HashMap<String, Vec<String>> {
+        match groups.unwrap() {
+            DuplicateGroups::Paths(groups) => groups,
+            DuplicateGroups::Suggested(groups) => {
+                panic!("Unexpected keep suggestions: {:?}", groups)
+            }
+        }
+    }
+
     #[test]
     fn test_find_duplicates() {
         let dir = tempdir().unwrap();
@@ -232,13 +973,14 @@ mod tests {
 
         Python::initialize();
         Python::attach(|py| {
-            let dups = find_duplicate_images(
+            let dups = plain(find_duplicate_images(
                 py,
-                dir.path().to_str().unwrap().to_string(),
+                dir.path().to_str().unwrap().to_string().into(),
                 vec!["png".to_string()],
                 false,
-            )
-            .unwrap();
+                None,
+                None,
+            ));
             assert_eq!(dups.len(), 1);
             let paths = dups.values().next().unwrap();
             assert_eq!(paths.len(), 2);
@@ -286,6 +1028,8 @@ mod tests {
                 dir.path().to_str().unwrap().to_string(),
                 vec!["png".to_string()],
                 5,
+                "ahash",
+                None,
             )
             .unwrap();
 
@@ -312,4 +1056,541 @@ mod tests {
             );
         });
     }
+
+    #[test]
+    fn test_find_similar_algorithms() {
+        let dir = tempdir().unwrap();
+
+        // Flat wallpapers that differ only in where a dark patch sits
+        fn wallpaper(patch_x: u32, patch_y: u32) -> RgbImage {
+            RgbImage::from_fn(200, 150, |x, y| {
+                let in_patch =
+                    (patch_x..patch_x + 32).contains(&x) && (patch_y..patch_y + 32).contains(&y);
+                if in_patch {
+                    Rgb([40, 40, 60])
+                } else {
+                    Rgb([70, 130, 180])
+                }
+            })
+        }
+        let original = wallpaper(30, 30);
+        // 5% cropped: 2.5% off every side
+        let cropped = image::imageops::crop_imm(&original, 5, 3, 190, 144).to_image();
+        original.save(dir.path().join("a.png")).unwrap();
+        cropped.save(dir.path().join("a_cropped.png")).unwrap();
+        wallpaper(150, 100).save(dir.path().join("b.png")).unwrap();
+        wallpaper(140, 30).save(dir.path().join("c.png")).unwrap();
+
+        Python::initialize();
+        Python::attach(|py| {
+            let groups = |algorithm: &str| {
+                let mut groups: Vec<Vec<String>> = find_similar_images_phash(
+                    py,
+                    dir.path().to_str().unwrap().to_string(),
+                    vec!["png".to_string()],
+                    16,
+                    algorithm,
+                    None,
+                )
+                .unwrap()
+                .into_values()
+                .map(|group| {
+                    let mut names: Vec<String> = group
+                        .iter()
+                        .map(|p| p.rsplit('/').next().unwrap().to_string())
+                        .collect();
+                    names.sort();
+                    names
+                })
+                .collect();
+                groups.sort();
+                groups
+            };
+
+            // The average and difference hashes see mostly the flat colour
+            // and lump them together
+            assert_eq!(groups("ahash").concat().len(), 4);
+            assert_eq!(groups("dhash").concat().len(), 4);
+            // The DCT hash tells the layouts apart but still matches the crop
+            assert_eq!(groups("phash"), [["a.png", "a_cropped.png"]]);
+            assert!(find_similar_images_phash(py, String::new(), vec![], 5, "md5", None).is_err());
+        });
+    }
+
+    #[test]
+    fn test_phash_matches_cropped_photo() {
+        // A landscape print scanned with a dark edge: flat sky over a
+        // furrowed field with a dark hill and a bright lake. Trimming 5%
+        // takes the edge off, which lifts the average brightness past the
+        // sky's, so the average hash flips every sky bit; the DCT hash
+        // follows the layout and barely moves.
+        let scan = RgbImage::from_fn(400, 300, |x, y| {
+            if !(8..392).contains(&x) || !(8..292).contains(&y) {
+                return Rgb([20, 20, 20]);
+            }
+            let (u, v) = (x as f64 / 400.0, y as f64 / 300.0);
+            let l = if v < 0.45 {
+                150.0
+            } else {
+                let hill = u < 0.45 && v < 0.45 + 0.35 * (1.0 - (u - 0.22).abs() / 0.23);
+                let lake = (u - 0.72).powi(2) / 0.04 + (v - 0.78).powi(2) / 0.02 < 1.0;
+                let furrows = 15.0 * (std::f64::consts::TAU * 18.0 * (v + 0.3 * u)).sin();
+                let feature = match (hill, lake) {
+                    (true, _) => -60.0,
+                    (_, true) => 60.0,
+                    _ => 0.0,
+                };
+                160.0 + furrows + feature
+            };
+            Rgb([l.clamp(0.0, 255.0) as u8; 3])
+        });
+        // 2.5% off every side
+        let cropped = image::imageops::crop_imm(&scan, 10, 7, 380, 286).to_image();
+
+        let distance = |algorithm: HashAlgorithm| {
+            hamming_distance(
+                algorithm.hash(&DynamicImage::ImageRgb8(scan.clone())),
+                algorithm.hash(&DynamicImage::ImageRgb8(cropped.clone())),
+            )
+        };
+        let (average, perceptual) = (
+            distance(HashAlgorithm::Average),
+            distance(HashAlgorithm::Perceptual),
+        );
+        assert!(average > 16, "ahash distance {}", average);
+        assert!(perceptual <= 16, "phash distance {}", perceptual);
+
+        let dir = tempdir().unwrap();
+        scan.save(dir.path().join("scan.png")).unwrap();
+        cropped.save(dir.path().join("scan_cropped.png")).unwrap();
+        Python::initialize();
+        Python::attach(|py| {
+            let group_sizes = |algorithm: &str| {
+                find_similar_images_phash(
+                    py,
+                    dir.path().to_str().unwrap().to_string(),
+                    vec!["png".to_string()],
+                    16,
+                    algorithm,
+                    None,
+                )
+                .unwrap()
+                .into_values()
+                .map(|group| group.len())
+                .collect::<Vec<_>>()
+            };
+            assert!(group_sizes("ahash").is_empty());
+            assert_eq!(group_sizes("phash"), [2]);
+        });
+    }
+
+    #[test]
+    fn test_group_similar_matches_brute_force() {
+        // The pairwise grouping find_similar_images_phash used to do
+        fn brute_force(hashes: &[u64], threshold: u32) -> Vec<Vec<usize>> {
+            let mut visited = vec![false; hashes.len()];
+            let mut groups = Vec::new();
+            for i in 0..hashes.len() {
+                if visited[i] {
+                    continue;
+                }
+                visited[i] = true;
+                let mut group = vec![i];
+                for j in (i + 1)..hashes.len() {
+                    if !visited[j] && hamming_distance(hashes[i], hashes[j]) <= threshold {
+                        group.push(j);
+                        visited[j] = true;
+                    }
+                }
+                if group.len() > 1 {
+                    groups.push(group);
+                }
+            }
+            groups
+        }
+
+        // xorshift64, so the test set is the same on every run
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        // Clusters of near-identical hashes among unrelated ones, with
+        // some exact repeats
+        let centres: Vec<u64> = (0..40).map(|_| next()).collect();
+        let hashes: Vec<u64> = (0..2000)
+            .map(|_| match next() % 4 {
+                0 => next(),
+                _ => {
+                    let mut hash = centres[(next() % 40) as usize];
+                    for _ in 0..next() % 8 {
+                        hash ^= 1 << (next() % 64);
+                    }
+                    hash
+                }
+            })
+            .collect();
+
+        for threshold in [0, 3, 5, 10, 16] {
+            assert_eq!(
+                group_similar(&hashes, threshold),
+                brute_force(&hashes, threshold),
+                "threshold {}",
+                threshold
+            );
+        }
+        assert!(group_similar(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn test_hash_cache() {
+        let dir = tempdir().unwrap();
+        let images = dir.path().join("images");
+        std::fs::create_dir(&images).unwrap();
+        let cache_path = dir.path().join("hashes.json");
+        let solid = |name: &str, color: [u8; 3]| {
+            RgbImage::from_pixel(40, 40, Rgb(color))
+                .save(images.join(name))
+                .unwrap();
+        };
+        solid("red.png", [255, 0, 0]);
+        solid("red_copy.png", [255, 0, 0]);
+        solid("green.png", [0, 255, 0]);
+
+        Python::initialize();
+        Python::attach(|py| {
+            let directory = images.to_str().unwrap().to_string();
+            let cache = Some(cache_path.to_str().unwrap().to_string());
+            let duplicates = |cache: &Option<String>| {
+                let mut groups: Vec<Vec<String>> = plain(find_duplicate_images(
+                    py,
+                    directory.clone().into(),
+                    vec!["png".into()],
+                    false,
+                    cache.clone(),
+                    None,
+                ))
+                .into_values()
+                .map(|group| {
+                    let mut names: Vec<String> = group
+                        .iter()
+                        .map(|p| p.rsplit('/').next().unwrap().to_string())
+                        .collect();
+                    names.sort();
+                    names
+                })
+                .collect();
+                groups.sort();
+                groups
+            };
+
+            assert_eq!(duplicates(&cache), [["red.png", "red_copy.png"]]);
+            find_similar_images_phash(
+                py,
+                directory.clone(),
+                vec!["png".into()],
+                0,
+                "phash",
+                cache.clone(),
+            )
+            .unwrap();
+            let stored = HashCache::load(&cache_path).unwrap();
+            assert_eq!(stored.len(), 3);
+            let green = images.join("green.png").to_string_lossy().to_string();
+            assert!(stored.get(&green).unwrap().sha256.is_some());
+            assert!(stored
+                .get(&green)
+                .unwrap()
+                .image_hashes
+                .contains_key("phash"));
+
+            // Unchanged files are not hashed again: a planted hash is used as is
+            let red = images.join("red.png").to_string_lossy().to_string();
+            let mut planted = HashCache::load(&cache_path).unwrap();
+            let mut entry = planted.get(&green).unwrap().clone();
+            entry.sha256 = planted.get(&red).unwrap().sha256.clone();
+            planted.entries.insert(green.clone(), entry);
+            planted.save(&cache_path).unwrap();
+            assert_eq!(
+                duplicates(&cache),
+                [["green.png", "red.png", "red_copy.png"]]
+            );
+            // Without the cache everything is hashed
+            assert_eq!(duplicates(&None), [["red.png", "red_copy.png"]]);
+
+            // A changed file is hashed again, and its other cached hashes dropped
+            solid("green.png", [0, 255, 1]);
+            std::fs::File::options()
+                .append(true)
+                .open(images.join("green.png"))
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+                .unwrap();
+            assert_eq!(duplicates(&cache), [["red.png", "red_copy.png"]]);
+            find_similar_images_phash(
+                py,
+                directory.clone(),
+                vec!["png".into()],
+                0,
+                "ahash",
+                cache.clone(),
+            )
+            .unwrap();
+            let stored = HashCache::load(&cache_path).unwrap();
+            let green_entry = stored.get(&green).unwrap();
+            assert!(green_entry.sha256.is_none());
+            assert_eq!(
+                green_entry.image_hashes.keys().collect::<Vec<_>>(),
+                ["ahash"]
+            );
+
+            // Dead entries are pruned; export and import carry the rest over
+            std::fs::remove_file(images.join("red_copy.png")).unwrap();
+            assert_eq!(prune_hash_cache(py, cache.clone().unwrap()).unwrap(), 1);
+            let exported = dir.path().join("export.json").to_string_lossy().to_string();
+            assert_eq!(
+                export_hash_cache(py, cache.clone().unwrap(), exported.clone()).unwrap(),
+                2
+            );
+            let fresh = dir.path().join("fresh.json").to_string_lossy().to_string();
+            assert_eq!(import_hash_cache(py, fresh.clone(), exported).unwrap(), 2);
+            assert!(HashCache::load(Path::new(&fresh))
+                .unwrap()
+                .get(&red)
+                .is_some());
+
+            std::fs::write(&cache_path, "{oops").unwrap();
+            assert!(find_duplicate_images(
+                py,
+                directory.clone().into(),
+                vec![],
+                false,
+                cache.clone(),
+                None
+            )
+            .is_err());
+        });
+    }
+
+    #[test]
+    fn test_duplicates_across_directories() {
+        let dir = tempdir().unwrap();
+        let pictures = dir.path().join("pictures");
+        let drive = dir.path().join("drive");
+        let nas = dir.path().join("nas").join("photos");
+        for folder in [&pictures, &drive, &nas] {
+            std::fs::create_dir_all(folder).unwrap();
+        }
+        let small = RgbImage::from_pixel(20, 10, Rgb([200, 10, 10]));
+        small.save(pictures.join("sunset.png")).unwrap();
+        std::fs::copy(pictures.join("sunset.png"), drive.join("sunset_backup.png")).unwrap();
+        std::fs::copy(pictures.join("sunset.png"), nas.join("s.png")).unwrap();
+        RgbImage::from_pixel(20, 10, Rgb([10, 10, 200]))
+            .save(drive.join("sea.png"))
+            .unwrap();
+        // Oldest first: the backup, then the original, then the NAS copy
+        let age = |path: &Path, seconds: u64| {
+            std::fs::File::options()
+                .append(true)
+                .open(path)
+                .unwrap()
+                .set_modified(
+                    std::time::SystemTime::now() - std::time::Duration::from_secs(seconds),
+                )
+                .unwrap();
+        };
+        age(&drive.join("sunset_backup.png"), 3000);
+        age(&pictures.join("sunset.png"), 2000);
+        age(&nas.join("s.png"), 1000);
+
+        Python::initialize();
+        Python::attach(|py| {
+            let roots: Vec<String> = [&pictures, &drive, &dir.path().join("nas")]
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect();
+            let suggest = |roots: Vec<String>, strategy: &str| match find_duplicate_images(
+                py,
+                Directories::Many(roots),
+                vec!["png".into()],
+                true,
+                None,
+                Some(strategy),
+            )
+            .unwrap()
+            {
+                DuplicateGroups::Suggested(groups) => {
+                    assert_eq!(groups.len(), 1);
+                    groups.into_values().next().unwrap()
+                }
+                DuplicateGroups::Paths(_) => panic!("No keep suggestions"),
+            };
+            let name = |path: &str| path.rsplit('/').next().unwrap().to_string();
+
+            let oldest = suggest(roots.clone(), "oldest");
+            assert_eq!(name(&oldest.keep), "sunset_backup.png");
+            assert_eq!(oldest.remove.len(), 2);
+            assert_eq!(name(&suggest(roots.clone(), "newest").keep), "s.png");
+            // nas/photos/s.png
+            assert_eq!(name(&suggest(roots.clone(), "shortest_path").keep), "s.png");
+            // All the same resolution, so the first path in order
+            assert_eq!(
+                name(&suggest(roots.clone(), "largest_resolution").keep),
+                "sunset_backup.png"
+            );
+
+            // A directory inside another one doesn't count its files twice
+            let mut overlapping = roots.clone();
+            overlapping.push(nas.to_string_lossy().to_string());
+            assert_eq!(suggest(overlapping, "oldest"), oldest);
+            // A single directory is still accepted, as a plain string
+            let single = plain(find_duplicate_images(
+                py,
+                pictures.to_string_lossy().to_string().into(),
+                vec!["png".into()],
+                true,
+                None,
+                None,
+            ));
+            assert!(single.is_empty());
+            assert!(find_duplicate_images(
+                py,
+                roots.clone().into(),
+                vec![],
+                true,
+                None,
+                Some("random")
+            )
+            .is_err());
+
+            // As Python sees it
+            let result = find_duplicate_images(
+                py,
+                roots.clone().into(),
+                vec!["png".into()],
+                true,
+                None,
+                Some("oldest"),
+            )
+            .unwrap()
+            .into_pyobject(py)
+            .unwrap()
+            .cast_into::<PyDict>()
+            .unwrap();
+            let group = result.values().get_item(0).unwrap();
+            assert_eq!(
+                group.get_item("keep").unwrap().extract::<String>().unwrap(),
+                oldest.keep
+            );
+
+            // Deleting: a dry run first, then for real, except a file that
+            // changed since the scan
+            let groups = HashMap::from([("hash".to_string(), oldest.clone())]);
+            let json = serde_json::to_string(&groups).unwrap();
+            let (removed, skipped) = delete_duplicates(py, &json, true).unwrap();
+            assert_eq!(removed.len(), 2);
+            assert!(skipped.is_empty());
+            assert!(oldest.remove.iter().all(|p| Path::new(p).exists()));
+
+            std::fs::write(&oldest.remove[1], b"edited").unwrap();
+            let (removed, skipped) = delete_duplicates(py, &json, false).unwrap();
+            assert_eq!(removed, [oldest.remove[0].clone()]);
+            assert_eq!(skipped[0].0, oldest.remove[1]);
+            assert!(!Path::new(&oldest.remove[0]).exists());
+            assert!(Path::new(&oldest.keep).exists());
+
+            // Never the file being kept, even if listed for removal too
+            let itself = DuplicateGroup {
+                keep: oldest.keep.clone(),
+                remove: vec![oldest.keep.clone()],
+            };
+            let report = delete_duplicates_core(&HashMap::from([("x".into(), itself)]), false);
+            assert!(report.removed.is_empty());
+            assert!(Path::new(&oldest.keep).exists());
+            assert!(delete_duplicates(py, "[1, 2]", true).is_err());
+        });
+    }
+
+    #[test]
+    fn test_group_duplicates_hashes_only_collisions() {
+        let dir = tempdir().unwrap();
+        let mut paths = Vec::new();
+        let mut write = |name: &str, bytes: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, bytes).unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        };
+        // Sizes nothing else has: never read
+        write("a.jpg", &[1; 10]);
+        write("b.jpg", &[1; 20]);
+        write("c.mp4", &[1; 30]);
+        // Same size, small: hashed whole
+        write("d.jpg", &[1; 100]);
+        write("e.jpg", &[2; 100]);
+        write("f.jpg", &[3; 200]);
+        write("f_copy.jpg", &[3; 200]);
+        // Same size, large: fingerprinted, then hashed whole if the
+        // fingerprints match
+        let video = vec![7; 3 * 1024 * 1024];
+        write("h.mp4", &video);
+        write("h_copy.mp4", &video);
+        let mut other_start = video.clone();
+        other_start.push(0);
+        write("j.mp4", &other_start);
+        other_start[0] = 0;
+        write("k.mp4", &other_start);
+        let mut other_middle = video.clone();
+        other_middle.extend([0, 0]);
+        write("l.mp4", &other_middle);
+        other_middle[1024 * 1024 + 5] = 0;
+        write("m.mp4", &other_middle);
+
+        let names = |groups: HashMap<String, Vec<String>>| {
+            let mut names: Vec<Vec<String>> = groups
+                .into_values()
+                .map(|group| {
+                    group
+                        .iter()
+                        .map(|p| p.rsplit('/').next().unwrap().to_string())
+                        .collect()
+                })
+                .collect();
+            names.sort();
+            names
+        };
+        let expected = [["f.jpg", "f_copy.jpg"], ["h.mp4", "h_copy.mp4"]];
+
+        let mut cache = HashCache::default();
+        let (groups, stats) = group_duplicates(&paths, &mut cache, 1024);
+        assert_eq!(names(groups), expected);
+        assert_eq!(
+            stats,
+            DuplicateScanStats {
+                partial_hashes: 6,
+                full_hashes: 8,
+            }
+        );
+
+        // Below the threshold every size collision is hashed whole
+        let (groups, stats) = group_duplicates(
+            &paths,
+            &mut HashCache::default(),
+            DEFAULT_PARTIAL_HASH_MIN_SIZE,
+        );
+        assert_eq!(names(groups), expected);
+        assert_eq!(
+            stats,
+            DuplicateScanStats {
+                partial_hashes: 0,
+                full_hashes: 10,
+            }
+        );
+
+        // Cached hashes aren't recomputed
+        let (groups, stats) = group_duplicates(&paths, &mut cache, 1024);
+        assert_eq!(names(groups), expected);
+        assert_eq!(stats.full_hashes, 0);
+    }
 }
@@ -1,16 +1,25 @@
+#[cfg(feature = "python")]
+use super::image_converter::resize_options;
+use super::image_converter::save_image;
+use super::text;
 use anyhow::{anyhow, Result};
 use fast_image_resize as fr;
-use image::{DynamicImage, ImageReader, RgbaImage};
+use image::{DynamicImage, ImageReader, Rgba, RgbaImage};
 #[cfg(feature = "python")]
 use pyo3::exceptions::PyValueError;
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
 // §2.12 — Two-pass streaming merger: Pass 1 reads only image headers (width/height)
 // via image::image_dimensions() so canvas dimensions can be computed without loading
 // any pixel data.  Pass 2 loads and blits one image at a time, dropping each
 // DynamicImage immediately after the overlay.  Peak RAM = 1 image + output canvas,
-// instead of N images + output canvas.
+// instead of N images + output canvas.  Canvases over `MergeOutput::pixel_budget`
+// going to PNG are kept in a raw file beside the output and streamed through the
+// PNG encoder row by row, so not even the canvas has to fit in RAM.
 
 fn load_img(path: &str) -> Result<DynamicImage> {
     ImageReader::open(path)
@@ -24,7 +33,256 @@ fn read_dimensions(path: &str) -> Result<(u32, u32)> {
     image::image_dimensions(path).map_err(|e| anyhow!("Failed to read dimensions of {}: {}", path, e))
 }
 
-fn fast_resize(img: DynamicImage, w: u32, h: u32) -> DynamicImage {
+/// Canvas color for a `#RRGGBB`, `#RRGGBBAA` or `transparent` background
+pub fn parse_background(spec: &str) -> Result<Rgba<u8>> {
+    parse_color(spec, "background")
+}
+
+/// `#RRGGBB`, `#RRGGBBAA` or `transparent`; `what` names the color in the
+/// error
+fn parse_color(spec: &str, what: &str) -> Result<Rgba<u8>> {
+    let spec = spec.trim();
+    if spec.eq_ignore_ascii_case("transparent") {
+        return Ok(Rgba([0, 0, 0, 0]));
+    }
+    let invalid = || {
+        anyhow!(
+            "Invalid {} color '{}': expected #RRGGBB, #RRGGBBAA or transparent",
+            what,
+            spec
+        )
+    };
+    let hex = spec.strip_prefix('#').ok_or_else(invalid)?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    let alpha = if hex.len() == 8 { channel(6) } else { 255 };
+    Ok(Rgba([channel(0), channel(2), channel(4), alpha]))
+}
+
+/// The canvas background and how the merged image is saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeOutput {
+    pub background: Rgba<u8>,
+    /// Format name such as `png` or `jpg`; without one, the output path's
+    /// extension
+    pub format: Option<String>,
+    /// 1-100 for JPEG and AVIF, and makes WebP lossy, as in
+    /// `convert_single_image`
+    pub quality: Option<u8>,
+    /// Canvases with more pixels than this are assembled on disk when the
+    /// output is PNG
+    pub pixel_budget: u64,
+}
+
+/// 256 megapixels, a 1GB RGBA canvas
+pub const DEFAULT_PIXEL_BUDGET: u64 = 256 * 1024 * 1024;
+
+impl Default for MergeOutput {
+    fn default() -> Self {
+        MergeOutput {
+            background: Rgba([255, 255, 255, 255]),
+            format: None,
+            quality: None,
+            pixel_budget: DEFAULT_PIXEL_BUDGET,
+        }
+    }
+}
+
+impl MergeOutput {
+    /// From the pyfunctions' `background`, `output_format` and `quality`;
+    /// no background is white
+    pub fn new(
+        background: Option<&str>,
+        format: Option<String>,
+        quality: Option<u8>,
+    ) -> Result<Self> {
+        Ok(MergeOutput {
+            background: match background {
+                Some(spec) => parse_background(spec)?,
+                None => MergeOutput::default().background,
+            },
+            format,
+            quality,
+            ..MergeOutput::default()
+        })
+    }
+
+    fn format(&self, output_path: &str) -> Result<String> {
+        match &self.format {
+            Some(format) => Ok(format.to_lowercase()),
+            None => Path::new(output_path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(str::to_lowercase)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No output format given and {} has no extension",
+                        output_path
+                    )
+                }),
+        }
+    }
+
+    /// A `w`x`h` canvas of the background color for `output_path`
+    fn canvas(&self, w: u32, h: u32, output_path: &str) -> Result<Canvas> {
+        if w as u64 * h as u64 <= self.pixel_budget || self.format(output_path)? != "png" {
+            return Ok(Canvas::Memory(RgbaImage::from_pixel(w, h, self.background)));
+        }
+        SpilledCanvas::create(output_path, w, h, self.background).map(Canvas::Spilled)
+    }
+
+    fn save(&self, canvas: Canvas, output_path: &str) -> Result<()> {
+        let canvas = match canvas {
+            Canvas::Memory(canvas) => canvas,
+            Canvas::Spilled(canvas) => return canvas.save_png(output_path),
+        };
+        let format = self.format(output_path)?;
+        save_image(&DynamicImage::ImageRgba8(canvas), output_path, &format, self.quality)
+    }
+}
+
+/// The merged image while it's being put together
+enum Canvas {
+    Memory(RgbaImage),
+    Spilled(SpilledCanvas),
+}
+
+impl Canvas {
+    /// Blend `img` over the canvas with its top-left at `(x, y)`
+    fn overlay(&mut self, img: &DynamicImage, x: u32, y: u32) -> Result<()> {
+        match self {
+            Canvas::Memory(canvas) => {
+                image::imageops::overlay(canvas, img, x as i64, y as i64);
+                Ok(())
+            }
+            Canvas::Spilled(canvas) => {
+                let img = img.to_rgba8();
+                canvas.edit(x, y, img.width(), img.height(), |region| {
+                    image::imageops::overlay(region, &img, 0, 0)
+                })
+            }
+        }
+    }
+
+    /// Run `f` over the `w`x`h` region at `(x, y)`, clipped to the canvas
+    fn edit(
+        &mut self,
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+        f: impl FnOnce(&mut RgbaImage),
+    ) -> Result<()> {
+        match self {
+            Canvas::Memory(canvas) => {
+                let mut region = image::imageops::crop_imm(canvas, x, y, w, h).to_image();
+                f(&mut region);
+                image::imageops::replace(canvas, &region, x as i64, y as i64);
+                Ok(())
+            }
+            Canvas::Spilled(canvas) => canvas.edit(x, y, w, h, f),
+        }
+    }
+}
+
+/// A canvas kept as raw RGBA rows in a file beside the output, deleted
+/// when dropped
+struct SpilledCanvas {
+    file: File,
+    path: PathBuf,
+    width: u32,
+    height: u32,
+}
+
+impl SpilledCanvas {
+    fn create(output_path: &str, width: u32, height: u32, background: Rgba<u8>) -> Result<Self> {
+        let path = Path::new(output_path).with_extension("canvas.tmp");
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+        let canvas = SpilledCanvas {
+            file,
+            path,
+            width,
+            height,
+        };
+        let row = background.0.repeat(width as usize);
+        let mut writer = BufWriter::new(&canvas.file);
+        for _ in 0..height {
+            writer.write_all(&row)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        Ok(canvas)
+    }
+
+    fn offset(&self, x: u32, y: u32) -> u64 {
+        (y as u64 * self.width as u64 + x as u64) * 4
+    }
+
+    fn edit(
+        &mut self,
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+        f: impl FnOnce(&mut RgbaImage),
+    ) -> Result<()> {
+        let w = w.min(self.width.saturating_sub(x));
+        let h = h.min(self.height.saturating_sub(y));
+        if w == 0 || h == 0 {
+            return Ok(());
+        }
+        let row_len = w as usize * 4;
+        let mut region = vec![0; row_len * h as usize];
+        for (r, row) in region.chunks_exact_mut(row_len).enumerate() {
+            self.file
+                .seek(SeekFrom::Start(self.offset(x, y + r as u32)))?;
+            self.file.read_exact(row)?;
+        }
+        let mut region = RgbaImage::from_raw(w, h, region).unwrap();
+        f(&mut region);
+        for (r, row) in region.chunks_exact(row_len).enumerate() {
+            self.file
+                .seek(SeekFrom::Start(self.offset(x, y + r as u32)))?;
+            self.file.write_all(row)?;
+        }
+        Ok(())
+    }
+
+    fn save_png(mut self, output_path: &str) -> Result<()> {
+        let out = File::create(output_path).map_err(|e| anyhow!("Failed to save image: {}", e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(out), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        let mut stream = writer.stream_writer()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut rows = BufReader::new(&self.file);
+        let mut row = vec![0; self.width as usize * 4];
+        for _ in 0..self.height {
+            rows.read_exact(&mut row)?;
+            stream.write_all(&row)?;
+        }
+        stream.finish()?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+impl Drop for SpilledCanvas {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn fast_resize(img: DynamicImage, w: u32, h: u32, options: &fr::ResizeOptions) -> DynamicImage {
     let src_w = img.width();
     let src_h = img.height();
     if src_w == w && src_h == h {
@@ -41,7 +299,7 @@ fn fast_resize(img: DynamicImage, w: u32, h: u32) -> DynamicImage {
 
     let mut dst_image = fr::images::Image::new(w, h, fr::PixelType::U8x4);
     let mut resizer = fr::Resizer::new();
-    resizer.resize(&src_image, &mut dst_image, None).unwrap();
+    resizer.resize(&src_image, &mut dst_image, options).unwrap();
 
     DynamicImage::ImageRgba8(RgbaImage::from_raw(w, h, dst_image.into_vec()).unwrap())
 }
@@ -51,6 +309,8 @@ pub fn merge_images_horizontal_core(
     output_path: &str,
     spacing: u32,
     align_mode: &str,
+    resize: &fr::ResizeOptions,
+    output: &MergeOutput,
 ) -> Result<bool> {
     if image_paths.is_empty() {
         return Ok(false);
@@ -70,10 +330,7 @@ pub fn merge_images_horizontal_core(
     let total_width: u32 = dims.iter().map(|&(w, _)| w).sum::<u32>()
         + (spacing * (dims.len() as u32 - 1));
 
-    let mut canvas = RgbaImage::new(total_width, max_h);
-    for p in canvas.pixels_mut() {
-        *p = image::Rgba([255, 255, 255, 255]);
-    }
+    let mut canvas = output.canvas(total_width, max_h, output_path)?;
 
     // Pass 2 — load, blit, drop one image at a time
     let mut current_x: u32 = 0;
@@ -87,7 +344,7 @@ pub fn merge_images_horizontal_core(
 
         let img = if align_mode == "stretch" || align_mode == "squish" {
             let w = img.width();
-            fast_resize(img, w, max_h)
+            fast_resize(img, w, max_h, resize)
         } else {
             img
         };
@@ -99,7 +356,7 @@ pub fn merge_images_horizontal_core(
             _ => 0,
         };
 
-        image::imageops::overlay(&mut canvas, &img, current_x as i64, y_offset as i64);
+        canvas.overlay(&img, current_x, y_offset)?;
         current_x += w + spacing;
         blitted += 1;
         // img is dropped here, freeing its pixel buffer immediately
@@ -109,22 +366,41 @@ pub fn merge_images_horizontal_core(
         return Ok(false);
     }
 
-    canvas
-        .save(output_path)
-        .map_err(|e| anyhow!("Failed to save: {}", e))?;
+    output.save(canvas, output_path)?;
     Ok(true)
 }
 
+/// `filter` picks the resize filter for the `stretch` and `squish` modes,
+/// as in `convert_single_image`. `background` is `#RRGGBB`, `#RRGGBBAA` or
+/// `transparent` (white by default); `output_format` overrides the output
+/// path's extension and `quality` is as in `convert_single_image`.
 #[cfg(feature = "python")]
 #[pyfunction]
+#[pyo3(signature = (image_paths, output_path, spacing, align_mode, filter=None, background=None, output_format=None, quality=None))]
+#[allow(clippy::too_many_arguments)]
 pub fn merge_images_horizontal(
     image_paths: Vec<String>,
     output_path: String,
     spacing: u32,
     align_mode: String,
+    filter: Option<String>,
+    background: Option<String>,
+    output_format: Option<String>,
+    quality: Option<u8>,
 ) -> PyResult<bool> {
-    merge_images_horizontal_core(&image_paths, &output_path, spacing, &align_mode)
-        .map_err(|e| PyValueError::new_err(format!("{}", e)))
+    let resize =
+        resize_options(filter.as_deref()).map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+    let output = MergeOutput::new(background.as_deref(), output_format, quality)
+        .map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+    merge_images_horizontal_core(
+        &image_paths,
+        &output_path,
+        spacing,
+        &align_mode,
+        &resize,
+        &output,
+    )
+    .map_err(|e| PyValueError::new_err(format!("{}", e)))
 }
 
 pub fn merge_images_vertical_core(
@@ -132,6 +408,7 @@ pub fn merge_images_vertical_core(
     output_path: &str,
     spacing: u32,
     align_mode: &str,
+    output: &MergeOutput,
 ) -> Result<bool> {
     if image_paths.is_empty() {
         return Ok(false);
@@ -151,10 +428,7 @@ pub fn merge_images_vertical_core(
     let total_height: u32 = dims.iter().map(|&(_, h)| h).sum::<u32>()
         + (spacing * (dims.len() as u32 - 1));
 
-    let mut canvas = RgbaImage::new(max_w, total_height);
-    for p in canvas.pixels_mut() {
-        *p = image::Rgba([255, 255, 255, 255]);
-    }
+    let mut canvas = output.canvas(max_w, total_height, output_path)?;
 
     // Pass 2 — load, blit, drop one image at a time
     let mut current_y: u32 = 0;
@@ -173,7 +447,7 @@ pub fn merge_images_vertical_core(
             _ => 0,
         };
 
-        image::imageops::overlay(&mut canvas, &img, x_offset as i64, current_y as i64);
+        canvas.overlay(&img, x_offset, current_y)?;
         current_y += h + spacing;
         blitted += 1;
         // img is dropped here, freeing its pixel buffer immediately
@@ -183,75 +457,222 @@ pub fn merge_images_vertical_core(
         return Ok(false);
     }
 
-    canvas
-        .save(output_path)
-        .map_err(|e| anyhow!("Failed to save: {}", e))?;
+    output.save(canvas, output_path)?;
     Ok(true)
 }
 
+/// `background`, `output_format` and `quality` as in `merge_images_horizontal`
 #[cfg(feature = "python")]
 #[pyfunction]
+#[pyo3(signature = (image_paths, output_path, spacing, align_mode, background=None, output_format=None, quality=None))]
 pub fn merge_images_vertical(
     image_paths: Vec<String>,
     output_path: String,
     spacing: u32,
     align_mode: String,
+    background: Option<String>,
+    output_format: Option<String>,
+    quality: Option<u8>,
 ) -> PyResult<bool> {
-    merge_images_vertical_core(&image_paths, &output_path, spacing, &align_mode)
+    let output = MergeOutput::new(background.as_deref(), output_format, quality)
+        .map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+    merge_images_vertical_core(&image_paths, &output_path, spacing, &align_mode, &output)
         .map_err(|e| PyValueError::new_err(format!("{}", e)))
 }
 
+/// How an image is fitted into its grid cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellMode {
+    /// Unscaled, centered in the cell
+    Fit,
+    /// Scaled to fill the cell, keeping its aspect ratio, and the overflow
+    /// cropped evenly off both sides
+    Cover,
+    /// Stretched to the cell's size
+    Resize,
+}
+
+impl CellMode {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "fit" => Ok(CellMode::Fit),
+            "cover" => Ok(CellMode::Cover),
+            "resize" => Ok(CellMode::Resize),
+            other => Err(anyhow!(
+                "Unknown cell mode: {} (expected fit, cover or resize)",
+                other
+            )),
+        }
+    }
+}
+
+/// `(rows, cols)` of a grid for `count` images. A 0 for either means "as
+/// many as needed"; with both 0 the grid is as near square as it can be,
+/// wider rather than taller.
+pub fn grid_shape(count: u32, rows: u32, cols: u32) -> Result<(u32, u32)> {
+    let count = count.max(1);
+    let (rows, cols) = match (rows, cols) {
+        (0, 0) => {
+            let cols = (count as f64).sqrt().ceil() as u32;
+            (count.div_ceil(cols), cols)
+        }
+        (0, cols) => (count.div_ceil(cols), cols),
+        (rows, 0) => (rows, count.div_ceil(rows)),
+        (rows, cols) => (rows, cols),
+    };
+    if count > rows * cols {
+        return Err(anyhow!(
+            "{} images don't fit in a {}x{} grid",
+            count,
+            rows,
+            cols
+        ));
+    }
+    Ok((rows, cols))
+}
+
+fn fit_to_cell(
+    img: DynamicImage,
+    cell_w: u32,
+    cell_h: u32,
+    mode: CellMode,
+    resize: &fr::ResizeOptions,
+) -> DynamicImage {
+    match mode {
+        CellMode::Fit => img,
+        CellMode::Resize => fast_resize(img, cell_w, cell_h, resize),
+        CellMode::Cover => {
+            let scale = f64::max(
+                cell_w as f64 / img.width() as f64,
+                cell_h as f64 / img.height() as f64,
+            );
+            let w = ((img.width() as f64 * scale).round() as u32).max(cell_w);
+            let h = ((img.height() as f64 * scale).round() as u32).max(cell_h);
+            fast_resize(img, w, h, resize).crop_imm(
+                (w - cell_w) / 2,
+                (h - cell_h) / 2,
+                cell_w,
+                cell_h,
+            )
+        }
+    }
+}
+
+/// Captions drawn in a strip under each grid cell
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridLabels {
+    /// One per image path, in order; images past the end go without
+    pub labels: Vec<String>,
+    /// Pixel height of the text
+    pub font_size: f32,
+    pub color: Rgba<u8>,
+}
+
+impl GridLabels {
+    /// From the pyfunction's `labels`, `label_font_size` and `label_color`
+    pub fn new(labels: Vec<String>, font_size: f32, color: &str) -> Result<Self> {
+        if !(font_size.is_finite() && font_size > 0.0) {
+            return Err(anyhow!(
+                "Label font size must be positive, got {}",
+                font_size
+            ));
+        }
+        Ok(GridLabels {
+            labels,
+            font_size,
+            color: parse_color(color, "label")?,
+        })
+    }
+
+    /// Height of the caption strip: a line of text with a quarter of the
+    /// font size above and below it
+    pub fn strip_height(&self) -> u32 {
+        (text::line_height(self.font_size) + self.font_size / 2.0).ceil() as u32
+    }
+
+    /// Draw caption `idx`, if there is one, centered in `strip`. Text wider
+    /// than the strip is clipped.
+    fn draw(&self, strip: &mut RgbaImage, idx: usize) {
+        let Some(rendered) = self
+            .labels
+            .get(idx)
+            .and_then(|label| text::render_text(label, self.font_size, self.color))
+        else {
+            return;
+        };
+        let x = strip.width().saturating_sub(rendered.width()) / 2;
+        let y = (self.font_size / 4.0) as i64;
+        image::imageops::overlay(strip, &rendered, x as i64, y);
+    }
+}
+
+/// Lays the readable images out row by row in cells the size of the largest
+/// one. `rows` or `cols` of 0 is worked out from the image count (see
+/// `grid_shape`); more images than the grid holds is an error. With
+/// `labels`, every row gets a caption strip under its cells.
+#[allow(clippy::too_many_arguments)]
 pub fn merge_images_grid_core(
     image_paths: &[String],
     output_path: &str,
     rows: u32,
     cols: u32,
     spacing: u32,
+    cell_mode: CellMode,
+    resize: &fr::ResizeOptions,
+    labels: Option<&GridLabels>,
+    output: &MergeOutput,
 ) -> Result<bool> {
     if image_paths.is_empty() {
         return Ok(false);
     }
 
-    // Pass 1 — read headers only to find max cell dimensions
-    let dims: Vec<(u32, u32)> = image_paths
+    // Pass 1 — read headers only to find max cell dimensions. Each image
+    // keeps its index in `image_paths`, which picks its label.
+    let images: Vec<(usize, &String, (u32, u32))> = image_paths
         .iter()
-        .filter_map(|p| read_dimensions(p).ok())
+        .enumerate()
+        .filter_map(|(i, p)| read_dimensions(p).ok().map(|dims| (i, p, dims)))
         .collect();
 
-    if dims.is_empty() {
+    if images.is_empty() {
         return Ok(false);
     }
 
-    let max_w = dims.iter().map(|&(w, _)| w).max().unwrap();
-    let max_h = dims.iter().map(|&(_, h)| h).max().unwrap();
+    let (rows, cols) = grid_shape(images.len() as u32, rows, cols)?;
+    let max_w = images.iter().map(|&(_, _, (w, _))| w).max().unwrap();
+    let max_h = images.iter().map(|&(_, _, (_, h))| h).max().unwrap();
+    let labels = labels.filter(|labels| !labels.labels.is_empty());
+    let strip_h = labels.map_or(0, GridLabels::strip_height);
+    let row_h = max_h + strip_h;
 
     let total_w = cols * max_w + (spacing * (cols - 1));
-    let total_h = rows * max_h + (spacing * (rows - 1));
+    let total_h = rows * row_h + (spacing * (rows - 1));
 
-    let mut canvas = RgbaImage::new(total_w, total_h);
-    for p in canvas.pixels_mut() {
-        *p = image::Rgba([255, 255, 255, 255]);
-    }
+    let mut canvas = output.canvas(total_w, total_h, output_path)?;
 
     // Pass 2 — load, blit, drop one image at a time
     let mut blitted = 0usize;
 
-    for (idx, path) in image_paths.iter().enumerate() {
+    for (idx, &(path_idx, path, _)) in images.iter().enumerate() {
         let row = idx as u32 / cols;
         let col = idx as u32 % cols;
-        if row >= rows {
-            break;
-        }
 
         let img = match load_img(path) {
-            Ok(i) => i,
+            Ok(i) => fit_to_cell(i, max_w, max_h, cell_mode, resize),
             Err(_) => continue,
         };
 
-        let x = col * (max_w + spacing) + (max_w - img.width()) / 2;
-        let y = row * (max_h + spacing) + (max_h - img.height()) / 2;
+        let cell_x = col * (max_w + spacing);
+        let cell_y = row * (row_h + spacing);
+        let x = cell_x + (max_w - img.width()) / 2;
+        let y = cell_y + (max_h - img.height()) / 2;
 
-        image::imageops::overlay(&mut canvas, &img, x as i64, y as i64);
+        canvas.overlay(&img, x, y)?;
+        if let Some(labels) = labels {
+            canvas.edit(cell_x, cell_y + max_h, max_w, strip_h, |strip| {
+                labels.draw(strip, path_idx)
+            })?;
+        }
         blitted += 1;
         // img is dropped here, freeing its pixel buffer immediately
     }
@@ -260,23 +681,57 @@ pub fn merge_images_grid_core(
         return Ok(false);
     }
 
-    canvas
-        .save(output_path)
-        .map_err(|e| anyhow!("Failed to save: {}", e))?;
+    output.save(canvas, output_path)?;
     Ok(true)
 }
 
+/// `rows` and `cols` default to 0, worked out from the image count.
+/// `cell_mode` is `fit` (the default), `cover` or `resize`, and `filter`
+/// picks the resize filter for the latter two, as in `convert_single_image`.
+/// `background`, `output_format` and `quality` as in
+/// `merge_images_horizontal`. `labels` captions the images in order, in
+/// `label_color` (`#RRGGBB` or `#RRGGBBAA`) at `label_font_size` pixels.
 #[cfg(feature = "python")]
 #[pyfunction]
+#[pyo3(signature = (image_paths, output_path, rows=0, cols=0, spacing=0, cell_mode="fit", filter=None, background=None, output_format=None, quality=None, labels=None, label_font_size=16.0, label_color="#000000"))]
+#[allow(clippy::too_many_arguments)]
 pub fn merge_images_grid(
     image_paths: Vec<String>,
     output_path: String,
     rows: u32,
     cols: u32,
     spacing: u32,
+    cell_mode: &str,
+    filter: Option<String>,
+    background: Option<String>,
+    output_format: Option<String>,
+    quality: Option<u8>,
+    labels: Option<Vec<String>>,
+    label_font_size: f32,
+    label_color: &str,
 ) -> PyResult<bool> {
-    merge_images_grid_core(&image_paths, &output_path, rows, cols, spacing)
-        .map_err(|e| PyValueError::new_err(format!("{}", e)))
+    let cell_mode =
+        CellMode::parse(cell_mode).map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+    let resize =
+        resize_options(filter.as_deref()).map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+    let output = MergeOutput::new(background.as_deref(), output_format, quality)
+        .map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+    let labels = labels
+        .map(|labels| GridLabels::new(labels, label_font_size, label_color))
+        .transpose()
+        .map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+    merge_images_grid_core(
+        &image_paths,
+        &output_path,
+        rows,
+        cols,
+        spacing,
+        cell_mode,
+        &resize,
+        labels.as_ref(),
+        &output,
+    )
+    .map_err(|e| PyValueError::new_err(format!("{}", e)))
 }
 
 #[cfg(test)]
@@ -310,7 +765,14 @@ mod tests {
             p2.to_str().unwrap().to_string(),
         ];
 
-        match merge_images_horizontal_core(&paths, out.to_str().unwrap(), 0, "top") {
+        match merge_images_horizontal_core(
+            &paths,
+            out.to_str().unwrap(),
+            0,
+            "top",
+            &fr::ResizeOptions::new(),
+            &MergeOutput::default(),
+        ) {
             Ok(res) => assert!(res),
             Err(e) => panic!("Merge failed: {}", e),
         }
@@ -335,7 +797,13 @@ mod tests {
             p2.to_str().unwrap().to_string(),
         ];
 
-        match merge_images_vertical_core(&paths, out.to_str().unwrap(), 0, "left") {
+        match merge_images_vertical_core(
+            &paths,
+            out.to_str().unwrap(),
+            0,
+            "left",
+            &MergeOutput::default(),
+        ) {
             Ok(res) => assert!(res),
             Err(e) => panic!("Merge failed: {}", e),
         }
@@ -357,7 +825,15 @@ mod tests {
             })
             .collect();
         let out = dir.path().join("h_stream.png");
-        assert!(merge_images_horizontal_core(&paths, out.to_str().unwrap(), 5, "center").unwrap());
+        assert!(merge_images_horizontal_core(
+            &paths,
+            out.to_str().unwrap(),
+            5,
+            "center",
+            &fr::ResizeOptions::new(),
+            &MergeOutput::default()
+        )
+        .unwrap());
         let img = image::open(&out).unwrap();
         // width = sum(100,120,140,160,180) + 4*5 spacing = 700 + 20 = 720
         assert_eq!(img.width(), 720);
@@ -376,7 +852,14 @@ mod tests {
             })
             .collect();
         let out = dir.path().join("v_stream.png");
-        assert!(merge_images_vertical_core(&paths, out.to_str().unwrap(), 2, "center").unwrap());
+        assert!(merge_images_vertical_core(
+            &paths,
+            out.to_str().unwrap(),
+            2,
+            "center",
+            &MergeOutput::default()
+        )
+        .unwrap());
         let img = image::open(&out).unwrap();
         // height = sum(40,55,70,85) + 3*2 = 250 + 6 = 256
         assert_eq!(img.height(), 256);
@@ -395,20 +878,339 @@ mod tests {
             })
             .collect();
         let out = dir.path().join("g_stream.png");
-        assert!(merge_images_grid_core(&paths, out.to_str().unwrap(), 2, 3, 10).unwrap());
+        assert!(merge_images_grid_core(
+            &paths,
+            out.to_str().unwrap(),
+            2,
+            3,
+            10,
+            CellMode::Fit,
+            &fr::ResizeOptions::new(),
+            None,
+            &MergeOutput::default()
+        )
+        .unwrap());
         let img = image::open(&out).unwrap();
         // total_w = 3*50 + 2*10 = 170; total_h = 2*50 + 1*10 = 110
         assert_eq!(img.width(), 170);
         assert_eq!(img.height(), 110);
     }
 
+    #[test]
+    fn test_merge_grid_auto_shape_and_cell_modes() {
+        let dir = tempdir().unwrap();
+        let mut paths: Vec<String> = (0..5u32)
+            .map(|i| {
+                let p = dir.path().join(format!("{}.png", i));
+                create_test_image(p.to_str().unwrap(), 40, 40, [0, 0, 255]);
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        // A wide one: a 100x50 cell for everything
+        let wide = dir.path().join("wide.png");
+        create_test_image(wide.to_str().unwrap(), 100, 20, [255, 0, 0]);
+        paths.insert(0, wide.to_str().unwrap().to_string());
+        let out = dir.path().join("grid.png");
+        let merge = |rows, cols, mode| {
+            merge_images_grid_core(
+                &paths,
+                out.to_str().unwrap(),
+                rows,
+                cols,
+                0,
+                mode,
+                &fr::ResizeOptions::new(),
+                None,
+                &MergeOutput::default(),
+            )
+            .map(|_| image::open(&out).unwrap().to_rgba8())
+        };
+
+        // 6 images: 2 rows of 3, or as many rows or columns as needed
+        let img = merge(0, 0, CellMode::Fit).unwrap();
+        assert_eq!(img.dimensions(), (300, 80));
+        assert_eq!(merge(0, 2, CellMode::Fit).unwrap().dimensions(), (200, 120));
+        assert_eq!(merge(1, 0, CellMode::Fit).unwrap().dimensions(), (600, 40));
+        assert_eq!(grid_shape(10, 0, 0).unwrap(), (3, 4));
+        assert_eq!(grid_shape(9, 0, 0).unwrap(), (3, 3));
+        // Too many for the grid asked for is an error, not a truncation
+        let err = merge(2, 2, CellMode::Fit).unwrap_err();
+        assert!(err.to_string().contains("6 images"), "{}", err);
+
+        // Fit leaves white around the wide image; cover and resize fill
+        // its cell with it
+        assert_eq!(img.get_pixel(50, 5).0, [255, 255, 255, 255]);
+        assert_eq!(img.get_pixel(50, 25).0, [255, 0, 0, 255]);
+        for mode in [CellMode::Cover, CellMode::Resize] {
+            let img = merge(0, 0, mode).unwrap();
+            assert_eq!(img.dimensions(), (300, 80));
+            assert_eq!(img.get_pixel(50, 5).0, [255, 0, 0, 255]);
+            assert_eq!(img.get_pixel(150, 5).0, [0, 0, 255, 255]);
+            assert_eq!(img.get_pixel(105, 45).0, [0, 0, 255, 255]);
+        }
+        assert!(CellMode::parse("stretch").is_err());
+    }
+
+    #[test]
+    fn test_merge_background_and_output_format() {
+        let dir = tempdir().unwrap();
+        let p1 = dir.path().join("1.png");
+        let p2 = dir.path().join("2.png");
+        create_test_image(p1.to_str().unwrap(), 40, 40, [255, 0, 0]);
+        create_test_image(p2.to_str().unwrap(), 20, 20, [0, 255, 0]);
+        let paths = vec![
+            p1.to_str().unwrap().to_string(),
+            p2.to_str().unwrap().to_string(),
+        ];
+
+        // Transparent between and around the images, for sprite sheets
+        let sheet = dir.path().join("sheet.png");
+        let transparent = MergeOutput::new(Some("transparent"), None, None).unwrap();
+        assert!(merge_images_vertical_core(
+            &paths,
+            sheet.to_str().unwrap(),
+            4,
+            "left",
+            &transparent
+        )
+        .unwrap());
+        let img = image::open(&sheet).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(30, 50).0, [0, 0, 0, 0]);
+        assert_eq!(img.get_pixel(5, 50).0, [0, 255, 0, 255]);
+
+        // A dark JPEG collage at quality 90, whatever the path's extension
+        let collage = dir.path().join("collage.out");
+        let dark = MergeOutput::new(Some("#202020"), Some("jpeg".into()), Some(90)).unwrap();
+        assert!(merge_images_grid_core(
+            &paths,
+            collage.to_str().unwrap(),
+            1,
+            0,
+            0,
+            CellMode::Fit,
+            &fr::ResizeOptions::new(),
+            None,
+            &dark,
+        )
+        .unwrap());
+        let reader = ImageReader::open(&collage)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(reader.format(), Some(image::ImageFormat::Jpeg));
+        let img = reader.decode().unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (80, 40));
+        assert!(img
+            .get_pixel(60, 2)
+            .0
+            .iter()
+            .all(|&c| c.abs_diff(0x20) <= 3));
+
+        assert_eq!(
+            parse_background("#11223380").unwrap().0,
+            [0x11, 0x22, 0x33, 0x80]
+        );
+        assert_eq!(parse_background("Transparent").unwrap().0, [0, 0, 0, 0]);
+        for bad in ["white", "#12345", "#1234567", "#GG0000", "112233", ""] {
+            let err = parse_background(bad).unwrap_err().to_string();
+            assert!(err.contains("#RRGGBB, #RRGGBBAA or transparent"), "{}", err);
+        }
+        // No format and no extension to go by
+        let no_ext = dir.path().join("merged");
+        assert!(merge_images_vertical_core(
+            &paths,
+            no_ext.to_str().unwrap(),
+            0,
+            "left",
+            &MergeOutput::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_merge_grid_labels() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = (0..4u32)
+            .map(|i| {
+                let p = dir.path().join(format!("{}.png", i));
+                create_test_image(p.to_str().unwrap(), 60, 30, [0, 0, 255]);
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let out = dir.path().join("grid.png");
+        let merge = |labels: Option<&GridLabels>| {
+            merge_images_grid_core(
+                &paths,
+                out.to_str().unwrap(),
+                2,
+                2,
+                10,
+                CellMode::Fit,
+                &fr::ResizeOptions::new(),
+                labels,
+                &MergeOutput::default(),
+            )
+            .map(|_| image::open(&out).unwrap().to_rgba8())
+        };
+        let plain = merge(None).unwrap();
+        assert_eq!(plain.dimensions(), (130, 70));
+
+        // Only the first two images have a caption
+        let labels =
+            GridLabels::new(vec!["first".into(), "second".into()], 16.0, "#ff0000").unwrap();
+        let strip_h = labels.strip_height();
+        assert!(strip_h >= 16, "{}", strip_h);
+        let img = merge(Some(&labels)).unwrap();
+        // Every row grows by the strip, labeled or not
+        assert_eq!(img.dimensions(), (130, 70 + 2 * strip_h));
+
+        let strip = |x0: u32, y0: u32| {
+            (y0..y0 + strip_h).flat_map(move |y| (x0..x0 + 60).map(move |x| (x, y)))
+        };
+        let inked = |x0, y0| {
+            strip(x0, y0)
+                .filter(|&(x, y)| {
+                    let [r, g, b, _] = img.get_pixel(x, y).0;
+                    r > 200 && g < 100 && b < 100
+                })
+                .count()
+        };
+        assert!(inked(0, 30) > 20);
+        assert!(inked(70, 30) > 20);
+        // Images past the labels' end get a blank strip
+        let second_row = 30 + strip_h + 10 + 30;
+        for x0 in [0, 70] {
+            assert!(strip(x0, second_row).all(|(x, y)| img.get_pixel(x, y).0 == [255; 4]));
+        }
+        // Images themselves stay where they were in each row
+        assert_eq!(img.get_pixel(30, 15).0, [0, 0, 255, 255]);
+        assert_eq!(
+            img.get_pixel(30, 30 + strip_h + 10 + 15).0,
+            [0, 0, 255, 255]
+        );
+
+        // No labels at all is the plain grid
+        let empty = GridLabels::new(Vec::new(), 16.0, "#000000").unwrap();
+        assert_eq!(merge(Some(&empty)).unwrap().dimensions(), (130, 70));
+        assert!(GridLabels::new(vec!["a".into()], 0.0, "#000000").is_err());
+        let err = GridLabels::new(vec!["a".into()], 12.0, "red").unwrap_err();
+        assert!(err.to_string().contains("label color"), "{}", err);
+    }
+
+    #[test]
+    fn test_spilled_canvas_matches_in_memory() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = [(50, 30), (20, 60), (40, 40)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| {
+                let p = dir.path().join(format!("{}.png", i));
+                create_test_image(p.to_str().unwrap(), w, h, [i as u8 * 100, 50, 200]);
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let in_memory = MergeOutput {
+            background: Rgba([10, 20, 30, 128]),
+            ..MergeOutput::default()
+        };
+        let spilled = MergeOutput {
+            pixel_budget: 0,
+            ..in_memory.clone()
+        };
+        let labels = GridLabels::new(vec!["a".into(), "bb".into()], 12.0, "#ffffff80").unwrap();
+        let resize = fr::ResizeOptions::new();
+        let merge = |output: &MergeOutput, name: &str| {
+            let out = dir.path().join(name);
+            let out = out.to_str().unwrap();
+            assert!(
+                merge_images_horizontal_core(&paths, out, 5, "center", &resize, output).unwrap()
+            );
+            let horizontal = image::open(out).unwrap().to_rgba8();
+            assert!(merge_images_vertical_core(&paths, out, 5, "right", output).unwrap());
+            let vertical = image::open(out).unwrap().to_rgba8();
+            assert!(merge_images_grid_core(
+                &paths,
+                out,
+                0,
+                2,
+                3,
+                CellMode::Fit,
+                &resize,
+                Some(&labels),
+                output
+            )
+            .unwrap());
+            let grid = image::open(out).unwrap().to_rgba8();
+            (horizontal, vertical, grid)
+        };
+
+        let expected = merge(&in_memory, "memory.png");
+        assert_eq!(expected.0.dimensions(), (120, 60));
+        assert_eq!(merge(&spilled, "spilled.png"), expected);
+        // The raw canvas is cleaned up, and only PNG output spills
+        assert!(!dir.path().join("spilled.canvas.tmp").exists());
+        assert!(merge_images_vertical_core(
+            &paths,
+            dir.path().join("spilled.jpg").to_str().unwrap(),
+            0,
+            "left",
+            &spilled
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_dimension_pass_reads_headers_only() {
+        // A header for a 60000x40000 PNG (9.6GB decoded) with no pixel data
+        // behind it: its one IDAT chunk holds just the zlib header
+        let dir = tempdir().unwrap();
+        let huge = dir.path().join("huge.png");
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 60_000, 40_000);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_chunk(png::chunk::IDAT, &[0x78, 0x9c]).unwrap();
+        drop(writer);
+        std::fs::write(&huge, &bytes).unwrap();
+        let huge = huge.to_str().unwrap();
+
+        assert_eq!(read_dimensions(huge).unwrap(), (60_000, 40_000));
+        assert!(load_img(huge).is_err());
+    }
+
     #[test]
     fn test_empty_paths_returns_false() {
         let dir = tempdir().unwrap();
         let out = dir.path().join("empty.png");
         let paths: Vec<String> = vec![];
-        assert!(!merge_images_horizontal_core(&paths, out.to_str().unwrap(), 0, "top").unwrap());
-        assert!(!merge_images_vertical_core(&paths, out.to_str().unwrap(), 0, "left").unwrap());
-        assert!(!merge_images_grid_core(&paths, out.to_str().unwrap(), 2, 2, 0).unwrap());
+        assert!(!merge_images_horizontal_core(
+            &paths,
+            out.to_str().unwrap(),
+            0,
+            "top",
+            &fr::ResizeOptions::new(),
+            &MergeOutput::default()
+        )
+        .unwrap());
+        assert!(!merge_images_vertical_core(
+            &paths,
+            out.to_str().unwrap(),
+            0,
+            "left",
+            &MergeOutput::default()
+        )
+        .unwrap());
+        assert!(!merge_images_grid_core(
+            &paths,
+            out.to_str().unwrap(),
+            2,
+            2,
+            0,
+            CellMode::Fit,
+            &fr::ResizeOptions::new(),
+            None,
+            &MergeOutput::default()
+        )
+        .unwrap());
     }
 }
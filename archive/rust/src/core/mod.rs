@@ -2,7 +2,9 @@ pub mod file_system;
 pub mod image_converter;
 pub mod image_finder;
 pub mod image_merger;
+mod text;
 pub mod video_converter;
 pub mod wallpaper;
+#[cfg(feature = "vault")]
 pub mod secure_vector_db;
 
@@ -0,0 +1,62 @@
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+
+/// DejaVu Sans, so text looks the same wherever the toolkit runs
+static FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+
+fn font() -> FontRef<'static> {
+    FontRef::try_from_slice(FONT).expect("bundled font is valid")
+}
+
+/// Height of a line of text `size` pixels high, from the top of the tallest
+/// glyph to the bottom of the lowest
+pub(crate) fn line_height(size: f32) -> f32 {
+    font().as_scaled(PxScale::from(size)).height()
+}
+
+/// `text` on one line in `color` on a transparent image `line_height(size)`
+/// tall and as wide as the text. `None` when there's nothing to draw.
+pub(crate) fn render_text(text: &str, size: f32, color: Rgba<u8>) -> Option<RgbaImage> {
+    let font = font();
+    let scaled = font.as_scaled(PxScale::from(size));
+
+    // Lay the glyphs out on a baseline one ascent down
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0f32;
+    let mut previous = None;
+    for c in text.chars().filter(|c| !c.is_control()) {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(scaled.scale(), point(caret, scaled.ascent())));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    let (w, h) = (caret.ceil() as u32, scaled.height().ceil() as u32);
+    if w == 0 || h == 0 {
+        return None;
+    }
+
+    let mut img = RgbaImage::new(w, h);
+    for glyph in glyphs {
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let x = bounds.min.x as i64 + gx as i64;
+            let y = bounds.min.y as i64 + gy as i64;
+            if x < 0 || y < 0 || x >= w as i64 || y >= h as i64 {
+                return;
+            }
+            let alpha = (coverage.clamp(0.0, 1.0) * color[3] as f32).round() as u8;
+            // Glyphs that kern into each other keep the stronger coverage
+            let pixel = img.get_pixel_mut(x as u32, y as u32);
+            if alpha > pixel[3] {
+                *pixel = Rgba([color[0], color[1], color[2], alpha]);
+            }
+        });
+    }
+    Some(img)
+}
@@ -1,26 +1,593 @@
+use anyhow::{anyhow, bail, Context, Result};
+use image::DynamicImage;
 #[cfg(feature = "python")]
-use pyo3::prelude::*;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
 #[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::HashSet;
 use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// Video encoders `resolve_encoder` accepts, software first
+pub const VIDEO_ENCODERS: [&str; 12] = [
+    "libx264",
+    "libx265",
+    "h264_nvenc",
+    "hevc_nvenc",
+    "h264_qsv",
+    "hevc_qsv",
+    "h264_vaapi",
+    "hevc_vaapi",
+    "h264_videotoolbox",
+    "hevc_videotoolbox",
+    "h264_amf",
+    "hevc_amf",
+];
+
+/// Encoder used when no hardware one works
+pub const SOFTWARE_ENCODER: &str = "libx264";
+
+/// Render node VAAPI encoders upload frames to
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// Latest point a video thumbnail is taken from, in seconds
+const MAX_THUMBNAIL_SEEK: f64 = 10.0;
+
+/// Caps how many ffmpeg/ffprobe children run at once, whatever the number
+/// of threads asking
+pub struct ProcessLimit {
+    running: Mutex<usize>,
+    freed: Condvar,
+    max: usize,
+}
+
+/// A slot taken from a `ProcessLimit`, given back on drop
+pub struct ProcessSlot<'a>(&'a ProcessLimit);
+
+impl ProcessLimit {
+    pub fn new(max: usize) -> Self {
+        ProcessLimit {
+            running: Mutex::new(0),
+            freed: Condvar::new(),
+            max: max.max(1),
+        }
+    }
+
+    /// Half the available cores, at least one
+    pub fn default_max() -> usize {
+        std::thread::available_parallelism()
+            .map_or(2, |n| n.get() / 2)
+            .max(1)
+    }
+
+    /// Block until fewer than `max` slots are taken, then take one
+    pub fn acquire(&self) -> ProcessSlot<'_> {
+        let mut running = self.running.lock().unwrap();
+        while *running >= self.max {
+            running = self.freed.wait(running).unwrap();
+        }
+        *running += 1;
+        ProcessSlot(self)
+    }
+}
+
+impl Drop for ProcessSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Duration of the video at `path` in seconds, from one ffprobe call.
+/// `None` if the container doesn't say (live streams, some raw formats).
+pub fn probe_duration(path: &Path) -> Result<Option<f64>> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()
+        .context("Failed to execute ffprobe")?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|d| d.is_finite() && *d > 0.0))
+}
+
+/// Where to take a thumbnail from: a tenth of the way in, and no later than
+/// `MAX_THUMBNAIL_SEEK`, so short clips still seek inside the video
+pub fn thumbnail_seek_point(duration: Option<f64>) -> f64 {
+    duration.map_or(0.0, |d| (d / 10.0).min(MAX_THUMBNAIL_SEEK))
+}
+
+/// One frame at `seconds` into the video at `path`, as JPEG bytes, from a
+/// single-threaded ffmpeg
+pub fn extract_frame(path: &Path, seconds: f64) -> Result<Vec<u8>> {
+    let output = Command::new("ffmpeg")
+        .args(["-v", "error", "-threads", "1"])
+        .args(["-ss", &format!("{:.3}", seconds), "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2", "-c:v", "mjpeg", "pipe:1"])
+        .output()
+        .context("Failed to execute ffmpeg")?;
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    if output.stdout.is_empty() {
+        return Err(anyhow!("ffmpeg extracted no frame at {:.3}s", seconds));
+    }
+    Ok(output.stdout)
+}
+
+/// Probe `path` and extract its thumbnail frame, holding a slot of `limit`
+/// only while the work runs. Without ffmpeg on `PATH`, the first frame is
+/// decoded in-process instead if the `native-video` feature is on.
+pub fn extract_thumbnail_frame(path: &Path, limit: &ProcessLimit) -> Result<DynamicImage> {
+    let _slot = limit.acquire();
+    match probe_duration(path) {
+        Ok(duration) => {
+            let jpeg = extract_frame(path, thumbnail_seek_point(duration))?;
+            Ok(image::load_from_memory(&jpeg)?)
+        }
+        Err(e) if is_not_found(&e) => decode_first_frame(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `e` is a child process failing to start because its binary is
+/// missing
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|io| io.kind() == ErrorKind::NotFound)
+}
+
+/// Which decoder video thumbnails go through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoBackend {
+    /// The ffmpeg and ffprobe binaries on `PATH`
+    Ffmpeg,
+    /// ffmpeg's libraries linked in, through the `native-video` feature
+    Native,
+}
+
+impl VideoBackend {
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoBackend::Ffmpeg => "ffmpeg",
+            VideoBackend::Native => "native",
+        }
+    }
+}
+
+/// Whether the ffmpeg binary can be started; checked once per process
+pub fn ffmpeg_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        let status = Command::new("ffmpeg")
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        !matches!(status, Err(e) if e.kind() == ErrorKind::NotFound)
+    })
+}
+
+/// The backend video thumbnails will use, or `None` if there is neither
+pub fn video_backend() -> Option<VideoBackend> {
+    if ffmpeg_available() {
+        Some(VideoBackend::Ffmpeg)
+    } else if cfg!(feature = "native-video") {
+        Some(VideoBackend::Native)
+    } else {
+        None
+    }
+}
+
+/// The first frame of the video at `path`, decoded with ffmpeg's libraries
+#[cfg(feature = "native-video")]
+pub fn decode_first_frame(path: &Path) -> Result<DynamicImage> {
+    use ffmpeg_next::{
+        codec, format, frame, media,
+        software::scaling::{self, Flags},
+        threading,
+    };
+    use image::RgbaImage;
+
+    let error = |e: ffmpeg_next::Error| anyhow!("Failed to decode {}: {}", path.display(), e);
+    ffmpeg_next::init().map_err(error)?;
+    let mut input = format::input(&path).map_err(error)?;
+    let stream = input
+        .streams()
+        .best(media::Type::Video)
+        .ok_or_else(|| anyhow!("No video stream in {}", path.display()))?;
+    let stream_index = stream.index();
+    let mut context =
+        codec::context::Context::from_parameters(stream.parameters()).map_err(error)?;
+    // One thread, as the external binary gets
+    context.set_threading(threading::Config::count(1));
+    let mut decoder = context.decoder().video().map_err(error)?;
+    let mut scaler = scaling::Context::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        format::Pixel::RGBA,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )
+    .map_err(error)?;
+
+    let mut decoded = frame::Video::empty();
+    let mut got_frame = false;
+    for (stream, packet) in input.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet).map_err(error)?;
+        if decoder.receive_frame(&mut decoded).is_ok() {
+            got_frame = true;
+            break;
+        }
+    }
+    if !got_frame {
+        // Frames the decoder held back until the end of the stream
+        decoder.send_eof().map_err(error)?;
+        decoder
+            .receive_frame(&mut decoded)
+            .map_err(|_| anyhow!("No frame decoded from {}", path.display()))?;
+    }
+
+    let mut rgba = frame::Video::empty();
+    scaler.run(&decoded, &mut rgba).map_err(error)?;
+    let (width, height) = (rgba.width(), rgba.height());
+    let row = width as usize * 4;
+    let mut pixels = Vec::with_capacity(row * height as usize);
+    for y in 0..height as usize {
+        pixels.extend_from_slice(&rgba.data(0)[y * rgba.stride(0)..][..row]);
+    }
+    RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| anyhow!("Failed to decode {}: bad frame size", path.display()))
+}
+
+#[cfg(not(feature = "native-video"))]
+pub fn decode_first_frame(path: &Path) -> Result<DynamicImage> {
+    Err(anyhow!(
+        "ffmpeg not found: cannot decode {} without it or the `native-video` feature",
+        path.display()
+    ))
+}
+
+/// The value of a `key=value` line of ffmpeg's `-progress` output
+fn progress_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.strip_prefix(key)?.strip_prefix('=').map(str::trim)
+}
+
+/// Where an ffmpeg run stood at its latest `-progress` report
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FfmpegProgress {
+    /// Output frames written so far
+    pub frame: u64,
+    /// Seconds of output written so far
+    pub out_time: f64,
+    /// Fraction of the expected output duration written, if that is known
+    pub fraction: Option<f64>,
+    /// Encoding speed as a multiple of real time
+    pub speed: Option<f64>,
+    /// Seconds left at the current speed
+    pub eta_seconds: Option<f64>,
+}
+
+impl FfmpegProgress {
+    /// Take in one line of a report against `duration` seconds of output;
+    /// true once the line ends a report
+    fn update(&mut self, line: &str, duration: Option<f64>) -> bool {
+        if let Some(frame) = progress_value(line, "frame").and_then(|v| v.parse().ok()) {
+            self.frame = frame;
+        } else if let Some(us) = progress_value(line, "out_time_us")
+            // Microseconds too, despite the name; older ffmpeg only sends this
+            .or_else(|| progress_value(line, "out_time_ms"))
+            .and_then(|v| v.parse::<f64>().ok())
+        {
+            self.out_time = (us / 1e6).max(0.0);
+        } else if let Some(speed) = progress_value(line, "speed") {
+            self.speed = speed
+                .trim_end_matches('x')
+                .parse()
+                .ok()
+                .filter(|s: &f64| s.is_finite() && *s > 0.0);
+        } else if let Some(state) = progress_value(line, "progress") {
+            let duration = duration.filter(|d| *d > 0.0);
+            self.fraction = match state {
+                "end" => duration.map(|_| 1.0),
+                _ => duration.map(|d| (self.out_time / d).clamp(0.0, 1.0)),
+            };
+            self.eta_seconds = match (state, duration, self.speed) {
+                ("end", ..) => Some(0.0),
+                (_, Some(d), Some(speed)) => Some((d - self.out_time).max(0.0) / speed),
+                _ => None,
+            };
+            return true;
+        }
+        false
+    }
+}
+
+/// `ffmpeg` sending progress reports to stdout and only errors to stderr,
+/// for `run_ffmpeg`; inputs and outputs go after
+pub fn ffmpeg_command() -> Command {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-nostdin", "-nostats", "-progress", "pipe:1"]);
+    command
+}
+
+/// Run `command` (from `ffmpeg_command`) to the end, passing each progress
+/// report to `on_progress` with the fraction done of `duration` seconds of
+/// output, and return the last report. Once `cancelled` returns true ffmpeg
+/// is killed and an error returned.
+pub fn run_ffmpeg(
+    mut command: Command,
+    duration: Option<f64>,
+    cancelled: impl Fn() -> bool,
+    mut on_progress: impl FnMut(&FfmpegProgress),
+) -> Result<FfmpegProgress> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute ffmpeg")?;
+    // Drained alongside stdout, so ffmpeg can't stall on a full pipe
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_reader = std::thread::spawn(move || {
+        let mut message = String::new();
+        let _ = stderr.read_to_string(&mut message);
+        message
+    });
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut progress = FfmpegProgress::default();
+    for line in BufReader::new(stdout).lines().map_while(|line| line.ok()) {
+        if cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("ffmpeg cancelled");
+        }
+        if progress.update(&line, duration) {
+            on_progress(&progress);
+        }
+    }
+
+    let status = child.wait().context("Failed to wait for ffmpeg")?;
+    let stderr = stderr_reader.join().unwrap_or_default();
+    if !status.success() {
+        bail!("ffmpeg failed ({}): {}", status, stderr.trim());
+    }
+    Ok(progress)
+}
+
+/// Write a frame of the video at `video_path` every `interval_ms` into
+/// `output_dir`, as `<stem>_000001.png` and on, and return their paths in
+/// order. `on_progress` gets the fraction of the video done as ffmpeg
+/// reports it; once `cancelled` returns true ffmpeg is stopped and an error
+/// returned.
+pub fn extract_video_frames_core(
+    video_path: &Path,
+    output_dir: &Path,
+    interval_ms: u64,
+    cancelled: impl Fn() -> bool,
+    mut on_progress: impl FnMut(f64),
+) -> Result<Vec<PathBuf>> {
+    if interval_ms == 0 {
+        bail!("Frame interval must be positive");
+    }
+    let duration = probe_duration(video_path)?;
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+    let stem = video_path
+        .file_stem()
+        .map_or_else(|| "frame".into(), |s| s.to_string_lossy());
+    // `%` starts a placeholder in ffmpeg's output pattern
+    let pattern = output_dir.join(format!("{}_%06d.png", stem.replace('%', "%%")));
+
+    let mut command = ffmpeg_command();
+    command
+        .args(["-y", "-i"])
+        .arg(video_path)
+        .args(["-vf", &format!("fps=1000/{}", interval_ms)])
+        .arg(&pattern);
+    let last = run_ffmpeg(command, duration, cancelled, |progress| {
+        if let Some(fraction) = progress.fraction {
+            on_progress(fraction);
+        }
+    })?;
+    Ok((1..=last.frame)
+        .map(|n| output_dir.join(format!("{}_{:06}.png", stem, n)))
+        .collect())
+}
+
+/// Paths of the PNGs `extract_video_frames_core` writes for `video_path`
 #[cfg(feature = "python")]
-use std::process::Command;
+#[pyfunction]
+pub fn extract_video_frames(
+    py: Python,
+    video_path: String,
+    output_dir: String,
+    interval_ms: u64,
+) -> PyResult<Vec<String>> {
+    let frames = py
+        .detach(|| {
+            extract_video_frames_core(
+                Path::new(&video_path),
+                Path::new(&output_dir),
+                interval_ms,
+                || false,
+                |_| {},
+            )
+        })
+        .map_err(|e| PyRuntimeError::new_err(format!("{:#}", e)))?;
+    Ok(frames
+        .iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect())
+}
 
+/// Whether the ffmpeg binary is on `PATH`, and the backend (`"ffmpeg"` or
+/// `"native"`) video thumbnails will use, `None` if neither is available
 #[cfg(feature = "python")]
 #[pyfunction]
+pub fn probe_ffmpeg_available() -> (bool, Option<&'static str>) {
+    (ffmpeg_available(), video_backend().map(VideoBackend::name))
+}
+
+/// Names of the video encoders in `ffmpeg -encoders` output
+fn parse_encoder_list(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let flags = fields.next()?;
+            flags.starts_with('V').then(|| fields.next()).flatten()
+        })
+        .map(String::from)
+        .collect()
+}
+
+/// The video encoders this ffmpeg build has; asked once per process
+fn listed_encoders() -> &'static HashSet<String> {
+    static LISTED: OnceLock<HashSet<String>> = OnceLock::new();
+    LISTED.get_or_init(|| {
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-encoders"])
+            .stderr(Stdio::null())
+            .output()
+            .map(|output| parse_encoder_list(&String::from_utf8_lossy(&output.stdout)))
+            .unwrap_or_default()
+    })
+}
+
+/// ffmpeg arguments for encoding with `encoder` through the `-vf` chain
+/// `filters`, for after the inputs. VAAPI encoders also get the device and
+/// the upload of frames to it.
+pub fn encoder_args(encoder: &str, filters: &[String]) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut filters = filters.to_vec();
+    if encoder.ends_with("_vaapi") {
+        args.extend(["-vaapi_device".to_string(), VAAPI_DEVICE.to_string()]);
+        filters.push("format=nv12,hwupload".to_string());
+    }
+    if !filters.is_empty() {
+        args.extend(["-vf".to_string(), filters.join(",")]);
+    }
+    args.extend(["-c:v".to_string(), encoder.to_string()]);
+    args
+}
+
+/// Whether `encoder` can encode a single frame here; ffmpeg may list
+/// hardware encoders whose device or driver is missing
+fn test_encode(encoder: &str) -> bool {
+    Command::new("ffmpeg")
+        .args(["-v", "error", "-nostdin", "-f", "lavfi"])
+        .args(["-i", "color=c=black:s=256x256:d=1", "-frames:v", "1"])
+        .args(encoder_args(encoder, &[]))
+        .args(["-f", "null", "-"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// The hardware encoders in `VIDEO_ENCODERS` that ffmpeg lists and that
+/// passed a one-frame test encode, in `VIDEO_ENCODERS` order. Checked once
+/// per process.
+pub fn detect_hw_encoders_core() -> &'static [String] {
+    static USABLE: OnceLock<Vec<String>> = OnceLock::new();
+    USABLE.get_or_init(|| {
+        let listed = listed_encoders();
+        VIDEO_ENCODERS
+            .iter()
+            .filter(|name| !name.starts_with("lib"))
+            .filter(|name| listed.contains(**name) && test_encode(name))
+            .map(|name| name.to_string())
+            .collect()
+    })
+}
+
+/// The encoder to pass to ffmpeg for `requested`: one of `VIDEO_ENCODERS`
+/// as is, or for `"auto"` the first usable H.264 hardware encoder, falling
+/// back to `SOFTWARE_ENCODER`
+pub fn resolve_encoder(requested: &str) -> Result<String> {
+    let requested = requested.trim().to_lowercase();
+    if requested == "auto" {
+        let hardware = detect_hw_encoders_core()
+            .iter()
+            .find(|name| name.starts_with("h264_"));
+        return Ok(hardware.map_or(SOFTWARE_ENCODER, |name| name).to_string());
+    }
+    if !VIDEO_ENCODERS.contains(&requested.as_str()) {
+        bail!(
+            "Unknown video encoder: {} (expected auto or one of {})",
+            requested,
+            VIDEO_ENCODERS.join(", ")
+        );
+    }
+    Ok(requested)
+}
+
+/// `resolve_encoder` for Python's optional `encoder` arguments
+#[cfg(feature = "python")]
+fn py_encoder_args(encoder: Option<&str>) -> PyResult<Vec<String>> {
+    let Some(encoder) = encoder else {
+        return Ok(Vec::new());
+    };
+    let encoder = resolve_encoder(encoder).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(encoder_args(&encoder, &[]))
+}
+
+/// The hardware video encoders usable here, e.g. `["h264_nvenc"]`
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn detect_hw_encoders(py: Python) -> Vec<String> {
+    py.detach(|| detect_hw_encoders_core().to_vec())
+}
+
+/// Convert `input_path` to `output_path` with ffmpeg, encoding the video
+/// with `encoder` (`"auto"` or one of `VIDEO_ENCODERS`) if given, else
+/// ffmpeg's default for the output format
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (input_path, output_path, delete_original, encoder=None))]
 pub fn convert_video(
     py: Python,
     input_path: String,
     output_path: String,
     delete_original: bool,
+    encoder: Option<String>,
 ) -> PyResult<bool> {
     py.detach(|| {
+        let encoder_args = py_encoder_args(encoder.as_deref())?;
         let status = Command::new("ffmpeg")
             .args(&[
                 "-y", // Overwrite output files
                 "-i",
                 &input_path,
-                &output_path,
             ])
+            .args(&encoder_args)
+            .arg(&output_path)
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::null())
             .status();
@@ -41,3 +608,214 @@ pub fn convert_video(
         }
     })
 }
+
+/// `convert_video`, reporting to `callback_obj.on_progress(percent, speed,
+/// eta_seconds)` as ffmpeg goes; any of those is `None` while unknown. Once
+/// the callback's `_is_running` turns false ffmpeg is stopped, the partial
+/// output removed and `False` returned. An exception from the callback
+/// stops it too and is raised.
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (input_path, output_path, delete_original, callback_obj, encoder=None))]
+pub fn convert_video_with_progress(
+    py: Python,
+    input_path: String,
+    output_path: String,
+    delete_original: bool,
+    callback_obj: Py<PyAny>,
+    encoder: Option<String>,
+) -> PyResult<bool> {
+    py.detach(|| {
+        let encoder_args = py_encoder_args(encoder.as_deref())?;
+        let duration = probe_duration(Path::new(&input_path)).ok().flatten();
+        let mut command = ffmpeg_command();
+        command
+            .args(["-y", "-i", &input_path])
+            .args(&encoder_args)
+            .arg(&output_path);
+
+        let stopped = std::cell::Cell::new(false);
+        let error = std::cell::RefCell::new(None::<PyErr>);
+        let result = run_ffmpeg(
+            command,
+            duration,
+            || stopped.get(),
+            |progress| {
+                let percent = progress.fraction.map(|f| f * 100.0);
+                let result = Python::attach(|py| {
+                    let args = (percent, progress.speed, progress.eta_seconds);
+                    callback_obj.call_method1(py, "on_progress", args)?;
+                    match callback_obj.getattr(py, "_is_running") {
+                        Ok(is_running) => is_running.extract::<bool>(py),
+                        Err(_) => Ok(true),
+                    }
+                });
+                match result {
+                    Ok(running) => stopped.set(!running),
+                    Err(err) => {
+                        stopped.set(true);
+                        error.borrow_mut().get_or_insert(err);
+                    }
+                }
+            },
+        );
+
+        if stopped.get() {
+            let _ = fs::remove_file(&output_path);
+        }
+        if let Some(err) = error.into_inner() {
+            return Err(err);
+        }
+        if result.is_err() {
+            return Ok(false);
+        }
+        if delete_original {
+            let _ = fs::remove_file(&input_path);
+        }
+        Ok(true)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_thumbnail_seek_point() {
+        assert_eq!(thumbnail_seek_point(None), 0.0);
+        assert_eq!(thumbnail_seek_point(Some(3.0)), 0.3);
+        assert_eq!(thumbnail_seek_point(Some(100.0)), MAX_THUMBNAIL_SEEK);
+        assert_eq!(thumbnail_seek_point(Some(3600.0)), MAX_THUMBNAIL_SEEK);
+    }
+
+    #[test]
+    fn test_process_limit() {
+        let limit = ProcessLimit::new(2);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _slot = limit.acquire();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(*limit.running.lock().unwrap(), 0);
+        assert_eq!(ProcessLimit::new(0).max, 1);
+    }
+
+    #[test]
+    fn test_ffmpeg_progress() {
+        assert_eq!(progress_value("frame=12", "frame"), Some("12"));
+        assert_eq!(
+            progress_value("out_time_us=1500000\n", "out_time_us"),
+            Some("1500000")
+        );
+        assert_eq!(progress_value("fps=25.0", "frame"), None);
+        assert_eq!(progress_value("frames=3", "frame"), None);
+
+        let report = "frame=50\nfps=25.0\nout_time_us=2000000\nout_time=00:00:02.000000\n\
+                      speed=2.00x\nprogress=continue";
+        let mut progress = FfmpegProgress::default();
+        let ends: Vec<bool> = report
+            .lines()
+            .map(|line| progress.update(line, Some(10.0)))
+            .collect();
+        assert_eq!(ends, [false, false, false, false, false, true]);
+        assert_eq!(progress.frame, 50);
+        assert_eq!(progress.fraction, Some(0.2));
+        assert_eq!(progress.speed, Some(2.0));
+        assert_eq!(progress.eta_seconds, Some(4.0));
+
+        // Older ffmpeg: out_time_ms, which is also in microseconds
+        assert!(!progress.update("out_time_ms=5000000", Some(10.0)));
+        assert!(!progress.update("speed=N/A", Some(10.0)));
+        assert!(progress.update("progress=continue", Some(10.0)));
+        assert_eq!(progress.fraction, Some(0.5));
+        assert_eq!(progress.eta_seconds, None);
+        assert!(progress.update("progress=end", Some(10.0)));
+        assert_eq!(progress.fraction, Some(1.0));
+        assert_eq!(progress.eta_seconds, Some(0.0));
+        // Without a duration there is nothing to measure against
+        assert!(progress.update("progress=continue", None));
+        assert_eq!((progress.fraction, progress.eta_seconds), (None, None));
+
+        let dir = tempfile::tempdir().unwrap();
+        let video = dir.path().join("clip.mp4");
+        std::fs::write(&video, "dummy").unwrap();
+        let out = dir.path().join("frames");
+        let extract =
+            |interval| extract_video_frames_core(&video, &out, interval, || false, |_| {});
+        assert!(extract(0).unwrap_err().to_string().contains("interval"));
+        // Not a video (or no ffprobe): an error, and nothing written
+        assert!(extract(500).is_err());
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn test_encoder_selection() {
+        let listing = "Encoders:
+ V..... = Video
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC (codec h264)
+ V....D h264_nvenc           NVIDIA NVENC H.264 encoder (codec h264)
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+        let listed = parse_encoder_list(listing);
+        assert_eq!(listed.len(), 2);
+        assert!(listed.contains("libx264") && listed.contains("h264_nvenc"));
+
+        assert_eq!(resolve_encoder("H264_NVENC").unwrap(), "h264_nvenc");
+        assert!(resolve_encoder("h264_bogus").is_err());
+        // Whatever this machine has, auto picks something ffmpeg can use
+        let auto = resolve_encoder("auto").unwrap();
+        assert!(auto == SOFTWARE_ENCODER || detect_hw_encoders_core().contains(&auto));
+
+        let filters = vec!["scale=640:360".to_string()];
+        assert_eq!(
+            encoder_args("libx264", &filters),
+            ["-vf", "scale=640:360", "-c:v", "libx264"]
+        );
+        assert_eq!(encoder_args("h264_nvenc", &[]), ["-c:v", "h264_nvenc"]);
+        assert_eq!(
+            encoder_args("h264_vaapi", &filters),
+            [
+                "-vaapi_device",
+                VAAPI_DEVICE,
+                "-vf",
+                "scale=640:360,format=nv12,hwupload",
+                "-c:v",
+                "h264_vaapi"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backend_fallback() {
+        let missing = Command::new("no-such-ffmpeg-binary")
+            .output()
+            .context("Failed to execute ffmpeg")
+            .unwrap_err();
+        assert!(is_not_found(&missing));
+        assert!(!is_not_found(&anyhow!("ffmpeg failed")));
+
+        let native = cfg!(feature = "native-video");
+        assert_eq!(video_backend().is_some(), ffmpeg_available() || native);
+        if ffmpeg_available() {
+            assert_eq!(video_backend(), Some(VideoBackend::Ffmpeg));
+        }
+
+        // Not a video, whichever decoder gets it
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("not_a_video.mp4");
+        std::fs::write(&path, "dummy").unwrap();
+        assert!(decode_first_frame(&path).is_err());
+        assert!(extract_thumbnail_frame(&path, &ProcessLimit::new(1)).is_err());
+    }
+}
@@ -1,9 +1,743 @@
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
-use std::process::Command;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::time::Duration;
 
 // Standard Rust functions for internal use (e.g. by slideshow_daemon)
 
+/// Style names KDE understands, mapped to fill modes by the slideshow daemon
+pub const KDE_STYLES: &[&str] = &[
+    "Scaled, Keep Proportions",
+    "Scaled",
+    "Scaled and Cropped (Zoom)",
+    "Centered",
+    "Tiled",
+    "Center Tiled",
+    "Span",
+    "Fill",
+];
+
+/// GNOME's `picture-options` values, matched case-insensitively
+pub const GNOME_STYLES: &[&str] = &[
+    "none",
+    "wallpaper",
+    "centered",
+    "scaled",
+    "stretched",
+    "zoom",
+    "spanned",
+];
+
+/// Style names for Windows and macOS, the ones `WallpaperFit` is named after
+pub const FIT_STYLES: &[&str] = &["Fill", "Fit", "Stretch", "Center", "Tile", "Span"];
+
+/// Prefix of the KDE video wallpaper styles, `SmartVideoWallpaper::<video fill mode>`
+pub const VIDEO_STYLE_PREFIX: &str = "SmartVideoWallpaper::";
+
+/// Whether `style` is one the slideshow daemon applies as asked instead of
+/// falling back to its default
+pub fn is_known_style(style: &str) -> bool {
+    KDE_STYLES.contains(&style)
+        || FIT_STYLES.contains(&style)
+        || GNOME_STYLES.contains(&style.to_lowercase().as_str())
+        || style.starts_with(VIDEO_STYLE_PREFIX)
+}
+
+/// Whether process `pid` is alive and is a slideshow daemon: the
+/// `slideshow_daemon` binary or Python running `slideshow_daemon.py`
+pub fn is_slideshow_daemon(pid: u32) -> bool {
+    let Ok(cmdline) = std::fs::read(format!("/proc/{}/cmdline", pid)) else {
+        return false;
+    };
+    let mut args = cmdline
+        .split(|&b| b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned());
+    let Some(program) = args.next() else {
+        return false;
+    };
+    let program = std::path::Path::new(&program)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    program == "slideshow_daemon"
+        || (program.starts_with("python") && args.any(|arg| arg.ends_with("slideshow_daemon.py")))
+}
+
+/// How an image covers a monitor on desktops that only offer the basic
+/// choices, Windows and macOS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallpaperFit {
+    /// Scaled to cover the monitor, cropping what doesn't fit
+    Fill,
+    /// Scaled to fit inside the monitor, leaving bars
+    Fit,
+    Stretch,
+    Center,
+    Tile,
+    /// One image across all monitors; Windows only, Fill elsewhere
+    Span,
+}
+
+impl WallpaperFit {
+    /// Closest fit for a style name from any desktop: these ones, KDE's or
+    /// GNOME's. Anything unrecognised fills.
+    pub fn from_style(style: &str) -> Self {
+        let style = style.to_lowercase();
+        if style.contains("stretch") {
+            WallpaperFit::Stretch
+        } else if style.contains("crop") || style.contains("zoom") || style.contains("fill") {
+            WallpaperFit::Fill
+        } else if style.contains("span") {
+            WallpaperFit::Span
+        } else if style.contains("tile") || style == "wallpaper" {
+            WallpaperFit::Tile
+        } else if style.contains("center") || style == "none" {
+            WallpaperFit::Center
+        } else if style.contains("fit")
+            || style.contains("keep proportions")
+            || style.contains("scaled")
+        {
+            WallpaperFit::Fit
+        } else {
+            WallpaperFit::Fill
+        }
+    }
+}
+
+/// The image in `path_map` for the `index`th monitor, which sits at
+/// `x`,`y` and is `width`x`height`. Monitors are keyed by index (the daemon)
+/// or by geometry as `WxH+X+Y` (the Tauri app).
+#[cfg_attr(
+    not(all(target_os = "windows", feature = "windows-wallpaper")),
+    allow(dead_code)
+)]
+fn monitor_image(
+    path_map: &HashMap<String, String>,
+    index: usize,
+    geometry: (i32, i32, i32, i32),
+) -> Option<&str> {
+    monitor_entry(path_map, index, geometry).map(|(_, path)| path)
+}
+
+/// `monitor_image` along with the key it was found under
+fn monitor_entry(
+    path_map: &HashMap<String, String>,
+    index: usize,
+    (width, height, x, y): (i32, i32, i32, i32),
+) -> Option<(&str, &str)> {
+    path_map
+        .get_key_value(&index.to_string())
+        .or_else(|| path_map.get_key_value(&format!("{}x{}+{}+{}", width, height, x, y)))
+        .map(|(key, path)| (key.as_str(), path.as_str()))
+}
+
+/// Image for monitors `path_map` has no entry for, when none of them matched:
+/// the one for monitor "0", or any
+#[cfg_attr(
+    not(all(target_os = "windows", feature = "windows-wallpaper")),
+    allow(dead_code)
+)]
+fn fallback_image(path_map: &HashMap<String, String>) -> Option<&str> {
+    path_map
+        .get("0")
+        .or_else(|| path_map.values().next())
+        .map(String::as_str)
+}
+
+fn absolute(path: &str) -> Result<String, String> {
+    std::fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .map_err(|e| format!("Invalid image path {}: {}", path, e))
+}
+
+/// Set each monitor's image from `path_map` with IDesktopWallpaper, which
+/// handles monitors separately, or with SystemParametersInfoW, one image for
+/// all of them, where that isn't available. Windows has one fit for all
+/// monitors.
+#[cfg(all(target_os = "windows", feature = "windows-wallpaper"))]
+pub fn set_wallpaper_windows_core(
+    path_map: &HashMap<String, String>,
+    fit: WallpaperFit,
+) -> Result<(), String> {
+    use windows::core::HSTRING;
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoTaskMemFree, CLSCTX_ALL, COINIT_APARTMENTTHREADED,
+    };
+    use windows::Win32::UI::Shell::{
+        DesktopWallpaper, IDesktopWallpaper, DWPOS_CENTER, DWPOS_FILL, DWPOS_FIT, DWPOS_SPAN,
+        DWPOS_STRETCH, DWPOS_TILE,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        SystemParametersInfoW, SPIF_SENDCHANGE, SPIF_UPDATEINIFILE, SPI_SETDESKWALLPAPER,
+    };
+
+    let fallback = absolute(fallback_image(path_map).ok_or("No image path provided")?)?;
+    let position = match fit {
+        WallpaperFit::Fill => DWPOS_FILL,
+        WallpaperFit::Fit => DWPOS_FIT,
+        WallpaperFit::Stretch => DWPOS_STRETCH,
+        WallpaperFit::Center => DWPOS_CENTER,
+        WallpaperFit::Tile => DWPOS_TILE,
+        WallpaperFit::Span => DWPOS_SPAN,
+    };
+
+    // SAFETY: plain COM calls; the monitor paths the API allocates are
+    // freed once used
+    let per_monitor = unsafe {
+        // Already initialised on this thread is fine too
+        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+        CoCreateInstance::<_, IDesktopWallpaper>(&DesktopWallpaper, None, CLSCTX_ALL).and_then(
+            |desktop| {
+                desktop.SetPosition(position)?;
+                let mut matched = false;
+                let mut monitors = Vec::new();
+                for index in 0..desktop.GetMonitorDevicePathCount()? {
+                    let id = desktop.GetMonitorDevicePathAt(index)?;
+                    let image = desktop.GetMonitorRECT(id).ok().and_then(|rect| {
+                        let geometry = (
+                            rect.right - rect.left,
+                            rect.bottom - rect.top,
+                            rect.left,
+                            rect.top,
+                        );
+                        monitor_image(path_map, index as usize, geometry)
+                    });
+                    matched |= image.is_some();
+                    monitors.push((id, image));
+                }
+                let mut result = Ok(());
+                for (id, image) in monitors {
+                    if result.is_ok() {
+                        let image = match image {
+                            Some(image) => absolute(image).unwrap_or_else(|_| fallback.clone()),
+                            // Monitors left out keep their image, unless
+                            // nothing matched and the ids mean something else
+                            None if matched => {
+                                CoTaskMemFree(Some(id.0 as _));
+                                continue;
+                            }
+                            None => fallback.clone(),
+                        };
+                        result = desktop.SetWallpaper(id, &HSTRING::from(image));
+                    }
+                    CoTaskMemFree(Some(id.0 as _));
+                }
+                result
+            },
+        )
+    };
+    if per_monitor.is_ok() {
+        return Ok(());
+    }
+
+    let (style, tile) = match fit {
+        WallpaperFit::Fill => ("10", "0"),
+        WallpaperFit::Fit => ("6", "0"),
+        WallpaperFit::Stretch => ("2", "0"),
+        WallpaperFit::Center => ("0", "0"),
+        WallpaperFit::Tile => ("0", "1"),
+        WallpaperFit::Span => ("22", "0"),
+    };
+    for (name, value) in [("WallpaperStyle", style), ("TileWallpaper", tile)] {
+        Command::new("reg")
+            .args([
+                "add",
+                r"HKCU\Control Panel\Desktop",
+                "/v",
+                name,
+                "/t",
+                "REG_SZ",
+            ])
+            .args(["/d", value, "/f"])
+            .output()
+            .map_err(|e| format!("Failed to run reg: {}", e))?;
+    }
+    let mut wide: Vec<u16> = fallback.encode_utf16().chain(std::iter::once(0)).collect();
+    // SAFETY: `wide` is a NUL-terminated UTF-16 path that outlives the call
+    unsafe {
+        SystemParametersInfoW(
+            SPI_SETDESKWALLPAPER,
+            0,
+            Some(wide.as_mut_ptr() as _),
+            SPIF_UPDATEINIFILE | SPIF_SENDCHANGE,
+        )
+    }
+    .map_err(|e| format!("SystemParametersInfoW failed: {}", e))
+}
+
+/// Sets each screen's image through NSWorkspace, by way of osascript's
+/// JavaScript bridge. Arguments: scaling, clipping, then key/path pairs.
+#[cfg(target_os = "macos")]
+const MACOS_SET_WALLPAPER: &str = r#"
+ObjC.import("AppKit");
+function run(argv) {
+    const scaling = parseInt(argv[0]);
+    const clipping = argv[1] === "1";
+    const images = {};
+    for (let i = 2; i + 1 < argv.length; i += 2) images[argv[i]] = argv[i + 1];
+    const fallback = images["0"] || argv[3];
+    const screens = $.NSScreen.screens;
+    const mainHeight = screens.objectAtIndex(0).frame.size.height;
+    const found = [];
+    for (let i = 0; i < screens.count; i++) {
+        const screen = screens.objectAtIndex(i);
+        const scale = screen.backingScaleFactor;
+        const frame = screen.frame;
+        const id = Math.round(frame.size.width * scale) + "x" + Math.round(frame.size.height * scale)
+            + "+" + Math.round(frame.origin.x * scale)
+            + "+" + Math.round((mainHeight - frame.origin.y - frame.size.height) * scale);
+        found.push(images[String(i)] || images[id]);
+    }
+    const matched = found.some((image) => image !== undefined);
+    const options = $.NSDictionary.dictionaryWithObjectsForKeys(
+        [$.NSNumber.numberWithInt(scaling), $.NSNumber.numberWithBool(clipping)],
+        [$.NSWorkspaceDesktopImageScalingKey, $.NSWorkspaceDesktopImageAllowClippingKey]
+    );
+    for (let i = 0; i < screens.count; i++) {
+        const image = found[i] || (matched ? undefined : fallback);
+        if (image === undefined) continue;
+        const url = $.NSURL.fileURLWithPath(image);
+        const error = $();
+        if (!$.NSWorkspace.sharedWorkspace.setDesktopImageURLForScreenOptionsError(
+            url, screens.objectAtIndex(i), options, error)) {
+            throw new Error("Screen " + i + ": " + error.localizedDescription.js);
+        }
+    }
+}
+"#;
+
+/// Set each screen's image from `path_map` through NSWorkspace. macOS can't
+/// tile or span, so those center and fill.
+#[cfg(target_os = "macos")]
+pub fn set_wallpaper_macos_core(
+    path_map: &HashMap<String, String>,
+    fit: WallpaperFit,
+) -> Result<(), String> {
+    // NSImageScaling: 1 stretches, 2 leaves the size alone, 3 keeps proportions
+    let (scaling, clipping) = match fit {
+        WallpaperFit::Fill | WallpaperFit::Span => (3, true),
+        WallpaperFit::Fit => (3, false),
+        WallpaperFit::Stretch => (1, false),
+        WallpaperFit::Center | WallpaperFit::Tile => (2, false),
+    };
+    if path_map.is_empty() {
+        return Err("No image path provided".to_string());
+    }
+    let mut command = Command::new("osascript");
+    command
+        .args(["-l", "JavaScript", "-e", MACOS_SET_WALLPAPER])
+        .arg(scaling.to_string())
+        .arg(if clipping { "1" } else { "0" });
+    // The fallback is the first pair's path unless there's a monitor "0"
+    let mut pairs: Vec<(&String, &String)> = path_map.iter().collect();
+    pairs.sort_by_key(|(key, _)| key.as_str() != "0");
+    for (key, path) in pairs {
+        command.arg(key).arg(absolute(path)?);
+    }
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to run osascript: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "osascript failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// Set wallpapers the platform's own way: `set_wallpaper_windows_core` or
+/// `set_wallpaper_macos_core`. Linux desktops each have their own, so this
+/// fails there, as it does on Windows without the `windows-wallpaper`
+/// feature.
+#[cfg(all(target_os = "windows", feature = "windows-wallpaper"))]
+pub fn set_wallpaper_native_core(
+    path_map: &HashMap<String, String>,
+    fit: WallpaperFit,
+) -> Result<(), String> {
+    set_wallpaper_windows_core(path_map, fit)
+}
+
+#[cfg(target_os = "macos")]
+pub fn set_wallpaper_native_core(
+    path_map: &HashMap<String, String>,
+    fit: WallpaperFit,
+) -> Result<(), String> {
+    set_wallpaper_macos_core(path_map, fit)
+}
+
+#[cfg(not(any(
+    all(target_os = "windows", feature = "windows-wallpaper"),
+    target_os = "macos"
+)))]
+pub fn set_wallpaper_native_core(
+    _path_map: &HashMap<String, String>,
+    _fit: WallpaperFit,
+) -> Result<(), String> {
+    Err("No native wallpaper setting on this platform".to_string())
+}
+
+/// Whether this is a wlroots-style Wayland session, Hyprland, Sway and the
+/// like, where wallpapers are drawn by swaybg or hyprpaper
+pub fn is_wlroots_session() -> bool {
+    if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some()
+        || std::env::var_os("SWAYSOCK").is_some()
+    {
+        return true;
+    }
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .to_lowercase();
+    ["hyprland", "sway", "wlroots", "river", "wayfire", "labwc"]
+        .iter()
+        .any(|name| desktop.contains(name))
+}
+
+/// A Wayland output as the compositor reports it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaylandOutput {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Deserialize)]
+struct HyprlandMonitor {
+    name: String,
+    width: i32,
+    height: i32,
+    x: i32,
+    y: i32,
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// Enabled outputs from `hyprctl monitors -j`
+pub fn parse_hyprctl_monitors(json: &str) -> Result<Vec<WaylandOutput>, String> {
+    let monitors: Vec<HyprlandMonitor> =
+        serde_json::from_str(json).map_err(|e| format!("Unexpected hyprctl output: {}", e))?;
+    Ok(monitors
+        .into_iter()
+        .filter(|m| !m.disabled)
+        .map(|m| WaylandOutput {
+            name: m.name,
+            width: m.width,
+            height: m.height,
+            x: m.x,
+            y: m.y,
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct SwayRect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+#[derive(Deserialize)]
+struct SwayMode {
+    width: i32,
+    height: i32,
+}
+
+#[derive(Deserialize)]
+struct SwayOutput {
+    name: String,
+    #[serde(default)]
+    active: Option<bool>,
+    rect: SwayRect,
+    #[serde(default)]
+    current_mode: Option<SwayMode>,
+}
+
+/// Active outputs from `swaymsg -t get_outputs -r`. The size is the mode's,
+/// in pixels, as `rect` is scaled.
+pub fn parse_sway_outputs(json: &str) -> Result<Vec<WaylandOutput>, String> {
+    let outputs: Vec<SwayOutput> =
+        serde_json::from_str(json).map_err(|e| format!("Unexpected swaymsg output: {}", e))?;
+    Ok(outputs
+        .into_iter()
+        .filter(|o| o.active != Some(false))
+        .map(|o| {
+            let (width, height) = o
+                .current_mode
+                .map_or((o.rect.width, o.rect.height), |m| (m.width, m.height));
+            WaylandOutput {
+                name: o.name,
+                width,
+                height,
+                x: o.rect.x,
+                y: o.rect.y,
+            }
+        })
+        .collect())
+}
+
+/// Outputs of the running compositor, if it's one that says: Hyprland or Sway
+pub fn get_wayland_outputs_core() -> Result<Vec<WaylandOutput>, String> {
+    let (program, args, parse): (_, &[&str], fn(&str) -> _) =
+        if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+            ("hyprctl", &["monitors", "-j"], parse_hyprctl_monitors)
+        } else if std::env::var_os("SWAYSOCK").is_some() {
+            ("swaymsg", &["-t", "get_outputs", "-r"], parse_sway_outputs)
+        } else {
+            return Ok(Vec::new());
+        };
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Which `path_map` entry goes on each output, as (output name, monitor id,
+/// image). Monitors are keyed by output name, index or geometry; outputs
+/// left out keep their image, unless nothing matched and the ids mean
+/// something else, when they all get the fallback.
+pub fn assign_outputs<'a>(
+    path_map: &'a HashMap<String, String>,
+    outputs: &'a [WaylandOutput],
+) -> Vec<(&'a str, &'a str, &'a str)> {
+    let matched: Vec<_> = outputs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, output)| {
+            let geometry = (output.width, output.height, output.x, output.y);
+            path_map
+                .get_key_value(&output.name)
+                .map(|(id, path)| (id.as_str(), path.as_str()))
+                .or_else(|| monitor_entry(path_map, index, geometry))
+                .map(|(id, path)| (output.name.as_str(), id, path))
+        })
+        .collect();
+    if !matched.is_empty() {
+        return matched;
+    }
+    let Some((id, path)) = path_map
+        .get_key_value("0")
+        .or_else(|| path_map.iter().next())
+    else {
+        return matched;
+    };
+    outputs
+        .iter()
+        .map(|output| (output.name.as_str(), id.as_str(), path.as_str()))
+        .collect()
+}
+
+/// The swaybg this process started last, waited on once it's replaced
+static SWAYBG: Mutex<Option<Child>> = Mutex::new(None);
+
+/// Pids of the running processes called `name`
+fn processes_named(name: &str) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                .is_ok_and(|comm| comm.trim_end() == name)
+        })
+        .collect()
+}
+
+/// (output, image, mode) for each output a swaybg command line sets.
+/// Options before any `-o` are for every output, `*`.
+fn parse_swaybg_args(args: &[String]) -> Vec<(String, String, String)> {
+    let mut outputs: Vec<(String, Option<String>, String)> = Vec::new();
+    let mut current = "*".to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        if !matches!(flag, "-o" | "--output" | "-i" | "--image" | "-m" | "--mode") {
+            continue;
+        }
+        let Some(value) = value.or_else(|| args.next().cloned()) else {
+            break;
+        };
+        if matches!(flag, "-o" | "--output") {
+            current = value;
+            continue;
+        }
+        let index = match outputs.iter().position(|(name, _, _)| *name == current) {
+            Some(index) => index,
+            None => {
+                outputs.push((current.clone(), None, "fill".to_string()));
+                outputs.len() - 1
+            }
+        };
+        match flag {
+            "-i" | "--image" => outputs[index].1 = Some(value),
+            _ => outputs[index].2 = value,
+        }
+    }
+    outputs
+        .into_iter()
+        .filter_map(|(name, image, mode)| Some((name, image?, mode)))
+        .collect()
+}
+
+fn swaybg_mode(fit: WallpaperFit) -> &'static str {
+    match fit {
+        WallpaperFit::Fill | WallpaperFit::Span => "fill",
+        WallpaperFit::Fit => "fit",
+        WallpaperFit::Stretch => "stretch",
+        WallpaperFit::Center => "center",
+        WallpaperFit::Tile => "tile",
+    }
+}
+
+/// Start a swaybg for `images` (output, image, mode), then stop the ones
+/// already running, keeping the outputs they drew that `images` leaves out.
+/// Starting first means the desktop never flashes empty.
+fn set_wallpaper_swaybg(images: Vec<(String, String, String)>) -> Result<(), String> {
+    let previous = processes_named("swaybg");
+    let mut outputs = images;
+    for pid in &previous {
+        let Ok(cmdline) = std::fs::read(format!("/proc/{}/cmdline", pid)) else {
+            continue;
+        };
+        let args: Vec<String> = cmdline
+            .split(|&b| b == 0)
+            .skip(1)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        for kept in parse_swaybg_args(&args) {
+            if !outputs.iter().any(|(name, _, _)| *name == kept.0) {
+                outputs.push(kept);
+            }
+        }
+    }
+    // `*` first, so the named outputs override it
+    outputs.sort_by_key(|(name, _, _)| name != "*");
+
+    let mut command = Command::new("swaybg");
+    for (name, image, mode) in &outputs {
+        command.args(["-o", name, "-i", image, "-m", mode]);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to start swaybg: {}", e))?;
+    std::thread::sleep(Duration::from_millis(300));
+    if let Ok(Some(status)) = child.try_wait() {
+        return Err(format!("swaybg exited straight away: {}", status));
+    }
+
+    if !previous.is_empty() {
+        Command::new("kill")
+            .args(previous.iter().map(u32::to_string))
+            .output()
+            .map_err(|e| format!("Failed to stop the old swaybg: {}", e))?;
+    }
+    let mut last = SWAYBG.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(mut old) = last.replace(child) {
+        let _ = old.wait();
+    }
+    Ok(())
+}
+
+fn hyprpaper_request(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("hyprctl")
+        .arg("hyprpaper")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run hyprctl: {}", e))?;
+    let reply = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || reply.trim() != "ok" {
+        return Err(format!(
+            "hyprpaper {} failed: {}{}",
+            args.join(" "),
+            reply.trim(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Hand `images` (output, image, fit) to a running hyprpaper, then let it
+/// drop the images no output shows any more. hyprpaper only covers, contains
+/// or tiles, so Fit and Center contain.
+fn set_wallpaper_hyprpaper(images: &[(String, String, WallpaperFit)]) -> Result<(), String> {
+    for (output, image, fit) in images {
+        // Versions that load on demand refuse this, which is fine
+        let _ = hyprpaper_request(&["preload", image]);
+        let prefix = match fit {
+            WallpaperFit::Fit | WallpaperFit::Center => "contain:",
+            WallpaperFit::Tile => "tile:",
+            _ => "",
+        };
+        // An empty output is every output to hyprpaper
+        let output = if output == "*" { "" } else { output };
+        hyprpaper_request(&["wallpaper", &format!("{},{}{}", output, prefix, image)])?;
+    }
+    let _ = hyprpaper_request(&["unload", "unused"]);
+    Ok(())
+}
+
+/// Set each output's image from `path_map` on a wlroots compositor, with
+/// hyprpaper if it's running, else swaybg, else hyprpaper started for the
+/// purpose. `fit` gives the fit for a monitor id of `path_map`.
+pub fn set_wallpaper_wlroots_core(
+    path_map: &HashMap<String, String>,
+    fit: impl Fn(&str) -> WallpaperFit,
+) -> Result<(), String> {
+    let outputs = get_wayland_outputs_core().unwrap_or_default();
+    let mut images = Vec::new();
+    for (output, id, path) in assign_outputs(path_map, &outputs) {
+        images.push((output.to_string(), absolute(path)?, fit(id)));
+    }
+    if images.is_empty() {
+        // A compositor that doesn't list its outputs: one image for all
+        let (id, path) = path_map
+            .get_key_value("0")
+            .or_else(|| path_map.iter().next())
+            .ok_or("No image path provided")?;
+        images.push(("*".to_string(), absolute(path)?, fit(id)));
+    }
+
+    let hyprpaper_running = !processes_named("hyprpaper").is_empty();
+    if !hyprpaper_running && which::which("swaybg").is_ok() {
+        let images = images
+            .into_iter()
+            .map(|(output, image, fit)| (output, image, swaybg_mode(fit).to_string()))
+            .collect();
+        return set_wallpaper_swaybg(images);
+    }
+    if !hyprpaper_running {
+        Command::new("hyprpaper")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|_| "Neither swaybg nor hyprpaper is installed".to_string())?;
+        std::thread::sleep(Duration::from_millis(500));
+    }
+    set_wallpaper_hyprpaper(&images)
+}
+
 pub fn set_wallpaper_gnome_core(uri: &str, mode: &str) -> std::io::Result<()> {
     Command::new("gsettings")
         .args(&["set", "org.gnome.desktop.background", "picture-uri", uri])
@@ -106,3 +840,251 @@ pub fn evaluate_kde_script(py: Python<'_>, qdbus_bin: String, script: String) ->
             .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_from_style() {
+        let cases = [
+            ("Fill", WallpaperFit::Fill),
+            ("Fit", WallpaperFit::Fit),
+            ("Tile", WallpaperFit::Tile),
+            ("Scaled, Keep Proportions", WallpaperFit::Fit),
+            ("Scaled and Cropped (Zoom)", WallpaperFit::Fill),
+            ("Centered", WallpaperFit::Center),
+            ("Center Tiled", WallpaperFit::Tile),
+            ("Span", WallpaperFit::Span),
+            ("stretched", WallpaperFit::Stretch),
+            ("wallpaper", WallpaperFit::Tile),
+            ("none", WallpaperFit::Center),
+            ("SmartVideoWallpaper::Keep Proportions", WallpaperFit::Fit),
+            ("something else", WallpaperFit::Fill),
+        ];
+        for (style, fit) in cases {
+            assert_eq!(WallpaperFit::from_style(style), fit, "{}", style);
+        }
+    }
+
+    #[test]
+    fn test_monitor_image_by_index_or_geometry() {
+        let path_map = HashMap::from([
+            ("0".to_string(), "/img/a.png".to_string()),
+            ("1920x1080+2560+0".to_string(), "/img/b.png".to_string()),
+        ]);
+        assert_eq!(
+            monitor_image(&path_map, 0, (2560, 1440, 0, 0)),
+            Some("/img/a.png")
+        );
+        assert_eq!(
+            monitor_image(&path_map, 1, (1920, 1080, 2560, 0)),
+            Some("/img/b.png")
+        );
+        assert_eq!(monitor_image(&path_map, 2, (1280, 1024, 0, 1440)), None);
+        assert_eq!(fallback_image(&path_map), Some("/img/a.png"));
+    }
+
+    /// `hyprctl monitors -j` on a laptop with an external monitor to its
+    /// left and a third output disabled
+    const HYPRCTL_MONITORS: &str = r#"[{
+    "id": 0,
+    "name": "DP-1",
+    "description": "Dell Inc. DELL U2720Q 8LXMZ23",
+    "make": "Dell Inc.",
+    "model": "DELL U2720Q",
+    "serial": "8LXMZ23",
+    "width": 3840,
+    "height": 2160,
+    "refreshRate": 59.99700,
+    "x": 0,
+    "y": 0,
+    "activeWorkspace": { "id": 1, "name": "1" },
+    "specialWorkspace": { "id": 0, "name": "" },
+    "reserved": [0, 40, 0, 0],
+    "scale": 1.50,
+    "transform": 0,
+    "focused": true,
+    "dpmsStatus": true,
+    "vrr": false,
+    "solitary": "0",
+    "activelyTearing": false,
+    "disabled": false,
+    "currentFormat": "XRGB8888",
+    "mirrorOf": "none",
+    "availableModes": ["3840x2160@60.00Hz", "2560x1440@59.95Hz"]
+},{
+    "id": 1,
+    "name": "eDP-1",
+    "description": "BOE 0x0BCA",
+    "make": "BOE",
+    "model": "0x0BCA",
+    "serial": "",
+    "width": 1920,
+    "height": 1200,
+    "refreshRate": 60.00100,
+    "x": 2560,
+    "y": 240,
+    "activeWorkspace": { "id": 2, "name": "2" },
+    "specialWorkspace": { "id": 0, "name": "" },
+    "reserved": [0, 0, 0, 0],
+    "scale": 1.00,
+    "transform": 0,
+    "focused": false,
+    "dpmsStatus": true,
+    "vrr": false,
+    "solitary": "0",
+    "activelyTearing": false,
+    "disabled": false,
+    "currentFormat": "XRGB8888",
+    "mirrorOf": "none",
+    "availableModes": ["1920x1200@60.00Hz"]
+},{
+    "id": -1,
+    "name": "HDMI-A-1",
+    "description": "",
+    "make": "",
+    "model": "",
+    "serial": "",
+    "width": 0,
+    "height": 0,
+    "refreshRate": 0.0,
+    "x": 0,
+    "y": 0,
+    "scale": 1.00,
+    "transform": 0,
+    "focused": false,
+    "disabled": true,
+    "availableModes": []
+}]"#;
+
+    fn hyprland_outputs() -> Vec<WaylandOutput> {
+        parse_hyprctl_monitors(HYPRCTL_MONITORS).unwrap()
+    }
+
+    #[test]
+    fn test_parse_hyprctl_monitors() {
+        assert_eq!(
+            hyprland_outputs(),
+            vec![
+                WaylandOutput {
+                    name: "DP-1".to_string(),
+                    width: 3840,
+                    height: 2160,
+                    x: 0,
+                    y: 0,
+                },
+                WaylandOutput {
+                    name: "eDP-1".to_string(),
+                    width: 1920,
+                    height: 1200,
+                    x: 2560,
+                    y: 240,
+                },
+            ]
+        );
+        assert!(parse_hyprctl_monitors("unknown request").is_err());
+    }
+
+    #[test]
+    fn test_parse_sway_outputs() {
+        let json = r#"[{
+            "id": 4, "type": "output", "name": "eDP-1", "active": true,
+            "rect": { "x": 2560, "y": 0, "width": 1280, "height": 800 },
+            "current_mode": { "width": 2560, "height": 1600, "refresh": 60000 },
+            "scale": 2.0, "focused": true
+        },{
+            "id": 5, "type": "output", "name": "HDMI-A-1", "active": false,
+            "rect": { "x": 0, "y": 0, "width": 0, "height": 0 }
+        }]"#;
+        assert_eq!(
+            parse_sway_outputs(json).unwrap(),
+            vec![WaylandOutput {
+                name: "eDP-1".to_string(),
+                width: 2560,
+                height: 1600,
+                x: 2560,
+                y: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_assign_outputs_by_index_name_or_geometry() {
+        let outputs = hyprland_outputs();
+        let by_index = HashMap::from([
+            ("0".to_string(), "/img/a.png".to_string()),
+            ("1".to_string(), "/img/b.png".to_string()),
+        ]);
+        assert_eq!(
+            assign_outputs(&by_index, &outputs),
+            vec![("DP-1", "0", "/img/a.png"), ("eDP-1", "1", "/img/b.png")]
+        );
+
+        let by_name = HashMap::from([("eDP-1".to_string(), "/img/b.png".to_string())]);
+        assert_eq!(
+            assign_outputs(&by_name, &outputs),
+            vec![("eDP-1", "eDP-1", "/img/b.png")]
+        );
+
+        let by_geometry =
+            HashMap::from([("1920x1200+2560+240".to_string(), "/img/b.png".to_string())]);
+        assert_eq!(
+            assign_outputs(&by_geometry, &outputs),
+            vec![("eDP-1", "1920x1200+2560+240", "/img/b.png")]
+        );
+
+        // Ids that match no output put the one image everywhere
+        let unmatched = HashMap::from([("Monitor 7".to_string(), "/img/c.png".to_string())]);
+        assert_eq!(
+            assign_outputs(&unmatched, &outputs),
+            vec![
+                ("DP-1", "Monitor 7", "/img/c.png"),
+                ("eDP-1", "Monitor 7", "/img/c.png")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_swaybg_args() {
+        let args: Vec<String> = [
+            "-i",
+            "/img/all.png",
+            "-o",
+            "DP-1",
+            "-i",
+            "/img/a.png",
+            "-m",
+            "fit",
+            "--output=eDP-1",
+            "--image=/img/b.png",
+            "-o",
+            "HDMI-A-1",
+            "-c",
+            "#000000",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        assert_eq!(
+            parse_swaybg_args(&args),
+            vec![
+                (
+                    "*".to_string(),
+                    "/img/all.png".to_string(),
+                    "fill".to_string()
+                ),
+                (
+                    "DP-1".to_string(),
+                    "/img/a.png".to_string(),
+                    "fit".to_string()
+                ),
+                (
+                    "eDP-1".to_string(),
+                    "/img/b.png".to_string(),
+                    "fill".to_string()
+                ),
+            ]
+        );
+    }
+}
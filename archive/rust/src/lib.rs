@@ -8,240 +8,403 @@ use pyo3::types::PyBytes;
 #[cfg(feature = "python")]
 use fast_image_resize as fr;
 #[cfg(feature = "python")]
-use image::ImageReader;
-#[cfg(feature = "python")]
 use rayon::prelude::*;
 #[cfg(feature = "python")]
-use std::process::Command;
+use std::path::Path;
+#[cfg(feature = "python")]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "python")]
+use std::sync::Mutex;
 #[cfg(feature = "python")]
 use walkdir::WalkDir;
 
+/// Decode `path`, upright unless `auto_orient` is false, and scale it to fit
+/// `thumbnail_size` with `resize`, as RGBA bytes
+#[cfg(feature = "python")]
+fn load_thumbnail(
+    path: &str,
+    thumbnail_size: u32,
+    auto_orient: bool,
+    resize: &fr::ResizeOptions,
+) -> Result<(Vec<u8>, u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+    // 1. Load and decode image, rotated before resizing so the bounds fit
+    let img = core::image_converter::load_image(path, auto_orient)?;
+    let width = img.width();
+    let height = img.height();
+
+    // 2. Calculate dimensions for aspect ratio
+    let aspect_ratio = width as f32 / height as f32;
+    let (new_w, new_h) = if width > height {
+        (
+            thumbnail_size,
+            (thumbnail_size as f32 / aspect_ratio) as u32,
+        )
+    } else {
+        (
+            (thumbnail_size as f32 * aspect_ratio) as u32,
+            thumbnail_size,
+        )
+    };
+
+    // 3. Resize using fast_image_resize
+    let src_image = fr::images::Image::from_vec_u8(
+        width,
+        height,
+        img.to_rgba8().into_raw(),
+        fr::PixelType::U8x4,
+    )?;
+
+    let mut dst_image = fr::images::Image::new(new_w, new_h, fr::PixelType::U8x4);
+
+    let mut resizer = fr::Resizer::new();
+    resizer.resize(&src_image, &mut dst_image, resize)?;
+
+    Ok((dst_image.buffer().to_vec(), new_w, new_h))
+}
+
+/// Reports `load_image_batch` progress to the Python callback object from
+/// the worker threads, holding the GIL only for each call. A callback that
+/// turns `_is_running` false or raises stops the batch.
+#[cfg(feature = "python")]
+struct BatchProgress {
+    callback_obj: Option<Py<PyAny>>,
+    total: usize,
+    processed: AtomicUsize,
+    cancelled: AtomicBool,
+    error: Mutex<Option<PyErr>>,
+}
+
+#[cfg(feature = "python")]
+impl BatchProgress {
+    fn new(callback_obj: Option<Py<PyAny>>, total: usize) -> Self {
+        BatchProgress {
+            callback_obj,
+            total,
+            processed: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            error: Mutex::new(None),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Check `_is_running` before any work starts
+    fn check_running(&self, py: Python) {
+        if let Some(callback_obj) = &self.callback_obj {
+            let result = Self::is_running(py, callback_obj);
+            self.record(result);
+        }
+    }
+
+    /// `path` is done; `error` says why it failed to load
+    fn report(&self, path: &str, error: Option<&str>) {
+        let processed = self.processed.fetch_add(1, Ordering::SeqCst) + 1;
+        let Some(callback_obj) = &self.callback_obj else {
+            return;
+        };
+        Python::attach(|py| {
+            let result = (|| {
+                if let Some(message) = error {
+                    callback_obj.call_method1(py, "on_error", (path, message))?;
+                }
+                callback_obj.call_method1(py, "on_progress", (processed, self.total, path))?;
+                Self::is_running(py, callback_obj)
+            })();
+            self.record(result);
+        });
+    }
+
+    fn is_running(py: Python, callback_obj: &Py<PyAny>) -> PyResult<bool> {
+        match callback_obj.getattr(py, "_is_running") {
+            Ok(is_running) => is_running.extract(py),
+            Err(_) => Ok(true),
+        }
+    }
+
+    fn record(&self, result: PyResult<bool>) {
+        match result {
+            Ok(true) => {}
+            Ok(false) => self.cancelled.store(true, Ordering::Relaxed),
+            Err(err) => {
+                self.cancelled.store(true, Ordering::Relaxed);
+                self.error.lock().unwrap().get_or_insert(err);
+            }
+        }
+    }
+
+    /// `value`, unless a callback raised along the way
+    fn finish<T>(self, value: T) -> PyResult<T> {
+        match self.error.into_inner().unwrap() {
+            Some(err) => Err(err),
+            None => Ok(value),
+        }
+    }
+}
+
+/// `(path, rgba, width, height)` as `load_image_batch` returns it
+#[cfg(feature = "python")]
+pub type Thumbnail = (String, Py<PyBytes>, u32, u32);
+
+/// Thumbnails of `paths` as `(path, rgba, width, height)`, and the
+/// `(path, error)` of every image that failed to load, both in input order.
+/// `callback_obj`, if given, gets `on_progress(processed, total, path)`
+/// after every image and `on_error(path, message)` for each failure; once
+/// its `_is_running` turns false, what was done so far is returned and the
+/// rest is in neither list. Images are turned upright as their EXIF
+/// orientation says unless `auto_orient` is false, and scaled with `filter`
+/// (`nearest`, `bilinear`, `lanczos3` or `catmullrom`; the default is
+/// `lanczos3`).
 #[cfg(feature = "python")]
 #[pyfunction]
+#[pyo3(signature = (paths, thumbnail_size, callback_obj=None, auto_orient=true, filter=None))]
 pub fn load_image_batch(
     py: Python,
     paths: Vec<String>,
     thumbnail_size: u32,
-) -> PyResult<Vec<(String, Py<PyBytes>, u32, u32)>> {
-    let results: Vec<(String, Option<(Vec<u8>, u32, u32)>)> = py.detach(|| {
+    callback_obj: Option<Py<PyAny>>,
+    auto_orient: bool,
+    filter: Option<String>,
+) -> PyResult<(Vec<Thumbnail>, ScanErrors)> {
+    let resize = core::image_converter::resize_options(filter.as_deref())
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+    let progress = BatchProgress::new(callback_obj, paths.len());
+    progress.check_running(py);
+
+    let results: Vec<_> = py.detach(|| {
         paths
             .par_iter()
-            .map(|path| {
-                let res =
-                    (|| -> Result<(Vec<u8>, u32, u32), Box<dyn std::error::Error + Send + Sync>> {
-                        // 1. Load and decode image
-                        let img = ImageReader::open(path)?.with_guessed_format()?.decode()?;
-                        let width = img.width();
-                        let height = img.height();
-
-                        // 2. Calculate dimensions for aspect ratio
-                        let aspect_ratio = width as f32 / height as f32;
-                        let (new_w, new_h) = if width > height {
-                            (
-                                thumbnail_size,
-                                (thumbnail_size as f32 / aspect_ratio) as u32,
-                            )
-                        } else {
-                            (
-                                (thumbnail_size as f32 * aspect_ratio) as u32,
-                                thumbnail_size,
-                            )
-                        };
-
-                        // 3. Resize using fast_image_resize
-                        let src_image = fr::images::Image::from_vec_u8(
-                            width,
-                            height,
-                            img.to_rgba8().into_raw(),
-                            fr::PixelType::U8x4,
-                        )?;
-
-                        let mut dst_image = fr::images::Image::new(new_w, new_h, fr::PixelType::U8x4);
-
-                        let mut resizer = fr::Resizer::new();
-                        resizer.resize(&src_image, &mut dst_image, None)?;
-
-                        Ok((dst_image.buffer().to_vec(), new_w, new_h))
-                    })();
-
-                match res {
-                    Ok((buffer, w, h)) => (path.clone(), Some((buffer, w, h))),
-                    Err(_) => (path.clone(), None),
+            .filter_map(|path| {
+                if progress.is_cancelled() {
+                    return None;
+                }
+                match load_thumbnail(path, thumbnail_size, auto_orient, &resize) {
+                    Ok((buffer, w, h)) => {
+                        progress.report(path, None);
+                        Some(Ok((path.clone(), buffer, w, h)))
+                    }
+                    Err(e) => {
+                        let message = e.to_string();
+                        progress.report(path, Some(&message));
+                        Some(Err((path.clone(), message)))
+                    }
                 }
             })
             .collect()
     });
 
     // Convert to Python response
-    let mut py_results = Vec::new();
-    for (path, data) in results {
-        if let Some((buf, w, h)) = data {
-            py_results.push((path, PyBytes::new(py, &buf).into(), w, h));
+    let mut thumbnails = Vec::with_capacity(results.len());
+    let mut errors = ScanErrors::new();
+    for result in results {
+        match result {
+            Ok((path, buf, w, h)) => thumbnails.push((path, PyBytes::new(py, &buf).into(), w, h)),
+            Err(failure) => errors.push(failure),
         }
     }
 
-    Ok(py_results)
+    progress.finish((thumbnails, errors))
 }
 
+/// `(path, error)` pairs for what `scan_files`, `load_image_batch` or
+/// `extract_video_thumbnails_batch` couldn't read
+#[cfg(feature = "python")]
+pub type ScanErrors = Vec<(String, String)>;
+
+/// Files under `directories` with one of `extensions`, sorted, skipping
+/// hidden entries, and the `(path, error)` of every directory or link that
+/// couldn't be read. With `follow_links`, symlinks are followed and loops
+/// reported as errors; with `strict`, the first error raises `OSError`.
+/// Files outside the size and modification time (Unix timestamp) bounds are
+/// left out, and the walk stops once `max_results` files are found.
 #[cfg(feature = "python")]
 #[pyfunction]
+#[pyo3(signature = (
+    directories,
+    extensions,
+    recursive,
+    follow_links=false,
+    strict=false,
+    min_size_bytes=None,
+    max_size_bytes=None,
+    modified_after=None,
+    modified_before=None,
+    max_results=None,
+))]
+#[allow(clippy::too_many_arguments)]
 pub fn scan_files(
     py: Python,
     directories: Vec<String>,
     extensions: Vec<String>,
     recursive: bool,
-) -> PyResult<Vec<String>> {
+    follow_links: bool,
+    strict: bool,
+    min_size_bytes: Option<u64>,
+    max_size_bytes: Option<u64>,
+    modified_after: Option<f64>,
+    modified_before: Option<f64>,
+    max_results: Option<usize>,
+) -> PyResult<(Vec<String>, ScanErrors)> {
+    let filter = core::file_system::ScanFilter {
+        min_size_bytes,
+        max_size_bytes,
+        modified_after,
+        modified_before,
+        max_results,
+    };
+    // Shared by the directories' walks, so together they stop at the limit
+    let found_count = AtomicUsize::new(0);
     py.detach(|| {
         let extensions: Vec<String> = extensions
             .iter()
             .map(|e| e.to_lowercase().replace(".", ""))
             .collect();
 
-        let results: Vec<Vec<String>> = directories
+        let results: Vec<(Vec<String>, ScanErrors)> = directories
             .par_iter()
             .map(|dir| {
                 let mut found = Vec::new();
-                let mut walker = WalkDir::new(dir);
+                let mut errors = Vec::new();
+                let mut walker = WalkDir::new(dir).follow_links(follow_links);
                 if !recursive {
                     walker = walker.max_depth(1);
                 }
 
-                for entry in walker
-                    .into_iter()
-                    .filter_entry(|e| {
-                        !e.file_name()
-                            .to_str()
-                            .map(|s| s.starts_with('.'))
-                            .unwrap_or(false)
-                    })
-                    .filter_map(|e| e.ok())
-                {
+                for entry in walker.into_iter().filter_entry(|e| {
+                    !e.file_name()
+                        .to_str()
+                        .map(|s| s.starts_with('.'))
+                        .unwrap_or(false)
+                }) {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            let path = err
+                                .path()
+                                .map_or_else(|| dir.clone(), |p| p.to_string_lossy().to_string());
+                            // The I/O error alone; the path is already given
+                            let message = match err.io_error() {
+                                Some(io) => io.to_string(),
+                                None => err.to_string(),
+                            };
+                            errors.push((path, message));
+                            if strict {
+                                break;
+                            }
+                            continue;
+                        }
+                    };
                     if entry.file_type().is_file() {
                         if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
                             let ext_lower = ext.to_lowercase();
-                            if extensions.iter().any(|e| e == &ext_lower) {
+                            if extensions.iter().any(|e| e == &ext_lower)
+                                && filter.matches_entry(&entry)
+                            {
+                                let count = found_count.fetch_add(1, Ordering::Relaxed);
+                                if filter.max_results.is_some_and(|max| count >= max) {
+                                    break;
+                                }
                                 found.push(entry.path().to_string_lossy().to_string());
                             }
                         }
                     }
                 }
-                found
+                (found, errors)
             })
             .collect();
 
         let mut flat_results = Vec::new();
-        for mut sub_results in results {
+        let mut flat_errors = Vec::new();
+        for (mut sub_results, mut sub_errors) in results {
             flat_results.append(&mut sub_results);
+            flat_errors.append(&mut sub_errors);
+        }
+        if strict {
+            if let Some((path, message)) = flat_errors.first() {
+                return Err(pyo3::exceptions::PyOSError::new_err(format!(
+                    "Failed to scan {}: {}",
+                    path, message
+                )));
+            }
         }
         flat_results.sort();
-        Ok(flat_results)
+        flat_errors.sort();
+        Ok((flat_results, flat_errors))
     })
 }
 
+/// `(path, rgba, width, height)` of a video's thumbnail
+#[cfg(feature = "python")]
+pub type VideoThumbnail = (String, Py<PyBytes>, u32, u32);
+
+/// Thumbnails of the videos in `paths`, scaled to fit `thumbnail_size`, and
+/// the `(path, error)` of each video that failed. Each video is probed once
+/// and its frame taken from a tenth of the way in (at most 10s); at most
+/// `max_parallel` ffmpeg children run at once, half the cores by default.
+/// Without ffmpeg on `PATH`, the first frame is decoded in-process when the
+/// `native-video` feature is on.
 #[cfg(feature = "python")]
 #[pyfunction]
+#[pyo3(signature = (paths, thumbnail_size, max_parallel=None))]
 pub fn extract_video_thumbnails_batch(
     py: Python,
     paths: Vec<String>,
     thumbnail_size: u32,
-) -> PyResult<Vec<(String, Py<PyBytes>, u32, u32)>> {
-    let results: Vec<(String, Option<(Vec<u8>, u32, u32)>)> = py.detach(|| {
+    max_parallel: Option<usize>,
+) -> PyResult<(Vec<VideoThumbnail>, ScanErrors)> {
+    let limit = ProcessLimit::new(max_parallel.unwrap_or_else(ProcessLimit::default_max));
+    let results = py.detach(|| {
         paths
             .par_iter()
             .map(|path| {
-                let res =
-                    (|| -> Result<(Vec<u8>, u32, u32), Box<dyn std::error::Error + Send + Sync>> {
-                        // Try multiple timestamps: 10s, 1s, 0s
-                        let timestamps = ["00:00:10", "00:00:01", "00:00:00"];
-                        let mut last_err = None;
-
-                        for ss in timestamps {
-                            let output = Command::new("ffmpeg")
-                                .args(&[
-                                    "-ss",
-                                    ss,
-                                    "-i",
-                                    path,
-                                    "-frames:v",
-                                    "1",
-                                    "-f",
-                                    "image2",
-                                    "-c:v",
-                                    "mjpeg",
-                                    "pipe:1",
-                                ])
-                                .output();
-
-                            match output {
-                                Ok(out) if out.status.success() && !out.stdout.is_empty() => {
-                                    // Decode MJPEG from stdout
-                                    let img = image::load_from_memory(&out.stdout)?;
-                                    let width = img.width();
-                                    let height = img.height();
-
-                                    // Resize logic (redundant with image loading but keep it for consistency)
-                                    let aspect_ratio = width as f32 / height as f32;
-                                    let (new_w, new_h) = if width > height {
-                                        (
-                                            thumbnail_size,
-                                            (thumbnail_size as f32 / aspect_ratio) as u32,
-                                        )
-                                    } else {
-                                        (
-                                            (thumbnail_size as f32 * aspect_ratio) as u32,
-                                            thumbnail_size,
-                                        )
-                                    };
-
-                                    let src_image = fr::images::Image::from_vec_u8(
-                                        width,
-                                        height,
-                                        img.to_rgba8().into_raw(),
-                                        fr::PixelType::U8x4,
-                                    )?;
-
-                                    let mut dst_image =
-                                        fr::images::Image::new(new_w, new_h, fr::PixelType::U8x4);
-                                    let mut resizer = fr::Resizer::new();
-                                    resizer.resize(&src_image, &mut dst_image, None)?;
-
-                                    return Ok((dst_image.buffer().to_vec(), new_w, new_h));
-                                }
-                                Ok(out) => {
-                                    last_err = Some(format!(
-                                        "ffmpeg failed for {}: status={:?}, stderr={}",
-                                        path,
-                                        out.status,
-                                        String::from_utf8_lossy(&out.stderr)
-                                    ));
-                                }
-                                Err(e) => {
-                                    last_err =
-                                        Some(format!("Failed to execute ffmpeg for {}: {}", path, e));
-                                }
-                            }
-                        }
-                        Err(last_err
-                            .unwrap_or_else(|| "No frames extracted".to_string())
-                            .into())
-                    })();
-
-                match res {
-                    Ok((buffer, w, h)) => (path.clone(), Some((buffer, w, h))),
-                    Err(_) => (path.clone(), None),
-                }
+                let res = (|| -> anyhow::Result<(Vec<u8>, u32, u32)> {
+                    let img = extract_thumbnail_frame(Path::new(path), &limit)?;
+                    let width = img.width();
+                    let height = img.height();
+
+                    let aspect_ratio = width as f32 / height as f32;
+                    let (new_w, new_h) = if width > height {
+                        (
+                            thumbnail_size,
+                            (thumbnail_size as f32 / aspect_ratio) as u32,
+                        )
+                    } else {
+                        (
+                            (thumbnail_size as f32 * aspect_ratio) as u32,
+                            thumbnail_size,
+                        )
+                    };
+
+                    let src_image = fr::images::Image::from_vec_u8(
+                        width,
+                        height,
+                        img.to_rgba8().into_raw(),
+                        fr::PixelType::U8x4,
+                    )?;
+
+                    let mut dst_image = fr::images::Image::new(new_w, new_h, fr::PixelType::U8x4);
+                    let mut resizer = fr::Resizer::new();
+                    resizer.resize(&src_image, &mut dst_image, None)?;
+
+                    Ok((dst_image.buffer().to_vec(), new_w, new_h))
+                })();
+
+                (path.clone(), res.map_err(|e| format!("{:#}", e)))
             })
-            .collect()
+            .collect::<Vec<_>>()
     });
 
     let mut py_results = Vec::new();
+    let mut errors = Vec::new();
     for (path, data) in results {
-        if let Some((buf, w, h)) = data {
-            py_results.push((path, PyBytes::new(py, &buf).into(), w, h));
+        match data {
+            Ok((buf, w, h)) => py_results.push((path, PyBytes::new(py, &buf).into(), w, h)),
+            Err(e) => errors.push((path, e)),
         }
     }
 
-    Ok(py_results)
+    Ok((py_results, errors))
 }
 
 #[cfg(feature = "python")]
@@ -434,6 +597,7 @@ pub fn delete_listing_secure(
 
 pub mod core;
 pub mod utils;
+#[cfg(feature = "web")]
 pub mod web;
 
 #[cfg(feature = "python")]
@@ -459,11 +623,17 @@ fn base(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(load_image_batch, m)?)?;
     m.add_function(wrap_pyfunction!(scan_files, m)?)?;
     m.add_function(wrap_pyfunction!(extract_video_thumbnails_batch, m)?)?;
+    m.add_function(wrap_pyfunction!(probe_ffmpeg_available, m)?)?;
 
     // Core Functions
     m.add_function(wrap_pyfunction!(convert_single_image, m)?)?;
     m.add_function(wrap_pyfunction!(convert_image_batch, m)?)?;
+    m.add_function(wrap_pyfunction!(convert_image_batch_detailed, m)?)?;
+    m.add_function(wrap_pyfunction!(apply_watermark_batch, m)?)?;
     m.add_function(wrap_pyfunction!(convert_video, m)?)?;
+    m.add_function(wrap_pyfunction!(convert_video_with_progress, m)?)?;
+    m.add_function(wrap_pyfunction!(detect_hw_encoders, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_video_frames, m)?)?;
     m.add_function(wrap_pyfunction!(set_wallpaper_gnome, m)?)?;
     m.add_function(wrap_pyfunction!(evaluate_kde_script, m)?)?;
 
@@ -471,10 +641,15 @@ fn base(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(get_files_by_extension, m)?)?;
     m.add_function(wrap_pyfunction!(delete_files_by_extensions, m)?)?;
     m.add_function(wrap_pyfunction!(delete_path, m)?)?;
+    m.add_function(wrap_pyfunction!(organize_files, m)?)?;
 
     // Image Finder
     m.add_function(wrap_pyfunction!(find_duplicate_images, m)?)?;
+    m.add_function(wrap_pyfunction!(delete_duplicates, m)?)?;
     m.add_function(wrap_pyfunction!(find_similar_images_phash, m)?)?;
+    m.add_function(wrap_pyfunction!(prune_hash_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(export_hash_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(import_hash_cache, m)?)?;
 
     // Image Merger
     m.add_function(wrap_pyfunction!(merge_images_horizontal, m)?)?;
@@ -484,6 +659,8 @@ fn base(m: &Bound<'_, PyModule>) -> PyResult<()> {
     // Web Functions
     m.add_function(wrap_pyfunction!(run_web_requests_sequence, m)?)?;
     m.add_function(wrap_pyfunction!(run_board_crawler, m)?)?;
+    m.add_function(wrap_pyfunction!(get_crawl_state, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_job_config, m)?)?;
     m.add_function(wrap_pyfunction!(run_reverse_image_search, m)?)?;
     m.add_function(wrap_pyfunction!(run_sync, m)?)?;
     m.add_function(wrap_pyfunction!(run_image_crawler, m)?)?;
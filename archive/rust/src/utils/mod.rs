@@ -1 +1,2 @@
+#[cfg(feature = "vault")]
 pub mod migration;
@@ -8,7 +8,10 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the config file is checked for changes between wallpapers
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
@@ -18,6 +21,9 @@ pub struct Config {
     pub interval_seconds: u64,
     #[serde(default = "default_style")]
     pub style: String,
+    /// Style for particular monitor ids, overriding `style` on those monitors
+    #[serde(default)]
+    pub monitor_styles: HashMap<String, String>,
     #[serde(default)]
     pub monitor_queues: HashMap<String, Vec<String>>,
     #[serde(default)]
@@ -36,6 +42,10 @@ pub struct Config {
     /// prefixes are shown.  An empty list means no filtering (show everything).
     #[serde(default)]
     pub filter_directories: Vec<String>,
+    /// Set by the GUI to move on to the next wallpapers right away; the daemon
+    /// clears it once it has done so
+    #[serde(default)]
+    pub advance_now: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -56,6 +66,19 @@ pub fn default_order() -> String {
     "Sequential".to_string()
 }
 
+/// The style to use on `monitor_id`: its entry in `monitor_styles`, or else
+/// the global `style`
+fn monitor_style<'a>(
+    style: &'a str,
+    monitor_styles: &'a HashMap<String, String>,
+    monitor_id: &str,
+) -> &'a str {
+    monitor_styles
+        .get(monitor_id)
+        .map(String::as_str)
+        .unwrap_or(style)
+}
+
 fn normalize_path(path: &str) -> String {
     path.trim_start_matches("file://")
         .trim_start_matches("file:/")
@@ -79,31 +102,45 @@ fn get_pid_path() -> Result<PathBuf> {
     Ok(get_config_dir()?.join(".slideshow.pid"))
 }
 
+/// The pid file, held while the daemon runs so a second one started
+/// alongside it exits instead of changing wallpapers on its own timer
 struct PidGuard(PathBuf);
 impl PidGuard {
     fn new(path: PathBuf) -> Result<Self> {
-        if path.exists() {
-            if let Ok(old_pid_content) = fs::read_to_string(&path) {
-                if let Ok(old_pid) = old_pid_content.trim().parse::<u32>() {
-                    if PathBuf::from(format!("/proc/{}", old_pid)).exists() {
-                        let cmdline = fs::read_to_string(format!("/proc/{}/cmdline", old_pid))
-                            .unwrap_or_default();
-                        let is_daemon = cmdline.contains("slideshow_daemon")
-                            && (cmdline.contains("bin") || cmdline.contains("target"));
-                        let is_wrapper =
-                            cmdline.contains("python") && cmdline.contains("slideshow_daemon.py");
-                        if is_daemon || is_wrapper {
+        loop {
+            // Created exclusively, so two daemons started together can't both
+            // find no pid file and carry on
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(Self(path));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let old_pid = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|content| content.trim().parse::<u32>().ok());
+                    if let Some(old_pid) = old_pid {
+                        if wallpaper::is_slideshow_daemon(old_pid) {
                             anyhow::bail!(
                                 "Slideshow daemon is already running (PID: {}). Exiting.",
                                 old_pid
                             );
                         }
                     }
+                    // Left behind by a daemon that was killed
+                    match fs::remove_file(&path) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
                 }
+                Err(e) => return Err(e.into()),
             }
         }
-        fs::write(&path, std::process::id().to_string())?;
-        Ok(Self(path))
     }
 }
 impl Drop for PidGuard {
@@ -119,6 +156,7 @@ fn load_config(path: &Path) -> Result<Config> {
             running: false,
             interval_seconds: 300,
             style: "Fill".to_string(),
+            monitor_styles: HashMap::new(),
             monitor_queues: HashMap::new(),
             current_paths: HashMap::new(),
             monitor_geometries: HashMap::new(),
@@ -127,6 +165,7 @@ fn load_config(path: &Path) -> Result<Config> {
             last_error: None,
             monitor_history: HashMap::new(),
             filter_directories: Vec::new(),
+            advance_now: false,
         });
     }
     let content = fs::read_to_string(path).context("Failed to read config file")?;
@@ -140,6 +179,25 @@ fn save_config(path: &Path, config: &Config) -> Result<()> {
     Ok(())
 }
 
+fn config_mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Sleep until `deadline`, or until the config at `path` is written (its
+/// modification time is no longer `seen`), whichever comes first
+fn wait_for_config_change(path: &Path, seen: Option<SystemTime>, deadline: Instant) {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        thread::sleep(remaining.min(CONFIG_POLL_INTERVAL));
+        if config_mtime(path) != seen {
+            return;
+        }
+    }
+}
+
 fn matches_filter(path: &str, filter_directories: &[String]) -> bool {
     if filter_directories.is_empty() {
         return true;
@@ -152,7 +210,15 @@ fn matches_filter(path: &str, filter_directories: &[String]) -> bool {
         .any(|d| norm.starts_with(d.as_str()))
 }
 
-fn select_next_wallpapers(config: &mut Config, increment: bool) -> HashMap<String, String> {
+/// Pick each monitor's next wallpaper and record it in `config`. With the
+/// "Random" (or "Shuffle") order, `monitor_history` holds what was shown
+/// since the queue was last exhausted, so nothing repeats until everything
+/// has been shown, and a restarted daemon carries on from the saved config.
+fn select_next_wallpapers(
+    config: &mut Config,
+    increment: bool,
+    rng: &mut impl rand::Rng,
+) -> HashMap<String, String> {
     let mut selected = HashMap::new();
     let mut monitor_ids: Vec<&String> = config.monitor_queues.keys().collect();
     monitor_ids.sort();
@@ -190,7 +256,7 @@ fn select_next_wallpapers(config: &mut Config, increment: bool) -> HashMap<Strin
         }
         if increment {
             match config.playback_order.as_str() {
-                "Random" => {
+                "Random" | "Shuffle" => {
                     use rand::seq::SliceRandom;
                     let history = config
                         .monitor_history
@@ -210,7 +276,7 @@ fn select_next_wallpapers(config: &mut Config, increment: bool) -> HashMap<Strin
                         }
                     }
 
-                    if let Some(&selected_idx) = valid_indices.choose(&mut rand::thread_rng()) {
+                    if let Some(&selected_idx) = valid_indices.choose(rng) {
                         idx = selected_idx;
                     }
 
@@ -232,15 +298,14 @@ fn select_next_wallpapers(config: &mut Config, increment: bool) -> HashMap<Strin
             }
         } else if !found {
             match config.playback_order.as_str() {
-                "Random" => {
+                "Random" | "Shuffle" => {
                     use rand::seq::SliceRandom;
                     let history = config
                         .monitor_history
                         .entry(monitor_id.clone())
                         .or_insert_with(Vec::new);
-                    if let Some(&selected_idx) = (0..queue.len())
-                        .collect::<Vec<usize>>()
-                        .choose(&mut rand::thread_rng())
+                    if let Some(&selected_idx) =
+                        (0..queue.len()).collect::<Vec<usize>>().choose(rng)
                     {
                         idx = selected_idx;
                     }
@@ -252,7 +317,7 @@ fn select_next_wallpapers(config: &mut Config, increment: bool) -> HashMap<Strin
                 "Reverse Sequential" => idx = queue.len() - 1,
                 _ => idx = 0,
             }
-        } else if config.playback_order.as_str() == "Random" {
+        } else if matches!(config.playback_order.as_str(), "Random" | "Shuffle") {
             let history = config
                 .monitor_history
                 .entry(monitor_id.clone())
@@ -315,34 +380,16 @@ fn get_best_video_plugin() -> Option<String> {
     None
 }
 
-fn apply_wallpaper_kde(
-    path_map: &HashMap<String, String>,
-    style: &str,
-    geometries: &HashMap<String, Geometry>,
-    log_path: &Option<PathBuf>,
-) -> Result<()> {
-    macro_rules! log {
-        ($($arg:tt)*) => {{
-            let msg = format!($($arg)*);
-            let now = chrono::Local::now().format("[%H:%M:%S]");
-            if let Some(ref lp) = log_path {
-                if let Ok(mut f) = fs::OpenOptions::new().append(true).open(lp) {
-                    let _ = writeln!(f, "{} {}", now, msg);
-                }
-            }
-        }};
-    }
-    let qdbus_bin = find_qdbus_binary();
-    log!("Using qdbus binary: {}", qdbus_bin);
-    let mut video_mode_active = false;
+/// KDE fill modes for `style`: the video plugin's when it's a
+/// `SmartVideoWallpaper::<mode>` style, and org.kde.image's
+fn kde_fill_modes(style: &str) -> (Option<i32>, i32) {
     let mut base_style_name = style;
-    let mut video_fill_mode = 2;
+    let mut video_fill_mode = None;
     if style.starts_with("SmartVideoWallpaper") && style.contains("::") {
-        video_mode_active = true;
         let parts: Vec<&str> = style.split("::").collect();
         if parts.len() > 1 {
             let v_style = parts[1].trim().to_lowercase();
-            video_fill_mode = if v_style.contains("keep proportions") {
+            video_fill_mode = Some(if v_style.contains("keep proportions") {
                 1
             } else if v_style.contains("scaled and cropped") {
                 2
@@ -350,7 +397,7 @@ fn apply_wallpaper_kde(
                 0
             } else {
                 2
-            };
+            });
             base_style_name = "Fill";
         }
     }
@@ -366,6 +413,34 @@ fn apply_wallpaper_kde(
         "Fill" => 2,
         _ => 2,
     };
+    (video_fill_mode, fill_mode)
+}
+
+fn apply_wallpaper_kde(
+    path_map: &HashMap<String, String>,
+    style: &str,
+    monitor_styles: &HashMap<String, String>,
+    geometries: &HashMap<String, Geometry>,
+    log_path: &Option<PathBuf>,
+) -> Result<()> {
+    macro_rules! log {
+        ($($arg:tt)*) => {{
+            let msg = format!($($arg)*);
+            let now = chrono::Local::now().format("[%H:%M:%S]");
+            if let Some(ref lp) = log_path {
+                if let Ok(mut f) = fs::OpenOptions::new().append(true).open(lp) {
+                    let _ = writeln!(f, "{} {}", now, msg);
+                }
+            }
+        }};
+    }
+    let qdbus_bin = find_qdbus_binary();
+    log!("Using qdbus binary: {}", qdbus_bin);
+    let video_mode_active = path_map.keys().any(|monitor_id| {
+        kde_fill_modes(monitor_style(style, monitor_styles, monitor_id))
+            .0
+            .is_some()
+    });
     log!("Fetching KDE desktops for mapping...");
     let mut kde_desktops = match wallpaper::get_kde_desktops_core(&qdbus_bin) {
         Ok(d) => d,
@@ -418,11 +493,14 @@ fn apply_wallpaper_kde(
             .get(monitor_id)
             .cloned()
             .unwrap_or_else(|| monitor_id.parse().unwrap_or(0));
+        let desktop_style = monitor_style(style, monitor_styles, monitor_id);
+        let (video_fill_mode, fill_mode) = kde_fill_modes(desktop_style);
         log!(
-            "Monitor {} -> KDE Desktop {} (Path: {})",
+            "Monitor {} -> KDE Desktop {} (Path: {}, Style: {})",
             monitor_id,
             i,
-            path
+            path,
+            desktop_style
         );
         let file_uri = if !path.starts_with("file://") {
             format!("file://{}", path)
@@ -440,7 +518,8 @@ fn apply_wallpaper_kde(
             .unwrap_or("")
             .to_lowercase();
         let dot_ext = format!(".{}", ext);
-        if video_extensions.contains(&dot_ext.as_str()) && video_mode_active {
+        let is_video = video_extensions.contains(&dot_ext.as_str());
+        if let Some(video_fill_mode) = video_fill_mode.filter(|_| is_video) {
             let is_smarter = target_plugin == "smartervideowallpaper";
             let video_key = if is_smarter {
                 "VideoWallpaperBackgroundVideo"
@@ -500,6 +579,7 @@ fn apply_wallpaper_kde(
 fn apply_wallpaper_kde_plasma_apply(
     path_map: &HashMap<String, String>,
     style: &str,
+    monitor_styles: &HashMap<String, String>,
     log_path: &Option<PathBuf>,
 ) -> Result<()> {
     macro_rules! log {
@@ -517,10 +597,12 @@ fn apply_wallpaper_kde_plasma_apply(
     let bin = "plasma-apply-wallpaperimage";
     let bin_path = which::which(bin).map_err(|e| anyhow::anyhow!("{} not found: {}", bin, e))?;
 
-    let path = path_map
-        .get("0")
-        .or_else(|| path_map.values().next())
+    // The tool sets every desktop at once, to the first monitor's image
+    let (monitor_id, path) = path_map
+        .get_key_value("0")
+        .or_else(|| path_map.iter().next())
         .context("No image path provided to set wallpaper")?;
+    let style = monitor_style(style, monitor_styles, monitor_id);
 
     let abs_path = fs::canonicalize(path).context("Invalid image path")?;
 
@@ -556,15 +638,21 @@ fn apply_wallpaper_kde_plasma_apply(
     Ok(())
 }
 
-fn apply_wallpaper_gnome(path_map: &HashMap<String, String>, style: &str) -> Result<()> {
-    if let Some(path) = path_map.values().next() {
+fn apply_wallpaper_gnome(
+    path_map: &HashMap<String, String>,
+    style: &str,
+    monitor_styles: &HashMap<String, String>,
+) -> Result<()> {
+    // GNOME has one background for all monitors, so the first monitor's
+    // image and style win
+    if let Some((monitor_id, path)) = path_map.iter().next() {
         let abs_path = fs::canonicalize(path).context("Invalid path")?;
         let file_uri = format!("file://{}", abs_path.to_string_lossy());
-        let mode = match style.to_lowercase().as_str() {
-            "none" | "wallpaper" | "centered" | "scaled" | "stretched" | "zoom" | "spanned" => {
-                style.to_lowercase()
-            }
-            _ => "zoom".to_string(),
+        let style = monitor_style(style, monitor_styles, monitor_id).to_lowercase();
+        let mode = if wallpaper::GNOME_STYLES.contains(&style.as_str()) {
+            style
+        } else {
+            "zoom".to_string()
         };
         wallpaper::set_wallpaper_gnome_core(&file_uri, &mode)
             .map_err(|e| anyhow::anyhow!("GNOME error: {}", e))?;
@@ -572,13 +660,52 @@ fn apply_wallpaper_gnome(path_map: &HashMap<String, String>, style: &str) -> Res
     Ok(())
 }
 
+/// Windows and macOS, which set one fit on every monitor: the style of
+/// monitor "0", or of any when there's no "0"
+fn apply_wallpaper_native(
+    path_map: &HashMap<String, String>,
+    style: &str,
+    monitor_styles: &HashMap<String, String>,
+) -> Result<()> {
+    let Some((monitor_id, _)) = path_map
+        .get_key_value("0")
+        .or_else(|| path_map.iter().next())
+    else {
+        return Ok(());
+    };
+    let fit = wallpaper::WallpaperFit::from_style(monitor_style(style, monitor_styles, monitor_id));
+    wallpaper::set_wallpaper_native_core(path_map, fit).map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+/// Hyprland, Sway and other wlroots compositors, through swaybg or
+/// hyprpaper, which fit each output separately
+fn apply_wallpaper_wlroots(
+    path_map: &HashMap<String, String>,
+    style: &str,
+    monitor_styles: &HashMap<String, String>,
+) -> Result<()> {
+    wallpaper::set_wallpaper_wlroots_core(path_map, |monitor_id| {
+        wallpaper::WallpaperFit::from_style(monitor_style(style, monitor_styles, monitor_id))
+    })
+    .map_err(|e| anyhow::anyhow!("wlroots error: {}", e))
+}
+
 #[derive(Debug)]
 enum DesktopEnvironment {
     Kde,
     Gnome,
+    Wlroots,
+    Windows,
+    MacOs,
     Unknown,
 }
 fn detect_desktop_environment() -> DesktopEnvironment {
+    if cfg!(target_os = "windows") {
+        return DesktopEnvironment::Windows;
+    }
+    if cfg!(target_os = "macos") {
+        return DesktopEnvironment::MacOs;
+    }
     let env = std::env::var("XDG_CURRENT_DESKTOP")
         .unwrap_or_default()
         .to_lowercase();
@@ -586,6 +713,8 @@ fn detect_desktop_environment() -> DesktopEnvironment {
         DesktopEnvironment::Kde
     } else if env.contains("gnome") || env.contains("ubuntu") {
         DesktopEnvironment::Gnome
+    } else if wallpaper::is_wlroots_session() {
+        DesktopEnvironment::Wlroots
     } else {
         DesktopEnvironment::Unknown
     }
@@ -632,6 +761,14 @@ fn main() {
     }
 }
 
+/// Wallpapers and styles the daemon last set
+#[derive(PartialEq)]
+struct Applied {
+    paths: HashMap<String, String>,
+    style: String,
+    monitor_styles: HashMap<String, String>,
+}
+
 fn run(log_path: &Option<PathBuf>) -> Result<()> {
     macro_rules! log {
         ($($arg:tt)*) => {{
@@ -652,6 +789,10 @@ fn run(log_path: &Option<PathBuf>) -> Result<()> {
     let de = detect_desktop_environment();
     log!("Detected desktop environment: {:?}", de);
     let mut first_run = true;
+    let mut last_advance = Instant::now();
+    // What was last applied, so a config change that doesn't touch it doesn't
+    // set the same wallpapers again
+    let mut applied: Option<Applied> = None;
     loop {
         // Retry logic for reading JSON to avoid race conditions with Python's write
         let mut config_result = None;
@@ -679,28 +820,61 @@ fn run(log_path: &Option<PathBuf>) -> Result<()> {
             log!("Slideshow disabled in config. Exiting.");
             break;
         }
-        let next_paths = select_next_wallpapers(&mut config, !first_run);
+        let interval = Duration::from_secs(config.interval_seconds.max(1));
+        let advance_now = config.advance_now;
+        if advance_now {
+            log!("Advance requested.");
+            config.advance_now = false;
+        }
+        let increment = advance_now || (!first_run && last_advance.elapsed() >= interval);
+        if increment {
+            last_advance = Instant::now();
+        }
+        let next_paths = select_next_wallpapers(&mut config, increment, &mut rand::thread_rng());
         if first_run {
             log!("Initial run. Current paths: {}", config.current_paths.len());
         }
-        if !next_paths.is_empty() {
-            log!("Applying wallpaper with style: {}", config.style);
+        let wanted = Applied {
+            paths: next_paths.clone(),
+            style: config.style.clone(),
+            monitor_styles: config.monitor_styles.clone(),
+        };
+        if !next_paths.is_empty() && applied.as_ref() != Some(&wanted) {
+            log!(
+                "Applying wallpaper with style: {} (per monitor: {:?})",
+                config.style,
+                config.monitor_styles
+            );
             let res = match de {
                 DesktopEnvironment::Kde => {
                     match apply_wallpaper_kde(
                         &next_paths,
                         &config.style,
+                        &config.monitor_styles,
                         &config.monitor_geometries,
                         log_path,
                     ) {
                         Ok(()) => Ok(()),
                         Err(e) => {
                             log!("KDE D-Bus wallpaper application failed: {}. Trying fallback with plasma-apply-wallpaperimage...", e);
-                            apply_wallpaper_kde_plasma_apply(&next_paths, &config.style, log_path)
+                            apply_wallpaper_kde_plasma_apply(
+                                &next_paths,
+                                &config.style,
+                                &config.monitor_styles,
+                                log_path,
+                            )
                         }
                     }
                 }
-                DesktopEnvironment::Gnome => apply_wallpaper_gnome(&next_paths, &config.style),
+                DesktopEnvironment::Gnome => {
+                    apply_wallpaper_gnome(&next_paths, &config.style, &config.monitor_styles)
+                }
+                DesktopEnvironment::Wlroots => {
+                    apply_wallpaper_wlroots(&next_paths, &config.style, &config.monitor_styles)
+                }
+                DesktopEnvironment::Windows | DesktopEnvironment::MacOs => {
+                    apply_wallpaper_native(&next_paths, &config.style, &config.monitor_styles)
+                }
                 _ => {
                     log!("Unsupported desktop environment.");
                     Ok(())
@@ -718,13 +892,19 @@ fn run(log_path: &Option<PathBuf>) -> Result<()> {
                     .as_secs();
                 config.last_error = None;
                 let _ = save_config(&config_path, &config);
+                applied = Some(wanted);
                 if !first_run {
                     log!("Successfully cycled wallpapers.");
                 }
             }
+        } else if advance_now {
+            // Nothing new to show, but the request still has to be cleared
+            let _ = save_config(&config_path, &config);
         }
         first_run = false;
-        thread::sleep(Duration::from_secs(config.interval_seconds.max(1)));
+        // Our own save above isn't a change to react to
+        let seen = config_mtime(&config_path);
+        wait_for_config_change(&config_path, seen, last_advance + interval);
     }
     Ok(())
 }
@@ -732,6 +912,7 @@ fn run(log_path: &Option<PathBuf>) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
 
     #[test]
     fn test_config_defaults() {
@@ -740,14 +921,36 @@ mod tests {
         assert_eq!(config.running, false);
         assert_eq!(config.interval_seconds, 300);
         assert_eq!(config.style, "Fill".to_string());
+        assert!(config.monitor_styles.is_empty());
+    }
+
+    #[test]
+    fn test_monitor_styles_override_global_style() {
+        let json = r#"{"style": "Fill", "monitor_styles": {"1": "Centered"}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            monitor_style(&config.style, &config.monitor_styles, "0"),
+            "Fill"
+        );
+        assert_eq!(
+            monitor_style(&config.style, &config.monitor_styles, "1"),
+            "Centered"
+        );
+        assert_eq!(kde_fill_modes("Centered"), (None, 6));
+        assert_eq!(
+            kde_fill_modes("SmartVideoWallpaper::Keep Proportions"),
+            (Some(1), 2)
+        );
     }
 
     #[test]
     fn test_selection_logic() {
+        let mut rng = StdRng::seed_from_u64(0);
         let mut config = Config {
             running: true,
             interval_seconds: 300,
             style: "Fill".to_string(),
+            monitor_styles: HashMap::new(),
             monitor_queues: HashMap::from([(
                 "0".to_string(),
                 vec!["a".to_string(), "b".to_string(), "c".to_string()],
@@ -759,27 +962,28 @@ mod tests {
             last_error: None,
             monitor_history: HashMap::new(),
             filter_directories: Vec::new(),
+            advance_now: false,
         };
 
         // Sequential: a -> b
-        let selected = select_next_wallpapers(&mut config, true);
+        let selected = select_next_wallpapers(&mut config, true, &mut rng);
         assert_eq!(selected.get("0").unwrap(), "b");
 
         // Sequential: b -> c
-        let selected = select_next_wallpapers(&mut config, true);
+        let selected = select_next_wallpapers(&mut config, true, &mut rng);
         assert_eq!(selected.get("0").unwrap(), "c");
 
         // Sequential: c -> a (loop)
-        let selected = select_next_wallpapers(&mut config, true);
+        let selected = select_next_wallpapers(&mut config, true, &mut rng);
         assert_eq!(selected.get("0").unwrap(), "a");
 
         // Reverse Sequential: a -> c
         config.playback_order = "Reverse Sequential".to_string();
-        let selected = select_next_wallpapers(&mut config, true);
+        let selected = select_next_wallpapers(&mut config, true, &mut rng);
         assert_eq!(selected.get("0").unwrap(), "c");
 
         // Reverse Sequential: c -> b
-        let selected = select_next_wallpapers(&mut config, true);
+        let selected = select_next_wallpapers(&mut config, true, &mut rng);
         assert_eq!(selected.get("0").unwrap(), "b");
 
         // Random: Should return some valid path and NOT repeat until all are shown
@@ -790,7 +994,7 @@ mod tests {
             .insert("0".to_string(), "a".to_string());
 
         // Initial setup for current path register
-        let _ = select_next_wallpapers(&mut config, false);
+        let _ = select_next_wallpapers(&mut config, false, &mut rng);
         assert!(config
             .monitor_history
             .get("0")
@@ -799,12 +1003,12 @@ mod tests {
 
         let mut selected_paths = Vec::new();
         // Cycle 1: should pick "b" or "c"
-        let sel1 = select_next_wallpapers(&mut config, true);
+        let sel1 = select_next_wallpapers(&mut config, true, &mut rng);
         let p1 = sel1.get("0").unwrap().clone();
         selected_paths.push(p1.clone());
 
         // Cycle 2: should pick the other of "b" or "c"
-        let sel2 = select_next_wallpapers(&mut config, true);
+        let sel2 = select_next_wallpapers(&mut config, true, &mut rng);
         let p2 = sel2.get("0").unwrap().clone();
         selected_paths.push(p2.clone());
 
@@ -813,13 +1017,72 @@ mod tests {
         assert!(selected_paths.contains(&"c".to_string()));
     }
 
+    fn shuffle_config(queue: &[&str]) -> Config {
+        let mut config: Config = serde_json::from_str(r#"{"playback_order": "Shuffle"}"#).unwrap();
+        config.monitor_queues.insert(
+            "0".to_string(),
+            queue.iter().map(|p| p.to_string()).collect(),
+        );
+        config
+    }
+
+    #[test]
+    fn test_shuffle_exhausts_queue_then_reshuffles() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut config = shuffle_config(&["a", "b", "c", "d"]);
+
+        let mut shown = vec![select_next_wallpapers(&mut config, false, &mut rng)["0"].clone()];
+        for _ in 0..3 {
+            shown.push(select_next_wallpapers(&mut config, true, &mut rng)["0"].clone());
+        }
+        let mut sorted = shown.clone();
+        sorted.sort();
+        assert_eq!(sorted, ["a", "b", "c", "d"]);
+
+        // Everything shown: start over, but not with the image just shown
+        for _ in 0..20 {
+            let last = config.current_paths["0"].clone();
+            let next = select_next_wallpapers(&mut config, true, &mut rng)["0"].clone();
+            assert_ne!(next, last);
+            let history = &config.monitor_history["0"];
+            assert_eq!(history.iter().filter(|p| **p == next).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_shuffle_resumes_after_restart() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut config = shuffle_config(&["a", "b", "c", "d", "e"]);
+        select_next_wallpapers(&mut config, false, &mut rng);
+        select_next_wallpapers(&mut config, true, &mut rng);
+        let before_restart = config.monitor_history["0"].clone();
+        assert_eq!(before_restart.len(), 2);
+
+        // The daemon saves the config after every change and reloads it on start
+        let saved = serde_json::to_string(&config).unwrap();
+        let mut config: Config = serde_json::from_str(&saved).unwrap();
+        let mut rng = StdRng::seed_from_u64(99);
+        let current = config.current_paths["0"].clone();
+        assert_eq!(
+            select_next_wallpapers(&mut config, false, &mut rng)["0"],
+            current
+        );
+        for _ in 0..3 {
+            let next = select_next_wallpapers(&mut config, true, &mut rng)["0"].clone();
+            assert!(!before_restart.contains(&next));
+        }
+        assert_eq!(config.monitor_history["0"].len(), 5);
+    }
+
     #[test]
     fn test_filter_directories_restricts_queue() {
+        let mut rng = StdRng::seed_from_u64(0);
         // Queue has paths under two different roots. Filter allows only /allowed/.
         let mut config = Config {
             running: true,
             interval_seconds: 300,
             style: "Fill".to_string(),
+            monitor_styles: HashMap::new(),
             monitor_queues: HashMap::from([(
                 "0".to_string(),
                 vec![
@@ -835,27 +1098,30 @@ mod tests {
             last_error: None,
             monitor_history: HashMap::new(),
             filter_directories: vec!["/allowed".to_string()],
+            advance_now: false,
         };
 
         // First selection from filtered queue ["/allowed/a.jpg", "/allowed/c.jpg"]
-        let sel1 = select_next_wallpapers(&mut config, false);
+        let sel1 = select_next_wallpapers(&mut config, false, &mut rng);
         assert!(sel1.get("0").unwrap().starts_with("/allowed/"));
 
-        let sel2 = select_next_wallpapers(&mut config, true);
+        let sel2 = select_next_wallpapers(&mut config, true, &mut rng);
         assert!(sel2.get("0").unwrap().starts_with("/allowed/"));
 
         // b.jpg must never be selected
-        let sel3 = select_next_wallpapers(&mut config, true);
+        let sel3 = select_next_wallpapers(&mut config, true, &mut rng);
         assert_ne!(sel3.get("0").unwrap(), "/other/b.jpg");
     }
 
     #[test]
     fn test_filter_directories_empty_falls_back_to_full_queue() {
+        let mut rng = StdRng::seed_from_u64(0);
         // Empty filter_directories → no filtering, all paths accessible
         let mut config = Config {
             running: true,
             interval_seconds: 300,
             style: "Fill".to_string(),
+            monitor_styles: HashMap::new(),
             monitor_queues: HashMap::from([(
                 "0".to_string(),
                 vec!["/x/a.jpg".to_string(), "/y/b.jpg".to_string()],
@@ -867,11 +1133,12 @@ mod tests {
             last_error: None,
             monitor_history: HashMap::new(),
             filter_directories: Vec::new(),
+            advance_now: false,
         };
-        let sel1 = select_next_wallpapers(&mut config, false);
+        let sel1 = select_next_wallpapers(&mut config, false, &mut rng);
         let p1 = sel1.get("0").unwrap();
         assert!(p1 == "/x/a.jpg" || p1 == "/y/b.jpg");
-        let sel2 = select_next_wallpapers(&mut config, true);
+        let sel2 = select_next_wallpapers(&mut config, true, &mut rng);
         let p2 = sel2.get("0").unwrap();
         assert!(p2 == "/x/a.jpg" || p2 == "/y/b.jpg");
         assert_ne!(p1, p2);
@@ -879,11 +1146,13 @@ mod tests {
 
     #[test]
     fn test_filter_no_match_falls_back_to_full_queue() {
+        let mut rng = StdRng::seed_from_u64(0);
         // Filter that matches nothing → fall back to full queue so slideshow keeps running
         let mut config = Config {
             running: true,
             interval_seconds: 300,
             style: "Fill".to_string(),
+            monitor_styles: HashMap::new(),
             monitor_queues: HashMap::from([(
                 "0".to_string(),
                 vec!["/home/user/wallpapers/a.jpg".to_string()],
@@ -895,8 +1164,9 @@ mod tests {
             last_error: None,
             monitor_history: HashMap::new(),
             filter_directories: vec!["/nonexistent/path".to_string()],
+            advance_now: false,
         };
-        let sel = select_next_wallpapers(&mut config, false);
+        let sel = select_next_wallpapers(&mut config, false, &mut rng);
         assert_eq!(
             sel.get("0").unwrap(),
             "/home/user/wallpapers/a.jpg",
@@ -904,6 +1174,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_config_change_cuts_the_wait_short() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, r#"{"running": true}"#).unwrap();
+        let seen = config_mtime(&path);
+
+        // Unchanged: waits out the deadline
+        let start = Instant::now();
+        wait_for_config_change(&path, seen, start + Duration::from_millis(200));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // Written by the GUI: wakes up long before the deadline
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                fs::write(&path, r#"{"running": true, "advance_now": true}"#).unwrap();
+            })
+        };
+        let start = Instant::now();
+        wait_for_config_change(&path, seen, start + Duration::from_secs(30));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        writer.join().unwrap();
+        let config = load_config(&path).unwrap();
+        assert!(config.advance_now);
+    }
+
+    #[test]
+    fn test_pid_guard_replaces_stale_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".slideshow.pid");
+        // Not a slideshow daemon: this test process
+        fs::write(&path, std::process::id().to_string()).unwrap();
+        {
+            let _guard = PidGuard::new(path.clone()).unwrap();
+            assert_eq!(
+                fs::read_to_string(&path).unwrap(),
+                std::process::id().to_string()
+            );
+        }
+        assert!(!path.exists());
+
+        fs::write(&path, "not a pid").unwrap();
+        assert!(PidGuard::new(path.clone()).is_ok());
+    }
+
     #[test]
     fn test_matches_filter_strips_file_uri_prefix() {
         assert!(matches_filter(
@@ -0,0 +1,448 @@
+use anyhow::{bail, Context, Result};
+use cookie_store::{CookieDomain, CookieExpiration, RawCookie};
+use reqwest::cookie::CookieStore;
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// A cookie as cookies.txt files, JSON files and the `cookies` config list
+/// give it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CookieRecord {
+    pub name: String,
+    pub value: String,
+    /// A leading dot, as older files have, means the same as `host_only`
+    /// being false
+    pub domain: String,
+    #[serde(default = "root_path")]
+    pub path: String,
+    /// Sent only to `domain` itself, not its subdomains
+    #[serde(default, alias = "hostOnly")]
+    pub host_only: bool,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default, alias = "httpOnly")]
+    pub http_only: bool,
+    /// Unix time it expires at; 0 for a cookie that lasts the session
+    #[serde(
+        default,
+        alias = "expirationDate",
+        deserialize_with = "deserialize_timestamp"
+    )]
+    pub expires: i64,
+}
+
+fn root_path() -> String {
+    "/".to_string()
+}
+
+/// Browser extensions export expiry times with fractions of a second
+fn deserialize_timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(0.0) as i64)
+}
+
+impl CookieRecord {
+    /// The cookie as a Set-Cookie value, and a URL it could have come from
+    fn to_set_cookie(&self, now: i64) -> Result<(String, Url)> {
+        let domain = self.domain.trim_start_matches('.');
+        if self.name.is_empty() || domain.is_empty() {
+            bail!("A cookie needs a name and a domain: {:?}", self);
+        }
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        let mut header = format!("{}={}; Path={}", self.name, self.value, path);
+        if !self.host_only && !self.domain.is_empty() {
+            header.push_str(&format!("; Domain={}", domain));
+        }
+        if self.secure {
+            header.push_str("; Secure");
+        }
+        if self.http_only {
+            header.push_str("; HttpOnly");
+        }
+        if self.expires > 0 {
+            header.push_str(&format!("; Max-Age={}", (self.expires - now).max(0)));
+        }
+        let url = Url::parse(&format!("https://{}{}", domain, path))
+            .with_context(|| format!("Bad cookie domain or path: {}{}", domain, path))?;
+        Ok((header, url))
+    }
+}
+
+/// Reads a Netscape cookies.txt file, as curl, wget and browser extensions
+/// write them: one cookie a line, with the domain, whether subdomains get
+/// it, path, whether it's https-only, expiry and name and value split by
+/// tabs. `#HttpOnly_` before the domain marks an HttpOnly cookie.
+pub fn parse_cookies_txt(text: &str) -> Result<Vec<CookieRecord>> {
+    let mut cookies = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        // An empty value may have lost its tab
+        if fields.len() != 7 && fields.len() != 6 {
+            bail!(
+                "Line {} of the cookies file has {} fields, not 7",
+                i + 1,
+                fields.len()
+            );
+        }
+        let flag = |field: &str| field.eq_ignore_ascii_case("TRUE");
+        let expires = fields[4]
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("Line {} has a bad expiry: {}", i + 1, fields[4]))?;
+        cookies.push(CookieRecord {
+            name: fields[5].to_string(),
+            value: fields.get(6).unwrap_or(&"").to_string(),
+            domain: fields[0].trim_start_matches('.').to_string(),
+            path: fields[2].to_string(),
+            host_only: !flag(fields[1]),
+            secure: flag(fields[3]),
+            http_only,
+            expires: expires as i64,
+        });
+    }
+    Ok(cookies)
+}
+
+/// Writes `cookies` in the format `parse_cookies_txt` reads
+pub fn write_cookies_txt(cookies: &[CookieRecord]) -> String {
+    let bool_field = |b: bool| if b { "TRUE" } else { "FALSE" };
+    let mut out = String::from("# Netscape HTTP Cookie File\n");
+    for cookie in cookies {
+        let domain = cookie.domain.trim_start_matches('.');
+        out.push_str(&format!(
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            if cookie.http_only { "#HttpOnly_" } else { "" },
+            if cookie.host_only { "" } else { "." },
+            domain,
+            bool_field(!cookie.host_only),
+            cookie.path,
+            bool_field(cookie.secure),
+            cookie.expires,
+            cookie.name,
+            cookie.value
+        ));
+    }
+    out
+}
+
+/// Whether `path` holds cookies as JSON rather than cookies.txt: a `.json`
+/// file, or one whose first character opens a list
+fn is_json_file(path: &Path, text: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+        || text.trim_start().starts_with('[')
+}
+
+/// The cookie store a session's client keeps its cookies in, which can be
+/// filled before a run and read back after it
+#[derive(Default)]
+pub struct CookieJar {
+    store: Mutex<cookie_store::CookieStore>,
+}
+
+impl CookieJar {
+    /// Adds `cookie`, unless it has expired
+    pub fn insert(&self, cookie: &CookieRecord) -> Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let (header, url) = cookie.to_set_cookie(now)?;
+        if cookie.expires > 0 && cookie.expires <= now {
+            return Ok(());
+        }
+        self.store
+            .lock()
+            .unwrap()
+            .parse(&header, &url)
+            .map(|_| ())
+            .map_err(|e| anyhow::anyhow!("Rejected cookie {}: {}", cookie.name, e))
+    }
+
+    /// The cookies that haven't expired, sorted by domain, path and name
+    pub fn records(&self) -> Vec<CookieRecord> {
+        let store = self.store.lock().unwrap();
+        let mut records: Vec<CookieRecord> = store
+            .iter_unexpired()
+            .filter_map(|cookie| {
+                let (domain, host_only) = match &cookie.domain {
+                    CookieDomain::HostOnly(domain) => (domain.clone(), true),
+                    CookieDomain::Suffix(domain) => (domain.clone(), false),
+                    _ => return None,
+                };
+                Some(CookieRecord {
+                    name: cookie.name().to_string(),
+                    value: cookie.value().to_string(),
+                    domain,
+                    path: String::from(cookie.path.as_ref()),
+                    host_only,
+                    secure: cookie.secure().unwrap_or(false),
+                    http_only: cookie.http_only().unwrap_or(false),
+                    expires: match &cookie.expires {
+                        CookieExpiration::AtUtc(at) => at.unix_timestamp(),
+                        CookieExpiration::SessionEnd => 0,
+                    },
+                })
+            })
+            .collect();
+        records.sort_by(|a, b| (&a.domain, &a.path, &a.name).cmp(&(&b.domain, &b.path, &b.name)));
+        records
+    }
+}
+
+impl CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| RawCookie::parse(value.to_string()).ok());
+        self.store
+            .lock()
+            .unwrap()
+            .store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let store = self.store.lock().unwrap();
+        let header = store
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+/// The cookies of a run: those in `cookies_file` from the runs before,
+/// then the `cookies` list of the config. `save` writes them all back.
+pub struct CookieSession {
+    jar: Arc<CookieJar>,
+    file: Option<PathBuf>,
+    /// Whether `file` is saved as JSON, as it was read
+    json: bool,
+}
+
+impl CookieSession {
+    pub fn from_config(config: &Value) -> Result<Self> {
+        let jar = Arc::new(CookieJar::default());
+        let file = config
+            .get("cookies_file")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        let mut json = file.as_ref().is_some_and(|path| is_json_file(path, ""));
+
+        // Not there before the first run
+        if let Some(path) = file.as_ref().filter(|path| path.exists()) {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read cookies from {:?}", path))?;
+            json = is_json_file(path, &text);
+            let cookies: Vec<CookieRecord> = if json {
+                serde_json::from_str(&text)
+                    .with_context(|| format!("Bad cookies JSON in {:?}", path))?
+            } else {
+                parse_cookies_txt(&text).with_context(|| format!("Bad cookies file {:?}", path))?
+            };
+            for cookie in &cookies {
+                jar.insert(cookie)?;
+            }
+        }
+
+        if let Some(list) = config.get("cookies").filter(|v| !v.is_null()) {
+            let cookies: Vec<CookieRecord> =
+                serde_json::from_value(list.clone()).context("Bad cookies list in the config")?;
+            for cookie in &cookies {
+                jar.insert(cookie)?;
+            }
+        }
+        Ok(CookieSession { jar, file, json })
+    }
+
+    /// The store to give the client, with `ClientBuilder::cookie_provider`
+    pub fn jar(&self) -> Arc<CookieJar> {
+        self.jar.clone()
+    }
+
+    /// Writes the cookies to `cookies_file`, when the config has one, in
+    /// the format it was read in or else the one it's named for
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let records = self.jar.records();
+        let text = if self.json {
+            serde_json::to_string_pretty(&records)?
+        } else {
+            write_cookies_txt(&records)
+        };
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, text).with_context(|| format!("Failed to save cookies to {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const COOKIES_TXT: &str = "# Netscape HTTP Cookie File\n\
+        # https://curl.se/docs/http-cookies.html\n\
+        \n\
+        .example.com\tTRUE\t/\tFALSE\t2000000000\tsession\tabc123\n\
+        #HttpOnly_booru.test\tFALSE\t/posts\tTRUE\t0\tpass_hash\tf00=\n\
+        example.com\tFALSE\t/\tFALSE\t0\tempty\n";
+
+    #[test]
+    fn test_cookies_txt_round_trip() {
+        let cookies = parse_cookies_txt(COOKIES_TXT).unwrap();
+        assert_eq!(
+            cookies,
+            vec![
+                CookieRecord {
+                    name: "session".to_string(),
+                    value: "abc123".to_string(),
+                    domain: "example.com".to_string(),
+                    path: "/".to_string(),
+                    host_only: false,
+                    secure: false,
+                    http_only: false,
+                    expires: 2000000000,
+                },
+                CookieRecord {
+                    name: "pass_hash".to_string(),
+                    value: "f00=".to_string(),
+                    domain: "booru.test".to_string(),
+                    path: "/posts".to_string(),
+                    host_only: true,
+                    secure: true,
+                    http_only: true,
+                    expires: 0,
+                },
+                CookieRecord {
+                    name: "empty".to_string(),
+                    value: String::new(),
+                    domain: "example.com".to_string(),
+                    path: "/".to_string(),
+                    host_only: true,
+                    secure: false,
+                    http_only: false,
+                    expires: 0,
+                },
+            ]
+        );
+
+        let written = write_cookies_txt(&cookies);
+        assert!(written.contains(".example.com\tTRUE\t/\tFALSE\t2000000000\tsession\tabc123\n"));
+        assert!(written.contains("#HttpOnly_booru.test\tFALSE\t/posts\tTRUE\t0\tpass_hash\tf00=\n"));
+        assert_eq!(parse_cookies_txt(&written).unwrap(), cookies);
+
+        // Windows line endings and fractional expiry times
+        let crlf = "a.test\tFALSE\t/\tFALSE\t1700000000.5\tk\tv\r\n";
+        assert_eq!(parse_cookies_txt(crlf).unwrap()[0].expires, 1700000000);
+        assert_eq!(parse_cookies_txt(crlf).unwrap()[0].value, "v");
+
+        let err = parse_cookies_txt("a.test\tFALSE\t/\tk\tv\n").unwrap_err();
+        assert!(err.to_string().contains("Line 1"), "{}", err);
+        assert!(parse_cookies_txt("a.test\tFALSE\t/\tFALSE\tsoon\tk\tv\n").is_err());
+    }
+
+    #[test]
+    fn test_cookie_jar() {
+        let jar = CookieJar::default();
+        for cookie in parse_cookies_txt(COOKIES_TXT).unwrap() {
+            jar.insert(&cookie).unwrap();
+        }
+        let header = |url: &str| {
+            jar.cookies(&Url::parse(url).unwrap())
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(
+            header("http://img.example.com/a.jpg").as_deref(),
+            Some("session=abc123")
+        );
+        assert_eq!(
+            header("https://booru.test/posts?page=2").as_deref(),
+            Some("pass_hash=f00=")
+        );
+        // Secure, host-only and path-bound
+        assert_eq!(header("http://booru.test/posts"), None);
+        assert_eq!(header("https://cdn.booru.test/posts"), None);
+        assert_eq!(header("https://booru.test/"), None);
+
+        // Set by a response
+        let set = HeaderValue::from_static("token=xyz; Path=/; HttpOnly");
+        jar.set_cookies(
+            &mut std::iter::once(&set),
+            &Url::parse("https://api.test/login").unwrap(),
+        );
+        let records = jar.records();
+        assert_eq!(records.len(), 4);
+        let token = records.iter().find(|c| c.name == "token").unwrap();
+        assert_eq!((token.domain.as_str(), token.host_only), ("api.test", true));
+        assert!(token.http_only);
+    }
+
+    #[test]
+    fn test_cookie_session_files() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in ["cookies.txt", "cookies.json"] {
+            let file = temp.path().join("nested").join(name);
+            let config = json!({
+                "cookies_file": file.to_str().unwrap(),
+                "cookies": [
+                    {"name": "auth", "value": "1", "domain": "example.com"},
+                    {"name": "old", "value": "x", "domain": "example.com", "expires": 1000}
+                ]
+            });
+            let session = CookieSession::from_config(&config).unwrap();
+            session.save().unwrap();
+
+            // The next run loads them back, without the config list
+            let session =
+                CookieSession::from_config(&json!({ "cookies_file": file.to_str().unwrap() }))
+                    .unwrap();
+            let records = session.jar().records();
+            assert_eq!(records.len(), 1, "{}", name);
+            assert_eq!(records[0].name, "auth");
+            assert!(!records[0].host_only);
+        }
+
+        // As browser extensions export them
+        let file = temp.path().join("export");
+        std::fs::write(
+            &file,
+            r#"[{"name": "sid", "value": "9", "domain": ".example.com", "hostOnly": false,
+                 "path": "/", "secure": true, "httpOnly": true, "expirationDate": 4102444800.25}]"#,
+        )
+        .unwrap();
+        let session =
+            CookieSession::from_config(&json!({ "cookies_file": file.to_str().unwrap() })).unwrap();
+        let records = session.jar().records();
+        assert_eq!(records[0].expires, 4102444800);
+        assert!(records[0].secure && records[0].http_only);
+        // Kept as JSON, though it isn't named for it
+        session.save().unwrap();
+        let saved: Vec<CookieRecord> =
+            serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(saved, records);
+
+        let bad = json!({ "cookies": [{"name": "no_domain", "value": "1", "domain": ""}] });
+        assert!(CookieSession::from_config(&bad).is_err());
+    }
+}
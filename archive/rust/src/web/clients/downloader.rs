@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use image::ImageReader;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Number of leading bytes fetched when probing remote image dimensions.
+const PROBE_BYTES: usize = 64 * 1024;
+
+pub const BROWSER_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+/// Replaces characters that are invalid in file names on common platforms and
+/// trims the result to a sane length. Never returns an empty string.
+pub fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = cleaned.trim().trim_matches('.');
+    let truncated: String = trimmed.chars().take(150).collect();
+    if truncated.is_empty() {
+        "image".to_string()
+    } else {
+        truncated
+    }
+}
+
+/// Derives a file name from the last path segment of `url`, falling back to
+/// `image.jpg` when the URL has none.
+pub fn filename_from_url(url: &str) -> String {
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|s| s.rsplit('/').next())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("image.jpg");
+    sanitize_filename(name)
+}
+
+/// Returns `dir/filename`, appending " (n)" before the extension until the path is free.
+pub fn unique_path(dir: &Path, filename: &str) -> PathBuf {
+    unique_path_by(dir, filename, |path| path.exists())
+}
+
+/// Like `unique_path`, with `taken` deciding which paths aren't free.
+pub fn unique_path_by(dir: &Path, filename: &str, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let mut path = dir.join(filename);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("image")
+        .to_string();
+    let ext = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|e| e.to_string());
+
+    let mut counter = 1;
+    while taken(&path) {
+        let candidate = match &ext {
+            Some(ext) => format!("{} ({}).{}", stem, counter, ext),
+            None => format!("{} ({})", stem, counter),
+        };
+        path = dir.join(candidate);
+        counter += 1;
+    }
+    path
+}
+
+/// `scheme://host/` of `url`, used as a Referer to get past hotlink protection.
+pub fn referer_for(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    Some(format!("{}://{}/", parsed.scheme(), parsed.host_str()?))
+}
+
+/// Writes `data` to a sibling temp file and renames it over `path`, so readers
+/// never observe a partially written file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create directories")?;
+    }
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("download");
+    let tmp = path.with_file_name(format!(".{}.part", file_name));
+    fs::write(&tmp, data).context("Failed to write temporary file")?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).context("Failed to move download into place");
+    }
+    Ok(())
+}
+
+/// Downloads `url` into `dest_dir` under a sanitized, unique file name.
+pub async fn download_to_dir(
+    client: &reqwest::Client,
+    url: &str,
+    dest_dir: &Path,
+    referer: Option<&str>,
+) -> Result<PathBuf> {
+    let mut request = client.get(url).header(
+        "Accept",
+        "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8",
+    );
+    if let Some(referer) = referer.map(String::from).or_else(|| referer_for(url)) {
+        request = request.header("Referer", referer);
+    }
+
+    let response = request.send().await.context("Request failed")?;
+    let response = response.error_for_status().context("Bad status")?;
+    let bytes = response
+        .bytes()
+        .await
+        .context("Failed to read response body")?;
+
+    fs::create_dir_all(dest_dir).context("Failed to create destination directory")?;
+    let save_path = unique_path(dest_dir, &filename_from_url(url));
+    write_atomic(&save_path, &bytes)?;
+    Ok(save_path)
+}
+
+/// Fetches only the first few KB of `url` and decodes the image header to get
+/// its dimensions. Returns `None` when the format cannot be determined.
+pub async fn probe_image_dimensions(
+    client: &reqwest::Client,
+    url: &str,
+    referer: Option<&str>,
+) -> Result<Option<(u32, u32)>> {
+    let mut request = client
+        .get(url)
+        .header("Range", format!("bytes=0-{}", PROBE_BYTES - 1));
+    if let Some(referer) = referer.map(String::from).or_else(|| referer_for(url)) {
+        request = request.header("Referer", referer);
+    }
+
+    let mut response = request
+        .send()
+        .await
+        .context("Probe request failed")?
+        .error_for_status()
+        .context("Bad status")?;
+
+    // Servers that ignore Range send the whole body; stop reading once we have enough
+    let mut head = Vec::with_capacity(PROBE_BYTES);
+    while let Some(chunk) = response.chunk().await? {
+        head.extend_from_slice(&chunk);
+        if head.len() >= PROBE_BYTES {
+            break;
+        }
+    }
+
+    let reader = match ImageReader::new(Cursor::new(head)).with_guessed_format() {
+        Ok(r) => r,
+        Err(_) => return Ok(None),
+    };
+    Ok(reader.into_dimensions().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("a/b:c*?.jpg"), "a_b_c__.jpg");
+        assert_eq!(sanitize_filename("  ..  "), "image");
+        assert_eq!(sanitize_filename(&"x".repeat(300)).len(), 150);
+    }
+
+    #[test]
+    fn test_filename_from_url() {
+        assert_eq!(
+            filename_from_url("https://cdn.test/img/photo.png?w=100#top"),
+            "photo.png"
+        );
+        assert_eq!(filename_from_url("https://cdn.test/"), "image.jpg");
+    }
+
+    #[test]
+    fn test_unique_path_and_atomic_write() {
+        let temp = tempdir().unwrap();
+        let first = unique_path(temp.path(), "pic.jpg");
+        write_atomic(&first, b"one").unwrap();
+        let second = unique_path(temp.path(), "pic.jpg");
+        assert_eq!(second, temp.path().join("pic (1).jpg"));
+        assert_eq!(fs::read(&first).unwrap(), b"one");
+        assert!(!temp.path().join(".pic.jpg.part").exists());
+    }
+
+    #[test]
+    fn test_referer_for() {
+        assert_eq!(
+            referer_for("https://img.example.com/a/b.jpg"),
+            Some("https://img.example.com/".to_string())
+        );
+        assert_eq!(referer_for("not a url"), None);
+    }
+}
@@ -1,2 +1,8 @@
+pub mod cookies;
+pub mod downloader;
+#[cfg(feature = "selenium")]
 pub mod file_loader;
+pub mod proxy;
+pub mod rate_limit;
+pub mod retry;
 pub mod web_requests;
@@ -0,0 +1,147 @@
+use crate::web::progress::ProgressSink;
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a wait sleeps at a time, so a long one still notices a cancel
+const WAIT_SLICE: Duration = Duration::from_millis(200);
+
+/// A token bucket: `burst` requests can go at once, and after that one
+/// each `60 / requests_per_minute` seconds. Shared by everything that
+/// talks to the same site, so the cap holds across them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    burst: u32,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Requests that can go now; below zero once requests are queued
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: f64, burst: u32) -> Self {
+        let burst = burst.max(1);
+        RateLimiter {
+            interval: Duration::from_secs_f64(60.0 / requests_per_minute),
+            burst,
+            state: Mutex::new(Bucket {
+                tokens: burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// The `rate_limit` block of `config`, `{"requests_per_minute": 30}`
+    /// with an optional `burst` (1 by default), or `None` without one
+    pub fn from_config(config: &Value) -> Result<Option<Self>> {
+        let Some(block) = config.get("rate_limit").filter(|v| !v.is_null()) else {
+            return Ok(None);
+        };
+        let Some(per_minute) = block.get("requests_per_minute").and_then(|v| v.as_f64()) else {
+            bail!("rate_limit needs requests_per_minute");
+        };
+        if !per_minute.is_finite() || per_minute <= 0.0 {
+            bail!("requests_per_minute must be above 0, not {}", per_minute);
+        }
+        let burst = block.get("burst").and_then(|v| v.as_u64()).unwrap_or(1) as u32;
+        Ok(Some(RateLimiter::new(per_minute, burst)))
+    }
+
+    pub fn requests_per_minute(&self) -> f64 {
+        60.0 / self.interval.as_secs_f64()
+    }
+
+    /// Takes a turn for a request made at `now`, and says how long to wait
+    /// before making it
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.state.lock().unwrap();
+        let refilled = now.saturating_duration_since(bucket.updated).as_secs_f64()
+            / self.interval.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(self.burst as f64);
+        bucket.updated = now;
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            self.interval.mul_f64(-bucket.tokens)
+        }
+    }
+
+    /// Waits for this request's turn. Tells `sink` about waits of a second
+    /// or more; returns early if it asks to stop.
+    pub fn wait(&self, sink: &dyn ProgressSink) {
+        let wait = self.reserve(Instant::now());
+        if wait.is_zero() {
+            return;
+        }
+        if wait >= Duration::from_secs(1) {
+            sink.on_status(&format!(
+                "Rate limiting active: Waiting {:.1}s...",
+                wait.as_secs_f64()
+            ));
+        }
+        let until = Instant::now() + wait;
+        loop {
+            let now = Instant::now();
+            if now >= until || sink.is_cancelled() {
+                return;
+            }
+            thread::sleep((until - now).min(WAIT_SLICE));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_token_bucket() {
+        // One a second, three at once
+        let limiter = RateLimiter::new(60.0, 3);
+        let start = Instant::now();
+        let secs = |n: f64| Duration::from_secs_f64(n);
+
+        for _ in 0..3 {
+            assert_eq!(limiter.reserve(start), Duration::ZERO);
+        }
+        // Queued up behind each other
+        assert_eq!(limiter.reserve(start), secs(1.0));
+        assert_eq!(limiter.reserve(start), secs(2.0));
+        // Two seconds on, the two queued have gone
+        assert_eq!(limiter.reserve(start + secs(2.0)), secs(1.0));
+        // A long pause only earns `burst` requests
+        let later = start + secs(60.0);
+        for _ in 0..3 {
+            assert_eq!(limiter.reserve(later), Duration::ZERO);
+        }
+        assert_eq!(limiter.reserve(later + secs(0.5)), secs(0.5));
+    }
+
+    #[test]
+    fn test_rate_limit_config() {
+        assert!(RateLimiter::from_config(&json!({})).unwrap().is_none());
+        let limiter = RateLimiter::from_config(&json!({"rate_limit": {"requests_per_minute": 30}}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(limiter.requests_per_minute(), 30.0);
+        assert_eq!(limiter.burst, 1);
+        assert_eq!(limiter.interval, Duration::from_secs(2));
+
+        for bad in [
+            json!({"rate_limit": {}}),
+            json!({"rate_limit": {"requests_per_minute": 0}}),
+            json!({"rate_limit": {"requests_per_minute": -5}}),
+        ] {
+            assert!(RateLimiter::from_config(&bad).is_err());
+        }
+    }
+}
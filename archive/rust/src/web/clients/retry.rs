@@ -0,0 +1,345 @@
+use crate::web::cloud::checksum::ChecksumMismatch;
+use crate::web::progress::ProgressSink;
+use anyhow::Result;
+use rand::Rng;
+use reqwest::blocking::Response;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A response with a status that isn't a success, and how long the server
+/// asked to be left alone for if it said
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: StatusCode,
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad status: {}", self.status)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// `response` if its status is a success, else an `HttpStatusError` with
+/// the Retry-After it came with
+pub fn check_status(response: Response) -> Result<Response, HttpStatusError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    Err(HttpStatusError {
+        status,
+        retry_after,
+    })
+}
+
+/// A Retry-After value, either seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.signed_duration_since(chrono::Utc::now());
+    Some(wait.to_std().unwrap_or_default())
+}
+
+/// Whether a failed attempt is worth another, and when the server said to
+/// come back
+#[derive(Debug, PartialEq)]
+enum Failure {
+    Permanent,
+    Transient(Option<Duration>),
+}
+
+fn classify(err: &anyhow::Error) -> Failure {
+    let status_failure = |status: StatusCode, retry_after: Option<Duration>| match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+            Failure::Transient(retry_after)
+        }
+        StatusCode::REQUEST_TIMEOUT | StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT => {
+            Failure::Transient(None)
+        }
+        status if status.is_server_error() => Failure::Transient(None),
+        // 404, 403 and the rest won't change by asking again
+        _ => Failure::Permanent,
+    };
+    for cause in err.chain() {
+        // A transfer cut short comes out whole on a second try
+        if cause.is::<ChecksumMismatch>() {
+            return Failure::Transient(None);
+        }
+        if let Some(e) = cause.downcast_ref::<HttpStatusError>() {
+            return status_failure(e.status, e.retry_after);
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return match e.status() {
+                Some(status) => status_failure(status, None),
+                // A body that isn't what was expected won't be next time
+                None if e.is_decode() || e.is_builder() => Failure::Permanent,
+                // Connection failures, timeouts and cut-off bodies
+                None => Failure::Transient(None),
+            };
+        }
+    }
+    Failure::Permanent
+}
+
+/// How often and how patiently to retry requests that failed in a way
+/// that might pass: network errors, 429, 408 and 5xx. Waits double from
+/// `base_delay` up to `max_delay`, with jitter, unless the server sends
+/// Retry-After; one longer than `max_retry_after` ends the retries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_retry_after: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// `max_retries`, `retry_base_delay` and `max_retry_after` (both in
+    /// seconds) from a crawl config
+    pub fn from_config(config: &Value) -> Self {
+        let default = Self::default();
+        RetryPolicy {
+            max_retries: config
+                .get("max_retries")
+                .and_then(|v| v.as_u64())
+                .map_or(default.max_retries, |n| n as u32),
+            base_delay: config
+                .get("retry_base_delay")
+                .and_then(|v| v.as_f64())
+                .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                .map_or(default.base_delay, Duration::from_secs_f64),
+            max_retry_after: config
+                .get("max_retry_after")
+                .and_then(|v| v.as_f64())
+                .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                .map_or(default.max_retry_after, Duration::from_secs_f64),
+            ..default
+        }
+    }
+
+    /// Wait before retry number `retry` (0 for the first): the server's
+    /// Retry-After if it gave one, else the doubled delay, somewhere
+    /// between half and all of it so crawls don't retry in lockstep. None
+    /// if the server asked for more than `max_retry_after`, which isn't
+    /// worth sitting through.
+    fn delay(
+        &self,
+        retry: u32,
+        retry_after: Option<Duration>,
+        rng: &mut impl Rng,
+    ) -> Option<Duration> {
+        if let Some(wait) = retry_after {
+            return (wait <= self.max_retry_after).then_some(wait);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        Some(backoff.mul_f64(rng.gen_range(0.5..=1.0)))
+    }
+
+    /// Run `attempt` until it succeeds, fails for good or runs out of
+    /// retries, telling `sink` about each retry of `what`. A Retry-After
+    /// over `max_retry_after` fails for good. Waits end early if the run is
+    /// cancelled, returning the last error.
+    pub fn run<T>(
+        &self,
+        sink: &dyn ProgressSink,
+        what: &str,
+        mut attempt: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let mut retry = 0;
+        loop {
+            let err = match attempt() {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let Failure::Transient(retry_after) = classify(&err) else {
+                return Err(err);
+            };
+            if retry >= self.max_retries {
+                return Err(err);
+            }
+            let Some(wait) = self.delay(retry, retry_after, &mut rand::thread_rng()) else {
+                let asked = retry_after.unwrap_or_default().as_secs();
+                return Err(err.context(format!(
+                    "Not retrying {}: the server asked to wait {}s, more than {}s",
+                    what,
+                    asked,
+                    self.max_retry_after.as_secs()
+                )));
+            };
+            retry += 1;
+            sink.on_status(&format!(
+                "Retrying {} in {:.1}s ({:#}), attempt {} of {}...",
+                what,
+                wait.as_secs_f32(),
+                err,
+                retry,
+                self.max_retries
+            ));
+            let deadline = Instant::now() + wait;
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                if sink.is_cancelled() {
+                    return Err(err);
+                }
+                thread::sleep(left.min(Duration::from_millis(100)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    fn status_error(status: u16, retry_after: Option<u64>) -> anyhow::Error {
+        anyhow::Error::new(HttpStatusError {
+            status: StatusCode::from_u16(status).unwrap(),
+            retry_after: retry_after.map(Duration::from_secs),
+        })
+        .context("Download failed")
+    }
+
+    #[test]
+    fn test_classify_statuses() {
+        assert_eq!(
+            classify(&status_error(429, Some(7))),
+            Failure::Transient(Some(Duration::from_secs(7)))
+        );
+        assert_eq!(classify(&status_error(503, None)), Failure::Transient(None));
+        assert_eq!(
+            classify(&status_error(502, Some(7))),
+            Failure::Transient(None)
+        );
+        assert_eq!(classify(&status_error(404, None)), Failure::Permanent);
+        assert_eq!(classify(&status_error(403, None)), Failure::Permanent);
+        assert_eq!(
+            classify(&anyhow::anyhow!("Failed to create file")),
+            Failure::Permanent
+        );
+        let mismatch = anyhow::Error::new(ChecksumMismatch {
+            path: "a.jpg".to_string(),
+            expected: "x".to_string(),
+            actual: "y".to_string(),
+        })
+        .context("Download failed");
+        assert_eq!(classify(&mismatch), Failure::Transient(None));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let soon = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let wait = parse_retry_after(&soon).unwrap();
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    struct QuietSink;
+
+    impl ProgressSink for QuietSink {
+        fn on_status(&self, _message: &str) {}
+        fn on_error(&self, _message: &str) {}
+        fn on_image_saved(&self, _path: &str) {}
+    }
+
+    #[test]
+    fn test_delay_backs_off_with_jitter() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_retry_after: Duration::from_secs(60),
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        for (retry, full) in [(0, 1), (1, 2), (2, 4), (3, 8), (4, 10), (9, 10)] {
+            let full = Duration::from_secs(full);
+            let delay = policy.delay(retry, None, &mut rng).unwrap();
+            assert!(delay >= full / 2 && delay <= full, "{}: {:?}", retry, delay);
+        }
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(30)), &mut rng),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_oversized_retry_after_fails_for_good() {
+        let policy = RetryPolicy::default();
+        let mut rng = StdRng::seed_from_u64(7);
+        let day = parse_retry_after("86400");
+        assert_eq!(policy.delay(0, day, &mut rng), None);
+        let days_away = (chrono::Utc::now() + chrono::Duration::days(3)).to_rfc2822();
+        assert_eq!(
+            policy.delay(0, parse_retry_after(&days_away), &mut rng),
+            None
+        );
+        assert_eq!(
+            policy.delay(0, Some(policy.max_retry_after), &mut rng),
+            Some(policy.max_retry_after)
+        );
+
+        let mut attempts = 0;
+        let started = Instant::now();
+        let err = policy
+            .run(&QuietSink, "page 1", || -> Result<()> {
+                attempts += 1;
+                Err(status_error(429, Some(86400)))
+            })
+            .unwrap_err();
+        assert_eq!(attempts, 1);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(format!("{:#}", err).contains("asked to wait 86400s"));
+        assert!(err.chain().any(|cause| cause.is::<HttpStatusError>()));
+    }
+
+    #[test]
+    fn test_policy_from_config() {
+        let policy = RetryPolicy::from_config(&json!({
+            "max_retries": 5,
+            "retry_base_delay": 0.25
+        }));
+        assert_eq!(policy.max_retries, 5);
+        assert_eq!(policy.base_delay, Duration::from_millis(250));
+        assert_eq!(
+            policy.max_retry_after,
+            RetryPolicy::default().max_retry_after
+        );
+        let policy = RetryPolicy::from_config(&json!({ "max_retry_after": 30 }));
+        assert_eq!(policy.max_retry_after, Duration::from_secs(30));
+        assert_eq!(RetryPolicy::from_config(&json!({})), RetryPolicy::default());
+    }
+}
@@ -1,9 +1,27 @@
 #[cfg(feature = "python")]
-use anyhow::{Context, Result};
+use crate::web::clients::cookies::CookieSession;
+#[cfg(feature = "python")]
+use crate::web::clients::proxy::ProxyConfig;
+#[cfg(feature = "python")]
+use crate::web::clients::rate_limit::RateLimiter;
+#[cfg(feature = "python")]
+use crate::web::config::{validate_job, wait_from_secs};
+#[cfg(feature = "python")]
+use crate::web::progress::PyProgressSink;
+#[cfg(feature = "python")]
+use anyhow::{anyhow, bail, Context, Result};
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
 #[cfg(feature = "python")]
-use reqwest::blocking::{Client, Response};
+use regex::Regex;
+#[cfg(feature = "python")]
+use reqwest::blocking::{Client, RequestBuilder, Response};
+#[cfg(feature = "python")]
+use reqwest::header::HeaderMap;
+#[cfg(feature = "python")]
+use reqwest::{Method, StatusCode};
+#[cfg(feature = "python")]
+use serde::Serialize;
 #[cfg(feature = "python")]
 use serde_json::Value;
 #[cfg(feature = "python")]
@@ -15,6 +33,26 @@ use std::path::Path;
 #[cfg(feature = "python")]
 use std::time::Duration;
 
+/// Nesting depth beyond which "Print JSON (pretty)" collapses containers.
+#[cfg(feature = "python")]
+const JSON_PRINT_MAX_DEPTH: usize = 8;
+
+/// Requests a minute without a `rate_limit` in the config
+#[cfg(feature = "python")]
+const DEFAULT_REQUESTS_PER_MINUTE: f64 = 120.0;
+/// Maximum number of characters emitted by "Print JSON (pretty)".
+#[cfg(feature = "python")]
+const JSON_PRINT_MAX_CHARS: usize = 20_000;
+/// Methods a request in the sequence may use.
+#[cfg(feature = "python")]
+const METHODS: [&str; 6] = ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD"];
+
+/// Values placeholders can reach into by path besides the saved variables,
+/// e.g. `{last_response.json.token}`. `last_response` holds the `status`,
+/// `headers` and parsed `json` body of the latest response.
+#[cfg(feature = "python")]
+type RequestContext = HashMap<String, Value>;
+
 #[cfg(feature = "python")]
 #[pyfunction]
 pub fn run_web_requests_sequence(
@@ -25,6 +63,8 @@ pub fn run_web_requests_sequence(
     let config_val: Value = serde_json::from_str(&config).map_err(|e| {
         PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid JSON: {}", e))
     })?;
+    validate_job("web_requests", &config_val)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
 
     let base_url = config_val
         .get("base_url")
@@ -40,122 +80,607 @@ pub fn run_web_requests_sequence(
         .and_then(|v| v.as_array())
         .cloned()
         .unwrap_or_default();
+    let repeat = config_val.get("repeat").map(RepeatConfig::from_value);
+
+    let config_error =
+        |e: anyhow::Error| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:#}", e));
+    let cookies = CookieSession::from_config(&config_val).map_err(config_error)?;
+    let proxy = ProxyConfig::from_config(&config_val).map_err(config_error)?;
+    let limiter = RateLimiter::from_config(&config_val)
+        .map_err(config_error)?
+        .unwrap_or_else(|| RateLimiter::new(DEFAULT_REQUESTS_PER_MINUTE, 1));
 
-    let client = Client::builder()
+    let mut builder = Client::builder()
         .timeout(Duration::from_secs(15))
-        .build()
-        .map_err(|e| {
-            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
-                "Failed to create client: {}",
-                e
-            ))
-        })?;
+        .cookie_provider(cookies.jar());
+    if let Some(proxy) = &proxy {
+        builder = builder.proxy(proxy.to_reqwest().map_err(config_error)?);
+    }
+    let client = builder.build().map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Failed to create client: {}", e))
+    })?;
 
     emit_status(
         py,
         &callback_obj,
         &format!("Starting request sequence for {}", base_url),
     )?;
-
-    for (i, req) in requests.iter().enumerate() {
-        // Check for cancellation (if the python object has a flag)
-        if let Ok(is_running) = callback_obj.getattr(py, "_is_running") {
-            if !is_running.extract::<bool>(py)? {
-                emit_status(py, &callback_obj, "Request sequence cancelled.")?;
-                return Ok("Cancelled.".to_string());
-            }
-        }
-
-        let req_type = req.get("type").and_then(|v| v.as_str()).unwrap_or("GET");
-        let param = req.get("param").and_then(|v| v.as_str()).unwrap_or("");
-
-        let mut url_to_request = base_url.to_string();
+    if let Some(proxy) = &proxy {
         emit_status(
             py,
             &callback_obj,
-            &format!(
-                "--- Request {}/{}: [{}] ---",
-                i + 1,
-                requests.len(),
-                req_type
-            ),
+            &format!("Using proxy {}", proxy.display()),
         )?;
+    }
 
-        let response_res = match req_type {
-            "GET" => {
-                if !param.is_empty() {
-                    url_to_request = format!(
-                        "{}/{}",
-                        base_url.trim_end_matches('/'),
-                        param.trim_start_matches('/')
-                    );
-                }
-                emit_status(
-                    py,
-                    &callback_obj,
-                    &format!("Executing GET: {}", url_to_request),
-                )?;
-                client.get(&url_to_request).send()
+    let mut variables: HashMap<String, String> = HashMap::new();
+    let mut context = RequestContext::new();
+    let mut totals = SequenceOutcome::default();
+
+    let report = match repeat {
+        None => {
+            let outcome = run_sequence_once(
+                py,
+                &client,
+                base_url,
+                &requests,
+                &actions,
+                &mut variables,
+                &mut context,
+                &limiter,
+                &callback_obj,
+            )?;
+            if !outcome.cancelled {
+                emit_status(py, &callback_obj, "--- All requests finished. ---")?;
             }
-            "POST" => {
-                let post_data = parse_post_data(param);
+            totals.add(&outcome);
+            sequence_report(&totals, 1, &outcome)
+        }
+        Some(repeat) => {
+            let mut iteration: u64 = 0;
+            loop {
+                iteration += 1;
+                if !repeat.persist_variables {
+                    variables.clear();
+                    context.clear();
+                }
                 emit_status(
                     py,
                     &callback_obj,
                     &format!(
-                        "Executing POST: {} with data: {:?}",
-                        url_to_request, post_data
+                        "=== Iteration {}{} ===",
+                        iteration,
+                        if repeat.count > 0 {
+                            format!("/{}", repeat.count)
+                        } else {
+                            String::new()
+                        }
                     ),
                 )?;
-                client.post(&url_to_request).form(&post_data).send()
-            }
-            _ => {
-                emit_error(
+
+                let last = run_sequence_once(
                     py,
+                    &client,
+                    base_url,
+                    &requests,
+                    &actions,
+                    &mut variables,
+                    &mut context,
+                    &limiter,
                     &callback_obj,
-                    &format!("Unsupported request type: {}", req_type),
                 )?;
+                totals.add(&last);
+                if last.cancelled {
+                    break sequence_report(&totals, iteration, &last);
+                }
+
+                let summary = serde_json::json!({
+                    "iteration": iteration,
+                    "requests": requests.len(),
+                    "succeeded": last.succeeded,
+                    "failed": last.failed,
+                    "results": last.results,
+                    "variables": variables,
+                });
+                emit_iteration_completed(py, &callback_obj, iteration, &summary.to_string())?;
+
+                if repeat.count > 0 && iteration >= repeat.count {
+                    emit_status(
+                        py,
+                        &callback_obj,
+                        &format!("--- All requests finished ({} iterations). ---", iteration),
+                    )?;
+                    break sequence_report(&totals, iteration, &last);
+                }
+                if !wait_between_iterations(py, &callback_obj, repeat.interval)? {
+                    emit_status(py, &callback_obj, "Request sequence cancelled.")?;
+                    totals.cancelled = true;
+                    break sequence_report(&totals, iteration, &last);
+                }
+            }
+        }
+    };
+
+    // Cookies are kept for the next run even when this one failed
+    if let Err(e) = cookies.save() {
+        emit_error(py, &callback_obj, &format!("{:#}", e))?;
+    }
+    Ok(report)
+}
+
+/// The JSON report `run_web_requests_sequence` returns: the counts over
+/// every iteration, and how each request went in the last one.
+#[cfg(feature = "python")]
+fn sequence_report(totals: &SequenceOutcome, iterations: u64, last: &SequenceOutcome) -> String {
+    serde_json::json!({
+        "status": if totals.cancelled { "cancelled" } else { "finished" },
+        "passed": totals.failed == 0,
+        "iterations": iterations,
+        "succeeded": totals.succeeded,
+        "failed": totals.failed,
+        "requests": last.results,
+    })
+    .to_string()
+}
+
+/// `repeat` block of the sequence config. A `count` of 0 loops until cancelled.
+#[cfg(feature = "python")]
+#[derive(Debug, Clone, PartialEq)]
+struct RepeatConfig {
+    count: u64,
+    interval: Duration,
+    persist_variables: bool,
+}
+
+#[cfg(feature = "python")]
+impl RepeatConfig {
+    fn from_value(value: &Value) -> Self {
+        let interval_secs = value
+            .get("interval_secs")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(0.0);
+        Self {
+            count: value.get("count").and_then(|v| v.as_u64()).unwrap_or(1),
+            interval: wait_from_secs(interval_secs),
+            persist_variables: value
+                .get("persist_variables")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Result of a single pass over the request list.
+#[cfg(feature = "python")]
+#[derive(Default)]
+struct SequenceOutcome {
+    succeeded: u32,
+    failed: u32,
+    cancelled: bool,
+    results: Vec<RequestResult>,
+}
+
+#[cfg(feature = "python")]
+impl SequenceOutcome {
+    fn record(&mut self, result: RequestResult) {
+        if result.passed {
+            self.succeeded += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.results.push(result);
+    }
+
+    /// Adds the counts of `other`, for the totals over several iterations
+    fn add(&mut self, other: &SequenceOutcome) {
+        self.succeeded += other.succeeded;
+        self.failed += other.failed;
+        self.cancelled |= other.cancelled;
+    }
+}
+
+/// How one request went. It passed if it got a response and none of its
+/// assertions or extractions failed.
+#[cfg(feature = "python")]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct RequestResult {
+    request: usize,
+    method: String,
+    url: Option<String>,
+    status: Option<u16>,
+    passed: bool,
+    errors: Vec<String>,
+}
+
+#[cfg(feature = "python")]
+impl RequestResult {
+    fn new(request: usize, method: String) -> Self {
+        RequestResult {
+            request,
+            method,
+            url: None,
+            status: None,
+            passed: true,
+            errors: Vec::new(),
+        }
+    }
+
+    fn fail(&mut self, error: String) {
+        self.passed = false;
+        self.errors.push(error);
+    }
+}
+
+#[cfg(feature = "python")]
+#[allow(clippy::too_many_arguments)]
+fn run_sequence_once(
+    py: Python<'_>,
+    client: &Client,
+    base_url: &str,
+    requests: &[Value],
+    actions: &[Value],
+    variables: &mut HashMap<String, String>,
+    context: &mut RequestContext,
+    limiter: &RateLimiter,
+    callback_obj: &Py<PyAny>,
+) -> PyResult<SequenceOutcome> {
+    let mut outcome = SequenceOutcome::default();
+
+    for (i, req) in requests.iter().enumerate() {
+        let sink = PyProgressSink::new(py, callback_obj.clone_ref(py));
+        limiter.wait(&sink);
+        sink.finish(())?;
+
+        // Check for cancellation (if the python object has a flag)
+        if is_cancelled(py, callback_obj)? {
+            emit_status(py, callback_obj, "Request sequence cancelled.")?;
+            outcome.cancelled = true;
+            return Ok(outcome);
+        }
+
+        let method = method_of(req);
+        emit_status(
+            py,
+            callback_obj,
+            &format!("--- Request {}/{}: [{}] ---", i + 1, requests.len(), method),
+        )?;
+        let mut result = RequestResult::new(i + 1, method);
+
+        let (request, description) = match build_request(client, base_url, req, variables, context)
+        {
+            Ok(built) => built,
+            Err(e) => {
+                let message = format!("{:#}", e);
+                emit_error(py, callback_obj, &message)?;
+                result.fail(message);
+                outcome.record(result);
                 continue;
             }
         };
+        emit_status(py, callback_obj, &description)?;
 
-        match response_res {
-            Ok(response) => {
-                let status = response.status();
+        match request
+            .send()
+            .map_err(anyhow::Error::from)
+            .and_then(Fetched::read)
+        {
+            Ok(fetched) => {
+                let status = fetched.status;
+                result.url = Some(fetched.url.clone());
+                result.status = Some(status.as_u16());
                 emit_status(
                     py,
-                    &callback_obj,
+                    callback_obj,
                     &format!("Request complete. Status: {}", status),
                 )?;
+                // Failed responses are kept too; their status may be what
+                // the next request needs
+                context.insert("last_response".to_string(), fetched.to_context());
 
-                if !status.is_success() {
-                    emit_error(
-                        py,
-                        &callback_obj,
-                        &format!("Request failed: HTTP {}", status),
-                    )?;
+                // The request's own actions run after the shared ones
+                let actions: Vec<Value> = actions
+                    .iter()
+                    .chain(
+                        req.get("actions")
+                            .and_then(|v| v.as_array())
+                            .into_iter()
+                            .flatten(),
+                    )
+                    .cloned()
+                    .collect();
+                // A status assertion decides for itself whether an error
+                // status is what was wanted
+                let checks_status = actions.iter().any(|a| {
+                    a.get("type").and_then(|v| v.as_str()) == Some("Assert Status Equals")
+                });
+                if !status.is_success() && !checks_status {
+                    let message = format!("Request failed: HTTP {}", status);
+                    emit_error(py, callback_obj, &message)?;
+                    result.fail(message);
+                    outcome.record(result);
                     continue;
                 }
 
                 // Run actions
-                if let Err(e) = run_actions(py, &callback_obj, response, &actions) {
-                    emit_error(
-                        py,
-                        &callback_obj,
-                        &format!("Action execution failed: {}", e),
-                    )?;
+                match run_actions(py, callback_obj, &fetched, &actions, variables, context) {
+                    Ok(failures) => failures.into_iter().for_each(|f| result.fail(f)),
+                    Err(e) => {
+                        let message = format!("Action execution failed: {}", e);
+                        emit_error(py, callback_obj, &message)?;
+                        result.fail(message);
+                    }
                 }
+                outcome.record(result);
             }
             Err(e) => {
-                emit_error(py, &callback_obj, &format!("Request failed: {}", e))?;
+                let message = format!("Request failed: {}", e);
+                emit_error(py, callback_obj, &message)?;
+                result.fail(message);
+                outcome.record(result);
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Returns true once the Python side has cleared its `_is_running` flag.
+#[cfg(feature = "python")]
+fn is_cancelled(py: Python<'_>, obj: &Py<PyAny>) -> PyResult<bool> {
+    if let Ok(is_running) = obj.getattr(py, "_is_running") {
+        return Ok(!is_running.extract::<bool>(py)?);
+    }
+    Ok(false)
+}
+
+/// Sleeps for `interval` in short slices so cancellation is noticed promptly.
+/// Returns false if the sequence was cancelled while waiting.
+#[cfg(feature = "python")]
+fn wait_between_iterations(py: Python<'_>, obj: &Py<PyAny>, interval: Duration) -> PyResult<bool> {
+    let deadline = std::time::Instant::now() + interval;
+    loop {
+        if is_cancelled(py, obj)? {
+            return Ok(false);
+        }
+        let now = std::time::Instant::now();
+        if now >= deadline {
+            return Ok(true);
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(200)));
+    }
+}
+
+/// Replaces `{name}` placeholders with values from the variable store, and
+/// `{root.path}` ones with the value at `path` in `context[root]`.
+/// Placeholders that resolve to nothing are left as they are.
+#[cfg(feature = "python")]
+fn substitute_variables(
+    input: &str,
+    variables: &HashMap<String, String>,
+    context: &RequestContext,
+) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let resolved = after.find('}').and_then(|end| {
+            lookup_placeholder(&after[..end], variables, context).map(|value| (value, end))
+        });
+        match resolved {
+            Some((value, end)) => {
+                out.push_str(&json_text(&value));
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
             }
         }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The value a placeholder's `name` stands for, if any
+#[cfg(feature = "python")]
+fn lookup_placeholder(
+    name: &str,
+    variables: &HashMap<String, String>,
+    context: &RequestContext,
+) -> Option<Value> {
+    if name.contains('{') {
+        return None;
+    }
+    if let Some(value) = variables.get(name) {
+        return Some(Value::String(value.clone()));
+    }
+    let (root, path) = name.split_once('.').unwrap_or((name, ""));
+    extract_json_path(context.get(root)?, path).cloned()
+}
+
+/// Fills the placeholders in the strings of a JSON body. A string that is
+/// a single placeholder takes its value as it is, so numbers and objects
+/// keep their type.
+#[cfg(feature = "python")]
+fn substitute_json(
+    body: &Value,
+    variables: &HashMap<String, String>,
+    context: &RequestContext,
+) -> Value {
+    match body {
+        Value::String(s) => {
+            let whole = s.strip_prefix('{').and_then(|s| s.strip_suffix('}'));
+            if let Some(value) = whole.and_then(|name| lookup_placeholder(name, variables, context))
+            {
+                return value;
+            }
+            Value::String(substitute_variables(s, variables, context))
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|v| substitute_json(v, variables, context))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), substitute_json(v, variables, context)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// A JSON value as text: strings without their quotes
+#[cfg(feature = "python")]
+fn json_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The method of a request config: `method`, or `type` as older configs
+/// have it
+#[cfg(feature = "python")]
+fn method_of(req: &Value) -> String {
+    req.get("method")
+        .or_else(|| req.get("type"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("GET")
+        .to_uppercase()
+}
+
+/// Builds the request `req` describes, with a line saying what it sends.
+///
+/// `path` goes under the base URL, `headers` is an object of header values
+/// and `body` is sent as `body_type` "form" (an object, or `key:val` pairs
+/// in a string), "json" (any value, or JSON text) or "raw". Without a
+/// `body`, `param` is the path of GET, HEAD and DELETE requests and the
+/// `key:val` form data of the others. Placeholders are filled everywhere.
+#[cfg(feature = "python")]
+fn build_request(
+    client: &Client,
+    base_url: &str,
+    req: &Value,
+    variables: &HashMap<String, String>,
+    context: &RequestContext,
+) -> Result<(RequestBuilder, String)> {
+    let fill = |text: &str| substitute_variables(text, variables, context);
+    let text = |key: &str| req.get(key).and_then(|v| v.as_str());
+
+    let method_name = method_of(req);
+    if !METHODS.contains(&method_name.as_str()) {
+        bail!("Unsupported request type: {}", method_name);
+    }
+    let method = Method::from_bytes(method_name.as_bytes())?;
+    let takes_body = !matches!(method, Method::GET | Method::HEAD | Method::DELETE);
+    let param = fill(text("param").unwrap_or(""));
+
+    let path = match text("path") {
+        Some(path) => fill(path),
+        None if !takes_body => param.clone(),
+        None => String::new(),
+    };
+    let url = if path.is_empty() {
+        base_url.to_string()
+    } else {
+        format!(
+            "{}/{}",
+            base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    };
+
+    let mut request = client.request(method.clone(), &url);
+    if let Some(headers) = req.get("headers").and_then(|v| v.as_object()) {
+        for (name, value) in headers {
+            request = request.header(name.as_str(), fill(&json_text(value)));
+        }
+    }
+
+    let mut description = format!("Executing {}: {}", method, url);
+    match req.get("body").filter(|body| !body.is_null()) {
+        None if takes_body => {
+            let post_data = parse_post_data(&param);
+            description.push_str(&format!(" with data: {:?}", post_data));
+            request = request.form(&post_data);
+        }
+        None => {}
+        Some(body) => match text("body_type").unwrap_or("form") {
+            "form" => {
+                let form_data: HashMap<String, String> = match body {
+                    Value::Object(map) => map
+                        .iter()
+                        .map(|(k, v)| (k.clone(), fill(&json_text(v))))
+                        .collect(),
+                    Value::String(pairs) => parse_post_data(&fill(pairs)),
+                    other => bail!(
+                        "A form body takes an object or key:val pairs, not {}",
+                        other
+                    ),
+                };
+                description.push_str(&format!(" with data: {:?}", form_data));
+                request = request.form(&form_data);
+            }
+            "json" => {
+                let json = match body {
+                    Value::String(text) => serde_json::from_str(&fill(text))
+                        .context("The JSON body is not valid JSON")?,
+                    other => substitute_json(other, variables, context),
+                };
+                description.push_str(&format!(" with JSON: {}", json));
+                request = request.json(&json);
+            }
+            "raw" => {
+                let raw = fill(&json_text(body));
+                description.push_str(&format!(" with {} bytes", raw.len()));
+                request = request.body(raw);
+            }
+            other => bail!("Unknown body_type: {}", other),
+        },
+    }
+    Ok((request, description))
+}
+
+/// A response read in full, for the actions and for later placeholders
+#[cfg(feature = "python")]
+struct Fetched {
+    url: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
 
-        std::thread::sleep(Duration::from_millis(500));
+#[cfg(feature = "python")]
+impl Fetched {
+    fn read(response: Response) -> Result<Self> {
+        Ok(Fetched {
+            url: response.url().to_string(),
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response
+                .bytes()
+                .context("Failed to read response body")?
+                .to_vec(),
+        })
     }
 
-    emit_status(py, &callback_obj, "--- All requests finished. ---")?;
-    Ok("All requests finished.".to_string())
+    /// What `{last_response.…}` placeholders see; `json` is null when the
+    /// body isn't JSON
+    fn to_context(&self) -> Value {
+        let headers: serde_json::Map<String, Value> = self
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), Value::from(value.to_str().ok()?))))
+            .collect();
+        serde_json::json!({
+            "status": self.status.as_u16(),
+            "headers": headers,
+            "json": serde_json::from_slice::<Value>(&self.body).unwrap_or(Value::Null),
+        })
+    }
 }
 
 #[cfg(feature = "python")]
@@ -170,6 +695,20 @@ fn emit_error(py: Python<'_>, obj: &Py<PyAny>, msg: &str) -> PyResult<()> {
     Ok(())
 }
 
+#[cfg(feature = "python")]
+fn emit_iteration_completed(
+    py: Python<'_>,
+    obj: &Py<PyAny>,
+    iteration: u64,
+    summary_json: &str,
+) -> PyResult<()> {
+    // Older callback objects predate looping mode; skip them quietly.
+    if obj.getattr(py, "on_iteration_completed").is_ok() {
+        obj.call_method1(py, "on_iteration_completed", (iteration, summary_json))?;
+    }
+    Ok(())
+}
+
 #[cfg(feature = "python")]
 fn parse_post_data(param_str: &str) -> HashMap<String, String> {
     let mut data = HashMap::new();
@@ -184,23 +723,141 @@ fn parse_post_data(param_str: &str) -> HashMap<String, String> {
     data
 }
 
+/// Resolves a simple dot path (e.g. `data.items.0.name`) against a JSON value.
+/// Numeric segments index into arrays; an empty path returns the value itself.
+#[cfg(feature = "python")]
+fn extract_json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut current = value;
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(arr) => arr.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Splits a `key=value` action param, e.g. `token=data.access_token`.
+/// Only the key is trimmed.
+#[cfg(feature = "python")]
+fn parse_assignment(param: &str) -> Option<(&str, &str)> {
+    let (key, value) = param.split_once('=')?;
+    let key = key.trim();
+    (!key.is_empty()).then_some((key, value))
+}
+
+/// Whether `value` is `expected`, given as JSON (`42`, `true`, `"a"`) or as
+/// text to compare with the value's own.
+#[cfg(feature = "python")]
+fn json_equals(value: &Value, expected: &str) -> bool {
+    serde_json::from_str::<Value>(expected).is_ok_and(|e| &e == value)
+        || json_text(value) == expected
+}
+
+/// What `pattern` matches first in `text`: its first group if it has
+/// groups, else the whole match.
+#[cfg(feature = "python")]
+fn regex_capture(pattern: &str, text: &str) -> Result<Option<String>> {
+    let re = Regex::new(pattern).with_context(|| format!("Invalid regex '{}'", pattern))?;
+    let group = if re.captures_len() > 1 { 1 } else { 0 };
+    Ok(re.captures(text).map(|caps| {
+        caps.get(group)
+            .map(|m| m.as_str().to_string())
+            .unwrap_or_default()
+    }))
+}
+
+/// Returns a copy of `value` with every container nested deeper than
+/// `max_depth` replaced by a short placeholder string.
+#[cfg(feature = "python")]
+fn truncate_json_depth(value: &Value, max_depth: usize) -> Value {
+    match value {
+        Value::Object(map) if max_depth == 0 => Value::String(format!("{{...{} keys}}", map.len())),
+        Value::Array(arr) if max_depth == 0 => Value::String(format!("[...{} items]", arr.len())),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), truncate_json_depth(v, max_depth - 1)))
+                .collect(),
+        ),
+        Value::Array(arr) => Value::Array(
+            arr.iter()
+                .map(|v| truncate_json_depth(v, max_depth - 1))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Pretty-prints JSON with the depth and size caps applied.
+#[cfg(feature = "python")]
+fn format_json_pretty(value: &Value, max_depth: usize, max_chars: usize) -> String {
+    let pretty = serde_json::to_string_pretty(&truncate_json_depth(value, max_depth))
+        .unwrap_or_else(|_| value.to_string());
+    if pretty.chars().count() <= max_chars {
+        return pretty;
+    }
+    let mut out: String = pretty.chars().take(max_chars).collect();
+    out.push_str("\n ... (truncated)");
+    out
+}
+
+/// Collects the trimmed text content of every element matching `selector`.
+#[cfg(feature = "python")]
+fn extract_html_text(html: &str, selector: &str) -> Result<Vec<String>> {
+    let document = scraper::Html::parse_document(html);
+    let sel = scraper::Selector::parse(selector)
+        .map_err(|e| anyhow!("Invalid selector '{}': {:?}", selector, e))?;
+    Ok(document
+        .select(&sel)
+        .map(|el| el.text().collect::<Vec<_>>().join(" ").trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect())
+}
+
+/// Collects the `href` of every element matching `selector`, resolved against
+/// `base_url`. Duplicates are dropped while preserving document order.
+#[cfg(feature = "python")]
+fn extract_links(html: &str, selector: &str, base_url: &str) -> Result<Vec<String>> {
+    let document = scraper::Html::parse_document(html);
+    let sel = scraper::Selector::parse(selector)
+        .map_err(|e| anyhow!("Invalid selector '{}': {:?}", selector, e))?;
+    let base = url::Url::parse(base_url).ok();
+
+    let mut seen = std::collections::HashSet::new();
+    let mut links = Vec::new();
+    for el in document.select(&sel) {
+        let Some(href) = el.value().attr("href") else {
+            continue;
+        };
+        let resolved = match &base {
+            Some(b) => b
+                .join(href)
+                .map(|u| u.to_string())
+                .unwrap_or_else(|_| href.to_string()),
+            None => href.to_string(),
+        };
+        if seen.insert(resolved.clone()) {
+            links.push(resolved);
+        }
+    }
+    Ok(links)
+}
+
+/// Runs `actions` against a response. Returns the assertions and
+/// extractions that failed, which have been reported already.
 #[cfg(feature = "python")]
 fn run_actions(
     py: Python<'_>,
     callback_obj: &Py<PyAny>,
-    response: Response,
-    actions: &Vec<Value>,
-) -> Result<()> {
-    // We need to consume the response body for some actions.
-    // However, some actions only need headers or URL.
-    // To handle multiple actions on the same response, we might need to buffer the response content.
-
-    let url = response.url().to_string();
-    let status = response.status();
-    let headers = response.headers().clone();
-
-    // Buffer content if needed
-    let mut content: Option<Vec<u8>> = None;
+    fetched: &Fetched,
+    actions: &[Value],
+    variables: &mut HashMap<String, String>,
+    context: &RequestContext,
+) -> Result<Vec<String>> {
+    let url = &fetched.url;
+    let status = fetched.status;
+    let headers = &fetched.headers;
 
     for action in actions {
         let action_type = action.get("type").and_then(|v| v.as_str()).unwrap_or("");
@@ -232,88 +889,295 @@ fn run_actions(
                     &format!("  > Action: Response Headers:\n {}", headers_str),
                 );
             }
-            "Print Response Content (Text)" => {
-                if content.is_none() {
-                    // This is slightly inefficient as it consumes the whole response even if not needed by other actions,
-                    // but it's simpler. We use a trick: run_actions is called once per request.
-                    // Actually, we SHOULD buffer it here if we want multiple actions.
-                    // Since Response is consumed by .bytes(), we must do it once.
-                }
-                // Wait, if we use blocking::Response, bytes() consumes it.
-                // Let's just consume it now if we need it for any action.
-                // Re-implementation logic:
-            }
             _ => {}
         }
     }
 
-    // Better implementation:
-    // Check if any action needs body.
-    let needs_body = actions.iter().any(|a| {
-        let t = a.get("type").and_then(|v| v.as_str()).unwrap_or("");
-        t == "Print Response Content (Text)" || t == "Save Response Content (Binary)"
-    });
-
-    if needs_body {
-        let bytes = response
-            .bytes()
-            .context("Failed to read response body")?
-            .to_vec();
-        content = Some(bytes);
-    }
-
+    let data = &fetched.body;
+    let mut failures = Vec::new();
+    let mut fail = |message: String| {
+        let _ = emit_error(py, callback_obj, &format!("  > {}", message));
+        failures.push(message);
+    };
     for action in actions {
         let action_type = action.get("type").and_then(|v| v.as_str()).unwrap_or("");
         let param = action.get("param").and_then(|v| v.as_str()).unwrap_or("");
 
         match action_type {
             "Print Response Content (Text)" => {
-                if let Some(ref data) = content {
-                    let text = String::from_utf8_lossy(data);
-                    let _ = emit_status(
+                let text = String::from_utf8_lossy(data);
+                let _ = emit_status(
+                    py,
+                    callback_obj,
+                    &format!("  > Action: Response Content:\n {}", text.trim()),
+                );
+            }
+            "Save Response Content (Binary)" => {
+                if param.is_empty() {
+                    let _ = emit_error(
                         py,
                         callback_obj,
-                        &format!("  > Action: Response Content:\n {}", text.trim()),
+                        "  > Action: Save failed. No file path provided in parameter.",
                     );
+                    continue;
+                }
+
+                let mut filepath = Path::new(param).to_path_buf();
+                if filepath.is_dir() {
+                    let filename = url
+                        .split('/')
+                        .last()
+                        .and_then(|s| s.split('?').next())
+                        .unwrap_or("response.dat");
+                    filepath = filepath.join(filename);
+                }
+
+                if let Some(parent) = filepath.parent() {
+                    fs::create_dir_all(parent).context("Failed to create directories")?;
+                }
+                fs::write(&filepath, data).context("Failed to write file")?;
+                let _ = emit_status(
+                    py,
+                    callback_obj,
+                    &format!("  > Action: Save Response content saved to {:?}", filepath),
+                );
+            }
+            "Print JSON (pretty)" => {
+                let max_depth = param.trim().parse().unwrap_or(JSON_PRINT_MAX_DEPTH);
+                match serde_json::from_slice::<Value>(data) {
+                    Ok(json) => {
+                        let _ = emit_status(
+                            py,
+                            callback_obj,
+                            &format!(
+                                "  > Action: JSON Content:\n{}",
+                                format_json_pretty(&json, max_depth, JSON_PRINT_MAX_CHARS)
+                            ),
+                        );
+                    }
+                    Err(e) => {
+                        let _ = emit_error(
+                            py,
+                            callback_obj,
+                            &format!("  > Action: Response is not valid JSON: {}", e),
+                        );
+                    }
                 }
             }
-            "Save Response Content (Binary)" => {
-                if let Some(ref data) = content {
-                    if param.is_empty() {
+            "Extract JSON Field" => {
+                let json = match serde_json::from_slice::<Value>(data) {
+                    Ok(json) => json,
+                    Err(e) => {
                         let _ = emit_error(
                             py,
                             callback_obj,
-                            "  > Action: Save failed. No file path provided in parameter.",
+                            &format!("  > Action: Response is not valid JSON: {}", e),
                         );
                         continue;
                     }
-
-                    let mut filepath = Path::new(param).to_path_buf();
-                    if filepath.is_dir() {
-                        let filename = url
-                            .split('/')
-                            .last()
-                            .and_then(|s| s.split('?').next())
-                            .unwrap_or("response.dat");
-                        filepath = filepath.join(filename);
+                };
+                match extract_json_path(&json, param) {
+                    Some(v) => {
+                        let text = json_text(v);
+                        let _ = emit_status(
+                            py,
+                            callback_obj,
+                            &format!("  > Action: JSON Field '{}': {}", param, text),
+                        );
+                        // Optionally keep the value for `{name}` substitution in later requests
+                        if let Some(name) = action.get("save_as").and_then(|v| v.as_str()) {
+                            variables.insert(name.to_string(), text);
+                        }
+                    }
+                    None => {
+                        let _ = emit_error(
+                            py,
+                            callback_obj,
+                            &format!("  > Action: JSON Field '{}' not found.", param),
+                        );
+                    }
+                }
+            }
+            "Extract HTML Text" => {
+                if param.is_empty() {
+                    let _ = emit_error(
+                        py,
+                        callback_obj,
+                        "  > Action: Extract failed. No CSS selector provided in parameter.",
+                    );
+                    continue;
+                }
+                let html = String::from_utf8_lossy(data);
+                let texts = match extract_html_text(&html, param) {
+                    Ok(texts) => texts,
+                    Err(e) => {
+                        let _ = emit_error(
+                            py,
+                            callback_obj,
+                            &format!("  > Action: Extract failed. {:#}", e),
+                        );
+                        continue;
                     }
+                };
+                let _ = emit_status(
+                    py,
+                    callback_obj,
+                    &format!(
+                        "  > Action: HTML Text '{}' ({} matches):\n {}",
+                        param,
+                        texts.len(),
+                        texts.join("\n ")
+                    ),
+                );
+            }
+            "Extract Links" => {
+                if param.is_empty() {
+                    let _ = emit_error(
+                        py,
+                        callback_obj,
+                        "  > Action: Extract Links failed. No output file provided in parameter.",
+                    );
+                    continue;
+                }
+                let selector = action
+                    .get("selector")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("a[href]");
+                let html = String::from_utf8_lossy(data);
+                let links = match extract_links(&html, selector, url) {
+                    Ok(links) => links,
+                    Err(e) => {
+                        let _ = emit_error(
+                            py,
+                            callback_obj,
+                            &format!("  > Action: Extract Links failed. {:#}", e),
+                        );
+                        continue;
+                    }
+                };
 
-                    if let Some(parent) = filepath.parent() {
-                        fs::create_dir_all(parent).context("Failed to create directories")?;
+                let filepath = Path::new(param);
+                if let Some(parent) = filepath.parent() {
+                    fs::create_dir_all(parent).context("Failed to create directories")?;
+                }
+                let mut out = links.join("\n");
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                fs::write(filepath, out).context("Failed to write links file")?;
+                let _ = emit_status(
+                    py,
+                    callback_obj,
+                    &format!(
+                        "  > Action: Extracted {} links to {:?}",
+                        links.len(),
+                        filepath
+                    ),
+                );
+            }
+            "Assert Status Equals" => {
+                let expected = substitute_variables(param, variables, context);
+                match expected.trim().parse::<u16>() {
+                    Ok(code) if code == status.as_u16() => {
+                        let _ = emit_status(
+                            py,
+                            callback_obj,
+                            &format!("  > Assert: Status is {}", code),
+                        );
                     }
-                    fs::write(&filepath, data).context("Failed to write file")?;
+                    Ok(code) => fail(format!(
+                        "Assert failed: Status is {}, expected {}",
+                        status.as_u16(),
+                        code
+                    )),
+                    Err(_) => fail(format!(
+                        "Assert failed: '{}' is not a status code",
+                        expected
+                    )),
+                }
+            }
+            "Assert Body Contains" => {
+                let expected = substitute_variables(param, variables, context);
+                if expected.is_empty() {
+                    fail("Assert failed: No text provided in parameter.".to_string());
+                } else if String::from_utf8_lossy(data).contains(expected.as_str()) {
                     let _ = emit_status(
                         py,
                         callback_obj,
-                        &format!("  > Action: Save Response content saved to {:?}", filepath),
+                        &format!("  > Assert: Body contains '{}'", expected),
                     );
+                } else {
+                    fail(format!(
+                        "Assert failed: Body does not contain '{}'",
+                        expected
+                    ));
+                }
+            }
+            "Assert JSON Path Equals" => {
+                let Some((path, expected)) = parse_assignment(param) else {
+                    fail(format!("Assert failed: '{}' is not path=value", param));
+                    continue;
+                };
+                let expected = substitute_variables(expected.trim(), variables, context);
+                let json = serde_json::from_slice::<Value>(data).ok();
+                match json.as_ref().and_then(|json| extract_json_path(json, path)) {
+                    Some(v) if json_equals(v, &expected) => {
+                        let _ = emit_status(
+                            py,
+                            callback_obj,
+                            &format!("  > Assert: JSON Field '{}' is {}", path, expected),
+                        );
+                    }
+                    Some(v) => fail(format!(
+                        "Assert failed: JSON Field '{}' is {}, expected {}",
+                        path, v, expected
+                    )),
+                    None => fail(format!("Assert failed: JSON Field '{}' not found.", path)),
+                }
+            }
+            "Extract JSON Path to Variable" => {
+                let Some((name, path)) = parse_assignment(param) else {
+                    fail(format!("Extract failed: '{}' is not name=path", param));
+                    continue;
+                };
+                let path = path.trim();
+                let json = serde_json::from_slice::<Value>(data).ok();
+                match json.as_ref().and_then(|json| extract_json_path(json, path)) {
+                    Some(v) => {
+                        let text = json_text(v);
+                        let _ = emit_status(
+                            py,
+                            callback_obj,
+                            &format!("  > Action: Saved {{{}}} = {}", name, text),
+                        );
+                        variables.insert(name.to_string(), text);
+                    }
+                    None => fail(format!("Extract failed: JSON Field '{}' not found.", path)),
+                }
+            }
+            "Extract Regex to Variable" => {
+                let Some((name, pattern)) = parse_assignment(param) else {
+                    fail(format!("Extract failed: '{}' is not name=regex", param));
+                    continue;
+                };
+                match regex_capture(pattern, &String::from_utf8_lossy(data)) {
+                    Ok(Some(value)) => {
+                        let _ = emit_status(
+                            py,
+                            callback_obj,
+                            &format!("  > Action: Saved {{{}}} = {}", name, value),
+                        );
+                        variables.insert(name.to_string(), value);
+                    }
+                    Ok(None) => fail(format!("Extract failed: No match for '{}'", pattern)),
+                    Err(e) => fail(format!("Extract failed: {:#}", e)),
                 }
             }
             _ => {}
         }
     }
 
-    Ok(())
+    Ok(failures)
 }
 
 #[cfg(test)]
@@ -331,4 +1195,209 @@ mod tests {
         assert_eq!(data.get("a"), Some(&"b".to_string()));
         assert_eq!(data.len(), 3);
     }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_extract_json_path() {
+        let json = serde_json::json!({"data": {"items": [{"name": "a"}, {"name": "b"}]}});
+        assert_eq!(
+            extract_json_path(&json, "data.items.1.name"),
+            Some(&Value::String("b".to_string()))
+        );
+        assert_eq!(extract_json_path(&json, ""), Some(&json));
+        assert!(extract_json_path(&json, "data.missing").is_none());
+        assert!(extract_json_path(&json, "data.items.5").is_none());
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_assertion_params() {
+        assert_eq!(
+            parse_assignment(" token =data.access_token"),
+            Some(("token", "data.access_token"))
+        );
+        assert_eq!(parse_assignment("id=(\\d+)=x"), Some(("id", "(\\d+)=x")));
+        assert_eq!(parse_assignment("=42"), None);
+        assert_eq!(parse_assignment("data.id"), None);
+
+        let value = serde_json::json!({"id": 42, "name": "toolkit", "ok": true});
+        assert!(json_equals(&value["id"], "42"));
+        assert!(json_equals(&value["name"], "toolkit"));
+        assert!(json_equals(&value["name"], "\"toolkit\""));
+        assert!(json_equals(&value["ok"], "true"));
+        assert!(!json_equals(&value["id"], "43"));
+        assert!(!json_equals(&value["ok"], "false"));
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_regex_capture() {
+        let body = "csrf_token=ab12; path=/";
+        assert_eq!(
+            regex_capture(r"csrf_token=(\w+)", body).unwrap(),
+            Some("ab12".to_string())
+        );
+        assert_eq!(
+            regex_capture(r"path=\S+", body).unwrap(),
+            Some("path=/".to_string())
+        );
+        assert_eq!(regex_capture(r"session=(\w+)", body).unwrap(), None);
+        assert!(regex_capture(r"(unclosed", body).is_err());
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_sequence_report() {
+        let mut outcome = SequenceOutcome::default();
+        outcome.record(RequestResult::new(1, "GET".to_string()));
+        let mut failed = RequestResult::new(2, "POST".to_string());
+        failed.status = Some(500);
+        failed.fail("Request failed: HTTP 500".to_string());
+        outcome.record(failed);
+
+        let mut totals = SequenceOutcome::default();
+        totals.add(&outcome);
+        totals.add(&outcome);
+        let report: Value = serde_json::from_str(&sequence_report(&totals, 2, &outcome)).unwrap();
+        assert_eq!(report["status"], "finished");
+        assert_eq!(report["passed"], false);
+        assert_eq!(report["succeeded"], 2);
+        assert_eq!(report["failed"], 2);
+        assert_eq!(report["requests"].as_array().unwrap().len(), 2);
+        assert_eq!(report["requests"][1]["status"], 500);
+        assert_eq!(
+            report["requests"][1]["errors"][0],
+            "Request failed: HTTP 500"
+        );
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_repeat_config_defaults() {
+        let cfg = RepeatConfig::from_value(&serde_json::json!({}));
+        assert_eq!(cfg.count, 1);
+        assert_eq!(cfg.interval, Duration::ZERO);
+        assert!(!cfg.persist_variables);
+
+        let cfg = RepeatConfig::from_value(
+            &serde_json::json!({"count": 0, "interval_secs": 1.5, "persist_variables": true}),
+        );
+        assert_eq!(cfg.count, 0);
+        assert_eq!(cfg.interval, Duration::from_millis(1500));
+        assert!(cfg.persist_variables);
+
+        let cfg = RepeatConfig::from_value(&serde_json::json!({"interval_secs": 1e20}));
+        assert_eq!(cfg.interval, crate::web::config::MAX_WAIT);
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_substitute_variables() {
+        let mut vars = HashMap::new();
+        vars.insert("page".to_string(), "3".to_string());
+        let context = RequestContext::new();
+        assert_eq!(
+            substitute_variables("items?page={page}", &vars, &context),
+            "items?page=3"
+        );
+        assert_eq!(
+            substitute_variables("{missing}", &vars, &context),
+            "{missing}"
+        );
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_substitute_last_response() {
+        let vars = HashMap::new();
+        let mut context = RequestContext::new();
+        context.insert(
+            "last_response".to_string(),
+            serde_json::json!({"status": 200, "json": {"token": "abc", "user": {"id": 7}}}),
+        );
+        assert_eq!(
+            substitute_variables("Bearer {last_response.json.token}", &vars, &context),
+            "Bearer abc"
+        );
+        // Braces that aren't placeholders stay, even around one that is
+        assert_eq!(
+            substitute_variables(r#"{"id": {last_response.json.user.id}}"#, &vars, &context),
+            r#"{"id": 7}"#
+        );
+
+        let body = serde_json::json!({
+            "user": "{last_response.json.user.id}",
+            "note": "status {last_response.status}"
+        });
+        assert_eq!(
+            substitute_json(&body, &vars, &context),
+            serde_json::json!({"user": 7, "note": "status 200"})
+        );
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_build_request() {
+        let client = Client::new();
+        let vars = HashMap::new();
+        let mut context = RequestContext::new();
+        context.insert(
+            "last_response".to_string(),
+            serde_json::json!({"json": {"token": "abc"}}),
+        );
+        let build = |req: Value| {
+            let (request, description) =
+                build_request(&client, "http://api.test/v1/", &req, &vars, &context).unwrap();
+            (request.build().unwrap(), description)
+        };
+
+        let (request, _) = build(serde_json::json!({
+            "method": "patch",
+            "path": "/items/3",
+            "headers": {"Authorization": "Bearer {last_response.json.token}"},
+            "body_type": "json",
+            "body": {"name": "new"}
+        }));
+        assert_eq!(request.method(), Method::PATCH);
+        assert_eq!(request.url().as_str(), "http://api.test/v1/items/3");
+        assert_eq!(request.headers()["authorization"], "Bearer abc");
+        assert_eq!(request.headers()["content-type"], "application/json");
+        let body = request.body().unwrap().as_bytes().unwrap();
+        assert_eq!(body, br#"{"name":"new"}"#);
+
+        // The older configs still work
+        let (request, description) = build(serde_json::json!({"type": "GET", "param": "a/b"}));
+        assert_eq!(request.url().as_str(), "http://api.test/v1/a/b");
+        assert_eq!(description, "Executing GET: http://api.test/v1/a/b");
+        let (request, _) = build(serde_json::json!({"type": "POST", "param": "k:v"}));
+        assert_eq!(request.url().as_str(), "http://api.test/v1/");
+        assert_eq!(request.body().unwrap().as_bytes().unwrap(), b"k=v");
+
+        let (request, _) = build(serde_json::json!({
+            "method": "PUT",
+            "body_type": "raw",
+            "body": "token={last_response.json.token}"
+        }));
+        assert_eq!(request.body().unwrap().as_bytes().unwrap(), b"token=abc");
+
+        for bad in [
+            serde_json::json!({"method": "TRACE"}),
+            serde_json::json!({"method": "POST", "body_type": "xml", "body": "<a/>"}),
+            serde_json::json!({"method": "POST", "body_type": "json", "body": "{oops"}),
+        ] {
+            assert!(build_request(&client, "http://api.test", &bad, &vars, &context).is_err());
+        }
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_format_json_pretty_caps() {
+        let json = serde_json::json!({"a": {"b": {"c": 1}}, "list": [1, 2, 3]});
+        let shallow = format_json_pretty(&json, 1, 10_000);
+        assert!(shallow.contains("{...1 keys}"));
+        assert!(shallow.contains("[...3 items]"));
+
+        let capped = format_json_pretty(&json, 8, 10);
+        assert!(capped.ends_with("(truncated)"));
+    }
 }
@@ -0,0 +1,269 @@
+use anyhow::Result;
+use base64::Engine;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Dropbox hashes content in blocks of this size
+const DROPBOX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// The content hash each provider reports for its files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashKind {
+    /// Dropbox `content_hash`: SHA-256 over the SHA-256 of every 4 MiB block
+    Dropbox,
+    /// Google Drive `md5Checksum`
+    Md5,
+    /// OneDrive `quickXorHash`
+    QuickXor,
+}
+
+impl HashKind {
+    /// The hash of `data`, written the way the provider's API writes it
+    pub fn compute(&self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// A hasher to feed a file through a piece at a time
+    pub fn hasher(&self) -> ContentHasher {
+        match self {
+            HashKind::Dropbox => ContentHasher::Dropbox(DropboxHasher::default()),
+            HashKind::Md5 => ContentHasher::Md5(Md5::new()),
+            HashKind::QuickXor => ContentHasher::QuickXor(QuickXorHash::default()),
+        }
+    }
+
+    /// Whether two hashes are the same. Hex digests may come in either case;
+    /// QuickXor's base64 may not.
+    pub fn matches(&self, a: &str, b: &str) -> bool {
+        match self {
+            HashKind::QuickXor => a == b,
+            _ => a.eq_ignore_ascii_case(b),
+        }
+    }
+}
+
+/// A transfer whose contents didn't hash to what the provider reported.
+/// Counted as transient by the retry policy, since a second try usually
+/// gets the whole file.
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub path: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checksum mismatch for {}: expected {}, got {}",
+            self.path, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Fails with `ChecksumMismatch` unless `data` hashes to `expected`
+pub fn verify(kind: HashKind, data: &[u8], expected: &str, path: &str) -> Result<()> {
+    check(kind, kind.compute(data), expected, path)
+}
+
+/// Fails with `ChecksumMismatch` unless the already computed `actual`
+/// is `expected`
+pub fn check(kind: HashKind, actual: String, expected: &str, path: &str) -> Result<()> {
+    if kind.matches(&actual, expected) {
+        Ok(())
+    } else {
+        Err(ChecksumMismatch {
+            path: path.to_string(),
+            expected: expected.to_string(),
+            actual,
+        }
+        .into())
+    }
+}
+
+pub fn dropbox_content_hash(data: &[u8]) -> String {
+    let mut hasher = DropboxHasher::default();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// A `HashKind` hash being built up from pieces of a file
+pub enum ContentHasher {
+    Dropbox(DropboxHasher),
+    Md5(Md5),
+    QuickXor(QuickXorHash),
+}
+
+impl ContentHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Dropbox(hasher) => hasher.update(data),
+            ContentHasher::Md5(hasher) => hasher.update(data),
+            ContentHasher::QuickXor(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish(self) -> String {
+        match self {
+            ContentHasher::Dropbox(hasher) => hasher.finish(),
+            ContentHasher::Md5(hasher) => hex::encode(hasher.finalize()),
+            ContentHasher::QuickXor(hasher) => hasher.finish(),
+        }
+    }
+}
+
+/// Dropbox's `content_hash`, fed in pieces that needn't line up with
+/// its 4 MiB blocks
+#[derive(Debug, Default)]
+pub struct DropboxHasher {
+    overall: Sha256,
+    block: Sha256,
+    in_block: usize,
+}
+
+impl DropboxHasher {
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = data.len().min(DROPBOX_BLOCK_SIZE - self.in_block);
+            self.block.update(&data[..take]);
+            self.in_block += take;
+            if self.in_block == DROPBOX_BLOCK_SIZE {
+                self.overall.update(self.block.finalize_reset());
+                self.in_block = 0;
+            }
+            data = &data[take..];
+        }
+    }
+
+    pub fn finish(mut self) -> String {
+        if self.in_block > 0 {
+            self.overall.update(self.block.finalize());
+        }
+        hex::encode(self.overall.finalize())
+    }
+}
+
+/// Microsoft's QuickXorHash: every byte XORed into a 160-bit value, each
+/// 11 bits further along than the last and wrapping around, with the
+/// length XORed into the last 8 bytes. Written out as base64.
+#[derive(Debug, Default)]
+pub struct QuickXorHash {
+    bits: [u8; 20],
+    len: u64,
+}
+
+impl QuickXorHash {
+    const WIDTH: u64 = 160;
+    const SHIFT: u64 = 11;
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            let offset = (self.len % Self::WIDTH) * Self::SHIFT % Self::WIDTH;
+            let index = (offset / 8) as usize;
+            let spread = (byte as u16) << (offset % 8);
+            self.bits[index] ^= spread as u8;
+            self.bits[(index + 1) % self.bits.len()] ^= (spread >> 8) as u8;
+            self.len += 1;
+        }
+    }
+
+    pub fn finish(&self) -> String {
+        let mut out = self.bits;
+        for (i, byte) in self.len.to_le_bytes().iter().enumerate() {
+            out[12 + i] ^= byte;
+        }
+        base64::engine::general_purpose::STANDARD.encode(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes 0..=250 repeated, so blocks and wraparounds all differ
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_dropbox_content_hash() {
+        assert_eq!(
+            dropbox_content_hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            dropbox_content_hash(b"hello"),
+            "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50"
+        );
+        // Exactly one block, then two full blocks and a partial one
+        assert_eq!(
+            dropbox_content_hash(&pattern(DROPBOX_BLOCK_SIZE)),
+            "b9654428408015906b44a00935b70af33830aa344b780b0eabd535a133150d04"
+        );
+        assert_eq!(
+            dropbox_content_hash(&pattern(2 * DROPBOX_BLOCK_SIZE + 3)),
+            "28a8a0b1a6c89ff110d9f2cfae055e22c59413323b0d3a0667be6fe74d315620"
+        );
+    }
+
+    #[test]
+    fn test_hasher_takes_pieces() {
+        // Pieces that straddle Dropbox's blocks come out the same as whole
+        let data = pattern(2 * DROPBOX_BLOCK_SIZE + 3);
+        for kind in [HashKind::Dropbox, HashKind::Md5, HashKind::QuickXor] {
+            let mut hasher = kind.hasher();
+            for piece in data.chunks(3 * 1024 * 1024 + 7) {
+                hasher.update(piece);
+            }
+            assert_eq!(hasher.finish(), kind.compute(&data), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_quick_xor_hash() {
+        let hash = |data: &[u8]| HashKind::QuickXor.compute(data);
+        assert_eq!(hash(b""), "AAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+        assert_eq!(hash(b"a"), "YQAAAAAAAAAAAAAAAQAAAAAAAAA=");
+        assert_eq!(hash(b"Hello, World!"), "SCgDG9jwBhaA4ApvnQMbyBACAAA=");
+        assert_eq!(hash(&pattern(1000)), "KbphcpColXb1/3Wm950vUzeX1es=");
+
+        // Fed in pieces it comes out the same
+        let data = pattern(1000);
+        let mut hasher = QuickXorHash::default();
+        for piece in data.chunks(7) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish(), hash(&data));
+    }
+
+    #[test]
+    fn test_md5_and_verify() {
+        assert_eq!(
+            HashKind::Md5.compute(b""),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+        assert_eq!(
+            HashKind::Md5.compute(b"The quick brown fox jumps over the lazy dog"),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+
+        let data = b"The quick brown fox jumps over the lazy dog";
+        assert!(verify(HashKind::Md5, data, "9E107D9D372BB6826BD81D3542A419D6", "a").is_ok());
+        let err = verify(
+            HashKind::Md5,
+            &data[..10],
+            "9e107d9d372bb6826bd81d3542a419d6",
+            "a",
+        )
+        .unwrap_err();
+        let mismatch = err.downcast_ref::<ChecksumMismatch>().unwrap();
+        assert_eq!(mismatch.expected, "9e107d9d372bb6826bd81d3542a419d6");
+        assert!(!HashKind::QuickXor.matches("YQAA", "yqaa"));
+    }
+}
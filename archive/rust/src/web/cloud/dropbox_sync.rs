@@ -1,8 +1,14 @@
-use super::sync::{CloudSync, SyncItem};
+use super::checksum::{check, verify, HashKind};
+use super::oauth::TokenExpired;
+use super::sync::{local_mtime, mtime_to_rfc3339, CloudSync, SyncItem};
+use super::upload::{needs_session, UploadSession, UploadState, Uploader};
+use crate::web::clients::retry::check_status;
 use anyhow::{Context, Result};
-use reqwest::blocking::Client;
-use serde_json::Value;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::path::Path;
 
 pub struct DropboxSyncImpl {
     pub access_token: String,
@@ -28,6 +34,153 @@ impl DropboxSyncImpl {
             remote_path: remote,
         }
     }
+
+    /// Uploads a file too big for one request through an upload session,
+    /// committed as `commit` describes
+    fn upload_in_session(
+        &self,
+        client: &Client,
+        uploader: &Uploader,
+        local_path: &str,
+        rel_path: &str,
+        commit: Value,
+    ) -> Result<()> {
+        let res = send(
+            &self.access_token,
+            client
+                .post("https://content.dropboxapi.com/2/files/upload_session/start")
+                .header("Dropbox-API-Arg", r#"{"close": false}"#)
+                .header("Content-Type", "application/octet-stream")
+                .body(Vec::new()),
+        )?;
+        if !res.status().is_success() {
+            return Err(anyhow::anyhow!("Dropbox Upload Error: {}", res.text()?));
+        }
+        let started: Value = res.json()?;
+        let mut session = DropboxUpload {
+            access_token: &self.access_token,
+            session_id: started
+                .get("session_id")
+                .and_then(|v| v.as_str())
+                .context("Missing session_id")?
+                .to_string(),
+            commit,
+        };
+
+        let (uploaded, hash) = uploader.send(
+            client,
+            &mut session,
+            local_path,
+            rel_path,
+            HashKind::Dropbox,
+        )?;
+        match uploaded.get("content_hash").and_then(|v| v.as_str()) {
+            Some(echoed) => check(HashKind::Dropbox, hash, echoed, rel_path),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An upload session, which takes the file in appends and commits it
+/// with the last one
+struct DropboxUpload<'a> {
+    access_token: &'a str,
+    session_id: String,
+    commit: Value,
+}
+
+impl DropboxUpload<'_> {
+    fn post(&self, client: &Client, endpoint: &str, arg: Value, data: &[u8]) -> Result<Response> {
+        send(
+            self.access_token,
+            client
+                .post(format!(
+                    "https://content.dropboxapi.com/2/files/upload_session/{}",
+                    endpoint
+                ))
+                .header("Dropbox-API-Arg", serde_json::to_string(&arg)?)
+                .header("Content-Type", "application/octet-stream")
+                .body(data.to_vec()),
+        )
+    }
+}
+
+impl UploadSession for DropboxUpload<'_> {
+    fn send_chunk(
+        &mut self,
+        client: &Client,
+        offset: u64,
+        chunk: &[u8],
+        total: u64,
+    ) -> Result<UploadState> {
+        let cursor = json!({ "session_id": self.session_id, "offset": offset });
+        let end = offset + chunk.len() as u64;
+        let res = if end == total {
+            let arg = json!({ "cursor": cursor, "commit": self.commit });
+            self.post(client, "finish", arg, chunk)?
+        } else {
+            let arg = json!({ "cursor": cursor, "close": false });
+            self.post(client, "append_v2", arg, chunk)?
+        };
+
+        if res.status() == StatusCode::CONFLICT {
+            let err_text = res.text()?;
+            return match correct_offset(&err_text) {
+                Some(received) => Ok(UploadState::Received(received)),
+                None => Err(anyhow::anyhow!("Dropbox Upload Error: {}", err_text)),
+            };
+        }
+        let res = check_status(res)?;
+        if end == total {
+            Ok(UploadState::Complete(res.json()?))
+        } else {
+            Ok(UploadState::Received(end))
+        }
+    }
+
+    /// Dropbox can't be asked outright, but an empty append at the wrong
+    /// offset is told the right one
+    fn status(&mut self, client: &Client, total: u64) -> Result<UploadState> {
+        self.send_chunk(client, 0, &[], total)
+    }
+}
+
+/// Sends `request` with `access_token`. Dropbox tokens aren't refreshed
+/// here, so one it rejects as expired or revoked fails as `TokenExpired`.
+fn send(access_token: &str, request: RequestBuilder) -> Result<Response> {
+    let res = request
+        .header("Authorization", format!("Bearer {}", access_token))
+        .send()?;
+    if res.status() != StatusCode::UNAUTHORIZED {
+        return Ok(res);
+    }
+    let err_text = res.text()?;
+    if token_rejected(&err_text) {
+        return Err(TokenExpired {
+            provider: "Dropbox".to_string(),
+            detail: err_text,
+        }
+        .into());
+    }
+    Err(anyhow::anyhow!("Dropbox Auth Error: {}", err_text))
+}
+
+/// Whether a 401 is down to the token itself, rather than say a missing
+/// scope that signing in again with the same app wouldn't fix
+fn token_rejected(err_text: &str) -> bool {
+    let err: Value = serde_json::from_str(err_text).unwrap_or_default();
+    matches!(
+        err.pointer("/error/.tag").and_then(|v| v.as_str()),
+        Some("expired_access_token" | "invalid_access_token")
+    )
+}
+
+/// The offset a 409 from an upload session says it's really at
+fn correct_offset(err_text: &str) -> Option<u64> {
+    let err: Value = serde_json::from_str(err_text).ok()?;
+    err.pointer("/error/correct_offset")
+        .or_else(|| err.pointer("/error/lookup_failed/correct_offset"))
+        .and_then(|v| v.as_u64())
 }
 
 impl CloudSync for DropboxSyncImpl {
@@ -36,10 +189,10 @@ impl CloudSync for DropboxSyncImpl {
     }
 
     fn authenticate(&mut self, client: &Client) -> Result<()> {
-        let res = client
-            .post("https://api.dropboxapi.com/2/users/get_current_account")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .send()?;
+        let res = send(
+            &self.access_token,
+            client.post("https://api.dropboxapi.com/2/users/get_current_account"),
+        )?;
 
         if res.status().is_success() {
             Ok(())
@@ -61,12 +214,13 @@ impl CloudSync for DropboxSyncImpl {
         });
 
         loop {
-            let res = client
-                .post(&url)
-                .header("Authorization", format!("Bearer {}", self.access_token))
-                .header("Content-Type", "application/json")
-                .json(&body)
-                .send()?;
+            let res = send(
+                &self.access_token,
+                client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .json(&body),
+            )?;
 
             if !res.status().is_success() {
                 let err_text = res.text()?;
@@ -119,6 +273,8 @@ impl CloudSync for DropboxSyncImpl {
                     0
                 };
 
+                let size = entry.get("size").and_then(|v| v.as_u64()).unwrap_or(0);
+
                 items.insert(
                     rel_path.clone(),
                     SyncItem {
@@ -126,6 +282,11 @@ impl CloudSync for DropboxSyncImpl {
                         abs_path_or_id: full_path.to_string(), // For dropbox, path works as ID
                         mtime,
                         is_folder,
+                        size,
+                        checksum: entry
+                            .get("content_hash")
+                            .and_then(|v| v.as_str())
+                            .map(|s| s.to_string()),
                     },
                 );
             }
@@ -147,39 +308,56 @@ impl CloudSync for DropboxSyncImpl {
         Ok(items)
     }
 
-    fn upload_file(&self, client: &Client, local_path: &str, rel_path: &str) -> Result<()> {
+    fn upload_file(
+        &self,
+        client: &Client,
+        uploader: &Uploader,
+        local_path: &str,
+        rel_path: &str,
+    ) -> Result<()> {
         let target_path = format!("{}/{}", self.remote_path, rel_path).replace("//", "/");
         let arg = serde_json::json!({
             "path": target_path,
             "mode": "overwrite",
             "autorename": true,
             "mute": false,
-            "strict_conflict": false
+            "strict_conflict": false,
+            // Keeps the local mtime, which listings report back as client_modified
+            "client_modified": mtime_to_rfc3339(local_mtime(Path::new(local_path)))
         });
 
+        if needs_session(local_path)? {
+            return self.upload_in_session(client, uploader, local_path, rel_path, arg);
+        }
+
         let file_bytes = std::fs::read(local_path)?;
-        let res = client
-            .post("https://content.dropboxapi.com/2/files/upload")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header("Dropbox-API-Arg", serde_json::to_string(&arg)?)
-            .header("Content-Type", "application/octet-stream")
-            .body(file_bytes)
-            .send()?;
+        let res = send(
+            &self.access_token,
+            client
+                .post("https://content.dropboxapi.com/2/files/upload")
+                .header("Dropbox-API-Arg", serde_json::to_string(&arg)?)
+                .header("Content-Type", "application/octet-stream")
+                .body(file_bytes.clone()),
+        )?;
 
-        if res.status().is_success() {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Dropbox Upload Error: {}", res.text()?))
+        if !res.status().is_success() {
+            return Err(anyhow::anyhow!("Dropbox Upload Error: {}", res.text()?));
+        }
+        let uploaded: Value = res.json()?;
+        match uploaded.get("content_hash").and_then(|v| v.as_str()) {
+            Some(echoed) => verify(HashKind::Dropbox, &file_bytes, echoed, rel_path),
+            None => Ok(()),
         }
     }
 
     fn download_file(&self, client: &Client, remote_id: &str, local_dest: &str) -> Result<()> {
         let arg = serde_json::json!({ "path": remote_id });
-        let res = client
-            .post("https://content.dropboxapi.com/2/files/download")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header("Dropbox-API-Arg", serde_json::to_string(&arg)?)
-            .send()?;
+        let res = send(
+            &self.access_token,
+            client
+                .post("https://content.dropboxapi.com/2/files/download")
+                .header("Dropbox-API-Arg", serde_json::to_string(&arg)?),
+        )?;
 
         if res.status().is_success() {
             let bytes = res.bytes()?;
@@ -190,17 +368,22 @@ impl CloudSync for DropboxSyncImpl {
         }
     }
 
+    fn hash_kind(&self) -> Option<HashKind> {
+        Some(HashKind::Dropbox)
+    }
+
     fn create_remote_folder(&self, client: &Client, rel_path: &str) -> Result<()> {
         let target_path = format!("{}/{}", self.remote_path, rel_path).replace("//", "/");
-        let res = client
-            .post("https://api.dropboxapi.com/2/files/create_folder_v2")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header("Content-Type", "application/json")
-            .json(&serde_json::json!({
-                "path": target_path,
-                "autorename": false
-            }))
-            .send()?;
+        let res = send(
+            &self.access_token,
+            client
+                .post("https://api.dropboxapi.com/2/files/create_folder_v2")
+                .header("Content-Type", "application/json")
+                .json(&serde_json::json!({
+                    "path": target_path,
+                    "autorename": false
+                })),
+        )?;
 
         if res.status().is_success() || res.status().as_u16() == 409 {
             // 409 Conflict often means group already exists
@@ -214,12 +397,13 @@ impl CloudSync for DropboxSyncImpl {
     }
 
     fn delete_remote(&self, client: &Client, remote_id: &str, _rel_path: &str) -> Result<()> {
-        let res = client
-            .post("https://api.dropboxapi.com/2/files/delete_v2")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header("Content-Type", "application/json")
-            .json(&serde_json::json!({ "path": remote_id }))
-            .send()?;
+        let res = send(
+            &self.access_token,
+            client
+                .post("https://api.dropboxapi.com/2/files/delete_v2")
+                .header("Content-Type", "application/json")
+                .json(&serde_json::json!({ "path": remote_id })),
+        )?;
 
         if res.status().is_success() {
             Ok(())
@@ -245,4 +429,25 @@ mod tests {
         assert_eq!(sync.remote_path, "/photos");
         assert_eq!(sync.name(), "Dropbox");
     }
+
+    #[test]
+    fn test_correct_offset() {
+        let append = r#"{"error_summary": "incorrect_offset/..", "error": {".tag": "incorrect_offset", "correct_offset": 20971520}}"#;
+        assert_eq!(correct_offset(append), Some(20971520));
+        let finish = r#"{"error": {".tag": "lookup_failed", "lookup_failed": {".tag": "incorrect_offset", "correct_offset": 7}}}"#;
+        assert_eq!(correct_offset(finish), Some(7));
+        assert_eq!(correct_offset(r#"{"error": {".tag": "not_found"}}"#), None);
+        assert_eq!(correct_offset("Bad Gateway"), None);
+    }
+
+    #[test]
+    fn test_token_rejected() {
+        let expired = r#"{"error_summary": "expired_access_token/..", "error": {".tag": "expired_access_token"}}"#;
+        assert!(token_rejected(expired));
+        let revoked = r#"{"error_summary": "invalid_access_token/...", "error": {".tag": "invalid_access_token"}}"#;
+        assert!(token_rejected(revoked));
+        let scope = r#"{"error_summary": "missing_scope/..", "error": {".tag": "missing_scope", "required_scope": "files.content.write"}}"#;
+        assert!(!token_rejected(scope));
+        assert!(!token_rejected("Unauthorized"));
+    }
 }
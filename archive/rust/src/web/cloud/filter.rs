@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Which paths a sync leaves alone, on both sides: those matching an
+/// exclude pattern, files without one of the included extensions and,
+/// when asked, hidden files and folders. A folder left alone takes
+/// everything in it along.
+///
+/// Patterns follow .gitignore: one without a slash matches at any depth
+/// (`*.part`, `.git`), one with a slash is anchored to the sync folder
+/// (`cache/**`, `/build`), and a trailing slash matches folders only.
+/// `!` negation isn't supported.
+#[derive(Debug)]
+pub struct SyncFilter {
+    any: GlobSet,
+    folders: GlobSet,
+    extensions: Vec<String>,
+    skip_hidden: bool,
+}
+
+impl SyncFilter {
+    pub fn new(patterns: &[String], extensions: &[String], skip_hidden: bool) -> Result<Self> {
+        let mut any = GlobSetBuilder::new();
+        let mut folders = GlobSetBuilder::new();
+        for pattern in patterns.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            if pattern.starts_with('!') {
+                return Err(anyhow!(
+                    "Negated exclude patterns aren't supported: {}",
+                    pattern
+                ));
+            }
+            let (glob, folder_only) = match pattern.strip_suffix('/') {
+                Some(folder) => (folder, true),
+                None => (pattern, false),
+            };
+            let glob = match glob.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if glob.contains('/') => glob.to_string(),
+                None => format!("**/{}", glob),
+            };
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid exclude pattern '{}'", pattern))?;
+            if folder_only {
+                folders.add(glob);
+            } else {
+                any.add(glob);
+            }
+        }
+
+        Ok(SyncFilter {
+            any: any.build()?,
+            folders: folders.build()?,
+            extensions: extensions
+                .iter()
+                .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+                .filter(|e| !e.is_empty())
+                .collect(),
+            skip_hidden,
+        })
+    }
+
+    /// Whether `rel_path` itself is left alone, regardless of the folders
+    /// it's in
+    pub fn matches(&self, rel_path: &str, is_folder: bool) -> bool {
+        let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        if self.skip_hidden && name.starts_with('.') {
+            return true;
+        }
+        if self.any.is_match(rel_path) || (is_folder && self.folders.is_match(rel_path)) {
+            return true;
+        }
+        if is_folder || self.extensions.is_empty() {
+            return false;
+        }
+        let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+        !extension.is_some_and(|ext| self.extensions.contains(&ext))
+    }
+
+    /// Whether one of the folders `rel_path` is in is left alone
+    pub fn in_excluded_folder(&self, rel_path: &str) -> bool {
+        rel_path
+            .match_indices('/')
+            .any(|(i, _)| self.matches(&rel_path[..i], true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(patterns: &[&str], extensions: &[&str], skip_hidden: bool) -> SyncFilter {
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        SyncFilter::new(&strings(patterns), &strings(extensions), skip_hidden).unwrap()
+    }
+
+    fn excluded(filter: &SyncFilter, rel_path: &str, is_folder: bool) -> bool {
+        filter.in_excluded_folder(rel_path) || filter.matches(rel_path, is_folder)
+    }
+
+    #[test]
+    fn test_exclude_patterns() {
+        let f = filter(
+            &["*.part", "cache/**", ".git", "/build", "/*.tmp", "thumbs/"],
+            &[],
+            false,
+        );
+
+        // No slash: any depth
+        assert!(excluded(&f, "a.jpg.part", false));
+        assert!(excluded(&f, "trips/2024/a.jpg.part", false));
+        assert!(excluded(&f, ".git", true));
+        assert!(excluded(&f, "sub/.git/objects/ab/cdef", false));
+        // With a slash: from the top only
+        assert!(excluded(&f, "cache/x.jpg", false));
+        assert!(excluded(&f, "cache/nested/deep/x.jpg", false));
+        assert!(!excluded(&f, "trips/cache/x.jpg", false));
+        assert!(excluded(&f, "build/out.png", false));
+        assert!(!excluded(&f, "src/build/out.png", false));
+        // Trailing slash: folders only
+        assert!(excluded(&f, "trips/thumbs/a.jpg", false));
+        assert!(!excluded(&f, "trips/thumbs", false));
+        // `*` stays within a folder
+        assert!(excluded(&f, "x.tmp", false));
+        assert!(!excluded(&f, "trips/x.tmp", false));
+        assert!(!excluded(&f, "trips/a.jpg", false));
+    }
+
+    #[test]
+    fn test_extensions_and_hidden() {
+        let f = filter(&[], &["jpg", ".PNG"], false);
+        assert!(!excluded(&f, "trips/a.JPG", false));
+        assert!(!excluded(&f, "b.png", false));
+        assert!(excluded(&f, "notes.txt", false));
+        assert!(excluded(&f, "README", false));
+        // Folders are never filtered by extension
+        assert!(!excluded(&f, "trips", true));
+        assert!(!excluded(&f, ".thumbnails/a.jpg", false));
+
+        let f = filter(&[], &[], true);
+        assert!(excluded(&f, ".thumbnails/a.jpg", false));
+        assert!(excluded(&f, "trips/.DS_Store", false));
+        assert!(!excluded(&f, "trips/a.jpg", false));
+    }
+
+    #[test]
+    fn test_bad_patterns() {
+        let err = SyncFilter::new(&["a[".to_string()], &[], false).unwrap_err();
+        assert!(err.to_string().contains("Invalid exclude pattern 'a['"));
+        assert!(SyncFilter::new(&["!keep.jpg".to_string()], &[], false).is_err());
+    }
+}
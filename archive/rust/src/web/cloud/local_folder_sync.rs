@@ -0,0 +1,166 @@
+use super::state::STATE_DIR;
+use super::sync::{local_mtime, CloudSync, SyncItem};
+use super::upload::Uploader;
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Syncs with another folder, on a second disk or a mounted share
+pub struct LocalFolderSyncImpl {
+    pub remote_path: String,
+    /// The folder being synced, to refuse a remote folder inside it
+    pub local_path: String,
+}
+
+impl LocalFolderSyncImpl {
+    pub fn new(config: &Value) -> Self {
+        let text = |key: &str| {
+            config
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string()
+        };
+        LocalFolderSyncImpl {
+            remote_path: text("remote_path"),
+            local_path: text("local_path"),
+        }
+    }
+
+    fn target(&self, rel_path: &str) -> PathBuf {
+        Path::new(&self.remote_path).join(rel_path)
+    }
+}
+
+/// Gives the file at `path` the modification time `mtime`
+fn set_mtime(path: &Path, mtime: i64) -> Result<()> {
+    let mtime = UNIX_EPOCH + Duration::from_secs(mtime.max(0) as u64);
+    File::options()
+        .write(true)
+        .open(path)
+        .and_then(|f| f.set_modified(mtime))
+        .with_context(|| format!("Failed to set the mtime of {:?}", path))
+}
+
+impl CloudSync for LocalFolderSyncImpl {
+    fn name(&self) -> &str {
+        "Local Folder"
+    }
+
+    /// Checks the folder is there. A missing one is more likely a disk
+    /// that isn't mounted than an empty folder, so it isn't created.
+    fn authenticate(&mut self, _client: &Client) -> Result<()> {
+        if self.remote_path.is_empty() {
+            return Err(anyhow::anyhow!("No remote_path to sync with"));
+        }
+        let remote = std::fs::canonicalize(&self.remote_path)
+            .with_context(|| format!("Remote folder not found: {}", self.remote_path))?;
+        if !remote.is_dir() {
+            return Err(anyhow::anyhow!("Not a folder: {}", self.remote_path));
+        }
+        if let Ok(local) = std::fs::canonicalize(&self.local_path) {
+            if remote.starts_with(&local) || local.starts_with(&remote) {
+                return Err(anyhow::anyhow!(
+                    "{} and {} overlap; sync them with a folder outside both",
+                    self.local_path,
+                    self.remote_path
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_remote_files(&self, _client: &Client) -> Result<HashMap<String, SyncItem>> {
+        let base_path = Path::new(&self.remote_path);
+        let mut items = HashMap::new();
+        let walk = walkdir::WalkDir::new(base_path)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| entry.depth() > 1 || entry.file_name() != STATE_DIR);
+        for entry in walk {
+            let entry = entry?;
+            let rel_path = entry
+                .path()
+                .strip_prefix(base_path)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let metadata = entry.metadata()?;
+            items.insert(
+                rel_path.clone(),
+                SyncItem {
+                    rel_path,
+                    abs_path_or_id: entry.path().to_string_lossy().to_string(),
+                    mtime: if metadata.is_dir() {
+                        0
+                    } else {
+                        local_mtime(entry.path())
+                    },
+                    is_folder: metadata.is_dir(),
+                    size: if metadata.is_dir() { 0 } else { metadata.len() },
+                    checksum: None,
+                },
+            );
+        }
+        Ok(items)
+    }
+
+    /// Copies the file next to its target first, so an interrupted copy
+    /// never leaves half a file under the real name, and keeps its mtime
+    fn upload_file(
+        &self,
+        _client: &Client,
+        uploader: &Uploader,
+        local_path: &str,
+        rel_path: &str,
+    ) -> Result<()> {
+        let target = self.target(rel_path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut part = target.clone().into_os_string();
+        part.push(".part");
+        let part = PathBuf::from(part);
+
+        let copied = File::create(&part)
+            .with_context(|| format!("Failed to create {:?}", part))
+            .and_then(|mut dest| uploader.copy(local_path, rel_path, &mut dest))
+            .and_then(|()| set_mtime(&part, local_mtime(Path::new(local_path))));
+        if copied.is_err() {
+            let _ = std::fs::remove_file(&part);
+        }
+        copied?;
+        std::fs::rename(&part, &target)
+            .with_context(|| format!("Failed to move {:?} into place", target))?;
+        Ok(())
+    }
+
+    fn download_file(&self, _client: &Client, remote_id: &str, local_dest: &str) -> Result<()> {
+        std::fs::copy(remote_id, local_dest)
+            .with_context(|| format!("Failed to copy {}", remote_id))?;
+        Ok(())
+    }
+
+    fn create_remote_folder(&self, _client: &Client, rel_path: &str) -> Result<()> {
+        std::fs::create_dir_all(self.target(rel_path))?;
+        Ok(())
+    }
+
+    /// Folders go with everything in them, as they do on the cloud
+    /// providers
+    fn delete_remote(&self, _client: &Client, remote_id: &str, _rel_path: &str) -> Result<()> {
+        let path = Path::new(remote_id);
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path)?,
+            Ok(_) => std::fs::remove_file(path)?,
+            // Already gone with a folder deleted before it
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+}
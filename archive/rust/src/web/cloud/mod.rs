@@ -1,4 +1,13 @@
+pub mod checksum;
 pub mod dropbox_sync;
+pub mod filter;
 pub mod google_drive_sync;
+pub mod local_folder_sync;
+pub mod oauth;
 pub mod one_drive_sync;
+#[cfg(feature = "sftp")]
+pub mod sftp_sync;
+pub mod state;
 pub mod sync;
+pub mod transfer;
+pub mod upload;
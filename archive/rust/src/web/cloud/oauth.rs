@@ -0,0 +1,276 @@
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::sync::Mutex;
+
+pub const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+pub const MICROSOFT_TOKEN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
+
+/// A provider turned the access token down and it couldn't be renewed.
+/// Asking again won't help; the user has to sign in again.
+#[derive(Debug)]
+pub struct TokenExpired {
+    pub provider: String,
+    pub detail: String,
+}
+
+impl fmt::Display for TokenExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The {} access token has expired or been revoked; sign in again ({})",
+            self.provider, self.detail
+        )
+    }
+}
+
+impl std::error::Error for TokenExpired {}
+
+/// The tokens a refresh handed out, for the app to keep in place of the
+/// ones it started the sync with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefreshedToken {
+    pub access_token: String,
+    /// Only when the provider replaced the refresh token as well
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Seconds the access token is good for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
+}
+
+/// A provider's OAuth access token, renewed with the config's
+/// `refresh_token`, `client_id` and `client_secret` once the provider
+/// rejects it. Safe to share between the threads of a sync; only one of
+/// them renews it.
+pub struct OAuthToken {
+    provider: String,
+    token_url: String,
+    access_token: Mutex<String>,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    refreshed: Mutex<Option<RefreshedToken>>,
+    renewing: Mutex<()>,
+}
+
+impl OAuthToken {
+    pub fn from_config(config: &Value, provider: &str, token_url: &str) -> Self {
+        let text = |key: &str| {
+            config
+                .get(key)
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+        };
+        OAuthToken {
+            provider: provider.to_string(),
+            token_url: token_url.to_string(),
+            access_token: Mutex::new(text("access_token").unwrap_or_default()),
+            refresh_token: text("refresh_token"),
+            client_id: text("client_id"),
+            client_secret: text("client_secret"),
+            refreshed: Mutex::new(None),
+            renewing: Mutex::new(()),
+        }
+    }
+
+    pub fn access_token(&self) -> String {
+        self.access_token.lock().unwrap().clone()
+    }
+
+    /// The newest tokens, if the access token was renewed
+    pub fn refreshed(&self) -> Option<RefreshedToken> {
+        self.refreshed.lock().unwrap().clone()
+    }
+
+    /// Sends the request `build` makes with the current access token. If
+    /// the provider answers 401 the token is renewed, when there's a
+    /// refresh token to do it with, and the request sent once more.
+    pub fn send(&self, client: &Client, build: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let used = self.access_token();
+        let res = self.authorized(build(), &used)?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        if self.refresh_token.is_none() {
+            return Err(self.expired(res));
+        }
+        {
+            // Requests turned down at the same time wait for the one
+            // refresh and then use its token
+            let _renewing = self.renewing.lock().unwrap();
+            if self.access_token() == used {
+                self.refresh(client)?;
+            }
+        }
+        let res = self.authorized(build(), &self.access_token())?;
+        if res.status() == StatusCode::UNAUTHORIZED {
+            return Err(self.expired(res));
+        }
+        Ok(res)
+    }
+
+    fn authorized(&self, request: RequestBuilder, access_token: &str) -> Result<Response> {
+        let bearer = format!("Bearer {}", access_token);
+        Ok(request.header("Authorization", bearer).send()?)
+    }
+
+    fn expired(&self, res: Response) -> anyhow::Error {
+        TokenExpired {
+            provider: self.provider.clone(),
+            detail: res.text().unwrap_or_default(),
+        }
+        .into()
+    }
+
+    /// Trades the refresh token for a new access token
+    pub fn refresh(&self, client: &Client) -> Result<()> {
+        let current = self.refreshed();
+        let refresh_token = current
+            .as_ref()
+            .and_then(|t| t.refresh_token.as_ref())
+            .or(self.refresh_token.as_ref())
+            .context("No refresh_token to renew the access token with")?;
+
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+        ];
+        if let Some(client_id) = &self.client_id {
+            form.push(("client_id", client_id));
+        }
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let res = client.post(&self.token_url).form(&form).send()?;
+        if !res.status().is_success() {
+            return Err(TokenExpired {
+                provider: self.provider.clone(),
+                detail: format!("refreshing failed: {}", res.text()?),
+            }
+            .into());
+        }
+
+        let body: Value = res.json()?;
+        let access_token = body
+            .get("access_token")
+            .and_then(|v| v.as_str())
+            .context("Token refresh returned no access_token")?
+            .to_string();
+        // Microsoft hands out a new refresh token each time; Google doesn't
+        let refresh_token = body
+            .get("refresh_token")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .or_else(|| current.and_then(|t| t.refresh_token));
+
+        *self.access_token.lock().unwrap() = access_token.clone();
+        *self.refreshed.lock().unwrap() = Some(RefreshedToken {
+            access_token,
+            refresh_token,
+            expires_in: body.get("expires_in").and_then(|v| v.as_u64()),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+    use serde_json::json;
+
+    fn token(server: &mockito::Server, refresh_token: Option<&str>) -> OAuthToken {
+        let mut config = json!({
+            "access_token": "old",
+            "client_id": "app",
+            "client_secret": "shh"
+        });
+        if let Some(refresh_token) = refresh_token {
+            config["refresh_token"] = json!(refresh_token);
+        }
+        OAuthToken::from_config(&config, "OneDrive", &format!("{}/token", server.url()))
+    }
+
+    #[test]
+    fn test_send_refreshes_once_on_401() {
+        let mut server = mockito::Server::new();
+        let rejected = server
+            .mock("GET", "/files")
+            .match_header("authorization", "Bearer old")
+            .with_status(401)
+            .expect(1)
+            .create();
+        let refresh = server
+            .mock("POST", "/token")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("grant_type".into(), "refresh_token".into()),
+                Matcher::UrlEncoded("refresh_token".into(), "r1".into()),
+                Matcher::UrlEncoded("client_id".into(), "app".into()),
+                Matcher::UrlEncoded("client_secret".into(), "shh".into()),
+            ]))
+            .with_body(r#"{"access_token": "new", "refresh_token": "r2", "expires_in": 3600}"#)
+            .expect(1)
+            .create();
+        let accepted = server
+            .mock("GET", "/files")
+            .match_header("authorization", "Bearer new")
+            .with_body("[]")
+            .expect(1)
+            .create();
+
+        let client = Client::new();
+        let token = token(&server, Some("r1"));
+        let url = format!("{}/files", server.url());
+        let res = token.send(&client, || client.get(&url)).unwrap();
+
+        assert_eq!(res.text().unwrap(), "[]");
+        for mock in [rejected, refresh, accepted] {
+            mock.assert();
+        }
+        assert_eq!(token.access_token(), "new");
+        assert_eq!(
+            token.refreshed(),
+            Some(RefreshedToken {
+                access_token: "new".to_string(),
+                refresh_token: Some("r2".to_string()),
+                expires_in: Some(3600),
+            })
+        );
+    }
+
+    #[test]
+    fn test_send_reports_expired_tokens() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/files")
+            .with_status(401)
+            .with_body("InvalidAuthenticationToken")
+            .create();
+        let client = Client::new();
+        let url = format!("{}/files", server.url());
+
+        // Without a refresh token there's nothing to try
+        let err = token(&server, None)
+            .send(&client, || client.get(&url))
+            .unwrap_err();
+        let expired = err.downcast_ref::<TokenExpired>().unwrap();
+        assert_eq!(expired.detail, "InvalidAuthenticationToken");
+
+        // A refresh token that's been revoked
+        server
+            .mock("POST", "/token")
+            .with_status(400)
+            .with_body(r#"{"error": "invalid_grant"}"#)
+            .create();
+        let token = token(&server, Some("r1"));
+        let err = token.send(&client, || client.get(&url)).unwrap_err();
+        assert!(err.is::<TokenExpired>());
+        assert!(err.to_string().contains("invalid_grant"), "{}", err);
+        assert_eq!(token.refreshed(), None);
+    }
+}
@@ -0,0 +1,369 @@
+use super::sync::{local_mtime, CloudSync, SyncItem};
+use super::upload::Uploader;
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use ssh2::{CheckResult, ErrorCode, FileStat, KnownHostFileKind, Session, Sftp};
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+/// The SFTP status for a path that isn't there
+const NO_SUCH_FILE: ErrorCode = ErrorCode::SFTP(2);
+
+/// Syncs with a folder on an SSH server. Signs in with `private_key`
+/// when the config has one, else `password`, else the SSH agent, and only
+/// talks to servers whose key is in `known_hosts`.
+pub struct SftpSyncImpl {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: Option<String>,
+    /// Path of the private key file
+    pub private_key: Option<String>,
+    pub passphrase: Option<String>,
+    /// Defaults to `~/.ssh/known_hosts`
+    pub known_hosts: Option<String>,
+    pub remote_path: String,
+    sftp: Option<Sftp>,
+}
+
+impl SftpSyncImpl {
+    pub fn new(config: &Value) -> Self {
+        let text = |key: &str| {
+            config
+                .get(key)
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+        };
+        SftpSyncImpl {
+            host: text("host").unwrap_or_default(),
+            port: config
+                .get("port")
+                .and_then(|v| v.as_u64())
+                .and_then(|port| u16::try_from(port).ok())
+                .unwrap_or(22),
+            username: text("username").unwrap_or_default(),
+            password: text("password"),
+            private_key: text("private_key"),
+            passphrase: text("passphrase"),
+            known_hosts: text("known_hosts"),
+            remote_path: text("remote_path")
+                .map(|path| path.trim_end_matches('/').to_string())
+                .unwrap_or_default(),
+            sftp: None,
+        }
+    }
+
+    fn sftp(&self) -> Result<&Sftp> {
+        self.sftp
+            .as_ref()
+            .context("Not connected to the SFTP server")
+    }
+
+    /// Where `rel_path` is on the server; relative to the login folder
+    /// when there's no `remote_path`
+    fn target(&self, rel_path: &str) -> String {
+        if self.remote_path.is_empty() {
+            rel_path.to_string()
+        } else {
+            format!("{}/{}", self.remote_path, rel_path)
+        }
+    }
+
+    fn known_hosts_path(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.known_hosts {
+            return Ok(PathBuf::from(path));
+        }
+        let home = std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .context("No home folder to find known_hosts in; set known_hosts")?;
+        Ok(Path::new(&home).join(".ssh").join("known_hosts"))
+    }
+
+    /// Fails unless the server's key is the one `known_hosts` has for it
+    fn check_host_key(&self, session: &Session) -> Result<()> {
+        let path = self.known_hosts_path()?;
+        let (key, _) = session
+            .host_key()
+            .context("The SFTP server sent no host key")?;
+        let mut known = session.known_hosts()?;
+        known
+            .read_file(&path, KnownHostFileKind::OpenSSH)
+            .with_context(|| format!("Failed to read known hosts from {:?}", path))?;
+        match known.check_port(&self.host, self.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => Err(anyhow::anyhow!(
+                "{} isn't in {:?}; connect once with ssh to check its key and add it",
+                self.host,
+                path
+            )),
+            CheckResult::Mismatch => Err(anyhow::anyhow!(
+                "The host key of {} doesn't match the one in {:?}; not connecting",
+                self.host,
+                path
+            )),
+            CheckResult::Failure => Err(anyhow::anyhow!(
+                "Failed to check the host key of {}",
+                self.host
+            )),
+        }
+    }
+
+    /// Adds the files and folders under the server folder `dir`, which
+    /// is `rel_dir` in the sync, to `items`
+    fn list_into(
+        &self,
+        sftp: &Sftp,
+        dir: &str,
+        rel_dir: &str,
+        items: &mut HashMap<String, SyncItem>,
+    ) -> Result<()> {
+        let entries = sftp
+            .readdir(Path::new(dir))
+            .with_context(|| format!("Failed to list {}", dir))?;
+        for (path, stat) in entries {
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if name == "." || name == ".." {
+                continue;
+            }
+            let rel_path = if rel_dir.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", rel_dir, name)
+            };
+            let abs_path = format!("{}/{}", dir, name);
+            let is_folder = stat.is_dir();
+            if is_folder {
+                self.list_into(sftp, &abs_path, &rel_path, items)?;
+            }
+            items.insert(
+                rel_path.clone(),
+                SyncItem {
+                    rel_path,
+                    abs_path_or_id: abs_path,
+                    mtime: if is_folder {
+                        0
+                    } else {
+                        stat.mtime.unwrap_or(0) as i64
+                    },
+                    is_folder,
+                    size: if is_folder { 0 } else { stat.size.unwrap_or(0) },
+                    checksum: None,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Makes `path` and any folders above it that aren't there yet
+    fn mkdir_all(&self, sftp: &Sftp, path: &str) -> Result<()> {
+        let mut current = String::new();
+        for part in path.split('/') {
+            if part.is_empty() {
+                current.push('/');
+                continue;
+            }
+            if !current.is_empty() && !current.ends_with('/') {
+                current.push('/');
+            }
+            current.push_str(part);
+            match sftp.stat(Path::new(&current)) {
+                Ok(stat) if stat.is_dir() => {}
+                Ok(_) => return Err(anyhow::anyhow!("{} is a file, not a folder", current)),
+                Err(e) if e.code() == NO_SUCH_FILE => sftp
+                    .mkdir(Path::new(&current), 0o755)
+                    .with_context(|| format!("Failed to create {}", current))?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes `path`, and everything in it if it's a folder
+    fn remove_all(&self, sftp: &Sftp, path: &str) -> Result<()> {
+        // A link to a folder goes, not what it links to
+        let stat = match sftp.lstat(Path::new(path)) {
+            Ok(stat) => stat,
+            // Already gone with a folder deleted before it
+            Err(e) if e.code() == NO_SUCH_FILE => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if !stat.is_dir() {
+            sftp.unlink(Path::new(path))?;
+            return Ok(());
+        }
+        for (child, _) in sftp.readdir(Path::new(path))? {
+            match child.file_name() {
+                Some(name) if name != "." && name != ".." => {
+                    let child = format!("{}/{}", path, name.to_string_lossy());
+                    self.remove_all(sftp, &child)?;
+                }
+                _ => {}
+            }
+        }
+        sftp.rmdir(Path::new(path))?;
+        Ok(())
+    }
+}
+
+impl CloudSync for SftpSyncImpl {
+    fn name(&self) -> &str {
+        "SFTP"
+    }
+
+    fn authenticate(&mut self, _client: &Client) -> Result<()> {
+        if self.host.is_empty() || self.username.is_empty() {
+            return Err(anyhow::anyhow!("SFTP needs a host and a username"));
+        }
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))
+            .with_context(|| format!("Failed to connect to {}:{}", self.host, self.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(60_000);
+        session.handshake().context("SSH handshake failed")?;
+        self.check_host_key(&session)?;
+
+        if let Some(key) = &self.private_key {
+            session.userauth_pubkey_file(
+                &self.username,
+                None,
+                Path::new(key),
+                self.passphrase.as_deref(),
+            )?;
+        } else if let Some(password) = &self.password {
+            session.userauth_password(&self.username, password)?;
+        } else {
+            session.userauth_agent(&self.username)?;
+        }
+        if !session.authenticated() {
+            return Err(anyhow::anyhow!(
+                "The SFTP server turned down {}",
+                self.username
+            ));
+        }
+        self.sftp = Some(session.sftp()?);
+        Ok(())
+    }
+
+    fn get_remote_files(&self, _client: &Client) -> Result<HashMap<String, SyncItem>> {
+        let sftp = self.sftp()?;
+        let root = if self.remote_path.is_empty() {
+            "."
+        } else {
+            &self.remote_path
+        };
+        let mut items = HashMap::new();
+        match sftp.stat(Path::new(root)) {
+            Ok(_) => self.list_into(sftp, root, "", &mut items)?,
+            // Made by the first upload
+            Err(e) if e.code() == NO_SUCH_FILE => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(items)
+    }
+
+    /// Writes the file next to its target first, so an interrupted upload
+    /// never leaves half a file under the real name, and keeps its mtime
+    fn upload_file(
+        &self,
+        _client: &Client,
+        uploader: &Uploader,
+        local_path: &str,
+        rel_path: &str,
+    ) -> Result<()> {
+        let sftp = self.sftp()?;
+        let target = self.target(rel_path);
+        if let Some((parent, _)) = target.rsplit_once('/') {
+            self.mkdir_all(sftp, parent)?;
+        }
+        let part = format!("{}.part", target);
+
+        let copied = sftp
+            .create(Path::new(&part))
+            .with_context(|| format!("Failed to create {}", part))
+            .and_then(|mut dest| uploader.copy(local_path, rel_path, &mut dest));
+        if copied.is_err() {
+            let _ = sftp.unlink(Path::new(&part));
+        }
+        copied?;
+
+        let mtime = local_mtime(Path::new(local_path)).max(0) as u64;
+        sftp.setstat(
+            Path::new(&part),
+            FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: None,
+                atime: Some(mtime),
+                mtime: Some(mtime),
+            },
+        )?;
+        // Plain SFTP renames don't replace files
+        match sftp.unlink(Path::new(&target)) {
+            Err(e) if e.code() != NO_SUCH_FILE => return Err(e.into()),
+            _ => {}
+        }
+        sftp.rename(Path::new(&part), Path::new(&target), None)
+            .with_context(|| format!("Failed to move {} into place", target))?;
+        Ok(())
+    }
+
+    fn download_file(&self, _client: &Client, remote_id: &str, local_dest: &str) -> Result<()> {
+        let mut src = self
+            .sftp()?
+            .open(Path::new(remote_id))
+            .with_context(|| format!("Failed to open {}", remote_id))?;
+        let mut dest = std::fs::File::create(local_dest)?;
+        std::io::copy(&mut src, &mut dest)
+            .with_context(|| format!("Failed to download {}", remote_id))?;
+        Ok(())
+    }
+
+    fn create_remote_folder(&self, _client: &Client, rel_path: &str) -> Result<()> {
+        self.mkdir_all(self.sftp()?, &self.target(rel_path))
+    }
+
+    /// Folders go with everything in them, as they do on the cloud
+    /// providers
+    fn delete_remote(&self, _client: &Client, remote_id: &str, _rel_path: &str) -> Result<()> {
+        self.remove_all(self.sftp()?, remote_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sftp_config() {
+        let sync = SftpSyncImpl::new(&json!({
+            "host": "nas.local",
+            "port": 2222,
+            "username": "photos",
+            "private_key": "/home/me/.ssh/id_ed25519",
+            "password": "",
+            "remote_path": "/srv/photos/"
+        }));
+        assert_eq!(sync.host, "nas.local");
+        assert_eq!(sync.port, 2222);
+        assert_eq!(sync.password, None);
+        assert_eq!(
+            sync.private_key.as_deref(),
+            Some("/home/me/.ssh/id_ed25519")
+        );
+        assert_eq!(sync.target("trips/a.jpg"), "/srv/photos/trips/a.jpg");
+
+        // The login folder, on the usual port
+        let sync = SftpSyncImpl::new(&json!({ "host": "nas.local", "port": 70000 }));
+        assert_eq!(sync.port, 22);
+        assert_eq!(sync.target("a.jpg"), "a.jpg");
+        assert!(sync.sftp().is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use super::sync::SyncItem;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The folder in the local sync folder two-way syncs keep their state in.
+/// It's never synced itself.
+pub const STATE_DIR: &str = ".image-toolkit-sync";
+
+/// A file or folder as both sides had it when the last sync finished
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncedItem {
+    pub is_folder: bool,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub local_mtime: i64,
+    #[serde(default)]
+    pub remote_mtime: i64,
+    /// The provider's hash of the file, when it listed one
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+impl SyncedItem {
+    /// Both sides left as `local` and `remote` are
+    pub fn of(local: &SyncItem, remote: &SyncItem) -> Self {
+        SyncedItem {
+            is_folder: local.is_folder,
+            size: local.size,
+            local_mtime: local.mtime,
+            remote_mtime: remote.mtime,
+            checksum: remote.checksum.clone(),
+        }
+    }
+
+    pub fn folder() -> Self {
+        SyncedItem {
+            is_folder: true,
+            size: 0,
+            local_mtime: 0,
+            remote_mtime: 0,
+            checksum: None,
+        }
+    }
+
+    /// Whether `local` differs from how it was left
+    pub fn local_changed(&self, local: &SyncItem, tolerance_secs: i64) -> bool {
+        if local.is_folder || self.is_folder {
+            return local.is_folder != self.is_folder;
+        }
+        local.size != self.size || (local.mtime - self.local_mtime).abs() > tolerance_secs
+    }
+
+    /// Whether `remote` differs from how it was left, by its hash when
+    /// there's one from both times
+    pub fn remote_changed(&self, remote: &SyncItem, tolerance_secs: i64) -> bool {
+        if remote.is_folder || self.is_folder {
+            return remote.is_folder != self.is_folder;
+        }
+        if let (Some(then), Some(now)) = (&self.checksum, &remote.checksum) {
+            return then != now;
+        }
+        remote.size != self.size || (remote.mtime - self.remote_mtime).abs() > tolerance_secs
+    }
+}
+
+/// What a two-way sync left on both sides, by path relative to the sync
+/// folder. The next sync compares each side against it to tell a file
+/// created on one side from one deleted on the other.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncState {
+    pub remote_path: String,
+    pub items: HashMap<String, SyncedItem>,
+}
+
+impl SyncState {
+    /// Where the state of syncing `local_path` with `provider` is kept
+    pub fn path_for(local_path: &str, provider: &str) -> PathBuf {
+        let name: String = provider
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        Path::new(local_path)
+            .join(STATE_DIR)
+            .join(format!("{}.json", name))
+    }
+
+    /// The state saved at `path`. Empty when there's none yet, or when it
+    /// was for a different remote folder.
+    pub fn load(path: &Path, remote_path: &str) -> Result<Self> {
+        let empty = SyncState {
+            remote_path: remote_path.to_string(),
+            items: HashMap::new(),
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(empty),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        let state: SyncState = serde_json::from_str(&text).with_context(|| {
+            format!(
+                "Sync state {:?} is corrupt; delete it to sync from scratch",
+                path
+            )
+        })?;
+        if state.remote_path != remote_path {
+            return Ok(empty);
+        }
+        Ok(state)
+    }
+
+    /// Writes the state to `path`, all at once so an interrupted save
+    /// doesn't leave half a file
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {:?}", tmp))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(mtime: i64, size: u64, checksum: Option<&str>) -> SyncItem {
+        SyncItem {
+            rel_path: "a.jpg".to_string(),
+            abs_path_or_id: "a.jpg".to_string(),
+            mtime,
+            is_folder: false,
+            size,
+            checksum: checksum.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_changes_since_last_sync() {
+        let synced = SyncedItem::of(&file(1000, 5, None), &file(1001, 5, Some("h1")));
+
+        assert!(!synced.local_changed(&file(1002, 5, None), 2));
+        assert!(synced.local_changed(&file(1003, 5, None), 2));
+        assert!(synced.local_changed(&file(1000, 6, None), 2));
+
+        // The hash decides when there is one
+        assert!(!synced.remote_changed(&file(1500, 5, Some("h1")), 2));
+        assert!(synced.remote_changed(&file(1001, 5, Some("h2")), 2));
+        assert!(synced.remote_changed(&file(1500, 5, None), 2));
+        assert!(!synced.remote_changed(&file(1001, 5, None), 2));
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().to_str().unwrap();
+        let path = SyncState::path_for(local, "Google Drive");
+        assert_eq!(
+            path,
+            dir.path().join(".image-toolkit-sync/google-drive.json")
+        );
+
+        // Nothing saved yet
+        let mut state = SyncState::load(&path, "Backup").unwrap();
+        assert_eq!(state.remote_path, "Backup");
+        assert!(state.items.is_empty());
+
+        state
+            .items
+            .insert("trips".to_string(), SyncedItem::folder());
+        state.items.insert(
+            "trips/a.jpg".to_string(),
+            SyncedItem::of(&file(1000, 5, None), &file(1000, 5, Some("h1"))),
+        );
+        state.save(&path).unwrap();
+        assert_eq!(SyncState::load(&path, "Backup").unwrap(), state);
+
+        // Another remote folder starts over
+        assert!(SyncState::load(&path, "Photos").unwrap().items.is_empty());
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(SyncState::load(&path, "Backup").is_err());
+    }
+}
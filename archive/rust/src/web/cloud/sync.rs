@@ -1,14 +1,18 @@
-#[cfg(feature = "python")]
-use anyhow::Context;
-use anyhow::Result;
-#[cfg(feature = "python")]
-use pyo3::prelude::*;
+use super::checksum::{verify, ChecksumMismatch, HashKind};
+use super::filter::SyncFilter;
+use super::oauth::RefreshedToken;
+use super::state::{SyncState, SyncedItem, STATE_DIR};
+use super::transfer::{run_pooled, Job};
+use super::upload::Uploader;
+use crate::web::clients::retry::RetryPolicy;
+use crate::web::progress::ProgressSink;
+use anyhow::{Context, Result};
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
-#[cfg(feature = "python")]
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncItem {
@@ -16,6 +20,12 @@ pub struct SyncItem {
     pub abs_path_or_id: String,
     pub mtime: i64,
     pub is_folder: bool,
+    /// In bytes; 0 for folders
+    #[serde(default)]
+    pub size: u64,
+    /// The provider's hash of the contents, of its `hash_kind`
+    #[serde(default)]
+    pub checksum: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,14 +36,302 @@ pub struct SyncStats {
     pub deleted_remote: u32,
     pub skipped: u32,
     pub ignored: u32,
+    /// Files on both sides that differed and were copied one way
+    pub updated: u32,
+    /// Files on both sides that differed and were kept in both versions
+    pub conflicted: u32,
+    /// Transfers that still didn't match the provider's checksum after
+    /// retrying; the rest of the sync carries on without them
+    pub verification_failed: u32,
+}
+
+/// Which version wins when a file on both sides differs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    NewerWins,
+    LocalWins,
+    RemoteWins,
+    /// Keep both, the older under a `.conflict-YYYYMMDD` name
+    KeepBoth,
+}
+
+impl ConflictPolicy {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "newer_wins" => Ok(ConflictPolicy::NewerWins),
+            "local_wins" => Ok(ConflictPolicy::LocalWins),
+            "remote_wins" => Ok(ConflictPolicy::RemoteWins),
+            "keep_both" => Ok(ConflictPolicy::KeepBoth),
+            _ => Err(anyhow::anyhow!(
+                "Unknown conflict_policy '{}' (expected newer_wins, local_wins, remote_wins or keep_both)",
+                name
+            )),
+        }
+    }
+}
+
+/// How a sync treats what's on only one side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// As `action_local` and `action_remote` say
+    OneWay,
+    /// Against the state the last sync left: files created, changed or
+    /// deleted on either side are created, changed or deleted on the other
+    TwoWay,
+}
+
+impl SyncMode {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "one_way" => Ok(SyncMode::OneWay),
+            "two_way" => Ok(SyncMode::TwoWay),
+            _ => Err(anyhow::anyhow!(
+                "Unknown mode '{}' (expected one_way or two_way)",
+                name
+            )),
+        }
+    }
+}
+
+/// What to do with a file found both locally and remotely
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Unchanged,
+    Upload,
+    Download,
+    KeepBoth { local_is_older: bool },
+}
+
+/// Compares the two sides of a file by size and mtime. Mtimes within
+/// `tolerance_secs` of each other count as the same, so clock skew and
+/// providers that round timestamps don't cause transfers.
+pub fn resolve(
+    local: &SyncItem,
+    remote: &SyncItem,
+    policy: ConflictPolicy,
+    tolerance_secs: i64,
+) -> Resolution {
+    let age = local.mtime - remote.mtime;
+    let same_time = age.abs() <= tolerance_secs;
+    if local.size == remote.size && same_time {
+        return Resolution::Unchanged;
+    }
+    let local_is_older = age <= 0;
+    match policy {
+        ConflictPolicy::LocalWins => Resolution::Upload,
+        ConflictPolicy::RemoteWins => Resolution::Download,
+        // Different contents written at the same time: neither is newer
+        ConflictPolicy::NewerWins if same_time => Resolution::KeepBoth { local_is_older },
+        ConflictPolicy::NewerWins if local_is_older => Resolution::Download,
+        ConflictPolicy::NewerWins => Resolution::Upload,
+        ConflictPolicy::KeepBoth => Resolution::KeepBoth { local_is_older },
+    }
+}
+
+/// One step of a two-way sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TwoWayOp {
+    Unchanged,
+    /// New locally
+    Upload,
+    /// New remotely
+    Download,
+    UpdateRemote,
+    UpdateLocal,
+    DeleteLocal,
+    DeleteRemote,
+    CreateRemoteFolder,
+    CreateLocalFolder,
+    KeepBoth {
+        local_is_older: bool,
+    },
+}
+
+impl TwoWayOp {
+    /// How the step is reported, as in "Uploading: a.jpg"
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TwoWayOp::Unchanged => "Unchanged",
+            TwoWayOp::Upload => "Uploading",
+            TwoWayOp::Download => "Downloading",
+            TwoWayOp::UpdateRemote => "Updating Remote",
+            TwoWayOp::UpdateLocal => "Updating Local",
+            TwoWayOp::DeleteLocal => "Deleting Local",
+            TwoWayOp::DeleteRemote => "Deleting Remote",
+            TwoWayOp::CreateRemoteFolder => "Creating Remote Folder",
+            TwoWayOp::CreateLocalFolder => "Creating Local Folder",
+            TwoWayOp::KeepBoth { .. } => "Keeping Both",
+        }
+    }
+}
+
+/// What a two-way sync does with each path, from both sides now and
+/// `state` as the last sync left them. A change on one side is carried to
+/// the other, deletions included, and beats a deletion on the other side.
+/// Files changed on both sides, or new on both, are settled by `policy`.
+///
+/// Folders are created before the files in them and deleted after, and
+/// only when nothing in them is staying.
+pub fn plan_two_way(
+    local: &HashMap<String, SyncItem>,
+    remote: &HashMap<String, SyncItem>,
+    state: &SyncState,
+    policy: ConflictPolicy,
+    tolerance_secs: i64,
+) -> Vec<(String, TwoWayOp)> {
+    use TwoWayOp as Op;
+
+    let mut paths: Vec<&String> = local.keys().chain(remote.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut plan = Vec::new();
+    for rel_path in paths {
+        let synced = state.items.get(rel_path);
+        let op = match (local.get(rel_path), remote.get(rel_path)) {
+            (Some(l), Some(r)) if l.is_folder || r.is_folder => Op::Unchanged,
+            (Some(l), Some(r)) => {
+                let changed = synced.map(|s| {
+                    (
+                        s.local_changed(l, tolerance_secs),
+                        s.remote_changed(r, tolerance_secs),
+                    )
+                });
+                match changed {
+                    Some((false, false)) => Op::Unchanged,
+                    Some((true, false)) => Op::UpdateRemote,
+                    Some((false, true)) => Op::UpdateLocal,
+                    // Changed on both sides, or new on both
+                    _ => match resolve(l, r, policy, tolerance_secs) {
+                        Resolution::Unchanged => Op::Unchanged,
+                        Resolution::Upload => Op::UpdateRemote,
+                        Resolution::Download => Op::UpdateLocal,
+                        Resolution::KeepBoth { local_is_older } => Op::KeepBoth { local_is_older },
+                    },
+                }
+            }
+            (Some(l), None) => match synced {
+                Some(s) if !s.local_changed(l, tolerance_secs) => Op::DeleteLocal,
+                _ if l.is_folder => Op::CreateRemoteFolder,
+                _ => Op::Upload,
+            },
+            (None, Some(r)) => match synced {
+                Some(s) if !s.remote_changed(r, tolerance_secs) => Op::DeleteRemote,
+                _ if r.is_folder => Op::CreateLocalFolder,
+                _ => Op::Download,
+            },
+            (None, None) => unreachable!(),
+        };
+        plan.push((rel_path.clone(), op));
+    }
+
+    let is_folder = |rel_path: &str| {
+        local
+            .get(rel_path)
+            .or_else(|| remote.get(rel_path))
+            .is_some_and(|item| item.is_folder)
+    };
+    // A folder deleted on one side is brought back if anything in it stays
+    let staying: Vec<String> = plan
+        .iter()
+        .filter(|(_, op)| !matches!(op, Op::DeleteLocal | Op::DeleteRemote))
+        .map(|(rel_path, _)| rel_path.clone())
+        .collect();
+    for (rel_path, op) in plan.iter_mut() {
+        let prefix = format!("{}/", rel_path);
+        if is_folder(rel_path) && staying.iter().any(|p| p.starts_with(&prefix)) {
+            match op {
+                Op::DeleteLocal => *op = Op::CreateRemoteFolder,
+                Op::DeleteRemote => *op = Op::CreateLocalFolder,
+                _ => {}
+            }
+        }
+    }
+
+    let depth = |rel_path: &str| rel_path.matches('/').count() as i64;
+    plan.sort_by_key(|(rel_path, op)| match op {
+        Op::CreateRemoteFolder | Op::CreateLocalFolder => (0, depth(rel_path), rel_path.clone()),
+        Op::DeleteLocal | Op::DeleteRemote if is_folder(rel_path) => {
+            (2, -depth(rel_path), rel_path.clone())
+        }
+        _ => (1, 0, rel_path.clone()),
+    });
+    plan
+}
+
+/// `rel_path` renamed for the older side of a conflict last modified at
+/// `mtime`: `dir/photo.jpg` becomes `dir/photo.conflict-20240131.jpg`. A
+/// number is added when `n` is above 1.
+pub fn conflict_name(rel_path: &str, mtime: i64, n: u32) -> String {
+    let date = chrono::DateTime::from_timestamp(mtime, 0)
+        .unwrap_or_default()
+        .format("%Y%m%d");
+    let suffix = if n > 1 {
+        format!("conflict-{}-{}", date, n)
+    } else {
+        format!("conflict-{}", date)
+    };
+    let (dir, file) = match rel_path.rfind('/') {
+        Some(i) => rel_path.split_at(i + 1),
+        None => ("", rel_path),
+    };
+    match file.rfind('.').filter(|&i| i > 0) {
+        Some(i) => format!("{}{}.{}{}", dir, &file[..i], suffix, &file[i..]),
+        None => format!("{}{}.{}", dir, file, suffix),
+    }
+}
+
+/// Seconds since the epoch `path` was last modified, 0 if unknown
+pub fn local_mtime(path: &Path) -> i64 {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// `mtime` as the RFC 3339 UTC timestamp the providers' APIs take
+pub fn mtime_to_rfc3339(mtime: i64) -> String {
+    chrono::DateTime::from_timestamp(mtime, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
 }
 
 pub trait CloudSync {
     fn name(&self) -> &str;
     fn authenticate(&mut self, client: &Client) -> Result<()>;
     fn get_remote_files(&self, client: &Client) -> Result<HashMap<String, SyncItem>>;
-    fn upload_file(&self, client: &Client, local_path: &str, rel_path: &str) -> Result<()>;
+    /// Uploads `local_path` as `rel_path`, through `uploader` a piece at
+    /// a time when it's too big for one request
+    fn upload_file(
+        &self,
+        client: &Client,
+        uploader: &Uploader,
+        local_path: &str,
+        rel_path: &str,
+    ) -> Result<()>;
+    /// Replaces the contents of an existing remote file. Providers whose
+    /// upload overwrites by path can leave this to `upload_file`.
+    fn update_file(
+        &self,
+        client: &Client,
+        uploader: &Uploader,
+        local_path: &str,
+        rel_path: &str,
+        _remote_id: &str,
+    ) -> Result<()> {
+        self.upload_file(client, uploader, local_path, rel_path)
+    }
     fn download_file(&self, client: &Client, remote_id: &str, local_dest: &str) -> Result<()>;
+    /// The hash the provider lists files with, to check downloads against
+    fn hash_kind(&self) -> Option<HashKind> {
+        None
+    }
+    /// The new tokens, if the access token was refreshed along the way
+    fn refreshed_token(&self) -> Option<RefreshedToken> {
+        None
+    }
     fn create_remote_folder(&self, client: &Client, rel_path: &str) -> Result<()>;
     fn delete_remote(&self, client: &Client, remote_id: &str, rel_path: &str) -> Result<()>;
 }
@@ -43,9 +341,43 @@ pub struct SyncRunner {
     pub remote_path: String,
     pub action_local: String,
     pub action_remote: String,
+    /// `one_way` or `two_way`; see `SyncMode`
+    pub mode: String,
     pub dry_run: bool,
+    /// How files present on both sides but differing are settled; one of
+    /// the `ConflictPolicy` names
+    pub conflict_policy: String,
+    pub mtime_tolerance_secs: i64,
+    /// Retries for transfers that fail on the network or a checksum
+    pub retry: RetryPolicy,
+    /// Gitignore-style globs of paths to leave alone on both sides
+    pub exclude_patterns: Vec<String>,
+    /// When not empty, the only file extensions synced
+    pub include_extensions: Vec<String>,
+    /// Leave alone files and folders whose names start with a dot
+    pub skip_hidden: bool,
+    /// How many files are uploaded or downloaded at a time
+    pub transfer_concurrency: usize,
 }
 
+/// The strings in the array `key` of `config`
+fn string_list(config: &Value, key: &str) -> Vec<String> {
+    config
+        .get(key)
+        .and_then(|v| v.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(|s| s.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A transfer, and what it adds to the stats once it has gone through
+type Queued<'a> = (Job<'a>, fn(&mut SyncStats));
+
 impl SyncRunner {
     pub fn new(config: &Value) -> Self {
         SyncRunner {
@@ -69,29 +401,77 @@ impl SyncRunner {
                 .and_then(|v| v.as_str())
                 .unwrap_or("download")
                 .to_string(),
+            mode: config
+                .get("mode")
+                .and_then(|v| v.as_str())
+                .unwrap_or("one_way")
+                .to_string(),
             dry_run: config
                 .get("dry_run")
                 .and_then(|v| v.as_bool())
                 .unwrap_or(false),
+            conflict_policy: config
+                .get("conflict_policy")
+                .and_then(|v| v.as_str())
+                .unwrap_or("newer_wins")
+                .to_string(),
+            mtime_tolerance_secs: config
+                .get("mtime_tolerance_secs")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(2)
+                .max(0),
+            retry: RetryPolicy::from_config(config),
+            exclude_patterns: string_list(config, "exclude_patterns"),
+            include_extensions: string_list(config, "include_extensions"),
+            skip_hidden: config
+                .get("skip_hidden")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            transfer_concurrency: config
+                .get("transfer_concurrency")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(1)
+                .max(1) as usize,
+        }
+    }
+
+    /// Reconcile `local_path` with the provider's files, as `action_local`
+    /// and `action_remote` say to treat files missing on the other side,
+    /// or in `two_way` mode against the state the last sync left.
+    /// Fails on the first provider error or once `sink` asks to stop.
+    /// A refreshed access token goes to `sink` either way, so the app can
+    /// keep it.
+    pub fn run<T: CloudSync + Sync>(
+        &self,
+        sync: &mut T,
+        client: &Client,
+        sink: &dyn ProgressSink,
+    ) -> Result<SyncStats> {
+        let result = self.sync_all(sync, client, sink);
+        if let Some(token) = sync.refreshed_token() {
+            sink.on_token_refreshed(&token);
         }
+        result
     }
 
-    #[cfg(feature = "python")]
-    pub fn run<T: CloudSync>(
+    fn sync_all<T: CloudSync + Sync>(
         &self,
-        py: Python<'_>,
         sync: &mut T,
         client: &Client,
-        callback_obj: Py<PyAny>,
+        sink: &dyn ProgressSink,
     ) -> Result<SyncStats> {
-        emit_status(
-            py,
-            &callback_obj,
-            &format!("Starting sync for {}", sync.name()),
+        let policy = ConflictPolicy::parse(&self.conflict_policy)?;
+        let mode = SyncMode::parse(&self.mode)?;
+        let filter = SyncFilter::new(
+            &self.exclude_patterns,
+            &self.include_extensions,
+            self.skip_hidden,
         )?;
+        sink.on_status(&format!("Starting sync for {}", sync.name()));
 
         sync.authenticate(client).context("Authentication failed")?;
-        emit_status(py, &callback_obj, "Authentication successful.")?;
+        sink.on_status("Authentication successful.");
+        let sync = &*sync;
 
         if !Path::new(&self.local_path).exists() {
             return Err(anyhow::anyhow!(
@@ -100,19 +480,28 @@ impl SyncRunner {
             ));
         }
 
-        emit_status(py, &callback_obj, "Scanning local and remote files...")?;
-        let local_items = self.get_local_files()?;
+        sink.on_status("Scanning local and remote files...");
+        let (local_items, mut excluded) = self.get_local_files(&filter)?;
         let mut remote_items = sync.get_remote_files(client)?;
+        // Left alone like their local counterparts, so they're neither
+        // downloaded nor deleted as orphans
+        remote_items.retain(|rel_path, item| {
+            if filter.in_excluded_folder(rel_path) {
+                return false;
+            }
+            let keep = !filter.matches(rel_path, item.is_folder);
+            if !keep {
+                excluded += 1;
+            }
+            keep
+        });
 
-        emit_status(
-            py,
-            &callback_obj,
-            &format!(
-                "Found {} local items and {} remote items.",
-                local_items.len(),
-                remote_items.len()
-            ),
-        )?;
+        sink.on_status(&format!(
+            "Found {} local items and {} remote items ({} excluded).",
+            local_items.len(),
+            remote_items.len(),
+            excluded
+        ));
 
         let mut stats = SyncStats {
             uploaded: 0,
@@ -120,12 +509,32 @@ impl SyncRunner {
             deleted_local: 0,
             deleted_remote: 0,
             skipped: 0,
-            ignored: 0,
+            ignored: excluded,
+            updated: 0,
+            conflicted: 0,
+            verification_failed: 0,
         };
 
+        if mode == SyncMode::TwoWay {
+            self.sync_two_way(
+                sync,
+                client,
+                sink,
+                &local_items,
+                &remote_items,
+                policy,
+                &mut stats,
+            )?;
+            return Ok(stats);
+        }
+
+        // Folders are made and files deleted as they come up; transfers run
+        // at the end, once the folders they go into are there
+        let mut queue: Vec<Queued> = Vec::new();
+
         // Process Local Items
         for (rel_path, local_item) in &local_items {
-            self.check_stop(py, &callback_obj)?;
+            self.check_stop(sink)?;
 
             if local_item.is_folder {
                 if remote_items.contains_key(rel_path) {
@@ -141,20 +550,63 @@ impl SyncRunner {
                 continue;
             }
 
-            if let Some(_remote_item) = remote_items.remove(rel_path) {
-                stats.skipped += 1;
+            if let Some(remote_item) = remote_items.remove(rel_path) {
+                match resolve(local_item, &remote_item, policy, self.mtime_tolerance_secs) {
+                    Resolution::Unchanged => stats.skipped += 1,
+                    Resolution::Upload => queue.push((
+                        Box::new(move |sink: &dyn ProgressSink| {
+                            sink.on_status(&format!("Updating Remote: {}", rel_path));
+                            self.upload(
+                                sync,
+                                client,
+                                sink,
+                                &local_item.abs_path_or_id,
+                                rel_path,
+                                Some(&remote_item.abs_path_or_id),
+                            )
+                        }),
+                        |stats| stats.updated += 1,
+                    )),
+                    Resolution::Download => queue.push((
+                        Box::new(move |sink: &dyn ProgressSink| {
+                            sink.on_status(&format!("Updating Local: {}", rel_path));
+                            self.download(sync, client, sink, &remote_item, rel_path)
+                        }),
+                        |stats| stats.updated += 1,
+                    )),
+                    Resolution::KeepBoth { local_is_older } => queue.push((
+                        Box::new(move |sink: &dyn ProgressSink| {
+                            self.keep_both(
+                                sync,
+                                client,
+                                sink,
+                                local_item,
+                                &remote_item,
+                                local_is_older,
+                            )
+                        }),
+                        |stats| stats.conflicted += 1,
+                    )),
+                }
             } else {
                 // Local Orphan
                 match self.action_local.as_str() {
-                    "upload" => {
-                        emit_status(py, &callback_obj, &format!("Uploading: {}", rel_path))?;
-                        if !self.dry_run {
-                            sync.upload_file(client, &local_item.abs_path_or_id, rel_path)?;
-                        }
-                        stats.uploaded += 1;
-                    }
+                    "upload" => queue.push((
+                        Box::new(move |sink: &dyn ProgressSink| {
+                            sink.on_status(&format!("Uploading: {}", rel_path));
+                            self.upload(
+                                sync,
+                                client,
+                                sink,
+                                &local_item.abs_path_or_id,
+                                rel_path,
+                                None,
+                            )
+                        }),
+                        |stats| stats.uploaded += 1,
+                    )),
                     "delete_local" => {
-                        emit_status(py, &callback_obj, &format!("Deleting Local: {}", rel_path))?;
+                        sink.on_status(&format!("Deleting Local: {}", rel_path));
                         if !self.dry_run {
                             std::fs::remove_file(&local_item.abs_path_or_id)?;
                         }
@@ -173,16 +625,12 @@ impl SyncRunner {
         };
 
         for rel_path in sorted_remote_keys {
-            self.check_stop(py, &callback_obj)?;
+            self.check_stop(sink)?;
             let remote_item = remote_items.get(&rel_path).unwrap();
 
             if remote_item.is_folder {
                 if self.action_remote == "delete_remote" {
-                    emit_status(
-                        py,
-                        &callback_obj,
-                        &format!("Deleting Remote Folder: {}", rel_path),
-                    )?;
+                    sink.on_status(&format!("Deleting Remote Folder: {}", rel_path));
                     if !self.dry_run {
                         sync.delete_remote(client, &remote_item.abs_path_or_id, &rel_path)?;
                     }
@@ -192,23 +640,15 @@ impl SyncRunner {
             }
 
             match self.action_remote.as_str() {
-                "download" => {
-                    emit_status(py, &callback_obj, &format!("Downloading: {}", rel_path))?;
-                    if !self.dry_run {
-                        let local_dest = Path::new(&self.local_path).join(&rel_path);
-                        if let Some(parent) = local_dest.parent() {
-                            std::fs::create_dir_all(parent)?;
-                        }
-                        sync.download_file(
-                            client,
-                            &remote_item.abs_path_or_id,
-                            local_dest.to_str().unwrap(),
-                        )?;
-                    }
-                    stats.downloaded += 1;
-                }
+                "download" => queue.push((
+                    Box::new(move |sink: &dyn ProgressSink| {
+                        sink.on_status(&format!("Downloading: {}", rel_path));
+                        self.download(sync, client, sink, remote_item, &rel_path)
+                    }),
+                    |stats| stats.downloaded += 1,
+                )),
                 "delete_remote" => {
-                    emit_status(py, &callback_obj, &format!("Deleting Remote: {}", rel_path))?;
+                    sink.on_status(&format!("Deleting Remote: {}", rel_path));
                     if !self.dry_run {
                         sync.delete_remote(client, &remote_item.abs_path_or_id, &rel_path)?;
                     }
@@ -218,22 +658,444 @@ impl SyncRunner {
             }
         }
 
+        let (jobs, counts): (Vec<_>, Vec<_>) = queue.into_iter().unzip();
+        let done = self.run_transfers(jobs, sink, &mut stats)?;
+        for (count, done) in counts.into_iter().zip(done) {
+            if done {
+                count(&mut stats);
+            }
+        }
+
         Ok(stats)
     }
 
-    #[cfg(feature = "python")]
-    fn get_local_files(&self) -> Result<HashMap<String, SyncItem>> {
+    /// Carries out `plan_two_way` and saves the state it leaves for the
+    /// next run. A dry run reports each step and leaves the state as is.
+    #[allow(clippy::too_many_arguments)]
+    fn sync_two_way<T: CloudSync + Sync>(
+        &self,
+        sync: &T,
+        client: &Client,
+        sink: &dyn ProgressSink,
+        local_items: &HashMap<String, SyncItem>,
+        remote_items: &HashMap<String, SyncItem>,
+        policy: ConflictPolicy,
+        stats: &mut SyncStats,
+    ) -> Result<()> {
+        let state_path = SyncState::path_for(&self.local_path, sync.name());
+        let state = SyncState::load(&state_path, &self.remote_path)?;
+
+        // An empty listing is more likely a wrong folder or a failed scan
+        // than every file deleted; mirroring it would empty the other side
+        let synced_files = state.items.values().filter(|i| !i.is_folder).count();
+        for (side, items) in [("local", local_items), ("remote", remote_items)] {
+            if synced_files > 0 && items.values().all(|i| i.is_folder) {
+                return Err(anyhow::anyhow!(
+                    "The {} folder has none of the {} files synced last time; not deleting them from the other side. Delete {:?} to sync from scratch.",
+                    side,
+                    synced_files,
+                    state_path
+                ));
+            }
+        }
+
+        let plan = plan_two_way(
+            local_items,
+            remote_items,
+            &state,
+            policy,
+            self.mtime_tolerance_secs,
+        );
+        let mut next = SyncState {
+            remote_path: self.remote_path.clone(),
+            items: HashMap::new(),
+        };
+
+        // Transfers run once the rest of the plan is done, so the folders
+        // they go into are there by then
+        let mut settled = Vec::new();
+        let mut queued = Vec::new();
+        let mut jobs: Vec<Job> = Vec::new();
+        for (rel_path, op) in &plan {
+            self.check_stop(sink)?;
+            let local = local_items.get(rel_path);
+            let remote = remote_items.get(rel_path);
+            let is_folder = local.or(remote).is_some_and(|i| i.is_folder);
+
+            let message = if self.dry_run {
+                format!("[Dry run] {}: {}", op.describe(), rel_path)
+            } else {
+                format!("{}: {}", op.describe(), rel_path)
+            };
+            let job: Job = match *op {
+                TwoWayOp::Upload | TwoWayOp::UpdateRemote => {
+                    let local = local.unwrap();
+                    let remote_id = remote.map(|r| r.abs_path_or_id.as_str());
+                    Box::new(move |sink: &dyn ProgressSink| {
+                        sink.on_status(&message);
+                        self.upload(
+                            sync,
+                            client,
+                            sink,
+                            &local.abs_path_or_id,
+                            rel_path,
+                            remote_id,
+                        )
+                    })
+                }
+                TwoWayOp::Download | TwoWayOp::UpdateLocal => {
+                    let remote = remote.unwrap();
+                    Box::new(move |sink: &dyn ProgressSink| {
+                        sink.on_status(&message);
+                        self.download(sync, client, sink, remote, rel_path)
+                    })
+                }
+                TwoWayOp::KeepBoth { local_is_older } => {
+                    let (local, remote) = (local.unwrap(), remote.unwrap());
+                    Box::new(move |sink: &dyn ProgressSink| {
+                        sink.on_status(&message);
+                        self.keep_both(sync, client, sink, local, remote, local_is_older)
+                    })
+                }
+                _ => {
+                    if *op != TwoWayOp::Unchanged {
+                        sink.on_status(&message);
+                    }
+                    let done =
+                        self.apply_in_place(sync, client, sink, *op, rel_path, local, remote)?;
+                    if *op == TwoWayOp::Unchanged && !is_folder {
+                        stats.skipped += 1;
+                    }
+                    settled.push((rel_path, *op, is_folder, done));
+                    continue;
+                }
+            };
+            jobs.push(job);
+            queued.push((rel_path, *op, is_folder));
+        }
+
+        let done = self.run_transfers(jobs, sink, stats)?;
+        settled.extend(
+            queued
+                .into_iter()
+                .zip(done)
+                .map(|((rel_path, op, is_folder), done)| (rel_path, op, is_folder, done)),
+        );
+
+        for (rel_path, op, is_folder, done) in settled {
+            let local = local_items.get(rel_path);
+            let remote = remote_items.get(rel_path);
+            if !done {
+                // A failed transfer is compared against the old state again
+                // next time, so it's retried. A folder that couldn't be
+                // deleted is forgotten, so it's created again on the other
+                // side.
+                if let (false, Some(synced)) = (is_folder, state.items.get(rel_path)) {
+                    next.items.insert(rel_path.clone(), synced.clone());
+                }
+                continue;
+            }
+            match op {
+                TwoWayOp::Unchanged => {}
+                TwoWayOp::Upload => stats.uploaded += 1,
+                TwoWayOp::Download => stats.downloaded += 1,
+                TwoWayOp::UpdateRemote | TwoWayOp::UpdateLocal => stats.updated += 1,
+                TwoWayOp::DeleteLocal => stats.deleted_local += 1,
+                TwoWayOp::DeleteRemote => stats.deleted_remote += 1,
+                TwoWayOp::CreateRemoteFolder => stats.uploaded += 1,
+                TwoWayOp::CreateLocalFolder => stats.downloaded += 1,
+                TwoWayOp::KeepBoth { .. } => stats.conflicted += 1,
+            }
+            if let Some(synced) = self.synced_after(op, rel_path, local, remote) {
+                next.items.insert(rel_path.clone(), synced);
+            }
+        }
+
+        if !self.dry_run {
+            next.save(&state_path)?;
+        }
+        Ok(())
+    }
+
+    /// Carries out an `op` of the plan that isn't a transfer; whether it
+    /// went through
+    #[allow(clippy::too_many_arguments)]
+    fn apply_in_place<T: CloudSync>(
+        &self,
+        sync: &T,
+        client: &Client,
+        sink: &dyn ProgressSink,
+        op: TwoWayOp,
+        rel_path: &str,
+        local: Option<&SyncItem>,
+        remote: Option<&SyncItem>,
+    ) -> Result<bool> {
+        let is_folder = local.or(remote).is_some_and(|i| i.is_folder);
+        match op {
+            TwoWayOp::DeleteLocal if self.dry_run => {}
+            TwoWayOp::DeleteLocal if is_folder => {
+                // Files the filter leaves alone may still be in it
+                if let Err(e) = std::fs::remove_dir(&local.unwrap().abs_path_or_id) {
+                    sink.on_error(&format!("Kept local folder {}: {}", rel_path, e));
+                    return Ok(false);
+                }
+            }
+            TwoWayOp::DeleteLocal => std::fs::remove_file(&local.unwrap().abs_path_or_id)?,
+            _ if self.dry_run => {}
+            TwoWayOp::DeleteRemote => {
+                sync.delete_remote(client, &remote.unwrap().abs_path_or_id, rel_path)?
+            }
+            TwoWayOp::CreateRemoteFolder => sync.create_remote_folder(client, rel_path)?,
+            TwoWayOp::CreateLocalFolder => {
+                std::fs::create_dir_all(Path::new(&self.local_path).join(rel_path))?
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// How `rel_path` is left on both sides once `op` is done; none when
+    /// it's gone from both. Uploads and downloads carry the mtime across.
+    fn synced_after(
+        &self,
+        op: TwoWayOp,
+        rel_path: &str,
+        local: Option<&SyncItem>,
+        remote: Option<&SyncItem>,
+    ) -> Option<SyncedItem> {
+        match op {
+            TwoWayOp::CreateRemoteFolder | TwoWayOp::CreateLocalFolder => {
+                Some(SyncedItem::folder())
+            }
+            TwoWayOp::Unchanged => Some(SyncedItem::of(local?, remote?)),
+            TwoWayOp::Upload | TwoWayOp::UpdateRemote => Some(SyncedItem::of(local?, local?)),
+            TwoWayOp::Download | TwoWayOp::UpdateLocal => {
+                let remote = remote?;
+                let local_path = Path::new(&self.local_path).join(rel_path);
+                Some(SyncedItem {
+                    local_mtime: local_mtime(&local_path),
+                    ..SyncedItem::of(remote, remote)
+                })
+            }
+            // Both names are new to both sides; the next run records them
+            TwoWayOp::KeepBoth { .. } | TwoWayOp::DeleteLocal | TwoWayOp::DeleteRemote => None,
+        }
+    }
+
+    /// Whether a transfer went through. One that still failed verification
+    /// after its retries is reported and counted instead of ending the sync.
+    fn transferred(
+        &self,
+        result: Result<()>,
+        sink: &dyn ProgressSink,
+        stats: &mut SyncStats,
+    ) -> Result<bool> {
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if e.chain().any(|cause| cause.is::<ChecksumMismatch>()) => {
+                sink.on_error(&format!("{:#}", e));
+                stats.verification_failed += 1;
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs `jobs`, `transfer_concurrency` at a time, and says which went
+    /// through. `stats` is only touched from this thread as each one ends.
+    /// Once `sink` asks to stop, transfers under way are cut short and the
+    /// rest never started.
+    fn run_transfers(
+        &self,
+        jobs: Vec<Job>,
+        sink: &dyn ProgressSink,
+        stats: &mut SyncStats,
+    ) -> Result<Vec<bool>> {
+        let mut done = vec![false; jobs.len()];
+        if self.transfer_concurrency <= 1 {
+            for (i, job) in jobs.into_iter().enumerate() {
+                self.check_stop(sink)?;
+                done[i] = self.transferred(job(sink), sink, stats)?;
+            }
+            return Ok(done);
+        }
+
+        let pooled = run_pooled(jobs, self.transfer_concurrency, sink, |i, result| {
+            done[i] = self.transferred(result, sink, stats)?;
+            Ok(())
+        });
+        // Transfers cut short fail with errors of their own
+        self.check_stop(sink)?;
+        pooled?;
+        Ok(done)
+    }
+
+    /// Uploads `local_path` as `rel_path`, replacing the remote file
+    /// `remote_id` if given. Nothing happens in a dry run.
+    fn upload<T: CloudSync>(
+        &self,
+        sync: &T,
+        client: &Client,
+        sink: &dyn ProgressSink,
+        local_path: &str,
+        rel_path: &str,
+        remote_id: Option<&str>,
+    ) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let uploader = Uploader::new(&self.retry, sink);
+        self.retry.run(
+            sink,
+            &format!("upload of {}", rel_path),
+            || match remote_id {
+                Some(id) => sync.update_file(client, &uploader, local_path, rel_path, id),
+                None => sync.upload_file(client, &uploader, local_path, rel_path),
+            },
+        )
+    }
+
+    /// Downloads `remote_item` to `rel_path` under the local folder, giving
+    /// it the remote mtime so the next run sees both sides as the same. It
+    /// only replaces the local file once it has checked out. Nothing happens
+    /// in a dry run.
+    fn download<T: CloudSync>(
+        &self,
+        sync: &T,
+        client: &Client,
+        sink: &dyn ProgressSink,
+        remote_item: &SyncItem,
+        rel_path: &str,
+    ) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let local_dest = Path::new(&self.local_path).join(rel_path);
+        if let Some(parent) = local_dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut part = local_dest.clone().into_os_string();
+        part.push(".part");
+        let part = PathBuf::from(part);
+
+        self.retry
+            .run(sink, &format!("download of {}", rel_path), || {
+                sync.download_file(client, &remote_item.abs_path_or_id, part.to_str().unwrap())?;
+                if let (Some(kind), Some(expected)) = (sync.hash_kind(), &remote_item.checksum) {
+                    let checked = std::fs::read(&part)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|data| verify(kind, &data, expected, rel_path));
+                    if checked.is_err() {
+                        let _ = std::fs::remove_file(&part);
+                    }
+                    checked?;
+                }
+                Ok(())
+            })?;
+        std::fs::rename(&part, &local_dest)
+            .with_context(|| format!("Failed to move {:?} into place", local_dest))?;
+
+        if remote_item.mtime > 0 {
+            let mtime = UNIX_EPOCH + Duration::from_secs(remote_item.mtime as u64);
+            std::fs::File::options()
+                .write(true)
+                .open(&local_dest)
+                .and_then(|f| f.set_modified(mtime))
+                .with_context(|| format!("Failed to set the mtime of {:?}", local_dest))?;
+        }
+        Ok(())
+    }
+
+    /// Settles a conflict by keeping both versions on both sides: the newer
+    /// one under the file's name and the older under its conflict name
+    fn keep_both<T: CloudSync>(
+        &self,
+        sync: &T,
+        client: &Client,
+        sink: &dyn ProgressSink,
+        local_item: &SyncItem,
+        remote_item: &SyncItem,
+        local_is_older: bool,
+    ) -> Result<()> {
+        let rel_path = &local_item.rel_path;
+        let older_mtime = if local_is_older {
+            local_item.mtime
+        } else {
+            remote_item.mtime
+        };
+        let (conflict_rel, conflict_path) = self.free_conflict_path(rel_path, older_mtime);
+        sink.on_status(&format!(
+            "Conflict: {} (keeping the older copy as {})",
+            rel_path, conflict_rel
+        ));
+        if self.dry_run {
+            return Ok(());
+        }
+
+        if local_is_older {
+            std::fs::rename(&local_item.abs_path_or_id, &conflict_path)?;
+            self.download(sync, client, sink, remote_item, rel_path)?;
+        } else {
+            self.download(sync, client, sink, remote_item, &conflict_rel)?;
+            self.upload(
+                sync,
+                client,
+                sink,
+                &local_item.abs_path_or_id,
+                rel_path,
+                Some(&remote_item.abs_path_or_id),
+            )?;
+        }
+        self.upload(
+            sync,
+            client,
+            sink,
+            &conflict_path.to_string_lossy(),
+            &conflict_rel,
+            None,
+        )
+    }
+
+    /// The first conflict name for `rel_path` not already taken locally
+    fn free_conflict_path(&self, rel_path: &str, mtime: i64) -> (String, PathBuf) {
+        let mut n = 1;
+        loop {
+            let rel = conflict_name(rel_path, mtime, n);
+            let path = Path::new(&self.local_path).join(&rel);
+            if !path.exists() {
+                return (rel, path);
+            }
+            n += 1;
+        }
+    }
+
+    /// The files and folders under `local_path`, and how many `filter`
+    /// left out; a folder left out counts once and isn't walked
+    fn get_local_files(&self, filter: &SyncFilter) -> Result<(HashMap<String, SyncItem>, u32)> {
         let mut items = HashMap::new();
+        let mut excluded = 0;
         let base_path = Path::new(&self.local_path);
+        let rel_path_of = |path: &Path| {
+            path.strip_prefix(base_path)
+                .map(|rel| rel.to_string_lossy().replace('\\', "/"))
+        };
 
-        for entry in walkdir::WalkDir::new(base_path) {
+        let walk = walkdir::WalkDir::new(base_path)
+            .into_iter()
+            .filter_entry(|entry| match rel_path_of(entry.path()) {
+                Ok(rel_path) if rel_path == STATE_DIR => false,
+                Ok(rel_path) if !rel_path.is_empty() => {
+                    let keep = !filter.matches(&rel_path, entry.file_type().is_dir());
+                    if !keep {
+                        excluded += 1;
+                    }
+                    keep
+                }
+                _ => true,
+            });
+        for entry in walk {
             let entry = entry?;
-            let rel_path = entry
-                .path()
-                .strip_prefix(base_path)?
-                .to_string_lossy()
-                .to_string()
-                .replace('\\', "/");
+            let rel_path = rel_path_of(entry.path())?;
             if rel_path.is_empty() {
                 continue;
             }
@@ -246,34 +1108,27 @@ impl SyncRunner {
                     abs_path_or_id: entry.path().to_string_lossy().to_string(),
                     mtime: metadata
                         .modified()
-                        .unwrap_or(std::time::SystemTime::UNIX_EPOCH)
-                        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                        .unwrap_or(UNIX_EPOCH)
+                        .duration_since(UNIX_EPOCH)
                         .unwrap()
                         .as_secs() as i64,
                     is_folder: metadata.is_dir(),
+                    size: if metadata.is_dir() { 0 } else { metadata.len() },
+                    checksum: None,
                 },
             );
         }
-        Ok(items)
+        Ok((items, excluded))
     }
 
-    #[cfg(feature = "python")]
-    fn check_stop(&self, py: Python<'_>, callback_obj: &Py<PyAny>) -> Result<()> {
-        if let Ok(is_running) = callback_obj.getattr(py, "_is_running") {
-            if !is_running.extract::<bool>(py)? {
-                return Err(anyhow::anyhow!("Synchronization manually interrupted."));
-            }
+    fn check_stop(&self, sink: &dyn ProgressSink) -> Result<()> {
+        if sink.is_cancelled() {
+            return Err(anyhow::anyhow!("Synchronization manually interrupted."));
         }
         Ok(())
     }
 }
 
-#[cfg(feature = "python")]
-fn emit_status(py: Python<'_>, obj: &Py<PyAny>, msg: &str) -> PyResult<()> {
-    obj.call_method1(py, "on_status_emitted", (msg,))?;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -286,7 +1141,11 @@ mod tests {
             "remote_path": "/remote",
             "action_local": "delete",
             "action_remote": "delete",
-            "dry_run": true
+            "dry_run": true,
+            "exclude_patterns": ["*.part", "cache/**"],
+            "include_extensions": ["jpg", 3],
+            "skip_hidden": true,
+            "transfer_concurrency": 8
         });
         let runner = SyncRunner::new(&config);
         assert_eq!(runner.local_path, "/local");
@@ -294,6 +1153,10 @@ mod tests {
         assert_eq!(runner.action_local, "delete");
         assert_eq!(runner.action_remote, "delete");
         assert_eq!(runner.dry_run, true);
+        assert_eq!(runner.exclude_patterns, vec!["*.part", "cache/**"]);
+        assert_eq!(runner.include_extensions, vec!["jpg"]);
+        assert!(runner.skip_hidden);
+        assert_eq!(runner.transfer_concurrency, 8);
     }
 
     #[test]
@@ -305,6 +1168,12 @@ mod tests {
         assert_eq!(runner.action_local, "upload");
         assert_eq!(runner.action_remote, "download");
         assert_eq!(runner.dry_run, false);
+        assert_eq!(runner.conflict_policy, "newer_wins");
+        assert_eq!(runner.mode, "one_way");
+        assert_eq!(runner.mtime_tolerance_secs, 2);
+        assert!(runner.exclude_patterns.is_empty());
+        assert!(!runner.skip_hidden);
+        assert_eq!(runner.transfer_concurrency, 1);
     }
 
     #[test]
@@ -314,6 +1183,8 @@ mod tests {
             abs_path_or_id: "id_1".to_string(),
             mtime: 100,
             is_folder: false,
+            size: 5,
+            checksum: None,
         };
         let serialized = serde_json::to_string(&item).unwrap();
         assert!(serialized.contains("foo.txt"));
@@ -322,5 +1193,203 @@ mod tests {
         let deserialized: SyncItem = serde_json::from_str(&serialized).unwrap();
         assert_eq!(deserialized.rel_path, "foo.txt");
         assert_eq!(deserialized.mtime, 100);
+        assert_eq!(deserialized.size, 5);
+
+        // Listings saved before sizes were tracked
+        let old: SyncItem = serde_json::from_str(
+            r#"{"rel_path": "a", "abs_path_or_id": "a", "mtime": 1, "is_folder": false}"#,
+        )
+        .unwrap();
+        assert_eq!(old.size, 0);
+    }
+
+    fn item(mtime: i64, size: u64) -> SyncItem {
+        SyncItem {
+            rel_path: "a.jpg".to_string(),
+            abs_path_or_id: "a.jpg".to_string(),
+            mtime,
+            is_folder: false,
+            size,
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        use ConflictPolicy as P;
+        use Resolution as R;
+
+        // Within the tolerance and the same size: nothing to do
+        assert_eq!(
+            resolve(&item(1000, 5), &item(1002, 5), P::NewerWins, 2),
+            R::Unchanged
+        );
+        assert_eq!(
+            resolve(&item(1000, 5), &item(1003, 5), P::NewerWins, 2),
+            R::Download
+        );
+        assert_eq!(
+            resolve(&item(1100, 5), &item(1000, 5), P::NewerWins, 2),
+            R::Upload
+        );
+        assert_eq!(
+            resolve(&item(1000, 5), &item(1500, 5), P::LocalWins, 2),
+            R::Upload
+        );
+        assert_eq!(
+            resolve(&item(1500, 5), &item(1000, 5), P::RemoteWins, 2),
+            R::Download
+        );
+
+        // Sizes differ but neither side is newer
+        assert_eq!(
+            resolve(&item(1000, 5), &item(1001, 6), P::NewerWins, 2),
+            R::KeepBoth {
+                local_is_older: true
+            }
+        );
+        assert_eq!(
+            resolve(&item(1500, 5), &item(1000, 6), P::KeepBoth, 2),
+            R::KeepBoth {
+                local_is_older: false
+            }
+        );
+        assert_eq!(
+            resolve(&item(1000, 5), &item(1000, 5), P::KeepBoth, 2),
+            R::Unchanged
+        );
+
+        assert!(ConflictPolicy::parse("newest").is_err());
+    }
+
+    fn listing(items: &[(&str, i64, u64)]) -> HashMap<String, SyncItem> {
+        items
+            .iter()
+            .map(|&(rel_path, mtime, size)| {
+                let item = SyncItem {
+                    rel_path: rel_path.to_string(),
+                    abs_path_or_id: rel_path.to_string(),
+                    mtime,
+                    // Sizes of 0 stand for folders here
+                    is_folder: size == 0,
+                    size,
+                    checksum: None,
+                };
+                (rel_path.to_string(), item)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_two_way() {
+        use TwoWayOp as Op;
+
+        let last = listing(&[
+            ("kept.jpg", 1000, 5),
+            ("edited_here.jpg", 1000, 5),
+            ("edited_there.jpg", 1000, 5),
+            ("edited_both.jpg", 1000, 5),
+            ("deleted_here.jpg", 1000, 5),
+            ("deleted_there.jpg", 1000, 5),
+            ("edited_here_deleted_there.jpg", 1000, 5),
+            ("old", 0, 0),
+            ("old/a.jpg", 1000, 5),
+            ("trips", 0, 0),
+            ("trips/a.jpg", 1000, 5),
+        ]);
+        let state = SyncState {
+            remote_path: String::new(),
+            items: last
+                .iter()
+                .map(|(p, item)| (p.clone(), SyncedItem::of(item, item)))
+                .collect(),
+        };
+        let local = listing(&[
+            ("kept.jpg", 1000, 5),
+            ("edited_here.jpg", 2000, 6),
+            ("edited_there.jpg", 1000, 5),
+            ("edited_both.jpg", 3000, 6),
+            ("deleted_there.jpg", 1000, 5),
+            ("edited_here_deleted_there.jpg", 2000, 5),
+            ("new_here.jpg", 1000, 5),
+            ("new_here", 0, 0),
+            ("new_here/b.jpg", 1000, 5),
+            ("old", 0, 0),
+            ("old/a.jpg", 1000, 5),
+            // The folder went remotely, but something new was put in it here
+            ("trips", 0, 0),
+            ("trips/a.jpg", 1000, 5),
+            ("trips/b.jpg", 1000, 5),
+        ]);
+        let remote = listing(&[
+            ("kept.jpg", 1001, 5),
+            ("edited_here.jpg", 1000, 5),
+            ("edited_there.jpg", 2000, 7),
+            ("edited_both.jpg", 2000, 7),
+            ("deleted_here.jpg", 1000, 5),
+            ("new_there.jpg", 1000, 5),
+        ]);
+
+        let plan = plan_two_way(&local, &remote, &state, ConflictPolicy::NewerWins, 2);
+        let plan: Vec<(&str, Op)> = plan.iter().map(|(p, op)| (p.as_str(), *op)).collect();
+        assert_eq!(
+            plan,
+            vec![
+                // Folders first, shallowest first
+                ("new_here", Op::CreateRemoteFolder),
+                ("trips", Op::CreateRemoteFolder),
+                ("deleted_here.jpg", Op::DeleteRemote),
+                ("deleted_there.jpg", Op::DeleteLocal),
+                ("edited_both.jpg", Op::UpdateRemote),
+                ("edited_here.jpg", Op::UpdateRemote),
+                ("edited_here_deleted_there.jpg", Op::Upload),
+                ("edited_there.jpg", Op::UpdateLocal),
+                ("kept.jpg", Op::Unchanged),
+                ("new_here.jpg", Op::Upload),
+                ("new_here/b.jpg", Op::Upload),
+                ("new_there.jpg", Op::Download),
+                ("old/a.jpg", Op::DeleteLocal),
+                ("trips/a.jpg", Op::DeleteLocal),
+                ("trips/b.jpg", Op::Upload),
+                // Emptied folders last
+                ("old", Op::DeleteLocal),
+            ]
+        );
+
+        // Without a state, nothing is deleted
+        let plan = plan_two_way(
+            &local,
+            &remote,
+            &SyncState::default(),
+            ConflictPolicy::NewerWins,
+            2,
+        );
+        assert!(plan
+            .iter()
+            .all(|(_, op)| !matches!(op, Op::DeleteLocal | Op::DeleteRemote)));
+
+        assert!(SyncMode::parse("both").is_err());
+    }
+
+    #[test]
+    fn test_conflict_name() {
+        // 2024-01-31 12:00:00 UTC
+        let mtime = 1_706_702_400;
+        assert_eq!(
+            conflict_name("dir/photo.jpg", mtime, 1),
+            "dir/photo.conflict-20240131.jpg"
+        );
+        assert_eq!(
+            conflict_name("a.b/photo.tar.gz", mtime, 2),
+            "a.b/photo.tar.conflict-20240131-2.gz"
+        );
+        assert_eq!(
+            conflict_name("README", mtime, 1),
+            "README.conflict-20240131"
+        );
+        assert_eq!(
+            conflict_name(".hidden", mtime, 1),
+            ".hidden.conflict-20240131"
+        );
     }
 }
@@ -0,0 +1,248 @@
+use crate::web::progress::ProgressSink;
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A file transfer, given the sink to report to
+pub type Job<'a> = Box<dyn FnOnce(&dyn ProgressSink) -> Result<()> + Send + 'a>;
+
+/// How often the calling thread asks its sink whether to stop while it
+/// waits on the pool
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a job reports, passed back to the thread that owns the sink
+enum Report {
+    Status(String),
+    Error(String),
+    ImageSaved(String),
+    Progress(String, u64, u64),
+    Done(usize, Result<()>),
+}
+
+/// Stands in for the caller's sink on a pool thread
+struct ChannelSink<'a> {
+    tx: Sender<Report>,
+    stop: &'a AtomicBool,
+}
+
+impl ProgressSink for ChannelSink<'_> {
+    fn on_status(&self, message: &str) {
+        let _ = self.tx.send(Report::Status(message.to_string()));
+    }
+
+    fn on_error(&self, message: &str) {
+        let _ = self.tx.send(Report::Error(message.to_string()));
+    }
+
+    fn on_image_saved(&self, path: &str) {
+        let _ = self.tx.send(Report::ImageSaved(path.to_string()));
+    }
+
+    fn on_transfer_progress(&self, name: &str, sent: u64, total: u64) {
+        let _ = self
+            .tx
+            .send(Report::Progress(name.to_string(), sent, total));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+}
+
+/// Runs `jobs` on up to `concurrency` threads, handing `done` each job's
+/// index and result as it finishes.
+///
+/// `sink` is only ever called from this thread, which is what the Python
+/// bindings need: jobs report to a stand-in that passes everything back
+/// over a channel. Once `sink` asks to stop or `done` fails, no more jobs
+/// are started, and those under way see their sink cancelled so they stop
+/// at their next chance. Returns when they have, with the first error from
+/// `done`; jobs that never started are never passed to `done`.
+pub fn run_pooled(
+    jobs: Vec<Job<'_>>,
+    concurrency: usize,
+    sink: &dyn ProgressSink,
+    mut done: impl FnMut(usize, Result<()>) -> Result<()>,
+) -> Result<()> {
+    let workers = concurrency.clamp(1, jobs.len().max(1));
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+    let mut first_error = None;
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let sink = ChannelSink {
+                tx: tx.clone(),
+                stop: &stop,
+            };
+            let queue = &queue;
+            scope.spawn(move || {
+                while !sink.is_cancelled() {
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, job)) = next else { break };
+                    let result = job(&sink);
+                    if sink.tx.send(Report::Done(index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // The workers hold the only senders left, so the channel closes
+        // once they've all finished
+        drop(tx);
+
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(Report::Status(message)) => sink.on_status(&message),
+                Ok(Report::Error(message)) => sink.on_error(&message),
+                Ok(Report::ImageSaved(path)) => sink.on_image_saved(&path),
+                Ok(Report::Progress(name, sent, total)) => {
+                    sink.on_transfer_progress(&name, sent, total)
+                }
+                Ok(Report::Done(index, result)) => {
+                    if first_error.is_none() {
+                        if let Err(e) = done(index, result) {
+                            first_error = Some(e);
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if !stop.load(Ordering::SeqCst) && sink.is_cancelled() {
+                stop.store(true, Ordering::SeqCst);
+            }
+        }
+    });
+
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread::{self, ThreadId};
+
+    /// Records which thread it was called on; asks to stop once it's seen
+    /// `cancel_after` statuses
+    struct ThreadSink {
+        owner: ThreadId,
+        statuses: RefCell<Vec<String>>,
+        cancel_after: usize,
+    }
+
+    impl ThreadSink {
+        fn new(cancel_after: usize) -> Self {
+            ThreadSink {
+                owner: thread::current().id(),
+                statuses: RefCell::new(Vec::new()),
+                cancel_after,
+            }
+        }
+    }
+
+    impl ProgressSink for ThreadSink {
+        fn on_status(&self, message: &str) {
+            assert_eq!(thread::current().id(), self.owner);
+            self.statuses.borrow_mut().push(message.to_string());
+        }
+        fn on_error(&self, _message: &str) {}
+        fn on_image_saved(&self, _path: &str) {}
+        fn is_cancelled(&self) -> bool {
+            assert_eq!(thread::current().id(), self.owner);
+            self.statuses.borrow().len() >= self.cancel_after
+        }
+    }
+
+    #[test]
+    fn test_run_pooled_bounds_concurrency() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let jobs: Vec<Job> = (0..12)
+            .map(|i| {
+                let (running, peak) = (&running, &peak);
+                Box::new(move |sink: &dyn ProgressSink| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    sink.on_status(&format!("job {}", i));
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    if i == 5 {
+                        anyhow::bail!("job 5 failed");
+                    }
+                    Ok(())
+                }) as Job
+            })
+            .collect();
+
+        let sink = ThreadSink::new(usize::MAX);
+        let mut finished = Vec::new();
+        run_pooled(jobs, 3, &sink, |index, result| {
+            finished.push((index, result.is_ok()));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        finished.sort();
+        let expected: Vec<_> = (0..12).map(|i| (i, i != 5)).collect();
+        assert_eq!(finished, expected);
+        assert_eq!(sink.statuses.borrow().len(), 12);
+    }
+
+    #[test]
+    fn test_run_pooled_stops_starting_jobs() {
+        let started = AtomicUsize::new(0);
+        let jobs: Vec<Job> = (0..50)
+            .map(|i| {
+                let started = &started;
+                Box::new(move |sink: &dyn ProgressSink| {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    sink.on_status(&format!("job {}", i));
+                    // Stands in for a transfer checking between chunks
+                    for _ in 0..50 {
+                        if sink.is_cancelled() {
+                            anyhow::bail!("interrupted");
+                        }
+                        thread::sleep(Duration::from_millis(2));
+                    }
+                    Ok(())
+                }) as Job
+            })
+            .collect();
+
+        // Cancelled by the sink
+        let sink = ThreadSink::new(4);
+        let mut finished = 0;
+        run_pooled(jobs, 4, &sink, |_, _| {
+            finished += 1;
+            Ok(())
+        })
+        .unwrap();
+        let started = started.load(Ordering::SeqCst);
+        assert!(started < 50, "{} jobs started", started);
+        assert_eq!(finished, started);
+
+        // Stopped by a failure
+        let jobs: Vec<Job> = (0..50)
+            .map(|_| Box::new(|_: &dyn ProgressSink| anyhow::bail!("offline")) as Job)
+            .collect();
+        let mut finished = 0;
+        let err = run_pooled(jobs, 2, &ThreadSink::new(usize::MAX), |_, result| {
+            finished += 1;
+            result
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "offline");
+        assert!(finished == 1, "{} results handled", finished);
+    }
+}
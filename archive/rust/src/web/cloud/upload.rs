@@ -0,0 +1,310 @@
+use super::checksum::HashKind;
+use crate::web::clients::retry::RetryPolicy;
+use crate::web::progress::ProgressSink;
+use anyhow::{bail, Context, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Files up to this size go up in a single request; OneDrive takes no
+/// more than 4 MiB that way
+pub const SIMPLE_UPLOAD_LIMIT: u64 = 4 * 1024 * 1024;
+
+/// Larger files go up in pieces of this size: 10 MiB, a multiple of the
+/// 320 KiB OneDrive and the 256 KiB Google Drive want pieces in
+pub const CHUNK_SIZE: usize = 10 * 1024 * 1024;
+
+/// Whether `local_path` is too big to send in a single request
+pub fn needs_session(local_path: &str) -> Result<bool> {
+    let size = std::fs::metadata(local_path)
+        .with_context(|| format!("Failed to read {}", local_path))?
+        .len();
+    Ok(size > SIMPLE_UPLOAD_LIMIT)
+}
+
+/// The `Content-Range` of `len` bytes at `offset` in a file of `total`
+pub fn content_range(offset: u64, len: usize, total: u64) -> String {
+    if len == 0 {
+        format!("bytes */{}", total)
+    } else {
+        format!("bytes {}-{}/{}", offset, offset + len as u64 - 1, total)
+    }
+}
+
+/// Where an upload session stands
+#[derive(Debug, Clone, PartialEq)]
+pub enum UploadState {
+    /// The provider holds this many bytes from the start of the file
+    Received(u64),
+    /// The provider has the whole file; its metadata as the API returns it
+    Complete(Value),
+}
+
+/// A provider's session for uploading one file a piece at a time
+pub trait UploadSession {
+    /// Sends `chunk`, which starts `offset` bytes into a file of `total`
+    fn send_chunk(
+        &mut self,
+        client: &Client,
+        offset: u64,
+        chunk: &[u8],
+        total: u64,
+    ) -> Result<UploadState>;
+    /// Asks how much of the file has arrived, after a piece failed part way
+    fn status(&mut self, client: &Client, total: u64) -> Result<UploadState>;
+}
+
+/// Streams files from disk through an `UploadSession`, retrying each
+/// piece and reporting progress as it goes
+pub struct Uploader<'a> {
+    pub retry: &'a RetryPolicy,
+    pub sink: &'a dyn ProgressSink,
+    pub chunk_size: usize,
+}
+
+impl<'a> Uploader<'a> {
+    pub fn new(retry: &'a RetryPolicy, sink: &'a dyn ProgressSink) -> Self {
+        Uploader {
+            retry,
+            sink,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    /// Sends `local_path` as `rel_path` through `session`. A piece that
+    /// fails is retried from wherever the provider says it got to. Returns
+    /// the uploaded file's metadata and the `kind` hash of what was sent.
+    pub fn send(
+        &self,
+        client: &Client,
+        session: &mut dyn UploadSession,
+        local_path: &str,
+        rel_path: &str,
+        kind: HashKind,
+    ) -> Result<(Value, String)> {
+        let mut file =
+            File::open(local_path).with_context(|| format!("Failed to open {}", local_path))?;
+        let total = file.metadata()?.len();
+        let mut hasher = kind.hasher();
+        let mut hashed = 0;
+        let mut offset = 0;
+        let mut buf = vec![0; self.chunk_size];
+        let what = format!("upload of {}", rel_path);
+
+        loop {
+            if self.sink.is_cancelled() {
+                bail!("Upload of {} interrupted", rel_path);
+            }
+            let len = (total - offset).min(self.chunk_size as u64) as usize;
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buf[..len])?;
+            let chunk = &buf[..len];
+            // A piece sent again is already in the hash
+            if offset + len as u64 > hashed {
+                hasher.update(&chunk[(hashed - offset) as usize..]);
+                hashed = offset + len as u64;
+            }
+
+            let mut failed = false;
+            let state = self.retry.run(self.sink, &what, || {
+                if failed {
+                    // Some of the piece may have arrived before it failed
+                    let state = session.status(client, total)?;
+                    if state != UploadState::Received(offset) {
+                        return Ok(state);
+                    }
+                }
+                failed = true;
+                session.send_chunk(client, offset, chunk, total)
+            })?;
+
+            match state {
+                UploadState::Complete(item) => {
+                    self.sink.on_transfer_progress(rel_path, total, total);
+                    return Ok((item, hasher.finish()));
+                }
+                UploadState::Received(n) if n > hashed => bail!(
+                    "The provider reports {} bytes of {}, more than were sent",
+                    n,
+                    rel_path
+                ),
+                UploadState::Received(n) if n >= total => bail!(
+                    "The provider has all of {} but didn't finish the upload",
+                    rel_path
+                ),
+                UploadState::Received(n) => {
+                    self.sink.on_transfer_progress(rel_path, n, total);
+                    offset = n;
+                }
+            }
+        }
+    }
+
+    /// Copies `local_path` into `dest` a piece at a time, for providers
+    /// that write files rather than post them. Progress and stopping work
+    /// as in `send`; a failed copy is retried whole by the caller.
+    pub fn copy(&self, local_path: &str, rel_path: &str, dest: &mut dyn Write) -> Result<()> {
+        let mut file =
+            File::open(local_path).with_context(|| format!("Failed to open {}", local_path))?;
+        let total = file.metadata()?.len();
+        let mut buf = vec![0; self.chunk_size];
+        let mut sent = 0;
+
+        loop {
+            if self.sink.is_cancelled() {
+                bail!("Upload of {} interrupted", rel_path);
+            }
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            dest.write_all(&buf[..len])?;
+            sent += len as u64;
+            self.sink.on_transfer_progress(rel_path, sent, total);
+        }
+        dest.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::clients::retry::HttpStatusError;
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        progress: Mutex<Vec<(u64, u64)>>,
+    }
+
+    impl ProgressSink for RecordingSink {
+        fn on_status(&self, _message: &str) {}
+        fn on_error(&self, _message: &str) {}
+        fn on_image_saved(&self, _path: &str) {}
+        fn on_transfer_progress(&self, _name: &str, sent: u64, total: u64) {
+            self.progress.lock().unwrap().push((sent, total));
+        }
+    }
+
+    /// Keeps what it's sent; the send numbered `cut_at` only gets the
+    /// first half of its piece through before failing
+    struct FakeSession {
+        received: Vec<u8>,
+        sends: Vec<u64>,
+        cut_at: usize,
+    }
+
+    impl UploadSession for FakeSession {
+        fn send_chunk(
+            &mut self,
+            client: &Client,
+            offset: u64,
+            chunk: &[u8],
+            total: u64,
+        ) -> Result<UploadState> {
+            assert_eq!(offset, self.received.len() as u64);
+            self.sends.push(offset);
+            if self.sends.len() == self.cut_at {
+                self.received.extend_from_slice(&chunk[..chunk.len() / 2]);
+                return Err(HttpStatusError {
+                    status: StatusCode::SERVICE_UNAVAILABLE,
+                    retry_after: None,
+                }
+                .into());
+            }
+            self.received.extend_from_slice(chunk);
+            self.status(client, total)
+        }
+
+        fn status(&mut self, _client: &Client, total: u64) -> Result<UploadState> {
+            let got = self.received.len() as u64;
+            Ok(if got == total {
+                UploadState::Complete(json!({ "size": got }))
+            } else {
+                UploadState::Received(got)
+            })
+        }
+    }
+
+    #[test]
+    fn test_uploader_resumes_a_cut_piece() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        let data: Vec<u8> = (0..25u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let retry = RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::ZERO,
+            ..RetryPolicy::default()
+        };
+        let sink = RecordingSink::default();
+        let uploader = Uploader {
+            retry: &retry,
+            sink: &sink,
+            chunk_size: 10,
+        };
+        let mut session = FakeSession {
+            received: Vec::new(),
+            sends: Vec::new(),
+            cut_at: 2,
+        };
+        let (item, hash) = uploader
+            .send(
+                &Client::new(),
+                &mut session,
+                path.to_str().unwrap(),
+                "video.mp4",
+                HashKind::Md5,
+            )
+            .unwrap();
+
+        assert_eq!(session.received, data);
+        // The second piece stopped half way, so the rest went from there
+        assert_eq!(session.sends, vec![0, 10, 15]);
+        assert_eq!(item, json!({ "size": 25 }));
+        assert_eq!(hash, HashKind::Md5.compute(&data));
+        assert_eq!(
+            *sink.progress.lock().unwrap(),
+            vec![(10, 25), (15, 25), (25, 25)]
+        );
+    }
+
+    #[test]
+    fn test_uploader_copies_in_pieces() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        let data: Vec<u8> = (0..25u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let retry = RetryPolicy::default();
+        let sink = RecordingSink::default();
+        let uploader = Uploader {
+            retry: &retry,
+            sink: &sink,
+            chunk_size: 10,
+        };
+        let mut copied = Vec::new();
+        uploader
+            .copy(path.to_str().unwrap(), "video.mp4", &mut copied)
+            .unwrap();
+
+        assert_eq!(copied, data);
+        assert_eq!(
+            *sink.progress.lock().unwrap(),
+            vec![(10, 25), (20, 25), (25, 25)]
+        );
+    }
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!(content_range(0, 10, 25), "bytes 0-9/25");
+        assert_eq!(content_range(20, 5, 25), "bytes 20-24/25");
+        assert_eq!(content_range(0, 0, 25), "bytes */25");
+    }
+}
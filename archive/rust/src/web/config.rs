@@ -0,0 +1,563 @@
+use crate::web::clients::cookies::CookieRecord;
+use crate::web::clients::proxy::ProxyConfig;
+use crate::web::clients::rate_limit::RateLimiter;
+use crate::web::cloud::sync::{ConflictPolicy, SyncMode};
+use anyhow::{bail, Result};
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Longest wait a `*_secs` setting can ask for; anything past it is clamped
+pub const MAX_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A `*_secs` setting as a `Duration`, clamped to `0..=MAX_WAIT` so a huge
+/// number can't panic `Duration` or overflow a deadline built from it
+pub fn wait_from_secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs.max(0.0)).map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT))
+}
+
+/// The jobs `validate_job` knows, by the names it takes
+pub const JOB_TYPES: [&str; 5] = [
+    "board_crawler",
+    "sync",
+    "image_crawler",
+    "reverse_image_search",
+    "web_requests",
+];
+
+/// What a board crawl reads from its config, for any of the boards
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardCrawlerConfig {
+    pub download_dir: Option<String>,
+    pub max_pages: Option<u64>,
+    pub limit: Option<u64>,
+    pub tags: Option<String>,
+    pub resume: Option<bool>,
+    pub max_retries: Option<u64>,
+    pub retry_base_delay: Option<f64>,
+    pub max_retry_after: Option<f64>,
+    pub url: Option<String>,
+    pub resource: Option<String>,
+    pub extra_params: Option<BTreeMap<String, String>>,
+    pub login_config: Option<LoginConfig>,
+    // The generic crawler's
+    pub endpoint: Option<String>,
+    pub name: Option<String>,
+    pub posts_path: Option<String>,
+    pub file_url_path: Option<String>,
+    pub id_path: Option<String>,
+    pub md5_path: Option<String>,
+    pub cookies_file: Option<String>,
+    pub cookies: Option<Vec<CookieRecord>>,
+    pub proxy: Option<ProxySetting>,
+    pub rate_limit: Option<RateLimitConfig>,
+    // Sent by the crawler tab, not read here
+    pub selection_mode: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub board_type: Option<String>,
+    pub screenshot_dir: Option<String>,
+    pub skip_first: Option<u64>,
+    pub skip_last: Option<u64>,
+    pub allow_unknown: Option<bool>,
+}
+
+/// What a cloud sync reads from its config, for any of the providers
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyncConfig {
+    pub local_path: Option<String>,
+    pub remote_path: Option<String>,
+    pub action_local: Option<String>,
+    pub action_remote: Option<String>,
+    pub mode: Option<String>,
+    pub dry_run: Option<bool>,
+    pub conflict_policy: Option<String>,
+    pub mtime_tolerance_secs: Option<i64>,
+    pub exclude_patterns: Option<Vec<String>>,
+    pub include_extensions: Option<Vec<String>>,
+    pub skip_hidden: Option<bool>,
+    pub transfer_concurrency: Option<u64>,
+    pub max_retries: Option<u64>,
+    pub retry_base_delay: Option<f64>,
+    pub max_retry_after: Option<f64>,
+    // Dropbox, Google Drive and OneDrive
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    // SFTP
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub private_key: Option<String>,
+    pub passphrase: Option<String>,
+    pub known_hosts: Option<String>,
+    pub proxy: Option<ProxySetting>,
+    pub allow_unknown: Option<bool>,
+}
+
+/// What the browser image crawler reads from its config
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageCrawlerConfig {
+    pub url: Option<String>,
+    pub download_dir: Option<String>,
+    pub screenshot_dir: Option<String>,
+    pub browser: Option<String>,
+    pub headless: Option<bool>,
+    pub webdriver_url: Option<String>,
+    pub auto_start_webdriver: Option<bool>,
+    pub replace_str: Option<String>,
+    pub replacements: Option<Vec<String>>,
+    pub follow_next_selector: Option<String>,
+    pub max_pages: Option<u64>,
+    pub sequence: Option<Vec<Value>>,
+    pub sequence_selector: Option<String>,
+    pub skip_first: Option<u64>,
+    pub skip_last: Option<u64>,
+    pub min_width: Option<u64>,
+    pub min_height: Option<u64>,
+    pub dedupe_by_hash: Option<bool>,
+    pub proxy: Option<ProxySetting>,
+    // Sent by the crawler tab, not read here
+    pub selection_mode: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub actions: Option<Vec<Value>>,
+    pub login_config: Option<LoginConfig>,
+    pub allow_unknown: Option<bool>,
+}
+
+/// What a reverse image search reads from its config, for one image or a
+/// batch
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReverseImageSearchConfig {
+    pub browser: Option<String>,
+    pub webdriver_url: Option<String>,
+    pub auto_start_webdriver: Option<bool>,
+    pub headless: Option<bool>,
+    pub image_path: Option<String>,
+    pub image_url: Option<String>,
+    pub search_mode: Option<String>,
+    pub engine: Option<String>,
+    pub max_results: Option<u64>,
+    pub exclude_domains: Option<Vec<String>>,
+    pub prefer_domains: Option<Vec<String>>,
+    pub results_timeout_secs: Option<f64>,
+    pub wait_for_manual_solve: Option<bool>,
+    pub manual_solve_timeout_secs: Option<f64>,
+    pub download: Option<SearchDownloadConfig>,
+    // Batches
+    pub image_paths: Option<Vec<String>>,
+    pub directory: Option<String>,
+    pub extensions: Option<Vec<String>>,
+    pub recursive: Option<bool>,
+    pub output_path: Option<String>,
+    pub csv_path: Option<String>,
+    pub delay_secs: Option<f64>,
+    pub proxy: Option<ProxySetting>,
+    pub allow_unknown: Option<bool>,
+}
+
+/// What a request sequence reads from its config. The requests and their
+/// actions are checked as the sequence runs.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebRequestsConfig {
+    pub base_url: Option<String>,
+    pub requests: Option<Vec<Value>>,
+    pub actions: Option<Vec<Value>>,
+    pub repeat: Option<RepeatSettings>,
+    pub cookies_file: Option<String>,
+    pub cookies: Option<Vec<CookieRecord>>,
+    pub proxy: Option<ProxySetting>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub allow_unknown: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginConfig {
+    pub username: Option<String>,
+    /// The password, or the API key for the boards that take one
+    pub password: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(
+    untagged,
+    deny_unknown_fields,
+    expecting = "a proxy URL, or {\"url\", \"username\", \"password\"}"
+)]
+pub enum ProxySetting {
+    Url(String),
+    Login {
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: f64,
+    pub burst: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchDownloadConfig {
+    pub enabled: Option<bool>,
+    pub top_n: Option<u64>,
+    pub dest_dir: Option<String>,
+    pub min_resolution: Option<MinResolution>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(
+    untagged,
+    deny_unknown_fields,
+    expecting = "a size such as \"1280x720\", or {\"width\", \"height\"}"
+)]
+pub enum MinResolution {
+    Text(String),
+    Size {
+        width: Option<u64>,
+        height: Option<u64>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatSettings {
+    pub count: Option<u64>,
+    pub interval_secs: Option<f64>,
+    pub persist_variables: Option<bool>,
+}
+
+/// What was wrong with a job's config, one line per field
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigErrors {
+    pub job: String,
+    pub issues: Vec<String>,
+    /// Whether any of them are fields it doesn't know
+    pub unknown: bool,
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {} config:", self.job)?;
+        for issue in &self.issues {
+            write!(f, "\n- {}", issue)?;
+        }
+        if self.unknown {
+            write!(
+                f,
+                "\nSet \"allow_unknown\": true to pass fields this version doesn't know."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// Checks `config` for the job `job_type`, one of `JOB_TYPES`. Every field
+/// that's unknown, of the wrong type or out of range is listed, instead of
+/// being left for a default to take its place. With `"allow_unknown": true`
+/// unknown fields at the top are let through.
+pub fn validate_job(job_type: &str, config: &Value) -> Result<()> {
+    if !config.is_object() {
+        bail!(
+            "The {} config should be a JSON object, not {}",
+            job_type,
+            config
+        );
+    }
+    let (mut issues, unknown) = match job_type {
+        "board_crawler" => field_issues::<BoardCrawlerConfig>(config),
+        "sync" => field_issues::<SyncConfig>(config),
+        "image_crawler" => field_issues::<ImageCrawlerConfig>(config),
+        "reverse_image_search" => field_issues::<ReverseImageSearchConfig>(config),
+        "web_requests" => field_issues::<WebRequestsConfig>(config),
+        _ => bail!(
+            "Unknown job type {}; use one of {}",
+            job_type,
+            JOB_TYPES.join(", ")
+        ),
+    };
+
+    // Values of the right type that the job would still refuse
+    let typed = |key: &str| config.get(key).is_some() && !issues.iter().any(|(f, _)| f == key);
+    let mut refused = Vec::new();
+    if typed("proxy") {
+        if let Err(e) = ProxyConfig::from_config(config) {
+            refused.push(("proxy".to_string(), format!("{:#}", e)));
+        }
+    }
+    if typed("rate_limit") {
+        if let Err(e) = RateLimiter::from_config(config) {
+            refused.push(("rate_limit".to_string(), format!("{:#}", e)));
+        }
+    }
+    if job_type == "sync" {
+        let text = |key: &str| config.get(key).and_then(|v| v.as_str());
+        if let Some(Err(e)) = text("mode").map(SyncMode::parse) {
+            refused.push(("mode".to_string(), format!("{:#}", e)));
+        }
+        if let Some(Err(e)) = text("conflict_policy").map(ConflictPolicy::parse) {
+            refused.push(("conflict_policy".to_string(), format!("{:#}", e)));
+        }
+    }
+    issues.extend(refused);
+
+    if issues.is_empty() {
+        return Ok(());
+    }
+    Err(ConfigErrors {
+        job: job_type.to_string(),
+        issues: issues
+            .into_iter()
+            .map(|(field, issue)| format!("`{}`: {}", field, issue))
+            .collect(),
+        unknown,
+    }
+    .into())
+}
+
+/// The fields of the object `config` that `T` won't take, and why, by
+/// field name, and whether any of them are unknown. Each is tried on its
+/// own so that one bad field doesn't hide the rest.
+fn field_issues<T: DeserializeOwned>(config: &Value) -> (Vec<(String, String)>, bool) {
+    let known = field_names::<T>();
+    let allow_unknown = config
+        .get("allow_unknown")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let mut issues = Vec::new();
+    let mut unknown = false;
+    for (key, value) in config.as_object().into_iter().flatten() {
+        if !known.contains(&key.as_str()) {
+            if !allow_unknown {
+                unknown = true;
+                let issue = match closest(key, known) {
+                    Some(name) => format!("unknown field; did you mean `{}`?", name),
+                    None => "unknown field".to_string(),
+                };
+                issues.push((key.clone(), issue));
+            }
+            continue;
+        }
+        let alone = Value::Object([(key.clone(), value.clone())].into_iter().collect());
+        if let Err(e) = serde_json::from_value::<T>(alone) {
+            issues.push((key.clone(), e.to_string()));
+        }
+    }
+    // serde_json keeps insertion order when a dependency turns on its
+    // preserve_order feature; the message shouldn't depend on that
+    issues.sort_by(|a, b| a.0.cmp(&b.0));
+    (issues, unknown)
+}
+
+/// The field names `T`'s derived `Deserialize` asks for
+fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut names: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut names));
+    names
+}
+
+/// A deserializer that only notes the fields a struct asks it for
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields noted"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// The name in `known` that `key` is most likely a typo of
+fn closest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|name| (edit_distance(key, name), *name))
+        .filter(|(distance, name)| *distance <= 2.max(name.len() / 4))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Levenshtein distance, with a swap of two neighbours counted as one
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn issues(job_type: &str, config: Value) -> Vec<String> {
+        match validate_job(job_type, &config) {
+            Ok(()) => Vec::new(),
+            Err(e) => e.downcast::<ConfigErrors>().unwrap().issues,
+        }
+    }
+
+    #[test]
+    fn test_validate_job_lists_every_issue() {
+        let config = json!({
+            "donwload_dir": "/tmp/x",
+            "limit": "20",
+            "max_pages": -1,
+            "login_config": {"username": "me", "api_key": "k"},
+            "rate_limit": {"requests_per_minute": 0},
+            "tags": "cat",
+        });
+        assert_eq!(
+            issues("board_crawler", config),
+            vec![
+                "`donwload_dir`: unknown field; did you mean `download_dir`?",
+                "`limit`: invalid type: string \"20\", expected u64",
+                "`login_config`: unknown field `api_key`, expected one of `username`, `password`, `url`",
+                "`max_pages`: invalid value: integer `-1`, expected u64",
+                "`rate_limit`: requests_per_minute must be above 0, not 0",
+            ]
+        );
+
+        let err = validate_job("sync", &json!({"mode": "mirror", "porrt": 22})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid sync config:\n\
+             - `porrt`: unknown field; did you mean `port`?\n\
+             - `mode`: Unknown mode 'mirror' (expected one_way or two_way)\n\
+             Set \"allow_unknown\": true to pass fields this version doesn't know."
+        );
+    }
+
+    #[test]
+    fn test_validate_job_accepts_what_the_jobs_take() {
+        // As the crawler tab sends them
+        let board = json!({
+            "download_dir": "/tmp/x", "selection_mode": "Download All (Default)",
+            "type": "board", "board_type": "danbooru", "url": "https://danbooru.donmai.us",
+            "tags": "cat", "resource": "posts", "extra_params": {"rating": "safe"},
+            "limit": 20, "max_pages": 5,
+            "login_config": {"username": null, "password": null},
+            "screenshot_dir": null, "skip_first": 0, "skip_last": 0,
+            "proxy": {"url": "socks5://vps:1080", "username": "me", "password": "pw"},
+        });
+        assert!(issues("board_crawler", board).is_empty());
+        let general = json!({
+            "download_dir": "/tmp/x", "type": "general", "url": "https://site",
+            "browser": "brave", "headless": true, "screenshot_dir": null,
+            "replace_str": null, "replacements": null, "actions": [],
+            "login_config": {"url": null, "username": null, "password": null},
+            "skip_first": 0, "skip_last": 0,
+        });
+        assert!(issues("image_crawler", general).is_empty());
+        let search = json!({
+            "image_path": "a.jpg", "engine": "all", "max_results": 5,
+            "download": {"enabled": true, "min_resolution": "1280x720"},
+        });
+        assert!(issues("reverse_image_search", search).is_empty());
+        let requests = json!({
+            "base_url": "https://httpbin.org/", "requests": [], "actions": [],
+            "repeat": {"count": 2, "interval_secs": 0.5}, "proxy": "http://10.0.0.2:3128",
+        });
+        assert!(issues("web_requests", requests).is_empty());
+
+        // Nested values of the wrong shape are named by their field
+        assert_eq!(
+            issues(
+                "reverse_image_search",
+                json!({"download": {"min_resolution": 720}})
+            ),
+            vec!["`download`: a size such as \"1280x720\", or {\"width\", \"height\"}"]
+        );
+        assert_eq!(
+            issues("sync", json!({"proxy": 8080})),
+            vec!["`proxy`: a proxy URL, or {\"url\", \"username\", \"password\"}"]
+        );
+    }
+
+    #[test]
+    fn test_validate_job_allow_unknown() {
+        let config = json!({"allow_unknown": true, "my_note": 1, "limit": "x"});
+        assert_eq!(
+            issues("board_crawler", config),
+            vec!["`limit`: invalid type: string \"x\", expected u64"]
+        );
+        assert!(validate_job(
+            "board_crawler",
+            &json!({"allow_unknown": true, "my_note": 1})
+        )
+        .is_ok());
+
+        assert!(validate_job("nope", &json!({})).is_err());
+        let err = validate_job("sync", &json!(["local_path"])).unwrap_err();
+        assert!(!err.is::<ConfigErrors>());
+    }
+
+    #[test]
+    fn test_wait_from_secs_clamps() {
+        assert_eq!(wait_from_secs(1.5), Duration::from_millis(1500));
+        assert_eq!(wait_from_secs(-3.0), Duration::ZERO);
+        assert_eq!(wait_from_secs(1e20), MAX_WAIT);
+        assert_eq!(wait_from_secs(f64::MAX), MAX_WAIT);
+    }
+
+    #[test]
+    fn test_closest_field() {
+        let known = field_names::<SyncConfig>();
+        assert!(known.contains(&"transfer_concurrency"));
+        assert_eq!(
+            closest("transfer_concurency", known),
+            Some("transfer_concurrency")
+        );
+        assert_eq!(closest("locla_path", known), Some("local_path"));
+        assert_eq!(closest("bandwidth", known), None);
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the manifest file kept in a board crawl's download directory
+pub const MANIFEST_FILE: &str = ".crawl_manifest.json";
+
+/// How far one crawler has got through one tag search
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CrawlState {
+    pub crawler: String,
+    pub tags: String,
+    /// Last page whose posts were all handled; 0 before the first
+    #[serde(default)]
+    pub last_page: u32,
+    /// Ids of the posts downloaded, or found already on disk
+    #[serde(default)]
+    pub downloaded: BTreeSet<String>,
+}
+
+/// Progress of every board crawl into a download directory, so an
+/// interrupted crawl can resume where it stopped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CrawlManifest {
+    #[serde(default)]
+    pub crawls: Vec<CrawlState>,
+}
+
+impl CrawlManifest {
+    pub fn path(download_dir: &Path) -> PathBuf {
+        download_dir.join(MANIFEST_FILE)
+    }
+
+    /// Loads the manifest at `path`, or returns an empty one if the file is missing
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read crawl manifest {:?}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse crawl manifest {:?}", path))
+    }
+
+    /// Writes the manifest via a temp file + rename so a crash never leaves
+    /// a truncated file behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path).context("Failed to move crawl manifest into place")?;
+        Ok(())
+    }
+
+    pub fn state(&self, crawler: &str, tags: &str) -> Option<&CrawlState> {
+        self.crawls
+            .iter()
+            .find(|s| s.crawler == crawler && s.tags == tags)
+    }
+
+    /// The state for `crawler` and `tags`, added if there isn't one yet
+    pub fn state_mut(&mut self, crawler: &str, tags: &str) -> &mut CrawlState {
+        let index = match self
+            .crawls
+            .iter()
+            .position(|s| s.crawler == crawler && s.tags == tags)
+        {
+            Some(index) => index,
+            None => {
+                self.crawls.push(CrawlState {
+                    crawler: crawler.to_string(),
+                    tags: tags.to_string(),
+                    ..Default::default()
+                });
+                self.crawls.len() - 1
+            }
+        };
+        &mut self.crawls[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = CrawlManifest::path(dir.path());
+        assert_eq!(
+            CrawlManifest::load(&path).unwrap(),
+            CrawlManifest::default()
+        );
+
+        let mut manifest = CrawlManifest::default();
+        let state = manifest.state_mut("Danbooru", "cat");
+        state.last_page = 3;
+        state.downloaded.insert("42".to_string());
+        manifest.state_mut("Danbooru", "dog");
+        manifest.state_mut("Danbooru", "cat").last_page = 4;
+        assert_eq!(manifest.crawls.len(), 2);
+        manifest.save(&path).unwrap();
+
+        let loaded = CrawlManifest::load(&path).unwrap();
+        assert_eq!(loaded, manifest);
+        let state = loaded.state("Danbooru", "cat").unwrap();
+        assert_eq!(state.last_page, 4);
+        assert!(state.downloaded.contains("42"));
+        assert!(loaded.state("Gelbooru", "cat").is_none());
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn test_corrupt_manifest_is_an_error() {
+        let dir = tempdir().unwrap();
+        let path = CrawlManifest::path(dir.path());
+        fs::write(&path, "{\"crawls\": [").unwrap();
+        assert!(CrawlManifest::load(&path).is_err());
+    }
+}
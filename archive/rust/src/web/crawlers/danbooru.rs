@@ -1,4 +1,5 @@
 use super::image_board_crawler::Crawler;
+use crate::web::clients::retry::check_status;
 use anyhow::{Context, Result};
 use reqwest::blocking::Client;
 use serde_json::Value;
@@ -104,7 +105,7 @@ impl Crawler for DanbooruCrawlerImpl {
             .query(&params)
             .send()
             .context("Request failed")?;
-        response.error_for_status_ref().context("Bad status")?;
+        let response = check_status(response)?;
 
         let data: Value = response.json().context("Failed to parse JSON")?;
 
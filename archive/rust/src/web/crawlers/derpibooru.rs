@@ -0,0 +1,214 @@
+use super::image_board_crawler::Crawler;
+use crate::web::clients::retry::check_status;
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+
+pub struct DerpibooruCrawlerImpl {
+    pub base_url: String,
+    pub tags: String,
+    pub limit: u32,
+    pub api_key: Option<String>,
+    pub extra_params: Vec<(String, String)>,
+}
+
+impl DerpibooruCrawlerImpl {
+    pub fn new(config: &Value) -> Self {
+        let api_key = config
+            .get("login_config")
+            .and_then(|c| c.get("password"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
+        let extra_params = config
+            .get("extra_params")
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .map(|(k, v)| (k.clone(), v.as_str().unwrap_or("").to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        DerpibooruCrawlerImpl {
+            base_url: config
+                .get("url")
+                .and_then(|v| v.as_str())
+                .unwrap_or("https://derpibooru.org")
+                .to_string(),
+            tags: config
+                .get("tags")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+            // The API refuses more than 50 a page
+            limit: (config.get("limit").and_then(|v| v.as_u64()).unwrap_or(20) as u32).min(50),
+            api_key,
+            extra_params,
+        }
+    }
+}
+
+impl Crawler for DerpibooruCrawlerImpl {
+    fn name(&self) -> &str {
+        "Derpibooru"
+    }
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn fetch_posts(&self, client: &Client, page: u32) -> Result<Vec<Value>> {
+        let endpoint = format!(
+            "{}/api/v1/json/search/images",
+            self.base_url.trim_end_matches('/')
+        );
+
+        // Searches need a query; `*` matches everything the filter allows
+        let query = if self.tags.is_empty() {
+            "*".to_string()
+        } else {
+            self.tags.clone()
+        };
+        let mut params = vec![
+            ("q".to_string(), query),
+            ("page".to_string(), page.to_string()),
+            ("per_page".to_string(), self.limit.to_string()),
+        ];
+
+        for (k, v) in &self.extra_params {
+            params.push((k.clone(), v.clone()));
+        }
+
+        if let Some(key) = &self.api_key {
+            params.push(("key".to_string(), key.clone()));
+        }
+
+        let response = client
+            .get(&endpoint)
+            .query(&params)
+            .send()
+            .context("Request failed")?;
+        let response = check_status(response)?;
+
+        let data: Value = response.json().context("Failed to parse JSON")?;
+
+        Ok(data
+            .get("images")
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// The full image, else the largest representation there is
+    fn extract_file_url(&self, post: &Value) -> Option<String> {
+        let representations = post.get("representations");
+        post.get("view_url")
+            .and_then(|v| v.as_str())
+            .or_else(|| {
+                ["full", "large", "medium"]
+                    .iter()
+                    .filter_map(|key| representations?.get(key)?.as_str())
+                    .next()
+            })
+            .map(|s| s.to_string())
+    }
+
+    /// Derpibooru hashes with SHA-512; the first 32 hex digits stand in for
+    /// the MD5 other boards name files with
+    fn extract_md5(&self, post: &Value) -> String {
+        post.get("orig_sha512_hash")
+            .or_else(|| post.get("sha512_hash"))
+            .and_then(|h| h.as_str())
+            .map(|s| s.chars().take(32).collect())
+            .unwrap_or_else(|| "none".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// One image from `/api/v1/json/search/images`, trimmed of its tag list
+    const SAMPLE_IMAGE: &str = r#"{
+        "id": 3013342,
+        "aspect_ratio": 1.4142857142857144,
+        "comment_count": 7,
+        "created_at": "2022-12-29T19:40:11Z",
+        "deletion_reason": null,
+        "description": "",
+        "downvotes": 2,
+        "duplicate_of": null,
+        "duration": 0.04,
+        "faves": 318,
+        "first_seen_at": "2022-12-29T19:40:11Z",
+        "format": "png",
+        "height": 2100,
+        "hidden_from_users": false,
+        "mime_type": "image/png",
+        "name": "winter_walk.png",
+        "orig_sha512_hash": "a1e4c0d7b2f39e68c5d1a0b7e4f2c9d8b6a3e1f0c7d5b2a9e8f6c4d3b1a0e9f7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5",
+        "processed": true,
+        "representations": {
+            "full": "https://derpicdn.net/img/view/2022/12/29/3013342.png",
+            "large": "https://derpicdn.net/img/2022/12/29/3013342/large.png",
+            "medium": "https://derpicdn.net/img/2022/12/29/3013342/medium.png",
+            "small": "https://derpicdn.net/img/2022/12/29/3013342/small.png",
+            "tall": "https://derpicdn.net/img/2022/12/29/3013342/tall.png",
+            "thumb": "https://derpicdn.net/img/2022/12/29/3013342/thumb.png",
+            "thumb_small": "https://derpicdn.net/img/2022/12/29/3013342/thumb_small.png",
+            "thumb_tiny": "https://derpicdn.net/img/2022/12/29/3013342/thumb_tiny.png"
+        },
+        "score": 316,
+        "sha512_hash": "a1e4c0d7b2f39e68c5d1a0b7e4f2c9d8b6a3e1f0c7d5b2a9e8f6c4d3b1a0e9f7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5",
+        "size": 4313520,
+        "source_url": "https://twitter.com/someartist/status/1608540000000000000",
+        "spoilered": false,
+        "tag_count": 24,
+        "thumbnails_generated": true,
+        "updated_at": "2023-01-14T02:31:57Z",
+        "uploader": "someartist",
+        "uploader_id": 521877,
+        "upvotes": 318,
+        "view_url": "https://derpicdn.net/img/view/2022/12/29/3013342__safe_artist-colon-someartist_winter_walk.png",
+        "width": 2970,
+        "wilson_score": 0.9725134583711357
+    }"#;
+
+    #[test]
+    fn test_derpibooru_config() {
+        let config = json!({
+            "tags": "safe, fluttershy",
+            "limit": 200,
+            "login_config": { "password": "key" }
+        });
+        let crawler = DerpibooruCrawlerImpl::new(&config);
+        assert_eq!(crawler.base_url, "https://derpibooru.org");
+        assert_eq!(crawler.tags, "safe, fluttershy");
+        assert_eq!(crawler.limit, 50);
+        assert_eq!(crawler.api_key.as_deref(), Some("key"));
+    }
+
+    #[test]
+    fn test_derpibooru_extract_file_url() {
+        let crawler = DerpibooruCrawlerImpl::new(&json!({}));
+        let mut image: Value = serde_json::from_str(SAMPLE_IMAGE).unwrap();
+        assert_eq!(
+            crawler.extract_file_url(&image).as_deref(),
+            Some("https://derpicdn.net/img/view/2022/12/29/3013342__safe_artist-colon-someartist_winter_walk.png")
+        );
+        assert_eq!(crawler.extract_id(&image), "3013342");
+        assert_eq!(
+            crawler.extract_md5(&image),
+            "a1e4c0d7b2f39e68c5d1a0b7e4f2c9d8"
+        );
+
+        image.as_object_mut().unwrap().remove("view_url");
+        assert_eq!(
+            crawler.extract_file_url(&image).as_deref(),
+            Some("https://derpicdn.net/img/view/2022/12/29/3013342.png")
+        );
+        image["representations"] = json!({});
+        assert_eq!(crawler.extract_file_url(&image), None);
+    }
+}
@@ -0,0 +1,342 @@
+use super::image_board_crawler::Crawler;
+use crate::web::clients::retry::check_status;
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+
+/// The values at `path` in `value`. Paths are dot-separated keys; `key[]`
+/// steps into every element of an array and `key[n]` into one, so
+/// `data.posts[].file.url` is every post's file URL. An empty path is
+/// `value` itself.
+pub fn resolve_json_path<'a>(value: &'a Value, path: &str) -> Vec<&'a Value> {
+    let mut current = vec![value];
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (key, mut indices) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        if !key.is_empty() {
+            current = current.into_iter().filter_map(|v| v.get(key)).collect();
+        }
+        while let Some(rest) = indices.strip_prefix('[') {
+            let Some(close) = rest.find(']') else {
+                return Vec::new();
+            };
+            let index = &rest[..close];
+            current = current
+                .into_iter()
+                .flat_map(|v| -> Vec<&Value> {
+                    if index.is_empty() {
+                        v.as_array().map(|a| a.iter().collect()).unwrap_or_default()
+                    } else {
+                        index
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|i| v.get(i))
+                            .into_iter()
+                            .collect()
+                    }
+                })
+                .collect();
+            indices = &rest[close + 1..];
+        }
+    }
+    current
+}
+
+/// Any booru whose JSON API fits a pattern: Moebooru, Shimmie and the other
+/// clones, configured entirely from the crawl config instead of an Impl
+/// per site
+pub struct GenericBooruCrawlerImpl {
+    pub name: String,
+    pub base_url: String,
+    /// URL with `{page}`, `{pid}` (the page counted from 0), `{limit}` and
+    /// `{tags}` filled in per request
+    pub endpoint: String,
+    pub tags: String,
+    pub limit: u32,
+    /// Where the posts are in a response; an array found there is its posts
+    pub posts_path: String,
+    /// Where a post's file URL, id and MD5 are, from the post or from the
+    /// response through `posts_path`
+    pub file_url_path: String,
+    pub id_path: String,
+    pub md5_path: String,
+    pub extra_params: Vec<(String, String)>,
+}
+
+impl GenericBooruCrawlerImpl {
+    pub fn new(config: &Value) -> Result<Self> {
+        let text = |key: &str, default: &str| {
+            config
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or(default)
+                .to_string()
+        };
+
+        let endpoint = text("endpoint", "");
+        if endpoint.is_empty() {
+            return Err(anyhow!(
+                "The generic crawler needs an endpoint, e.g. https://site/post.json?page={{page}}&limit={{limit}}&tags={{tags}}"
+            ));
+        }
+        let base_url = config
+            .get("url")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .or_else(|| {
+                let url = url::Url::parse(&endpoint).ok()?;
+                Some(format!("{}://{}", url.scheme(), url.host_str()?))
+            })
+            .unwrap_or_else(|| endpoint.clone());
+
+        let extra_params = config
+            .get("extra_params")
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .map(|(k, v)| (k.clone(), v.as_str().unwrap_or("").to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(GenericBooruCrawlerImpl {
+            name: text("name", "Generic"),
+            base_url,
+            endpoint,
+            tags: text("tags", ""),
+            limit: config.get("limit").and_then(|v| v.as_u64()).unwrap_or(20) as u32,
+            posts_path: text("posts_path", ""),
+            file_url_path: text("file_url_path", "file_url"),
+            id_path: text("id_path", "id"),
+            md5_path: text("md5_path", "md5"),
+            extra_params,
+        })
+    }
+
+    /// The endpoint for `page`, counted from 1
+    pub fn page_url(&self, page: u32) -> String {
+        let tags: String = url::form_urlencoded::byte_serialize(self.tags.as_bytes()).collect();
+        self.endpoint
+            .replace("{page}", &page.to_string())
+            .replace("{pid}", &page.saturating_sub(1).to_string())
+            .replace("{limit}", &self.limit.to_string())
+            .replace("{tags}", &tags)
+    }
+
+    /// The posts in a response
+    pub fn extract_posts(&self, data: &Value) -> Vec<Value> {
+        let mut posts = Vec::new();
+        for found in resolve_json_path(data, &self.posts_path) {
+            match found.as_array() {
+                Some(items) => posts.extend(items.iter().cloned()),
+                None => posts.push(found.clone()),
+            }
+        }
+        posts
+    }
+
+    /// The first string or number at `path` in `post`, with the posts path
+    /// taken off the front if it was written from the response
+    fn field(&self, post: &Value, path: &str) -> Option<String> {
+        let posts_path = self.posts_path.trim_end_matches("[]");
+        let relative = path
+            .strip_prefix(posts_path)
+            .filter(|rest| {
+                !posts_path.is_empty()
+                    && (rest.is_empty() || rest.starts_with('[') || rest.starts_with('.'))
+            })
+            .map(|rest| rest.trim_start_matches("[]").trim_start_matches('.'))
+            .unwrap_or(path);
+        resolve_json_path(post, relative)
+            .into_iter()
+            .find_map(|v| match v {
+                Value::String(s) if !s.is_empty() => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+    }
+}
+
+impl Crawler for GenericBooruCrawlerImpl {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn fetch_posts(&self, client: &Client, page: u32) -> Result<Vec<Value>> {
+        let response = client
+            .get(self.page_url(page))
+            .query(&self.extra_params)
+            .send()
+            .context("Request failed")?;
+        let response = check_status(response)?;
+
+        let data: Value = response.json().context("Failed to parse JSON")?;
+
+        Ok(self.extract_posts(&data))
+    }
+
+    fn extract_file_url(&self, post: &Value) -> Option<String> {
+        let url = self.field(post, &self.file_url_path)?;
+        // Some clones give URLs relative to the site
+        if url.starts_with("//") {
+            Some(format!("https:{}", url))
+        } else if url.starts_with('/') {
+            Some(format!("{}{}", self.base_url.trim_end_matches('/'), url))
+        } else {
+            Some(url)
+        }
+    }
+
+    fn extract_id(&self, post: &Value) -> String {
+        self.field(post, &self.id_path)
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    fn extract_md5(&self, post: &Value) -> String {
+        self.field(post, &self.md5_path)
+            .unwrap_or_else(|| "none".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve_json_path() {
+        let data = json!({
+            "data": {
+                "posts": [
+                    {"file": {"url": "a.png"}},
+                    {"file": {"url": "b.png"}},
+                    {"file": {}}
+                ]
+            }
+        });
+        assert_eq!(
+            resolve_json_path(&data, "data.posts[].file.url"),
+            vec![&json!("a.png"), &json!("b.png")]
+        );
+        assert_eq!(
+            resolve_json_path(&data, "data.posts[1].file.url"),
+            vec![&json!("b.png")]
+        );
+        assert_eq!(resolve_json_path(&data, "data.posts").len(), 1);
+        assert_eq!(resolve_json_path(&data, ""), vec![&data]);
+        assert!(resolve_json_path(&data, "data.missing[].url").is_empty());
+        assert_eq!(
+            resolve_json_path(&json!([[1, 2], [3]]), "[][]"),
+            vec![&json!(1), &json!(2), &json!(3)]
+        );
+    }
+
+    #[test]
+    fn test_generic_needs_endpoint() {
+        assert!(GenericBooruCrawlerImpl::new(&json!({})).is_err());
+    }
+
+    /// Moebooru (konachan, yande.re): a bare array of flat posts
+    #[test]
+    fn test_generic_moebooru_shape() {
+        let crawler = GenericBooruCrawlerImpl::new(&json!({
+            "name": "Konachan",
+            "endpoint": "https://konachan.net/post.json?page={page}&limit={limit}&tags={tags}",
+            "tags": "landscape rating:s",
+            "limit": 40
+        }))
+        .unwrap();
+        assert_eq!(crawler.base_url, "https://konachan.net");
+        assert_eq!(
+            crawler.page_url(3),
+            "https://konachan.net/post.json?page=3&limit=40&tags=landscape+rating%3As"
+        );
+
+        let response: Value = serde_json::from_str(
+            r#"[{
+                "id": 371205,
+                "tags": "clouds landscape original scenic sky",
+                "created_at": 1712094020,
+                "author": "someone",
+                "md5": "0b2e7c4d1f9a8e6b5c3d2a1f0e9d8c7b",
+                "file_size": 3318821,
+                "file_url": "https://konachan.net/image/0b2e7c4d1f9a8e6b5c3d2a1f0e9d8c7b/Konachan.com%20-%20371205.jpg",
+                "preview_url": "https://konachan.net/data/preview/0b/2e/0b2e7c4d1f9a8e6b5c3d2a1f0e9d8c7b.jpg",
+                "sample_url": "https://konachan.net/sample/0b2e7c4d1f9a8e6b5c3d2a1f0e9d8c7b/sample.jpg",
+                "rating": "s",
+                "width": 2560,
+                "height": 1440
+            }]"#,
+        )
+        .unwrap();
+        let posts = crawler.extract_posts(&response);
+        assert_eq!(posts.len(), 1);
+        assert_eq!(
+            crawler.extract_file_url(&posts[0]).as_deref(),
+            Some("https://konachan.net/image/0b2e7c4d1f9a8e6b5c3d2a1f0e9d8c7b/Konachan.com%20-%20371205.jpg")
+        );
+        assert_eq!(crawler.extract_id(&posts[0]), "371205");
+        assert_eq!(
+            crawler.extract_md5(&posts[0]),
+            "0b2e7c4d1f9a8e6b5c3d2a1f0e9d8c7b"
+        );
+    }
+
+    /// Posts wrapped in an envelope, with nested file data and relative URLs
+    #[test]
+    fn test_generic_nested_shape() {
+        let crawler = GenericBooruCrawlerImpl::new(&json!({
+            "endpoint": "https://booru.example/api/posts?offset={pid}&per_page={limit}&q={tags}",
+            "tags": "cat",
+            "posts_path": "data.posts[]",
+            "file_url_path": "data.posts[].file.url",
+            "id_path": "post_id",
+            "md5_path": "file.hashes.md5"
+        }))
+        .unwrap();
+        assert_eq!(crawler.name(), "Generic");
+        assert_eq!(
+            crawler.page_url(1),
+            "https://booru.example/api/posts?offset=0&per_page=20&q=cat"
+        );
+
+        let response = json!({
+            "status": "ok",
+            "data": {
+                "total": 2,
+                "posts": [
+                    {
+                        "post_id": "p-17",
+                        "file": {
+                            "url": "/_images/17/cat.png",
+                            "hashes": {"md5": "d41d8cd98f00b204e9800998ecf8427e"}
+                        }
+                    },
+                    {
+                        "post_id": 18,
+                        "file": {"url": "//cdn.booru.example/18.jpg"}
+                    }
+                ]
+            }
+        });
+        let posts = crawler.extract_posts(&response);
+        assert_eq!(posts.len(), 2);
+        assert_eq!(
+            crawler.extract_file_url(&posts[0]).as_deref(),
+            Some("https://booru.example/_images/17/cat.png")
+        );
+        assert_eq!(crawler.extract_id(&posts[0]), "p-17");
+        assert_eq!(
+            crawler.extract_md5(&posts[0]),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+        assert_eq!(
+            crawler.extract_file_url(&posts[1]).as_deref(),
+            Some("https://cdn.booru.example/18.jpg")
+        );
+        assert_eq!(crawler.extract_id(&posts[1]), "18");
+        assert_eq!(crawler.extract_md5(&posts[1]), "none");
+    }
+}
@@ -1,18 +1,14 @@
-#[cfg(feature = "python")]
-use anyhow::Context;
-use anyhow::Result;
-#[cfg(feature = "python")]
-use pyo3::prelude::*;
+use crate::web::clients::rate_limit::RateLimiter;
+use crate::web::clients::retry::{check_status, RetryPolicy};
+use crate::web::crawlers::crawl_manifest::CrawlManifest;
+use crate::web::progress::ProgressSink;
+use anyhow::{Context, Result};
 use reqwest::blocking::Client;
+use reqwest::header::HeaderMap;
 use serde_json::Value;
-#[cfg(feature = "python")]
 use std::fs;
-#[cfg(feature = "python")]
 use std::path::Path;
-#[cfg(feature = "python")]
-use std::thread;
-#[cfg(feature = "python")]
-use std::time::Duration;
+use std::sync::Arc;
 
 pub trait Crawler {
     fn name(&self) -> &str;
@@ -30,6 +26,10 @@ pub trait Crawler {
             })
             .unwrap_or_else(|| "unknown".to_string())
     }
+    /// Headers the board wants on file downloads, such as a login token
+    fn download_headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
     fn extract_md5(&self, post: &Value) -> String {
         post.get("md5")
             .and_then(|m| m.as_str())
@@ -38,14 +38,23 @@ pub trait Crawler {
     }
 }
 
+/// The pace without a `rate_limit` in the config: bursts of 5, then 2 a
+/// second
+const DEFAULT_REQUESTS_PER_MINUTE: f64 = 120.0;
+const DEFAULT_BURST: u32 = 5;
+
 pub struct BoardCrawler {
     pub download_dir: String,
     pub max_pages: u32,
     pub limit: u32,
     pub tags: String,
-    pub request_limit: u32,
-    pub sleep_time: f32,
-    pub current_request_count: std::cell::Cell<u32>,
+    /// Start after the last page the manifest records and skip the posts it
+    /// lists, instead of starting from page 1
+    pub resume: bool,
+    /// Retries for page fetches and downloads that fail in passing
+    pub retry: RetryPolicy,
+    /// Paces page fetches and downloads alike
+    pub rate_limit: Arc<RateLimiter>,
 }
 
 impl BoardCrawler {
@@ -69,75 +78,100 @@ impl BoardCrawler {
                 .and_then(|v| v.as_str())
                 .unwrap_or("")
                 .to_string(),
-            request_limit: 5,
-            sleep_time: 1.0,
-            current_request_count: std::cell::Cell::new(0),
+            resume: config_val
+                .get("resume")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            retry: RetryPolicy::from_config(config_val),
+            rate_limit: Arc::new(RateLimiter::new(DEFAULT_REQUESTS_PER_MINUTE, DEFAULT_BURST)),
         }
     }
 
-    #[cfg(feature = "python")]
-    pub fn check_rate_limit(&self, py: Python<'_>, callback_obj: &Py<PyAny>) -> PyResult<()> {
-        let count = self.current_request_count.get() + 1;
-        self.current_request_count.set(count);
-        if count % self.request_limit == 0 {
-            emit_status(
-                py,
-                callback_obj,
-                &format!("Rate limiting active: Waiting {}s...", self.sleep_time),
-            )?;
-            thread::sleep(Duration::from_secs_f32(self.sleep_time));
-        }
-        Ok(())
+    /// Paces the crawl with `rate_limit` instead of the default, which may
+    /// be shared with other crawls of the same site
+    pub fn with_rate_limit(mut self, rate_limit: Arc<RateLimiter>) -> Self {
+        self.rate_limit = rate_limit;
+        self
     }
 
-    #[cfg(feature = "python")]
-    pub fn run<T: Crawler>(
-        &self,
-        py: Python<'_>,
-        crawler: &T,
-        client: &Client,
-        callback_obj: Py<PyAny>,
-    ) -> PyResult<u32> {
+    pub fn check_rate_limit(&self, sink: &dyn ProgressSink) {
+        self.rate_limit.wait(sink);
+    }
+
+    /// Crawl up to `max_pages` pages of `crawler`, saving each post's file and
+    /// its JSON next to it in `download_dir`. Progress goes to the manifest
+    /// there as it's made, and with `resume` the crawl picks up from it.
+    /// Returns how many were downloaded.
+    pub fn run<T: Crawler>(&self, crawler: &T, client: &Client, sink: &dyn ProgressSink) -> u32 {
         let mut total_downloaded = 0;
-        emit_status(
-            py,
-            &callback_obj,
-            &format!(
-                "Starting {} Crawl on: {}",
-                crawler.name(),
-                crawler.base_url()
-            ),
-        )?;
+        sink.on_status(&format!(
+            "Starting {} Crawl on: {}",
+            crawler.name(),
+            crawler.base_url()
+        ));
 
         if let Err(e) = fs::create_dir_all(&self.download_dir) {
-            emit_error(
-                py,
-                &callback_obj,
-                &format!("Failed to create download directory: {}", e),
-            )?;
-            return Ok(0);
+            sink.on_error(&format!("Failed to create download directory: {}", e));
+            return 0;
         }
 
-        for page in 1..=self.max_pages {
-            // Check for cancellation
-            if let Ok(is_running) = callback_obj.getattr(py, "_is_running") {
-                if !is_running.extract::<bool>(py)? {
-                    emit_status(py, &callback_obj, "Crawl cancelled.")?;
-                    return Ok(total_downloaded);
-                }
+        let manifest_path = CrawlManifest::path(Path::new(&self.download_dir));
+        let mut manifest = CrawlManifest::load(&manifest_path).unwrap_or_else(|e| {
+            sink.on_error(&format!("Starting a new crawl manifest: {:#}", e));
+            CrawlManifest::default()
+        });
+        let save_manifest = |manifest: &CrawlManifest| {
+            if let Err(e) = manifest.save(&manifest_path) {
+                sink.on_error(&format!("Failed to save crawl manifest: {:#}", e));
             }
+        };
 
-            emit_status(py, &callback_obj, &format!("Fetching page {}...", page))?;
-            self.check_rate_limit(py, &callback_obj)?;
+        let first_page = match manifest.state(crawler.name(), &self.tags) {
+            Some(state) if self.resume => state.last_page + 1,
+            _ => 1,
+        };
+        if first_page > self.max_pages {
+            sink.on_status(&format!(
+                "All {} pages were crawled already.",
+                self.max_pages
+            ));
+        } else if first_page > 1 {
+            sink.on_status(&format!("Resuming from page {}...", first_page));
+        }
+
+        for page in first_page..=self.max_pages {
+            if sink.is_cancelled() {
+                sink.on_status("Crawl cancelled.");
+                return total_downloaded;
+            }
 
-            match crawler.fetch_posts(client, page) {
+            sink.on_status(&format!("Fetching page {}...", page));
+            self.check_rate_limit(sink);
+
+            let fetched = self.retry.run(sink, &format!("page {}", page), || {
+                crawler.fetch_posts(client, page)
+            });
+            match fetched {
                 Ok(posts) => {
                     if posts.is_empty() {
-                        emit_status(py, &callback_obj, "No posts found or end of results.")?;
+                        sink.on_status("No posts found or end of results.");
                         break;
                     }
 
+                    let mut known = 0;
                     for post in posts {
+                        if sink.is_cancelled() {
+                            sink.on_status("Crawl cancelled.");
+                            return total_downloaded;
+                        }
+
+                        let id = crawler.extract_id(&post);
+                        let state = manifest.state_mut(crawler.name(), &self.tags);
+                        if self.resume && state.downloaded.contains(&id) {
+                            known += 1;
+                            continue;
+                        }
+
                         let file_url = match crawler.extract_file_url(&post) {
                             Some(url) => url,
                             None => continue,
@@ -147,74 +181,82 @@ impl BoardCrawler {
                             .extension()
                             .and_then(|s| s.to_str())
                             .unwrap_or("jpg");
-                        let id = crawler.extract_id(&post);
                         let md5 = crawler.extract_md5(&post);
 
                         let filename = format!("{}_{}.{}", id, md5, ext);
                         let save_path = Path::new(&self.download_dir).join(&filename);
 
                         if save_path.exists() {
-                            emit_status(
-                                py,
-                                &callback_obj,
-                                &format!("Skipping existing file: {}", filename),
-                            )?;
+                            sink.on_status(&format!("Skipping existing file: {}", filename));
+                            state.downloaded.insert(id);
                             continue;
                         }
 
-                        emit_status(py, &callback_obj, &format!("Downloading: {}", filename))?;
-                        self.check_rate_limit(py, &callback_obj)?;
+                        sink.on_status(&format!("Downloading: {}", filename));
+                        self.check_rate_limit(sink);
 
-                        match download_image(client, &file_url, &save_path) {
+                        let downloaded = self.retry.run(sink, &filename, || {
+                            download_image(
+                                client,
+                                &file_url,
+                                crawler.download_headers(),
+                                &save_path,
+                            )
+                        });
+                        match downloaded {
                             Ok(_) => {
                                 total_downloaded += 1;
-                                let _ = callback_obj.call_method1(
-                                    py,
-                                    "on_image_saved",
-                                    (save_path.to_string_lossy().to_string(),),
-                                );
+                                sink.on_image_saved(&save_path.to_string_lossy());
                                 save_metadata(&save_path, &post);
-                                thread::sleep(Duration::from_millis(500));
+                                state.downloaded.insert(id);
+                                save_manifest(&manifest);
                             }
                             Err(e) => {
-                                emit_error(
-                                    py,
-                                    &callback_obj,
-                                    &format!("Download failed for {}: {}", file_url, e),
-                                )?;
+                                sink.on_error(&format!(
+                                    "Download failed for {}: {:#}",
+                                    file_url, e
+                                ));
                             }
                         }
                     }
+                    if known > 0 {
+                        sink.on_status(&format!("Skipped {} posts downloaded before.", known));
+                    }
+                    manifest.state_mut(crawler.name(), &self.tags).last_page = page;
+                    save_manifest(&manifest);
                 }
                 Err(e) => {
-                    emit_error(py, &callback_obj, &format!("Fetch failed: {}", e))?;
+                    sink.on_error(&format!("Fetch failed: {:#}", e));
                     break;
                 }
             }
-            thread::sleep(std::time::Duration::from_millis(500));
         }
 
-        emit_status(
-            py,
-            &callback_obj,
-            &format!("Crawl complete. Downloaded {} images.", total_downloaded),
-        )?;
-        Ok(total_downloaded)
+        sink.on_status(&format!(
+            "Crawl complete. Downloaded {} images.",
+            total_downloaded
+        ));
+        total_downloaded
     }
 }
 
-#[cfg(feature = "python")]
-fn download_image(client: &Client, url: &str, save_path: &Path) -> Result<()> {
-    let mut response = client.get(url).send().context("Request failed")?;
-    response.error_for_status_ref().context("Bad status")?;
+fn download_image(client: &Client, url: &str, headers: HeaderMap, save_path: &Path) -> Result<()> {
+    let response = client
+        .get(url)
+        .headers(headers)
+        .send()
+        .context("Request failed")?;
+    let mut response = check_status(response)?;
     let mut file = fs::File::create(save_path).context("Failed to create file")?;
-    response
-        .copy_to(&mut file)
-        .context("Failed to save content")?;
+    if let Err(e) = response.copy_to(&mut file) {
+        // A partial file would be skipped as already downloaded next time
+        drop(file);
+        let _ = fs::remove_file(save_path);
+        return Err(e).context("Failed to save content");
+    }
     Ok(())
 }
 
-#[cfg(feature = "python")]
 fn save_metadata(image_path: &Path, post: &Value) {
     let json_path = image_path.with_extension("json");
     if let Ok(content) = serde_json::to_string_pretty(post) {
@@ -222,18 +264,6 @@ fn save_metadata(image_path: &Path, post: &Value) {
     }
 }
 
-#[cfg(feature = "python")]
-fn emit_status(py: Python<'_>, obj: &Py<PyAny>, msg: &str) -> PyResult<()> {
-    obj.call_method1(py, "on_status_emitted", (msg,))?;
-    Ok(())
-}
-
-#[cfg(feature = "python")]
-fn emit_error(py: Python<'_>, obj: &Py<PyAny>, msg: &str) -> PyResult<()> {
-    obj.call_method1(py, "on_error_emitted", (msg,))?;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -284,13 +314,15 @@ mod tests {
             "download_dir": "/tmp/test",
             "max_pages": 10,
             "limit": 50,
-            "tags": "cat"
+            "tags": "cat",
+            "resume": true
         });
         let bc = BoardCrawler::new(&config);
         assert_eq!(bc.download_dir, "/tmp/test");
         assert_eq!(bc.max_pages, 10);
         assert_eq!(bc.limit, 50);
         assert_eq!(bc.tags, "cat");
+        assert!(bc.resume);
     }
 
     #[test]
@@ -301,5 +333,6 @@ mod tests {
         assert_eq!(bc.max_pages, 5);
         assert_eq!(bc.limit, 20);
         assert_eq!(bc.tags, "");
+        assert!(!bc.resume);
     }
 }
@@ -1,15 +1,24 @@
 #[cfg(feature = "python")]
-use anyhow::{anyhow, Result};
+use super::sequence::{run_sequence, Action, SequenceBrowser};
+#[cfg(feature = "python")]
+use super::webdriver::connect_error;
+use super::webdriver::WebDriverOptions;
+use crate::web::clients::proxy::ProxyConfig;
+#[cfg(feature = "python")]
+use crate::web::config::validate_job;
+#[cfg(feature = "python")]
+use anyhow::anyhow;
+use anyhow::{Context, Result};
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 
-#[cfg(feature = "python")]
 use base64::prelude::*;
-#[cfg(feature = "python")]
 use std::fs;
-#[cfg(feature = "python")]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 #[cfg(feature = "python")]
 use std::time::Duration;
 #[cfg(feature = "python")]
@@ -21,29 +30,362 @@ pub struct ImageCrawlerRust {
     pub download_dir: String,
     pub screenshot_dir: String,
     pub browser_name: String,
+    pub webdriver: WebDriverOptions,
+    pub filter: RefCell<DownloadFilter>,
+    /// For the browser and for downloads made outside it
+    pub proxy: Option<ProxyConfig>,
+}
+
+/// Name of the file in a download directory recording the SHA-256 of every
+/// image the crawler saved there
+pub const HASHES_FILE: &str = "hashes.json";
+
+/// Downloads left out of a page, and why
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SkipCounts {
+    pub repeated: u32,
+    pub too_small: u32,
+    pub duplicates: u32,
+}
+
+impl SkipCounts {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "Skipped {} repeated URLs, {} images below the minimum size and {} duplicates on this page",
+            self.repeated, self.too_small, self.duplicates
+        )
+    }
+}
+
+/// Keeps junk out of a crawl: images smaller than `min_width` x
+/// `min_height` (tracking pixels, avatars), URLs tried before in the same
+/// run, and with `dedupe_by_hash` content saved to the download directory
+/// by any earlier run
+#[derive(Debug, Default)]
+pub struct DownloadFilter {
+    pub min_width: u32,
+    pub min_height: u32,
+    pub dedupe_by_hash: bool,
+    hashes_path: PathBuf,
+    seen_urls: HashSet<String>,
+    /// SHA-256 of each saved image, to the file it was saved as
+    hashes: BTreeMap<String, String>,
+    skipped: SkipCounts,
+}
+
+impl DownloadFilter {
+    /// The filter for `min_width`, `min_height` and `dedupe_by_hash` in a
+    /// crawl config, with the hashes already in `download_dir` when
+    /// deduplicating. An unreadable hashes file starts over empty.
+    pub fn from_config(config: &Value, download_dir: &str) -> Self {
+        let dimension = |key: &str| config.get(key).and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        let dedupe_by_hash = config
+            .get("dedupe_by_hash")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let hashes_path = Path::new(download_dir).join(HASHES_FILE);
+        let hashes = if dedupe_by_hash {
+            fs::read_to_string(&hashes_path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_default()
+        } else {
+            BTreeMap::new()
+        };
+
+        DownloadFilter {
+            min_width: dimension("min_width"),
+            min_height: dimension("min_height"),
+            dedupe_by_hash,
+            hashes_path,
+            hashes,
+            ..Default::default()
+        }
+    }
+
+    /// Whether `url` is new this run; each URL is only tried once
+    pub fn first_visit(&mut self, url: &str) -> bool {
+        let new = self.seen_urls.insert(url.to_string());
+        if !new {
+            self.skipped.repeated += 1;
+        }
+        new
+    }
+
+    /// Whether `data` should be written: false if deduplicating and the same
+    /// content was saved before
+    pub fn is_new_content(&mut self, data: &[u8]) -> bool {
+        if self.dedupe_by_hash && self.hashes.contains_key(&content_hash(data)) {
+            self.skipped.duplicates += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Checks the image just saved at `path` from `data`: deleted if it's
+    /// below the minimum size, else kept and its hash recorded. Images whose
+    /// size can't be read are kept.
+    pub fn keep(&mut self, path: &Path, data: &[u8]) -> Result<bool> {
+        if let Some((width, height)) = image_dimensions(path) {
+            if width < self.min_width || height < self.min_height {
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+                self.skipped.too_small += 1;
+                return Ok(false);
+            }
+        }
+        if self.dedupe_by_hash {
+            let filename = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            self.hashes.insert(content_hash(data), filename);
+            self.save_hashes()?;
+        }
+        Ok(true)
+    }
+
+    /// The counts since the last call, starting them again from zero
+    pub fn take_skipped(&mut self) -> SkipCounts {
+        std::mem::take(&mut self.skipped)
+    }
+
+    /// Writes the hashes via a temp file + rename so a crash never leaves a
+    /// truncated file behind
+    fn save_hashes(&self) -> Result<()> {
+        let tmp = self.hashes_path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.hashes)?)?;
+        fs::rename(&tmp, &self.hashes_path).context("Failed to move hashes file into place")?;
+        Ok(())
+    }
+}
+
+fn content_hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Dimensions from the image header, going by the content rather than the
+/// extension, which the URL an image came from can get wrong
+fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    image::ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// The pages a crawl visits: the configured URLs, and with
+/// `follow_next_selector` each "next page" link found along the way. No page
+/// is visited twice, so a gallery whose last page links back to the first
+/// ends instead of going round forever.
+#[derive(Debug, Default)]
+pub struct PageQueue {
+    pending: VecDeque<String>,
+    visited: HashSet<String>,
+    /// Pages to visit at most, 0 for no limit
+    pub max_pages: usize,
+    current: Option<String>,
+}
+
+impl PageQueue {
+    pub fn new(urls: Vec<String>, max_pages: usize) -> Self {
+        PageQueue {
+            pending: urls.into(),
+            max_pages,
+            ..Default::default()
+        }
+    }
+
+    /// The next page not yet visited, or None once the queue is empty or
+    /// `max_pages` have been visited
+    pub fn next_page(&mut self) -> Option<String> {
+        while self.max_pages == 0 || self.visited.len() < self.max_pages {
+            let url = self.pending.pop_front()?;
+            if self.visited.insert(page_key(&url)) {
+                self.current = Some(url.clone());
+                return Some(url);
+            }
+        }
+        None
+    }
+
+    /// Pages visited so far, counting the current one
+    pub fn visited_count(&self) -> usize {
+        self.visited.len()
+    }
+
+    /// Whether pages are still waiting, e.g. after `max_pages` cut a crawl short
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// `href` as an absolute URL, resolved against the current page
+    pub fn resolve(&self, href: &str) -> String {
+        self.current
+            .as_deref()
+            .and_then(|base| url::Url::parse(base).ok())
+            .and_then(|base| base.join(href.trim()).ok())
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| href.trim().to_string())
+    }
+
+    /// Queues `url` to be visited next, unless it has been visited already
+    pub fn follow(&mut self, url: String) -> bool {
+        if self.visited.contains(&page_key(&url)) {
+            return false;
+        }
+        self.pending.push_front(url);
+        true
+    }
+}
+
+/// What makes two page URLs the same page: everything but the fragment
+fn page_key(page_url: &str) -> String {
+    match url::Url::parse(page_url) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => page_url.to_string(),
+    }
+}
+
+/// Fetches `arguments[0]` from inside the page, with its cookies, and
+/// resolves to the response as a data URL, or null. Run from the image's own
+/// tab the request is same-origin, and usually served from the cache.
+#[cfg(feature = "python")]
+const FETCH_SCRIPT: &str = r#"
+    return fetch(arguments[0], { credentials: 'include' })
+        .then((response) => (response.ok ? response.blob() : null))
+        .then((blob) => blob && new Promise((resolve) => {
+            const reader = new FileReader();
+            reader.onloadend = () => resolve(reader.result);
+            reader.onerror = () => resolve(null);
+            reader.readAsDataURL(blob);
+        }))
+        .catch(() => null);
+"#;
+
+/// Draws the page's first image onto a canvas and resolves to it as a PNG
+/// data URL, or null. Only for when fetching fails: the original encoding
+/// and metadata are lost, and cross-origin images taint the canvas.
+#[cfg(feature = "python")]
+const CANVAS_SCRIPT: &str = r#"
+    return new Promise((resolve) => {
+        const img = document.querySelector('img');
+        if (!img) {
+            resolve(null);
+            return;
+        }
+        const draw = () => {
+            const canvas = document.createElement('canvas');
+            canvas.width = img.naturalWidth;
+            canvas.height = img.naturalHeight;
+            canvas.getContext('2d').drawImage(img, 0, 0);
+            try {
+                resolve(canvas.toDataURL('image/png'));
+            } catch (e) {
+                resolve(null);
+            }
+        };
+        if (img.complete && img.naturalHeight !== 0) {
+            draw();
+        } else {
+            img.onload = draw;
+            setTimeout(() => resolve(null), 3000);
+        }
+    });
+"#;
+
+/// The MIME type and bytes of a base64 `data:` URL
+pub fn decode_data_url(data_url: &str) -> Option<(String, Vec<u8>)> {
+    let (header, payload) = data_url.strip_prefix("data:")?.split_once(',')?;
+    let mime = header.strip_suffix(";base64")?;
+    let bytes = BASE64_STANDARD.decode(payload.trim()).ok()?;
+    if bytes.is_empty() {
+        return None;
+    }
+    Some((mime.split(';').next().unwrap_or("").to_string(), bytes))
+}
+
+/// The name to save `url` as when it came back as `mime`: the last path
+/// segment, with the extension for `mime` put on when the name has none or
+/// names another format. Unknown types keep the name as it is.
+pub fn download_filename(url: &str, mime: &str) -> String {
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or("")
+        .rsplit('/')
+        .next()
+        .unwrap_or("");
+    let name = if name.is_empty() { "image" } else { name };
+    let Some(format) = image::ImageFormat::from_mime_type(mime) else {
+        return name.to_string();
+    };
+    let extensions = format.extensions_str();
+    let path = Path::new(name);
+    let current = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if extensions.iter().any(|e| e.eq_ignore_ascii_case(current)) {
+        return name.to_string();
+    }
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    format!("{}.{}", stem, extensions[0])
+}
+
+/// What came of trying to save one image
+#[cfg(feature = "python")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Download {
+    Saved,
+    /// Left out by the `DownloadFilter`; not worth trying another way
+    Skipped,
+    Failed,
 }
 
 impl ImageCrawlerRust {
     pub fn new(config: &Value) -> Self {
+        let browser_name = config
+            .get("browser")
+            .and_then(|v| v.as_str())
+            .unwrap_or("brave")
+            .to_string();
+        let download_dir = config
+            .get("download_dir")
+            .and_then(|v| v.as_str())
+            .unwrap_or("downloads")
+            .to_string();
         ImageCrawlerRust {
-            download_dir: config
-                .get("download_dir")
-                .and_then(|v| v.as_str())
-                .unwrap_or("downloads")
-                .to_string(),
+            filter: RefCell::new(DownloadFilter::from_config(config, &download_dir)),
+            download_dir,
             screenshot_dir: config
                 .get("screenshot_dir")
                 .and_then(|v| v.as_str())
                 .unwrap_or("screenshots")
                 .to_string(),
-            browser_name: config
-                .get("browser")
-                .and_then(|v| v.as_str())
-                .unwrap_or("brave")
-                .to_string(),
+            webdriver: WebDriverOptions::from_config(config, &browser_name),
+            browser_name,
+            // A bad one is reported when the crawl starts
+            proxy: ProxyConfig::from_config(config).ok().flatten(),
         }
     }
 
+    /// The client for downloads and pages fetched without the browser
+    #[cfg(feature = "python")]
+    fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36");
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.to_reqwest()?);
+        }
+        Ok(builder.build()?)
+    }
+
     #[cfg(feature = "python")]
     pub fn run(
         &self,
@@ -78,6 +420,8 @@ impl ImageCrawlerRust {
         config: Value,
         callback_obj: Py<PyAny>,
     ) -> Result<u32> {
+        // `new` leaves a bad proxy out; fail rather than crawl without it
+        ProxyConfig::from_config(&config)?;
         let headless = config
             .get("headless")
             .and_then(|v| v.as_bool())
@@ -106,7 +450,64 @@ impl ImageCrawlerRust {
             }
         }
 
-        let driver_res = if self.browser_name.to_lowercase() == "firefox" {
+        let follow_next_selector = config
+            .get("follow_next_selector")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
+        let max_pages = config
+            .get("max_pages")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0) as usize;
+        let page_count = target_urls.len();
+        let mut pages = PageQueue::new(target_urls, max_pages);
+
+        // With a sequence, each matching element gets its steps instead of
+        // the page's images being downloaded
+        let (sequence, sequence_errors) = Action::parse_sequence(config.get("sequence"));
+        for err in &sequence_errors {
+            emit_error(py, &callback_obj, err)?;
+        }
+        let sequence_selector = config
+            .get("sequence_selector")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .unwrap_or("img")
+            .to_string();
+
+        // An auto-started driver lives until this returns, however it returns
+        let (webdriver_url, driver_service) = self.webdriver.endpoint().await?;
+        if driver_service.is_some() {
+            emit_status(
+                py,
+                &callback_obj,
+                &format!(
+                    "Started {} at {}",
+                    self.webdriver.driver_binary(),
+                    webdriver_url
+                ),
+            )?;
+        }
+
+        let is_firefox = self.browser_name.to_lowercase() == "firefox";
+        if let Some(proxy) = &self.proxy {
+            let note = if is_firefox {
+                " (downloads only; set Firefox's proxy in its profile)"
+            } else if proxy.has_credentials() {
+                " (the browser can't take its credentials from a flag)"
+            } else {
+                ""
+            };
+            emit_status(
+                py,
+                &callback_obj,
+                &format!("Using proxy {}{}", proxy.display(), note),
+            )?;
+        }
+
+        let driver_res = if is_firefox {
             let mut caps = DesiredCapabilities::firefox();
             if headless {
                 caps.add_arg("--headless")?;
@@ -114,7 +515,7 @@ impl ImageCrawlerRust {
             caps.add_arg("--no-sandbox")?;
             caps.add_arg("--disable-dev-shm-usage")?;
             caps.add_arg("--user-agent=Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")?;
-            WebDriver::new("http://localhost:9515", caps).await
+            WebDriver::new(webdriver_url.as_str(), caps).await
         } else {
             let mut caps = DesiredCapabilities::chrome();
             if headless {
@@ -127,10 +528,13 @@ impl ImageCrawlerRust {
             caps.add_arg("--exclude-switches=enable-automation")?;
             caps.add_arg("--disable-automation")?;
             caps.add_arg("--disable-extensions")?;
-            WebDriver::new("http://localhost:9515", caps).await
+            if let Some(proxy) = &self.proxy {
+                caps.add_arg(&proxy.chrome_arg())?;
+            }
+            WebDriver::new(webdriver_url.as_str(), caps).await
         };
 
-        let driver = driver_res?;
+        let driver = driver_res.map_err(|e| connect_error(&webdriver_url, e))?;
 
         // Anti-Detection: Hide webdriver property
         let _ = driver
@@ -140,18 +544,22 @@ impl ImageCrawlerRust {
             )
             .await;
 
-        emit_status(
-            py,
-            &callback_obj,
-            &format!(
+        let start_message = match &follow_next_selector {
+            Some(selector) => format!(
+                "Connected to WebDriver. Starting crawl at {}, following {}...",
+                base_url, selector
+            ),
+            None => format!(
                 "Connected to WebDriver. Starting crawl of {} pages...",
-                target_urls.len()
+                page_count
             ),
-        )?;
+        };
+        emit_status(py, &callback_obj, &start_message)?;
 
         let mut total_downloaded_count = 0;
 
-        for (page_idx, target_url) in target_urls.iter().enumerate() {
+        while let Some(target_url) = pages.next_page() {
+            let target_url = &target_url;
             // Pre-navigation check: catch cancellation before starting a new page
             if let Ok(is_running) = callback_obj.getattr(py, "_is_running") {
                 if !is_running.extract::<bool>(py).unwrap_or(true) {
@@ -160,15 +568,15 @@ impl ImageCrawlerRust {
                 }
             }
 
+            let page_label = if follow_next_selector.is_some() {
+                pages.visited_count().to_string()
+            } else {
+                format!("{}/{}", pages.visited_count(), page_count)
+            };
             emit_status(
                 py,
                 &callback_obj,
-                &format!(
-                    "Navigating to page {}/{}: {}",
-                    page_idx + 1,
-                    target_urls.len(),
-                    target_url
-                ),
+                &format!("Navigating to page {}: {}", page_label, target_url),
             )?;
 
             if let Err(e) = driver.goto(target_url).await {
@@ -354,13 +762,13 @@ impl ImageCrawlerRust {
             emit_status(
                 py,
                 &callback_obj,
-                &format!("Found {} img tags on page", total_found),
+                &format!("Found {} img tags on {}", total_found, target_url),
             )?;
 
             let mut fallback_urls = Vec::new();
 
             // FALLBACK: If very few images found, try direct HTML parsing
-            if total_found < 5 {
+            if sequence.is_empty() && total_found < 5 {
                 emit_status(
                     py,
                     &callback_obj,
@@ -399,14 +807,34 @@ impl ImageCrawlerRust {
                 emit_status(
                     py,
                     &callback_obj,
-                    "No images left to process after skipping and no fallbacks found.",
+                    &format!(
+                        "No images left to process on {} after skipping and no fallbacks found.",
+                        target_url
+                    ),
                 )?;
+                if let Some(selector) = &follow_next_selector {
+                    self.follow_next_page(&driver, selector, &mut pages, py, &callback_obj)
+                        .await?;
+                }
                 continue;
             }
 
+            if !sequence.is_empty() {
+                total_downloaded_count += self
+                    .run_sequence_on_page(
+                        &driver,
+                        &sequence_selector,
+                        &sequence,
+                        (skip_first, skip_last),
+                        py,
+                        &callback_obj,
+                    )
+                    .await?;
+            }
+
             // Process Selenium images
             // Extract all image URLs first, then download them without opening tabs (to avoid anti-bot)
-            if total_found > 0 {
+            if sequence.is_empty() && total_found > 0 {
                 emit_status(py, &callback_obj, "Extracting image URLs from page...")?;
 
                 let mut image_urls = Vec::new();
@@ -435,6 +863,10 @@ impl ImageCrawlerRust {
 
                 // Download images using browser method (but from extracted URLs, not by opening tabs)
                 for (idx, url) in image_urls.iter().enumerate() {
+                    if !self.filter.borrow_mut().first_visit(url) {
+                        continue;
+                    }
+
                     // Check for cancellation
                     if let Ok(is_running) = callback_obj.getattr(py, "_is_running") {
                         if !is_running.extract::<bool>(py)? {
@@ -461,11 +893,8 @@ impl ImageCrawlerRust {
                         )
                         .await
                     {
-                        Ok(success) => {
-                            if success {
-                                total_downloaded_count += 1;
-                            }
-                        }
+                        Ok(Download::Saved) => total_downloaded_count += 1,
+                        Ok(_) => {}
                         Err(e) => {
                             emit_error(
                                 py,
@@ -504,6 +933,10 @@ impl ImageCrawlerRust {
                 }
 
                 for (idx, url) in working_urls.iter().enumerate() {
+                    if !self.filter.borrow_mut().first_visit(url) {
+                        continue;
+                    }
+
                     // Check for cancellation
                     if let Ok(is_running) = callback_obj.getattr(py, "_is_running") {
                         if !is_running.extract::<bool>(py)? {
@@ -546,11 +979,8 @@ impl ImageCrawlerRust {
                         )
                         .await
                     {
-                        Ok(success) => {
-                            if success {
-                                total_downloaded_count += 1;
-                            }
-                        }
+                        Ok(Download::Saved) => total_downloaded_count += 1,
+                        Ok(_) => {}
                         Err(e) => {
                             let error_msg = e.to_string();
                             // Check if browser session died
@@ -574,6 +1004,28 @@ impl ImageCrawlerRust {
                     }
                 }
             }
+
+            let skipped = self.filter.borrow_mut().take_skipped();
+            if !skipped.is_empty() {
+                emit_status(
+                    py,
+                    &callback_obj,
+                    &format!("{}: {}", target_url, skipped.summary()),
+                )?;
+            }
+
+            if let Some(selector) = &follow_next_selector {
+                self.follow_next_page(&driver, selector, &mut pages, py, &callback_obj)
+                    .await?;
+            }
+        }
+
+        if max_pages > 0 && pages.visited_count() >= max_pages && pages.has_pending() {
+            emit_status(
+                py,
+                &callback_obj,
+                &format!("Stopped after max_pages ({}) pages.", max_pages),
+            )?;
         }
 
         // Try to quit the driver, but ignore errors if session already ended
@@ -581,114 +1033,133 @@ impl ImageCrawlerRust {
         Ok(total_downloaded_count)
     }
 
-    #[allow(dead_code)]
+    /// Finds the "next page" element on the current page and queues where it
+    /// leads: its href, or where clicking it goes for buttons without one.
+    /// Selectors starting with `/` or `(` are XPath, anything else CSS.
     #[cfg(feature = "python")]
-    async fn execute_sequence(
+    async fn follow_next_page(
         &self,
         driver: &WebDriver,
-        element: &WebElement,
-        actions: &[Value],
-        original_handle: &WindowHandle,
+        selector: &str,
+        pages: &mut PageQueue,
         py: Python<'_>,
         callback_obj: &Py<PyAny>,
-    ) -> Result<bool> {
-        let mut current_element = element.clone();
-        let mut downloaded = false;
-        let mut scraped_data = serde_json::Map::new();
+    ) -> Result<()> {
+        let current = driver
+            .current_url()
+            .await
+            .map(|u| u.to_string())
+            .unwrap_or_default();
+        let by = if selector.starts_with('/') || selector.starts_with('(') {
+            By::XPath(selector)
+        } else {
+            By::Css(selector)
+        };
+        let element = match driver.find(by).await {
+            Ok(element) => element,
+            Err(_) => {
+                emit_status(
+                    py,
+                    callback_obj,
+                    &format!("No next page link on {}. Crawl complete.", current),
+                )?;
+                return Ok(());
+            }
+        };
 
-        for action in actions {
-            let action_type = action.get("type").and_then(|v| v.as_str()).unwrap_or("");
-            let param = action.get("param").and_then(|v| v.as_str()).unwrap_or("");
+        let href = element
+            .attr("href")
+            .await
+            .ok()
+            .flatten()
+            .filter(|h| !h.is_empty() && !h.starts_with('#') && !h.starts_with("javascript:"));
+        let next_url = match href {
+            Some(href) => pages.resolve(&href),
+            None => {
+                element.click().await?;
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                driver.current_url().await?.to_string()
+            }
+        };
 
-            match action_type {
-                "Find Parent Link (<a>)" => {
-                    current_element = current_element.find(By::XPath("./ancestor::a")).await?;
-                }
-                "Open Link in New Tab" => {
-                    if let Some(href) = current_element.attr("href").await? {
-                        driver
-                            .execute(&format!("window.open('{}', '_blank');", href), vec![])
-                            .await?;
-                        let handles = driver.windows().await?;
-                        driver
-                            .switch_to_window(handles.last().unwrap().clone())
-                            .await?;
-                        tokio::time::sleep(Duration::from_secs(2)).await;
-                    }
-                }
-                "Download Image from Element" => {
-                    let url = current_element
-                        .attr("src")
-                        .await?
-                        .or(current_element.attr("href").await?);
-                    if let Some(src) = url {
-                        // Try browser-based download first (works with Cloudflare-protected sites)
-                        match self
-                            .download_via_browser(driver, &src, &scraped_data, py, callback_obj)
-                            .await
-                        {
-                            Ok(success) => {
-                                if success {
-                                    downloaded = true;
-                                } else {
-                                    // Fallback to direct download if browser download didn't work
-                                    if self
-                                        .download_from_url(&src, &scraped_data, py, callback_obj)
-                                        .await?
-                                    {
-                                        downloaded = true;
-                                    }
-                                }
-                            }
-                            Err(_) => {
-                                // Fallback to direct download on error
-                                if self
-                                    .download_from_url(&src, &scraped_data, py, callback_obj)
-                                    .await?
-                                {
-                                    downloaded = true;
-                                }
-                            }
-                        }
-                    }
-                }
-                "Scrape Text (Saves to JSON)" => {
-                    if let Some((key, selector)) = param.split_once(':') {
-                        let text = driver.find(By::Css(selector.trim())).await?.text().await?;
-                        scraped_data.insert(
-                            key.trim().to_string(),
-                            Value::String(text.trim().to_string()),
-                        );
-                        emit_status(
-                            py,
-                            callback_obj,
-                            &format!("Extracted {}: {}", key.trim(), text.trim()),
-                        )?;
-                    }
-                }
-                "Wait X Seconds" => {
-                    if let Ok(secs) = param.parse::<u64>() {
-                        tokio::time::sleep(Duration::from_secs(secs)).await;
-                    }
-                }
-                "Close Current Tab" => {
-                    driver.close_window().await?;
-                    driver.switch_to_window(original_handle.clone()).await?;
-                }
-                "Click Element by Text" => {
-                    let el = driver.find(By::LinkText(param)).await?;
-                    el.click().await?;
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                }
-                _ => {
-                    // TODO: Implement more actions
+        if pages.follow(next_url.clone()) {
+            emit_status(
+                py,
+                callback_obj,
+                &format!("Next page from {}: {}", current, next_url),
+            )?;
+        } else {
+            emit_status(
+                py,
+                callback_obj,
+                &format!(
+                    "Next page from {} is {}, which was already crawled. Stopping.",
+                    current, next_url
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Runs `actions` for each element on the page matching `selector`,
+    /// leaving out `skip` (first, last) of them. A failing sequence is
+    /// reported and the crawl goes on with the next element.
+    #[cfg(feature = "python")]
+    async fn run_sequence_on_page(
+        &self,
+        driver: &WebDriver,
+        selector: &str,
+        actions: &[Action],
+        skip: (usize, usize),
+        py: Python<'_>,
+        callback_obj: &Py<PyAny>,
+    ) -> Result<u32> {
+        let count = driver.find_all(By::Css(selector)).await?.len();
+        let end = count.saturating_sub(skip.1);
+        emit_status(
+            py,
+            callback_obj,
+            &format!(
+                "Running {} sequence steps on {} elements matching {}",
+                actions.len(),
+                end.saturating_sub(skip.0),
+                selector
+            ),
+        )?;
+
+        let mut session = CrawlSession {
+            crawler: self,
+            driver,
+            original_handle: driver.window().await?,
+            py,
+            callback_obj,
+        };
+        let mut saved = 0;
+        for idx in skip.0..end {
+            if let Ok(is_running) = callback_obj.getattr(py, "_is_running") {
+                if !is_running.extract::<bool>(py).unwrap_or(true) {
+                    break;
                 }
             }
+
+            // Found again each time, as steps like Go Back leave the old
+            // handles stale
+            let elements = driver.find_all(By::Css(selector)).await?;
+            let Some(element) = elements.into_iter().nth(idx) else {
+                break;
+            };
+            match run_sequence(&mut session, element, actions).await {
+                Ok(n) => saved += n,
+                Err(e) => emit_error(
+                    py,
+                    callback_obj,
+                    &format!("Sequence failed on element {}/{}: {}", idx + 1, count, e),
+                )?,
+            }
         }
-        Ok(downloaded)
+        Ok(saved)
     }
 
-    #[allow(dead_code)]
     #[cfg(feature = "python")]
     async fn download_from_url(
         &self,
@@ -696,7 +1167,7 @@ impl ImageCrawlerRust {
         metadata: &serde_json::Map<String, Value>,
         py: Python<'_>,
         callback_obj: &Py<PyAny>,
-    ) -> Result<bool> {
+    ) -> Result<Download> {
         // Strip proxy URLs (i0.wp.com, i1.wp.com, etc.)
         let actual_url = if url.contains("://i") && url.contains(".wp.com/") {
             // Extract the actual URL from WordPress Photon CDN proxy
@@ -717,9 +1188,7 @@ impl ImageCrawlerRust {
         };
 
         // Use async reqwest to download (we're in an async function)
-        let client = reqwest::Client::builder()
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
-            .build()?;
+        let client = self.http_client()?;
 
         // Extract domain from URL for referer header (prevents hotlink blocking)
         let referer = if let Ok(parsed_url) = url::Url::parse(&actual_url) {
@@ -744,6 +1213,11 @@ impl ImageCrawlerRust {
             .await?;
 
         if res.status().is_success() {
+            let image_data = res.bytes().await?;
+            if !self.filter.borrow_mut().is_new_content(&image_data) {
+                return Ok(Download::Skipped);
+            }
+
             let filename = url
                 .split('/')
                 .last()
@@ -767,7 +1241,10 @@ impl ImageCrawlerRust {
                 counter += 1;
             }
 
-            fs::write(&save_path, res.bytes().await?)?;
+            fs::write(&save_path, &image_data)?;
+            if !self.filter.borrow_mut().keep(&save_path, &image_data)? {
+                return Ok(Download::Skipped);
+            }
             emit_status(
                 py,
                 callback_obj,
@@ -784,7 +1261,7 @@ impl ImageCrawlerRust {
                 let json_val = Value::Object(metadata.clone());
                 fs::write(json_path, serde_json::to_string_pretty(&json_val)?)?;
             }
-            return Ok(true);
+            return Ok(Download::Saved);
         } else {
             emit_error(
                 py,
@@ -796,7 +1273,7 @@ impl ImageCrawlerRust {
                 ),
             )?;
         }
-        Ok(false)
+        Ok(Download::Failed)
     }
 
     #[cfg(feature = "python")]
@@ -807,13 +1284,13 @@ impl ImageCrawlerRust {
         metadata: &serde_json::Map<String, Value>,
         py: Python<'_>,
         callback_obj: &Py<PyAny>,
-    ) -> Result<bool> {
+    ) -> Result<Download> {
         let actual_url = url.to_string();
 
         // If we can't get the current window the session is already dead — bail cleanly
         let original_window = match driver.window().await {
             Ok(w) => w,
-            Err(_) => return Ok(false),
+            Err(_) => return Ok(Download::Failed),
         };
 
         // Open image URL in a new tab (popup-blocker safe: ignore failure)
@@ -833,121 +1310,97 @@ impl ImageCrawlerRust {
         let windows = driver.windows().await.unwrap_or_default();
         let new_window = match windows.into_iter().find(|w| *w != original_window) {
             Some(w) => w,
-            None => return Ok(false), // popup blocked or session dead
+            None => return Ok(Download::Failed), // popup blocked or session dead
         };
 
         let _ = driver.switch_to_window(new_window.clone()).await;
         tokio::time::sleep(Duration::from_secs(2)).await;
 
-        let script = r#"
-            return new Promise((resolve) => {
-                const images = document.querySelectorAll('img');
-                if (images.length === 0) {
-                    resolve(null);
-                    return;
-                }
-
-                const img = images[0];
-                if (!img.complete || img.naturalHeight === 0) {
-                    img.onload = function() {
-                        const canvas = document.createElement('canvas');
-                        canvas.width = img.naturalWidth;
-                        canvas.height = img.naturalHeight;
-                        const ctx = canvas.getContext('2d');
-                        ctx.drawImage(img, 0, 0);
-                        try {
-                            const dataUrl = canvas.toDataURL('image/png');
-                            resolve(dataUrl.split(',')[1]);
-                        } catch(e) {
-                            resolve(null);
-                        }
-                    };
-                    setTimeout(() => resolve(null), 3000);
-                } else {
-                    const canvas = document.createElement('canvas');
-                    canvas.width = img.naturalWidth;
-                    canvas.height = img.naturalHeight;
-                    const ctx = canvas.getContext('2d');
-                    ctx.drawImage(img, 0, 0);
-                    try {
-                        const dataUrl = canvas.toDataURL('image/png');
-                        resolve(dataUrl.split(',')[1]);
-                    } catch(e) {
-                        resolve(null);
-                    }
-                }
-            });
-        "#;
-
-        // Run canvas extraction — capture result so cleanup can run unconditionally below
-        let canvas_result = driver.execute(script, vec![]).await;
+        // The original bytes if the page can fetch them, else a PNG re-encode
+        // of what it shows. Results are captured so cleanup runs either way.
+        let fetched = driver
+            .execute(FETCH_SCRIPT, vec![Value::String(actual_url.clone())])
+            .await;
+        let mut re_encoded = false;
+        let mut downloaded = fetched
+            .ok()
+            .and_then(|r| r.convert::<Option<String>>().ok().flatten())
+            .and_then(|data_url| decode_data_url(&data_url));
+        if downloaded.is_none() {
+            re_encoded = true;
+            downloaded = driver
+                .execute(CANVAS_SCRIPT, vec![])
+                .await
+                .ok()
+                .and_then(|r| r.convert::<Option<String>>().ok().flatten())
+                .and_then(|data_url| decode_data_url(&data_url));
+        }
 
         // ── Always clean up: close the image tab and restore original context ──
         let _ = driver.close_window().await;
         let _ = driver.switch_to_window(original_window).await;
         tokio::time::sleep(Duration::from_secs(1)).await;
 
-        let result = match canvas_result {
-            Ok(r) => r,
-            Err(_) => return Ok(false),
+        let Some((mime, image_data)) = downloaded else {
+            return Ok(Download::Failed);
         };
+        if !self.filter.borrow_mut().is_new_content(&image_data) {
+            return Ok(Download::Skipped);
+        }
 
-        if let Ok(base64_data) = result.convert::<String>() {
-            if !base64_data.is_empty() && base64_data != "null" {
-                if let Ok(image_data) = BASE64_STANDARD.decode(base64_data) {
-                    let filename = actual_url
-                        .split('/')
-                        .last()
-                        .and_then(|s| s.split('?').next())
-                        .unwrap_or("image.jpg");
-                    let mut save_path = PathBuf::from(&self.download_dir).join(filename);
-
-                    let mut counter = 1;
-                    while save_path.exists() {
-                        let stem = save_path
-                            .file_stem()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or("image");
-                        let ext = save_path
-                            .extension()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or("jpg");
-                        save_path = PathBuf::from(&self.download_dir)
-                            .join(format!("{} ({}).{}", stem, counter, ext));
-                        counter += 1;
-                    }
-
-                    fs::write(&save_path, image_data)?;
-                    emit_status(
-                        py,
-                        callback_obj,
-                        &format!("Saved image via browser: {}", save_path.to_string_lossy()),
-                    )?;
-                    let _ = callback_obj.call_method1(
-                        py,
-                        "on_image_saved",
-                        (save_path.to_string_lossy().to_string(),),
-                    );
-
-                    if !metadata.is_empty() {
-                        let json_path = save_path.with_extension("json");
-                        let json_val = Value::Object(metadata.clone());
-                        fs::write(json_path, serde_json::to_string_pretty(&json_val)?)?;
-                    }
+        let filename = download_filename(&actual_url, &mime);
+        let mut save_path = PathBuf::from(&self.download_dir).join(filename);
+
+        let mut counter = 1;
+        while save_path.exists() {
+            let stem = save_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("image");
+            let ext = save_path
+                .extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("jpg");
+            save_path =
+                PathBuf::from(&self.download_dir).join(format!("{} ({}).{}", stem, counter, ext));
+            counter += 1;
+        }
 
-                    return Ok(true);
-                }
-            }
+        fs::write(&save_path, &image_data)?;
+        if !self.filter.borrow_mut().keep(&save_path, &image_data)? {
+            return Ok(Download::Skipped);
+        }
+        emit_status(
+            py,
+            callback_obj,
+            &format!(
+                "Saved image via browser{}: {}",
+                if re_encoded {
+                    " (re-encoded as PNG)"
+                } else {
+                    ""
+                },
+                save_path.to_string_lossy()
+            ),
+        )?;
+        let _ = callback_obj.call_method1(
+            py,
+            "on_image_saved",
+            (save_path.to_string_lossy().to_string(),),
+        );
+
+        if !metadata.is_empty() {
+            let json_path = save_path.with_extension("json");
+            let json_val = Value::Object(metadata.clone());
+            fs::write(json_path, serde_json::to_string_pretty(&json_val)?)?;
         }
 
-        Ok(false)
+        Ok(Download::Saved)
     }
 
     #[cfg(feature = "python")]
     async fn fetch_images_via_request(&self, url: &str) -> Result<Vec<String>> {
-        let client = reqwest::Client::builder()
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
-            .build()?;
+        let client = self.http_client()?;
 
         let html_content = client.get(url).send().await?.text().await?;
         let document = scraper::Html::parse_document(&html_content);
@@ -1020,10 +1473,143 @@ pub fn run_image_crawler(
     let config: Value = serde_json::from_str(&config_json).map_err(|e| {
         PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid JSON: {}", e))
     })?;
+    validate_job("image_crawler", &config)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
     let crawler = ImageCrawlerRust::new(&config);
     crawler.run(py, config_json, callback_obj)
 }
 
+/// The crawl's WebDriver session, as sequences see it
+#[cfg(feature = "python")]
+struct CrawlSession<'a> {
+    crawler: &'a ImageCrawlerRust,
+    driver: &'a WebDriver,
+    original_handle: WindowHandle,
+    py: Python<'a>,
+    callback_obj: &'a Py<PyAny>,
+}
+
+#[cfg(feature = "python")]
+impl SequenceBrowser for CrawlSession<'_> {
+    type Element = WebElement;
+
+    async fn find(&mut self, selector: &str) -> Result<WebElement> {
+        Ok(self.driver.find(By::Css(selector)).await?)
+    }
+
+    async fn parent_link(&mut self, element: &WebElement) -> Result<WebElement> {
+        Ok(element.find(By::XPath("./ancestor::a")).await?)
+    }
+
+    async fn attribute(&mut self, element: &WebElement, name: &str) -> Result<Option<String>> {
+        Ok(element.attr(name).await?)
+    }
+
+    async fn text(&mut self, element: &WebElement) -> Result<String> {
+        Ok(element.text().await?)
+    }
+
+    async fn image_urls(&mut self, element: &WebElement) -> Result<Vec<String>> {
+        let mut urls = Vec::new();
+        for img in element.find_all(By::Tag("img")).await? {
+            if let Some(src) = img.attr("src").await? {
+                if !src.is_empty() && !src.starts_with("data:") {
+                    urls.push(src);
+                }
+            }
+        }
+        Ok(urls)
+    }
+
+    async fn click(&mut self, element: &WebElement) -> Result<()> {
+        Ok(element.click().await?)
+    }
+
+    async fn click_link_text(&mut self, text: &str) -> Result<()> {
+        Ok(self.driver.find(By::LinkText(text)).await?.click().await?)
+    }
+
+    async fn hover(&mut self, element: &WebElement) -> Result<()> {
+        Ok(self
+            .driver
+            .action_chain()
+            .move_to_element_center(element)
+            .perform()
+            .await?)
+    }
+
+    async fn scroll_into_view(&mut self, element: &WebElement) -> Result<()> {
+        Ok(element.scroll_into_view().await?)
+    }
+
+    async fn open_tab(&mut self, url: &str) -> Result<()> {
+        self.driver
+            .execute(
+                "window.open(arguments[0], '_blank');",
+                vec![Value::String(url.to_string())],
+            )
+            .await?;
+        let handles = self.driver.windows().await?;
+        let newest = handles
+            .last()
+            .ok_or_else(|| anyhow!("No tab opened for {}", url))?;
+        Ok(self.driver.switch_to_window(newest.clone()).await?)
+    }
+
+    async fn close_tab(&mut self) -> Result<()> {
+        self.driver.close_window().await?;
+        Ok(self
+            .driver
+            .switch_to_window(self.original_handle.clone())
+            .await?)
+    }
+
+    async fn go_back(&mut self) -> Result<()> {
+        Ok(self.driver.back().await?)
+    }
+
+    async fn switch_frame(&mut self, frame: Option<&WebElement>) -> Result<()> {
+        match frame {
+            Some(frame) => Ok(frame.clone().enter_frame().await?),
+            None => Ok(self.driver.enter_default_frame().await?),
+        }
+    }
+
+    async fn download(
+        &mut self,
+        url: &str,
+        metadata: &serde_json::Map<String, Value>,
+    ) -> Result<bool> {
+        if !self.crawler.filter.borrow_mut().first_visit(url) {
+            return Ok(false);
+        }
+        let (py, callback_obj) = (self.py, self.callback_obj);
+        // Try browser-based download first (works with Cloudflare-protected sites)
+        let outcome = match self
+            .crawler
+            .download_via_browser(self.driver, url, metadata, py, callback_obj)
+            .await
+        {
+            Ok(Download::Failed) | Err(_) => {
+                // Fallback to direct download if browser download didn't work
+                self.crawler
+                    .download_from_url(url, metadata, py, callback_obj)
+                    .await?
+            }
+            Ok(outcome) => outcome,
+        };
+        Ok(outcome == Download::Saved)
+    }
+
+    async fn sleep(&mut self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    fn status(&mut self, message: &str) {
+        let _ = emit_status(self.py, self.callback_obj, message);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1040,6 +1626,7 @@ mod tests {
         assert_eq!(crawler.download_dir, "/tmp/img");
         assert_eq!(crawler.screenshot_dir, "/tmp/scr");
         assert_eq!(crawler.browser_name, "firefox");
+        assert_eq!(crawler.webdriver.driver_binary(), "geckodriver");
     }
 
     #[test]
@@ -1049,5 +1636,190 @@ mod tests {
         assert_eq!(crawler.download_dir, "downloads");
         assert_eq!(crawler.screenshot_dir, "screenshots");
         assert_eq!(crawler.browser_name, "brave");
+        assert_eq!(crawler.webdriver.url, "http://localhost:9515");
+        assert!(!crawler.webdriver.auto_start);
+        assert_eq!(crawler.filter.borrow().min_width, 0);
+        assert!(!crawler.filter.borrow().dedupe_by_hash);
+    }
+
+    fn save_png(path: &Path, width: u32, height: u32, shade: u8) -> Vec<u8> {
+        let img = image::RgbImage::from_pixel(width, height, image::Rgb([shade, 0, 0]));
+        img.save_with_format(path, image::ImageFormat::Png).unwrap();
+        fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn test_download_filter_skips() {
+        let dir = tempfile::tempdir().unwrap();
+        let download_dir = dir.path().to_str().unwrap();
+        let config = json!({"min_width": 64, "min_height": 32, "dedupe_by_hash": true});
+        let mut filter = DownloadFilter::from_config(&config, download_dir);
+
+        assert!(filter.first_visit("https://example.com/a.png"));
+        assert!(!filter.first_visit("https://example.com/a.png"));
+
+        // Saved under a .jpg name, though it's a PNG
+        let small = dir.path().join("pixel.jpg");
+        let data = save_png(&small, 1, 1, 0);
+        assert!(filter.is_new_content(&data));
+        assert!(!filter.keep(&small, &data).unwrap());
+        assert!(!small.exists());
+
+        let large = dir.path().join("large.png");
+        let data = save_png(&large, 64, 48, 0);
+        assert!(filter.keep(&large, &data).unwrap());
+        assert!(large.exists());
+        assert!(!filter.is_new_content(&data));
+
+        // Not an image: its size is unknown, so it's kept
+        let text = dir.path().join("notes.jpg");
+        fs::write(&text, b"not an image").unwrap();
+        assert!(filter.keep(&text, b"not an image").unwrap());
+
+        let skipped = filter.take_skipped();
+        assert_eq!(
+            skipped,
+            SkipCounts {
+                repeated: 1,
+                too_small: 1,
+                duplicates: 1
+            }
+        );
+        assert!(filter.take_skipped().is_empty());
+
+        // The next run knows what this one saved
+        let mut next = DownloadFilter::from_config(&config, download_dir);
+        assert!(next.first_visit("https://example.com/a.png"));
+        assert!(!next.is_new_content(&data));
+        let other = save_png(&dir.path().join("other.png"), 64, 48, 255);
+        assert!(next.is_new_content(&other));
+    }
+
+    #[test]
+    fn test_download_filter_without_dedupe() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut filter = DownloadFilter::from_config(&json!({}), dir.path().to_str().unwrap());
+        let path = dir.path().join("pixel.png");
+        let data = save_png(&path, 1, 1, 0);
+        assert!(filter.keep(&path, &data).unwrap());
+        assert!(filter.is_new_content(&data));
+        assert!(!dir.path().join(HASHES_FILE).exists());
+    }
+
+    #[test]
+    fn test_page_queue_follows_until_cycle() {
+        let mut pages = PageQueue::new(vec!["https://gallery.example/set/1".to_string()], 0);
+        assert_eq!(
+            pages.next_page().as_deref(),
+            Some("https://gallery.example/set/1")
+        );
+
+        let next = pages.resolve("2?sort=new");
+        assert_eq!(next, "https://gallery.example/set/2?sort=new");
+        assert!(pages.follow(next));
+        assert_eq!(
+            pages.next_page().as_deref(),
+            Some("https://gallery.example/set/2?sort=new")
+        );
+
+        // The last page links back to the first; only the fragment differs
+        let back = pages.resolve("/set/1#top");
+        assert!(!pages.follow(back));
+        assert_eq!(pages.next_page(), None);
+        assert_eq!(pages.visited_count(), 2);
+    }
+
+    #[test]
+    fn test_page_queue_skips_repeats_and_stops_at_max_pages() {
+        let urls = ["a", "b", "a", "c", "d"]
+            .iter()
+            .map(|p| format!("https://site.example/{}", p))
+            .collect();
+        let mut pages = PageQueue::new(urls, 3);
+        let mut visited = Vec::new();
+        while let Some(url) = pages.next_page() {
+            visited.push(url);
+        }
+        assert_eq!(
+            visited,
+            vec![
+                "https://site.example/a",
+                "https://site.example/b",
+                "https://site.example/c"
+            ]
+        );
+        assert!(pages.has_pending());
+
+        // Followed links go before the rest of the list
+        let mut pages = PageQueue::new(
+            vec![
+                "https://site.example/a".to_string(),
+                "https://site.example/z".to_string(),
+            ],
+            0,
+        );
+        pages.next_page();
+        assert!(pages.follow("https://site.example/b".to_string()));
+        assert_eq!(pages.next_page().as_deref(), Some("https://site.example/b"));
+    }
+
+    #[test]
+    fn test_decode_data_url() {
+        let bytes = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x10, b'E', b'x', b'i', b'f'];
+        let data_url = format!("data:image/jpeg;base64,{}", BASE64_STANDARD.encode(&bytes));
+        assert_eq!(
+            decode_data_url(&data_url),
+            Some(("image/jpeg".to_string(), bytes))
+        );
+
+        let with_params = format!(
+            "data:image/svg+xml;charset=utf-8;base64,{}",
+            BASE64_STANDARD.encode("<svg/>")
+        );
+        assert_eq!(decode_data_url(&with_params).unwrap().0, "image/svg+xml");
+
+        // What a blocked canvas or an empty response comes back as
+        assert_eq!(decode_data_url("data:,"), None);
+        assert_eq!(decode_data_url("data:image/png;base64,"), None);
+        assert_eq!(decode_data_url("data:text/plain,hello"), None);
+        assert_eq!(decode_data_url("data:image/png;base64,@@@"), None);
+        assert_eq!(decode_data_url("null"), None);
+    }
+
+    #[test]
+    fn test_download_filename_keeps_original_extension() {
+        let url = "https://cdn.example/photos/IMG_2041.JPG?w=1200#main";
+        assert_eq!(download_filename(url, "image/jpeg"), "IMG_2041.JPG");
+        assert_eq!(
+            download_filename("https://cdn.example/a/photo.jpeg", "image/jpeg"),
+            "photo.jpeg"
+        );
+        assert_eq!(
+            download_filename("https://cdn.example/a/anim.webp", "image/webp"),
+            "anim.webp"
+        );
+
+        // Served as something other than the URL says, or with no extension
+        assert_eq!(
+            download_filename("https://cdn.example/a/photo.jpg", "image/png"),
+            "photo.png"
+        );
+        assert_eq!(
+            download_filename("https://cdn.example/media/8a7f3c", "image/gif"),
+            "8a7f3c.gif"
+        );
+        assert_eq!(
+            download_filename("https://cdn.example/", "image/png"),
+            "image.png"
+        );
+
+        // Nothing to go on: keep the name
+        assert_eq!(
+            download_filename(
+                "https://cdn.example/a/photo.jpg",
+                "application/octet-stream"
+            ),
+            "photo.jpg"
+        );
     }
 }
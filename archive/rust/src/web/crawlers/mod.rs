@@ -1,8 +1,17 @@
+pub mod crawl_manifest;
+#[cfg(feature = "selenium")]
 pub mod crawler;
 pub mod danbooru;
+pub mod derpibooru;
+pub mod e621;
 pub mod gelbooru;
+pub mod generic_booru;
 pub mod image_board_crawler;
+#[cfg(feature = "selenium")]
 pub mod image_crawler;
 #[cfg(feature = "python")]
 pub mod reverse_image_search;
 pub mod sankaku;
+pub mod sequence;
+#[cfg(feature = "selenium")]
+pub mod webdriver;
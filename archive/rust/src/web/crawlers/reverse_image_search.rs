@@ -1,24 +1,40 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use thirtyfour::prelude::*;
 use tokio::runtime::Runtime;
+use walkdir::WalkDir;
+
+use super::webdriver::{connect_error, DriverService, WebDriverOptions};
+use crate::web::clients::downloader::{
+    download_to_dir, probe_image_dimensions, BROWSER_USER_AGENT,
+};
+use crate::web::clients::proxy::ProxyConfig;
+use crate::web::config::{validate_job, wait_from_secs};
+
+const DEFAULT_BATCH_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "bmp", "gif"];
 
 pub struct ReverseImageSearchRust {
     pub browser_name: String,
+    pub webdriver: WebDriverOptions,
 }
 
 impl ReverseImageSearchRust {
     pub fn new(config: &Value) -> Self {
+        let browser_name = config
+            .get("browser")
+            .and_then(|v| v.as_str())
+            .unwrap_or("brave")
+            .to_string();
         ReverseImageSearchRust {
-            browser_name: config
-                .get("browser")
-                .and_then(|v| v.as_str())
-                .unwrap_or("brave")
-                .to_string(),
+            webdriver: WebDriverOptions::from_config(config, &browser_name),
+            browser_name,
         }
     }
 
@@ -54,207 +70,2192 @@ impl ReverseImageSearchRust {
         config: Value,
         callback_obj: Py<PyAny>,
     ) -> Result<String> {
+        // Batch mode: one WebDriver session shared by every image
+        if let Some(image_paths) = collect_batch_paths(&config)? {
+            let options = BatchOptions::from_config(&config);
+            let mut engine = BrowserSearchEngine::connect(&config, &self.webdriver).await?;
+            let summary = run_batch(py, &mut engine, &image_paths, &options, &callback_obj).await;
+            engine.shutdown().await?;
+            return Ok(serde_json::to_string(&summary?)?);
+        }
+
+        let input = SearchInput::from_config(&config)?;
+
+        let mut engine = BrowserSearchEngine::connect(&config, &self.webdriver).await?;
+        let outcome = engine.search(py, &callback_obj, &input).await;
+        engine.shutdown().await?;
+        let outcome = outcome?;
+        let results = &outcome.results;
+
+        if let Some(options) = DownloadOptions::from_config(&config) {
+            let mut builder = reqwest::Client::builder()
+                .user_agent(BROWSER_USER_AGENT)
+                .timeout(Duration::from_secs(30));
+            if let Some(proxy) = ProxyConfig::from_config(&config)? {
+                builder = builder.proxy(proxy.to_reqwest()?);
+            }
+            let client = builder.build()?;
+            download_top_results(py, &client, results, &options, &callback_obj).await?;
+        }
+
+        Ok(serde_json::to_string(&outcome)?)
+    }
+}
+
+/// What to search for: a local file to upload, or a remote image URL.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchInput {
+    File(String),
+    Url(String),
+}
+
+impl SearchInput {
+    /// Reads `image_path` / `image_url` from a single-search config. Exactly one
+    /// of the two must be present.
+    pub fn from_config(config: &Value) -> Result<Self> {
+        let field = |key: &str| {
+            config
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+        };
+
+        match (field("image_path"), field("image_url")) {
+            (Some(_), Some(_)) => Err(anyhow::anyhow!(
+                "Provide either 'image_path' or 'image_url', not both"
+            )),
+            (None, None) => Err(anyhow::anyhow!(
+                "Missing search input: set 'image_path' or 'image_url'"
+            )),
+            (Some(path), None) => {
+                if !Path::new(path).exists() {
+                    return Err(anyhow::anyhow!("Image not found: {}", path));
+                }
+                Ok(SearchInput::File(path.to_string()))
+            }
+            (None, Some(image_url)) => {
+                let parsed = url::Url::parse(image_url)
+                    .map_err(|e| anyhow::anyhow!("Invalid image_url '{}': {}", image_url, e))?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    return Err(anyhow::anyhow!(
+                        "Invalid image_url '{}': only http(s) URLs are supported",
+                        image_url
+                    ));
+                }
+                Ok(SearchInput::Url(image_url.to_string()))
+            }
+        }
+    }
+}
+
+/// Google Lens entry point that fetches the image itself from `image_url`.
+pub fn lens_upload_url(image_url: &str) -> Result<String> {
+    let url = url::Url::parse_with_params(
+        "https://lens.google.com/uploadbyurl",
+        &[("url", image_url), ("hl", "en")],
+    )?;
+    Ok(url.to_string())
+}
+
+/// Bing Visual Search for the image at `image_url`.
+pub fn bing_search_url(image_url: &str) -> Result<String> {
+    let url = url::Url::parse_with_params(
+        "https://www.bing.com/images/search",
+        &[
+            ("view", "detailv2"),
+            ("iss", "sbi"),
+            ("q", &format!("imgurl:{}", image_url)),
+        ],
+    )?;
+    Ok(url.to_string())
+}
+
+/// Yandex Images search for the image at `image_url`.
+pub fn yandex_search_url(image_url: &str) -> Result<String> {
+    let url = url::Url::parse_with_params(
+        "https://yandex.com/images/search",
+        &[("rpt", "imageview"), ("url", image_url)],
+    )?;
+    Ok(url.to_string())
+}
+
+/// TinEye search for the image at `image_url`.
+pub fn tineye_search_url(image_url: &str) -> Result<String> {
+    let url = url::Url::parse_with_params("https://tineye.com/search", &[("url", image_url)])?;
+    Ok(url.to_string())
+}
+
+/// Domains dropped from results unless the config supplies its own `exclude_domains`.
+pub const DEFAULT_EXCLUDE_DOMAINS: &[&str] = &["*.google.com", "*.googleusercontent.com"];
+
+/// Domain allow/deny rules applied while collecting results.
+///
+/// Patterns match the domain itself and any subdomain, so `pinterest.com` and
+/// `*.pinterest.com` both cover `www.pinterest.com` and `pinterest.com`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainFilter {
+    pub exclude: Vec<String>,
+    pub prefer: Vec<String>,
+}
+
+impl Default for DomainFilter {
+    fn default() -> Self {
+        DomainFilter {
+            exclude: DEFAULT_EXCLUDE_DOMAINS
+                .iter()
+                .map(|d| d.to_string())
+                .collect(),
+            prefer: Vec::new(),
+        }
+    }
+}
+
+impl DomainFilter {
+    pub fn from_config(config: &Value) -> Self {
+        let list = |key: &str| {
+            config.get(key).and_then(|v| v.as_array()).map(|arr| {
+                arr.iter()
+                    .filter_map(|v| v.as_str())
+                    .map(|d| d.trim().to_lowercase())
+                    .filter(|d| !d.is_empty())
+                    .collect::<Vec<_>>()
+            })
+        };
+        let defaults = DomainFilter::default();
+        DomainFilter {
+            exclude: list("exclude_domains").unwrap_or(defaults.exclude),
+            prefer: list("prefer_domains").unwrap_or(defaults.prefer),
+        }
+    }
+
+    pub fn is_excluded(&self, url: &str) -> bool {
+        host_of(url)
+            .map(|host| self.exclude.iter().any(|p| domain_matches(&host, p)))
+            .unwrap_or(false)
+    }
+
+    fn is_preferred(&self, url: &str) -> bool {
+        host_of(url)
+            .map(|host| self.prefer.iter().any(|p| domain_matches(&host, p)))
+            .unwrap_or(false)
+    }
+
+    /// Moves results from preferred domains to the front, keeping the original
+    /// order within both groups.
+    pub fn sort_preferred(&self, results: &mut [SearchResult]) {
+        if self.prefer.is_empty() {
+            return;
+        }
+        results.sort_by_key(|r| !self.is_preferred(&r.url));
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(|h| h.to_lowercase())
+}
+
+fn domain_matches(host: &str, pattern: &str) -> bool {
+    let domain = pattern.trim_start_matches("*.");
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// Clicks through the EU cookie consent dialog if it is shown.
+async fn dismiss_consent(driver: &WebDriver) -> Result<()> {
+    let consent_xpath = "//button[contains(text(), 'Accept all') or contains(text(), 'Reject all')] | //div[text()='Reject all']//ancestor::button";
+    if let Ok(btn) = driver.find(By::XPath(consent_xpath)).await {
+        btn.click().await?;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Ok(())
+}
+
+// ===== CAPTCHA Handling =====
+
+/// Final state of a single search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchStatus {
+    Completed,
+    CaptchaBlocked,
+    Timeout,
+}
+
+/// Results of a single search together with how it ended, so "blocked" and
+/// "no matches" are distinguishable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchOutcome {
+    pub status: SearchStatus,
+    pub results: Vec<SearchResult>,
+}
+
+/// How long to wait for results and whether to pause for a human on CAPTCHA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptchaOptions {
+    pub results_timeout: Duration,
+    /// Only honoured with a visible browser; a headless session can't be solved.
+    pub wait_for_manual_solve: bool,
+    pub manual_solve_timeout: Duration,
+}
+
+impl CaptchaOptions {
+    pub fn from_config(config: &Value) -> Self {
+        let secs = |key: &str, default: f64| {
+            wait_from_secs(config.get(key).and_then(|v| v.as_f64()).unwrap_or(default))
+        };
         let headless = config
             .get("headless")
             .and_then(|v| v.as_bool())
             .unwrap_or(false);
-        let image_path = config
-            .get("image_path")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        let search_mode = config
-            .get("search_mode")
-            .and_then(|v| v.as_str())
-            .unwrap_or("Visual matches");
+        let wait_requested = config
+            .get("wait_for_manual_solve")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
 
-        if !Path::new(image_path).exists() {
-            return Err(anyhow::anyhow!("Image not found: {}", image_path));
+        CaptchaOptions {
+            results_timeout: secs("results_timeout_secs", 50.0),
+            wait_for_manual_solve: wait_requested && !headless,
+            manual_solve_timeout: secs("manual_solve_timeout_secs", 300.0),
         }
+    }
+}
 
-        let mut caps = DesiredCapabilities::chrome();
-        if headless {
-            caps.add_arg("--headless")?;
-        }
-        caps.add_arg("--no-sandbox")?;
-        caps.add_arg("--disable-dev-shm-usage")?;
+/// Page checks used while waiting for results; mocked in tests.
+#[allow(async_fn_in_trait)]
+pub trait PageProbe {
+    async fn results_present(&mut self) -> bool;
+    async fn captcha_present(&mut self) -> bool;
+}
 
-        let driver = WebDriver::new("http://localhost:9515", caps).await?;
+/// Looks for a search engine's results and CAPTCHA in the browser
+struct DriverProbe<'a> {
+    driver: &'a WebDriver,
+    results_css: &'static str,
+    captcha_xpath: &'static str,
+}
 
-        emit_status(py, &callback_obj, "Navigating to Google Images...")?;
-        driver.goto("https://images.google.com/?hl=en").await?;
+impl PageProbe for DriverProbe<'_> {
+    async fn results_present(&mut self) -> bool {
+        self.driver.find(By::Css(self.results_css)).await.is_ok()
+    }
 
-        // Consent (EU)
-        let consent_xpath = "//button[contains(text(), 'Accept all') or contains(text(), 'Reject all')] | //div[text()='Reject all']//ancestor::button";
-        if let Ok(btn) = driver.find(By::XPath(consent_xpath)).await {
-            btn.click().await?;
-            tokio::time::sleep(Duration::from_secs(1)).await;
-        }
+    async fn captcha_present(&mut self) -> bool {
+        self.driver
+            .find(By::XPath(self.captcha_xpath))
+            .await
+            .is_ok()
+    }
+}
 
-        // Click Camera Icon
-        emit_status(py, &callback_obj, "Opening Google Lens upload...")?;
-        let camera_selectors = vec![
-            By::Css("svg.Gdd5U"),
-            By::XPath("//*[name()='svg' and @viewBox='0 -960 960 960']"),
-            By::Css("div[aria-label='Search by image']"),
-        ];
+/// Polls `probe` until results appear. `on_captcha` fires once, the first time a
+/// CAPTCHA is seen; with `wait_for_manual_solve` the deadline is then extended
+/// by `manual_solve_timeout` instead of giving up immediately.
+pub async fn wait_for_results<P: PageProbe>(
+    probe: &mut P,
+    options: &CaptchaOptions,
+    poll_interval: Duration,
+    on_captcha: &mut dyn FnMut() -> Result<()>,
+) -> Result<SearchStatus> {
+    let mut deadline = tokio::time::Instant::now() + options.results_timeout;
+    let mut captcha_seen = false;
+
+    loop {
+        if probe.results_present().await {
+            return Ok(SearchStatus::Completed);
+        }
 
-        let mut camera_btn = None;
-        for selector in camera_selectors {
-            if let Ok(btn) = driver.find(selector).await {
-                camera_btn = Some(btn);
-                break;
+        let captcha = probe.captcha_present().await;
+        if captcha && !captcha_seen {
+            captcha_seen = true;
+            on_captcha()?;
+            if !options.wait_for_manual_solve {
+                return Ok(SearchStatus::CaptchaBlocked);
             }
+            deadline = deadline.max(tokio::time::Instant::now() + options.manual_solve_timeout);
         }
 
-        if let Some(btn) = camera_btn {
-            btn.click().await?;
-        } else {
-            // Fallback
-            let el = driver.find(By::Css("svg.Gdd5U")).await?;
-            driver
-                .execute(
-                    "arguments[0].parentElement.click();",
-                    vec![serde_json::to_value(&el)?],
-                )
-                .await?;
+        if tokio::time::Instant::now() >= deadline {
+            return Ok(if captcha_seen {
+                SearchStatus::CaptchaBlocked
+            } else {
+                SearchStatus::Timeout
+            });
         }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
 
-        tokio::time::sleep(Duration::from_secs(1)).await;
+/// A reverse image search backend. Implementations keep whatever session they
+/// need (browser, API client) alive between calls so batches reuse it.
+#[allow(async_fn_in_trait)]
+pub trait ReverseSearchEngine {
+    fn name(&self) -> &str;
 
-        // Upload Image
-        emit_status(py, &callback_obj, "Uploading image...")?;
-        let file_input = driver
-            .find(By::Css("input[type='file'][name='encoded_image']"))
-            .await?;
-        file_input.send_keys(image_path).await?;
+    /// Searches for a single image and returns the result objects.
+    async fn search(
+        &mut self,
+        py: Python<'_>,
+        callback_obj: &Py<PyAny>,
+        input: &SearchInput,
+    ) -> Result<SearchOutcome>;
 
-        emit_status(
-            py,
-            &callback_obj,
-            "Analyzing image (waiting for results/CAPTCHA)...",
-        )?;
+    /// Releases the underlying session.
+    async fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The reverse image search sites, chosen with the config's `engine`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    Google,
+    Bing,
+    Yandex,
+    TinEye,
+}
+
+impl EngineKind {
+    pub const ALL: [EngineKind; 4] = [
+        EngineKind::Google,
+        EngineKind::Bing,
+        EngineKind::Yandex,
+        EngineKind::TinEye,
+    ];
 
-        // Wait for results
-        let mut results_detected = false;
-        for _ in 0..50 {
-            if let Ok(_) = driver.find(By::Css("div[data-ved] img")).await {
-                results_detected = true;
-                break;
+    /// The sites to search, in order: one of "google" (the default), "bing",
+    /// "yandex" and "tineye", or "all" for every one of them
+    pub fn from_config(config: &Value) -> Result<Vec<Self>> {
+        let engine = config
+            .get("engine")
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_lowercase())
+            .unwrap_or_default();
+        Ok(match engine.as_str() {
+            "" | "google" => vec![EngineKind::Google],
+            "bing" => vec![EngineKind::Bing],
+            "yandex" => vec![EngineKind::Yandex],
+            "tineye" => vec![EngineKind::TinEye],
+            "all" => EngineKind::ALL.to_vec(),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Unknown engine '{}': use google, bing, yandex, tineye or all",
+                    other
+                ))
             }
-            tokio::time::sleep(Duration::from_secs(1)).await;
-        }
+        })
+    }
 
-        if !results_detected {
-            emit_status(
-                py,
-                &callback_obj,
-                "Timeout waiting for results. Check for CAPTCHA.",
-            )?;
-        } else {
-            emit_status(py, &callback_obj, "Results detected.")?;
+    /// The `engine` value results are tagged with
+    pub fn id(&self) -> &'static str {
+        match self {
+            EngineKind::Google => "google",
+            EngineKind::Bing => "bing",
+            EngineKind::Yandex => "yandex",
+            EngineKind::TinEye => "tineye",
         }
+    }
+}
 
-        // Search Mode
-        if search_mode != "All" {
-            emit_status(
-                py,
-                &callback_obj,
-                &format!("Switching to {} mode...", search_mode),
-            )?;
-            let search_btn_xpath = format!("//a[contains(text(), 'Find image source')] | //span[@class='R1QWuf' and contains(text(), '{}')]", search_mode);
-            if let Ok(btn) = driver.find(By::XPath(&search_btn_xpath)).await {
-                btn.click().await?;
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
+/// One site's part of a browser search: getting the image to it and reading
+/// the matches back out. The waiting, CAPTCHA handling and merging around
+/// them is shared by `BrowserSearchEngine`.
+#[allow(async_fn_in_trait)]
+pub trait SearchEngine {
+    fn name(&self) -> &'static str;
+    /// Shown once the site has results
+    fn results_css(&self) -> &'static str;
+    fn captcha_xpath(&self) -> &'static str;
+    /// The site's own domains, never results
+    fn own_domains(&self) -> &'static [&'static str];
+
+    /// Submits the image, leaving the browser on the results page
+    async fn upload(&self, driver: &WebDriver, input: &SearchInput) -> Result<()>;
+
+    /// Up to `max_results` matches from the results page, without the ones
+    /// `filter` excludes
+    async fn scrape(
+        &self,
+        driver: &WebDriver,
+        filter: &DomainFilter,
+        max_results: usize,
+    ) -> Result<Vec<SearchResult>>;
+}
+
+/// Uploads `path` through the first file input on the page
+async fn send_file(driver: &WebDriver, input_css: &str, path: &str) -> Result<()> {
+    let file_input = driver
+        .find(By::Css(input_css))
+        .await
+        .context("No upload field on the page")?;
+    file_input.send_keys(path).await?;
+    Ok(())
+}
+
+/// Clicks the first of `selectors` on the page, if any is there
+async fn click_first(driver: &WebDriver, selectors: Vec<By>) -> Result<bool> {
+    for selector in selectors {
+        if let Ok(element) = driver.find(selector).await {
+            element.click().await?;
+            return Ok(true);
         }
+    }
+    Ok(false)
+}
 
-        // Scrape Results
-        emit_status(py, &callback_obj, "Scraping search results...")?;
-        let potential_links = driver
-            .find_all(By::XPath("//a[contains(@href, 'http')]"))
-            .await?;
+/// How many times a results page is scrolled for more matches before
+/// settling for what it has shown
+const MAX_SCROLLS: usize = 10;
+
+/// How far up from a link to look for its result card's size and thumbnail
+const CARD_DEPTH: usize = 3;
 
-        let mut results = vec![];
-        let mut seen_urls = std::collections::HashSet::new();
+/// Up to `max_results` results for the links matching `links_xpath`,
+/// skipping the site's own pages, excluded domains and repeats. While there
+/// are fewer than that the page is scrolled to load more, until it stops
+/// showing new links.
+async fn scrape_links(
+    driver: &WebDriver,
+    links_xpath: &str,
+    own_domains: &[&str],
+    filter: &DomainFilter,
+    max_results: usize,
+) -> Result<Vec<SearchResult>> {
+    let mut results = vec![];
+    let mut seen_urls = std::collections::HashSet::new();
 
-        for link_elem in potential_links {
+    for scrolls in 0.. {
+        let mut found_new = false;
+        for link_elem in driver.find_all(By::XPath(links_xpath)).await? {
+            if results.len() >= max_results {
+                return Ok(results);
+            }
             let href = match link_elem.attr("href").await? {
                 Some(h) => h,
                 None => continue,
             };
+            if !seen_urls.insert(href.clone()) {
+                continue;
+            }
+            found_new = true;
 
-            if href.contains("google.com")
-                || href.contains("googleusercontent")
-                || seen_urls.contains(&href)
-            {
+            let own = host_of(&href).is_some_and(|host| {
+                own_domains
+                    .iter()
+                    .any(|domain| domain_matches(&host, domain))
+            });
+            if own || filter.is_excluded(&href) {
                 continue;
             }
 
-            let is_direct = href.ends_with(".jpg")
-                || href.ends_with(".jpeg")
-                || href.ends_with(".png")
-                || href.ends_with(".webp");
-            let title = link_elem
-                .attr("title")
-                .await?
-                .unwrap_or_else(|| "Result".to_string());
+            // The "W × H" label and the thumbnail usually sit next to the link
+            // inside the result card, so look further out for whatever the
+            // link's own markup lacks.
+            let link_html = link_elem.outer_html().await.unwrap_or_default();
+            let mut result = extract_result_from_html(&href, &link_html);
+            let mut ancestor = link_elem;
+            for _ in 0..CARD_DEPTH {
+                if result.width.is_some() && result.thumbnail_url.is_some() {
+                    break;
+                }
+                let Ok(parent) = ancestor.find(By::XPath("..")).await else {
+                    break;
+                };
+                let parent_html = parent.outer_html().await.unwrap_or_default();
+                result.fill_missing(extract_result_from_html(&href, &parent_html));
+                ancestor = parent;
+            }
+
+            results.push(result);
+        }
+
+        if results.len() >= max_results || !found_new || scrolls >= MAX_SCROLLS {
+            break;
+        }
+        driver
+            .execute("window.scrollTo(0, document.body.scrollHeight);", vec![])
+            .await?;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+    Ok(results)
+}
+
+/// Google Images / Lens
+pub struct GoogleLens {
+    /// The Lens tab to scrape, e.g. "Visual matches"; "All" stays put
+    pub search_mode: String,
+}
+
+impl SearchEngine for GoogleLens {
+    fn name(&self) -> &'static str {
+        "Google Lens"
+    }
+
+    fn results_css(&self) -> &'static str {
+        "div[data-ved] img"
+    }
+
+    fn captcha_xpath(&self) -> &'static str {
+        "//iframe[contains(@src, 'recaptcha')] | //form[@id='captcha-form'] | //*[contains(text(), 'unusual traffic')]"
+    }
 
-            seen_urls.insert(href.clone());
-            results.push(serde_json::json!({
-                "url": href,
-                "title": title,
-                "is_direct": is_direct,
-                "resolution": "Unknown" // Scraped from text in python, can be added later
-            }));
+    fn own_domains(&self) -> &'static [&'static str] {
+        &["google.com", "gstatic.com"]
+    }
+
+    async fn upload(&self, driver: &WebDriver, input: &SearchInput) -> Result<()> {
+        match input {
+            SearchInput::Url(image_url) => {
+                driver.goto(lens_upload_url(image_url)?).await?;
+                dismiss_consent(driver).await?;
+            }
+            SearchInput::File(image_path) => {
+                driver.goto("https://images.google.com/?hl=en").await?;
+                dismiss_consent(driver).await?;
+
+                // Click Camera Icon
+                let camera_selectors = vec![
+                    By::Css("svg.Gdd5U"),
+                    By::XPath("//*[name()='svg' and @viewBox='0 -960 960 960']"),
+                    By::Css("div[aria-label='Search by image']"),
+                ];
+                if !click_first(driver, camera_selectors).await? {
+                    // Fallback
+                    let el = driver.find(By::Css("svg.Gdd5U")).await?;
+                    driver
+                        .execute(
+                            "arguments[0].parentElement.click();",
+                            vec![serde_json::to_value(&el)?],
+                        )
+                        .await?;
+                }
+
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                send_file(
+                    driver,
+                    "input[type='file'][name='encoded_image']",
+                    image_path,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
 
-            if results.len() >= 20 {
-                break;
+    async fn scrape(
+        &self,
+        driver: &WebDriver,
+        filter: &DomainFilter,
+        max_results: usize,
+    ) -> Result<Vec<SearchResult>> {
+        if self.search_mode != "All" {
+            let search_btn_xpath = format!("//a[contains(text(), 'Find image source')] | //span[@class='R1QWuf' and contains(text(), '{}')]", self.search_mode);
+            if let Ok(btn) = driver.find(By::XPath(&search_btn_xpath)).await {
+                btn.click().await?;
+                tokio::time::sleep(Duration::from_secs(3)).await;
             }
         }
+        scrape_links(
+            driver,
+            "//a[contains(@href, 'http')]",
+            self.own_domains(),
+            filter,
+            max_results,
+        )
+        .await
+    }
+}
 
-        driver.quit().await?;
+/// Bing Visual Search
+pub struct Bing;
 
-        Ok(serde_json::to_string(&results)?)
+impl SearchEngine for Bing {
+    fn name(&self) -> &'static str {
+        "Bing"
+    }
+
+    fn results_css(&self) -> &'static str {
+        "#i_results a[href^='http'], .insights a[href^='http']"
+    }
+
+    fn captcha_xpath(&self) -> &'static str {
+        "//iframe[contains(@src, 'captcha')] | //*[@id='b_captcha'] | //*[contains(text(), 'solve the challenge')]"
+    }
+
+    fn own_domains(&self) -> &'static [&'static str] {
+        &["bing.com", "bing.net", "microsoft.com", "msn.com"]
+    }
+
+    async fn upload(&self, driver: &WebDriver, input: &SearchInput) -> Result<()> {
+        match input {
+            SearchInput::Url(image_url) => driver.goto(bing_search_url(image_url)?).await?,
+            SearchInput::File(image_path) => {
+                driver
+                    .goto("https://www.bing.com/images?setlang=en")
+                    .await?;
+                click_first(
+                    driver,
+                    vec![
+                        By::Css("#sb_sbi"),
+                        By::Css("div[aria-label='Search using an image']"),
+                    ],
+                )
+                .await?;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                send_file(driver, "input[type='file']", image_path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn scrape(
+        &self,
+        driver: &WebDriver,
+        filter: &DomainFilter,
+        max_results: usize,
+    ) -> Result<Vec<SearchResult>> {
+        scrape_links(
+            driver,
+            "//*[@id='i_results' or contains(@class, 'insights')]//a[contains(@href, 'http')]",
+            self.own_domains(),
+            filter,
+            max_results,
+        )
+        .await
     }
 }
 
-fn emit_status(py: Python<'_>, obj: &Py<PyAny>, msg: &str) -> PyResult<()> {
-    obj.call_method1(py, "on_status_emitted", (msg,))?;
-    Ok(())
+/// Yandex Images
+pub struct Yandex;
+
+impl SearchEngine for Yandex {
+    fn name(&self) -> &'static str {
+        "Yandex"
+    }
+
+    fn results_css(&self) -> &'static str {
+        ".CbirSites-Item, .CbirSimilar-Thumb"
+    }
+
+    fn captcha_xpath(&self) -> &'static str {
+        "//form[contains(@action, 'checkcaptcha')] | //*[contains(@class, 'CheckboxCaptcha')] | //*[contains(@class, 'AdvancedCaptcha')]"
+    }
+
+    fn own_domains(&self) -> &'static [&'static str] {
+        &["yandex.com", "yandex.ru", "yandex.net", "ya.ru"]
+    }
+
+    async fn upload(&self, driver: &WebDriver, input: &SearchInput) -> Result<()> {
+        match input {
+            SearchInput::Url(image_url) => driver.goto(yandex_search_url(image_url)?).await?,
+            SearchInput::File(image_path) => {
+                driver.goto("https://yandex.com/images/").await?;
+                click_first(
+                    driver,
+                    vec![
+                        By::Css("button[aria-label='Image search']"),
+                        By::Css(".input__cbir-button"),
+                    ],
+                )
+                .await?;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                send_file(driver, "input[type='file']", image_path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn scrape(
+        &self,
+        driver: &WebDriver,
+        filter: &DomainFilter,
+        max_results: usize,
+    ) -> Result<Vec<SearchResult>> {
+        scrape_links(
+            driver,
+            "//*[contains(@class, 'CbirSites-Item')]//a[contains(@href, 'http')]",
+            self.own_domains(),
+            filter,
+            max_results,
+        )
+        .await
+    }
 }
 
-#[cfg(feature = "python")]
-#[pyfunction]
-pub fn run_reverse_image_search(
-    py: Python<'_>,
-    config_json: String,
-    callback_obj: Py<PyAny>,
-) -> PyResult<String> {
-    let config: Value = serde_json::from_str(&config_json).map_err(|e| {
-        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid JSON: {}", e))
-    })?;
-    let search = ReverseImageSearchRust::new(&config);
-    search.run(py, config_json, callback_obj)
+/// TinEye
+pub struct TinEye;
+
+impl SearchEngine for TinEye {
+    fn name(&self) -> &'static str {
+        "TinEye"
+    }
+
+    fn results_css(&self) -> &'static str {
+        ".match-row, .match, .no-results"
+    }
+
+    fn captcha_xpath(&self) -> &'static str {
+        "//iframe[contains(@src, 'recaptcha') or contains(@src, 'hcaptcha')]"
+    }
+
+    fn own_domains(&self) -> &'static [&'static str] {
+        &["tineye.com"]
+    }
+
+    async fn upload(&self, driver: &WebDriver, input: &SearchInput) -> Result<()> {
+        match input {
+            SearchInput::Url(image_url) => driver.goto(tineye_search_url(image_url)?).await?,
+            SearchInput::File(image_path) => {
+                driver.goto("https://tineye.com/").await?;
+                send_file(driver, "input#upload-box, input[type='file']", image_path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn scrape(
+        &self,
+        driver: &WebDriver,
+        filter: &DomainFilter,
+        max_results: usize,
+    ) -> Result<Vec<SearchResult>> {
+        scrape_links(
+            driver,
+            "//*[contains(@class, 'match')]//a[contains(@href, 'http')]",
+            self.own_domains(),
+            filter,
+            max_results,
+        )
+        .await
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+/// Tags each engine's results with its `engine` and merges them in engine
+/// order, keeping the first result for each URL.
+pub fn merge_results(per_engine: Vec<(&str, Vec<SearchResult>)>) -> Vec<SearchResult> {
+    let mut seen = std::collections::HashSet::new();
+    let mut merged = Vec::new();
+    for (engine, results) in per_engine {
+        for mut result in results {
+            if !seen.insert(result.url.clone()) {
+                continue;
+            }
+            result.engine = Some(engine.to_string());
+            merged.push(result);
+        }
+    }
+    merged
+}
 
-    #[test]
-    fn test_reverse_search_config() {
-        let config = json!({
-            "browser": "brave"
-        });
-        let search = ReverseImageSearchRust::new(&config);
-        assert_eq!(search.browser_name, "brave");
+/// How a search over several engines ended: completed if any engine got
+/// through, blocked if every engine was, else timed out
+pub fn combine_statuses(statuses: &[SearchStatus]) -> SearchStatus {
+    if statuses.contains(&SearchStatus::Completed) {
+        SearchStatus::Completed
+    } else if !statuses.is_empty() && statuses.iter().all(|s| *s == SearchStatus::CaptchaBlocked) {
+        SearchStatus::CaptchaBlocked
+    } else {
+        SearchStatus::Timeout
     }
+}
 
-    #[test]
-    fn test_reverse_search_defaults() {
-        let config = json!({});
-        let search = ReverseImageSearchRust::new(&config);
-        assert_eq!(search.browser_name, "brave");
+/// `max_results`: how many matches to collect from each engine, 20 unless set
+pub fn max_results_from_config(config: &Value) -> usize {
+    config
+        .get("max_results")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(20)
+        .max(1) as usize
+}
+
+/// Reverse image search in a browser driven through chromedriver, or
+/// geckodriver for Firefox, on each of the configured engines in turn.
+pub struct BrowserSearchEngine {
+    driver: Option<WebDriver>,
+    /// The driver started for this engine, if any; stopped on drop
+    service: Option<DriverService>,
+    engines: Vec<EngineKind>,
+    name: String,
+    search_mode: String,
+    filter: DomainFilter,
+    captcha: CaptchaOptions,
+    /// Results wanted from each engine
+    max_results: usize,
+}
+
+impl BrowserSearchEngine {
+    pub async fn connect(config: &Value, webdriver: &WebDriverOptions) -> Result<Self> {
+        let engines = EngineKind::from_config(config)?;
+        let headless = config
+            .get("headless")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let search_mode = config
+            .get("search_mode")
+            .and_then(|v| v.as_str())
+            .unwrap_or("Visual matches");
+        // Only Chrome takes it from a flag; Firefox keeps its profile's
+        let proxy = ProxyConfig::from_config(config)?;
+
+        let (url, service) = webdriver.endpoint().await?;
+        let driver = if webdriver.firefox {
+            let mut caps = DesiredCapabilities::firefox();
+            if headless {
+                caps.add_arg("-headless")?;
+            }
+            WebDriver::new(url.as_str(), caps).await
+        } else {
+            let mut caps = DesiredCapabilities::chrome();
+            if headless {
+                caps.add_arg("--headless")?;
+            }
+            caps.add_arg("--no-sandbox")?;
+            caps.add_arg("--disable-dev-shm-usage")?;
+            if let Some(proxy) = &proxy {
+                caps.add_arg(&proxy.chrome_arg())?;
+            }
+            WebDriver::new(url.as_str(), caps).await
+        }
+        .map_err(|e| connect_error(&url, e))?;
+
+        let search_mode = search_mode.to_string();
+        let name = engines
+            .iter()
+            .map(|kind| match kind {
+                EngineKind::Google => GoogleLens {
+                    search_mode: search_mode.clone(),
+                }
+                .name(),
+                EngineKind::Bing => Bing.name(),
+                EngineKind::Yandex => Yandex.name(),
+                EngineKind::TinEye => TinEye.name(),
+            })
+            .collect::<Vec<_>>()
+            .join(" + ");
+
+        Ok(BrowserSearchEngine {
+            driver: Some(driver),
+            service,
+            engines,
+            name,
+            search_mode,
+            filter: DomainFilter::from_config(config),
+            captcha: CaptchaOptions::from_config(config),
+            max_results: max_results_from_config(config),
+        })
+    }
+
+    fn driver(&self) -> Result<&WebDriver> {
+        self.driver
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("WebDriver session already closed"))
+    }
+
+    /// Searches `site` for `input`: upload, wait out the results (or a
+    /// CAPTCHA), then scrape
+    async fn search_site<S: SearchEngine>(
+        &self,
+        site: &S,
+        py: Python<'_>,
+        callback_obj: &Py<PyAny>,
+        input: &SearchInput,
+    ) -> Result<SearchOutcome> {
+        let driver = self.driver()?;
+        let name = site.name();
+
+        emit_status(py, callback_obj, &format!("[{}] Uploading image...", name))?;
+        site.upload(driver, input).await?;
+
+        emit_status(
+            py,
+            callback_obj,
+            &format!(
+                "[{}] Analyzing image (waiting for results/CAPTCHA)...",
+                name
+            ),
+        )?;
+
+        // Wait for results, pausing for a manual CAPTCHA solve if allowed
+        let mut probe = DriverProbe {
+            driver,
+            results_css: site.results_css(),
+            captcha_xpath: site.captcha_xpath(),
+        };
+        let mut on_captcha = || -> Result<()> {
+            emit_status(py, callback_obj, &format!("[{}] CAPTCHA detected.", name))?;
+            if callback_obj.getattr(py, "on_captcha_detected").is_ok() {
+                let msg = if self.captcha.wait_for_manual_solve {
+                    "Solve the CAPTCHA in the browser window to continue."
+                } else {
+                    "CAPTCHA blocked the search."
+                };
+                callback_obj.call_method1(py, "on_captcha_detected", (msg,))?;
+            }
+            Ok(())
+        };
+        let status = wait_for_results(
+            &mut probe,
+            &self.captcha,
+            Duration::from_secs(1),
+            &mut on_captcha,
+        )
+        .await?;
+
+        match status {
+            SearchStatus::Completed => {
+                emit_status(py, callback_obj, &format!("[{}] Results detected.", name))?
+            }
+            SearchStatus::Timeout => emit_status(
+                py,
+                callback_obj,
+                &format!("[{}] Timeout waiting for results. Check for CAPTCHA.", name),
+            )?,
+            SearchStatus::CaptchaBlocked => {
+                emit_status(
+                    py,
+                    callback_obj,
+                    &format!("[{}] Search blocked by CAPTCHA; no results scraped.", name),
+                )?;
+                return Ok(SearchOutcome {
+                    status,
+                    results: Vec::new(),
+                });
+            }
+        }
+
+        emit_status(
+            py,
+            callback_obj,
+            &format!("[{}] Scraping search results...", name),
+        )?;
+        let results = site.scrape(driver, &self.filter, self.max_results).await?;
+        Ok(SearchOutcome { status, results })
+    }
+}
+
+impl ReverseSearchEngine for BrowserSearchEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn search(
+        &mut self,
+        py: Python<'_>,
+        callback_obj: &Py<PyAny>,
+        input: &SearchInput,
+    ) -> Result<SearchOutcome> {
+        let mut statuses = Vec::new();
+        let mut per_engine = Vec::new();
+        for kind in self.engines.clone() {
+            let outcome = match kind {
+                EngineKind::Google => {
+                    let site = GoogleLens {
+                        search_mode: self.search_mode.clone(),
+                    };
+                    self.search_site(&site, py, callback_obj, input).await
+                }
+                EngineKind::Bing => self.search_site(&Bing, py, callback_obj, input).await,
+                EngineKind::Yandex => self.search_site(&Yandex, py, callback_obj, input).await,
+                EngineKind::TinEye => self.search_site(&TinEye, py, callback_obj, input).await,
+            };
+            match outcome {
+                Ok(outcome) => {
+                    statuses.push(outcome.status);
+                    per_engine.push((kind.id(), outcome.results));
+                }
+                // One engine failing shouldn't lose the others' results
+                Err(e) if self.engines.len() > 1 => {
+                    emit_status(
+                        py,
+                        callback_obj,
+                        &format!("{} search failed: {}", kind.id(), e),
+                    )?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if statuses.is_empty() {
+            return Err(anyhow::anyhow!("Every search engine failed"));
+        }
+        let mut results = merge_results(per_engine);
+        self.filter.sort_preferred(&mut results);
+        Ok(SearchOutcome {
+            status: combine_statuses(&statuses),
+            results,
+        })
+    }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        let quit = match self.driver.take() {
+            Some(driver) => driver.quit().await,
+            None => Ok(()),
+        };
+        // Only once the browser is gone
+        self.service = None;
+        quit?;
+        Ok(())
+    }
+}
+
+/// One match from a reverse image search.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub url: String,
+    #[serde(default)]
+    pub title: String,
+    /// The URL is the image itself rather than a page showing it
+    #[serde(default)]
+    pub is_direct: bool,
+    /// Size of the matched image as the engine reports it, if it does
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+    /// Host of `url` without a leading "www."
+    #[serde(default)]
+    pub source_domain: String,
+    /// Set when results from several engines are merged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+}
+
+impl SearchResult {
+    /// A result for `url` with nothing known about it beyond the URL itself
+    pub fn new(url: &str) -> Self {
+        let lower = url.to_lowercase();
+        let path = lower.split(['?', '#']).next().unwrap_or("");
+        SearchResult {
+            url: url.to_string(),
+            is_direct: [".jpg", ".jpeg", ".png", ".webp"]
+                .iter()
+                .any(|ext| path.ends_with(ext)),
+            source_domain: host_of(url)
+                .map(|host| host.trim_start_matches("www.").to_string())
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Takes the size and thumbnail from `other` where this result has none
+    pub fn fill_missing(&mut self, other: SearchResult) {
+        if self.width.is_none() || self.height.is_none() {
+            self.width = other.width;
+            self.height = other.height;
+        }
+        if self.thumbnail_url.is_none() {
+            self.thumbnail_url = other.thumbnail_url;
+        }
+    }
+}
+
+/// Builds a result for `href` from the outer HTML of its link or surrounding
+/// result card. `width`, `height` and `thumbnail_url` are left empty when the
+/// markup does not contain them.
+pub fn extract_result_from_html(href: &str, outer_html: &str) -> SearchResult {
+    let fragment = scraper::Html::parse_fragment(outer_html);
+
+    let link_sel = scraper::Selector::parse("a[href]").expect("valid selector");
+    let link = fragment
+        .select(&link_sel)
+        .find(|a| a.value().attr("href") == Some(href))
+        .or_else(|| fragment.select(&link_sel).next());
+    let title = link
+        .and_then(|a| {
+            a.value()
+                .attr("title")
+                .or_else(|| a.value().attr("aria-label"))
+        })
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| "Result".to_string());
+
+    let img_sel = scraper::Selector::parse("img").expect("valid selector");
+    let thumbnail_url = fragment.select(&img_sel).find_map(|img| {
+        img.value()
+            .attr("src")
+            .or_else(|| img.value().attr("data-src"))
+            .filter(|src| !src.is_empty())
+            .map(|src| src.to_string())
+    });
+
+    let text = fragment.root_element().text().collect::<Vec<_>>().join(" ");
+    let dimensions = parse_dimensions(&text);
+
+    SearchResult {
+        title,
+        width: dimensions.map(|(w, _)| w),
+        height: dimensions.map(|(_, h)| h),
+        thumbnail_url,
+        ..SearchResult::new(href)
+    }
+}
+
+/// Finds the first "W x H" / "W×H" pair in free text.
+pub fn parse_dimensions(text: &str) -> Option<(u32, u32)> {
+    let chars: Vec<char> = text.chars().collect();
+    let read_number = |start: usize| -> (Option<u32>, usize) {
+        let mut end = start;
+        while end < chars.len() && chars[end].is_ascii_digit() {
+            end += 1;
+        }
+        let digits: String = chars[start..end].iter().collect();
+        (digits.parse().ok(), end)
+    };
+    let skip_spaces = |mut i: usize| {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        i
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let starts_number =
+            chars[i].is_ascii_digit() && (i == 0 || !chars[i - 1].is_alphanumeric());
+        if !starts_number {
+            i += 1;
+            continue;
+        }
+        let (width, after_width) = read_number(i);
+        let sep = skip_spaces(after_width);
+        if sep < chars.len() && matches!(chars[sep], 'x' | 'X' | '×') {
+            let h_start = skip_spaces(sep + 1);
+            if h_start < chars.len() && chars[h_start].is_ascii_digit() {
+                let (height, after_height) = read_number(h_start);
+                let bounded = after_height == chars.len() || !chars[after_height].is_alphanumeric();
+                if let (Some(w), Some(h), true) = (width, height, bounded) {
+                    if w > 0 && h > 0 {
+                        return Some((w, h));
+                    }
+                }
+            }
+        }
+        i = after_width.max(i + 1);
+    }
+    None
+}
+
+// ===== Result Downloads =====
+
+/// `download` block of the config: fetch the best results after a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadOptions {
+    pub top_n: usize,
+    pub dest_dir: PathBuf,
+    /// Minimum (width, height); smaller images are skipped after probing.
+    pub min_resolution: Option<(u32, u32)>,
+}
+
+impl DownloadOptions {
+    /// Returns `None` unless `download.enabled` is true.
+    pub fn from_config(config: &Value) -> Option<Self> {
+        let download = config.get("download")?;
+        if !download
+            .get("enabled")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        // Accepts "1280x720" or {"width": 1280, "height": 720}
+        let min_resolution = download.get("min_resolution").and_then(|v| match v {
+            Value::String(s) => parse_dimensions(s),
+            Value::Object(_) => Some((
+                v.get("width").and_then(|w| w.as_u64()).unwrap_or(0) as u32,
+                v.get("height").and_then(|h| h.as_u64()).unwrap_or(0) as u32,
+            )),
+            _ => None,
+        });
+
+        Some(DownloadOptions {
+            top_n: download.get("top_n").and_then(|v| v.as_u64()).unwrap_or(3) as usize,
+            dest_dir: PathBuf::from(
+                download
+                    .get("dest_dir")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("downloads"),
+            ),
+            min_resolution,
+        })
+    }
+}
+
+/// Looks up the `og:image` of a result page, resolved against the page URL.
+async fn fetch_og_image(client: &reqwest::Client, page_url: &str) -> Option<String> {
+    let html = client
+        .get(page_url)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    let document = scraper::Html::parse_document(&html);
+    let selector = scraper::Selector::parse(
+        "meta[property='og:image'], meta[name='og:image'], meta[property='og:image:url']",
+    )
+    .ok()?;
+    let content = document
+        .select(&selector)
+        .find_map(|m| m.value().attr("content"))?;
+    url::Url::parse(page_url)
+        .ok()?
+        .join(content.trim())
+        .ok()
+        .map(|u| u.to_string())
+}
+
+/// Downloads up to `top_n` results in rank order. Direct image links are used as
+/// is; other results are resolved through their page's `og:image`. Returns the
+/// saved paths, each of which is also reported through `on_image_saved`.
+pub async fn download_top_results(
+    py: Python<'_>,
+    client: &reqwest::Client,
+    results: &[SearchResult],
+    options: &DownloadOptions,
+    callback_obj: &Py<PyAny>,
+) -> Result<Vec<PathBuf>> {
+    let mut saved = Vec::new();
+
+    for result in results {
+        if saved.len() >= options.top_n {
+            break;
+        }
+        if is_cancelled(py, callback_obj)? {
+            break;
+        }
+
+        let page_url = result.url.as_str();
+        if page_url.is_empty() {
+            continue;
+        }
+
+        let (image_url, referer) = if result.is_direct {
+            (page_url.to_string(), None)
+        } else {
+            match fetch_og_image(client, page_url).await {
+                Some(og) => (og, Some(page_url)),
+                None => continue,
+            }
+        };
+
+        if let Some((min_w, min_h)) = options.min_resolution {
+            match probe_image_dimensions(client, &image_url, referer).await {
+                Ok(Some((w, h))) if w >= min_w && h >= min_h => {}
+                Ok(Some((w, h))) => {
+                    emit_status(
+                        py,
+                        callback_obj,
+                        &format!(
+                            "Skipping {} ({}x{} is below {}x{})",
+                            image_url, w, h, min_w, min_h
+                        ),
+                    )?;
+                    continue;
+                }
+                Ok(None) | Err(_) => {
+                    emit_status(
+                        py,
+                        callback_obj,
+                        &format!("Skipping {} (could not determine size)", image_url),
+                    )?;
+                    continue;
+                }
+            }
+        }
+
+        match download_to_dir(client, &image_url, &options.dest_dir, referer).await {
+            Ok(path) => {
+                let path_str = path.to_string_lossy().to_string();
+                emit_status(py, callback_obj, &format!("Saved image: {}", path_str))?;
+                callback_obj.call_method1(py, "on_image_saved", (path_str,))?;
+                saved.push(path);
+            }
+            Err(e) => {
+                emit_status(
+                    py,
+                    callback_obj,
+                    &format!("Download failed for {}: {}", image_url, e),
+                )?;
+            }
+        }
+    }
+
+    Ok(saved)
+}
+
+// ===== Batch Mode =====
+
+/// Batch settings read from the top-level config.
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// JSON checkpoint rewritten after every image; also used to resume.
+    pub output_path: Option<PathBuf>,
+    /// Optional flat CSV export (one row per result) refreshed with the checkpoint.
+    pub csv_path: Option<PathBuf>,
+    /// Pause between images to stay under CAPTCHA thresholds.
+    pub delay: Duration,
+}
+
+impl BatchOptions {
+    pub fn from_config(config: &Value) -> Self {
+        let delay_secs = config
+            .get("delay_secs")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(5.0);
+        BatchOptions {
+            output_path: config
+                .get("output_path")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from),
+            csv_path: config
+                .get("csv_path")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from),
+            delay: wait_from_secs(delay_secs),
+        }
+    }
+}
+
+/// Per-image results accumulated over a batch run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchCheckpoint {
+    #[serde(default)]
+    pub results: BTreeMap<String, Vec<SearchResult>>,
+    /// Images that failed; they are retried on resume.
+    #[serde(default)]
+    pub errors: BTreeMap<String, String>,
+}
+
+impl BatchCheckpoint {
+    /// Loads an existing checkpoint, or returns an empty one if the file is missing.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read checkpoint {:?}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse checkpoint {:?}", path))
+    }
+
+    /// Writes the checkpoint via a temp file + rename so a crash never leaves
+    /// a truncated file behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path).context("Failed to move checkpoint into place")?;
+        Ok(())
+    }
+
+    pub fn is_done(&self, image_path: &str) -> bool {
+        self.results.contains_key(image_path)
+    }
+
+    /// Writes one CSV row per result: image_path, rank, url, title, is_direct.
+    pub fn write_csv(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = String::from("image_path,rank,url,title,is_direct\n");
+        for (image_path, results) in &self.results {
+            for (rank, result) in results.iter().enumerate() {
+                out.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    csv_escape(image_path),
+                    rank + 1,
+                    csv_escape(&result.url),
+                    csv_escape(&result.title),
+                    result.is_direct
+                ));
+            }
+        }
+        fs::write(path, out)?;
+        Ok(())
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Summary returned to Python when a batch finishes or is cancelled.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchSummary {
+    pub processed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub cancelled: bool,
+    pub output_path: Option<String>,
+    pub results: BTreeMap<String, Vec<SearchResult>>,
+}
+
+/// Resolves the batch image list from `image_paths` or `directory` (+ `extensions`,
+/// `recursive`). Returns `None` when the config describes a single search.
+pub fn collect_batch_paths(config: &Value) -> Result<Option<Vec<String>>> {
+    if let Some(paths) = config.get("image_paths").and_then(|v| v.as_array()) {
+        return Ok(Some(
+            paths
+                .iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect(),
+        ));
+    }
+
+    let Some(directory) = config.get("directory").and_then(|v| v.as_str()) else {
+        return Ok(None);
+    };
+    if !Path::new(directory).is_dir() {
+        return Err(anyhow::anyhow!("Directory not found: {}", directory));
+    }
+
+    let extensions: Vec<String> = config
+        .get("extensions")
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v.as_str())
+                .map(|e| e.trim_start_matches('.').to_lowercase())
+                .collect()
+        })
+        .unwrap_or_else(|| {
+            DEFAULT_BATCH_EXTENSIONS
+                .iter()
+                .map(|e| e.to_string())
+                .collect()
+        });
+    let recursive = config
+        .get("recursive")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let walker = if recursive {
+        WalkDir::new(directory)
+    } else {
+        WalkDir::new(directory).max_depth(1)
+    };
+
+    let mut paths: Vec<String> = walker
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            e.path()
+                .extension()
+                .and_then(|s| s.to_str())
+                .map(|ext| extensions.contains(&ext.to_lowercase()))
+                .unwrap_or(false)
+        })
+        .map(|e| e.path().to_string_lossy().to_string())
+        .collect();
+    paths.sort();
+    Ok(Some(paths))
+}
+
+/// Runs `engine` over every image not already present in the checkpoint,
+/// saving progress after each one so an interrupted run can be resumed.
+pub async fn run_batch<E: ReverseSearchEngine>(
+    py: Python<'_>,
+    engine: &mut E,
+    image_paths: &[String],
+    options: &BatchOptions,
+    callback_obj: &Py<PyAny>,
+) -> Result<BatchSummary> {
+    let mut checkpoint = match &options.output_path {
+        Some(path) => BatchCheckpoint::load(path)?,
+        None => BatchCheckpoint::default(),
+    };
+
+    let pending: Vec<&String> = image_paths
+        .iter()
+        .filter(|p| !checkpoint.is_done(p))
+        .collect();
+    let skipped = image_paths.len() - pending.len();
+    if skipped > 0 {
+        emit_status(
+            py,
+            callback_obj,
+            &format!("Resuming batch: {} image(s) already done.", skipped),
+        )?;
+    }
+
+    let mut summary = BatchSummary {
+        processed: 0,
+        skipped,
+        failed: 0,
+        cancelled: false,
+        output_path: options
+            .output_path
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string()),
+        results: BTreeMap::new(),
+    };
+
+    for (i, image_path) in pending.iter().enumerate() {
+        if is_cancelled(py, callback_obj)? {
+            emit_status(py, callback_obj, "Batch search cancelled.")?;
+            summary.cancelled = true;
+            break;
+        }
+
+        emit_status(
+            py,
+            callback_obj,
+            &format!(
+                "--- [{}] Image {}/{}: {} ---",
+                engine.name(),
+                i + 1,
+                pending.len(),
+                image_path
+            ),
+        )?;
+
+        let outcome = if Path::new(image_path.as_str()).exists() {
+            let input = SearchInput::File(image_path.to_string());
+            engine.search(py, callback_obj, &input).await
+        } else {
+            Err(anyhow::anyhow!("Image not found: {}", image_path))
+        };
+
+        let result_count = match outcome {
+            Ok(SearchOutcome {
+                status: SearchStatus::CaptchaBlocked,
+                ..
+            }) => {
+                emit_status(py, callback_obj, "Search blocked by CAPTCHA.")?;
+                checkpoint
+                    .errors
+                    .insert(image_path.to_string(), "CAPTCHA blocked".to_string());
+                summary.failed += 1;
+                0
+            }
+            Ok(SearchOutcome { results, .. }) => {
+                let count = results.len();
+                checkpoint.errors.remove(image_path.as_str());
+                checkpoint.results.insert(image_path.to_string(), results);
+                summary.processed += 1;
+                count
+            }
+            Err(e) => {
+                emit_status(py, callback_obj, &format!("Search failed: {}", e))?;
+                checkpoint
+                    .errors
+                    .insert(image_path.to_string(), e.to_string());
+                summary.failed += 1;
+                0
+            }
+        };
+
+        if let Some(path) = &options.output_path {
+            checkpoint.save(path)?;
+        }
+        if let Some(path) = &options.csv_path {
+            checkpoint.write_csv(path)?;
+        }
+
+        callback_obj.call_method1(
+            py,
+            "on_image_completed",
+            (image_path.as_str(), result_count),
+        )?;
+
+        if i + 1 < pending.len() && !sleep_unless_cancelled(py, callback_obj, options.delay).await?
+        {
+            emit_status(py, callback_obj, "Batch search cancelled.")?;
+            summary.cancelled = true;
+            break;
+        }
+    }
+
+    summary.results = checkpoint.results;
+    Ok(summary)
+}
+
+/// Returns true once the Python side has cleared its `_is_running` flag.
+fn is_cancelled(py: Python<'_>, obj: &Py<PyAny>) -> PyResult<bool> {
+    if let Ok(is_running) = obj.getattr(py, "_is_running") {
+        return Ok(!is_running.extract::<bool>(py)?);
+    }
+    Ok(false)
+}
+
+/// Sleeps in short slices; returns false if cancelled before `delay` elapsed.
+async fn sleep_unless_cancelled(
+    py: Python<'_>,
+    obj: &Py<PyAny>,
+    delay: Duration,
+) -> PyResult<bool> {
+    let deadline = tokio::time::Instant::now() + delay;
+    loop {
+        if is_cancelled(py, obj)? {
+            return Ok(false);
+        }
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            return Ok(true);
+        }
+        tokio::time::sleep((deadline - now).min(Duration::from_millis(250))).await;
+    }
+}
+
+fn emit_status(py: Python<'_>, obj: &Py<PyAny>, msg: &str) -> PyResult<()> {
+    obj.call_method1(py, "on_status_emitted", (msg,))?;
+    Ok(())
+}
+
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn run_reverse_image_search(
+    py: Python<'_>,
+    config_json: String,
+    callback_obj: Py<PyAny>,
+) -> PyResult<String> {
+    let config: Value = serde_json::from_str(&config_json).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid JSON: {}", e))
+    })?;
+    validate_job("reverse_image_search", &config)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+    let search = ReverseImageSearchRust::new(&config);
+    search.run(py, config_json, callback_obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reverse_search_config() {
+        let config = json!({
+            "browser": "brave"
+        });
+        let search = ReverseImageSearchRust::new(&config);
+        assert_eq!(search.browser_name, "brave");
+        assert!(!search.webdriver.firefox);
+
+        let config = json!({
+            "browser": "firefox",
+            "webdriver_url": "http://localhost:4444"
+        });
+        let search = ReverseImageSearchRust::new(&config);
+        assert!(search.webdriver.firefox);
+        assert_eq!(search.webdriver.url, "http://localhost:4444");
+    }
+
+    #[test]
+    fn test_reverse_search_defaults() {
+        let config = json!({});
+        let search = ReverseImageSearchRust::new(&config);
+        assert_eq!(search.browser_name, "brave");
+        assert_eq!(search.webdriver.url, "http://localhost:9515");
+    }
+
+    #[test]
+    fn test_parse_dimensions() {
+        assert_eq!(parse_dimensions("1920 × 1080"), Some((1920, 1080)));
+        assert_eq!(parse_dimensions("Size: 800x600 px"), Some((800, 600)));
+        assert_eq!(
+            parse_dimensions("see 1024 X 768, or more"),
+            Some((1024, 768))
+        );
+        assert_eq!(parse_dimensions("no size here"), None);
+        assert_eq!(parse_dimensions("v2x3 build 4x"), None);
+    }
+
+    #[test]
+    fn test_extract_result_from_card_fixture() {
+        let html = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/reverse_search/google_result_card.html"
+        ));
+        let result = extract_result_from_html("https://art.example.com/posts/123", html);
+        assert_eq!(
+            result,
+            SearchResult {
+                url: "https://art.example.com/posts/123".to_string(),
+                title: "Sunset over the bay - Example Art".to_string(),
+                is_direct: false,
+                width: Some(1920),
+                height: Some(1080),
+                thumbnail_url: Some(
+                    "https://encrypted-tbn0.gstatic.com/images?q=tbn:abc123".to_string()
+                ),
+                source_domain: "art.example.com".to_string(),
+                engine: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_extract_result_from_bare_link_fixture() {
+        let html = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/reverse_search/google_result_link.html"
+        ));
+        let mut result =
+            extract_result_from_html("https://cdn.example.org/full/image.PNG?w=1", html);
+        assert_eq!(result.title, "Result");
+        assert_eq!(result.width, None);
+        assert_eq!(result.height, None);
+        assert_eq!(result.thumbnail_url, None);
+        assert!(result.is_direct);
+        assert_eq!(result.source_domain, "cdn.example.org");
+
+        // What the link lacks comes from its result card
+        let card = extract_result_from_html(
+            "https://cdn.example.org/full/image.PNG?w=1",
+            r#"<div><img data-src="https://thumbs.test/1.jpg"><span>640 × 480</span></div>"#,
+        );
+        result.fill_missing(card);
+        assert_eq!((result.width, result.height), (Some(640), Some(480)));
+        assert_eq!(
+            result.thumbnail_url.as_deref(),
+            Some("https://thumbs.test/1.jpg")
+        );
+        assert_eq!(result.title, "Result");
+    }
+
+    #[test]
+    fn test_search_result_json() {
+        let mut result = SearchResult::new("https://www.pixiv.net/artworks/1");
+        result.width = Some(800);
+        result.height = Some(600);
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            json!({
+                "url": "https://www.pixiv.net/artworks/1",
+                "title": "",
+                "is_direct": false,
+                "width": 800,
+                "height": 600,
+                "thumbnail_url": null,
+                "source_domain": "pixiv.net"
+            })
+        );
+
+        // Results saved before they were structured still load
+        let old: SearchResult = serde_json::from_value(json!({
+            "url": "https://x.test/a.jpg",
+            "title": "A",
+            "is_direct": true,
+            "resolution": "Unknown"
+        }))
+        .unwrap();
+        assert_eq!(old.title, "A");
+        assert_eq!(old.width, None);
+    }
+
+    #[test]
+    fn test_max_results_from_config() {
+        assert_eq!(max_results_from_config(&json!({})), 20);
+        assert_eq!(max_results_from_config(&json!({"max_results": 75})), 75);
+        assert_eq!(max_results_from_config(&json!({"max_results": 0})), 1);
+    }
+
+    #[test]
+    fn test_search_input_validation() {
+        let temp = tempdir().unwrap();
+        let local = temp.path().join("query.jpg");
+        fs::write(&local, "x").unwrap();
+        let local_str = local.to_str().unwrap();
+
+        assert_eq!(
+            SearchInput::from_config(&json!({"image_path": local_str})).unwrap(),
+            SearchInput::File(local_str.to_string())
+        );
+        assert_eq!(
+            SearchInput::from_config(&json!({"image_url": "https://x.test/a.jpg"})).unwrap(),
+            SearchInput::Url("https://x.test/a.jpg".to_string())
+        );
+
+        let both = SearchInput::from_config(
+            &json!({"image_path": local_str, "image_url": "https://x.test/a.jpg"}),
+        );
+        assert!(both.unwrap_err().to_string().contains("not both"));
+
+        let neither = SearchInput::from_config(&json!({"image_path": "  "}));
+        assert!(neither
+            .unwrap_err()
+            .to_string()
+            .contains("Missing search input"));
+
+        let missing = SearchInput::from_config(&json!({"image_path": "/no/such/file.jpg"}));
+        assert!(missing.unwrap_err().to_string().contains("Image not found"));
+
+        assert!(SearchInput::from_config(&json!({"image_url": "ftp://x.test/a.jpg"})).is_err());
+        assert!(SearchInput::from_config(&json!({"image_url": "not a url"})).is_err());
+    }
+
+    #[test]
+    fn test_lens_upload_url() {
+        assert_eq!(
+            lens_upload_url("https://x.test/pics/a b.jpg?size=large&v=2").unwrap(),
+            "https://lens.google.com/uploadbyurl?url=https%3A%2F%2Fx.test%2Fpics%2Fa+b.jpg%3Fsize%3Dlarge%26v%3D2&hl=en"
+        );
+    }
+
+    #[test]
+    fn test_engine_search_urls() {
+        let image_url = "https://x.test/pics/a b.jpg?size=large";
+        assert_eq!(
+            bing_search_url(image_url).unwrap(),
+            "https://www.bing.com/images/search?view=detailv2&iss=sbi&q=imgurl%3Ahttps%3A%2F%2Fx.test%2Fpics%2Fa+b.jpg%3Fsize%3Dlarge"
+        );
+        assert_eq!(
+            yandex_search_url(image_url).unwrap(),
+            "https://yandex.com/images/search?rpt=imageview&url=https%3A%2F%2Fx.test%2Fpics%2Fa+b.jpg%3Fsize%3Dlarge"
+        );
+        assert_eq!(
+            tineye_search_url(image_url).unwrap(),
+            "https://tineye.com/search?url=https%3A%2F%2Fx.test%2Fpics%2Fa+b.jpg%3Fsize%3Dlarge"
+        );
+    }
+
+    #[test]
+    fn test_engine_kind_from_config() {
+        assert_eq!(
+            EngineKind::from_config(&json!({})).unwrap(),
+            vec![EngineKind::Google]
+        );
+        assert_eq!(
+            EngineKind::from_config(&json!({"engine": " TinEye "})).unwrap(),
+            vec![EngineKind::TinEye]
+        );
+        assert_eq!(
+            EngineKind::from_config(&json!({"engine": "all"})).unwrap(),
+            EngineKind::ALL.to_vec()
+        );
+        let err = EngineKind::from_config(&json!({"engine": "altavista"})).unwrap_err();
+        assert!(err.to_string().contains("Unknown engine 'altavista'"));
+    }
+
+    fn titled(url: &str, title: &str) -> SearchResult {
+        SearchResult {
+            title: title.to_string(),
+            ..SearchResult::new(url)
+        }
+    }
+
+    #[test]
+    fn test_merge_results_tags_and_dedupes() {
+        let merged = merge_results(vec![
+            (
+                "google",
+                vec![
+                    titled("https://a.test/1", "A"),
+                    titled("https://b.test/2", "B"),
+                ],
+            ),
+            ("bing", vec![]),
+            (
+                "yandex",
+                vec![
+                    titled("https://b.test/2", "B again"),
+                    titled("https://c.test/3", "C"),
+                ],
+            ),
+        ]);
+        let tagged: Vec<_> = merged
+            .iter()
+            .map(|r| (r.url.as_str(), r.title.as_str(), r.engine.as_deref()))
+            .collect();
+        assert_eq!(
+            tagged,
+            vec![
+                ("https://a.test/1", "A", Some("google")),
+                ("https://b.test/2", "B", Some("google")),
+                ("https://c.test/3", "C", Some("yandex")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_combine_statuses() {
+        use SearchStatus::*;
+        assert_eq!(combine_statuses(&[CaptchaBlocked, Completed]), Completed);
+        assert_eq!(
+            combine_statuses(&[CaptchaBlocked, CaptchaBlocked]),
+            CaptchaBlocked
+        );
+        assert_eq!(combine_statuses(&[CaptchaBlocked, Timeout]), Timeout);
+        assert_eq!(combine_statuses(&[Completed]), Completed);
+    }
+
+    #[test]
+    fn test_domain_filter_exclude() {
+        let default = DomainFilter::from_config(&json!({}));
+        assert!(default.is_excluded("https://lens.google.com/search"));
+        assert!(default.is_excluded("https://lh3.googleusercontent.com/x.jpg"));
+        assert!(!default.is_excluded("https://notgoogle.com/page"));
+
+        let filter = DomainFilter::from_config(&json!({
+            "exclude_domains": ["*.pinterest.com", "Spam.Example"]
+        }));
+        assert!(filter.is_excluded("https://pinterest.com/pin/1"));
+        assert!(filter.is_excluded("https://i.pinimg.pinterest.com/a.jpg"));
+        assert!(filter.is_excluded("https://www.spam.example/a"));
+        assert!(!filter.is_excluded("https://notpinterest.com/a"));
+        // An explicit list replaces the defaults
+        assert!(!filter.is_excluded("https://www.google.com/"));
+        assert!(!filter.is_excluded("not a url"));
+    }
+
+    #[test]
+    fn test_domain_filter_prefer_is_stable() {
+        let filter = DomainFilter::from_config(&json!({
+            "prefer_domains": ["*.danbooru.donmai.us", "pixiv.net"]
+        }));
+        let mut results: Vec<_> = [
+            "https://a.test/1",
+            "https://www.pixiv.net/artworks/1",
+            "https://b.test/2",
+            "https://danbooru.donmai.us/posts/5",
+            "https://c.test/3",
+        ]
+        .into_iter()
+        .map(SearchResult::new)
+        .collect();
+        filter.sort_preferred(&mut results);
+        let urls: Vec<_> = results.iter().map(|r| r.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://www.pixiv.net/artworks/1",
+                "https://danbooru.donmai.us/posts/5",
+                "https://a.test/1",
+                "https://b.test/2",
+                "https://c.test/3",
+            ]
+        );
+    }
+
+    /// Replays scripted (results, captcha) answers, repeating the last one.
+    struct ScriptedProbe {
+        steps: Vec<(bool, bool)>,
+        calls: usize,
+    }
+
+    impl ScriptedProbe {
+        fn step(&self) -> (bool, bool) {
+            self.steps[self.calls.min(self.steps.len() - 1)]
+        }
+    }
+
+    impl PageProbe for ScriptedProbe {
+        async fn results_present(&mut self) -> bool {
+            let (results, _) = self.step();
+            results
+        }
+        async fn captcha_present(&mut self) -> bool {
+            let (_, captcha) = self.step();
+            self.calls += 1;
+            captcha
+        }
+    }
+
+    fn captcha_options(wait: bool) -> CaptchaOptions {
+        CaptchaOptions {
+            results_timeout: Duration::from_millis(30),
+            wait_for_manual_solve: wait,
+            manual_solve_timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn run_wait(steps: Vec<(bool, bool)>, options: CaptchaOptions) -> (SearchStatus, usize) {
+        let rt = Runtime::new().unwrap();
+        let mut probe = ScriptedProbe { steps, calls: 0 };
+        let mut captcha_events = 0;
+        let mut on_captcha = || -> Result<()> {
+            captcha_events += 1;
+            Ok(())
+        };
+        let status = rt
+            .block_on(wait_for_results(
+                &mut probe,
+                &options,
+                Duration::from_millis(1),
+                &mut on_captcha,
+            ))
+            .unwrap();
+        (status, captcha_events)
+    }
+
+    #[test]
+    fn test_wait_for_results_statuses() {
+        assert_eq!(
+            run_wait(vec![(false, false), (true, false)], captcha_options(false)),
+            (SearchStatus::Completed, 0)
+        );
+        assert_eq!(
+            run_wait(vec![(false, false)], captcha_options(false)),
+            (SearchStatus::Timeout, 0)
+        );
+        assert_eq!(
+            run_wait(vec![(false, true)], captcha_options(false)),
+            (SearchStatus::CaptchaBlocked, 1)
+        );
+    }
+
+    #[test]
+    fn test_wait_for_results_manual_solve() {
+        // CAPTCHA shows for a while, then the user solves it and results load.
+        let mut steps = vec![(false, true); 50];
+        steps.push((true, false));
+        assert_eq!(
+            run_wait(steps, captcha_options(true)),
+            (SearchStatus::Completed, 1)
+        );
+
+        let mut unsolved = captcha_options(true);
+        unsolved.manual_solve_timeout = Duration::from_millis(20);
+        assert_eq!(
+            run_wait(vec![(false, true)], unsolved),
+            (SearchStatus::CaptchaBlocked, 1)
+        );
+    }
+
+    #[test]
+    fn test_captcha_options_require_visible_browser() {
+        let opts = CaptchaOptions::from_config(&json!({"wait_for_manual_solve": true}));
+        assert!(opts.wait_for_manual_solve);
+        assert_eq!(opts.results_timeout, Duration::from_secs(50));
+
+        let headless =
+            CaptchaOptions::from_config(&json!({"wait_for_manual_solve": true, "headless": true}));
+        assert!(!headless.wait_for_manual_solve);
+
+        // A runaway timeout is clamped rather than overflowing the deadline
+        let huge = CaptchaOptions::from_config(&json!({"manual_solve_timeout_secs": 1e20}));
+        assert_eq!(huge.manual_solve_timeout, crate::web::config::MAX_WAIT);
+    }
+
+    #[test]
+    fn test_batch_options_delay() {
+        assert_eq!(
+            BatchOptions::from_config(&json!({})).delay,
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            BatchOptions::from_config(&json!({"delay_secs": -1.0})).delay,
+            Duration::ZERO
+        );
+        assert_eq!(
+            BatchOptions::from_config(&json!({"delay_secs": 1e20})).delay,
+            crate::web::config::MAX_WAIT
+        );
+    }
+
+    #[test]
+    fn test_search_outcome_json() {
+        let outcome = SearchOutcome {
+            status: SearchStatus::CaptchaBlocked,
+            results: vec![],
+        };
+        assert_eq!(
+            serde_json::to_value(&outcome).unwrap(),
+            json!({"status": "captcha_blocked", "results": []})
+        );
+    }
+
+    #[test]
+    fn test_download_options() {
+        assert!(DownloadOptions::from_config(&json!({})).is_none());
+        assert!(DownloadOptions::from_config(&json!({"download": {"enabled": false}})).is_none());
+
+        let opts = DownloadOptions::from_config(&json!({"download": {"enabled": true}})).unwrap();
+        assert_eq!(opts.top_n, 3);
+        assert_eq!(opts.dest_dir, PathBuf::from("downloads"));
+        assert_eq!(opts.min_resolution, None);
+
+        let opts = DownloadOptions::from_config(&json!({
+            "download": {"enabled": true, "top_n": 1, "dest_dir": "out", "min_resolution": "800x600"}
+        }))
+        .unwrap();
+        assert_eq!(opts.min_resolution, Some((800, 600)));
+
+        let opts = DownloadOptions::from_config(&json!({
+            "download": {"enabled": true, "min_resolution": {"width": 640, "height": 480}}
+        }))
+        .unwrap();
+        assert_eq!(opts.min_resolution, Some((640, 480)));
+    }
+
+    #[test]
+    fn test_collect_batch_paths() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("b.PNG"), "x").unwrap();
+        fs::write(temp.path().join("a.jpg"), "x").unwrap();
+        fs::write(temp.path().join("notes.txt"), "x").unwrap();
+
+        assert!(collect_batch_paths(&json!({"image_path": "x.jpg"}))
+            .unwrap()
+            .is_none());
+
+        let paths = collect_batch_paths(&json!({"directory": temp.path().to_str().unwrap()}))
+            .unwrap()
+            .unwrap();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|p| Path::new(p).file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a.jpg", "b.PNG"]);
+
+        let explicit = collect_batch_paths(&json!({"image_paths": ["1.jpg", "2.jpg"]}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(explicit, vec!["1.jpg", "2.jpg"]);
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip_and_csv() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("out").join("results.json");
+
+        assert_eq!(
+            BatchCheckpoint::load(&path).unwrap(),
+            BatchCheckpoint::default()
+        );
+
+        let mut checkpoint = BatchCheckpoint::default();
+        checkpoint.results.insert(
+            "a.jpg".to_string(),
+            vec![titled("https://x.test/a.jpg", "A, \"quoted\"")],
+        );
+        checkpoint
+            .errors
+            .insert("b.jpg".to_string(), "boom".to_string());
+        checkpoint.save(&path).unwrap();
+
+        let loaded = BatchCheckpoint::load(&path).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert!(loaded.is_done("a.jpg"));
+        assert!(!loaded.is_done("b.jpg"));
+
+        let csv_path = temp.path().join("results.csv");
+        loaded.write_csv(&csv_path).unwrap();
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        assert_eq!(
+            csv,
+            "image_path,rank,url,title,is_direct\na.jpg,1,https://x.test/a.jpg,\"A, \"\"quoted\"\"\",true\n"
+        );
     }
 }
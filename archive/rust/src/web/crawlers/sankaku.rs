@@ -1,7 +1,9 @@
 use super::image_board_crawler::Crawler;
+use crate::web::clients::retry::check_status;
 use anyhow::{Context, Result};
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, Response};
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST};
+use reqwest::StatusCode;
 use serde_json::Value;
 
 pub struct SankakuCrawlerImpl {
@@ -13,6 +15,8 @@ pub struct SankakuCrawlerImpl {
     pub api_key: Option<String>,
     pub extra_params: Vec<(String, String)>,
     pub token: std::cell::RefCell<Option<String>>,
+    /// Trades for a new access token once the current one expires
+    pub refresh_token: std::cell::RefCell<Option<String>>,
 }
 
 impl SankakuCrawlerImpl {
@@ -50,6 +54,7 @@ impl SankakuCrawlerImpl {
             api_key,
             extra_params,
             token: std::cell::RefCell::new(None),
+            refresh_token: std::cell::RefCell::new(None),
         }
     }
 
@@ -62,7 +67,25 @@ impl SankakuCrawlerImpl {
             "login": self.username,
             "password": self.api_key,
         });
+        self.request_token(client, &payload)
+    }
+
+    /// Swap the refresh token for a new access token, or log in again if
+    /// there isn't one or it's been refused too
+    pub fn reauthenticate(&self, client: &Client) -> Result<()> {
+        let refresh_token = self.refresh_token.borrow().clone();
+        if let Some(refresh_token) = refresh_token {
+            let payload = serde_json::json!({ "refresh_token": refresh_token });
+            if self.request_token(client, &payload).is_ok() {
+                return Ok(());
+            }
+        }
+        *self.token.borrow_mut() = None;
+        *self.refresh_token.borrow_mut() = None;
+        self.authenticate(client)
+    }
 
+    fn request_token(&self, client: &Client, payload: &Value) -> Result<()> {
         let mut headers = HeaderMap::new();
         headers.insert(HOST, HeaderValue::from_static("login.sankakucomplex.com"));
         headers.insert(
@@ -72,22 +95,45 @@ impl SankakuCrawlerImpl {
 
         let response = client
             .post(&self.login_url)
-            .json(&payload)
+            .json(payload)
             .headers(headers)
             .send()
             .context("Auth request failed")?;
 
         response.error_for_status_ref().context("Auth failed")?;
         let data: Value = response.json().context("Failed to parse auth response")?;
+        self.store_tokens(&data);
+
+        Ok(())
+    }
 
+    /// Keep the access and refresh tokens from an auth response; a response
+    /// without a refresh token leaves the old one in place
+    fn store_tokens(&self, data: &Value) {
         if let (Some(token), Some(token_type)) = (
             data.get("access_token").and_then(|v| v.as_str()),
             data.get("token_type").and_then(|v| v.as_str()),
         ) {
             *self.token.borrow_mut() = Some(format!("{} {}", token_type, token));
         }
+        if let Some(refresh_token) = data.get("refresh_token").and_then(|v| v.as_str()) {
+            *self.refresh_token.borrow_mut() = Some(refresh_token.to_string());
+        }
+    }
 
-        Ok(())
+    fn send_with_token(
+        &self,
+        client: &Client,
+        endpoint: &str,
+        params: &[(String, String)],
+    ) -> Result<Response> {
+        let mut request = client.get(endpoint).query(params);
+
+        if let Some(token) = self.token.borrow().as_ref() {
+            request = request.header(AUTHORIZATION, token);
+        }
+
+        request.send().context("Request failed")
     }
 }
 
@@ -118,14 +164,14 @@ impl Crawler for SankakuCrawlerImpl {
             params.push((k.clone(), v.clone()));
         }
 
-        let mut request = client.get(&endpoint).query(&params);
-
-        if let Some(token) = self.token.borrow().as_ref() {
-            request = request.header(AUTHORIZATION, token);
+        let mut response = self.send_with_token(client, &endpoint, &params)?;
+        // Access tokens expire during long crawls; renew once and try again
+        if response.status() == StatusCode::UNAUTHORIZED && self.username.is_some() {
+            self.reauthenticate(client)
+                .context("Token expired and re-authentication failed")?;
+            response = self.send_with_token(client, &endpoint, &params)?;
         }
-
-        let response = request.send().context("Request failed")?;
-        response.error_for_status_ref().context("Bad status")?;
+        let response = check_status(response)?;
 
         let data: Value = response.json().context("Failed to parse JSON")?;
 
@@ -138,6 +184,19 @@ impl Crawler for SankakuCrawlerImpl {
         }
     }
 
+    fn download_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = self
+            .token
+            .borrow()
+            .as_deref()
+            .and_then(|t| HeaderValue::from_str(t).ok())
+        {
+            headers.insert(AUTHORIZATION, token);
+        }
+        headers
+    }
+
     fn extract_file_url(&self, post: &Value) -> Option<String> {
         post.get("file_url")
             .or_else(|| post.get("sample_url"))
@@ -0,0 +1,532 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::time::Duration;
+
+/// One step of an image crawler sequence, from `{"type": ..., "param": ...}`
+/// in the crawl config. Steps act on the current element, starting with the
+/// element the sequence runs for; optional selectors pick another element
+/// on the page instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    FindParentLink,
+    OpenLinkInNewTab,
+    DownloadImage,
+    /// `key:selector`
+    ScrapeText {
+        key: String,
+        selector: String,
+    },
+    Wait(Duration),
+    CloseCurrentTab,
+    ClickByText(String),
+    ClickByCss(String),
+    Hover(Option<String>),
+    ScrollIntoView(Option<String>),
+    /// `key:attribute`, or just `attribute` to save it under its own name
+    ExtractAttribute {
+        key: String,
+        attribute: String,
+    },
+    GoBack,
+    /// A frame's selector, or none for the top of the page
+    SwitchToFrame(Option<String>),
+    DownloadAllInContainer(Option<String>),
+}
+
+impl Action {
+    pub fn parse(value: &Value) -> Result<Self> {
+        let action_type = value.get("type").and_then(|v| v.as_str()).unwrap_or("");
+        // The GUI sends numbers for some parameters
+        let param = match value.get("param") {
+            Some(Value::String(s)) => s.trim().to_string(),
+            Some(Value::Number(n)) => n.to_string(),
+            _ => String::new(),
+        };
+        let optional = || Some(param.clone()).filter(|p| !p.is_empty());
+        let required =
+            || optional().ok_or_else(|| anyhow!("\"{}\" needs a parameter", action_type));
+
+        Ok(match action_type {
+            "Find Parent Link (<a>)" => Action::FindParentLink,
+            "Open Link in New Tab" => Action::OpenLinkInNewTab,
+            "Download Image from Element" => Action::DownloadImage,
+            "Scrape Text (Saves to JSON)" => {
+                let Some((key, selector)) = param.split_once(':') else {
+                    bail!("\"{}\" needs key:selector, got \"{}\"", action_type, param);
+                };
+                Action::ScrapeText {
+                    key: key.trim().to_string(),
+                    selector: selector.trim().to_string(),
+                }
+            }
+            "Wait X Seconds" => {
+                let secs = param
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|s| s.is_finite() && *s >= 0.0)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "\"{}\" needs a number of seconds, got \"{}\"",
+                            action_type,
+                            param
+                        )
+                    })?;
+                Action::Wait(Duration::from_secs_f64(secs))
+            }
+            "Close Current Tab" => Action::CloseCurrentTab,
+            "Click Element by Text" => Action::ClickByText(required()?),
+            "Click Element by CSS" => Action::ClickByCss(required()?),
+            "Hover Element" => Action::Hover(optional()),
+            "Scroll Into View" => Action::ScrollIntoView(optional()),
+            "Extract Attribute (saves to JSON)" => {
+                let param = required()?;
+                let (key, attribute) = param.split_once(':').unwrap_or((&param, &param));
+                Action::ExtractAttribute {
+                    key: key.trim().to_string(),
+                    attribute: attribute.trim().to_string(),
+                }
+            }
+            "Go Back" => Action::GoBack,
+            "Switch to Frame" => Action::SwitchToFrame(optional()),
+            "Download All Images in Container" => Action::DownloadAllInContainer(optional()),
+            "" => bail!("Sequence step without a type"),
+            other => bail!("Unknown action type \"{}\"", other),
+        })
+    }
+
+    /// The steps of a `sequence` array, and an error for each entry that
+    /// isn't one. Bad entries are left out and the rest still run.
+    pub fn parse_sequence(value: Option<&Value>) -> (Vec<Action>, Vec<String>) {
+        let mut actions = Vec::new();
+        let mut errors = Vec::new();
+        for (idx, step) in value
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            match Action::parse(step) {
+                Ok(action) => actions.push(action),
+                Err(e) => errors.push(format!("Sequence step {}: {}", idx + 1, e)),
+            }
+        }
+        (actions, errors)
+    }
+}
+
+/// What a sequence needs from the browser. Implemented over the WebDriver
+/// session for crawls, and by a recording fake in the tests.
+#[allow(async_fn_in_trait)]
+pub trait SequenceBrowser {
+    type Element: Clone;
+
+    /// The first element on the page matching a CSS selector
+    async fn find(&mut self, selector: &str) -> Result<Self::Element>;
+    /// The `<a>` around `element`
+    async fn parent_link(&mut self, element: &Self::Element) -> Result<Self::Element>;
+    async fn attribute(&mut self, element: &Self::Element, name: &str) -> Result<Option<String>>;
+    async fn text(&mut self, element: &Self::Element) -> Result<String>;
+    /// The image URLs inside `element`
+    async fn image_urls(&mut self, element: &Self::Element) -> Result<Vec<String>>;
+    async fn click(&mut self, element: &Self::Element) -> Result<()>;
+    async fn click_link_text(&mut self, text: &str) -> Result<()>;
+    async fn hover(&mut self, element: &Self::Element) -> Result<()>;
+    async fn scroll_into_view(&mut self, element: &Self::Element) -> Result<()>;
+    /// Opens `url` in a new tab and switches to it
+    async fn open_tab(&mut self, url: &str) -> Result<()>;
+    /// Closes the current tab and switches back to the crawl's tab
+    async fn close_tab(&mut self) -> Result<()>;
+    async fn go_back(&mut self) -> Result<()>;
+    /// Switches into `frame`, or back to the top of the page for None
+    async fn switch_frame(&mut self, frame: Option<&Self::Element>) -> Result<()>;
+    /// Saves the image at `url` with `metadata` beside it; false if it
+    /// wasn't saved
+    async fn download(&mut self, url: &str, metadata: &Map<String, Value>) -> Result<bool>;
+    async fn sleep(&mut self, duration: Duration);
+    fn status(&mut self, message: &str);
+}
+
+/// Runs `actions` in order for `element`, returning how many images were
+/// saved. A failing step ends the sequence with its error. Either way a tab
+/// it opened is closed and a frame it entered is left, so the next element
+/// starts from the page it was found on.
+pub async fn run_sequence<B: SequenceBrowser>(
+    browser: &mut B,
+    element: B::Element,
+    actions: &[Action],
+) -> Result<u32> {
+    let mut state = SequenceState {
+        current: element,
+        scraped: Map::new(),
+        downloaded: 0,
+        tabs_open: 0,
+        in_frame: false,
+    };
+    let mut result = Ok(());
+    for action in actions {
+        result = state.step(browser, action).await;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    while state.tabs_open > 0 {
+        state.tabs_open -= 1;
+        let _ = browser.close_tab().await;
+    }
+    if state.in_frame {
+        let _ = browser.switch_frame(None).await;
+    }
+    result.map(|_| state.downloaded)
+}
+
+struct SequenceState<E> {
+    current: E,
+    /// Saved with every image downloaded after it was scraped
+    scraped: Map<String, Value>,
+    downloaded: u32,
+    tabs_open: u32,
+    in_frame: bool,
+}
+
+impl<E: Clone> SequenceState<E> {
+    async fn target<B: SequenceBrowser<Element = E>>(
+        &self,
+        browser: &mut B,
+        selector: &Option<String>,
+    ) -> Result<E> {
+        match selector {
+            Some(selector) => browser.find(selector).await,
+            None => Ok(self.current.clone()),
+        }
+    }
+
+    async fn save<B: SequenceBrowser<Element = E>>(
+        &mut self,
+        browser: &mut B,
+        url: &str,
+    ) -> Result<()> {
+        if browser.download(url, &self.scraped).await? {
+            self.downloaded += 1;
+        }
+        Ok(())
+    }
+
+    async fn step<B: SequenceBrowser<Element = E>>(
+        &mut self,
+        browser: &mut B,
+        action: &Action,
+    ) -> Result<()> {
+        match action {
+            Action::FindParentLink => {
+                self.current = browser.parent_link(&self.current).await?;
+            }
+            Action::OpenLinkInNewTab => {
+                if let Some(href) = browser.attribute(&self.current, "href").await? {
+                    browser.open_tab(&href).await?;
+                    self.tabs_open += 1;
+                    browser.sleep(Duration::from_secs(2)).await;
+                }
+            }
+            Action::DownloadImage => {
+                let mut url = browser.attribute(&self.current, "src").await?;
+                if url.is_none() {
+                    url = browser.attribute(&self.current, "href").await?;
+                }
+                if let Some(url) = url {
+                    self.save(browser, &url).await?;
+                }
+            }
+            Action::ScrapeText { key, selector } => {
+                let element = browser.find(selector).await?;
+                let text = browser.text(&element).await?;
+                browser.status(&format!("Extracted {}: {}", key, text.trim()));
+                self.scraped
+                    .insert(key.clone(), Value::String(text.trim().to_string()));
+            }
+            Action::Wait(duration) => browser.sleep(*duration).await,
+            Action::CloseCurrentTab => {
+                browser.close_tab().await?;
+                self.tabs_open = self.tabs_open.saturating_sub(1);
+            }
+            Action::ClickByText(text) => {
+                browser.click_link_text(text).await?;
+                browser.sleep(Duration::from_secs(2)).await;
+            }
+            Action::ClickByCss(selector) => {
+                let element = browser.find(selector).await?;
+                browser.click(&element).await?;
+                browser.sleep(Duration::from_secs(2)).await;
+            }
+            Action::Hover(selector) => {
+                let element = self.target(browser, selector).await?;
+                browser.hover(&element).await?;
+                browser.sleep(Duration::from_millis(500)).await;
+                self.current = element;
+            }
+            Action::ScrollIntoView(selector) => {
+                let element = self.target(browser, selector).await?;
+                browser.scroll_into_view(&element).await?;
+                self.current = element;
+            }
+            Action::ExtractAttribute { key, attribute } => {
+                let value = browser.attribute(&self.current, attribute).await?;
+                let value = value.ok_or_else(|| anyhow!("The element has no {}", attribute))?;
+                browser.status(&format!("Extracted {}: {}", key, value));
+                self.scraped.insert(key.clone(), Value::String(value));
+            }
+            Action::GoBack => {
+                browser.go_back().await?;
+                browser.sleep(Duration::from_secs(2)).await;
+            }
+            Action::SwitchToFrame(selector) => match selector {
+                Some(selector) => {
+                    let frame = browser.find(selector).await?;
+                    browser.switch_frame(Some(&frame)).await?;
+                    self.in_frame = true;
+                }
+                None => {
+                    browser.switch_frame(None).await?;
+                    self.in_frame = false;
+                }
+            },
+            Action::DownloadAllInContainer(selector) => {
+                let container = self.target(browser, selector).await?;
+                for url in browser.image_urls(&container).await? {
+                    self.save(browser, &url).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_actions() {
+        let step = |t: &str, p: Value| Action::parse(&json!({"type": t, "param": p}));
+
+        assert_eq!(
+            step("Wait X Seconds", json!(1.5)).unwrap(),
+            Action::Wait(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            step("Wait X Seconds", json!("2")).unwrap(),
+            Action::Wait(Duration::from_secs(2))
+        );
+        assert!(step("Wait X Seconds", json!("soon")).is_err());
+        assert_eq!(
+            step("Scrape Text (Saves to JSON)", json!("title: h1.post-title")).unwrap(),
+            Action::ScrapeText {
+                key: "title".to_string(),
+                selector: "h1.post-title".to_string()
+            }
+        );
+        assert_eq!(
+            step("Extract Attribute (saves to JSON)", json!("alt")).unwrap(),
+            Action::ExtractAttribute {
+                key: "alt".to_string(),
+                attribute: "alt".to_string()
+            }
+        );
+        assert_eq!(
+            step(
+                "Extract Attribute (saves to JSON)",
+                json!("caption:data-title")
+            )
+            .unwrap(),
+            Action::ExtractAttribute {
+                key: "caption".to_string(),
+                attribute: "data-title".to_string()
+            }
+        );
+        assert_eq!(
+            step("Hover Element", Value::Null).unwrap(),
+            Action::Hover(None)
+        );
+        assert_eq!(
+            step("Switch to Frame", json!("iframe#viewer")).unwrap(),
+            Action::SwitchToFrame(Some("iframe#viewer".to_string()))
+        );
+        assert_eq!(
+            step("Download All Images in Container", json!("")).unwrap(),
+            Action::DownloadAllInContainer(None)
+        );
+        assert!(step("Click Element by CSS", json!(" ")).is_err());
+
+        let (actions, errors) = Action::parse_sequence(Some(&json!([
+            {"type": "Find Parent Link (<a>)"},
+            {"type": "Teleport", "param": "somewhere"},
+            {"type": "Go Back", "param": null},
+            {"param": "x"}
+        ])));
+        assert_eq!(actions, vec![Action::FindParentLink, Action::GoBack]);
+        assert_eq!(
+            errors,
+            vec![
+                "Sequence step 2: Unknown action type \"Teleport\"",
+                "Sequence step 4: Sequence step without a type"
+            ]
+        );
+        assert_eq!(Action::parse_sequence(None), (Vec::new(), Vec::new()));
+    }
+
+    /// Records every call; elements are names, and `find` of a selector
+    /// starting with `missing` fails
+    #[derive(Default)]
+    struct FakeBrowser {
+        calls: Vec<String>,
+        downloads: Vec<(String, Map<String, Value>)>,
+    }
+
+    impl SequenceBrowser for FakeBrowser {
+        type Element = String;
+
+        async fn find(&mut self, selector: &str) -> Result<String> {
+            self.calls.push(format!("find {}", selector));
+            if selector.starts_with("missing") {
+                bail!("no such element: {}", selector);
+            }
+            Ok(selector.to_string())
+        }
+        async fn parent_link(&mut self, element: &String) -> Result<String> {
+            self.calls.push(format!("parent_link {}", element));
+            Ok(format!("a>{}", element))
+        }
+        async fn attribute(&mut self, element: &String, name: &str) -> Result<Option<String>> {
+            self.calls.push(format!("attribute {} {}", element, name));
+            Ok(match name {
+                "href" if element.starts_with("a>") => Some(format!("https://site/{}", element)),
+                "src" if !element.starts_with("a>") => Some(format!("https://cdn/{}.png", element)),
+                "alt" => Some(format!("alt of {}", element)),
+                _ => None,
+            })
+        }
+        async fn text(&mut self, element: &String) -> Result<String> {
+            self.calls.push(format!("text {}", element));
+            Ok(format!(" text of {} ", element))
+        }
+        async fn image_urls(&mut self, element: &String) -> Result<Vec<String>> {
+            self.calls.push(format!("image_urls {}", element));
+            Ok(vec!["https://cdn/1.png".into(), "https://cdn/2.png".into()])
+        }
+        async fn click(&mut self, element: &String) -> Result<()> {
+            self.calls.push(format!("click {}", element));
+            Ok(())
+        }
+        async fn click_link_text(&mut self, text: &str) -> Result<()> {
+            self.calls.push(format!("click_link_text {}", text));
+            Ok(())
+        }
+        async fn hover(&mut self, element: &String) -> Result<()> {
+            self.calls.push(format!("hover {}", element));
+            Ok(())
+        }
+        async fn scroll_into_view(&mut self, element: &String) -> Result<()> {
+            self.calls.push(format!("scroll_into_view {}", element));
+            Ok(())
+        }
+        async fn open_tab(&mut self, url: &str) -> Result<()> {
+            self.calls.push(format!("open_tab {}", url));
+            Ok(())
+        }
+        async fn close_tab(&mut self) -> Result<()> {
+            self.calls.push("close_tab".to_string());
+            Ok(())
+        }
+        async fn go_back(&mut self) -> Result<()> {
+            self.calls.push("go_back".to_string());
+            Ok(())
+        }
+        async fn switch_frame(&mut self, frame: Option<&String>) -> Result<()> {
+            self.calls.push(format!("switch_frame {:?}", frame));
+            Ok(())
+        }
+        async fn download(&mut self, url: &str, metadata: &Map<String, Value>) -> Result<bool> {
+            self.calls.push(format!("download {}", url));
+            self.downloads.push((url.to_string(), metadata.clone()));
+            Ok(true)
+        }
+        async fn sleep(&mut self, _duration: Duration) {
+            self.calls.push("sleep".to_string());
+        }
+        fn status(&mut self, message: &str) {
+            self.calls.push(format!("status {}", message));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sequence_runs_in_order() {
+        let actions = vec![
+            Action::FindParentLink,
+            Action::OpenLinkInNewTab,
+            Action::ScrapeText {
+                key: "title".to_string(),
+                selector: "h1".to_string(),
+            },
+            Action::SwitchToFrame(Some("iframe".to_string())),
+            Action::DownloadAllInContainer(Some(".gallery".to_string())),
+            Action::SwitchToFrame(None),
+            Action::CloseCurrentTab,
+            Action::ExtractAttribute {
+                key: "alt".to_string(),
+                attribute: "alt".to_string(),
+            },
+        ];
+        let mut browser = FakeBrowser::default();
+        let saved = run_sequence(&mut browser, "img".to_string(), &actions)
+            .await
+            .unwrap();
+        assert_eq!(saved, 2);
+        assert_eq!(
+            browser.calls,
+            vec![
+                "parent_link img",
+                "attribute a>img href",
+                "open_tab https://site/a>img",
+                "sleep",
+                "find h1",
+                "text h1",
+                "status Extracted title: text of h1",
+                "find iframe",
+                "switch_frame Some(\"iframe\")",
+                "find .gallery",
+                "image_urls .gallery",
+                "download https://cdn/1.png",
+                "download https://cdn/2.png",
+                "switch_frame None",
+                "close_tab",
+                "attribute a>img alt",
+                "status Extracted alt: alt of a>img",
+            ]
+        );
+        // What was scraped before a download is saved with it
+        assert_eq!(browser.downloads[0].1["title"], "text of h1");
+    }
+
+    #[tokio::test]
+    async fn test_sequence_failure_stops_and_cleans_up() {
+        let actions = vec![
+            Action::FindParentLink,
+            Action::OpenLinkInNewTab,
+            Action::SwitchToFrame(Some("iframe".to_string())),
+            Action::ClickByCss("missing-button".to_string()),
+            Action::DownloadImage,
+        ];
+        let mut browser = FakeBrowser::default();
+        let err = run_sequence(&mut browser, "img".to_string(), &actions)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("missing-button"), "{}", err);
+        assert!(browser.downloads.is_empty());
+        // Back to the crawl's tab and out of the frame after the failure
+        assert_eq!(
+            &browser.calls[browser.calls.len() - 3..],
+            &["find missing-button", "close_tab", "switch_frame None"]
+        );
+    }
+}
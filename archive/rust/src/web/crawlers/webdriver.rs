@@ -0,0 +1,221 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::fmt;
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Where the browser crawlers find a WebDriver server unless the config
+/// says otherwise
+pub const DEFAULT_WEBDRIVER_URL: &str = "http://localhost:9515";
+
+/// How long a started driver gets to answer `/status`
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The WebDriver server a crawl talks to: `webdriver_url`, or with
+/// `auto_start_webdriver` a driver started on a free port for the crawl,
+/// geckodriver when `firefox` and chromedriver otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebDriverOptions {
+    pub url: String,
+    pub auto_start: bool,
+    pub firefox: bool,
+}
+
+impl WebDriverOptions {
+    pub fn from_config(config: &Value, browser: &str) -> Self {
+        WebDriverOptions {
+            url: config
+                .get("webdriver_url")
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .unwrap_or(DEFAULT_WEBDRIVER_URL)
+                .to_string(),
+            auto_start: config
+                .get("auto_start_webdriver")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            firefox: browser.eq_ignore_ascii_case("firefox"),
+        }
+    }
+
+    /// The driver executable auto-start looks for on PATH
+    pub fn driver_binary(&self) -> &'static str {
+        if self.firefox {
+            "geckodriver"
+        } else {
+            "chromedriver"
+        }
+    }
+
+    /// The URL to open sessions on, and the driver started for it when
+    /// auto-starting. The crawl must hold on to the service until it ends.
+    pub async fn endpoint(&self) -> Result<(String, Option<DriverService>)> {
+        if !self.auto_start {
+            return Ok((self.url.clone(), None));
+        }
+        let service = DriverService::start(self.driver_binary(), STARTUP_TIMEOUT).await?;
+        Ok((service.url.clone(), Some(service)))
+    }
+}
+
+/// Error for a session that couldn't be opened on the server at `url`
+pub fn connect_error(url: &str, err: impl fmt::Display) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Could not open a browser session on the WebDriver at {} ({}). \
+         Start chromedriver or geckodriver there, or set auto_start_webdriver.",
+        url,
+        err
+    )
+}
+
+/// A driver process started for one crawl. It's killed when dropped, so a
+/// crawl that fails part way doesn't leave it running.
+pub struct DriverService {
+    child: Child,
+    pub url: String,
+}
+
+impl DriverService {
+    /// Starts `binary` (a name on PATH, or a path) on a free port and waits
+    /// until it reports ready
+    pub async fn start(binary: &str, timeout: Duration) -> Result<Self> {
+        let path = which::which(binary).with_context(|| {
+            format!(
+                "{} was not found on PATH; install it or set webdriver_url instead",
+                binary
+            )
+        })?;
+        let port = free_port()?;
+        let child = Command::new(&path)
+            .arg(format!("--port={}", port))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to start {}", path.display()))?;
+
+        let mut service = DriverService {
+            child,
+            url: format!("http://127.0.0.1:{}", port),
+        };
+        service.wait_until_ready(timeout).await?;
+        Ok(service)
+    }
+
+    async fn wait_until_ready(&mut self, timeout: Duration) -> Result<()> {
+        let client = reqwest::Client::new();
+        let status_url = format!("{}/status", self.url);
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                bail!("The WebDriver exited while starting ({})", status);
+            }
+            let response = client
+                .get(&status_url)
+                .timeout(Duration::from_secs(1))
+                .send()
+                .await;
+            if let Ok(response) = response {
+                let status: Value = response.json().await.unwrap_or_default();
+                if status.pointer("/value/ready").and_then(|v| v.as_bool()) == Some(true) {
+                    return Ok(());
+                }
+            }
+            if Instant::now() >= deadline {
+                bail!(
+                    "The WebDriver at {} wasn't ready after {}s",
+                    self.url,
+                    timeout.as_secs()
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+impl Drop for DriverService {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A port nothing is listening on right now
+fn free_port() -> Result<u16> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).context("No free port for the WebDriver")?;
+    Ok(listener.local_addr()?.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_webdriver_options() {
+        let options = WebDriverOptions::from_config(&json!({}), "brave");
+        assert_eq!(options.url, DEFAULT_WEBDRIVER_URL);
+        assert!(!options.auto_start);
+        assert_eq!(options.driver_binary(), "chromedriver");
+
+        let config = json!({
+            "webdriver_url": "http://192.168.1.20:4444",
+            "auto_start_webdriver": true
+        });
+        let options = WebDriverOptions::from_config(&config, "Firefox");
+        assert_eq!(options.url, "http://192.168.1.20:4444");
+        assert!(options.auto_start);
+        assert_eq!(options.driver_binary(), "geckodriver");
+    }
+
+    #[tokio::test]
+    async fn test_driver_service_waits_for_status() {
+        let mut server = mockito::Server::new_async().await;
+        let starting = server
+            .mock("GET", "/status")
+            .with_body(r#"{"value": {"ready": false, "message": "starting"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let ready = server
+            .mock("GET", "/status")
+            .with_body(r#"{"value": {"ready": true, "message": "ChromeDriver ready"}}"#)
+            .create_async()
+            .await;
+
+        // A long-running stand-in for the driver, serving nothing itself
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id();
+        let mut service = DriverService {
+            child,
+            url: server.url(),
+        };
+        service
+            .wait_until_ready(Duration::from_secs(5))
+            .await
+            .unwrap();
+        starting.assert_async().await;
+        ready.assert_async().await;
+
+        drop(service);
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_driver_service_start_failures() {
+        let err = DriverService::start("no-such-webdriver-binary", Duration::from_secs(1))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("not found on PATH"), "{}", err);
+
+        // Exits straight away instead of serving
+        let err = DriverService::start("false", Duration::from_secs(5))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("exited while starting"), "{}", err);
+    }
+}
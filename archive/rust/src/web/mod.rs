@@ -1,30 +1,41 @@
-pub mod crawlers;
-pub mod cloud;
 pub mod clients;
+pub mod cloud;
+pub mod config;
+pub mod crawlers;
+pub mod progress;
 
-#[cfg(feature = "python")]
-use crate::web::crawlers::danbooru::DanbooruCrawlerImpl;
-#[cfg(feature = "python")]
+use crate::web::clients::cookies::CookieSession;
+use crate::web::clients::proxy::ProxyConfig;
+use crate::web::clients::rate_limit::RateLimiter;
 use crate::web::cloud::dropbox_sync::DropboxSyncImpl;
-#[cfg(feature = "python")]
-use crate::web::crawlers::gelbooru::GelbooruCrawlerImpl;
-#[cfg(feature = "python")]
 use crate::web::cloud::google_drive_sync::GoogleDriveSyncImpl;
-#[cfg(feature = "python")]
-use crate::web::crawlers::image_board_crawler::BoardCrawler;
-#[cfg(feature = "python")]
+use crate::web::cloud::local_folder_sync::LocalFolderSyncImpl;
 use crate::web::cloud::one_drive_sync::OneDriveSyncImpl;
+#[cfg(feature = "sftp")]
+use crate::web::cloud::sftp_sync::SftpSyncImpl;
+use crate::web::cloud::sync::{SyncRunner, SyncStats};
+use crate::web::config::validate_job;
+#[cfg(feature = "python")]
+use crate::web::config::ConfigErrors;
 #[cfg(feature = "python")]
+use crate::web::crawlers::crawl_manifest::CrawlManifest;
+use crate::web::crawlers::danbooru::DanbooruCrawlerImpl;
+use crate::web::crawlers::derpibooru::DerpibooruCrawlerImpl;
+use crate::web::crawlers::e621::E621CrawlerImpl;
+use crate::web::crawlers::gelbooru::GelbooruCrawlerImpl;
+use crate::web::crawlers::generic_booru::GenericBooruCrawlerImpl;
+use crate::web::crawlers::image_board_crawler::BoardCrawler;
 use crate::web::crawlers::sankaku::SankakuCrawlerImpl;
+use crate::web::progress::ProgressSink;
 #[cfg(feature = "python")]
-use crate::web::cloud::sync::SyncRunner;
+use crate::web::progress::PyProgressSink;
+use anyhow::{anyhow, Context, Result};
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
-#[cfg(feature = "python")]
 use reqwest::blocking::Client;
-#[cfg(feature = "python")]
+use reqwest::blocking::ClientBuilder;
 use serde_json::Value;
-#[cfg(feature = "python")]
+use std::sync::Arc;
 use std::time::Duration;
 
 #[cfg(feature = "python")]
@@ -32,6 +43,126 @@ pub use crawlers::image_crawler::run_image_crawler;
 #[cfg(feature = "python")]
 pub use crawlers::reverse_image_search::run_reverse_image_search;
 
+/// Crawl the board `crawler_name` (danbooru, gelbooru, sankaku, e621,
+/// derpibooru, or generic for any other booru the config describes) with
+/// `config`, reporting to `sink`. Returns how many images were downloaded.
+/// The crawler and its downloads share one session, with the cookies of
+/// `cookies_file` and `cookies` in the config, through its `proxy` and at
+/// the pace of its `rate_limit`. A config with unknown or mistyped fields
+/// is refused. Blocking; call it off any async runtime.
+pub fn crawl_board(crawler_name: &str, config_val: &Value, sink: &dyn ProgressSink) -> Result<u32> {
+    validate_job("board_crawler", config_val)?;
+    let cookies = CookieSession::from_config(config_val)?;
+    let builder = Client::builder()
+        .timeout(Duration::from_secs(30))
+        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
+        .cookie_provider(cookies.jar());
+    let client = with_proxy(builder, config_val, sink)?
+        .build()
+        .context("Failed to create client")?;
+
+    let mut board_crawler = BoardCrawler::new(config_val);
+    if let Some(limiter) = RateLimiter::from_config(config_val)? {
+        board_crawler = board_crawler.with_rate_limit(Arc::new(limiter));
+    }
+
+    let downloaded = match crawler_name.to_lowercase().as_str() {
+        "danbooru" => {
+            let crawler = DanbooruCrawlerImpl::new(config_val);
+            board_crawler.run(&crawler, &client, sink)
+        }
+        "gelbooru" => {
+            let crawler = GelbooruCrawlerImpl::new(config_val);
+            board_crawler.run(&crawler, &client, sink)
+        }
+        "sankaku" | "sankakucrawler" => {
+            let crawler = SankakuCrawlerImpl::new(config_val);
+            board_crawler.run(&crawler, &client, sink)
+        }
+        "e621" => {
+            let crawler = E621CrawlerImpl::new(config_val);
+            board_crawler.run(&crawler, &client, sink)
+        }
+        "derpibooru" => {
+            let crawler = DerpibooruCrawlerImpl::new(config_val);
+            board_crawler.run(&crawler, &client, sink)
+        }
+        "generic" => {
+            let crawler = GenericBooruCrawlerImpl::new(config_val)?;
+            board_crawler.run(&crawler, &client, sink)
+        }
+        _ => return Err(anyhow!("Unknown crawler: {}", crawler_name)),
+    };
+
+    if let Err(e) = cookies.save() {
+        sink.on_error(&format!("{:#}", e));
+    }
+    Ok(downloaded)
+}
+
+/// Sync `config`'s local folder with `provider_name` (dropbox, google_drive,
+/// one_drive, local or sftp), reporting to `sink`. The cloud APIs are
+/// reached through the config's `proxy`. A config with unknown or mistyped
+/// fields is refused. Blocking; call it off any async runtime.
+pub fn sync_cloud(
+    provider_name: &str,
+    config_val: &Value,
+    sink: &dyn ProgressSink,
+) -> Result<SyncStats> {
+    validate_job("sync", config_val)?;
+    let builder = Client::builder().timeout(Duration::from_secs(60));
+    let client = with_proxy(builder, config_val, sink)?
+        .build()
+        .context("Failed to create client")?;
+
+    let runner = SyncRunner::new(config_val);
+
+    match provider_name.to_lowercase().as_str() {
+        "dropbox" => {
+            let mut cloud = DropboxSyncImpl::new(config_val);
+            runner.run(&mut cloud, &client, sink)
+        }
+        "google_drive" | "google" | "drive" => {
+            let mut cloud = GoogleDriveSyncImpl::new(config_val);
+            runner.run(&mut cloud, &client, sink)
+        }
+        "one_drive" | "onedrive" | "microsoft" => {
+            let mut cloud = OneDriveSyncImpl::new(config_val);
+            runner.run(&mut cloud, &client, sink)
+        }
+        "local" | "local_folder" => {
+            let mut cloud = LocalFolderSyncImpl::new(config_val);
+            runner.run(&mut cloud, &client, sink)
+        }
+        #[cfg(feature = "sftp")]
+        "sftp" => {
+            let mut cloud = SftpSyncImpl::new(config_val);
+            runner.run(&mut cloud, &client, sink)
+        }
+        #[cfg(not(feature = "sftp"))]
+        "sftp" => Err(anyhow!(
+            "SFTP sync is not enabled: build base with the `sftp` feature"
+        )),
+        _ => Err(anyhow!("Unknown cloud provider: {}", provider_name)),
+    }
+}
+
+/// Sends everything `builder`'s client makes through the config's `proxy`,
+/// if it has one
+fn with_proxy(
+    builder: ClientBuilder,
+    config_val: &Value,
+    sink: &dyn ProgressSink,
+) -> Result<ClientBuilder> {
+    Ok(match ProxyConfig::from_config(config_val)? {
+        Some(proxy) => {
+            sink.on_status(&format!("Using proxy {}", proxy.display()));
+            builder.proxy(proxy.to_reqwest()?)
+        }
+        None => builder,
+    })
+}
+
 #[cfg(feature = "python")]
 #[pyfunction]
 pub fn run_board_crawler(
@@ -44,32 +175,27 @@ pub fn run_board_crawler(
         PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid JSON: {}", e))
     })?;
 
-    let client = Client::builder()
-        .timeout(Duration::from_secs(30))
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
-        .build()
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Failed to create client: {}", e)))?;
-
-    let board_crawler = BoardCrawler::new(&config_val);
+    let sink = PyProgressSink::new(py, callback_obj);
+    let downloaded = crawl_board(&crawler_name, &config_val, &sink)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:#}", e)))?;
+    sink.finish(downloaded)
+}
 
-    match crawler_name.to_lowercase().as_str() {
-        "danbooru" => {
-            let crawler = DanbooruCrawlerImpl::new(&config_val);
-            board_crawler.run(py, &crawler, &client, callback_obj)
-        }
-        "gelbooru" => {
-            let crawler = GelbooruCrawlerImpl::new(&config_val);
-            board_crawler.run(py, &crawler, &client, callback_obj)
-        }
-        "sankaku" | "sankakucrawler" => {
-            let crawler = SankakuCrawlerImpl::new(&config_val);
-            board_crawler.run(py, &crawler, &client, callback_obj)
-        }
-        _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-            "Unknown crawler: {}",
-            crawler_name
-        ))),
-    }
+/// The crawl manifest in `download_dir` as JSON: for each crawler and tags,
+/// the last page completed and the post ids downloaded. Empty when nothing
+/// has been crawled there.
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn get_crawl_state(download_dir: String) -> PyResult<String> {
+    let path = CrawlManifest::path(std::path::Path::new(&download_dir));
+    let manifest = CrawlManifest::load(&path)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("{:#}", e)))?;
+    serde_json::to_string(&manifest).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+            "JSON serialization error: {}",
+            e
+        ))
+    })
 }
 
 #[cfg(feature = "python")]
@@ -84,39 +210,15 @@ pub fn run_sync(
         PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid JSON: {}", e))
     })?;
 
-    let client = Client::builder()
-        .timeout(Duration::from_secs(60))
-        .build()
-        .map_err(|e| {
-            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
-                "Failed to create client: {}",
-                e
-            ))
-        })?;
-
-    let runner = SyncRunner::new(&config_val);
-
-    let stats = match provider_name.to_lowercase().as_str() {
-        "dropbox" => {
-            let mut cloud = DropboxSyncImpl::new(&config_val);
-            runner.run(py, &mut cloud, &client, callback_obj)
+    let sink = PyProgressSink::new(py, callback_obj);
+    let result = sync_cloud(&provider_name, &config_val, &sink);
+    let stats = sink.finish(result)?.map_err(|e| {
+        if e.is::<ConfigErrors>() {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string())
+        } else {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Cloud sync Error: {}", e))
         }
-        "google_drive" | "google" | "drive" => {
-            let mut cloud = GoogleDriveSyncImpl::new(&config_val);
-            runner.run(py, &mut cloud, &client, callback_obj)
-        }
-        "one_drive" | "onedrive" | "microsoft" => {
-            let mut cloud = OneDriveSyncImpl::new(&config_val);
-            runner.run(py, &mut cloud, &client, callback_obj)
-        }
-        _ => {
-            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                "Unknown cloud provider: {}",
-                provider_name
-            )))
-        }
-    }
-    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Cloud sync Error: {}", e)))?;
+    })?;
 
     serde_json::to_string(&stats).map_err(|e| {
         PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
@@ -125,3 +227,16 @@ pub fn run_sync(
         ))
     })
 }
+
+/// Checks `config_json` for the job `job_type` (board_crawler, sync,
+/// image_crawler, reverse_image_search or web_requests) before it's run.
+/// Raises ValueError listing every unknown or invalid field.
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn validate_job_config(job_type: String, config_json: String) -> PyResult<()> {
+    let config_val: Value = serde_json::from_str(&config_json).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid JSON: {}", e))
+    })?;
+    validate_job(&job_type, &config_val)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))
+}
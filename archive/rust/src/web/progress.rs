@@ -0,0 +1,115 @@
+use crate::web::cloud::oauth::RefreshedToken;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use std::cell::RefCell;
+
+/// Receives what a crawl or sync reports while it runs, and tells it when
+/// to stop. The Python bindings adapt their callback objects to this; the
+/// desktop app forwards to its event system.
+pub trait ProgressSink {
+    fn on_status(&self, message: &str);
+    fn on_error(&self, message: &str);
+    fn on_image_saved(&self, path: &str);
+    /// How much of the file `name` a transfer has sent so far
+    fn on_transfer_progress(&self, _name: &str, _sent: u64, _total: u64) {}
+    /// A sync renewed its access token; the old one is no good any more
+    fn on_token_refreshed(&self, _token: &RefreshedToken) {}
+    /// Checked between pages and files; `true` ends the run early
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Forwards to a Python callback object's `on_status_emitted`,
+/// `on_error_emitted`, `on_image_saved`, `on_transfer_progress` and
+/// `on_token_refreshed` (given the tokens as JSON), and stops once its
+/// `_is_running` turns false. The first exception a callback raises also
+/// stops the run and is returned by `finish`.
+#[cfg(feature = "python")]
+pub struct PyProgressSink<'py> {
+    py: Python<'py>,
+    callback_obj: Py<PyAny>,
+    error: RefCell<Option<PyErr>>,
+}
+
+#[cfg(feature = "python")]
+impl<'py> PyProgressSink<'py> {
+    pub fn new(py: Python<'py>, callback_obj: Py<PyAny>) -> Self {
+        PyProgressSink {
+            py,
+            callback_obj,
+            error: RefCell::new(None),
+        }
+    }
+
+    /// `value`, unless a callback raised along the way
+    pub fn finish<T>(self, value: T) -> PyResult<T> {
+        match self.error.into_inner() {
+            Some(err) => Err(err),
+            None => Ok(value),
+        }
+    }
+
+    fn record(&self, result: PyResult<()>) {
+        if let Err(err) = result {
+            self.error.borrow_mut().get_or_insert(err);
+        }
+    }
+}
+
+#[cfg(feature = "python")]
+impl ProgressSink for PyProgressSink<'_> {
+    fn on_status(&self, message: &str) {
+        let result = self
+            .callback_obj
+            .call_method1(self.py, "on_status_emitted", (message,));
+        self.record(result.map(|_| ()));
+    }
+
+    fn on_error(&self, message: &str) {
+        let result = self
+            .callback_obj
+            .call_method1(self.py, "on_error_emitted", (message,));
+        self.record(result.map(|_| ()));
+    }
+
+    fn on_image_saved(&self, path: &str) {
+        // Optional on the Python side
+        let _ = self
+            .callback_obj
+            .call_method1(self.py, "on_image_saved", (path,));
+    }
+
+    fn on_transfer_progress(&self, name: &str, sent: u64, total: u64) {
+        // Optional on the Python side
+        let _ =
+            self.callback_obj
+                .call_method1(self.py, "on_transfer_progress", (name, sent, total));
+    }
+
+    fn on_token_refreshed(&self, token: &RefreshedToken) {
+        // Optional on the Python side
+        if let Ok(json) = serde_json::to_string(token) {
+            let _ = self
+                .callback_obj
+                .call_method1(self.py, "on_token_refreshed", (json,));
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        if self.error.borrow().is_some() {
+            return true;
+        }
+        match self.callback_obj.getattr(self.py, "_is_running") {
+            Ok(is_running) => match is_running.extract::<bool>(self.py) {
+                Ok(running) => !running,
+                Err(err) => {
+                    self.record(Err(err));
+                    true
+                }
+            },
+            Err(_) => false,
+        }
+    }
+}
@@ -42,8 +42,17 @@ fn test_full_workflow_integration() {
         assert_eq!(files.len(), 3);
 
         // 3. Test Duplicate Finder
-        let dups =
-            find_duplicate_images(py, dir_path.clone(), vec!["png".to_string()], false).unwrap();
+        let DuplicateGroups::Paths(dups) = find_duplicate_images(
+            py,
+            dir_path.clone().into(),
+            vec!["png".to_string()],
+            false,
+            None,
+            None,
+        )
+        .unwrap() else {
+            panic!("No keep strategy was given");
+        };
         assert_eq!(dups.len(), 1);
         let dup_group = dups.values().next().unwrap();
         assert_eq!(dup_group.len(), 2);
@@ -61,6 +70,10 @@ fn test_full_workflow_integration() {
             out_merge.to_str().unwrap().to_string(),
             10,
             "center".to_string(),
+            None,
+            None,
+            None,
+            None,
         )
         .unwrap();
         assert!(res_merge);
@@ -79,6 +92,11 @@ fn test_full_workflow_integration() {
             false,
             Some(1.0), // Square
             Some("crop".to_string()),
+            true,
+            None,
+            None,
+            None,
+            None,
         )
         .unwrap();
         assert!(res_conv);
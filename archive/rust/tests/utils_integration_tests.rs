@@ -2,9 +2,42 @@ use base::extract_video_thumbnails_batch;
 use base::load_image_batch;
 use base::scan_files;
 use image::{Rgb, RgbImage};
-use pyo3::Python;
+use pyo3::prelude::*;
+use std::path::Path;
 use tempfile::tempdir;
 
+#[pyclass]
+struct BatchCallback {
+    progress: Vec<(usize, usize, String)>,
+    errors: Vec<String>,
+    #[pyo3(get, set)]
+    _is_running: bool,
+}
+
+#[pymethods]
+impl BatchCallback {
+    #[new]
+    fn new() -> Self {
+        BatchCallback {
+            progress: Vec::new(),
+            errors: Vec::new(),
+            _is_running: true,
+        }
+    }
+    fn on_progress(&mut self, processed: usize, total: usize, path: String) {
+        self.progress.push((processed, total, path));
+    }
+    fn on_error(&mut self, path: String, _message: String) {
+        self.errors.push(path);
+    }
+}
+
+fn save_red_image(path: &Path, width: u32, height: u32) {
+    RgbImage::from_pixel(width, height, Rgb([255, 0, 0]))
+        .save(path)
+        .unwrap();
+}
+
 #[test]
 fn test_scan_files_integration() {
     Python::initialize();
@@ -28,8 +61,16 @@ fn test_scan_files_integration() {
             vec![sub.to_str().unwrap().to_string()],
             vec!["jpg".to_string()],
             false,
+            false,
+            false,
+            None,
+            None,
+            None,
+            None,
+            None,
         )
-        .unwrap();
+        .unwrap()
+        .0;
         assert_eq!(results.len(), 1, "Expected 1 jpg file");
         assert!(results[0].ends_with("b.jpg"));
 
@@ -39,12 +80,143 @@ fn test_scan_files_integration() {
             vec![sub.to_str().unwrap().to_string()],
             vec!["txt".to_string(), "png".to_string()],
             false,
+            false,
+            false,
+            None,
+            None,
+            None,
+            None,
+            None,
         )
-        .unwrap();
+        .unwrap()
+        .0;
         assert_eq!(results.len(), 2, "Expected 2 files (txt, png)");
     });
 }
 
+#[test]
+fn test_scan_files_reports_errors() {
+    Python::initialize();
+    Python::attach(|py| {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("library");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.png"), "png").unwrap();
+        std::fs::write(root.join("sub").join("b.png"), "png").unwrap();
+        // A link back up the tree and one to nothing
+        std::os::unix::fs::symlink(&root, root.join("sub").join("loop")).unwrap();
+        std::os::unix::fs::symlink(root.join("gone"), root.join("dangling")).unwrap();
+
+        let scan = |follow_links: bool, strict: bool| {
+            scan_files(
+                py,
+                vec![root.to_str().unwrap().to_string()],
+                vec!["png".to_string()],
+                true,
+                follow_links,
+                strict,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+        let expected = vec![
+            root.join("a.png").to_string_lossy().to_string(),
+            root.join("sub").join("b.png").to_string_lossy().to_string(),
+        ];
+
+        // Links are entries of their own unless followed
+        let (files, errors) = scan(false, false).unwrap();
+        assert_eq!(files, expected);
+        assert!(errors.is_empty());
+
+        let (files, errors) = scan(true, false).unwrap();
+        assert_eq!(files, expected);
+        let failed: Vec<&str> = errors.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            failed,
+            [
+                root.join("dangling").to_str().unwrap(),
+                root.join("sub").join("loop").to_str().unwrap(),
+            ]
+        );
+        assert!(errors.iter().all(|(_, message)| !message.is_empty()));
+
+        assert!(scan(true, true).is_err());
+        assert!(scan(false, true).is_ok());
+    });
+}
+
+#[test]
+fn test_scan_files_filters() {
+    Python::initialize();
+    Python::attach(|py| {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("library");
+        std::fs::create_dir(&root).unwrap();
+        let day = 86_400;
+        let now = std::time::SystemTime::now();
+        let now_secs = now.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as f64;
+        // (name, size, age in days)
+        let files = [
+            ("old_big", 5000, 60),
+            ("new_big", 5000, 1),
+            ("new_small", 10, 1),
+        ];
+        for (name, size, age) in files {
+            let path = root.join(format!("{}.png", name));
+            std::fs::write(&path, vec![0u8; size]).unwrap();
+            let modified = now - std::time::Duration::from_secs(age * day);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        let names = |min_size: Option<u64>,
+                     max_size: Option<u64>,
+                     after: Option<f64>,
+                     before: Option<f64>,
+                     max_results: Option<usize>| {
+            let (files, _) = scan_files(
+                py,
+                vec![root.to_str().unwrap().to_string()],
+                vec!["png".to_string()],
+                true,
+                false,
+                false,
+                min_size,
+                max_size,
+                after,
+                before,
+                max_results,
+            )
+            .unwrap();
+            files
+                .iter()
+                .map(|f| {
+                    Path::new(f)
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let month_ago = Some(now_secs - 30.0 * day as f64);
+        assert_eq!(names(Some(1000), None, month_ago, None, None), ["new_big"]);
+        assert_eq!(names(None, Some(1000), None, None, None), ["new_small"]);
+        assert_eq!(names(None, None, None, month_ago, None), ["old_big"]);
+        assert_eq!(names(None, None, None, None, None).len(), 3);
+        assert_eq!(names(None, None, None, None, Some(2)).len(), 2);
+        assert!(names(None, None, None, None, Some(0)).is_empty());
+    });
+}
+
 #[test]
 fn test_load_image_batch_integration() {
     Python::initialize();
@@ -63,9 +235,10 @@ fn test_load_image_batch_integration() {
         // Load with target size 20
         // Aspect ratio 2:1. If width > height: (20, 20/2) = (20, 10)
         let paths = vec![p1.to_str().unwrap().to_string()];
-        let results = load_image_batch(py, paths, 20).unwrap();
+        let (results, errors) = load_image_batch(py, paths, 20, None, true, None).unwrap();
 
         assert_eq!(results.len(), 1);
+        assert!(errors.is_empty());
         let (path, _bytes, w, h) = &results[0];
         assert_eq!(path, p1.to_str().unwrap());
         assert_eq!(*w, 20);
@@ -73,6 +246,130 @@ fn test_load_image_batch_integration() {
     });
 }
 
+#[test]
+fn test_load_image_batch_reports_progress() {
+    Python::initialize();
+    Python::attach(|py| {
+        let dir = tempdir().unwrap();
+        let good = [dir.path().join("a.png"), dir.path().join("b.png")];
+        for path in &good {
+            save_red_image(path, 40, 40);
+        }
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, "not an image").unwrap();
+
+        let paths: Vec<String> = [&good[0], &broken, &good[1]]
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
+        let callback = Py::new(py, BatchCallback::new()).unwrap();
+        let (results, errors) = load_image_batch(
+            py,
+            paths.clone(),
+            20,
+            Some(callback.clone_ref(py).into_any()),
+            true,
+            None,
+        )
+        .unwrap();
+
+        let loaded: Vec<&str> = results.iter().map(|(path, ..)| path.as_str()).collect();
+        assert_eq!(loaded, [paths[0].as_str(), paths[2].as_str()]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, paths[1]);
+        assert!(errors[0].1.contains("broken.png"), "{}", errors[0].1);
+
+        let callback = callback.borrow(py);
+        assert_eq!(callback.errors, [paths[1].clone()]);
+        let mut counts: Vec<usize> = callback.progress.iter().map(|(n, ..)| *n).collect();
+        counts.sort();
+        assert_eq!(counts, [1, 2, 3]);
+        assert!(callback.progress.iter().all(|(_, total, _)| *total == 3));
+    });
+}
+
+#[test]
+fn test_load_image_batch_cancelled() {
+    Python::initialize();
+    Python::attach(|py| {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.png");
+        save_red_image(&path, 40, 40);
+
+        let callback = Py::new(py, BatchCallback::new()).unwrap();
+        callback.borrow_mut(py)._is_running = false;
+        let paths = vec![path.to_str().unwrap().to_string()];
+        let (results, errors) = load_image_batch(
+            py,
+            paths,
+            20,
+            Some(callback.clone_ref(py).into_any()),
+            true,
+            None,
+        )
+        .unwrap();
+
+        assert!(results.is_empty());
+        assert!(errors.is_empty());
+        assert!(callback.borrow(py).progress.is_empty());
+    });
+}
+
+/// `orientation_<n>.jpg` for EXIF orientations 1 to 8: each shows, once
+/// turned upright, a 24x16 white image with a red 8x8 top-left corner
+fn orientation_fixture(n: u32) -> String {
+    format!(
+        "{}/tests/fixtures/orientation/orientation_{}.jpg",
+        env!("CARGO_MANIFEST_DIR"),
+        n
+    )
+}
+
+fn is_red(rgba: &[u8]) -> bool {
+    rgba[0] > 200 && rgba[1] < 60 && rgba[2] < 60
+}
+
+#[test]
+fn test_load_image_batch_orientation() {
+    Python::initialize();
+    Python::attach(|py| {
+        let paths: Vec<String> = (1..=8).map(orientation_fixture).collect();
+        let (results, _) = load_image_batch(py, paths.clone(), 12, None, true, None).unwrap();
+        assert_eq!(results.len(), 8);
+
+        for (n, (path, bytes, w, h)) in (1..=8).zip(&results) {
+            assert_eq!(path, &paths[n - 1]);
+            assert_eq!((*w, *h), (12, 8), "orientation {}", n);
+            let rgba = bytes.as_bytes(py);
+            let pixel = |x: u32, y: u32| &rgba[((y * w + x) * 4) as usize..][..4];
+            assert!(is_red(pixel(1, 1)), "orientation {} top-left", n);
+            assert!(!is_red(pixel(10, 1)), "orientation {} top-right", n);
+            assert!(!is_red(pixel(1, 6)), "orientation {} bottom-left", n);
+        }
+
+        // Raw pixels on request: stored sideways, so taller than wide
+        let (raw, _) =
+            load_image_batch(py, vec![orientation_fixture(6)], 12, None, false, None).unwrap();
+        assert_eq!((raw[0].2, raw[0].3), (8, 12));
+
+        // Nearest-neighbour never blends the red corner into the white
+        let (nearest, _) = load_image_batch(
+            py,
+            vec![orientation_fixture(1)],
+            12,
+            None,
+            true,
+            Some("nearest".to_string()),
+        )
+        .unwrap();
+        let rgba = nearest[0].1.as_bytes(py);
+        assert!(rgba
+            .chunks(4)
+            .all(|p| is_red(p) || (p[0] > 200 && p[1] > 200 && p[2] > 200)));
+        assert!(load_image_batch(py, vec![], 12, None, true, Some("bicubic".to_string())).is_err());
+    });
+}
+
 #[test]
 fn test_extract_video_thumbnails_integration_failure() {
     // Tests that function runs but returns empty for non-video file (or handles ffmpeg fail)
@@ -83,9 +380,13 @@ fn test_extract_video_thumbnails_integration_failure() {
         std::fs::write(&p1, "dummy").unwrap();
 
         let paths = vec![p1.to_str().unwrap().to_string()];
-        let results = extract_video_thumbnails_batch(py, paths, 100).unwrap();
+        let (results, errors) = extract_video_thumbnails_batch(py, paths, 100, Some(1)).unwrap();
 
         // Should be empty list because ffmpeg failed to extract or decode
         assert!(results.is_empty());
+        // And the failure reported against the file
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, p1.to_str().unwrap());
+        assert!(!errors[0].1.is_empty());
     });
 }
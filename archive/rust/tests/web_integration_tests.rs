@@ -1,12 +1,25 @@
 use anyhow::Result;
-use base::web::image_board_crawler::{BoardCrawler, Crawler};
-use base::web::cloud::{CloudSync, SyncItem, SyncRunner};
+use base::web::cloud::checksum::HashKind;
+use base::web::cloud::oauth::{RefreshedToken, TokenExpired};
+use base::web::cloud::sync::{local_mtime, CloudSync, SyncItem, SyncRunner};
+use base::web::cloud::upload::Uploader;
+use base::web::config::ConfigErrors;
+use base::web::crawlers::crawl_manifest::CrawlManifest;
+use base::web::crawlers::image_board_crawler::{BoardCrawler, Crawler};
+use base::web::crawlers::reverse_image_search::{
+    download_top_results, run_batch, BatchCheckpoint, BatchOptions, DownloadOptions,
+    ReverseSearchEngine, SearchInput, SearchOutcome, SearchResult, SearchStatus,
+};
+use base::web::progress::{ProgressSink, PyProgressSink};
+use base::web::{crawl_board, sync_cloud};
 use mockito::Server;
 use pyo3::prelude::*;
 use reqwest::blocking::Client;
 use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 use tempfile::tempdir;
 
 // --- Mocks ---
@@ -14,6 +27,7 @@ use tempfile::tempdir;
 struct MockSync {
     remote_files: Arc<Mutex<HashMap<String, SyncItem>>>,
     actions: Arc<Mutex<Vec<String>>>,
+    hash_kind: Option<HashKind>,
 }
 
 impl CloudSync for MockSync {
@@ -26,7 +40,13 @@ impl CloudSync for MockSync {
     fn get_remote_files(&self, _client: &Client) -> Result<HashMap<String, SyncItem>> {
         Ok(self.remote_files.lock().unwrap().clone())
     }
-    fn upload_file(&self, _client: &Client, _local_path: &str, rel_path: &str) -> Result<()> {
+    fn upload_file(
+        &self,
+        _client: &Client,
+        _uploader: &Uploader,
+        _local_path: &str,
+        rel_path: &str,
+    ) -> Result<()> {
         self.actions
             .lock()
             .unwrap()
@@ -41,6 +61,9 @@ impl CloudSync for MockSync {
         std::fs::write(local_dest, "mock data")?;
         Ok(())
     }
+    fn hash_kind(&self) -> Option<HashKind> {
+        self.hash_kind
+    }
     fn create_remote_folder(&self, _client: &Client, rel_path: &str) -> Result<()> {
         self.actions
             .lock()
@@ -80,9 +103,50 @@ impl Crawler for MockCrawler {
     }
 }
 
+/// Records what a run reports, in the same shape as `MockCallback`
+#[derive(Default)]
+struct RecordingSink {
+    messages: Mutex<Vec<String>>,
+    cancelled: AtomicBool,
+}
+
+impl RecordingSink {
+    fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+impl ProgressSink for RecordingSink {
+    fn on_status(&self, message: &str) {
+        self.messages.lock().unwrap().push(message.to_string());
+    }
+    fn on_error(&self, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(format!("ERROR:{}", message));
+    }
+    fn on_image_saved(&self, path: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(format!("image_saved:{}", path));
+    }
+    fn on_token_refreshed(&self, token: &RefreshedToken) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(format!("token_refreshed:{}", token.access_token));
+    }
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
 #[pyclass]
 struct MockCallback {
     pub messages: Arc<Mutex<Vec<String>>>,
+    #[pyo3(get, set)]
     pub _is_running: bool,
 }
 
@@ -107,108 +171,720 @@ impl MockCallback {
             .unwrap()
             .push(format!("image_saved:{}", msg));
     }
+    fn on_image_completed(&self, path: String, result_count: usize) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(format!("image_completed:{}:{}", path, result_count));
+    }
+    fn on_iteration_completed(&self, iteration: u64, summary_json: String) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(format!("iteration:{}:{}", iteration, summary_json));
+    }
 }
 
 // --- Tests ---
 
 #[test]
 fn test_sync_runner_upload() {
-    Python::initialize();
-    Python::attach(|py| {
-        let temp = tempdir().unwrap();
-        let local_dir = temp.path().join("local");
-        std::fs::create_dir(&local_dir).unwrap();
+    let temp = tempdir().unwrap();
+    let local_dir = temp.path().join("local");
+    std::fs::create_dir(&local_dir).unwrap();
 
-        let file1 = local_dir.join("test.txt");
-        std::fs::write(&file1, "hello").unwrap();
+    let file1 = local_dir.join("test.txt");
+    std::fs::write(&file1, "hello").unwrap();
 
-        let config = json!({
-            "local_path": local_dir.to_str().unwrap(),
-            "remote_path": "remote",
-            "action_local": "upload",
-            "action_remote": "download",
-            "dry_run": false
-        });
+    let config = json!({
+        "local_path": local_dir.to_str().unwrap(),
+        "remote_path": "remote",
+        "action_local": "upload",
+        "action_remote": "download",
+        "dry_run": false
+    });
 
-        let runner = SyncRunner::new(&config);
-        let remote_files = Arc::new(Mutex::new(HashMap::new()));
-        let actions = Arc::new(Mutex::new(Vec::new()));
-        let mut sync = MockSync {
-            remote_files: remote_files.clone(),
-            actions: actions.clone(),
-        };
+    let runner = SyncRunner::new(&config);
+    let remote_files = Arc::new(Mutex::new(HashMap::new()));
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: remote_files.clone(),
+        actions: actions.clone(),
+        hash_kind: None,
+    };
 
-        let callback = Bound::new(py, MockCallback::new()).unwrap();
-        let client = Client::new();
+    let sink = RecordingSink::default();
+    let client = Client::new();
 
-        let _stats = runner
-            .run(
-                py,
-                &mut sync,
-                &client,
-                callback.to_owned().into_any().unbind(),
-            )
-            .unwrap();
+    let stats = runner.run(&mut sync, &client, &sink).unwrap();
 
-        let act = actions.lock().unwrap();
-        assert!(act.contains(&"upload:test.txt".to_string()));
-    });
+    assert_eq!(stats.uploaded, 1);
+    let act = actions.lock().unwrap();
+    assert!(act.contains(&"upload:test.txt".to_string()));
+    assert!(sink.messages().contains(&"Uploading: test.txt".to_string()));
 }
 
 #[test]
 fn test_sync_runner_download() {
-    Python::initialize();
-    Python::attach(|py| {
-        let temp = tempdir().unwrap();
-        let local_dir = temp.path().join("local");
-        std::fs::create_dir(&local_dir).unwrap();
+    let temp = tempdir().unwrap();
+    let local_dir = temp.path().join("local");
+    std::fs::create_dir(&local_dir).unwrap();
 
-        let config = json!({
-            "local_path": local_dir.to_str().unwrap(),
-            "remote_path": "remote",
-            "action_local": "upload",
-            "action_remote": "download",
-            "dry_run": false
-        });
+    let config = json!({
+        "local_path": local_dir.to_str().unwrap(),
+        "remote_path": "remote",
+        "action_local": "upload",
+        "action_remote": "download",
+        "dry_run": false
+    });
+
+    let runner = SyncRunner::new(&config);
+    let mut remote_files = HashMap::new();
+    remote_files.insert(
+        "remote_file.txt".to_string(),
+        SyncItem {
+            rel_path: "remote_file.txt".to_string(),
+            abs_path_or_id: "id123".to_string(),
+            mtime: 0,
+            is_folder: false,
+            size: 0,
+            checksum: None,
+        },
+    );
+
+    let remote_files_arc = Arc::new(Mutex::new(remote_files));
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: remote_files_arc,
+        actions,
+        hash_kind: None,
+    };
+
+    let sink = RecordingSink::default();
+    let client = Client::new();
+
+    let _stats = runner.run(&mut sync, &client, &sink).unwrap();
+
+    assert!(local_dir.join("remote_file.txt").exists());
+}
+
+#[test]
+fn test_sync_runner_settles_files_on_both_sides() {
+    // 2024-01-31 12:00:00 UTC
+    let t = 1_706_702_400;
+    let temp = tempdir().unwrap();
+    let local_dir = temp.path().join("local");
+    std::fs::create_dir(&local_dir).unwrap();
+
+    let mut remote_files = HashMap::new();
+    // (name, local mtime, remote mtime, remote size); every local file is 5 bytes
+    for (name, local_mtime, remote_mtime, remote_size) in [
+        ("same.txt", t, t + 1, 5),
+        ("edited.txt", t + 600, t, 3),
+        ("stale.txt", t, t + 600, 5),
+        ("clash.txt", t, t + 1, 9),
+    ] {
+        let path = local_dir.join(name);
+        std::fs::write(&path, "hello").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(local_mtime as u64))
+            .unwrap();
+        remote_files.insert(
+            name.to_string(),
+            SyncItem {
+                rel_path: name.to_string(),
+                abs_path_or_id: format!("id-{}", name),
+                mtime: remote_mtime,
+                is_folder: false,
+                size: remote_size,
+                checksum: None,
+            },
+        );
+    }
+
+    let config = json!({ "local_path": local_dir.to_str().unwrap() });
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: Arc::new(Mutex::new(remote_files)),
+        actions: actions.clone(),
+        hash_kind: None,
+    };
+    let sink = RecordingSink::default();
+    let stats = SyncRunner::new(&config)
+        .run(&mut sync, &Client::new(), &sink)
+        .unwrap();
+
+    assert_eq!(stats.skipped, 1);
+    assert_eq!(stats.updated, 2);
+    assert_eq!(stats.conflicted, 1);
+    assert_eq!(stats.uploaded + stats.downloaded, 0);
+
+    let mut act = actions.lock().unwrap().clone();
+    act.sort();
+    let local = |name: &str| local_dir.join(name).to_str().unwrap().to_string();
+    assert_eq!(
+        act,
+        vec![
+            format!("download:{}.part", local("clash.txt")),
+            format!("download:{}.part", local("stale.txt")),
+            "upload:clash.conflict-20240131.txt".to_string(),
+            "upload:edited.txt".to_string(),
+        ]
+    );
+
+    // The older clashing copy is kept beside the remote version
+    assert_eq!(
+        std::fs::read_to_string(local_dir.join("clash.conflict-20240131.txt")).unwrap(),
+        "hello"
+    );
+    assert_eq!(
+        std::fs::read_to_string(local_dir.join("clash.txt")).unwrap(),
+        "mock data"
+    );
+    // Downloads take the remote mtime, so the next run leaves them alone
+    let stale_mtime = std::fs::metadata(local_dir.join("stale.txt"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(
+        stale_mtime,
+        UNIX_EPOCH + Duration::from_secs((t + 600) as u64)
+    );
+}
+
+#[test]
+fn test_sync_runner_verifies_downloads() {
+    let temp = tempdir().unwrap();
+    let local_dir = temp.path().join("local");
+    std::fs::create_dir(&local_dir).unwrap();
 
-        let runner = SyncRunner::new(&config);
-        let mut remote_files = HashMap::new();
+    let mut remote_files = HashMap::new();
+    for (name, checksum) in [
+        ("good.txt", HashKind::Md5.compute(b"mock data")),
+        ("bad.txt", HashKind::Md5.compute(b"other data")),
+    ] {
         remote_files.insert(
-            "remote_file.txt".to_string(),
+            name.to_string(),
             SyncItem {
-                rel_path: "remote_file.txt".to_string(),
-                abs_path_or_id: "id123".to_string(),
+                rel_path: name.to_string(),
+                abs_path_or_id: format!("id-{}", name),
                 mtime: 0,
                 is_folder: false,
+                size: 9,
+                checksum: Some(checksum),
             },
         );
+    }
 
-        let remote_files_arc = Arc::new(Mutex::new(remote_files));
-        let actions = Arc::new(Mutex::new(Vec::new()));
-        let mut sync = MockSync {
-            remote_files: remote_files_arc,
-            actions,
-        };
+    let config = json!({
+        "local_path": local_dir.to_str().unwrap(),
+        "max_retries": 1,
+        "retry_base_delay": 0
+    });
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: Arc::new(Mutex::new(remote_files)),
+        actions: actions.clone(),
+        hash_kind: Some(HashKind::Md5),
+    };
+    let sink = RecordingSink::default();
+    let stats = SyncRunner::new(&config)
+        .run(&mut sync, &Client::new(), &sink)
+        .unwrap();
 
-        let callback = Bound::new(py, MockCallback::new()).unwrap();
-        let client = Client::new();
+    assert_eq!(stats.downloaded, 1);
+    assert_eq!(stats.verification_failed, 1);
+    assert_eq!(
+        std::fs::read_to_string(local_dir.join("good.txt")).unwrap(),
+        "mock data"
+    );
 
-        let _stats = runner
-            .run(
-                py,
-                &mut sync,
-                &client,
-                callback.to_owned().into_any().unbind(),
-            )
+    // The bad download is tried again, then dropped without a trace
+    let bad = format!("download:{}.part", local_dir.join("bad.txt").display());
+    let act = actions.lock().unwrap();
+    assert_eq!(act.iter().filter(|a| **a == bad).count(), 2);
+    assert!(!local_dir.join("bad.txt").exists());
+    assert!(!local_dir.join("bad.txt.part").exists());
+    assert!(sink
+        .messages()
+        .iter()
+        .any(|m| m.starts_with("ERROR:") && m.contains("Checksum mismatch for bad.txt")));
+}
+
+#[test]
+fn test_sync_runner_leaves_excluded_paths_alone() {
+    let temp = tempdir().unwrap();
+    let local_dir = temp.path().join("local");
+    for dir in ["cache/thumbs", ".git", "trips"] {
+        std::fs::create_dir_all(local_dir.join(dir)).unwrap();
+    }
+    for file in [
+        "a.jpg",
+        "trips/b.jpg.part",
+        "trips/notes.txt",
+        "cache/thumbs/t.jpg",
+        ".git/HEAD",
+    ] {
+        std::fs::write(local_dir.join(file), "hello").unwrap();
+    }
+
+    let mut remote_files = HashMap::new();
+    for name in ["old.jpg", "trips/c.jpg.part", "cache/x.jpg", ".hidden.jpg"] {
+        remote_files.insert(
+            name.to_string(),
+            SyncItem {
+                rel_path: name.to_string(),
+                abs_path_or_id: format!("id-{}", name),
+                mtime: 0,
+                is_folder: false,
+                size: 5,
+                checksum: None,
+            },
+        );
+    }
+
+    let config = json!({
+        "local_path": local_dir.to_str().unwrap(),
+        "action_remote": "delete_remote",
+        "exclude_patterns": ["*.part", "cache/**"],
+        "include_extensions": ["jpg"],
+        "skip_hidden": true
+    });
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: Arc::new(Mutex::new(remote_files)),
+        actions: actions.clone(),
+        hash_kind: None,
+    };
+    let stats = SyncRunner::new(&config)
+        .run(&mut sync, &Client::new(), &RecordingSink::default())
+        .unwrap();
+
+    let mut act = actions.lock().unwrap().clone();
+    act.sort();
+    // Only the remote orphan that isn't excluded is deleted
+    assert_eq!(
+        act,
+        vec![
+            "delete_remote:old.jpg",
+            "mkdir:cache",
+            "mkdir:trips",
+            "upload:a.jpg"
+        ]
+    );
+    // Locally b.jpg.part, notes.txt, cache/thumbs and .git; remotely
+    // c.jpg.part, cache/x.jpg and .hidden.jpg
+    assert_eq!(stats.ignored, 7);
+}
+
+#[test]
+fn test_sync_runner_two_way_carries_deletions() {
+    // 2024-01-31 12:00:00 UTC
+    let t = 1_706_702_400;
+    let temp = tempdir().unwrap();
+    let local_dir = temp.path().join("local");
+    std::fs::create_dir(&local_dir).unwrap();
+    let remote_file = |name: &str, size: u64| SyncItem {
+        rel_path: name.to_string(),
+        abs_path_or_id: format!("id-{}", name),
+        mtime: t,
+        is_folder: false,
+        size,
+        checksum: None,
+    };
+    for name in ["a.jpg", "b.jpg"] {
+        let path = local_dir.join(name);
+        std::fs::write(&path, "hello").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(t as u64))
+            .unwrap();
+    }
+    let remote_files = Arc::new(Mutex::new(HashMap::from([
+        ("b.jpg".to_string(), remote_file("b.jpg", 5)),
+        ("c.jpg".to_string(), remote_file("c.jpg", 9)),
+    ])));
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: remote_files.clone(),
+        actions: actions.clone(),
+        hash_kind: None,
+    };
+    let mut config = json!({
+        "local_path": local_dir.to_str().unwrap(),
+        "mode": "two_way"
+    });
+    let run = |config: &Value, sync: &mut MockSync| {
+        let sink = RecordingSink::default();
+        let stats = SyncRunner::new(config)
+            .run(sync, &Client::new(), &sink)
             .unwrap();
+        (stats, sink.messages())
+    };
 
-        assert!(local_dir.join("remote_file.txt").exists());
+    // The first run has nothing to compare against, so only copies
+    let (stats, _) = run(&config, &mut sync);
+    assert_eq!((stats.uploaded, stats.downloaded, stats.skipped), (1, 1, 1));
+    let state_file = local_dir.join(".image-toolkit-sync/mock.json");
+    assert!(state_file.exists());
+    remote_files
+        .lock()
+        .unwrap()
+        .insert("a.jpg".to_string(), remote_file("a.jpg", 5));
+
+    // Since then b.jpg was deleted here and c.jpg there
+    std::fs::remove_file(local_dir.join("b.jpg")).unwrap();
+    remote_files.lock().unwrap().remove("c.jpg");
+    actions.lock().unwrap().clear();
+    let state_before = std::fs::read_to_string(&state_file).unwrap();
+
+    config["dry_run"] = json!(true);
+    let (stats, messages) = run(&config, &mut sync);
+    assert_eq!((stats.deleted_local, stats.deleted_remote), (1, 1));
+    assert!(messages.contains(&"[Dry run] Deleting Remote: b.jpg".to_string()));
+    assert!(messages.contains(&"[Dry run] Deleting Local: c.jpg".to_string()));
+    assert!(actions.lock().unwrap().is_empty());
+    assert!(local_dir.join("c.jpg").exists());
+    assert_eq!(std::fs::read_to_string(&state_file).unwrap(), state_before);
+
+    config["dry_run"] = json!(false);
+    let (stats, _) = run(&config, &mut sync);
+    assert_eq!((stats.deleted_local, stats.deleted_remote), (1, 1));
+    assert_eq!(stats.uploaded + stats.downloaded, 0);
+    assert_eq!(*actions.lock().unwrap(), vec!["delete_remote:b.jpg"]);
+    assert!(!local_dir.join("c.jpg").exists());
+    assert!(local_dir.join("a.jpg").exists());
+
+    // A remote listing that comes back empty isn't taken as everything
+    // having been deleted there
+    remote_files.lock().unwrap().clear();
+    let sink = RecordingSink::default();
+    let err = SyncRunner::new(&config)
+        .run(&mut sync, &Client::new(), &sink)
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("remote folder has none"),
+        "{}",
+        err
+    );
+    assert!(local_dir.join("a.jpg").exists());
+}
+
+/// Renewed its token, then had the new one turned down as well
+struct RevokedSync;
+
+impl CloudSync for RevokedSync {
+    fn name(&self) -> &str {
+        "Revoked"
+    }
+    fn authenticate(&mut self, _client: &Client) -> Result<()> {
+        Err(TokenExpired {
+            provider: "Revoked".to_string(),
+            detail: "invalid_grant".to_string(),
+        }
+        .into())
+    }
+    fn get_remote_files(&self, _client: &Client) -> Result<HashMap<String, SyncItem>> {
+        unreachable!()
+    }
+    fn upload_file(&self, _: &Client, _: &Uploader, _: &str, _: &str) -> Result<()> {
+        unreachable!()
+    }
+    fn download_file(&self, _: &Client, _: &str, _: &str) -> Result<()> {
+        unreachable!()
+    }
+    fn refreshed_token(&self) -> Option<RefreshedToken> {
+        Some(RefreshedToken {
+            access_token: "renewed".to_string(),
+            refresh_token: Some("r2".to_string()),
+            expires_in: Some(3600),
+        })
+    }
+    fn create_remote_folder(&self, _: &Client, _: &str) -> Result<()> {
+        unreachable!()
+    }
+    fn delete_remote(&self, _: &Client, _: &str, _: &str) -> Result<()> {
+        unreachable!()
+    }
+}
+
+#[test]
+fn test_sync_runner_hands_over_refreshed_token_when_failing() {
+    let local_dir = tempdir().unwrap();
+    let config = json!({ "local_path": local_dir.path().to_str().unwrap() });
+    let sink = RecordingSink::default();
+    let err = SyncRunner::new(&config)
+        .run(&mut RevokedSync, &Client::new(), &sink)
+        .unwrap_err();
+
+    // Classified as expired under the runner's context
+    assert!(err.downcast_ref::<TokenExpired>().is_some(), "{:#}", err);
+    // The rotated refresh token would be lost otherwise
+    assert_eq!(
+        sink.messages().last().map(String::as_str),
+        Some("token_refreshed:renewed")
+    );
+}
+
+#[test]
+fn test_sync_runner_rejects_unknown_conflict_policy() {
+    let temp = tempdir().unwrap();
+    let config = json!({
+        "local_path": temp.path().to_str().unwrap(),
+        "conflict_policy": "newest"
+    });
+    let mut sync = MockSync {
+        remote_files: Arc::new(Mutex::new(HashMap::new())),
+        actions: Arc::new(Mutex::new(Vec::new())),
+        hash_kind: None,
+    };
+    let err = SyncRunner::new(&config)
+        .run(&mut sync, &Client::new(), &RecordingSink::default())
+        .unwrap_err();
+    assert!(err.to_string().contains("Unknown conflict_policy 'newest'"));
+}
+
+#[test]
+fn test_sync_runner_transfers_in_parallel() {
+    let temp = tempdir().unwrap();
+    let local_dir = temp.path().join("local");
+    std::fs::create_dir_all(local_dir.join("album")).unwrap();
+    for i in 0..20 {
+        std::fs::write(local_dir.join(format!("album/{}.jpg", i)), "photo").unwrap();
+    }
+
+    let mut remote = HashMap::new();
+    for i in 0..5 {
+        let rel_path = format!("remote_{}.jpg", i);
+        remote.insert(
+            rel_path.clone(),
+            SyncItem {
+                rel_path,
+                abs_path_or_id: format!("id_{}", i),
+                mtime: 1000,
+                is_folder: false,
+                size: 9,
+                checksum: None,
+            },
+        );
+    }
+
+    let config = json!({
+        "local_path": local_dir.to_str().unwrap(),
+        "transfer_concurrency": 4
+    });
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: Arc::new(Mutex::new(remote)),
+        actions: actions.clone(),
+        hash_kind: None,
+    };
+
+    let sink = RecordingSink::default();
+    let stats = SyncRunner::new(&config)
+        .run(&mut sync, &Client::new(), &sink)
+        .unwrap();
+
+    assert_eq!(stats.uploaded, 21);
+    assert_eq!(stats.downloaded, 5);
+    let act = actions.lock().unwrap();
+    assert_eq!(act.len(), 26);
+    // The folder is there before anything goes into it
+    assert_eq!(act[0], "mkdir:album");
+    for i in 0..20 {
+        assert!(act.contains(&format!("upload:album/{}.jpg", i)));
+        assert!(sink
+            .messages()
+            .contains(&format!("Uploading: album/{}.jpg", i)));
+    }
+    for i in 0..5 {
+        assert!(local_dir.join(format!("remote_{}.jpg", i)).exists());
+    }
+}
+
+/// Writes `contents` to `path`, with its folders, last modified at `mtime`
+fn write_file(path: &std::path::Path, contents: &str, mtime: u64) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(mtime))
+        .unwrap();
+}
+
+#[test]
+fn test_local_folder_sync_uploads_and_downloads() {
+    let temp = tempdir().unwrap();
+    let local = temp.path().join("photos");
+    let remote = temp.path().join("backup");
+    write_file(&local.join("a.jpg"), "a", 1_600_000_000);
+    write_file(&local.join("trips/b.jpg"), "b", 1_600_000_100);
+    write_file(&remote.join("c.jpg"), "c", 1_500_000_000);
+
+    let config = json!({
+        "local_path": local.to_str().unwrap(),
+        "remote_path": remote.to_str().unwrap(),
+        "transfer_concurrency": 2
+    });
+    let sink = RecordingSink::default();
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+
+    assert_eq!(stats.uploaded, 3); // trips, a.jpg, trips/b.jpg
+    assert_eq!(stats.downloaded, 1);
+    assert_eq!(
+        std::fs::read_to_string(remote.join("trips/b.jpg")).unwrap(),
+        "b"
+    );
+    assert_eq!(std::fs::read_to_string(local.join("c.jpg")).unwrap(), "c");
+    // Copies keep their mtimes, so the next run has nothing to do
+    assert_eq!(local_mtime(&remote.join("a.jpg")), 1_600_000_000);
+    assert_eq!(local_mtime(&local.join("c.jpg")), 1_500_000_000);
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+    assert_eq!((stats.uploaded, stats.downloaded, stats.updated), (0, 0, 0));
+    assert_eq!(stats.skipped, 3);
+
+    // An edit goes across, replacing the older copy
+    write_file(&local.join("a.jpg"), "a, edited", 1_600_000_500);
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+    assert_eq!(stats.updated, 1);
+    assert_eq!(
+        std::fs::read_to_string(remote.join("a.jpg")).unwrap(),
+        "a, edited"
+    );
+    assert!(!remote.join("a.jpg.part").exists());
+}
+
+#[test]
+fn test_local_folder_sync_deletes_orphans() {
+    let temp = tempdir().unwrap();
+    let local = temp.path().join("photos");
+    let remote = temp.path().join("backup");
+    write_file(&local.join("kept.jpg"), "kept", 1_600_000_000);
+    write_file(&local.join("stray.jpg"), "stray", 1_600_000_000);
+    write_file(&remote.join("kept.jpg"), "kept", 1_600_000_000);
+    write_file(&remote.join("old/x.jpg"), "x", 1_600_000_000);
+    write_file(&remote.join("old/deeper/y.jpg"), "y", 1_600_000_000);
+
+    let mut config = json!({
+        "local_path": local.to_str().unwrap(),
+        "remote_path": remote.to_str().unwrap(),
+        "action_local": "delete_local",
+        "action_remote": "delete_remote",
+        "dry_run": true
+    });
+    let sink = RecordingSink::default();
+
+    // A dry run only says what it would do
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+    assert_eq!(stats.deleted_local, 1);
+    assert_eq!(stats.deleted_remote, 4);
+    assert!(local.join("stray.jpg").exists());
+    assert!(remote.join("old/deeper/y.jpg").exists());
+
+    config["dry_run"] = json!(false);
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+    assert_eq!(stats.deleted_local, 1);
+    assert_eq!(stats.deleted_remote, 4);
+    assert_eq!(stats.skipped, 1);
+    assert!(!local.join("stray.jpg").exists());
+    assert!(!remote.join("old").exists());
+    assert!(local.join("kept.jpg").exists() && remote.join("kept.jpg").exists());
+}
+
+#[test]
+fn test_local_folder_sync_two_way() {
+    let temp = tempdir().unwrap();
+    let local = temp.path().join("photos");
+    let remote = temp.path().join("backup");
+    write_file(&local.join("a.jpg"), "a", 1_600_000_000);
+    write_file(&local.join("b.jpg"), "b", 1_600_000_000);
+    write_file(&remote.join("c.jpg"), "c", 1_600_000_000);
+
+    let config = json!({
+        "local_path": local.to_str().unwrap(),
+        "remote_path": remote.to_str().unwrap(),
+        "mode": "two_way",
+        "transfer_concurrency": 2
+    });
+    let sink = RecordingSink::default();
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+    assert_eq!((stats.uploaded, stats.downloaded), (2, 1));
+
+    // Deleted on one side, so deleted on the other
+    std::fs::remove_file(remote.join("a.jpg")).unwrap();
+    std::fs::remove_file(local.join("c.jpg")).unwrap();
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+    assert_eq!((stats.deleted_local, stats.deleted_remote), (1, 1));
+    assert!(!local.join("a.jpg").exists());
+    assert!(!remote.join("c.jpg").exists());
+    assert!(local.join("b.jpg").exists() && remote.join("b.jpg").exists());
+    // The state lives in the local folder and isn't synced
+    assert!(!remote.join(".image-toolkit-sync").exists());
+}
+
+#[test]
+fn test_local_folder_sync_refuses_unsafe_folders() {
+    let temp = tempdir().unwrap();
+    let local = temp.path().join("photos");
+    write_file(&local.join("a.jpg"), "a", 1_600_000_000);
+    let sink = RecordingSink::default();
+
+    // Not mounted, say; nothing is deleted on its account
+    let config = json!({
+        "local_path": local.to_str().unwrap(),
+        "remote_path": temp.path().join("missing").to_str().unwrap(),
+        "action_local": "delete_local"
     });
+    let err = sync_cloud("local", &config, &sink).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Remote folder not found"),
+        "{:#}",
+        err
+    );
+    assert!(local.join("a.jpg").exists());
+
+    let config = json!({
+        "local_path": local.to_str().unwrap(),
+        "remote_path": local.join("backup").to_str().unwrap()
+    });
+    std::fs::create_dir(local.join("backup")).unwrap();
+    let err = sync_cloud("local", &config, &sink).unwrap_err();
+    assert!(format!("{:#}", err).contains("overlap"), "{:#}", err);
+}
+
+#[test]
+fn test_sync_runner_stops_when_cancelled() {
+    let temp = tempdir().unwrap();
+    std::fs::write(temp.path().join("test.txt"), "hello").unwrap();
+
+    let config = json!({ "local_path": temp.path().to_str().unwrap() });
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let mut sync = MockSync {
+        remote_files: Arc::new(Mutex::new(HashMap::new())),
+        actions: actions.clone(),
+        hash_kind: None,
+    };
+
+    let sink = RecordingSink::default();
+    sink.cancelled.store(true, Ordering::SeqCst);
+    let err = SyncRunner::new(&config)
+        .run(&mut sync, &Client::new(), &sink)
+        .unwrap_err();
+
+    assert!(err.to_string().contains("interrupted"));
+    assert!(actions.lock().unwrap().is_empty());
 }
 
 #[tokio::test]
 async fn test_file_loader_wait() {
-    use base::web::file_loader::WebFileLoaderRust;
+    use base::web::clients::file_loader::WebFileLoaderRust;
     use std::collections::HashSet;
     use std::time::Duration;
 
@@ -240,65 +916,1140 @@ async fn test_file_loader_wait() {
     );
 }
 
+fn mock_board(server: &mut Server) -> Vec<mockito::Mock> {
+    let posts = server
+        .mock("GET", "/posts")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!([
+                {
+                    "id": 1,
+                    "md5": "abc12345",
+                    "file_url": format!("{}/image1.jpg", server.url())
+                }
+            ])
+            .to_string(),
+        )
+        .create();
+
+    let image = server
+        .mock("GET", "/image1.jpg")
+        .with_status(200)
+        .with_body("fake-image-bytes")
+        .create();
+
+    vec![posts, image]
+}
+
 #[test]
 fn test_board_crawler_run() {
-    Python::initialize();
-    Python::attach(|py| {
-        let mut server = Server::new();
-        let _m1 = server
-            .mock("GET", "/posts")
-            .with_status(200)
-            .with_header("content-type", "application/json")
-            .with_body(
-                json!([
-                    {
-                        "id": 1,
-                        "md5": "abc12345",
-                        "file_url": format!("{}/image1.jpg", server.url())
-                    }
-                ])
-                .to_string(),
-            )
-            .create();
+    let mut server = Server::new();
+    let _mocks = mock_board(&mut server);
 
-        let _m2 = server
-            .mock("GET", "/image1.jpg")
-            .with_status(200)
-            .with_body("fake-image-bytes")
-            .create();
-
-        let temp = tempdir().unwrap();
-        let download_dir = temp.path().join("downloads");
+    let temp = tempdir().unwrap();
+    let download_dir = temp.path().join("downloads");
 
-        let config = json!({
-            "download_dir": download_dir.to_str().unwrap(),
-            "max_pages": 1,
-            "limit": 10,
-            "tags": "test"
-        });
+    let config = json!({
+        "download_dir": download_dir.to_str().unwrap(),
+        "max_pages": 1,
+        "limit": 10,
+        "tags": "test"
+    });
 
-        let crawler_handler = BoardCrawler::new(&config);
-        let mock_crawler = MockCrawler {
-            base_url: server.url(),
-        };
+    let crawler_handler = BoardCrawler::new(&config);
+    let mock_crawler = MockCrawler {
+        base_url: server.url(),
+    };
 
-        let callback = Bound::new(py, MockCallback::new()).unwrap();
-        let client = Client::new();
+    let sink = RecordingSink::default();
+    let client = Client::new();
 
-        let downloaded = crawler_handler
-            .run(
-                py,
-                &mock_crawler,
-                &client,
-                callback.to_owned().into_any().unbind(),
-            )
-            .unwrap();
+    let downloaded = crawler_handler.run(&mock_crawler, &client, &sink);
 
-        assert_eq!(downloaded, 1);
-        assert!(download_dir.join("1_abc12345.jpg").exists());
-        assert!(download_dir.join("1_abc12345.json").exists());
+    assert_eq!(downloaded, 1);
+    assert!(download_dir.join("1_abc12345.jpg").exists());
+    assert!(download_dir.join("1_abc12345.json").exists());
 
-        let msgs = callback.borrow().messages.lock().unwrap().clone();
+    let msgs = sink.messages();
+    assert!(msgs.iter().any(|m| m.contains("image_saved:")));
+    assert_eq!(
+        msgs.last().map(String::as_str),
+        Some("Crawl complete. Downloaded 1 images.")
+    );
+}
+
+#[test]
+fn test_crawl_board_keeps_cookies() {
+    let mut server = Server::new();
+    let host = server.host_with_port();
+    let domain = host.split(':').next().unwrap().to_string();
+    let posts = server
+        .mock("GET", "/posts.json")
+        .match_header("cookie", "pass_hash=abc")
+        .with_status(200)
+        .with_header("set-cookie", "cf_clearance=ok; Path=/")
+        .with_body(
+            json!([{"id": 1, "md5": "abc12345", "file_url": format!("{}/image1.jpg", server.url())}])
+                .to_string(),
+        )
+        .create();
+    // The download goes out with what the crawl was given and what it got
+    let image = server
+        .mock("GET", "/image1.jpg")
+        .match_header(
+            "cookie",
+            mockito::Matcher::Regex("cf_clearance=ok".to_string()),
+        )
+        .match_header(
+            "cookie",
+            mockito::Matcher::Regex("pass_hash=abc".to_string()),
+        )
+        .with_status(200)
+        .with_body("fake-image-bytes")
+        .create();
+
+    let temp = tempdir().unwrap();
+    let cookies_file = temp.path().join("cookies.txt");
+    std::fs::write(
+        &cookies_file,
+        format!("{}\tFALSE\t/\tFALSE\t0\tpass_hash\tabc\n", domain),
+    )
+    .unwrap();
+    let config = json!({
+        "endpoint": format!("{}/posts.json", server.url()),
+        "download_dir": temp.path().join("downloads").to_str().unwrap(),
+        "max_pages": 1,
+        "cookies_file": cookies_file.to_str().unwrap(),
+    });
+
+    let sink = RecordingSink::default();
+    assert_eq!(crawl_board("generic", &config, &sink).unwrap(), 1);
+    posts.assert();
+    image.assert();
+
+    let saved = std::fs::read_to_string(&cookies_file).unwrap();
+    assert!(saved.contains(&format!("{}\tFALSE\t/\tFALSE\t0\tcf_clearance\tok", domain)));
+    assert!(saved.contains("\tpass_hash\tabc"));
+}
+
+#[test]
+fn test_crawl_board_through_proxy_with_rate_limit() {
+    // The mock server is the proxy; the board's host doesn't resolve
+    let mut proxy = Server::new();
+    let posts = proxy
+        .mock("GET", "/posts.json")
+        .match_header("host", "booru.invalid")
+        .match_header("proxy-authorization", "Basic bWU6cHc=")
+        .with_status(200)
+        .with_body(
+            json!([{"id": 1, "md5": "abc12345", "file_url": "http://booru.invalid/image1.jpg"}])
+                .to_string(),
+        )
+        .create();
+    let image = proxy
+        .mock("GET", "/image1.jpg")
+        .match_header("host", "booru.invalid")
+        .with_status(200)
+        .with_body("fake-image-bytes")
+        .create();
+
+    let temp = tempdir().unwrap();
+    let config = json!({
+        "endpoint": "http://booru.invalid/posts.json",
+        "download_dir": temp.path().to_str().unwrap(),
+        "max_pages": 1,
+        "proxy": {"url": proxy.url(), "username": "me", "password": "pw"},
+        "rate_limit": {"requests_per_minute": 300},
+    });
+
+    let sink = RecordingSink::default();
+    let started = std::time::Instant::now();
+    assert_eq!(crawl_board("generic", &config, &sink).unwrap(), 1);
+    posts.assert();
+    image.assert();
+    // The download waited its turn behind the page
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert!(sink.messages().contains(&format!(
+        "Using proxy http://me:***@{}",
+        proxy.host_with_port()
+    )));
+
+    let bad = json!({"endpoint": "http://booru.invalid/posts.json", "proxy": "ftp://x"});
+    assert!(crawl_board("generic", &bad, &sink).is_err());
+}
+
+#[test]
+fn test_jobs_refuse_mistyped_configs() {
+    let temp = tempdir().unwrap();
+    let sink = RecordingSink::default();
+
+    // Nothing is fetched or created before the config is refused
+    let config = json!({
+        "donwload_dir": temp.path().join("board").to_str().unwrap(),
+        "limit": "50",
+    });
+    let err = crawl_board("danbooru", &config, &sink).unwrap_err();
+    let errors = err.downcast_ref::<ConfigErrors>().unwrap();
+    assert_eq!(
+        errors.issues,
+        vec![
+            "`donwload_dir`: unknown field; did you mean `download_dir`?",
+            "`limit`: invalid type: string \"50\", expected u64",
+        ]
+    );
+    assert!(sink.messages().is_empty());
+    assert!(!temp.path().join("board").exists());
+
+    let local = temp.path().join("local");
+    let remote = temp.path().join("remote");
+    std::fs::create_dir_all(&local).unwrap();
+    std::fs::create_dir_all(&remote).unwrap();
+    std::fs::write(local.join("a.jpg"), b"a").unwrap();
+    let config = json!({
+        "local_path": local.to_str().unwrap(),
+        "remote_path": remote.to_str().unwrap(),
+        "dry_run": "false",
+    });
+    let err = sync_cloud("local", &config, &sink).unwrap_err();
+    assert!(err.is::<ConfigErrors>());
+    assert!(!remote.join("a.jpg").exists());
+
+    // Fields from a newer version pass with allow_unknown
+    let mut config = config;
+    config["dry_run"] = json!(false);
+    config["bandwidth_limit"] = json!(1000);
+    config["allow_unknown"] = json!(true);
+    let stats = sync_cloud("local", &config, &sink).unwrap();
+    assert_eq!(stats.uploaded, 1);
+}
+
+#[test]
+fn test_board_crawler_resume() {
+    let mut server = Server::new();
+    let [posts, image]: [mockito::Mock; 2] = mock_board(&mut server).try_into().unwrap();
+
+    let temp = tempdir().unwrap();
+    let download_dir = temp.path().join("downloads");
+    let manifest_path = CrawlManifest::path(&download_dir);
+    let config = |max_pages: u32| {
+        json!({
+            "download_dir": download_dir.to_str().unwrap(),
+            "max_pages": max_pages,
+            "tags": "test",
+            "resume": true
+        })
+    };
+    let mock_crawler = MockCrawler {
+        base_url: server.url(),
+    };
+    let client = Client::new();
+
+    let sink = RecordingSink::default();
+    assert_eq!(
+        BoardCrawler::new(&config(1)).run(&mock_crawler, &client, &sink),
+        1
+    );
+    let manifest = CrawlManifest::load(&manifest_path).unwrap();
+    let state = manifest.state("MockCrawler", "test").unwrap();
+    assert_eq!(state.last_page, 1);
+    assert!(state.downloaded.contains("1"));
+
+    // A known post isn't downloaded again even once its file is gone, and
+    // the crawl carries on from page 2
+    std::fs::remove_file(download_dir.join("1_abc12345.jpg")).unwrap();
+    let sink = RecordingSink::default();
+    assert_eq!(
+        BoardCrawler::new(&config(2)).run(&mock_crawler, &client, &sink),
+        0
+    );
+    let msgs = sink.messages();
+    assert!(msgs.contains(&"Resuming from page 2...".to_string()));
+    assert!(msgs.contains(&"Skipped 1 posts downloaded before.".to_string()));
+    assert!(!download_dir.join("1_abc12345.jpg").exists());
+    image.assert();
+    let manifest = CrawlManifest::load(&manifest_path).unwrap();
+    assert_eq!(manifest.state("MockCrawler", "test").unwrap().last_page, 2);
+
+    let sink = RecordingSink::default();
+    BoardCrawler::new(&config(2)).run(&mock_crawler, &client, &sink);
+    assert!(sink
+        .messages()
+        .contains(&"All 2 pages were crawled already.".to_string()));
+    // Only pages 1 and 2 were ever fetched
+    posts.expect(2).assert();
+}
+
+#[test]
+fn test_board_crawler_retries_transient_failures() {
+    let mut server = Server::new();
+    let posts = server
+        .mock("GET", "/posts")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!([
+                {"id": 1, "md5": "aaa", "file_url": format!("{}/busy.jpg", server.url())},
+                {"id": 2, "md5": "bbb", "file_url": format!("{}/gone.jpg", server.url())}
+            ])
+            .to_string(),
+        )
+        .create();
+    // Busy once, then there
+    let busy = server
+        .mock("GET", "/busy.jpg")
+        .with_status(429)
+        .with_header("retry-after", "0")
+        .expect(1)
+        .create();
+    let ready = server
+        .mock("GET", "/busy.jpg")
+        .with_status(200)
+        .with_body("fake-image-bytes")
+        .expect(1)
+        .create();
+    let gone = server
+        .mock("GET", "/gone.jpg")
+        .with_status(404)
+        .expect(1)
+        .create();
+
+    let temp = tempdir().unwrap();
+    let download_dir = temp.path().join("downloads");
+    let config = json!({
+        "download_dir": download_dir.to_str().unwrap(),
+        "max_pages": 1,
+        "retry_base_delay": 0.01
+    });
+    let mock_crawler = MockCrawler {
+        base_url: server.url(),
+    };
+    let sink = RecordingSink::default();
+    let downloaded = BoardCrawler::new(&config).run(&mock_crawler, &Client::new(), &sink);
+
+    assert_eq!(downloaded, 1);
+    assert!(download_dir.join("1_aaa.jpg").exists());
+    assert!(!download_dir.join("2_bbb.jpg").exists());
+    posts.assert();
+    busy.assert();
+    ready.assert();
+    gone.assert();
+    let msgs = sink.messages();
+    let retries: Vec<_> = msgs.iter().filter(|m| m.starts_with("Retrying")).collect();
+    assert_eq!(retries.len(), 1);
+    assert!(retries[0].starts_with("Retrying 1_aaa.jpg in 0.0s"));
+    assert!(retries[0].contains("429"));
+    assert!(msgs
+        .iter()
+        .any(|m| m.starts_with("ERROR:Download failed") && m.contains("404")));
+}
+
+#[test]
+fn test_sankaku_refreshes_expired_token() {
+    use base::web::crawlers::sankaku::SankakuCrawlerImpl;
+    use mockito::Matcher;
+
+    let mut server = Server::new();
+    let login = server
+        .mock("POST", "/auth/token")
+        .match_body(Matcher::PartialJson(json!({"login": "user"})))
+        .with_body(r#"{"access_token": "old", "token_type": "Bearer", "refresh_token": "r1"}"#)
+        .create();
+    let refresh = server
+        .mock("POST", "/auth/token")
+        .match_body(Matcher::PartialJson(json!({"refresh_token": "r1"})))
+        .with_body(r#"{"access_token": "new", "token_type": "Bearer"}"#)
+        .create();
+    let expired = server
+        .mock("GET", "/posts")
+        .match_query(Matcher::Any)
+        .match_header("authorization", "Bearer old")
+        .with_status(401)
+        .create();
+    let posts = server
+        .mock("GET", "/posts")
+        .match_query(Matcher::Any)
+        .match_header("authorization", "Bearer new")
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!([{"id": 7, "md5": "abc", "file_url": format!("{}/sample.jpg", server.url())}])
+                .to_string(),
+        )
+        .create();
+    // Samples are only served to logged in users
+    let image = server
+        .mock("GET", "/sample.jpg")
+        .match_header("authorization", "Bearer new")
+        .with_body("fake-image-bytes")
+        .create();
+
+    let temp = tempdir().unwrap();
+    let download_dir = temp.path().join("downloads");
+    let config = json!({
+        "download_dir": download_dir.to_str().unwrap(),
+        "max_pages": 1,
+        "login_config": {"username": "user", "password": "secret"}
+    });
+    let mut crawler = SankakuCrawlerImpl::new(&config);
+    crawler.base_url = server.url();
+    crawler.login_url = format!("{}/auth/token", server.url());
+
+    let sink = RecordingSink::default();
+    let downloaded = BoardCrawler::new(&config).run(&crawler, &Client::new(), &sink);
+
+    assert_eq!(downloaded, 1, "{:?}", sink.messages());
+    assert!(download_dir.join("7_abc.jpg").exists());
+    for mock in [login, refresh, expired, posts, image] {
+        mock.assert();
+    }
+    assert_eq!(crawler.refresh_token.borrow().as_deref(), Some("r1"));
+}
+
+#[test]
+fn test_board_crawler_python_callback() {
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _mocks = mock_board(&mut server);
+
+        let temp = tempdir().unwrap();
+        let download_dir = temp.path().join("downloads");
+        let config = json!({
+            "download_dir": download_dir.to_str().unwrap(),
+            "max_pages": 1
+        });
+        let mock_crawler = MockCrawler {
+            base_url: server.url(),
+        };
+
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        let sink = PyProgressSink::new(py, callback.to_owned().into_any().unbind());
+        let downloaded = BoardCrawler::new(&config).run(&mock_crawler, &Client::new(), &sink);
+        assert_eq!(sink.finish(downloaded).unwrap(), 1);
+        let msgs = callback.borrow().messages.lock().unwrap().clone();
         assert!(msgs.iter().any(|m| m.contains("image_saved:")));
+
+        // Clearing `_is_running` stops the next run before the first page
+        callback.borrow_mut()._is_running = false;
+        let sink = PyProgressSink::new(py, callback.to_owned().into_any().unbind());
+        let downloaded = BoardCrawler::new(&config).run(&mock_crawler, &Client::new(), &sink);
+        assert_eq!(sink.finish(downloaded).unwrap(), 0);
+        let msgs = callback.borrow().messages.lock().unwrap().clone();
+        assert_eq!(msgs.last().map(String::as_str), Some("Crawl cancelled."));
+    });
+}
+
+fn run_sequence_with_actions(py: Python<'_>, base_url: &str, actions: Value) -> Vec<String> {
+    use base::web::clients::web_requests::run_web_requests_sequence;
+
+    let config = json!({
+        "base_url": base_url,
+        "requests": [{"type": "GET", "param": "data"}],
+        "actions": actions
+    });
+    let callback = Bound::new(py, MockCallback::new()).unwrap();
+    run_web_requests_sequence(
+        py,
+        config.to_string(),
+        callback.to_owned().into_any().unbind(),
+    )
+    .unwrap();
+    let msgs = callback.borrow().messages.lock().unwrap().clone();
+    msgs
+}
+
+#[test]
+fn test_web_requests_print_json_pretty() {
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/data")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"name":"toolkit","nested":{"deep":{"value":1}}}"#)
+            .create();
+
+        let msgs = run_sequence_with_actions(
+            py,
+            &server.url(),
+            json!([{"type": "Print JSON (pretty)", "param": "1"}]),
+        );
+
+        let printed = msgs
+            .iter()
+            .find(|m| m.starts_with("  > Action: JSON Content:"))
+            .expect("pretty JSON was not emitted");
+        assert!(printed.contains("\"name\": \"toolkit\""));
+        assert!(printed.contains("{...1 keys}"));
+    });
+}
+
+#[test]
+fn test_web_requests_extract_json_field() {
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/data")
+            .with_status(200)
+            .with_body(r#"{"items":[{"id":7,"title":"first"}]}"#)
+            .create();
+
+        let msgs = run_sequence_with_actions(
+            py,
+            &server.url(),
+            json!([
+                {"type": "Extract JSON Field", "param": "items.0.title"},
+                {"type": "Extract JSON Field", "param": "items.3.title"}
+            ]),
+        );
+
+        assert!(msgs.contains(&"  > Action: JSON Field 'items.0.title': first".to_string()));
+        assert!(
+            msgs.contains(&"ERROR:  > Action: JSON Field 'items.3.title' not found.".to_string())
+        );
+    });
+}
+
+#[test]
+fn test_web_requests_extract_html_text() {
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/data")
+            .with_status(200)
+            .with_body("<html><body><h1 class='t'>Hello</h1><h1 class='t'>World</h1></body></html>")
+            .create();
+
+        let msgs = run_sequence_with_actions(
+            py,
+            &server.url(),
+            json!([{"type": "Extract HTML Text", "param": "h1.t"}]),
+        );
+
+        assert!(
+            msgs.contains(&"  > Action: HTML Text 'h1.t' (2 matches):\n Hello\n World".to_string())
+        );
+    });
+}
+
+#[test]
+fn test_web_requests_extract_links_to_file() {
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/data")
+            .with_status(200)
+            .with_body(
+                "<a href='/img/1.jpg'>1</a><a href='https://example.com/2.jpg'>2</a>\
+                 <a href='/img/1.jpg'>dup</a><a name='no-href'>x</a>",
+            )
+            .create();
+
+        let temp = tempdir().unwrap();
+        let out_file = temp.path().join("links").join("harvest.txt");
+
+        let msgs = run_sequence_with_actions(
+            py,
+            &server.url(),
+            json!([{"type": "Extract Links", "param": out_file.to_str().unwrap()}]),
+        );
+
+        assert!(msgs
+            .iter()
+            .any(|m| m.starts_with("  > Action: Extracted 2 links to")));
+        let written = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!(
+            written,
+            format!("{}/img/1.jpg\nhttps://example.com/2.jpg\n", server.url())
+        );
+    });
+}
+
+#[test]
+fn test_web_requests_bad_selector_skips_action() {
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/data")
+            .with_status(200)
+            .with_body("<h1 class='t'>Hello</h1><a href='/1.jpg'>1</a>")
+            .create();
+
+        let temp = tempdir().unwrap();
+        let out_file = temp.path().join("harvest.txt");
+
+        let msgs = run_sequence_with_actions(
+            py,
+            &server.url(),
+            json!([
+                {"type": "Extract HTML Text", "param": "h1["},
+                {"type": "Extract Links", "param": out_file.to_str().unwrap(), "selector": "a[[href"},
+                {"type": "Extract HTML Text", "param": "h1.t"}
+            ]),
+        );
+
+        assert!(msgs
+            .iter()
+            .any(|m| m.starts_with("ERROR:  > Action: Extract failed. Invalid selector 'h1['")));
+        assert!(msgs.iter().any(
+            |m| m.starts_with("ERROR:  > Action: Extract Links failed. Invalid selector 'a[[href'")
+        ));
+        assert!(!out_file.exists());
+        // The actions after the bad ones still run
+        assert!(msgs.contains(&"  > Action: HTML Text 'h1.t' (1 matches):\n Hello".to_string()));
+    });
+}
+
+#[test]
+fn test_web_requests_repeat_runs_each_iteration() {
+    use base::web::clients::web_requests::run_web_requests_sequence;
+
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let m = server
+            .mock("GET", "/poll")
+            .with_status(200)
+            .with_body(r#"{"cursor":"abc"}"#)
+            .expect(3)
+            .create();
+
+        let config = json!({
+            "base_url": server.url(),
+            "requests": [{"type": "GET", "param": "poll"}],
+            "actions": [{"type": "Extract JSON Field", "param": "cursor", "save_as": "cursor"}],
+            "repeat": {"count": 3, "interval_secs": 0, "persist_variables": true}
+        });
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        let result = run_web_requests_sequence(
+            py,
+            config.to_string(),
+            callback.to_owned().into_any().unbind(),
+        )
+        .unwrap();
+
+        let report: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(report["status"], "finished");
+        assert_eq!(report["iterations"], 3);
+        assert_eq!(report["succeeded"], 3);
+        m.assert();
+
+        let msgs = callback.borrow().messages.lock().unwrap().clone();
+        let iterations: Vec<Value> = msgs
+            .iter()
+            .filter_map(|m| m.strip_prefix("iteration:"))
+            .map(|rest| {
+                let (_, summary) = rest.split_once(':').unwrap();
+                serde_json::from_str(summary).unwrap()
+            })
+            .collect();
+        assert_eq!(iterations.len(), 3);
+        for (i, summary) in iterations.iter().enumerate() {
+            assert_eq!(summary["iteration"], json!(i + 1));
+            assert_eq!(summary["succeeded"], json!(1));
+            assert_eq!(summary["variables"]["cursor"], json!("abc"));
+        }
+    });
+}
+
+#[test]
+fn test_web_requests_methods_headers_and_bodies() {
+    use base::web::clients::web_requests::run_web_requests_sequence;
+
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let login = server
+            .mock("POST", "/login")
+            .match_header("content-type", "application/json")
+            .match_body(mockito::Matcher::Json(
+                json!({"user": "me", "remember": true}),
+            ))
+            .with_status(200)
+            .with_body(r#"{"token":"t0k","id":42}"#)
+            .create();
+        let patch = server
+            .mock("PATCH", "/items/42")
+            .match_header("authorization", "Bearer t0k")
+            .match_body(mockito::Matcher::Json(json!({"owner": 42})))
+            .with_status(204)
+            .create();
+        let legacy = server
+            .mock("POST", "/")
+            .match_body("name=old")
+            .with_status(200)
+            .create();
+        let head = server.mock("HEAD", "/status").with_status(200).create();
+        let delete = server
+            .mock("DELETE", "/items/42")
+            .match_header("x-status", "200")
+            .with_status(200)
+            .create();
+
+        let config = json!({
+            "base_url": server.url(),
+            "requests": [
+                {
+                    "method": "POST",
+                    "path": "login",
+                    "body_type": "json",
+                    "body": {"user": "me", "remember": true}
+                },
+                {
+                    "method": "PATCH",
+                    "path": "items/{last_response.json.id}",
+                    "headers": {"Authorization": "Bearer {last_response.json.token}"},
+                    "body_type": "json",
+                    "body": "{\"owner\": {last_response.json.id}}"
+                },
+                {"type": "POST", "param": "name:old"},
+                {"method": "HEAD", "path": "status"},
+                {
+                    "method": "DELETE",
+                    "path": "items/42",
+                    "headers": {"X-Status": "{last_response.status}"}
+                },
+                {"method": "TRACE"}
+            ],
+            "actions": []
+        });
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        run_web_requests_sequence(
+            py,
+            config.to_string(),
+            callback.to_owned().into_any().unbind(),
+        )
+        .unwrap();
+
+        for mock in [&login, &patch, &legacy, &head, &delete] {
+            mock.assert();
+        }
+        let msgs = callback.borrow().messages.lock().unwrap().clone();
+        assert!(msgs.contains(&"ERROR:Unsupported request type: TRACE".to_string()));
+    });
+}
+
+#[test]
+fn test_web_requests_assertions_and_extraction() {
+    use base::web::clients::web_requests::run_web_requests_sequence;
+
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _login = server
+            .mock("POST", "/login")
+            .with_status(200)
+            .with_body(r#"{"data":{"id":42,"access_token":"t0k"}}"#)
+            .create();
+        let _page = server
+            .mock("GET", "/page")
+            .with_status(200)
+            .with_body("<input name='csrf' value='c5rf'>")
+            .create();
+        let item = server
+            .mock("POST", "/items")
+            .match_header("authorization", "Bearer t0k")
+            .match_header("x-csrf", "c5rf")
+            .with_status(201)
+            .with_body(r#"{"data":{"id":"new"}}"#)
+            .create();
+        let _missing = server.mock("GET", "/gone").with_status(404).create();
+
+        let config = json!({
+            "base_url": server.url(),
+            "requests": [
+                {
+                    "method": "POST",
+                    "path": "login",
+                    "actions": [
+                        {"type": "Assert Status Equals", "param": "200"},
+                        {"type": "Assert JSON Path Equals", "param": "data.id=42"},
+                        {"type": "Extract JSON Path to Variable", "param": "token=data.access_token"}
+                    ]
+                },
+                {
+                    "method": "GET",
+                    "path": "page",
+                    "actions": [
+                        {"type": "Extract Regex to Variable", "param": "csrf=value='(\\w+)'"}
+                    ]
+                },
+                {
+                    "method": "POST",
+                    "path": "items",
+                    "headers": {"Authorization": "Bearer {token}", "X-Csrf": "{csrf}"},
+                    "actions": [
+                        {"type": "Assert Status Equals", "param": "201"},
+                        {"type": "Assert Body Contains", "param": "missing text"},
+                        {"type": "Assert JSON Path Equals", "param": "data.id=old"}
+                    ]
+                },
+                {
+                    "method": "GET",
+                    "path": "gone",
+                    "actions": [{"type": "Assert Status Equals", "param": "404"}]
+                }
+            ],
+            "actions": []
+        });
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        let result = run_web_requests_sequence(
+            py,
+            config.to_string(),
+            callback.to_owned().into_any().unbind(),
+        )
+        .unwrap();
+        item.assert();
+
+        let report: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(report["passed"], false);
+        assert_eq!(report["succeeded"], 3);
+        assert_eq!(report["failed"], 1);
+        let passed: Vec<bool> = report["requests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["passed"].as_bool().unwrap())
+            .collect();
+        assert_eq!(passed, vec![true, true, false, true]);
+        assert_eq!(
+            report["requests"][2]["errors"],
+            json!([
+                "Assert failed: Body does not contain 'missing text'",
+                "Assert failed: JSON Field 'data.id' is \"new\", expected old"
+            ])
+        );
+
+        let msgs = callback.borrow().messages.lock().unwrap().clone();
+        assert!(msgs.contains(&"  > Action: Saved {token} = t0k".to_string()));
+        assert!(msgs.contains(
+            &"ERROR:  > Assert failed: Body does not contain 'missing text'".to_string()
+        ));
+    });
+}
+
+#[test]
+fn test_web_requests_keep_session_cookies() {
+    use base::web::clients::web_requests::run_web_requests_sequence;
+
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let login = server
+            .mock("POST", "/login")
+            .with_status(200)
+            .with_header("set-cookie", "sid=s3ss; Path=/; HttpOnly")
+            .expect(1)
+            .create();
+        let account = server
+            .mock("GET", "/account")
+            .match_header("cookie", mockito::Matcher::Regex("sid=s3ss".to_string()))
+            .match_header("cookie", mockito::Matcher::Regex("lang=en".to_string()))
+            .with_status(200)
+            .expect(2)
+            .create();
+
+        let temp = tempdir().unwrap();
+        let cookies_file = temp.path().join("cookies.json");
+        let run = |requests: Value| {
+            let config = json!({
+                "base_url": server.url(),
+                "requests": requests,
+                "actions": [],
+                "cookies_file": cookies_file.to_str().unwrap(),
+                "cookies": [{"name": "lang", "value": "en", "domain": "127.0.0.1"}]
+            });
+            let callback = Bound::new(py, MockCallback::new()).unwrap();
+            let result = run_web_requests_sequence(
+                py,
+                config.to_string(),
+                callback.to_owned().into_any().unbind(),
+            )
+            .unwrap();
+            serde_json::from_str::<Value>(&result).unwrap()
+        };
+
+        let report = run(json!([
+            {"method": "POST", "path": "login"},
+            {"method": "GET", "path": "account"}
+        ]));
+        assert_eq!(report["passed"], true, "{}", report);
+
+        // The next run is still signed in
+        let report = run(json!([{"method": "GET", "path": "account"}]));
+        assert_eq!(report["passed"], true, "{}", report);
+        login.assert();
+        account.assert();
+
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(&cookies_file).unwrap()).unwrap();
+        let names: Vec<&str> = saved
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["lang", "sid"]);
+    });
+}
+
+#[test]
+fn test_web_requests_proxy_and_rate_limit() {
+    use base::web::clients::web_requests::run_web_requests_sequence;
+
+    Python::initialize();
+    Python::attach(|py| {
+        let mut proxy = Server::new();
+        let api = proxy
+            .mock("GET", "/status")
+            .match_header("host", "api.invalid")
+            .with_status(200)
+            .expect(3)
+            .create();
+
+        let config = json!({
+            "base_url": "http://api.invalid",
+            "requests": [{"path": "status"}, {"path": "status"}, {"path": "status"}],
+            "actions": [],
+            "proxy": proxy.url(),
+            "rate_limit": {"requests_per_minute": 600, "burst": 2},
+        });
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        let started = std::time::Instant::now();
+        let result = run_web_requests_sequence(
+            py,
+            config.to_string(),
+            callback.to_owned().into_any().unbind(),
+        )
+        .unwrap();
+        let report: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(report["succeeded"], 3, "{}", report);
+        api.assert();
+        // Two at once, then the third a tenth of a second on
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+
+        let bad =
+            json!({"base_url": "http://api.invalid", "rate_limit": {"requests_per_minute": 0}});
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        assert!(run_web_requests_sequence(
+            py,
+            bad.to_string(),
+            callback.to_owned().into_any().unbind()
+        )
+        .is_err());
+    });
+}
+
+#[test]
+fn test_validate_job_config() {
+    use base::web::validate_job_config;
+
+    Python::initialize();
+    Python::attach(|py| {
+        validate_job_config(
+            "web_requests".to_string(),
+            json!({"base_url": "https://httpbin.org/", "requests": []}).to_string(),
+        )
+        .unwrap();
+
+        let err = validate_job_config(
+            "image_crawler".to_string(),
+            json!({"url": "https://site", "heedless": true, "max_pages": 2.5}).to_string(),
+        )
+        .unwrap_err();
+        assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+        let message = err.value(py).to_string();
+        assert!(
+            message.starts_with("Invalid image_crawler config:"),
+            "{}",
+            message
+        );
+        assert!(message.contains("`heedless`: unknown field; did you mean `headless`?"));
+        assert!(message.contains("`max_pages`: invalid type: floating point `2.5`, expected u64"));
+
+        assert!(validate_job_config("crawl".to_string(), "{}".to_string()).is_err());
+        assert!(validate_job_config("sync".to_string(), "{".to_string()).is_err());
+    });
+}
+
+struct MockEngine {
+    searched: Vec<String>,
+    fail_on: Option<String>,
+}
+
+impl ReverseSearchEngine for MockEngine {
+    fn name(&self) -> &str {
+        "MockEngine"
+    }
+    async fn search(
+        &mut self,
+        _py: Python<'_>,
+        _callback_obj: &Py<PyAny>,
+        input: &SearchInput,
+    ) -> Result<SearchOutcome> {
+        let SearchInput::File(image_path) = input else {
+            return Err(anyhow::anyhow!("batch mode only searches local files"));
+        };
+        self.searched.push(image_path.to_string());
+        if self.fail_on.as_deref() == Some(image_path) {
+            return Err(anyhow::anyhow!("engine blew up"));
+        }
+        Ok(SearchOutcome {
+            status: SearchStatus::Completed,
+            results: vec![SearchResult {
+                title: "hit".to_string(),
+                ..SearchResult::new(&format!("https://src.test/{}", image_path.len()))
+            }],
+        })
+    }
+}
+
+#[test]
+fn test_reverse_search_batch_checkpoint_resume() {
+    Python::initialize();
+    Python::attach(|py| {
+        let temp = tempdir().unwrap();
+        let images: Vec<String> = ["a.jpg", "b.jpg", "c.jpg"]
+            .iter()
+            .map(|name| {
+                let p = temp.path().join(name);
+                std::fs::write(&p, "img").unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let output = temp.path().join("results.json");
+        let options = BatchOptions {
+            output_path: Some(output.clone()),
+            csv_path: None,
+            delay: std::time::Duration::ZERO,
+        };
+
+        // Simulate an interrupted run that only finished the first image.
+        let mut partial = BatchCheckpoint::default();
+        partial.results.insert(
+            images[0].clone(),
+            vec![SearchResult::new("https://old.test")],
+        );
+        partial.save(&output).unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        let callback_obj = callback.to_owned().into_any().unbind();
+        let mut engine = MockEngine {
+            searched: Vec::new(),
+            fail_on: Some(images[2].clone()),
+        };
+
+        let summary = rt
+            .block_on(run_batch(py, &mut engine, &images, &options, &callback_obj))
+            .unwrap();
+
+        // The finished image is not searched again.
+        assert_eq!(engine.searched, vec![images[1].clone(), images[2].clone()]);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.processed, 1);
+        assert_eq!(summary.failed, 1);
+
+        let saved = BatchCheckpoint::load(&output).unwrap();
+        assert!(saved.is_done(&images[0]));
+        assert!(saved.is_done(&images[1]));
+        assert!(!saved.is_done(&images[2]));
+        assert!(saved.errors[&images[2]].contains("engine blew up"));
+
+        let msgs = callback.borrow().messages.lock().unwrap().clone();
+        assert!(msgs.contains(&format!("image_completed:{}:1", images[1])));
+        assert!(msgs.contains(&format!("image_completed:{}:0", images[2])));
+
+        // A second run only retries the failed image.
+        engine.searched.clear();
+        engine.fail_on = None;
+        let summary = rt
+            .block_on(run_batch(py, &mut engine, &images, &options, &callback_obj))
+            .unwrap();
+        assert_eq!(engine.searched, vec![images[2].clone()]);
+        assert_eq!(summary.skipped, 2);
+        assert_eq!(summary.results.len(), 3);
+        assert!(BatchCheckpoint::load(&output).unwrap().errors.is_empty());
+    });
+}
+
+fn png_bytes(width: u32, height: u32) -> Vec<u8> {
+    let img = image::RgbImage::new(width, height);
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+    buf.into_inner()
+}
+
+#[test]
+fn test_reverse_search_download_top_results() {
+    Python::initialize();
+    Python::attach(|py| {
+        let mut server = Server::new();
+        let _small = server
+            .mock("GET", "/small.png")
+            .with_status(200)
+            .with_body(png_bytes(100, 50))
+            .create();
+        let _large = server
+            .mock("GET", "/large.png")
+            .with_status(200)
+            .with_body(png_bytes(640, 480))
+            .create();
+        let _page = server
+            .mock("GET", "/post/9")
+            .with_status(200)
+            .with_body(
+                r#"<html><head><meta property="og:image" content="/large.png"></head></html>"#,
+            )
+            .create();
+
+        let temp = tempdir().unwrap();
+        let dest = temp.path().join("hits");
+        let results: Vec<_> = ["small.png", "large.png", "post/9", "large.png"]
+            .iter()
+            .map(|path| SearchResult::new(&format!("{}/{}", server.url(), path)))
+            .collect();
+        let options = DownloadOptions {
+            top_n: 2,
+            dest_dir: dest.clone(),
+            min_resolution: Some((320, 240)),
+        };
+
+        let callback = Bound::new(py, MockCallback::new()).unwrap();
+        let callback_obj = callback.to_owned().into_any().unbind();
+        let client = reqwest::Client::new();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let saved = rt
+            .block_on(download_top_results(
+                py,
+                &client,
+                &results,
+                &options,
+                &callback_obj,
+            ))
+            .unwrap();
+
+        // The small image is filtered out; the direct hit and the og:image page fill top_n.
+        assert_eq!(
+            saved,
+            vec![dest.join("large.png"), dest.join("large (1).png")]
+        );
+        assert!(!dest.join("small.png").exists());
+
+        let msgs = callback.borrow().messages.lock().unwrap().clone();
+        assert!(msgs.iter().any(|m| m.contains("100x50 is below 320x240")));
+        assert_eq!(
+            msgs.iter()
+                .filter(|m| m.starts_with("image_saved:"))
+                .count(),
+            2
+        );
     });
 }
@@ -85,12 +85,12 @@ def bench_image_single():
         tmp_path = tmp.name
 
     # Benchmark load_image_batch on a 1 item list
-    results = base.load_image_batch([tmp_path], 180) # pyrefly: ignore [missing-attribute]
+    thumbnails, _ = base.load_image_batch([tmp_path], 180) # pyrefly: ignore [missing-attribute]
 
     # Cleanup
     Path(tmp_path).unlink()
 
-    return len(results) if results else 0
+    return len(thumbnails)
 
 
 @runner.benchmark("image_thumbnail_batch_10_native", iterations=5, warmup=1)
@@ -109,13 +109,13 @@ def bench_image_batch_10():
             tmp_paths.append(tmp.name)
 
     # Benchmark parallel batch processing
-    results = base.load_image_batch(tmp_paths, 180) # pyrefly: ignore [missing-attribute]
+    thumbnails, _ = base.load_image_batch(tmp_paths, 180) # pyrefly: ignore [missing-attribute]
 
     # Cleanup
     for path in tmp_paths:
         Path(path).unlink()
 
-    return len(results) if results else 0
+    return len(thumbnails)
 
 
 @runner.benchmark("video_thumbnail_single_180px", iterations=5, warmup=1)
@@ -43,6 +43,8 @@ class NativeExt:
 
     # Phase 9: web extensions
     run_board_crawler              = staticmethod(lambda *a, **kw: _base.web.run_board_crawler(*a, **kw))
+    get_crawl_state                = staticmethod(lambda *a, **kw: _base.web.get_crawl_state(*a, **kw))
+    validate_job_config            = staticmethod(lambda *a, **kw: _base.web.validate_job_config(*a, **kw))
     run_sync                       = staticmethod(lambda *a, **kw: _base.web.run_sync(*a, **kw))
     run_reverse_image_search       = staticmethod(lambda *a, **kw: _base.web.run_reverse_image_search(*a, **kw))
     run_image_crawler              = staticmethod(lambda *a, **kw: _base.web.run_image_crawler(*a, **kw))
@@ -50,6 +52,7 @@ class NativeExt:
     # Phase 8: core
     convert_single_image           = staticmethod(lambda *a, **kw: _base.core.convert_single_image(*a, **kw))
     convert_image_batch            = staticmethod(lambda *a, **kw: _base.core.convert_image_batch(*a, **kw))
+    convert_image_batch_detailed   = staticmethod(lambda *a, **kw: _base.core.convert_image_batch_detailed(*a, **kw))
     convert_video                  = staticmethod(lambda *a, **kw: _base.core.convert_video(*a, **kw))
     get_files_by_extension         = staticmethod(lambda *a, **kw: _base.core.get_files_by_extension(*a, **kw))
     delete_files_by_extensions     = staticmethod(lambda *a, **kw: _base.core.delete_files_by_extensions(*a, **kw))
@@ -16,6 +16,7 @@ class DropboxDriveSync:
         access_token: Any | None = None,
         dry_run: bool = False,
         logger: Callable[[str], None] = print,
+        progress: Callable[[str, int, int], None] | None = None,
         action_local_orphans: str = "upload",
         action_remote_orphans: str = "download",
         **kwargs,
@@ -29,6 +30,7 @@ class DropboxDriveSync:
             "action_remote": action_remote_orphans,
         }
         self.logger = logger
+        self.progress = progress
         self._is_running = True
 
     def stop(self):
@@ -38,6 +40,11 @@ class DropboxDriveSync:
         """Called by C++ to log messages."""
         self.logger(msg)
 
+    def on_transfer_progress(self, name: str, sent: int, total: int):
+        """Called by C++ as a large upload goes up."""
+        if self.progress:
+            self.progress(name, sent, total)
+
     def execute_sync(self) -> tuple[bool, str]:
         try:
             config_json = json.dumps(self.config)
@@ -46,6 +53,8 @@ class DropboxDriveSync:
             stats = json.loads(result_json)
 
             summary = f"Completed with {stats['uploaded'] + stats['downloaded'] + stats['deleted_local'] + stats['deleted_remote']} actions. (Up: {stats['uploaded']}, Down: {stats['downloaded']}, Del-L: {stats['deleted_local']}, Del-R: {stats['deleted_remote']})"
+            if stats.get("verification_failed"):
+                summary += f" {stats['verification_failed']} transfer(s) failed checksum verification."
             return (True, summary)
         except Exception as e:
             self.logger(f"❌ Critical Error in C++ Sync: {e}")
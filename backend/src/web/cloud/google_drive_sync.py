@@ -1,5 +1,6 @@
 import json
 import os
+from datetime import datetime, timedelta, timezone
 from typing import Any, Callable
 
 import base  # Native extension
@@ -19,6 +20,7 @@ class GoogleDriveSync:
         google_access_token: str = None,  # Used for personal account
         dry_run: bool = False,
         logger: Callable[[str], None] = print,
+        progress: Callable[[str, int, int], None] | None = None,
         action_local_orphans: str = "upload",
         action_remote_orphans: str = "download",
         service_account_data: Any = None,
@@ -29,6 +31,8 @@ class GoogleDriveSync:
     ):
         SCOPES = ["https://www.googleapis.com/auth/drive"]
         access_token = google_access_token
+        # Personal credentials, which carry what's needed to refresh them
+        user_creds = None
 
         # 1. Resolve Service Account Authentication
         if not access_token and service_account_data:
@@ -121,6 +125,7 @@ class GoogleDriveSync:
 
                 if creds:
                     access_token = creds.token
+                    user_creds = creds
             except Exception as e:
                 logger(f"❌ Error obtaining personal account access token: {e}")
 
@@ -132,7 +137,17 @@ class GoogleDriveSync:
             "action_local": action_local_orphans,
             "action_remote": action_remote_orphans,
         }
+        if user_creds:
+            # Lets the C++ side renew the access token if it expires mid-sync
+            self.config.update({
+                "refresh_token": user_creds.refresh_token,
+                "client_id": user_creds.client_id,
+                "client_secret": user_creds.client_secret,
+            })
+        self._user_creds = user_creds
+        self.token_file = token_file
         self.logger = logger
+        self.progress = progress
         self._is_running = True
         self.user_email_to_share_with = user_email_to_share_with
 
@@ -143,6 +158,29 @@ class GoogleDriveSync:
         """Called by C++ to log messages."""
         self.logger(msg)
 
+    def on_transfer_progress(self, name: str, sent: int, total: int):
+        """Called by C++ as a large upload goes up."""
+        if self.progress:
+            self.progress(name, sent, total)
+
+    def on_token_refreshed(self, token_json: str):
+        """Called by C++ after it renewed the access token; keeps the token file current."""
+        token = json.loads(token_json)
+        self.config["access_token"] = token["access_token"]
+        if not (self._user_creds and self.token_file):
+            return
+        self._user_creds.token = token["access_token"]
+        if token.get("expires_in"):
+            # google-auth keeps expiry as naive UTC
+            self._user_creds.expiry = datetime.now(timezone.utc).replace(tzinfo=None) + timedelta(
+                seconds=token["expires_in"]
+            )
+        try:
+            with open(self.token_file, "w") as f:
+                f.write(self._user_creds.to_json())
+        except OSError as e:
+            self.logger(f"⚠️ Failed to save refreshed token: {e}")
+
     def execute_sync(self) -> tuple[bool, str]:
         try:
             config_json = json.dumps(self.config)
@@ -151,6 +189,8 @@ class GoogleDriveSync:
             stats = json.loads(result_json)
 
             summary = f"Completed with {stats['uploaded'] + stats['downloaded'] + stats['deleted_local'] + stats['deleted_remote']} actions. (Up: {stats['uploaded']}, Down: {stats['downloaded']}, Del-L: {stats['deleted_local']}, Del-R: {stats['deleted_remote']})"
+            if stats.get("verification_failed"):
+                summary += f" {stats['verification_failed']} transfer(s) failed checksum verification."
             return (True, summary)
         except Exception as e:
             self.logger(f"❌ Critical Error in C++ Sync: {e}")
@@ -16,9 +16,12 @@ class OneDriveSync:
         access_token: str = None,
         dry_run: bool = False,
         logger: Callable[[str], None] = print,
+        progress: Callable[[str, int, int], None] | None = None,
         action_local_orphans: str = "upload",
         action_remote_orphans: str = "download",
         client_id: Any | None = None,
+        refresh_token: str | None = None,
+        client_secret: str | None = None,
         **kwargs,
     ):
         self.config = {
@@ -28,8 +31,13 @@ class OneDriveSync:
             "dry_run": dry_run,
             "action_local": action_local_orphans,
             "action_remote": action_remote_orphans,
+            # With these the C++ side renews an expired access token
+            "refresh_token": refresh_token,
+            "client_id": client_id,
+            "client_secret": client_secret,
         }
         self.logger = logger
+        self.progress = progress
         self._is_running = True
         self.client_id = client_id
 
@@ -40,6 +48,18 @@ class OneDriveSync:
         """Called by C++ to log messages."""
         self.logger(msg)
 
+    def on_transfer_progress(self, name: str, sent: int, total: int):
+        """Called by C++ as a large upload goes up."""
+        if self.progress:
+            self.progress(name, sent, total)
+
+    def on_token_refreshed(self, token_json: str):
+        """Called by C++ after it renewed the access token. Microsoft replaces the refresh token too."""
+        token = json.loads(token_json)
+        self.config["access_token"] = token["access_token"]
+        if token.get("refresh_token"):
+            self.config["refresh_token"] = token["refresh_token"]
+
     def execute_sync(self) -> tuple[bool, str]:
         try:
             config_json = json.dumps(self.config)
@@ -48,6 +68,8 @@ class OneDriveSync:
             stats = json.loads(result_json)
 
             summary = f"Completed with {stats['uploaded'] + stats['downloaded'] + stats['deleted_local'] + stats['deleted_remote']} actions. (Up: {stats['uploaded']}, Down: {stats['downloaded']}, Del-L: {stats['deleted_local']}, Del-R: {stats['deleted_remote']})"
+            if stats.get("verification_failed"):
+                summary += f" {stats['verification_failed']} transfer(s) failed checksum verification."
             return (True, summary)
         except Exception as e:
             self.logger(f"❌ Critical Error in C++ Sync: {e}")
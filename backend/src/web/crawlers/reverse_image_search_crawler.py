@@ -163,19 +163,27 @@ class GoogleSearchStrategy(ReverseSearchEngine):
             # the strategy itself needing to inherit from QObject.
             proxy = _StatusProxy(self._emit_status)
             results_json: str = base.run_reverse_image_search(json.dumps(config), proxy)
-            raw: List[dict] = json.loads(results_json)
+            payload = json.loads(results_json)
         except Exception as exc:
             log.error("C++ reverse image search failed: %s", exc)
             self._emit_status(f"Error: {exc}")
             return []
 
+        # The extension reports {"status": ..., "results": [...]}; each result
+        # carries width/height when the engine showed a size.
+        raw: List[dict] = payload.get("results", []) if isinstance(payload, dict) else payload
         results = [
             ReverseSearchResult(
                 url=r.get("url", ""),
-                engine=ENGINE_GOOGLE,
+                engine=r.get("engine") or ENGINE_GOOGLE,
                 score=1.0,
-                resolution=r.get("resolution", "Unknown"),
+                resolution=(
+                    f"{r['width']}x{r['height']}"
+                    if r.get("width") and r.get("height")
+                    else "Unknown"
+                ),
                 title=r.get("title"),
+                thumbnail_url=r.get("thumbnail_url"),
             )
             for r in raw
             if r.get("url")
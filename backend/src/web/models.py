@@ -15,6 +15,8 @@ class ReverseSearchResult:
         resolution: Human-readable dimension string, e.g. ``"1920x1080"``,
             or ``"Unknown"`` when unavailable.
         title: Optional page title or filename label.
+        thumbnail_url: Optional preview image of the match, when the engine
+            shows one.
     """
 
     url: str
@@ -22,6 +24,7 @@ class ReverseSearchResult:
     score: float = 1.0
     resolution: str = "Unknown"
     title: Optional[str] = None
+    thumbnail_url: Optional[str] = None
 
     def to_dict(self) -> dict:
         """Serialise to the legacy ``Dict[str, str]`` format expected by the GUI."""
@@ -31,4 +34,5 @@ class ReverseSearchResult:
             "score": f"{self.score:.4f}",
             "engine": self.engine,
             "title": self.title or "",
+            "thumbnail_url": self.thumbnail_url or "",
         }
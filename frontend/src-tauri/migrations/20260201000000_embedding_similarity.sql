@@ -0,0 +1,6 @@
+-- Embedding column for similarity search (already present on databases created from the initial schema)
+ALTER TABLE images ADD COLUMN IF NOT EXISTS embedding vector(128);
+
+-- search_similar orders by cosine distance, so index with cosine ops instead of L2
+DROP INDEX IF EXISTS idx_images_embedding;
+CREATE INDEX IF NOT EXISTS idx_images_embedding_cosine ON images USING hnsw (embedding vector_cosine_ops);
@@ -0,0 +1,7 @@
+-- Trigram matching for tag autocomplete and fuzzy filename search
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Case-insensitive prefix lookups (lower(name) LIKE 'abc%')
+CREATE INDEX IF NOT EXISTS idx_tags_name_lower ON tags (lower(name) text_pattern_ops);
+CREATE INDEX IF NOT EXISTS idx_tags_name_trgm ON tags USING gin (name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_images_filename_trgm ON images USING gin (filename gin_trgm_ops);
@@ -0,0 +1,15 @@
+-- Cached thumbnails, one per (image, max edge). source_modified is the
+-- image's date_modified when the thumbnail was generated; a mismatch means
+-- the thumbnail is stale.
+CREATE TABLE IF NOT EXISTS thumbnails (
+    image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+    size INTEGER NOT NULL,
+    format VARCHAR(10) NOT NULL,
+    bytes BYTEA NOT NULL,
+    byte_size INTEGER NOT NULL,
+    source_modified TIMESTAMPTZ,
+    generated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (image_id, size)
+);
+
+CREATE INDEX IF NOT EXISTS idx_thumbnails_generated_at ON thumbnails(generated_at);
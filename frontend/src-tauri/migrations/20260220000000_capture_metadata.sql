@@ -0,0 +1,14 @@
+-- Camera settings, capture time and location parsed from EXIF on import
+ALTER TABLE images ADD COLUMN IF NOT EXISTS camera_make VARCHAR(255);
+ALTER TABLE images ADD COLUMN IF NOT EXISTS camera_model VARCHAR(255);
+ALTER TABLE images ADD COLUMN IF NOT EXISTS lens VARCHAR(255);
+ALTER TABLE images ADD COLUMN IF NOT EXISTS focal_length DOUBLE PRECISION;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS iso INTEGER;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS aperture DOUBLE PRECISION;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS shutter VARCHAR(32);
+ALTER TABLE images ADD COLUMN IF NOT EXISTS date_taken TIMESTAMPTZ;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS gps_lat DOUBLE PRECISION;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS gps_lon DOUBLE PRECISION;
+
+CREATE INDEX IF NOT EXISTS idx_images_camera_model ON images(camera_model);
+CREATE INDEX IF NOT EXISTS idx_images_date_taken ON images(date_taken);
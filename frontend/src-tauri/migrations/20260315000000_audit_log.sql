@@ -0,0 +1,14 @@
+-- Change log of image adds, updates, deletes and tag edits, for the
+-- recent-changes feed and undo. payload is the image with its tags as it was
+-- before the change (after it, for adds). image_id has no foreign key so
+-- entries outlive the images they describe.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    operation VARCHAR(16) NOT NULL,
+    image_id INTEGER NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    undone_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_image ON audit_log(image_id, id);
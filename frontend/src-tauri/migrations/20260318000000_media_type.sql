@@ -0,0 +1,7 @@
+-- Videos share the images table: `media_type` tells them apart, and the
+-- duration and codec come from ffprobe when they are imported
+ALTER TABLE images ADD COLUMN IF NOT EXISTS media_type TEXT NOT NULL DEFAULT 'image';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS duration_secs DOUBLE PRECISION;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS video_codec TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_images_media_type ON images(media_type);
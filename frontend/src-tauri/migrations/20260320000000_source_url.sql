@@ -0,0 +1,3 @@
+-- Where a crawled image was first posted, as the board's source field
+-- gives it, recorded when crawled images are imported with their metadata
+ALTER TABLE images ADD COLUMN IF NOT EXISTS source_url TEXT;
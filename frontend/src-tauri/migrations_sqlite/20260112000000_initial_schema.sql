@@ -0,0 +1,49 @@
+-- SQLite schema mirroring the PostgreSQL migrations, minus pgvector embeddings
+
+-- Create groups table
+CREATE TABLE IF NOT EXISTS groups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT UNIQUE NOT NULL
+);
+
+-- Create subgroups table
+CREATE TABLE IF NOT EXISTS subgroups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    group_id INTEGER REFERENCES groups(id) ON DELETE CASCADE,
+    UNIQUE(name, group_id)
+);
+
+-- Create tags table
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT UNIQUE NOT NULL,
+    type TEXT
+);
+
+-- Create images table
+CREATE TABLE IF NOT EXISTS images (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT UNIQUE NOT NULL,
+    filename TEXT NOT NULL,
+    file_size INTEGER,
+    width INTEGER,
+    height INTEGER,
+    group_name TEXT,
+    subgroup_name TEXT,
+    date_added TEXT NOT NULL,
+    date_modified TEXT
+);
+
+-- Create image_tags junction table
+CREATE TABLE IF NOT EXISTS image_tags (
+    image_id INTEGER REFERENCES images(id) ON DELETE CASCADE,
+    tag_id INTEGER REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (image_id, tag_id)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX IF NOT EXISTS idx_images_group ON images(group_name);
+CREATE INDEX IF NOT EXISTS idx_images_subgroup ON images(subgroup_name);
+CREATE INDEX IF NOT EXISTS idx_images_path ON images(file_path);
+CREATE INDEX IF NOT EXISTS idx_tags_name_nocase ON tags(name COLLATE NOCASE);
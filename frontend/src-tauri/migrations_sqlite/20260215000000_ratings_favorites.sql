@@ -0,0 +1,8 @@
+-- Star ratings (1-5, NULL = unrated), favorites, and view tracking
+ALTER TABLE images ADD COLUMN rating INTEGER CHECK (rating BETWEEN 1 AND 5);
+ALTER TABLE images ADD COLUMN favorite BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE images ADD COLUMN view_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE images ADD COLUMN last_viewed TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_images_rating ON images(rating);
+CREATE INDEX IF NOT EXISTS idx_images_favorite ON images(favorite) WHERE favorite;
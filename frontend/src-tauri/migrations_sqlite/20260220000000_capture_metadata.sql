@@ -0,0 +1,14 @@
+-- Camera settings, capture time and location parsed from EXIF on import
+ALTER TABLE images ADD COLUMN camera_make TEXT;
+ALTER TABLE images ADD COLUMN camera_model TEXT;
+ALTER TABLE images ADD COLUMN lens TEXT;
+ALTER TABLE images ADD COLUMN focal_length REAL;
+ALTER TABLE images ADD COLUMN iso INTEGER;
+ALTER TABLE images ADD COLUMN aperture REAL;
+ALTER TABLE images ADD COLUMN shutter TEXT;
+ALTER TABLE images ADD COLUMN date_taken TEXT;
+ALTER TABLE images ADD COLUMN gps_lat REAL;
+ALTER TABLE images ADD COLUMN gps_lon REAL;
+
+CREATE INDEX IF NOT EXISTS idx_images_camera_model ON images(camera_model);
+CREATE INDEX IF NOT EXISTS idx_images_date_taken ON images(date_taken);
@@ -0,0 +1,8 @@
+-- Normalized, platform case-folded path (path_key) and lower-cased filename
+-- (filename_key) stored alongside the display path. Existing rows are
+-- backfilled by the app, which owns the normalization rules, and rows that
+-- turn out to share a path_key are merged before its unique index is built.
+ALTER TABLE images ADD COLUMN path_key TEXT;
+ALTER TABLE images ADD COLUMN filename_key TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_images_filename_key ON images(filename_key);
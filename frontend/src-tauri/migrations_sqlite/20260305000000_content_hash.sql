@@ -0,0 +1,5 @@
+-- SHA-256 of the file contents, recorded at import so records can be
+-- relinked to their files after they move on disk
+ALTER TABLE images ADD COLUMN content_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_images_content_hash ON images(content_hash);
@@ -0,0 +1,21 @@
+-- Hand-curated albums. Unlike groups an image can belong to any number of
+-- collections; position gives the manual order within each one.
+CREATE TABLE IF NOT EXISTS collections (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT UNIQUE NOT NULL,
+    description TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS collection_images (
+    collection_id INTEGER NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+    image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    added_at TEXT NOT NULL,
+    PRIMARY KEY (collection_id, image_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_collection_images_position
+    ON collection_images(collection_id, position);
+CREATE INDEX IF NOT EXISTS idx_collection_images_image ON collection_images(image_id);
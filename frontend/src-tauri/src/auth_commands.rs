@@ -1,6 +1,12 @@
+use crate::settings::{SettingsData, SettingsStore};
+use crate::vault::legacy::{self, LegacyFiles};
+use crate::vault::{KdfParams, Vault, VaultError};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
 
 #[derive(Serialize, Deserialize)]
 pub struct AuthResult {
@@ -9,320 +15,265 @@ pub struct AuthResult {
     pub profiles: Option<Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SettingsData {
-    pub theme: String,
-    pub tab_configurations: HashMap<String, HashMap<String, serde_json::Value>>,
-    pub system_preference_profiles: HashMap<String, serde_json::Value>,
-    pub active_tab_configs: HashMap<String, String>,
+impl AuthResult {
+    fn failure(message: impl ToString) -> Self {
+        AuthResult {
+            success: false,
+            message: Some(message.to_string()),
+            profiles: None,
+        }
+    }
 }
 
-/// Authenticate a user using the Python VaultManager backend
-#[tauri::command]
-pub fn authenticate_user(account_name: String, password: String) -> Result<AuthResult, String> {
-    // Call Python backend for authentication
-    // This is a bridge between Tauri and the existing Python VaultManager system
-
-    let python_script = format!(
-        r#"
-import sys
-import json
-import hashlib
-# Redirect stdout to stderr
-_orig_stdout = sys.stdout
-sys.stdout = sys.stderr
-
-sys.path.insert(0, '../../backend/src')
-
-result = {{'success': False, 'message': 'Unknown error'}}
-
-try:
-    from core.vault_manager import VaultManager
-    import constants as udef
-
-    udef.update_cryptographic_values('{}')
-    vm = VaultManager(udef.JAR_FILE)
-    vm.load_keystore(udef.KEYSTORE_FILE, '{}')
-    vm.get_secret_key(udef.KEY_ALIAS, '{}')
-    vm.init_vault(udef.VAULT_FILE)
-    
-    stored_data = vm.load_account_credentials()
-    
-    if stored_data.get('account_name') != '{}':
-        result = {{'success': False, 'message': 'Account name mismatch'}}
-    else:
-        # Verify password (hash comparison)
-        stored_hash = stored_data.get('hashed_password')
-        stored_salt = stored_data.get('salt')
-        
-        password_combined = ('{}' + stored_salt + vm.PEPPER).encode('utf-8')
-        verification_hash = hashlib.sha256(password_combined).hexdigest()
-        
-        if verification_hash == stored_hash:
-            profiles = list(stored_data.get('system_preference_profiles', {{}}).keys())
-            result = {{'success': True, 'profiles': profiles}}
-        else:
-            result = {{'success': False, 'message': 'Invalid password'}}
-        
-except Exception as e:
-    result = {{'success': False, 'message': str(e)}}
-
-sys.stdout = _orig_stdout
-print(f"RESULT: {{json.dumps(result)}}")
-"#,
-        account_name, password, password, account_name, password
-    );
-
-    let output = Command::new("python3")
-        .arg("-c")
-        .arg(&python_script)
-        .output()
-        .map_err(|e| format!("Failed to execute Python: {}", e))?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
+/// Vaults unlocked this session, by account name. Settings commands only
+/// work for accounts signed in here.
+#[derive(Default)]
+pub struct AuthState {
+    unlocked: Mutex<HashMap<String, Vault>>,
+}
 
-    if !stderr.is_empty() {
-        log::info!("Python stderr: {}", stderr);
+impl AuthState {
+    fn unlock(&self, vault: Vault) {
+        self.unlocked
+            .lock()
+            .unwrap()
+            .insert(vault.account_name().to_string(), vault);
     }
 
-    // Find the line starting with RESULT:
-    let result_line = stdout
-        .lines()
-        .find(|line| line.starts_with("RESULT: "))
-        .ok_or_else(|| {
-            log::error!("No RESULT: marker found in output. Raw output: {}", stdout);
-            format!("No authentication result marker found in backend output")
-        })?;
-
-    let json_str = &result_line["RESULT: ".len()..];
-
-    serde_json::from_str(json_str).map_err(|e| {
-        format!(
-            "Failed to parse authentication result: {}. JSON was: {}",
-            e, json_str
-        )
-    })
+    fn with_vault<T>(
+        &self,
+        account_name: &str,
+        f: impl FnOnce(&mut Vault) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut unlocked = self.unlocked.lock().unwrap();
+        let vault = unlocked
+            .get_mut(account_name)
+            .ok_or_else(|| locked(account_name))?;
+        f(vault)
+    }
 }
 
-/// Create a new user account using the Python VaultManager backend
-#[tauri::command]
-pub fn create_user_account(account_name: String, password: String) -> Result<AuthResult, String> {
-    let python_script = format!(
-        r#"
-import sys
-import json
-import os
-# Redirect stdout to stderr
-_orig_stdout = sys.stdout
-sys.stdout = sys.stderr
+fn locked(account_name: &str) -> String {
+    format!("Account '{}' is not signed in", account_name)
+}
 
-sys.path.insert(0, '../../backend/src')
-result = {{'success': False, 'message': 'Unknown error'}}
+/// Vaults live in `<app data>/vaults`, one file per account
+fn vault_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("vaults"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
 
-try:
-    from core.vault_manager import VaultManager
-    import constants as udef
+/// Settings without a signed-in account live in `<app config>/settings.json`
+fn settings_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("settings.json"))
+        .map_err(|e| format!("Failed to resolve app config directory: {}", e))
+}
 
-    udef.update_cryptographic_values('{}')
-    
-    if os.path.exists(udef.KEYSTORE_FILE) or os.path.exists(udef.VAULT_FILE):
-        result = {{'success': False, 'message': 'Account already exists'}}
-    else:
-        vm = VaultManager(udef.JAR_FILE)
-        vm.load_keystore(udef.KEYSTORE_FILE, '{}')
-        vm.create_key_if_missing(udef.KEY_ALIAS, udef.KEYSTORE_FILE, '{}')
-        vm.get_secret_key(udef.KEY_ALIAS, '{}')
-        vm.init_vault(udef.VAULT_FILE)
-        vm.save_account_credentials('{}', '{}')
-        result = {{'success': True}}
-    
-except Exception as e:
-    result = {{'success': False, 'message': str(e)}}
+/// Run `f` on `account_name`'s settings, in its vault, or on the settings
+/// file when no account is given
+fn with_settings<T>(
+    app: &AppHandle,
+    state: &AuthState,
+    account_name: Option<String>,
+    f: impl FnOnce(&mut SettingsStore) -> anyhow::Result<T>,
+) -> Result<T, String> {
+    let result = match account_name {
+        Some(name) => state.with_vault(&name, |vault| Ok(f(&mut SettingsStore::Vault(vault))))?,
+        None => f(&mut SettingsStore::File(settings_path(app)?)),
+    };
+    result.map_err(|e| format!("{:#}", e))
+}
 
-sys.stdout = _orig_stdout
-print(f"RESULT: {{json.dumps(result)}}")
-"#,
-        account_name, password, password, password, account_name, password
-    );
+/// Where the Python `VaultManager` kept its files
+fn legacy_secrets_dir(app: &AppHandle) -> Option<PathBuf> {
+    app.path()
+        .home_dir()
+        .ok()
+        .map(|home| home.join(".image-toolkit").join("secrets"))
+}
 
-    let output = Command::new("python3")
-        .arg("-c")
-        .arg(&python_script)
-        .output()
-        .map_err(|e| format!("Failed to execute Python: {}", e))?;
+/// Repository checkout holding the Python backend, needed only to import an
+/// old vault: `IMAGE_TOOLKIT_ROOT`, else two levels up as in a dev build
+fn backend_root() -> PathBuf {
+    std::env::var_os("IMAGE_TOOLKIT_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("../.."))
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
+fn profile_names(vault: &Vault) -> Vec<String> {
+    vault
+        .data()
+        .get("system_preference_profiles")
+        .and_then(Value::as_object)
+        .map(|profiles| profiles.keys().cloned().collect())
+        .unwrap_or_default()
+}
 
-    if !stderr.is_empty() {
-        log::info!("Python stderr: {}", stderr);
+/// Open the account's vault, importing the old Python one the first time
+fn open_or_import(
+    dir: &Path,
+    secrets_dir: Option<&Path>,
+    account_name: &str,
+    password: &str,
+) -> Result<Vault, VaultError> {
+    match Vault::open(dir, account_name, password) {
+        Err(VaultError::NotFound(name)) => {
+            let files = secrets_dir
+                .map(|secrets| LegacyFiles::locate(secrets, account_name))
+                .filter(LegacyFiles::exist)
+                .ok_or(VaultError::NotFound(name))?;
+            legacy::import(
+                dir,
+                account_name,
+                password,
+                &files,
+                KdfParams::default(),
+                || legacy::decrypt_with_backend(&backend_root(), account_name, password),
+            )
+        }
+        result => result,
     }
-
-    // Find the line starting with RESULT:
-    let result_line = stdout
-        .lines()
-        .find(|line| line.starts_with("RESULT: "))
-        .ok_or_else(|| {
-            log::error!("No RESULT: marker found in output. Raw output: {}", stdout);
-            format!("No account creation result marker found in backend output")
-        })?;
-
-    let json_str = &result_line["RESULT: ".len()..];
-
-    serde_json::from_str(json_str).map_err(|e| {
-        format!(
-            "Failed to parse account creation result: {}. JSON was: {}",
-            e, json_str
-        )
-    })
 }
 
-/// Load user settings from VaultManager
+/// Authenticate a user by unlocking their vault
 #[tauri::command]
-pub fn load_user_settings(account_name: String) -> Result<SettingsData, String> {
-    let output = Command::new("python")
-        .arg("-c")
-        .arg(format!(
-            r#"
-import sys
-import json
-# Redirect stdout to stderr
-_original_stdout = sys.stdout
-sys.stdout = sys.stderr
-
-sys.path.insert(0, '../../backend/src')
-try:
-    from core.vault_manager import VaultManager
-    import constants as udef
-
-    udef.update_cryptographic_values('{}')
-    vm = VaultManager(udef.JAR_FILE)
-    # Load without password (assumes already authenticated in session)
-    vm.init_vault(udef.VAULT_FILE)
-    
-    stored_data = vm.load_account_credentials()
-    
-    settings = {{
-        'theme': stored_data.get('theme', 'dark'),
-        'tab_configurations': stored_data.get('tab_configurations', {{}}),
-        'system_preference_profiles': stored_data.get('system_preference_profiles', {{}}),
-        'active_tab_configs': stored_data.get('active_tab_configs', {{}})
-    }}
-    
-    sys.stdout = _original_stdout
-    print(json.dumps(settings))
-    
-except Exception as e:
-    print(str(e))
-    sys.exit(1)
-"#,
-            account_name
-        ))
-        .output()
-        .map_err(|e| format!("Failed to execute Python: {}", e))?;
-
-    if output.status.success() {
-        let result_str = String::from_utf8_lossy(&output.stdout);
-        let settings: SettingsData = serde_json::from_str(&result_str)
-            .map_err(|e| format!("Failed to parse settings: {}", e))?;
-        Ok(settings)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to load settings: {}", error))
+pub async fn authenticate_user(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_name: String,
+    password: String,
+) -> Result<AuthResult, String> {
+    let dir = vault_dir(&app)?;
+    let secrets_dir = legacy_secrets_dir(&app);
+    let name = account_name.clone();
+    // Key derivation is deliberately slow; keep it off the async workers
+    let opened = tauri::async_runtime::spawn_blocking(move || {
+        open_or_import(&dir, secrets_dir.as_deref(), &name, &password)
+    })
+    .await
+    .map_err(|e| format!("Authentication failed: {}", e))?;
+
+    match opened {
+        Ok(vault) => {
+            let profiles = profile_names(&vault);
+            state.unlock(vault);
+            Ok(AuthResult {
+                success: true,
+                message: None,
+                profiles: Some(profiles),
+            })
+        }
+        Err(e) => {
+            log::warn!("Sign-in for '{}' failed: {}", account_name, e);
+            Ok(AuthResult::failure(e))
+        }
     }
 }
 
-/// Save user settings to VaultManager
+/// Create a new user account with an empty vault and sign it in
 #[tauri::command]
-pub fn save_user_settings(account_name: String, settings: SettingsData) -> Result<bool, String> {
-    let settings_json = serde_json::to_string(&settings)
-        .map_err(|e| format!("Failed to serialize settings: {}", e))?;
-
-    let output = Command::new("python")
-        .arg("-c")
-        .arg(format!(
-            r#"
-import sys
-import json
-# Redirect stdout to stderr
-_original_stdout = sys.stdout
-sys.stdout = sys.stderr
-
-sys.path.insert(0, '../../backend/src')
-try:
-    from core.vault_manager import VaultManager
-    import constants as udef
-
-    udef.update_cryptographic_values('{}')
-    vm = VaultManager(udef.JAR_FILE)
-    vm.init_vault(udef.VAULT_FILE)
-    
-    user_data = vm.load_account_credentials()
-    settings = json.loads('{}')
-    
-    user_data.update(settings)
-    vm.save_data(json.dumps(user_data))
-    
-    sys.stdout = _original_stdout
-    print(json.dumps({{'success': True}}))
-    
-except Exception as e:
-    print(str(e))
-    sys.exit(1)
-"#,
-            account_name,
-            settings_json.replace("'", "\\'")
-        ))
-        .output()
-        .map_err(|e| format!("Failed to execute Python: {}", e))?;
+pub async fn create_user_account(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_name: String,
+    password: String,
+) -> Result<AuthResult, String> {
+    if password.is_empty() {
+        return Ok(AuthResult::failure("Password cannot be empty"));
+    }
+    // An account still in the old format is imported on first sign-in
+    let has_legacy = legacy_secrets_dir(&app)
+        .map(|secrets| LegacyFiles::locate(&secrets, &account_name).exist())
+        .unwrap_or(false);
+    if has_legacy {
+        return Ok(AuthResult::failure(VaultError::AlreadyExists(account_name)));
+    }
 
-    if output.status.success() {
-        Ok(true)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to save settings: {}", error))
+    let dir = vault_dir(&app)?;
+    let created =
+        tauri::async_runtime::spawn_blocking(move || Vault::create(&dir, &account_name, &password))
+            .await
+            .map_err(|e| format!("Account creation failed: {}", e))?;
+
+    match created {
+        Ok(vault) => {
+            state.unlock(vault);
+            Ok(AuthResult {
+                success: true,
+                message: None,
+                profiles: Some(Vec::new()),
+            })
+        }
+        Err(e) => Ok(AuthResult::failure(e)),
     }
 }
 
-/// Update master password
+/// Load user settings from the signed-in account's vault, or the app's
+/// settings file without an account. Older settings are upgraded on the way.
 #[tauri::command]
-pub fn update_master_password(account_name: String, new_password: String) -> Result<bool, String> {
-    let output = Command::new("python")
-        .arg("-c")
-        .arg(format!(
-            r#"
-import sys
-import json
-# Redirect stdout to stderr
-_original_stdout = sys.stdout
-sys.stdout = sys.stderr
+pub fn load_user_settings(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_name: Option<String>,
+) -> Result<SettingsData, String> {
+    with_settings(&app, &state, account_name, |store| store.load())
+        .map_err(|e| format!("Failed to load settings: {}", e))
+}
 
-sys.path.insert(0, '../../backend/src')
-try:
-    from core.vault_manager import VaultManager
-    import constants as udef
+/// Save user settings where `load_user_settings` reads them
+#[tauri::command]
+pub fn save_user_settings(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_name: Option<String>,
+    settings: SettingsData,
+) -> Result<bool, String> {
+    with_settings(&app, &state, account_name, |store| store.save(settings))
+        .map(|_| true)
+        .map_err(|e| format!("Failed to save settings: {}", e))
+}
 
-    udef.update_cryptographic_values('{}')
-    vm = VaultManager(udef.JAR_FILE)
-    vm.update_account_password('{}', '{}')
-    
-    sys.stdout = _original_stdout
-    print(json.dumps({{'success': True}}))
-    
-except Exception as e:
-    print(str(e))
-    sys.exit(1)
-"#,
-            account_name, account_name, new_password
-        ))
-        .output()
-        .map_err(|e| format!("Failed to execute Python: {}", e))?;
+/// Put the settings back to their defaults and return them
+#[tauri::command]
+pub fn reset_settings(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_name: Option<String>,
+) -> Result<SettingsData, String> {
+    with_settings(&app, &state, account_name, |store| store.reset())
+        .map_err(|e| format!("Failed to reset settings: {}", e))
+}
 
-    if output.status.success() {
-        Ok(true)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to update password: {}", error))
+/// Update master password, re-encrypting the signed-in account's vault
+#[tauri::command]
+pub async fn update_master_password(
+    state: State<'_, AuthState>,
+    account_name: String,
+    new_password: String,
+) -> Result<bool, String> {
+    if new_password.is_empty() {
+        return Err("Password cannot be empty".to_string());
     }
+    let mut vault = state
+        .unlocked
+        .lock()
+        .unwrap()
+        .remove(&account_name)
+        .ok_or_else(|| locked(&account_name))?;
+
+    let (vault, result) = tauri::async_runtime::spawn_blocking(move || {
+        let result = vault.change_password(&new_password);
+        (vault, result)
+    })
+    .await
+    .map_err(|e| format!("Failed to update password: {}", e))?;
+
+    state.unlock(vault);
+    result
+        .map(|_| true)
+        .map_err(|e| format!("Failed to update password: {}", e))
 }
@@ -0,0 +1,368 @@
+//! Batch format conversion with an optional aspect-ratio fix, through the
+//! base library's `image_converter`
+
+use crate::tasks::{self, CancellationToken, TaskError};
+use anyhow::{anyhow, Context, Result};
+use base::core::image_converter::{
+    convert_file, image_format, parse_transforms, resize_options, Watermark,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Images converted between progress reports and cancellation checks
+pub const CONVERT_CHUNK: usize = 16;
+
+/// How an image is brought to a target aspect ratio
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AspectMode {
+    /// Cut equal amounts off both sides of the long dimension
+    #[default]
+    Crop,
+    /// Center on a transparent canvas of the target ratio
+    Pad,
+    /// Resize the short dimension until the ratio matches
+    Stretch,
+}
+
+impl AspectMode {
+    /// The frontend's `arMode`; anything unknown crops, as before
+    pub fn parse(mode: &str) -> Self {
+        match mode.to_lowercase().as_str() {
+            "pad" => AspectMode::Pad,
+            "stretch" => AspectMode::Stretch,
+            _ => AspectMode::Crop,
+        }
+    }
+
+    /// The base library's name for the mode
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AspectMode::Crop => "crop",
+            AspectMode::Pad => "pad",
+            AspectMode::Stretch => "stretch",
+        }
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Convert each `(input, output)` pair across the available cores and return
+/// the outputs written, resizing with the `filter_name` filter, rotating and
+/// flipping as `transform` lists (`rotate90`, `flip_h` and so on) and
+/// stamping on `watermark`. EXIF orientation is applied first. With
+/// `delete_original`, each source is removed once its output is written
+/// (unless they are the same file). Failed images are logged and left out.
+#[allow(clippy::too_many_arguments)]
+pub fn convert_batch(
+    pairs: &[(String, String)],
+    format_name: &str,
+    delete_original: bool,
+    aspect: Option<(f32, AspectMode)>,
+    filter_name: Option<&str>,
+    transform: &[String],
+    watermark: Option<&Watermark>,
+    cancel: &CancellationToken,
+    on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<String>, TaskError> {
+    image_format(format_name)?;
+    let resize = resize_options(filter_name)?;
+    let transforms = parse_transforms(transform)?;
+    if let Some((ratio, _)) = aspect {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(anyhow!("Invalid aspect ratio: {}", ratio).into());
+        }
+    }
+    let (ratio, mode) = match aspect {
+        Some((ratio, mode)) => (Some(ratio), mode),
+        None => (None, AspectMode::Crop),
+    };
+
+    let convert = |input: &str, output: &str| -> Result<()> {
+        if let Some(parent) = Path::new(output).parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        convert_file(
+            input,
+            output,
+            format_name,
+            ratio,
+            mode.as_str(),
+            true,
+            &resize,
+            None,
+            None,
+            &transforms,
+            watermark,
+        )?;
+        if delete_original && !same_file(input, output) {
+            std::fs::remove_file(input).with_context(|| format!("Failed to delete {}", input))?;
+        }
+        Ok(())
+    };
+    let converted = tasks::map_in_chunks(pairs, CONVERT_CHUNK, cancel, on_progress, |(i, o)| {
+        convert(i, o)
+            .map(|_| o.clone())
+            .map_err(|e| log::warn!("Conversion of {} failed: {:#}", i, e))
+            .ok()
+    })?;
+    Ok(converted.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    fn create(path: &Path, w: u32, h: u32) -> String {
+        RgbImage::from_pixel(w, h, Rgb([255, 0, 0]))
+            .save(path)
+            .unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn dims(path: &Path) -> (u32, u32) {
+        image::image_dimensions(path).unwrap()
+    }
+
+    #[test]
+    fn test_aspect_modes() {
+        let dir = tempdir().unwrap();
+        let wide = create(&dir.path().join("wide.png"), 200, 100);
+        let square = create(&dir.path().join("square.png"), 100, 100);
+        let cases = [
+            (&wide, 1.0, AspectMode::Crop, (100, 100)),
+            (&wide, 1.0, AspectMode::Pad, (200, 200)),
+            (&square, 2.0, AspectMode::Stretch, (200, 100)),
+            (&square, 0.5, AspectMode::Crop, (50, 100)),
+        ];
+        for (i, (input, ratio, mode, expected)) in cases.into_iter().enumerate() {
+            let out = dir.path().join(format!("out{}.png", i));
+            convert_batch(
+                &[(input.clone(), out.to_string_lossy().to_string())],
+                "png",
+                false,
+                Some((ratio, mode)),
+                None,
+                &[],
+                None,
+                &CancellationToken::new(),
+                |_, _| {},
+            )
+            .unwrap();
+            assert_eq!(dims(&out), expected, "{:?} to {}", mode, ratio);
+        }
+        assert_eq!(AspectMode::parse("PAD"), AspectMode::Pad);
+        assert_eq!(AspectMode::parse("whatever"), AspectMode::Crop);
+        assert_eq!(AspectMode::parse("Stretch").as_str(), "stretch");
+    }
+
+    #[test]
+    fn test_convert_batch() {
+        let dir = tempdir().unwrap();
+        let a = create(&dir.path().join("a.png"), 50, 40);
+        let b = create(&dir.path().join("b.png"), 50, 40);
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, b"not an image").unwrap();
+        let out = |name: &str| {
+            dir.path()
+                .join("out")
+                .join(name)
+                .to_string_lossy()
+                .to_string()
+        };
+        let pairs = vec![
+            (a.clone(), out("a.jpg")),
+            (b.clone(), out("b.jpg")),
+            (broken.to_string_lossy().to_string(), out("broken.jpg")),
+        ];
+
+        let mut reports = Vec::new();
+        let converted = convert_batch(
+            &pairs,
+            "JPEG",
+            true,
+            None,
+            None,
+            &[],
+            None,
+            &CancellationToken::new(),
+            |done, total| reports.push((done, total)),
+        )
+        .unwrap();
+        assert_eq!(converted, [out("a.jpg"), out("b.jpg")]);
+        assert_eq!(reports.last(), Some(&(3, 3)));
+        assert_eq!(dims(Path::new(&out("a.jpg"))), (50, 40));
+        assert!(!Path::new(&a).exists());
+        assert!(broken.exists());
+
+        assert!(convert_batch(
+            &pairs,
+            "psd",
+            false,
+            None,
+            None,
+            &[],
+            None,
+            &CancellationToken::new(),
+            |_, _| {}
+        )
+        .is_err());
+        assert!(convert_batch(
+            &pairs,
+            "png",
+            false,
+            None,
+            Some("bicubic"),
+            &[],
+            None,
+            &CancellationToken::new(),
+            |_, _| {}
+        )
+        .is_err());
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        assert!(matches!(
+            convert_batch(
+                &pairs,
+                "png",
+                false,
+                None,
+                None,
+                &[],
+                None,
+                &cancelled,
+                |_, _| {}
+            ),
+            Err(TaskError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_convert_in_place_keeps_file() {
+        let dir = tempdir().unwrap();
+        let path = create(&dir.path().join("same.png"), 10, 10);
+        let converted = convert_batch(
+            &[(path.clone(), path.clone())],
+            "png",
+            true,
+            None,
+            None,
+            &[],
+            None,
+            &CancellationToken::new(),
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(converted, [path.as_str()]);
+        assert!(Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_stretch_filters() {
+        let dir = tempdir().unwrap();
+        let board = dir.path().join("board.png");
+        RgbImage::from_fn(10, 10, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })
+        .save(&board)
+        .unwrap();
+
+        // Nearest-neighbour keeps the checkerboard black and white; the
+        // default Lanczos3 blends its edges
+        let blended = |filter: Option<&str>| {
+            let out = dir.path().join(format!("board_{:?}.png", filter));
+            let pairs = [(
+                board.to_string_lossy().to_string(),
+                out.to_string_lossy().to_string(),
+            )];
+            let aspect = Some((2.0, AspectMode::Stretch));
+            convert_batch(
+                &pairs,
+                "png",
+                false,
+                aspect,
+                filter,
+                &[],
+                None,
+                &CancellationToken::new(),
+                |_, _| {},
+            )
+            .unwrap();
+            let img = image::open(&out).unwrap().to_rgb8();
+            assert_eq!(img.dimensions(), (20, 10));
+            img.pixels().any(|p| p[0] != 0 && p[0] != 255)
+        };
+        assert!(!blended(Some("Nearest")));
+        assert!(blended(None));
+        assert!(blended(Some("bilinear")));
+    }
+    #[test]
+    fn test_watermark_in_batch() {
+        let dir = tempdir().unwrap();
+        let input = create(&dir.path().join("in.png"), 200, 100);
+        let logo = dir.path().join("logo.png");
+        RgbImage::from_pixel(10, 10, Rgb([0, 0, 255]))
+            .save(&logo)
+            .unwrap();
+        let watermark = Watermark::new(&logo.to_string_lossy(), "center", 1.0, 0.2).unwrap();
+        let out = dir.path().join("out.png").to_string_lossy().to_string();
+
+        let converted = convert_batch(
+            &[(input, out.clone())],
+            "png",
+            false,
+            Some((1.0, AspectMode::Crop)),
+            None,
+            &[],
+            Some(&watermark),
+            &CancellationToken::new(),
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(converted, [out.as_str()]);
+        // Stamped on the cropped square, 20px wide in its middle
+        let img = image::open(&out).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (100, 100));
+        assert_eq!(img.get_pixel(50, 50).0, [0, 0, 255]);
+        assert_eq!(img.get_pixel(35, 50).0, [255, 0, 0]);
+    }
+    #[test]
+    fn test_transforms_before_aspect() {
+        let dir = tempdir().unwrap();
+        let input = create(&dir.path().join("scan.png"), 200, 100);
+        let out = dir.path().join("out.png").to_string_lossy().to_string();
+        let convert = |transform: &[&str]| {
+            let transform: Vec<String> = transform.iter().map(|t| t.to_string()).collect();
+            convert_batch(
+                &[(input.clone(), out.clone())],
+                "png",
+                false,
+                Some((2.0, AspectMode::Crop)),
+                None,
+                &transform,
+                None,
+                &CancellationToken::new(),
+                |_, _| {},
+            )
+        };
+
+        // Turned to 100x200 first, then cropped to 2:1
+        convert(&["rotate90", "flip_v"]).unwrap();
+        assert_eq!(dims(Path::new(&out)), (100, 50));
+        convert(&["rotate180"]).unwrap();
+        assert_eq!(dims(Path::new(&out)), (200, 100));
+        assert!(convert(&["rotate90", "sideways"]).is_err());
+    }
+}
@@ -1,60 +1,153 @@
-use std::collections::HashSet;
-use walkdir::WalkDir;
+use crate::converter::{self, AspectMode};
+use crate::finder::{self, FinderPhase, FinderProgress, ImageGroup};
+use crate::library::{collect_files_matching, DEFAULT_IMAGE_EXTENSIONS};
+use crate::merger::{self, MergeConfig};
+use crate::metadata::{self, ImageMetadata, MetadataResult};
+use crate::tasks::{TaskEvent, TaskEvents, TaskInfo, TaskManager, TaskProgress};
+use crate::thumbnails::{self, DiskThumbnail, ThumbnailFormat};
+use base::core::file_system::ScanFilter;
+use base::core::image_converter::Watermark;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager, State};
 
+impl TaskEvents for AppHandle {
+    fn send(&self, event: TaskEvent) {
+        let _ = self.emit(event.name(), &event);
+    }
+}
+
+/// Files under `directory` with one of `extensions`, sorted. Files outside
+/// the size and modification time (Unix timestamp) bounds are left out, and
+/// the walk stops once `max_results` files are found.
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn scan_files(
     directory: String,
     extensions: Option<Vec<String>>,
     recursive: Option<bool>,
+    min_size_bytes: Option<u64>,
+    max_size_bytes: Option<u64>,
+    modified_after: Option<f64>,
+    modified_before: Option<f64>,
+    max_results: Option<usize>,
 ) -> Result<Vec<String>, String> {
-    let exts: Vec<String> = extensions
-        .unwrap_or_else(|| {
-            vec!["jpg", "jpeg", "png", "webp", "bmp"]
-                .into_iter()
-                .map(String::from)
-                .collect()
-        })
-        .into_iter()
-        .map(|e| e.trim_start_matches('.').to_lowercase())
-        .collect();
-
-    let rec = recursive.unwrap_or(true);
-
-    // Simple file scanner implementation
-    let mut set = HashSet::new();
-    let walker = if rec {
-        WalkDir::new(&directory)
-    } else {
-        WalkDir::new(&directory).max_depth(1)
+    let exts = extensions.unwrap_or_else(|| DEFAULT_IMAGE_EXTENSIONS.map(String::from).to_vec());
+    let filter = ScanFilter {
+        min_size_bytes,
+        max_size_bytes,
+        modified_after,
+        modified_before,
+        max_results,
     };
-
-    for entry in walker.into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
-                let ext_lower = ext.to_lowercase();
-                if exts.contains(&ext_lower) {
-                    set.insert(entry.path().to_string_lossy().to_string());
-                }
-            }
-        }
-    }
-
-    let mut out: Vec<String> = set.into_iter().collect();
-    out.sort();
-    Ok(out)
+    Ok(collect_files_matching(
+        &directory,
+        &exts,
+        recursive.unwrap_or(true),
+        &filter,
+    ))
 }
 
+/// Convert each `(input, output)` pair to `output_format`, optionally
+/// cropping, padding or stretching to `aspect_ratio` (stretching with
+/// `filter`: `nearest`, `bilinear`, `lanczos3` or `catmullrom`); returns the
+/// outputs written. `transform` lists rotations and flips such as
+/// `["rotate90", "flip_h"]`, applied in order before the aspect ratio. Runs
+/// as a `conversion` task that `cancel_task` can stop.
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn convert_image_batch(
-    _pairs: Vec<(String, String)>,
-    _output_format: String,
-    _delete_original: Option<bool>,
-    _aspect_ratio: Option<f32>,
-    _ar_mode: Option<String>,
+    app: AppHandle,
+    manager: State<'_, TaskManager>,
+    pairs: Vec<(String, String)>,
+    output_format: String,
+    delete_original: Option<bool>,
+    aspect_ratio: Option<f32>,
+    ar_mode: Option<String>,
+    filter: Option<String>,
+    transform: Option<Vec<String>>,
+    task_id: Option<String>,
 ) -> Result<Vec<String>, String> {
-    // TODO: Implement image conversion once base library is refactored
-    log::warn!("Image conversion not yet implemented in Tauri backend");
-    Err("Image conversion not yet implemented".to_string())
+    let metadata = json!({ "count": pairs.len(), "outputFormat": output_format });
+    let aspect =
+        aspect_ratio.map(|ratio| (ratio, AspectMode::parse(ar_mode.as_deref().unwrap_or(""))));
+    let task = manager.start_task_for(task_id, "conversion", metadata)?;
+    task.run(app, move |ctx| {
+        converter::convert_batch(
+            &pairs,
+            &output_format,
+            delete_original.unwrap_or(false),
+            aspect,
+            filter.as_deref(),
+            &transform.unwrap_or_default(),
+            None,
+            ctx.token(),
+            |done, total| {
+                ctx.step(
+                    done,
+                    total,
+                    format!("Converted {} of {} images", done, total),
+                )
+            },
+        )
+    })
+    .await
+    .map_err(|e| format!("Failed to convert images: {}", e))
+}
+
+/// `convert_image_batch` with a watermark stamped on in the same pass:
+/// `watermark` is a logo file or, if there's no such file, text, placed at
+/// `position` (`top-left`, `top-right`, `bottom-left`, `bottom-right`,
+/// `center` or `tiled`; default `bottom-right`) with `opacity` (0 to 1,
+/// default 0.5) and `scale` times the image's width (default 0.2). Runs as a
+/// `watermark` task that `cancel_task` can stop.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn apply_watermark_batch(
+    app: AppHandle,
+    manager: State<'_, TaskManager>,
+    pairs: Vec<(String, String)>,
+    watermark: String,
+    position: Option<String>,
+    opacity: Option<f32>,
+    scale: Option<f32>,
+    output_format: String,
+    aspect_ratio: Option<f32>,
+    ar_mode: Option<String>,
+    filter: Option<String>,
+    task_id: Option<String>,
+) -> Result<Vec<String>, String> {
+    let metadata = json!({ "count": pairs.len(), "outputFormat": output_format });
+    let aspect =
+        aspect_ratio.map(|ratio| (ratio, AspectMode::parse(ar_mode.as_deref().unwrap_or(""))));
+    let task = manager.start_task_for(task_id, "watermark", metadata)?;
+    task.run(app, move |ctx| {
+        let watermark = Watermark::new(
+            &watermark,
+            position.as_deref().unwrap_or("bottom-right"),
+            opacity.unwrap_or(0.5),
+            scale.unwrap_or(0.2),
+        )?;
+        converter::convert_batch(
+            &pairs,
+            &output_format,
+            false,
+            aspect,
+            filter.as_deref(),
+            &[],
+            Some(&watermark),
+            ctx.token(),
+            |done, total| {
+                ctx.step(
+                    done,
+                    total,
+                    format!("Watermarked {} of {} images", done, total),
+                )
+            },
+        )
+    })
+    .await
+    .map_err(|e| format!("Failed to watermark images: {}", e))
 }
 
 #[tauri::command]
@@ -75,13 +168,200 @@ pub fn delete_directory(path: String) -> Result<bool, String> {
         .map_err(|e| e.to_string())
 }
 
+/// Merge `image_paths` into one image at `output_path`, laid out as
+/// `config` says. Runs as a `merge` task that `cancel_task` can stop.
 #[tauri::command]
 pub async fn merge_images(
-    _image_paths: Vec<String>,
-    _output_path: String,
-    _config: serde_json::Value,
+    app: AppHandle,
+    manager: State<'_, TaskManager>,
+    image_paths: Vec<String>,
+    output_path: String,
+    config: MergeConfig,
+    task_id: Option<String>,
 ) -> Result<bool, String> {
-    // TODO: Implement image merging once base library is refactored
-    log::warn!("Image merging not yet implemented in Tauri backend");
-    Err("Image merging not yet implemented".to_string())
+    let metadata = json!({ "count": image_paths.len(), "outputPath": output_path });
+    let task = manager.start_task_for(task_id, "merge", metadata)?;
+    task.run(app, move |ctx| {
+        merger::merge_images(
+            &image_paths,
+            &output_path,
+            &config,
+            ctx.token(),
+            |done, total| ctx.step(done, total, format!("Merged {} of {} images", done, total)),
+        )
+    })
+    .await
+    .map(|_| true)
+    .map_err(|e| format!("Failed to merge images: {}", e))
+}
+
+/// Format, dimensions, file info and EXIF highlights of the file at `path`,
+/// which need not be in the database. Non-images get just their file info.
+#[tauri::command]
+pub async fn get_image_metadata(path: String) -> Result<ImageMetadata, String> {
+    tokio::task::spawn_blocking(move || metadata::read_metadata(Path::new(&path)))
+        .await
+        .map_err(|e| format!("Metadata task failed: {}", e))?
+        .map_err(|e| format!("Failed to read metadata: {:#}", e))
+}
+
+/// `get_image_metadata` for several files; each entry carries its metadata
+/// or the reason it couldn't be read
+#[tauri::command]
+pub async fn get_image_metadata_batch(paths: Vec<String>) -> Result<Vec<MetadataResult>, String> {
+    tokio::task::spawn_blocking(move || metadata::read_metadata_batch(&paths))
+        .await
+        .map_err(|e| format!("Metadata task failed: {}", e))
+}
+
+/// Thumbnails written by `generate_thumbnails` live in `<app cache>/thumbnails`
+pub(crate) fn thumbnail_cache_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_cache_dir()
+        .map(|dir| dir.join("thumbnails"))
+        .map_err(|e| format!("Failed to resolve app cache directory: {}", e))
+}
+
+/// Render thumbnails for `paths` (longest edge `size`, default 256) into the
+/// app cache directory and return the cached files, for the webview to load
+/// through the asset protocol. With a `task_id`, emits `task-progress` after
+/// each chunk.
+#[tauri::command]
+pub async fn generate_thumbnails(
+    app: AppHandle,
+    paths: Vec<String>,
+    size: Option<u32>,
+    format: Option<ThumbnailFormat>,
+    task_id: Option<String>,
+) -> Result<Vec<DiskThumbnail>, String> {
+    let cache_dir = thumbnail_cache_dir(&app)?;
+    let report = move |done: usize, total: usize| {
+        if let Some(task_id) = &task_id {
+            let _ = app.emit(
+                "task-progress",
+                TaskProgress {
+                    task_id: task_id.clone(),
+                    progress: (done * 100 / total.max(1)) as u32,
+                    message: format!("Generated {} of {} thumbnails", done, total),
+                    status: "running".to_string(),
+                },
+            );
+        }
+    };
+
+    tokio::task::spawn_blocking(move || {
+        thumbnails::generate_disk_thumbnails(
+            &cache_dir,
+            &paths,
+            size.unwrap_or(256),
+            format.unwrap_or_default(),
+            report,
+        )
+    })
+    .await
+    .map_err(|e| format!("Thumbnail task failed: {}", e))?
+    .map_err(|e| format!("Failed to generate thumbnails: {:#}", e))
+}
+
+/// Delete every thumbnail written by `generate_thumbnails`; returns how many
+/// files were removed
+#[tauri::command]
+pub fn clear_thumbnail_cache(app: AppHandle) -> Result<usize, String> {
+    let cache_dir = thumbnail_cache_dir(&app)?;
+    thumbnails::clear_disk_cache(&cache_dir)
+        .map_err(|e| format!("Failed to clear thumbnail cache: {:#}", e))
+}
+
+/// A finder's progress as a percentage and message, with scanning, hashing
+/// and grouping taking 5%, 90% and 5% of the bar
+fn finder_progress(progress: FinderProgress) -> (u32, String) {
+    let (start, span, message) = match progress.phase {
+        FinderPhase::Scanning => (0, 5, format!("Found {} files", progress.done)),
+        FinderPhase::Hashing => (
+            5,
+            90,
+            format!("Hashed {} of {} files", progress.done, progress.total),
+        ),
+        FinderPhase::Grouping => (95, 5, "Grouping results".to_string()),
+    };
+    let percent = start + span * progress.done / progress.total.max(1);
+    (percent as u32, message)
+}
+
+/// Groups of byte-identical images under `directory`, with each file's size
+/// and dimensions. Reports progress as task `task_id`, which `cancel_task`
+/// can stop.
+#[tauri::command]
+pub async fn find_duplicate_images(
+    app: AppHandle,
+    manager: State<'_, TaskManager>,
+    directory: String,
+    extensions: Option<Vec<String>>,
+    recursive: Option<bool>,
+    task_id: String,
+) -> Result<Vec<ImageGroup>, String> {
+    let exts = extensions.unwrap_or_else(|| DEFAULT_IMAGE_EXTENSIONS.map(String::from).to_vec());
+    let metadata = json!({ "directory": directory });
+    let task = manager.start_task_as(&task_id, "duplicate_scan", metadata)?;
+    task.run(app, move |ctx| {
+        let groups = finder::find_duplicates(
+            &directory,
+            &exts,
+            recursive.unwrap_or(true),
+            ctx.token(),
+            |progress| {
+                let (percent, message) = finder_progress(progress);
+                ctx.progress(percent, message)
+            },
+        )?;
+        Ok(groups)
+    })
+    .await
+    .map_err(|e| format!("Failed to find duplicates: {}", e))
+}
+
+/// Groups of visually similar images under `directory`: average hashes at
+/// most `threshold` bits apart (default 5 of 64). Reports progress as task
+/// `task_id`, which `cancel_task` can stop.
+#[tauri::command]
+pub async fn find_similar_images(
+    app: AppHandle,
+    manager: State<'_, TaskManager>,
+    directory: String,
+    extensions: Option<Vec<String>>,
+    recursive: Option<bool>,
+    threshold: Option<u32>,
+    task_id: String,
+) -> Result<Vec<ImageGroup>, String> {
+    let exts = extensions.unwrap_or_else(|| DEFAULT_IMAGE_EXTENSIONS.map(String::from).to_vec());
+    let metadata = json!({ "directory": directory });
+    let task = manager.start_task_as(&task_id, "similarity_scan", metadata)?;
+    task.run(app, move |ctx| {
+        let groups = finder::find_similar(
+            &directory,
+            &exts,
+            recursive.unwrap_or(true),
+            threshold.unwrap_or(finder::DEFAULT_SIMILARITY_THRESHOLD),
+            ctx.token(),
+            |progress| {
+                let (percent, message) = finder_progress(progress);
+                ctx.progress(percent, message)
+            },
+        )?;
+        Ok(groups)
+    })
+    .await
+    .map_err(|e| format!("Failed to find similar images: {}", e))
+}
+
+/// Ask a running task to stop; `false` if it isn't running
+#[tauri::command]
+pub fn cancel_task(manager: State<'_, TaskManager>, task_id: String) -> bool {
+    manager.cancel(&task_id)
+}
+
+/// Tasks currently running, oldest first
+#[tauri::command]
+pub fn list_active_tasks(manager: State<'_, TaskManager>) -> Vec<TaskInfo> {
+    manager.list_active()
 }
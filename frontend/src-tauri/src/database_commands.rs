@@ -1,17 +1,201 @@
-use crate::db::{Db, DatabaseStats, ImageRecord, SearchQuery};
-use tauri::State;
+use crate::core_commands::thumbnail_cache_dir;
+use crate::db::{
+    BatchAddResult, CaptureMetadata, ChangeLogEntry, Collection, ConflictPolicy, DatabaseStats, Db,
+    DbConfig, DetailedStats, ExportFormat, GroupStats, ImageRecord, ImportReport, NewImage,
+    SavedSearch, SearchQuery, SearchResults, UpdateImage,
+};
+use crate::export::{self, ExportManifest, ExportOptions, ExportSource};
+use crate::library::{
+    self, AuditReport, CrawlImportSummary, GroupMapping, ImportOptions, ImportPhase,
+    ImportProgress, ImportSummary, IndexOptions, IndexProgress, IndexSummary, RelinkReport,
+};
+use crate::tasks::TaskManager;
+use crate::thumbnails::{self, ThumbnailData};
+use crate::videos::{self, VideoImportPhase, VideoImportProgress, VideoImportSummary};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager, State};
 
 /// Search for images in the database
 #[tauri::command]
-pub async fn search_images(
+pub async fn search_images(db: State<'_, Db>, query: SearchQuery) -> Result<SearchResults, String> {
+    db.search_images(query)
+        .await
+        .map_err(|e| format!("Failed to search images: {}", e))
+}
+
+/// Store an embedding vector for an image
+#[tauri::command]
+pub async fn set_image_embedding(
     db: State<'_, Db>,
-    query: SearchQuery,
+    image_id: i32,
+    embedding: Vec<f32>,
+) -> Result<(), String> {
+    db.upsert_embedding(image_id, embedding)
+        .await
+        .map_err(|e| format!("Failed to set embedding: {}", e))
+}
+
+/// Find images with the most similar embeddings
+#[tauri::command]
+pub async fn search_similar_images(
+    db: State<'_, Db>,
+    embedding: Vec<f32>,
+    limit: Option<i64>,
+    filters: Option<SearchQuery>,
 ) -> Result<Vec<ImageRecord>, String> {
+    db.search_similar(embedding, limit.unwrap_or(20), filters)
+        .await
+        .map_err(|e| format!("Failed to search similar images: {}", e))
+}
+
+/// Save the current filters under a name, replacing an existing search with that name
+#[tauri::command]
+pub async fn save_search(
+    db: State<'_, Db>,
+    name: String,
+    query: SearchQuery,
+) -> Result<SavedSearch, String> {
+    db.save_search(&name, &query)
+        .await
+        .map_err(|e| format!("Failed to save search: {}", e))
+}
+
+/// List saved searches
+#[tauri::command]
+pub async fn list_saved_searches(db: State<'_, Db>) -> Result<Vec<SavedSearch>, String> {
+    db.list_saved_searches()
+        .await
+        .map_err(|e| format!("Failed to list saved searches: {}", e))
+}
+
+/// Delete a saved search
+#[tauri::command]
+pub async fn delete_saved_search(db: State<'_, Db>, name: String) -> Result<(), String> {
+    db.delete_saved_search(&name)
+        .await
+        .map_err(|e| format!("Failed to delete saved search: {}", e))
+}
+
+/// Run a saved search; `page` and `limit` override the stored paging
+#[tauri::command]
+pub async fn run_saved_search(
+    db: State<'_, Db>,
+    name: String,
+    page: Option<i64>,
+    limit: Option<i32>,
+) -> Result<SearchResults, String> {
+    let mut query = db
+        .get_saved_search(&name)
+        .await
+        .map_err(|e| format!("Failed to load saved search: {}", e))?
+        .query;
+    if page.is_some() {
+        query.page = page;
+        query.offset = None;
+    }
+    if limit.is_some() {
+        query.limit = limit;
+    }
+
     db.search_images(query)
         .await
         .map_err(|e| format!("Failed to search images: {}", e))
 }
 
+/// Create an empty collection
+#[tauri::command]
+pub async fn create_collection(
+    db: State<'_, Db>,
+    name: String,
+    description: Option<String>,
+) -> Result<Collection, String> {
+    db.create_collection(&name, description.as_deref())
+        .await
+        .map_err(|e| format!("Failed to create collection: {}", e))
+}
+
+/// Rename a collection
+#[tauri::command]
+pub async fn rename_collection(
+    db: State<'_, Db>,
+    old_name: String,
+    new_name: String,
+) -> Result<(), String> {
+    db.rename_collection(&old_name, &new_name)
+        .await
+        .map_err(|e| format!("Failed to rename collection: {}", e))
+}
+
+/// Delete a collection (its images stay in the library)
+#[tauri::command]
+pub async fn delete_collection(db: State<'_, Db>, name: String) -> Result<(), String> {
+    db.delete_collection(&name)
+        .await
+        .map_err(|e| format!("Failed to delete collection: {}", e))
+}
+
+/// List collections with their image counts
+#[tauri::command]
+pub async fn list_collections(db: State<'_, Db>) -> Result<Vec<Collection>, String> {
+    db.list_collections_with_counts()
+        .await
+        .map_err(|e| format!("Failed to list collections: {}", e))
+}
+
+/// Append images to a collection; returns how many were added
+#[tauri::command]
+pub async fn add_to_collection(
+    db: State<'_, Db>,
+    name: String,
+    image_ids: Vec<i32>,
+) -> Result<u64, String> {
+    db.add_to_collection(&name, &image_ids)
+        .await
+        .map_err(|e| format!("Failed to add images to collection: {}", e))
+}
+
+/// Remove images from a collection; returns how many were removed
+#[tauri::command]
+pub async fn remove_from_collection(
+    db: State<'_, Db>,
+    name: String,
+    image_ids: Vec<i32>,
+) -> Result<u64, String> {
+    db.remove_from_collection(&name, &image_ids)
+        .await
+        .map_err(|e| format!("Failed to remove images from collection: {}", e))
+}
+
+/// Move images to the front of a collection in the given order
+#[tauri::command]
+pub async fn reorder_collection(
+    db: State<'_, Db>,
+    name: String,
+    image_ids: Vec<i32>,
+) -> Result<(), String> {
+    db.reorder_collection(&name, &image_ids)
+        .await
+        .map_err(|e| format!("Failed to reorder collection: {}", e))
+}
+
+/// One page of a collection's images in collection order
+#[tauri::command]
+pub async fn get_collection_images(
+    db: State<'_, Db>,
+    name: String,
+    limit: Option<i32>,
+    offset: Option<i64>,
+) -> Result<SearchResults, String> {
+    db.get_collection_images(&name, limit, offset)
+        .await
+        .map_err(|e| format!("Failed to get collection images: {}", e))
+}
+
 /// Get all tags from the database
 #[tauri::command]
 pub async fn get_all_tags(db: State<'_, Db>) -> Result<Vec<String>, String> {
@@ -20,6 +204,66 @@ pub async fn get_all_tags(db: State<'_, Db>) -> Result<Vec<String>, String> {
         .map_err(|e| format!("Failed to get tags: {}", e))
 }
 
+/// Autocomplete tags by case-insensitive prefix
+#[tauri::command]
+pub async fn search_tags(
+    db: State<'_, Db>,
+    prefix: String,
+    limit: Option<i64>,
+) -> Result<Vec<String>, String> {
+    db.search_tags(&prefix, limit.unwrap_or(20))
+        .await
+        .map_err(|e| format!("Failed to search tags: {}", e))
+}
+
+/// Get tags that aren't attached to any image
+#[tauri::command]
+pub async fn get_unused_tags(db: State<'_, Db>) -> Result<Vec<String>, String> {
+    db.get_unused_tags()
+        .await
+        .map_err(|e| format!("Failed to get unused tags: {}", e))
+}
+
+/// Delete all tags that aren't attached to any image
+#[tauri::command]
+pub async fn delete_unused_tags(db: State<'_, Db>) -> Result<u64, String> {
+    db.delete_unused_tags()
+        .await
+        .map_err(|e| format!("Failed to delete unused tags: {}", e))
+}
+
+/// Rename a tag, merging into the new name if it already exists
+#[tauri::command]
+pub async fn rename_tag(
+    db: State<'_, Db>,
+    old_name: String,
+    new_name: String,
+) -> Result<(), String> {
+    db.rename_tag(&old_name, &new_name)
+        .await
+        .map_err(|e| format!("Failed to rename tag: {}", e))
+}
+
+/// Merge several tags into one
+#[tauri::command]
+pub async fn merge_tags(
+    db: State<'_, Db>,
+    sources: Vec<String>,
+    target: String,
+) -> Result<u64, String> {
+    db.merge_tags(&sources, &target)
+        .await
+        .map_err(|e| format!("Failed to merge tags: {}", e))
+}
+
+/// Delete a tag from every image
+#[tauri::command]
+pub async fn delete_tag(db: State<'_, Db>, name: String) -> Result<(), String> {
+    db.delete_tag(&name)
+        .await
+        .map_err(|e| format!("Failed to delete tag: {}", e))
+}
+
 /// Get all groups from the database
 #[tauri::command]
 pub async fn get_all_groups(db: State<'_, Db>) -> Result<Vec<String>, String> {
@@ -39,6 +283,89 @@ pub async fn get_subgroups_for_group(
         .map_err(|e| format!("Failed to get subgroups: {}", e))
 }
 
+/// Image counts per group and subgroup
+#[tauri::command]
+pub async fn get_group_stats(db: State<'_, Db>) -> Result<Vec<GroupStats>, String> {
+    db.get_group_stats()
+        .await
+        .map_err(|e| format!("Failed to get group stats: {}", e))
+}
+
+/// Rename a group, merging into `new_name` if it already exists
+#[tauri::command]
+pub async fn rename_group(
+    db: State<'_, Db>,
+    old_name: String,
+    new_name: String,
+) -> Result<(), String> {
+    db.rename_group(&old_name, &new_name)
+        .await
+        .map_err(|e| format!("Failed to rename group: {}", e))
+}
+
+/// Merge several groups into one, returning how many groups were removed
+#[tauri::command]
+pub async fn merge_groups(
+    db: State<'_, Db>,
+    sources: Vec<String>,
+    target: String,
+) -> Result<u64, String> {
+    db.merge_groups(&sources, &target)
+        .await
+        .map_err(|e| format!("Failed to merge groups: {}", e))
+}
+
+/// Delete a group, optionally moving its images into another one
+#[tauri::command]
+pub async fn delete_group(
+    db: State<'_, Db>,
+    name: String,
+    reassign_to: Option<String>,
+) -> Result<u64, String> {
+    db.delete_group(&name, reassign_to.as_deref())
+        .await
+        .map_err(|e| format!("Failed to delete group: {}", e))
+}
+
+/// Rename a subgroup, merging into `new_name` if it already exists
+#[tauri::command]
+pub async fn rename_subgroup(
+    db: State<'_, Db>,
+    group_name: String,
+    old_name: String,
+    new_name: String,
+) -> Result<(), String> {
+    db.rename_subgroup(&group_name, &old_name, &new_name)
+        .await
+        .map_err(|e| format!("Failed to rename subgroup: {}", e))
+}
+
+/// Merge subgroups of a group into one, returning how many were removed
+#[tauri::command]
+pub async fn merge_subgroups(
+    db: State<'_, Db>,
+    group_name: String,
+    sources: Vec<String>,
+    target: String,
+) -> Result<u64, String> {
+    db.merge_subgroups(&group_name, &sources, &target)
+        .await
+        .map_err(|e| format!("Failed to merge subgroups: {}", e))
+}
+
+/// Delete a subgroup, optionally moving its images into another one
+#[tauri::command]
+pub async fn delete_subgroup(
+    db: State<'_, Db>,
+    group_name: String,
+    name: String,
+    reassign_to: Option<String>,
+) -> Result<u64, String> {
+    db.delete_subgroup(&group_name, &name, reassign_to.as_deref())
+        .await
+        .map_err(|e| format!("Failed to delete subgroup: {}", e))
+}
+
 /// Add a new image to the database
 #[tauri::command]
 pub async fn add_image_to_database(
@@ -64,7 +391,82 @@ pub async fn add_image_to_database(
     .map_err(|e| format!("Failed to add image: {}", e))
 }
 
-/// Delete an image from the database
+/// Update selected fields of an existing image record
+#[tauri::command]
+pub async fn update_image_record(
+    db: State<'_, Db>,
+    image_id: i32,
+    update: UpdateImage,
+) -> Result<ImageRecord, String> {
+    db.update_image(image_id, update)
+        .await
+        .map_err(|e| format!("Failed to update image: {}", e))
+}
+
+/// Add tags to several images in one operation
+#[tauri::command]
+pub async fn add_tags_to_images(
+    db: State<'_, Db>,
+    image_ids: Vec<i32>,
+    tags: Vec<String>,
+) -> Result<u64, String> {
+    db.add_tags_to_images(&image_ids, &tags)
+        .await
+        .map_err(|e| format!("Failed to add tags: {}", e))
+}
+
+/// Remove tags from several images in one operation
+#[tauri::command]
+pub async fn remove_tags_from_images(
+    db: State<'_, Db>,
+    image_ids: Vec<i32>,
+    tags: Vec<String>,
+) -> Result<u64, String> {
+    db.remove_tags_from_images(&image_ids, &tags)
+        .await
+        .map_err(|e| format!("Failed to remove tags: {}", e))
+}
+
+/// EXIF camera settings, capture time and location stored for an image
+#[tauri::command]
+pub async fn get_capture_metadata(
+    db: State<'_, Db>,
+    image_id: i32,
+) -> Result<CaptureMetadata, String> {
+    db.get_capture_metadata(image_id)
+        .await
+        .map_err(|e| format!("Failed to get capture metadata: {}", e))
+}
+
+/// Set or clear (with `null`) an image's star rating
+#[tauri::command]
+pub async fn set_image_rating(
+    db: State<'_, Db>,
+    image_id: i32,
+    rating: Option<i16>,
+) -> Result<(), String> {
+    db.set_rating(image_id, rating)
+        .await
+        .map_err(|e| format!("Failed to set rating: {}", e))
+}
+
+/// Flip an image's favorite flag, returning the new value
+#[tauri::command]
+pub async fn toggle_image_favorite(db: State<'_, Db>, image_id: i32) -> Result<bool, String> {
+    db.toggle_favorite(image_id)
+        .await
+        .map_err(|e| format!("Failed to toggle favorite: {}", e))
+}
+
+/// Count a view of an image, returning the new view count
+#[tauri::command]
+pub async fn record_image_view(db: State<'_, Db>, image_id: i32) -> Result<i32, String> {
+    db.record_view(image_id)
+        .await
+        .map_err(|e| format!("Failed to record view: {}", e))
+}
+
+/// Delete an image from the database; `undo_change` can restore it
 #[tauri::command]
 pub async fn delete_image_from_database(db: State<'_, Db>, image_id: i32) -> Result<(), String> {
     db.delete_image(image_id)
@@ -72,6 +474,25 @@ pub async fn delete_image_from_database(db: State<'_, Db>, image_id: i32) -> Res
         .map_err(|e| format!("Failed to delete image: {}", e))
 }
 
+/// The most recent adds, updates, deletes and tag edits, newest first
+#[tauri::command]
+pub async fn get_recent_changes(
+    db: State<'_, Db>,
+    limit: Option<i64>,
+) -> Result<Vec<ChangeLogEntry>, String> {
+    db.get_recent_changes(limit.unwrap_or(50))
+        .await
+        .map_err(|e| format!("Failed to get recent changes: {}", e))
+}
+
+/// Undo a change from `get_recent_changes`
+#[tauri::command]
+pub async fn undo_change(db: State<'_, Db>, log_id: i64) -> Result<ChangeLogEntry, String> {
+    db.undo_change(log_id)
+        .await
+        .map_err(|e| format!("Failed to undo change: {}", e))
+}
+
 /// Get database statistics
 #[tauri::command]
 pub async fn get_database_stats(db: State<'_, Db>) -> Result<DatabaseStats, String> {
@@ -80,6 +501,18 @@ pub async fn get_database_stats(db: State<'_, Db>) -> Result<DatabaseStats, Stri
         .map_err(|e| format!("Failed to get statistics: {}", e))
 }
 
+/// Per-group, per-extension, per-tag and per-month breakdowns for the
+/// statistics dashboard; `top_tags` defaults to 20
+#[tauri::command]
+pub async fn get_detailed_database_stats(
+    db: State<'_, Db>,
+    top_tags: Option<i64>,
+) -> Result<DetailedStats, String> {
+    db.get_detailed_statistics(top_tags.unwrap_or(20))
+        .await
+        .map_err(|e| format!("Failed to get detailed statistics: {}", e))
+}
+
 /// Test database connection
 #[tauri::command]
 pub async fn test_database_connection(db: State<'_, Db>) -> Result<bool, String> {
@@ -88,33 +521,520 @@ pub async fn test_database_connection(db: State<'_, Db>) -> Result<bool, String>
         .map_err(|e| format!("Database connection failed: {}", e))
 }
 
-/// Batch add images to database
+/// Where and how to connect, managed at startup so the database can be
+/// reconnected later
+pub struct DatabaseSettings {
+    pub url: String,
+    pub config: DbConfig,
+    /// Held while connecting so concurrent attempts don't open two pools
+    connecting: tokio::sync::Mutex<()>,
+}
+
+impl DatabaseSettings {
+    pub fn new(url: String, config: DbConfig) -> Self {
+        DatabaseSettings {
+            url,
+            config,
+            connecting: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+/// Payload of the `database-connection` event
+#[derive(Clone, Serialize)]
+struct ConnectionStatus {
+    connected: bool,
+    backend: Option<&'static str>,
+    error: Option<String>,
+}
+
+fn emit_connection_status(app: &AppHandle, connected: bool, error: Option<String>) {
+    let backend = app.try_state::<Db>().map(|db| db.backend_name());
+    let _ = app.emit(
+        "database-connection",
+        ConnectionStatus {
+            connected,
+            backend,
+            error,
+        },
+    );
+}
+
+/// Connects with the managed `DatabaseSettings` (retrying with backoff),
+/// manages the `Db` and starts the heartbeat that emits
+/// `database-connection` when connectivity is lost or restored. Does nothing
+/// if a `Db` is already managed.
+pub async fn open_database(app: AppHandle) -> Result<(), String> {
+    let settings = app.state::<DatabaseSettings>();
+    let _connecting = settings.connecting.lock().await;
+    if app.try_state::<Db>().is_some() {
+        return Ok(());
+    }
+
+    let db = match Db::connect(&settings.url, &settings.config).await {
+        Ok(db) => db,
+        Err(e) => {
+            let error = format!("Failed to connect to database: {:#}", e);
+            emit_connection_status(&app, false, Some(error.clone()));
+            return Err(error);
+        }
+    };
+    // Ensure the pgvector extension
+    if let Err(e) = db.init_schema().await {
+        log::error!("Failed to initialize database schema: {}", e);
+    }
+    log::info!("Database connected successfully ({})", db.backend_name());
+    app.manage(db);
+    emit_connection_status(&app, true, None);
+
+    let interval = settings.config.heartbeat_interval;
+    let heartbeat_app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let db = heartbeat_app.state::<Db>();
+        db.run_heartbeat(interval, |connected, error| {
+            match &error {
+                Some(e) => log::warn!("Database connection lost: {}", e),
+                None => log::info!("Database connection restored"),
+            }
+            emit_connection_status(&heartbeat_app, connected, error);
+        })
+        .await;
+    });
+
+    Ok(())
+}
+
+/// Reconnect after the database became unreachable, or connect for the
+/// first time if that failed at startup
+#[tauri::command]
+pub async fn database_reconnect(
+    app: AppHandle,
+    settings: State<'_, DatabaseSettings>,
+) -> Result<(), String> {
+    let Some(db) = app.try_state::<Db>() else {
+        return open_database(app.clone()).await;
+    };
+
+    let result = db.reconnect(&settings.config).await;
+    emit_connection_status(
+        &app,
+        result.is_ok(),
+        result.as_ref().err().map(|e| format!("{:#}", e)),
+    );
+    result.map_err(|e| format!("Failed to reconnect to database: {}", e))
+}
+
+/// Batch add images to database, reporting inserted, updated, and failed items
 #[tauri::command]
 pub async fn batch_add_images(
     db: State<'_, Db>,
-    images: Vec<(String, String, Option<String>, Option<String>)>,
-) -> Result<Vec<i32>, String> {
-    let mut ids = Vec::new();
-
-    for (file_path, filename, group_name, subgroup_name) in images {
-        match db
-            .add_image(
-                &file_path,
-                &filename,
-                None,
-                None,
-                group_name.as_deref(),
-                subgroup_name.as_deref(),
-                None,
+    images: Vec<NewImage>,
+) -> Result<BatchAddResult, String> {
+    db.batch_add_images(images)
+        .await
+        .map_err(|e| format!("Failed to batch add images: {}", e))
+}
+
+/// Thumbnails for `ids` (longest edge `size`, default 256) as base64, served
+/// from the database cache and generated on a miss
+#[tauri::command]
+pub async fn get_image_thumbnails(
+    db: State<'_, Db>,
+    ids: Vec<i32>,
+    size: Option<u32>,
+) -> Result<Vec<ThumbnailData>, String> {
+    thumbnails::get_cached_thumbnails(&db, &ids, size.unwrap_or(256))
+        .await
+        .map_err(|e| format!("Failed to get thumbnails: {}", e))
+}
+
+/// Compare the database with the files under `roots`
+#[tauri::command]
+pub async fn audit_library(db: State<'_, Db>, roots: Vec<String>) -> Result<AuditReport, String> {
+    library::audit_library(&db, &roots)
+        .await
+        .map_err(|e| format!("Failed to audit library: {}", e))
+}
+
+/// Delete records (from an audit) whose files are still missing
+#[tauri::command]
+pub async fn remove_missing_records(db: State<'_, Db>, ids: Vec<i32>) -> Result<u64, String> {
+    library::remove_missing_records(&db, &ids)
+        .await
+        .map_err(|e| format!("Failed to remove missing records: {}", e))
+}
+
+/// Add untracked files (from an audit) to the database
+#[tauri::command]
+pub async fn import_untracked(
+    db: State<'_, Db>,
+    paths: Vec<String>,
+    group: Option<String>,
+) -> Result<BatchAddResult, String> {
+    library::import_untracked(&db, paths, group)
+        .await
+        .map_err(|e| format!("Failed to import files: {}", e))
+}
+
+/// An import's progress as a percentage and message, with hashing, copying
+/// and registering taking 30%, 60% and 10% of the bar
+fn import_progress(progress: ImportProgress) -> (u32, String) {
+    let (start, span, message) = match progress.phase {
+        ImportPhase::Hashing => (
+            0,
+            30,
+            format!("Checked {} of {} files", progress.done, progress.total),
+        ),
+        ImportPhase::Copying => (
+            30,
+            60,
+            format!("Copied {} of {} files", progress.done, progress.total),
+        ),
+        ImportPhase::Registering => (90, 10, "Adding files to the library".to_string()),
+    };
+    let percent = start + span * progress.done / progress.total.max(1);
+    (percent as u32, message)
+}
+
+/// Copy dropped files into `dest_dir` and add them to the library in
+/// `group`/`subgroup`, skipping content already there unless `options` says
+/// otherwise. Runs as an `import` task that `cancel_task` can stop.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn import_files(
+    app: AppHandle,
+    db: State<'_, Db>,
+    manager: State<'_, TaskManager>,
+    paths: Vec<String>,
+    dest_dir: String,
+    group: Option<String>,
+    subgroup: Option<String>,
+    options: Option<ImportOptions>,
+    task_id: Option<String>,
+) -> Result<ImportSummary, String> {
+    let metadata = json!({ "count": paths.len(), "destDir": dest_dir });
+    let task = manager.start_task_for(task_id, "import", metadata)?;
+    task.run_async(app, |ctx| async move {
+        let token = ctx.token().clone();
+        library::import_files(
+            &db,
+            paths,
+            &dest_dir,
+            group,
+            subgroup,
+            options.unwrap_or_default(),
+            &token,
+            move |progress| {
+                let (percent, message) = import_progress(progress);
+                ctx.progress(percent, message)
+            },
+        )
+        .await
+    })
+    .await
+    .map_err(|e| format!("Failed to import files: {}", e))
+}
+
+/// Add the downloads of a board crawl in `dir` to the library in
+/// `group`/`subgroup`, tagged from the metadata saved next to each one.
+/// Runs as an `import` task that `cancel_task` can stop while the metadata
+/// is being read.
+#[tauri::command]
+pub async fn import_crawled_directory(
+    app: AppHandle,
+    db: State<'_, Db>,
+    manager: State<'_, TaskManager>,
+    dir: String,
+    group: Option<String>,
+    subgroup: Option<String>,
+    task_id: Option<String>,
+) -> Result<CrawlImportSummary, String> {
+    let metadata = json!({ "dir": dir });
+    let task = manager.start_task_for(task_id, "import", metadata)?;
+    task.run_async(app, |ctx| async move {
+        let token = ctx.token().clone();
+        library::import_crawled_directory(&db, &dir, group, subgroup, &token, move |done, total| {
+            // Adding and tagging take the last tenth
+            let percent = 90 * done / total.max(1);
+            ctx.progress(
+                percent as u32,
+                format!("Read metadata of {} of {} images", done, total),
             )
-            .await
-        {
-            Ok(id) => ids.push(id),
-            Err(e) => {
-                log::error!("Failed to add image {}: {}", file_path, e);
-            }
+        })
+        .await
+    })
+    .await
+    .map_err(|e| format!("Failed to import crawled images: {}", e))
+}
+
+/// Checkpoints of `index_directory` runs live in `<app data>/index`, one
+/// per root
+fn index_checkpoint_path(app: &AppHandle, root: &str) -> Result<PathBuf, String> {
+    let name = hex::encode(&Sha256::digest(root.as_bytes())[..8]);
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("index").join(format!("{}.json", name)))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+/// "Indexed 1200 of 400000 files (1150 added, 40 updated, 10 failed), about
+/// 2h 5m left"
+fn index_message(progress: &IndexProgress) -> String {
+    let mut message = format!(
+        "Indexed {} of {} files ({} added, {} updated, {} failed)",
+        progress.done, progress.total, progress.inserted, progress.updated, progress.failed
+    );
+    if let Some(secs) = progress.eta_secs {
+        let left = match secs {
+            0..=59 => format!("{}s", secs),
+            60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+            _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        };
+        message.push_str(&format!(", about {} left", left));
+    }
+    message
+}
+
+/// Payload of the `index-progress` event
+#[derive(Clone, Serialize)]
+struct IndexProgressEvent {
+    task_id: String,
+    #[serde(flatten)]
+    progress: IndexProgress,
+}
+
+/// Add every image under `root` to the library, walking, probing and
+/// inserting a thousand files at a time, and file them into groups by
+/// folder as `group_mapping_mode` says (`none`, `group` or
+/// `group_and_subgroup`). Progress goes out as `task-progress` and, with the
+/// counts and ETA, as `index-progress`. Runs as an `index` task that
+/// `cancel_task` can stop; what was added by then stays, and indexing the
+/// same root again carries on from there, as it does after a crash.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn index_directory(
+    app: AppHandle,
+    db: State<'_, Db>,
+    manager: State<'_, TaskManager>,
+    root: String,
+    group_mapping_mode: Option<GroupMapping>,
+    extensions: Option<Vec<String>>,
+    task_id: Option<String>,
+) -> Result<IndexSummary, String> {
+    let checkpoint = index_checkpoint_path(&app, &root)?;
+    let mut options = IndexOptions {
+        group_mapping: group_mapping_mode.unwrap_or_default(),
+        ..Default::default()
+    };
+    if let Some(extensions) = extensions {
+        options.extensions = extensions;
+    }
+
+    let metadata = json!({ "root": root });
+    let task = manager.start_task_for(task_id, "index", metadata)?;
+    let events = app.clone();
+    task.run_async(app, |ctx| async move {
+        let token = ctx.token().clone();
+        ctx.progress(0, "Counting files");
+        library::index_directory(&db, &root, options, &checkpoint, &token, move |progress| {
+            let _ = events.emit(
+                "index-progress",
+                IndexProgressEvent {
+                    task_id: ctx.task_id().to_string(),
+                    progress,
+                },
+            );
+            ctx.step(progress.done, progress.total, index_message(&progress))
+        })
+        .await
+    })
+    .await
+    .map_err(|e| format!("Failed to index directory: {}", e))
+}
+
+/// A video import's progress as a percentage and message, with probing,
+/// registering and posters taking 40%, 10% and 50% of the bar
+fn video_import_progress(progress: VideoImportProgress) -> (u32, String) {
+    let (start, span, message) = match progress.phase {
+        VideoImportPhase::Probing => (
+            0,
+            40,
+            format!("Probed {} of {} videos", progress.done, progress.total),
+        ),
+        VideoImportPhase::Registering => (40, 10, "Adding videos to the library".to_string()),
+        VideoImportPhase::Posters => (
+            50,
+            50,
+            format!("Wrote {} of {} posters", progress.done, progress.total),
+        ),
+    };
+    let percent = start + span * progress.done / progress.total.max(1);
+    (percent as u32, message)
+}
+
+/// Add the videos at `paths` to the library in `group`, with their
+/// duration, resolution and codec from ffprobe, and write a poster of each
+/// into the thumbnail cache. Runs as an `import` task that `cancel_task`
+/// can stop while the videos are being probed.
+#[tauri::command]
+pub async fn import_videos(
+    app: AppHandle,
+    db: State<'_, Db>,
+    manager: State<'_, TaskManager>,
+    paths: Vec<String>,
+    group: Option<String>,
+    task_id: Option<String>,
+) -> Result<VideoImportSummary, String> {
+    let cache_dir = thumbnail_cache_dir(&app)?;
+    let metadata = json!({ "count": paths.len() });
+    let task = manager.start_task_for(task_id, "import", metadata)?;
+    task.run_async(app, |ctx| async move {
+        let token = ctx.token().clone();
+        videos::import_videos(&db, paths, group, &cache_dir, &token, move |progress| {
+            let (percent, message) = video_import_progress(progress);
+            ctx.progress(percent, message)
+        })
+        .await
+    })
+    .await
+    .map_err(|e| format!("Failed to import videos: {}", e))
+}
+
+/// Export images, given by database id or by path, into `dest_dir`:
+/// converted, scaled and named as `options` says, and zipped into one
+/// archive with `as_archive`. Runs as an `export` task that `cancel_task`
+/// can stop; the manifest says where each image went or why it didn't.
+#[tauri::command]
+pub async fn export_images(
+    app: AppHandle,
+    db: State<'_, Db>,
+    manager: State<'_, TaskManager>,
+    ids_or_paths: Vec<ExportSource>,
+    dest_dir: String,
+    options: Option<ExportOptions>,
+    task_id: Option<String>,
+) -> Result<ExportManifest, String> {
+    let metadata = json!({ "count": ids_or_paths.len(), "destDir": dest_dir });
+    let task = manager.start_task_for(task_id, "export", metadata)?;
+    task.run_async(app, |ctx| async move {
+        let sources = export::resolve_sources(&db, ids_or_paths).await?;
+        let options = options.unwrap_or_default();
+        tokio::task::spawn_blocking(move || {
+            export::export_images(
+                sources,
+                Path::new(&dest_dir),
+                &options,
+                ctx.token(),
+                |progress| {
+                    ctx.step(
+                        progress.done,
+                        progress.total,
+                        format!("Exported {} of {} images", progress.done, progress.total),
+                    )
+                },
+            )
+        })
+        .await
+        .unwrap_or_else(|e| Err(anyhow::anyhow!("Export task panicked: {}", e).into()))
+    })
+    .await
+    .map_err(|e| format!("Failed to export images: {}", e))
+}
+
+/// Move every record at or under `old_prefix` to `new_prefix`, e.g. after
+/// a folder was moved or a drive letter changed
+#[tauri::command]
+pub async fn relink_images(
+    db: State<'_, Db>,
+    old_prefix: String,
+    new_prefix: String,
+) -> Result<u64, String> {
+    db.relink_images(&old_prefix, &new_prefix)
+        .await
+        .map_err(|e| format!("Failed to relink images: {}", e))
+}
+
+/// Find the files of missing records under `directory` by content hash
+#[tauri::command]
+pub async fn relink_by_content(
+    db: State<'_, Db>,
+    directory: String,
+) -> Result<RelinkReport, String> {
+    library::relink_by_content(&db, &directory)
+        .await
+        .map_err(|e| format!("Failed to relink images: {}", e))
+}
+
+/// Payload of the `library-transfer-progress` event
+#[derive(Clone, Serialize)]
+struct TransferProgress {
+    operation: &'static str,
+    processed: u64,
+}
+
+/// Write the whole library to `path` (chosen with the file dialog). The file
+/// only appears once the export has finished.
+#[tauri::command]
+pub async fn export_library(
+    app: tauri::AppHandle,
+    db: State<'_, Db>,
+    path: String,
+    format: ExportFormat,
+) -> Result<u64, String> {
+    let partial = format!("{}.partial", path);
+    let file =
+        File::create(&partial).map_err(|e| format!("Failed to create {}: {}", partial, e))?;
+
+    let written = db
+        .export_library(file, format, |processed| {
+            let _ = app.emit(
+                "library-transfer-progress",
+                TransferProgress {
+                    operation: "export",
+                    processed,
+                },
+            );
+        })
+        .await;
+
+    match written {
+        Ok(count) => {
+            std::fs::rename(&partial, &path)
+                .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+            Ok(count)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(format!("Failed to export library: {}", e))
         }
     }
+}
 
-    Ok(ids)
+/// Load a file written by `export_library`, matching existing images by path
+#[tauri::command]
+pub async fn import_library(
+    app: tauri::AppHandle,
+    db: State<'_, Db>,
+    path: String,
+    format: ExportFormat,
+    policy: Option<ConflictPolicy>,
+) -> Result<ImportReport, String> {
+    let file = File::open(&path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+
+    db.import_library(
+        BufReader::new(file),
+        format,
+        policy.unwrap_or_default(),
+        |processed| {
+            let _ = app.emit(
+                "library-transfer-progress",
+                TransferProgress {
+                    operation: "import",
+                    processed,
+                },
+            );
+        },
+    )
+    .await
+    .map_err(|e| format!("Failed to import library: {}", e))
 }
@@ -1,48 +1,86 @@
+mod postgres;
+mod sqlite;
+
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::{PgPool, PgPoolOptions};
 use sqlx::FromRow;
-use std::sync::Arc;
+use std::collections::HashMap;
+
+use crate::paths::PathStyle;
+use postgres::PgStore;
+use sqlite::SqliteStore;
+
+/// Default and maximum page size for `search_images`
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Rows per multi-row INSERT (and per transaction) in `batch_add_images`
+const BATCH_CHUNK_SIZE: usize = 500;
+
+/// Largest encoded thumbnail `put_thumbnail` will store
+pub const MAX_THUMBNAIL_BYTES: usize = 512 * 1024;
+
+/// Dimension of the `images.embedding` column
+pub const EMBEDDING_DIM: usize = 128;
+
+/// Database connection state managed by Tauri. PostgreSQL is used when
+/// DATABASE_URL points at a server; otherwise a local SQLite file.
+pub enum Db {
+    Postgres(PgStore),
+    Sqlite(SqliteStore),
+}
 
-/// Database connection state managed by Tauri
-pub struct Db {
-    pool: Arc<PgPool>,
+/// Forwards a call to whichever backend is active
+macro_rules! dispatch {
+    ($self:ident.$method:ident($($arg:expr),*)) => {
+        match $self {
+            Db::Postgres(store) => store.$method($($arg),*).await,
+            Db::Sqlite(store) => store.$method($($arg),*).await,
+        }
+    };
 }
 
+// Declared after `dispatch!` so the modules can use it
+mod audit;
+mod connection;
+mod portable;
+
+pub use audit::{AuditLogRow, ChangeKind, ChangeLogEntry};
+pub use connection::{retry_with_backoff, DbConfig};
+pub use portable::{ConflictPolicy, ExportFormat, ExportedImage, ImportReport};
+
 impl Db {
-    /// Initialize database connection from environment variables
+    /// Connect to `database_url` with settings from the environment
     pub async fn new(database_url: &str) -> Result<Self> {
-        let pool = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(database_url)
-            .await
-            .context("Failed to connect to PostgreSQL database")?;
-
-        // Run migrations if available
-        sqlx::migrate!("./migrations")
-            .run(&pool)
-            .await
-            .context("Failed to run database migrations")?;
+        Self::connect(database_url, &DbConfig::from_env()).await
+    }
 
-        Ok(Self {
-            pool: Arc::new(pool),
-        })
+    /// Connect to `database_url`, choosing the backend from its scheme and
+    /// retrying with backoff while the server is unreachable
+    pub async fn connect(database_url: &str, config: &DbConfig) -> Result<Self> {
+        if database_url.starts_with("sqlite:") {
+            Ok(Db::Sqlite(SqliteStore::new(database_url, config).await?))
+        } else {
+            Ok(Db::Postgres(PgStore::new(database_url, config).await?))
+        }
     }
 
-    /// Initialize database and ensure pgvector extension exists
+    /// Initialize backend-specific schema pieces (the pgvector extension)
     pub async fn init_schema(&self) -> Result<()> {
-        sqlx::query("CREATE EXTENSION IF NOT EXISTS vector")
-            .execute(&*self.pool)
-            .await
-            .context("Failed to create pgvector extension")?;
-
-        Ok(())
+        match self {
+            Db::Postgres(store) => store.init_schema().await,
+            Db::Sqlite(_) => Ok(()),
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn pool(&self) -> &PgPool {
-        &self.pool
+    /// Short backend name for logging
+    pub fn backend_name(&self) -> &'static str {
+        match self {
+            Db::Postgres(_) => "PostgreSQL",
+            Db::Sqlite(_) => "SQLite",
+        }
     }
 }
 
@@ -60,20 +98,254 @@ pub struct ImageRecord {
     pub subgroup_name: Option<String>,
     pub date_added: DateTime<Utc>,
     pub date_modified: Option<DateTime<Utc>>,
+    /// Star rating from 1 to 5; `None` when unrated
+    pub rating: Option<i16>,
+    pub favorite: bool,
+    pub view_count: i32,
+    pub last_viewed: Option<DateTime<Utc>>,
+    /// Where a crawled image was first posted
+    pub source_url: Option<String>,
+    #[sqlx(try_from = "String")]
+    pub media_type: MediaType,
+    /// Length of a video in seconds, from ffprobe
+    pub duration_secs: Option<f64>,
+    /// ffprobe's name for a video's codec, e.g. "h264"
+    pub video_codec: Option<String>,
     #[sqlx(skip)]
     pub tags: Vec<String>,
-    #[sqlx(skip)]
+    /// Cosine distance to the query embedding; only set by `search_similar`
+    #[sqlx(default)]
     pub distance: Option<f32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Filters, sort and paging for `search_images`. Missing fields take their
+/// defaults and unknown ones are ignored, so saved searches keep working as
+/// fields are added.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SearchQuery {
     pub group_name: Option<String>,
     pub subgroup_name: Option<String>,
     pub tags: Option<Vec<String>>,
+    /// Whether images need any (default) or all of `tags`
+    pub tag_mode: Option<TagMode>,
+    /// Images carrying any of these tags are left out
+    pub exclude_tags: Option<Vec<String>>,
     pub filename_pattern: Option<String>,
+    /// Fuzzy filename term matched with trigram word similarity
+    pub filename_fts: Option<String>,
     pub input_formats: Option<Vec<String>>,
+    pub min_width: Option<i32>,
+    pub min_height: Option<i32>,
+    pub min_file_size: Option<i64>,
+    pub max_file_size: Option<i64>,
+    pub date_added_after: Option<DateTime<Utc>>,
+    pub date_added_before: Option<DateTime<Utc>>,
+    /// Only images rated at least this many stars
+    pub min_rating: Option<i16>,
+    pub favorites_only: Option<bool>,
+    /// Case-insensitive substring of the EXIF camera model
+    pub camera_model: Option<String>,
+    pub date_taken_after: Option<DateTime<Utc>>,
+    pub date_taken_before: Option<DateTime<Utc>>,
+    /// Only images in the collection with exactly this name
+    pub collection: Option<String>,
+    /// Only images or only videos
+    pub media_type: Option<MediaType>,
+    /// Only videos at least this many seconds long
+    pub min_duration: Option<f64>,
+    /// Only videos at most this many seconds long
+    pub max_duration: Option<f64>,
+    /// Sort column (default `date_added`)
+    pub sort_by: Option<SortBy>,
+    /// Sort direction (default descending)
+    pub descending: Option<bool>,
+    /// Page size (default 100, capped at 1000)
     pub limit: Option<i32>,
+    /// Number of rows to skip; takes precedence over `page`
+    pub offset: Option<i64>,
+    /// Zero-based page index, multiplied by `limit`
+    pub page: Option<i64>,
+}
+
+/// Whether a record is a still image or a video
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    #[default]
+    Image,
+    Video,
+}
+
+impl MediaType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaType::Image => "image",
+            MediaType::Video => "video",
+        }
+    }
+}
+
+impl TryFrom<String> for MediaType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Ok(match value.as_str() {
+            "image" => MediaType::Image,
+            "video" => MediaType::Video,
+            other => anyhow::bail!("Unknown media type '{}'", other),
+        })
+    }
+}
+
+/// How `SearchQuery::tags` is matched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMode {
+    #[default]
+    Any,
+    All,
+}
+
+/// Columns `search_images` can order by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    #[default]
+    DateAdded,
+    DateModified,
+    Filename,
+    FileSize,
+    #[serde(rename = "width*height", alias = "area")]
+    Area,
+    Rating,
+    ViewCount,
+    LastViewed,
+    DateTaken,
+    Duration,
+}
+
+impl SortBy {
+    fn sql(self) -> &'static str {
+        match self {
+            SortBy::DateAdded => "i.date_added",
+            SortBy::DateModified => "i.date_modified",
+            SortBy::Filename => "i.filename",
+            SortBy::FileSize => "i.file_size",
+            SortBy::Area => "(CAST(i.width AS BIGINT) * CAST(i.height AS BIGINT))",
+            SortBy::Rating => "i.rating",
+            SortBy::ViewCount => "i.view_count",
+            SortBy::LastViewed => "i.last_viewed",
+            SortBy::DateTaken => "i.date_taken",
+            SortBy::Duration => "i.duration_secs",
+        }
+    }
+}
+
+impl SearchQuery {
+    /// ORDER BY clause for the requested sort; `i.id` breaks ties so paging is stable
+    fn order_by_sql(&self) -> String {
+        let column = self.sort_by.unwrap_or_default().sql();
+        let direction = if self.descending.unwrap_or(true) {
+            "DESC NULLS LAST, i.id DESC"
+        } else {
+            "ASC NULLS LAST, i.id ASC"
+        };
+        format!(" ORDER BY {} {}", column, direction)
+    }
+
+    /// Resolves `limit`/`offset`/`page` into a clamped (limit, offset) pair.
+    pub fn page_bounds(&self) -> (i64, i64) {
+        let limit = self
+            .limit
+            .map(i64::from)
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let offset = self
+            .offset
+            .unwrap_or_else(|| self.page.unwrap_or(0).saturating_mul(limit))
+            .max(0);
+        (limit, offset)
+    }
+}
+
+/// One page of search results plus the total number of matches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub images: Vec<ImageRecord>,
+    pub total_count: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Fields to change on an existing image; `None` leaves the column untouched
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateImage {
+    /// New location; `filename` is derived from it so the two stay consistent
+    pub file_path: Option<String>,
+    pub group_name: Option<String>,
+    pub subgroup_name: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+/// One image in a `batch_add_images` call
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NewImage {
+    pub file_path: String,
+    /// Defaults to the last component of `file_path`
+    pub filename: Option<String>,
+    pub file_size: Option<i64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub group_name: Option<String>,
+    pub subgroup_name: Option<String>,
+    /// Read from the file's EXIF during import when not supplied
+    pub capture: Option<CaptureMetadata>,
+    /// SHA-256 of the file, computed during import when not supplied
+    pub content_hash: Option<String>,
+    /// Where a crawled image was first posted; an existing value is kept
+    /// when this is `None`
+    pub source_url: Option<String>,
+    #[serde(default)]
+    pub media_type: MediaType,
+    pub duration_secs: Option<f64>,
+    pub video_codec: Option<String>,
+}
+
+/// Camera settings, capture time and location from EXIF. Images without
+/// EXIF have every field `None`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct CaptureMetadata {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    /// Millimetres
+    pub focal_length: Option<f64>,
+    pub iso: Option<i32>,
+    /// f-number
+    pub aperture: Option<f64>,
+    /// Exposure time, e.g. "1/250"
+    pub shutter: Option<String>,
+    pub date_taken: Option<DateTime<Utc>>,
+    pub gps_lat: Option<f64>,
+    pub gps_lon: Option<f64>,
+}
+
+/// Outcome of `batch_add_images`: new rows, rows updated via the conflict
+/// path, and the items that failed (by index into the input)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchAddResult {
+    pub inserted: Vec<i32>,
+    pub updated: Vec<i32>,
+    pub errors: Vec<BatchItemError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchItemError {
+    pub index: usize,
+    pub file_path: String,
+    pub error: String,
 }
 
 #[allow(dead_code)]
@@ -92,6 +364,116 @@ pub struct Group {
     pub name: String,
 }
 
+/// A named, stored `SearchQuery`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub id: i32,
+    pub name: String,
+    pub query: SearchQuery,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// `saved_searches` row with the query still serialized
+#[derive(FromRow)]
+pub struct SavedSearchRow {
+    id: i32,
+    name: String,
+    query: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<SavedSearchRow> for SavedSearch {
+    type Error = anyhow::Error;
+
+    fn try_from(row: SavedSearchRow) -> Result<Self> {
+        let query = serde_json::from_str(&row.query)
+            .with_context(|| format!("Saved search '{}' is not a valid query", row.name))?;
+        Ok(SavedSearch {
+            id: row.id,
+            name: row.name,
+            query,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+/// A hand-curated, ordered album; an image can be in any number of them
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Collection {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub image_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Image counts for a group and each of its subgroups
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupStats {
+    pub name: String,
+    pub image_count: i64,
+    pub subgroups: Vec<SubgroupStats>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubgroupStats {
+    pub name: String,
+    pub image_count: i64,
+}
+
+/// The columns of an image row that can be checked against the file on disk
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PathRecord {
+    pub id: i32,
+    pub file_path: String,
+    pub file_size: Option<i64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// SHA-256 of the file as imported, when known
+    pub content_hash: Option<String>,
+}
+
+/// New location for an image row, with the columns derived from the path
+#[derive(Debug, Clone, Serialize)]
+pub struct PathMove {
+    pub id: i32,
+    pub file_path: String,
+    pub filename: String,
+    pub path_key: String,
+    pub filename_key: String,
+}
+
+impl PathMove {
+    /// `None` when `file_path` has no file name
+    pub fn new(id: i32, file_path: String) -> Option<Self> {
+        let filename = std::path::Path::new(&file_path)
+            .file_name()?
+            .to_str()?
+            .to_string();
+        Some(PathMove {
+            id,
+            path_key: crate::paths::path_key(&file_path),
+            filename_key: crate::paths::filename_key(&filename),
+            file_path,
+            filename,
+        })
+    }
+}
+
+/// A cached thumbnail; `size` is the longest edge in pixels
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Thumbnail {
+    pub image_id: i32,
+    pub size: i32,
+    pub format: String,
+    pub bytes: Vec<u8>,
+    pub generated_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseStats {
     pub total_images: i64,
@@ -100,160 +482,220 @@ pub struct DatabaseStats {
     pub total_subgroups: i64,
 }
 
-// ===== Database Operations =====
+/// Breakdowns behind the statistics dashboard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetailedStats {
+    pub totals: DatabaseStats,
+    /// Sum of the known file sizes, in bytes
+    pub total_file_size: i64,
+    pub untagged_images: i64,
+    pub ungrouped_images: i64,
+    pub groups: Vec<GroupStats>,
+    /// Largest count first
+    pub extensions: Vec<ExtensionStats>,
+    /// Most used first
+    pub top_tags: Vec<TagUsage>,
+    /// The last twelve months, oldest first, including months with no images
+    pub added_per_month: Vec<MonthlyCount>,
+}
 
-impl Db {
-    /// Search for images based on various filters
-    pub async fn search_images(&self, query: SearchQuery) -> Result<Vec<ImageRecord>> {
-        let limit = query.limit.unwrap_or(100).min(1000); // Cap at 1000
+/// Images sharing a lower-cased file extension; `""` for names without one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ExtensionStats {
+    pub extension: String,
+    pub image_count: i64,
+    pub total_file_size: i64,
+}
 
-        let mut sql = String::from("SELECT DISTINCT i.* FROM images i");
-        let mut conditions = Vec::new();
-        let mut param_count = 0;
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct TagUsage {
+    pub name: String,
+    pub image_count: i64,
+}
 
-        // Join with tags if needed
-        if query.tags.is_some() {
-            sql.push_str(
-                " JOIN image_tags it ON i.id = it.image_id JOIN tags t ON it.tag_id = t.id",
-            );
-        }
+/// Images added in a calendar month (UTC), formatted `YYYY-MM`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonthlyCount {
+    pub month: String,
+    pub image_count: i64,
+}
 
-        // Build WHERE clauses
-        if let Some(_group) = &query.group_name {
-            param_count += 1;
-            conditions.push(format!("i.group_name ILIKE ${}", param_count));
-        }
+/// Untagged and ungrouped image counts and the total file size
+#[derive(Debug, Clone, Copy, Default, FromRow)]
+pub struct LibraryTotals {
+    pub total_file_size: i64,
+    pub untagged_images: i64,
+    pub ungrouped_images: i64,
+}
 
-        if let Some(_subgroup) = &query.subgroup_name {
-            param_count += 1;
-            conditions.push(format!("i.subgroup_name ILIKE ${}", param_count));
-        }
+/// The first days of the `count` months ending with the month of `now`,
+/// oldest first
+fn recent_months(now: DateTime<Utc>, count: u32) -> Vec<chrono::NaiveDate> {
+    use chrono::Datelike;
+    let current = now.year() * 12 + now.month0() as i32;
+    (current + 1 - count as i32..=current)
+        .filter_map(|m| {
+            chrono::NaiveDate::from_ymd_opt(m.div_euclid(12), m.rem_euclid(12) as u32 + 1, 1)
+        })
+        .collect()
+}
 
-        if let Some(_pattern) = &query.filename_pattern {
-            param_count += 1;
-            conditions.push(format!("i.filename ILIKE ${}", param_count));
-        }
+// ===== Database Operations =====
 
-        if let Some(tags) = &query.tags {
-            if !tags.is_empty() {
-                let placeholders: Vec<String> = (0..tags.len())
-                    .map(|_i| {
-                        param_count += 1;
-                        format!("${}", param_count)
-                    })
-                    .collect();
-                conditions.push(format!("t.name IN ({})", placeholders.join(", ")));
-            }
-        }
+impl Db {
+    /// Search for images based on various filters
+    pub async fn search_images(&self, query: SearchQuery) -> Result<SearchResults> {
+        dispatch!(self.search_images(query))
+    }
 
-        if let Some(formats) = &query.input_formats {
-            if !formats.is_empty() {
-                let ext_conditions: Vec<String> = formats
-                    .iter()
-                    .map(|_| {
-                        param_count += 1;
-                        format!("i.filename ILIKE ${}", param_count)
-                    })
-                    .collect();
-                conditions.push(format!("({})", ext_conditions.join(" OR ")));
-            }
-        }
+    /// Store (or replace) the embedding for an image
+    pub async fn upsert_embedding(&self, image_id: i32, embedding: Vec<f32>) -> Result<()> {
+        dispatch!(self.upsert_embedding(image_id, embedding))
+    }
 
-        if !conditions.is_empty() {
-            sql.push_str(" WHERE ");
-            sql.push_str(&conditions.join(" AND "));
-        }
+    /// Find the images closest to `embedding` by cosine distance, optionally
+    /// narrowed by the usual search filters (their paging fields are ignored)
+    pub async fn search_similar(
+        &self,
+        embedding: Vec<f32>,
+        limit: i64,
+        filters: Option<SearchQuery>,
+    ) -> Result<Vec<ImageRecord>> {
+        dispatch!(self.search_similar(embedding, limit, filters))
+    }
 
-        sql.push_str(" ORDER BY i.date_added DESC");
-        sql.push_str(&format!(" LIMIT {}", limit));
+    /// Get tags for several images at once, keyed by image ID
+    pub async fn get_tags_for_images(
+        &self,
+        image_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<String>>> {
+        dispatch!(self.get_tags_for_images(image_ids))
+    }
 
-        // Build query with parameters
-        let mut query_builder = sqlx::query_as::<_, ImageRecord>(&sql);
+    /// File path of an image already stored with each of `hashes`, keyed by
+    /// hash; hashes not in the library are absent
+    pub async fn paths_by_content_hash(
+        &self,
+        hashes: &[String],
+    ) -> Result<HashMap<String, String>> {
+        dispatch!(self.paths_by_content_hash(hashes))
+    }
 
-        if let Some(group) = &query.group_name {
-            query_builder = query_builder.bind(format!("%{}%", group));
-        }
-        if let Some(subgroup) = &query.subgroup_name {
-            query_builder = query_builder.bind(format!("%{}%", subgroup));
-        }
-        if let Some(pattern) = &query.filename_pattern {
-            query_builder = query_builder.bind(format!("%{}%", pattern));
-        }
-        if let Some(tags) = &query.tags {
-            for tag in tags {
-                query_builder = query_builder.bind(tag);
-            }
-        }
-        if let Some(formats) = &query.input_formats {
-            for format in formats {
-                let clean_ext = format.trim_start_matches('.');
-                query_builder = query_builder.bind(format!("%.{}", clean_ext));
-            }
-        }
+    /// Get all tags for a specific image
+    pub async fn get_image_tags(&self, image_id: i32) -> Result<Vec<String>> {
+        dispatch!(self.get_image_tags(image_id))
+    }
 
-        let mut images = query_builder.fetch_all(&*self.pool).await?;
+    /// Get all unique tags from the database
+    pub async fn get_all_tags(&self) -> Result<Vec<String>> {
+        dispatch!(self.get_all_tags())
+    }
 
-        // Fetch tags for each image
-        for image in &mut images {
-            image.tags = self.get_image_tags(image.id).await?;
-        }
+    /// Case-insensitive prefix search over tag names, for autocomplete
+    pub async fn search_tags(&self, prefix: &str, limit: i64) -> Result<Vec<String>> {
+        dispatch!(self.search_tags(prefix, limit))
+    }
 
-        Ok(images)
+    /// Get tags that aren't attached to any image
+    pub async fn get_unused_tags(&self) -> Result<Vec<String>> {
+        dispatch!(self.get_unused_tags())
     }
 
-    /// Get all tags for a specific image
-    pub async fn get_image_tags(&self, image_id: i32) -> Result<Vec<String>> {
-        let tags = sqlx::query_scalar::<_, String>(
-            r#"
-            SELECT t.name FROM tags t
-            JOIN image_tags it ON t.id = it.tag_id
-            WHERE it.image_id = $1
-            ORDER BY t.name
-            "#,
-        )
-        .bind(image_id)
-        .fetch_all(&*self.pool)
-        .await?;
+    /// Delete every tag that isn't attached to any image; returns how many were removed
+    pub async fn delete_unused_tags(&self) -> Result<u64> {
+        dispatch!(self.delete_unused_tags())
+    }
 
-        Ok(tags)
+    /// Rename a tag. If `new_name` already exists the two are merged.
+    pub async fn rename_tag(&self, old_name: &str, new_name: &str) -> Result<()> {
+        dispatch!(self.rename_tag(old_name, new_name))
     }
 
-    /// Get all unique tags from the database
-    pub async fn get_all_tags(&self) -> Result<Vec<String>> {
-        let tags = sqlx::query_scalar::<_, String>("SELECT name FROM tags ORDER BY name")
-            .fetch_all(&*self.pool)
-            .await?;
+    /// Merge `sources` into `target` (created if missing); returns how many source tags were removed
+    pub async fn merge_tags(&self, sources: &[String], target: &str) -> Result<u64> {
+        dispatch!(self.merge_tags(sources, target))
+    }
 
-        Ok(tags)
+    /// Delete a tag and detach it from every image
+    pub async fn delete_tag(&self, name: &str) -> Result<()> {
+        dispatch!(self.delete_tag(name))
     }
 
     /// Get all groups
     pub async fn get_all_groups(&self) -> Result<Vec<String>> {
-        let groups = sqlx::query_scalar::<_, String>("SELECT name FROM groups ORDER BY name")
-            .fetch_all(&*self.pool)
-            .await?;
-
-        Ok(groups)
+        dispatch!(self.get_all_groups())
     }
 
     /// Get all subgroups for a specific group
     pub async fn get_subgroups_for_group(&self, group_name: &str) -> Result<Vec<String>> {
-        let subgroups = sqlx::query_scalar::<_, String>(
-            r#"
-            SELECT s.name FROM subgroups s
-            JOIN groups g ON s.group_id = g.id
-            WHERE g.name = $1
-            ORDER BY s.name
-            "#,
-        )
-        .bind(group_name)
-        .fetch_all(&*self.pool)
-        .await?;
+        dispatch!(self.get_subgroups_for_group(group_name))
+    }
+
+    /// Rename a group and the images in it. If `new_name` already exists the two are merged.
+    pub async fn rename_group(&self, old_name: &str, new_name: &str) -> Result<()> {
+        dispatch!(self.rename_group(old_name, new_name))
+    }
+
+    /// Merge `sources` into `target` (created if missing); returns how many source groups were removed
+    pub async fn merge_groups(&self, sources: &[String], target: &str) -> Result<u64> {
+        dispatch!(self.merge_groups(sources, target))
+    }
+
+    /// Delete a group, moving its images into `reassign_to` or leaving them
+    /// ungrouped. Returns how many images were affected.
+    pub async fn delete_group(&self, name: &str, reassign_to: Option<&str>) -> Result<u64> {
+        dispatch!(self.delete_group(name, reassign_to))
+    }
+
+    /// Rename a subgroup within `group`. If `new_name` already exists the two are merged.
+    pub async fn rename_subgroup(&self, group: &str, old_name: &str, new_name: &str) -> Result<()> {
+        dispatch!(self.rename_subgroup(group, old_name, new_name))
+    }
+
+    /// Merge subgroups of `group` into `target`; returns how many source subgroups were removed
+    pub async fn merge_subgroups(
+        &self,
+        group: &str,
+        sources: &[String],
+        target: &str,
+    ) -> Result<u64> {
+        dispatch!(self.merge_subgroups(group, sources, target))
+    }
+
+    /// Delete a subgroup, moving its images into `reassign_to` or clearing
+    /// their subgroup. Returns how many images were affected.
+    pub async fn delete_subgroup(
+        &self,
+        group: &str,
+        name: &str,
+        reassign_to: Option<&str>,
+    ) -> Result<u64> {
+        dispatch!(self.delete_subgroup(group, name, reassign_to))
+    }
 
-        Ok(subgroups)
+    /// Image counts for every group and subgroup, empty ones included
+    pub async fn get_group_stats(&self) -> Result<Vec<GroupStats>> {
+        let groups = dispatch!(self.group_counts())?;
+        let mut subgroups: HashMap<String, Vec<SubgroupStats>> = HashMap::new();
+        for (group, name, image_count) in dispatch!(self.subgroup_counts())? {
+            subgroups
+                .entry(group)
+                .or_default()
+                .push(SubgroupStats { name, image_count });
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|(name, image_count)| GroupStats {
+                subgroups: subgroups.remove(&name).unwrap_or_default(),
+                name,
+                image_count,
+            })
+            .collect())
     }
 
     /// Add a new image to the database
+    #[allow(clippy::too_many_arguments)]
     pub async fn add_image(
         &self,
         file_path: &str,
@@ -264,161 +706,1872 @@ impl Db {
         subgroup_name: Option<&str>,
         tags: Option<Vec<String>>,
     ) -> Result<i32> {
-        // Ensure group exists if provided
-        if let Some(group) = group_name {
-            self.ensure_group_exists(group).await?;
-        }
-
-        // Ensure subgroup exists if provided
-        if let (Some(group), Some(subgroup)) = (group_name, subgroup_name) {
-            self.ensure_subgroup_exists(subgroup, group).await?;
-        }
+        let file_path = &crate::paths::normalize_path(file_path);
+        dispatch!(self.add_image(
+            file_path,
+            filename,
+            width,
+            height,
+            group_name,
+            subgroup_name,
+            tags
+        ))
+    }
 
-        let now = Utc::now();
+    /// Add many images in chunked, transactional multi-row inserts. A failing
+    /// chunk is retried row by row so one bad item doesn't sink the rest.
+    pub async fn batch_add_images(&self, images: Vec<NewImage>) -> Result<BatchAddResult> {
+        let mut result = BatchAddResult::default();
 
-        let image_id = sqlx::query_scalar::<_, i32>(
-            r#"
-            INSERT INTO images
-            (file_path, filename, file_size, width, height, group_name, subgroup_name, date_added, date_modified)
-            VALUES ($1, $2, 0, $3, $4, $5, $6, $7, $7)
-            ON CONFLICT (file_path) DO UPDATE SET
-                width = EXCLUDED.width,
-                height = EXCLUDED.height,
-                group_name = EXCLUDED.group_name,
-                subgroup_name = EXCLUDED.subgroup_name,
-                date_modified = $7
-            RETURNING id
-            "#,
-        )
-        .bind(file_path)
-        .bind(filename)
-        .bind(width)
-        .bind(height)
-        .bind(group_name)
-        .bind(subgroup_name)
-        .bind(now)
-        .fetch_one(&*self.pool)
+        // Path canonicalization, EXIF parsing and hashing are blocking file I/O
+        let mut pending;
+        (pending, result.errors) = tokio::task::spawn_blocking(move || {
+            let mut errors = Vec::new();
+            let mut pending = validate_new_images(images, &mut errors);
+            for (_, image) in pending.iter_mut() {
+                let path = std::path::Path::new(&image.file_path);
+                // Videos carry no EXIF
+                if image.capture.is_none() && image.media_type == MediaType::Image {
+                    image.capture = Some(crate::exif_reader::read_capture_metadata(path));
+                }
+                if image.content_hash.is_none() {
+                    image.content_hash = crate::library::compute_sha256(path);
+                }
+            }
+            (pending, errors)
+        })
         .await?;
 
-        // Add tags if provided
-        if let Some(tag_list) = tags {
-            self.set_image_tags(image_id, tag_list).await?;
+        // Groups and subgroups are created once per distinct value up front
+        let mut groups: Vec<String> = pending
+            .iter()
+            .filter_map(|(_, image)| image.group_name.clone())
+            .collect();
+        groups.sort();
+        groups.dedup();
+        let mut subgroups: Vec<(String, String)> = pending
+            .iter()
+            .filter_map(|(_, image)| {
+                Some((image.group_name.clone()?, image.subgroup_name.clone()?))
+            })
+            .collect();
+        subgroups.sort();
+        subgroups.dedup();
+
+        if dispatch!(self.ensure_groups(&groups)).is_err() {
+            for group in &groups {
+                if let Err(e) = dispatch!(self.ensure_groups(std::slice::from_ref(group))) {
+                    fail_matching(&mut pending, &mut result.errors, &e, |image| {
+                        image.group_name.as_ref() == Some(group)
+                    });
+                }
+            }
+        }
+        if dispatch!(self.ensure_subgroups(&subgroups)).is_err() {
+            for pair in &subgroups {
+                if let Err(e) = dispatch!(self.ensure_subgroups(std::slice::from_ref(pair))) {
+                    fail_matching(&mut pending, &mut result.errors, &e, |image| {
+                        image.group_name.as_ref() == Some(&pair.0)
+                            && image.subgroup_name.as_ref() == Some(&pair.1)
+                    });
+                }
+            }
+        }
+
+        for chunk in pending.chunks(BATCH_CHUNK_SIZE) {
+            let images: Vec<NewImage> = chunk.iter().map(|(_, image)| image.clone()).collect();
+            match dispatch!(self.insert_images_chunk(&images)) {
+                Ok(rows) => {
+                    for (id, inserted) in rows {
+                        if inserted {
+                            result.inserted.push(id);
+                        } else {
+                            result.updated.push(id);
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Batch chunk failed ({}), retrying row by row", e);
+                    for (index, image) in chunk {
+                        match dispatch!(self.insert_images_chunk(std::slice::from_ref(image))) {
+                            Ok(rows) => {
+                                for (id, inserted) in rows {
+                                    if inserted {
+                                        result.inserted.push(id);
+                                    } else {
+                                        result.updated.push(id);
+                                    }
+                                }
+                            }
+                            Err(e) => result.errors.push(BatchItemError {
+                                index: *index,
+                                file_path: image.file_path.clone(),
+                                error: e.to_string(),
+                            }),
+                        }
+                    }
+                }
+            }
         }
 
-        Ok(image_id)
+        result.errors.sort_by_key(|e| e.index);
+        Ok(result)
     }
 
     /// Set tags for an image (replaces existing tags)
     pub async fn set_image_tags(&self, image_id: i32, tags: Vec<String>) -> Result<()> {
-        // Delete existing tags
-        sqlx::query("DELETE FROM image_tags WHERE image_id = $1")
-            .bind(image_id)
-            .execute(&*self.pool)
-            .await?;
-
-        // Add new tags
-        for tag_name in tags {
-            let tag_id = self.get_or_create_tag(&tag_name).await?;
-            sqlx::query(
-                "INSERT INTO image_tags (image_id, tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-            )
-            .bind(image_id)
-            .bind(tag_id)
-            .execute(&*self.pool)
-            .await?;
-        }
+        dispatch!(self.set_image_tags(image_id, tags))
+    }
 
-        Ok(())
+    /// Add tags to many images at once; returns the number of new image/tag links
+    pub async fn add_tags_to_images(&self, image_ids: &[i32], tags: &[String]) -> Result<u64> {
+        dispatch!(self.add_tags_to_images(image_ids, tags))
     }
 
-    /// Get or create a tag
-    async fn get_or_create_tag(&self, name: &str) -> Result<i32> {
-        let tag_id = sqlx::query_scalar::<_, i32>(
-            r#"
-            INSERT INTO tags (name) VALUES ($1)
-            ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
-            RETURNING id
-            "#,
-        )
-        .bind(name)
-        .fetch_one(&*self.pool)
-        .await?;
+    /// Remove tags from many images at once; returns the number of links removed
+    pub async fn remove_tags_from_images(&self, image_ids: &[i32], tags: &[String]) -> Result<u64> {
+        dispatch!(self.remove_tags_from_images(image_ids, tags))
+    }
 
-        Ok(tag_id)
+    /// Apply a partial update to an image and return the updated record
+    pub async fn update_image(
+        &self,
+        image_id: i32,
+        mut update: UpdateImage,
+    ) -> Result<ImageRecord> {
+        update.file_path = update
+            .file_path
+            .map(|path| crate::paths::normalize_path(&path));
+        dispatch!(self.update_image(image_id, update))
     }
 
-    /// Ensure a group exists
-    async fn ensure_group_exists(&self, name: &str) -> Result<()> {
-        sqlx::query("INSERT INTO groups (name) VALUES ($1) ON CONFLICT (name) DO NOTHING")
-            .bind(name)
-            .execute(&*self.pool)
-            .await?;
+    /// Set an image's star rating (1-5), or clear it with `None`
+    pub async fn set_rating(&self, image_id: i32, rating: Option<i16>) -> Result<()> {
+        if let Some(rating) = rating.filter(|r| !(1..=5).contains(r)) {
+            anyhow::bail!("Rating must be between 1 and 5, got {}", rating);
+        }
+        dispatch!(self.set_rating(image_id, rating))
+    }
 
-        Ok(())
+    /// Flip an image's favorite flag, returning the new value
+    pub async fn toggle_favorite(&self, image_id: i32) -> Result<bool> {
+        dispatch!(self.toggle_favorite(image_id))
     }
 
-    /// Ensure a subgroup exists for a given group
-    async fn ensure_subgroup_exists(&self, subgroup_name: &str, group_name: &str) -> Result<()> {
-        // Get group ID
-        let group_id = sqlx::query_scalar::<_, i32>("SELECT id FROM groups WHERE name = $1")
-            .bind(group_name)
-            .fetch_one(&*self.pool)
-            .await?;
+    /// Count a view and stamp `last_viewed`, returning the new view count
+    pub async fn record_view(&self, image_id: i32) -> Result<i32> {
+        dispatch!(self.record_view(image_id))
+    }
 
-        sqlx::query(
-            "INSERT INTO subgroups (name, group_id) VALUES ($1, $2) ON CONFLICT (name, group_id) DO NOTHING",
-        )
-        .bind(subgroup_name)
-        .bind(group_id)
-        .execute(&*self.pool)
-        .await?;
+    /// Rewrite the paths of every image at or under `old_prefix` to sit under
+    /// `new_prefix` instead, in one transaction. A prefix naming a single file
+    /// renames it. Returns the number of images moved.
+    pub async fn relink_images(&self, old_prefix: &str, new_prefix: &str) -> Result<u64> {
+        let old_prefix = crate::paths::lexical_normalize(old_prefix, PathStyle::native());
+        let new_prefix = crate::paths::normalize_path(new_prefix);
+        if old_prefix == "." || new_prefix == "." {
+            anyhow::bail!("Relink prefixes cannot be empty");
+        }
+        dispatch!(self.relink_prefix(&old_prefix, &new_prefix))
+    }
 
-        Ok(())
+    /// Point each image in `moves` (id, new path) at its new path, in one
+    /// transaction. Returns the number of images updated.
+    pub async fn set_image_paths(&self, moves: Vec<(i32, String)>) -> Result<u64> {
+        let moves = moves
+            .into_iter()
+            .map(|(id, path)| {
+                PathMove::new(id, crate::paths::normalize_path(&path))
+                    .with_context(|| format!("'{}' has no file name", path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        dispatch!(self.set_paths(&moves))
     }
 
     /// Delete an image by ID
     pub async fn delete_image(&self, image_id: i32) -> Result<()> {
-        sqlx::query("DELETE FROM images WHERE id = $1")
-            .bind(image_id)
-            .execute(&*self.pool)
-            .await?;
-
-        Ok(())
+        dispatch!(self.delete_image(image_id))
     }
 
     /// Get database statistics
     pub async fn get_statistics(&self) -> Result<DatabaseStats> {
-        let total_images = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM images")
-            .fetch_one(&*self.pool)
-            .await?;
-
-        let total_tags = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM tags")
-            .fetch_one(&*self.pool)
-            .await?;
-
-        let total_groups = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM groups")
-            .fetch_one(&*self.pool)
-            .await?;
-
-        let total_subgroups = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM subgroups")
-            .fetch_one(&*self.pool)
-            .await?;
-
-        Ok(DatabaseStats {
-            total_images,
-            total_tags,
-            total_groups,
-            total_subgroups,
+        dispatch!(self.get_statistics())
+    }
+
+    /// Totals plus per-group, per-extension, per-tag (the `top_tags` most
+    /// used) and per-month breakdowns, all computed in the database
+    pub async fn get_detailed_statistics(&self, top_tags: i64) -> Result<DetailedStats> {
+        let months = recent_months(Utc::now(), 12);
+        let since = months[0].and_time(chrono::NaiveTime::MIN).and_utc();
+
+        let totals = self.get_statistics().await?;
+        let library = dispatch!(self.library_totals())?;
+        let groups = self.get_group_stats().await?;
+        let extensions = dispatch!(self.extension_stats())?;
+        let top_tags = dispatch!(self.tag_usage(top_tags.max(0)))?;
+        let added: HashMap<String, i64> = dispatch!(self.monthly_additions(since))?
+            .into_iter()
+            .collect();
+
+        let added_per_month = months
+            .iter()
+            .map(|first| {
+                let month = first.format("%Y-%m").to_string();
+                MonthlyCount {
+                    image_count: added.get(&month).copied().unwrap_or(0),
+                    month,
+                }
+            })
+            .collect();
+
+        Ok(DetailedStats {
+            totals,
+            total_file_size: library.total_file_size,
+            untagged_images: library.untagged_images,
+            ungrouped_images: library.ungrouped_images,
+            groups,
+            extensions,
+            top_tags,
+            added_per_month,
         })
     }
 
     /// Test database connection
     pub async fn test_connection(&self) -> Result<bool> {
-        sqlx::query("SELECT 1")
-            .execute(&*self.pool)
-            .await
-            .context("Database connection test failed")?;
+        dispatch!(self.test_connection())
+    }
+
+    /// Path and stored metadata for each of `image_ids` that exists
+    pub async fn get_path_records(&self, image_ids: &[i32]) -> Result<Vec<PathRecord>> {
+        dispatch!(self.get_path_records(image_ids))
+    }
+
+    /// Cache a thumbnail, replacing any previous one for the same size. It
+    /// stays valid until the image's `date_modified` changes.
+    pub async fn put_thumbnail(
+        &self,
+        image_id: i32,
+        size: i32,
+        format: &str,
+        bytes: &[u8],
+    ) -> Result<()> {
+        if bytes.len() > MAX_THUMBNAIL_BYTES {
+            anyhow::bail!(
+                "Thumbnail is {} bytes, limit is {}",
+                bytes.len(),
+                MAX_THUMBNAIL_BYTES
+            );
+        }
+        dispatch!(self.put_thumbnail(image_id, size, format, bytes))
+    }
+
+    /// The cached thumbnail, unless missing or stale
+    pub async fn get_thumbnail(&self, image_id: i32, size: i32) -> Result<Option<Thumbnail>> {
+        dispatch!(self.get_thumbnail(image_id, size))
+    }
+
+    /// Fresh cached thumbnails for any of `image_ids`
+    pub async fn get_thumbnails(&self, image_ids: &[i32], size: i32) -> Result<Vec<Thumbnail>> {
+        dispatch!(self.get_thumbnails(image_ids, size))
+    }
+
+    /// Drop stale thumbnails, then the oldest ones until the cache holds at
+    /// most `max_total_bytes`. Returns the number removed.
+    pub async fn prune_thumbnails(&self, max_total_bytes: i64) -> Result<u64> {
+        dispatch!(self.prune_thumbnails(max_total_bytes))
+    }
+
+    /// Store `query` under `name`, replacing any saved search with that name
+    pub async fn save_search(&self, name: &str, query: &SearchQuery) -> Result<SavedSearch> {
+        if name.trim().is_empty() {
+            anyhow::bail!("Saved search name cannot be empty");
+        }
+        let json = serde_json::to_string(query)?;
+        dispatch!(self.save_search(name, &json))?.try_into()
+    }
+
+    /// All saved searches, by name
+    pub async fn list_saved_searches(&self) -> Result<Vec<SavedSearch>> {
+        dispatch!(self.list_saved_searches())?
+            .into_iter()
+            .map(SavedSearch::try_from)
+            .collect()
+    }
+
+    pub async fn get_saved_search(&self, name: &str) -> Result<SavedSearch> {
+        dispatch!(self.get_saved_search(name))?
+            .with_context(|| format!("Saved search '{}' not found", name))?
+            .try_into()
+    }
+
+    pub async fn delete_saved_search(&self, name: &str) -> Result<()> {
+        dispatch!(self.delete_saved_search(name))
+    }
+
+    /// Create an empty collection; fails if the name is taken
+    pub async fn create_collection(
+        &self,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<Collection> {
+        if name.trim().is_empty() {
+            anyhow::bail!("Collection name cannot be empty");
+        }
+        dispatch!(self.create_collection(name, description))?
+            .with_context(|| format!("Collection '{}' already exists", name))
+    }
+
+    pub async fn rename_collection(&self, old_name: &str, new_name: &str) -> Result<()> {
+        if new_name.trim().is_empty() {
+            anyhow::bail!("Collection name cannot be empty");
+        }
+        dispatch!(self.rename_collection(old_name, new_name))
+    }
+
+    /// Delete a collection; its images are untouched
+    pub async fn delete_collection(&self, name: &str) -> Result<()> {
+        dispatch!(self.delete_collection(name))
+    }
+
+    /// All collections with their image counts, by name
+    pub async fn list_collections_with_counts(&self) -> Result<Vec<Collection>> {
+        dispatch!(self.list_collections_with_counts())
+    }
+
+    /// Append `image_ids` to the end of a collection in the order given.
+    /// Images already in it keep their place; unknown ids are ignored.
+    /// Returns the number of images added.
+    pub async fn add_to_collection(&self, name: &str, image_ids: &[i32]) -> Result<u64> {
+        dispatch!(self.add_to_collection(name, image_ids))
+    }
+
+    /// Returns the number of images removed
+    pub async fn remove_from_collection(&self, name: &str, image_ids: &[i32]) -> Result<u64> {
+        dispatch!(self.remove_from_collection(name, image_ids))
+    }
+
+    /// Move `image_ids` to the front of a collection in the order given; the
+    /// remaining images follow in their current order. Every id must already
+    /// be in the collection.
+    pub async fn reorder_collection(&self, name: &str, image_ids: &[i32]) -> Result<()> {
+        let mut seen = std::collections::HashSet::new();
+        let order: Vec<i32> = image_ids
+            .iter()
+            .copied()
+            .filter(|id| seen.insert(*id))
+            .collect();
+        dispatch!(self.reorder_collection(name, &order))
+    }
+
+    /// One page of a collection's images in collection order
+    pub async fn get_collection_images(
+        &self,
+        name: &str,
+        limit: Option<i32>,
+        offset: Option<i64>,
+    ) -> Result<SearchResults> {
+        let (limit, offset) = SearchQuery {
+            limit,
+            offset,
+            ..Default::default()
+        }
+        .page_bounds();
+        dispatch!(self.get_collection_images(name, limit, offset))
+    }
+
+    /// EXIF capture metadata stored for an image
+    pub async fn get_capture_metadata(&self, image_id: i32) -> Result<CaptureMetadata> {
+        dispatch!(self.get_capture_metadata(image_id))
+    }
+
+    /// Stream the path and stored metadata of every image, ordered by id.
+    /// Holds a pooled connection until the stream is dropped.
+    pub fn get_all_paths(&self) -> BoxStream<'_, Result<PathRecord>> {
+        match self {
+            Db::Postgres(store) => store.get_all_paths(),
+            Db::Sqlite(store) => store.get_all_paths(),
+        }
+    }
+}
+
+/// Normalizes paths, fills in filenames and rejects items that can't be
+/// inserted (empty paths, no file name, or a path repeated within the batch,
+/// however it is spelled). Returns the remaining items paired with their index
+/// in the input.
+fn validate_new_images(
+    images: Vec<NewImage>,
+    errors: &mut Vec<BatchItemError>,
+) -> Vec<(usize, NewImage)> {
+    let mut seen = std::collections::HashSet::new();
+    let mut valid = Vec::with_capacity(images.len());
+
+    for (index, mut image) in images.into_iter().enumerate() {
+        if !image.file_path.trim().is_empty() {
+            image.file_path = crate::paths::normalize_path(&image.file_path);
+        }
+        let derived = std::path::Path::new(&image.file_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(String::from);
+        image.filename = image.filename.filter(|f| !f.is_empty()).or(derived);
+
+        let problem = if image.file_path.trim().is_empty() {
+            Some("file_path is empty")
+        } else if image.filename.is_none() {
+            Some("file_path has no file name")
+        } else if !seen.insert(crate::paths::path_key(&image.file_path)) {
+            Some("file_path appears more than once in the batch")
+        } else {
+            None
+        };
+
+        match problem {
+            Some(problem) => errors.push(BatchItemError {
+                index,
+                file_path: image.file_path,
+                error: problem.to_string(),
+            }),
+            None => valid.push((index, image)),
+        }
+    }
+
+    valid
+}
+
+/// Moves every pending item matching `predicate` into `errors`
+fn fail_matching(
+    pending: &mut Vec<(usize, NewImage)>,
+    errors: &mut Vec<BatchItemError>,
+    error: &anyhow::Error,
+    predicate: impl Fn(&NewImage) -> bool,
+) {
+    pending.retain(|(index, image)| {
+        if predicate(image) {
+            errors.push(BatchItemError {
+                index: *index,
+                file_path: image.file_path.clone(),
+                error: error.to_string(),
+            });
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Escapes LIKE wildcards so user input only ever matches literally
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Full order for a collection after moving `front` (deduplicated) ahead of
+/// the other `members`, which keep their relative order
+fn collection_order(name: &str, members: &[i32], front: &[i32]) -> Result<Vec<i32>> {
+    let member_set: std::collections::HashSet<i32> = members.iter().copied().collect();
+    if let Some(stranger) = front.iter().find(|id| !member_set.contains(id)) {
+        anyhow::bail!("Image {} is not in collection '{}'", stranger, name);
+    }
+
+    let mut order = front.to_vec();
+    order.extend(members.iter().filter(|id| !front.contains(id)));
+    Ok(order)
+}
+
+/// LIKE pattern for the paths inside `prefix`; callers also match
+/// `file_path = prefix` and re-check with `paths::rebase_path`
+fn prefix_like_pattern(prefix: &str) -> String {
+    let separator = PathStyle::native().separator();
+    if prefix.ends_with(separator) {
+        format!("{}%", escape_like(prefix))
+    } else {
+        format!("{}{}%", escape_like(prefix), separator)
+    }
+}
+
+/// Rejects embeddings that don't match the column dimension
+fn check_embedding_dim(embedding: &[f32]) -> Result<()> {
+    if embedding.len() != EMBEDDING_DIM {
+        anyhow::bail!(
+            "Embedding has {} dimensions, expected {}",
+            embedding.len(),
+            EMBEDDING_DIM
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sets columns the public API doesn't expose, so filters can be exercised
+    async fn set_size_and_date(db: &Db, id: i32, size: i64, date_added: DateTime<Utc>) {
+        let sql = "UPDATE images SET file_size = $1, date_added = $2 WHERE id = $3";
+        match db {
+            Db::Postgres(store) => {
+                sqlx::query(sql)
+                    .bind(size)
+                    .bind(date_added)
+                    .bind(id)
+                    .execute(store.pool())
+                    .await
+                    .unwrap();
+            }
+            Db::Sqlite(store) => {
+                sqlx::query(sql)
+                    .bind(size)
+                    .bind(date_added)
+                    .bind(id)
+                    .execute(store.pool())
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    /// In-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    async fn test_databases() -> Vec<Db> {
+        let mut databases = vec![Db::new("sqlite::memory:").await.unwrap()];
+        if let Ok(url) = std::env::var("TEST_DATABASE_URL") {
+            databases.push(Db::new(&url).await.unwrap());
+        }
+        databases
+    }
+
+    fn query(limit: Option<i32>, offset: Option<i64>, page: Option<i64>) -> SearchQuery {
+        SearchQuery {
+            limit,
+            offset,
+            page,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_embedding_dim() {
+        assert!(check_embedding_dim(&[0.0; EMBEDDING_DIM]).is_ok());
+        let err = check_embedding_dim(&[0.0; 3]).unwrap_err();
+        assert_eq!(err.to_string(), "Embedding has 3 dimensions, expected 128");
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_rejects_similarity_search() {
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let err = db
+            .search_similar(vec![0.0; EMBEDDING_DIM], 5, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not supported"));
+    }
+
+    /// Needs a PostgreSQL database with pgvector; skipped unless TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_search_similar_ordering() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            return;
+        };
+        let db = Db::new(&url).await.unwrap();
+        let group = format!("similar-{}", uuid::Uuid::new_v4());
+
+        let axis = |i: usize, w: f32| {
+            let mut v = vec![0.0; EMBEDDING_DIM];
+            v[0] = 1.0;
+            v[i] = w;
+            v
+        };
+        for (name, embedding) in [
+            ("far", axis(1, 5.0)),
+            ("near", axis(1, 0.1)),
+            ("mid", axis(1, 1.0)),
+        ] {
+            let path = format!("/{}/{}.png", group, name);
+            let id = db
+                .add_image(&path, name, None, None, Some(&group), None, None)
+                .await
+                .unwrap();
+            db.upsert_embedding(id, embedding).await.unwrap();
+        }
+
+        let mut filters = query(None, None, None);
+        filters.group_name = Some(group.clone());
+        let results = db
+            .search_similar(axis(1, 0.0), 10, Some(filters))
+            .await
+            .unwrap();
+
+        let names: Vec<&str> = results.iter().map(|r| r.filename.as_str()).collect();
+        assert_eq!(names, ["near", "mid", "far"]);
+        assert!(results[0].distance.unwrap() < results[1].distance.unwrap());
+        assert!(db.search_similar(vec![1.0; 4], 10, None).await.is_err());
+    }
+
+    /// Runs against in-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_update_image_partial() {
+        for db in test_databases().await {
+            let dir = format!("/update-{}", uuid::Uuid::new_v4());
+            let id = db
+                .add_image(
+                    &format!("{}/a.png", dir),
+                    "a.png",
+                    Some(10),
+                    Some(20),
+                    Some("before"),
+                    None,
+                    Some(vec!["keep".to_string()]),
+                )
+                .await
+                .unwrap();
+
+            let moved = db
+                .update_image(
+                    id,
+                    UpdateImage {
+                        file_path: Some(format!("{}/sub/b.jpg", dir)),
+                        width: Some(30),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(moved.file_path, format!("{}/sub/b.jpg", dir));
+            assert_eq!(moved.filename, "b.jpg");
+            assert_eq!((moved.width, moved.height), (Some(30), Some(20)));
+            assert_eq!(moved.group_name.as_deref(), Some("before"));
+            assert_eq!(moved.tags, ["keep"]);
+            assert!(moved.date_modified.is_some());
+
+            let err = db
+                .update_image(-1, UpdateImage::default())
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), "Image -1 not found");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ratings_and_favorites() {
+        for db in test_databases().await {
+            let group = format!("rated-{}", uuid::Uuid::new_v4());
+            let mut ids = Vec::new();
+            for name in ["a.png", "b.png", "c.png"] {
+                let path = format!("/{}/{}", group, name);
+                let id = db
+                    .add_image(&path, name, None, None, Some(&group), None, None)
+                    .await
+                    .unwrap();
+                ids.push(id);
+            }
+
+            db.set_rating(ids[0], Some(5)).await.unwrap();
+            db.set_rating(ids[1], Some(3)).await.unwrap();
+            assert!(db.set_rating(ids[2], Some(6)).await.is_err());
+            assert!(db.set_rating(-1, Some(1)).await.is_err());
+            assert!(db.toggle_favorite(ids[1]).await.unwrap());
+            assert!(db.toggle_favorite(ids[2]).await.unwrap());
+            assert!(!db.toggle_favorite(ids[2]).await.unwrap());
+
+            let in_group = |query: SearchQuery| SearchQuery {
+                group_name: Some(group.clone()),
+                ..query
+            };
+
+            let rated = db
+                .search_images(in_group(SearchQuery {
+                    min_rating: Some(3),
+                    sort_by: Some(SortBy::Rating),
+                    descending: Some(false),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+            let rated_ids: Vec<i32> = rated.images.iter().map(|i| i.id).collect();
+            assert_eq!(rated_ids, [ids[1], ids[0]]);
+
+            let favorites = db
+                .search_images(in_group(SearchQuery {
+                    favorites_only: Some(true),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+            assert_eq!(favorites.total_count, 1);
+            assert!(favorites.images[0].favorite);
+            assert_eq!(favorites.images[0].rating, Some(3));
+
+            // Unrated images sort last in either direction
+            let by_rating = db
+                .search_images(in_group(SearchQuery {
+                    sort_by: Some(SortBy::Rating),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+            assert_eq!(by_rating.images[2].id, ids[2]);
+
+            db.set_rating(ids[0], None).await.unwrap();
+            let cleared = db
+                .search_images(in_group(SearchQuery {
+                    min_rating: Some(1),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+            assert_eq!(cleared.total_count, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_view_is_atomic() {
+        for db in test_databases().await {
+            let path = format!("/views-{}/a.png", uuid::Uuid::new_v4());
+            let id = db
+                .add_image(&path, "a.png", None, None, None, None, None)
+                .await
+                .unwrap();
+
+            let mut counts =
+                futures_util::future::try_join_all((0..20).map(|_| db.record_view(id)))
+                    .await
+                    .unwrap();
+            counts.sort();
+            assert_eq!(counts, (1..=20).collect::<Vec<i32>>());
+
+            let record = db.update_image(id, UpdateImage::default()).await.unwrap();
+            assert_eq!(record.view_count, 20);
+            assert!(record.last_viewed.is_some());
+            assert!(db.record_view(-1).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_merge_with_conflicts() {
+        for db in test_databases().await {
+            let prefix = uuid::Uuid::new_v4().to_string();
+            let (a, b) = (format!("{}-a", prefix), format!("{}-b", prefix));
+            for (group, subgroup, name) in [
+                (&a, "x", "1"),
+                (&a, "y", "2"),
+                (&b, "y", "3"),
+                (&b, "z", "4"),
+            ] {
+                let path = format!("/{}/{}.png", prefix, name);
+                db.add_image(&path, name, None, None, Some(group), Some(subgroup), None)
+                    .await
+                    .unwrap();
+            }
+
+            // b/y already exists, so a/y folds into it
+            assert_eq!(
+                db.merge_groups(std::slice::from_ref(&a), &b).await.unwrap(),
+                1
+            );
+            assert_eq!(
+                db.get_subgroups_for_group(&b).await.unwrap(),
+                ["x", "y", "z"]
+            );
+
+            let stats: Vec<GroupStats> = db
+                .get_group_stats()
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|g| g.name.starts_with(&prefix))
+                .collect();
+            assert_eq!(stats.len(), 1);
+            assert_eq!(stats[0].image_count, 4);
+            let counts: Vec<(&str, i64)> = stats[0]
+                .subgroups
+                .iter()
+                .map(|s| (s.name.as_str(), s.image_count))
+                .collect();
+            assert_eq!(counts, [("x", 1), ("y", 2), ("z", 1)]);
+
+            // Renaming onto an existing subgroup merges them
+            db.rename_subgroup(&b, "x", "z").await.unwrap();
+            assert_eq!(db.get_subgroups_for_group(&b).await.unwrap(), ["y", "z"]);
+            assert!(db.rename_subgroup(&b, "x", "w").await.is_err());
+
+            assert_eq!(db.delete_subgroup(&b, "y", None).await.unwrap(), 2);
+            let renamed = format!("{}-renamed", prefix);
+            db.rename_group(&b, &renamed).await.unwrap();
+            let moved = db
+                .search_images(SearchQuery {
+                    group_name: Some(renamed.clone()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(moved.total_count, 4);
+            assert_eq!(
+                moved
+                    .images
+                    .iter()
+                    .filter(|i| i.subgroup_name.is_none())
+                    .count(),
+                2
+            );
+
+            assert_eq!(db.delete_group(&renamed, None).await.unwrap(), 4);
+            assert!(db.delete_group(&renamed, None).await.is_err());
+            assert!(!db.get_all_groups().await.unwrap().contains(&renamed));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_metadata_import_and_search() {
+        use exif::{Field, In, Tag, Value};
+
+        let temp = tempfile::tempdir().unwrap();
+        let tagged = temp.path().join("tagged.jpg");
+        let ascii = |tag, text: &str| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+        };
+        let fields = [
+            ascii(Tag::Make, "Canon"),
+            ascii(Tag::Model, "Canon EOS R5"),
+            ascii(Tag::DateTimeOriginal, "2023:08:01 09:15:00"),
+        ];
+        std::fs::write(&tagged, crate::exif_reader::jpeg_with_exif(&fields)).unwrap();
+        let plain = temp.path().join("plain.png");
+        image::RgbImage::new(4, 4).save(&plain).unwrap();
+
+        for db in test_databases().await {
+            // Paths are shared across backends, so scope the search by group
+            let group = format!("exif-{}", uuid::Uuid::new_v4());
+            let added = db
+                .batch_add_images(
+                    [&tagged, &plain]
+                        .iter()
+                        .map(|path| NewImage {
+                            file_path: path.to_string_lossy().to_string(),
+                            group_name: Some(group.clone()),
+                            ..Default::default()
+                        })
+                        .collect(),
+                )
+                .await
+                .unwrap();
+            let ids = [added.inserted, added.updated].concat();
+            assert_eq!(ids.len(), 2);
+
+            let found = db
+                .search_images(SearchQuery {
+                    group_name: Some(group.clone()),
+                    camera_model: Some("eos r5".to_string()),
+                    date_taken_after: Some("2023-08-01T00:00:00Z".parse().unwrap()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(found.total_count, 1);
+
+            let meta = db.get_capture_metadata(found.images[0].id).await.unwrap();
+            assert_eq!(meta.camera_make.as_deref(), Some("Canon"));
+            assert_eq!(
+                meta.date_taken.unwrap().to_rfc3339(),
+                "2023-08-01T09:15:00+00:00"
+            );
+
+            let other = ids.iter().find(|id| **id != found.images[0].id).unwrap();
+            assert_eq!(
+                db.get_capture_metadata(*other).await.unwrap(),
+                CaptureMetadata::default()
+            );
+            assert!(db.get_capture_metadata(-1).await.is_err());
+        }
+    }
+
+    #[test]
+    fn test_search_query_tolerates_old_and_new_fields() {
+        let parsed: SearchQuery = serde_json::from_value(serde_json::json!({
+            "group_name": "Trips",
+            "added_in_a_future_version": true
+        }))
+        .unwrap();
+        assert_eq!(parsed.group_name.as_deref(), Some("Trips"));
+        assert!(parsed.tags.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_collection_ordering_and_cascade() {
+        for db in test_databases().await {
+            let dir = format!("/collection-{}", uuid::Uuid::new_v4());
+            let mut ids = Vec::new();
+            for name in ["a.jpg", "b.jpg", "c.jpg", "d.jpg"] {
+                ids.push(
+                    db.add_image(
+                        &format!("{}/{}", dir, name),
+                        name,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                    )
+                    .await
+                    .unwrap(),
+                );
+            }
+            let [a, b, c, d] = ids[..] else {
+                unreachable!()
+            };
+            let name = format!("Best of {}", dir);
+
+            let created = db.create_collection(&name, Some("Prints")).await.unwrap();
+            assert_eq!(created.image_count, 0);
+            assert!(db.create_collection(&name, None).await.is_err());
+
+            assert_eq!(db.add_to_collection(&name, &[c, a, c, b]).await.unwrap(), 3);
+            // Members keep their place; new ones are appended, unknown ids skipped
+            assert_eq!(db.add_to_collection(&name, &[a, d, -1]).await.unwrap(), 1);
+            assert_eq!(collection_ids(&db, &name).await, [c, a, b, d]);
+
+            db.reorder_collection(&name, &[d, a]).await.unwrap();
+            assert_eq!(collection_ids(&db, &name).await, [d, a, c, b]);
+            assert!(db.reorder_collection(&name, &[-1]).await.is_err());
+
+            assert_eq!(db.remove_from_collection(&name, &[c]).await.unwrap(), 1);
+            let page = db
+                .get_collection_images(&name, Some(2), Some(1))
+                .await
+                .unwrap();
+            assert_eq!(page.total_count, 3);
+            assert_eq!(page.images.iter().map(|i| i.id).collect::<Vec<_>>(), [a, b]);
+
+            let filtered = db
+                .search_images(SearchQuery {
+                    collection: Some(name.clone()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(filtered.total_count, 3);
+
+            // Deleting an image drops it from the collection
+            db.delete_image(a).await.unwrap();
+            assert_eq!(collection_ids(&db, &name).await, [d, b]);
+            let listed = db.list_collections_with_counts().await.unwrap();
+            let listed = listed.iter().find(|col| col.name == name).unwrap();
+            assert_eq!(listed.image_count, 2);
+            assert_eq!(listed.description.as_deref(), Some("Prints"));
+
+            let renamed = format!("{} (renamed)", name);
+            db.rename_collection(&name, &renamed).await.unwrap();
+            db.delete_collection(&renamed).await.unwrap();
+            assert!(db
+                .get_collection_images(&renamed, None, None)
+                .await
+                .is_err());
+            assert!(db.delete_collection(&renamed).await.is_err());
+        }
+    }
+
+    /// This image's entries in the change log, newest first
+    async fn changes_for(db: &Db, image_id: i32) -> Vec<ChangeLogEntry> {
+        db.get_recent_changes(MAX_PAGE_SIZE)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|entry| entry.image_id == image_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_delete_then_undo_restores_image() {
+        for db in test_databases().await {
+            let path = format!("/undo-{}/a.jpg", uuid::Uuid::new_v4());
+            let tags = vec!["beach".to_string(), "sunset".to_string()];
+            let id = db
+                .add_image(
+                    &path,
+                    "a.jpg",
+                    Some(4),
+                    Some(3),
+                    Some("Trips"),
+                    None,
+                    Some(tags.clone()),
+                )
+                .await
+                .unwrap();
+            db.set_rating(id, Some(4)).await.unwrap();
+
+            db.delete_image(id).await.unwrap();
+            assert!(db.get_path_records(&[id]).await.unwrap().is_empty());
+
+            let deleted = changes_for(&db, id).await.remove(0);
+            assert_eq!(deleted.operation, ChangeKind::Delete);
+            assert_eq!(deleted.image.tags, tags);
+            assert_eq!(deleted.image.rating, Some(4));
+
+            let undone = db.undo_change(deleted.id).await.unwrap();
+            assert!(undone.undone_at.is_some());
+            let restored = db.get_path_records(&[id]).await.unwrap();
+            assert_eq!(restored.len(), 1);
+            assert_eq!(restored[0].file_path, path);
+            assert_eq!(restored[0].width, Some(4));
+            assert_eq!(db.get_image_tags(id).await.unwrap(), tags);
+
+            let err = db.undo_change(deleted.id).await.unwrap_err();
+            assert!(err.to_string().contains("already undone"), "{}", err);
+            assert!(db.undo_change(-1).await.is_err());
+
+            // The restored path is tracked again, so re-adding it updates the row
+            let again = db
+                .add_image(&path, "a.jpg", None, None, None, None, None)
+                .await
+                .unwrap();
+            assert_eq!(again, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_change_log_order_and_undo_update() {
+        for db in test_databases().await {
+            let stem = format!("changes-{}", uuid::Uuid::new_v4());
+            let dir = format!("/{}", stem);
+            let original = format!("{}.jpg", stem);
+            let id = db
+                .add_image(
+                    &format!("{}/{}", dir, original),
+                    &original,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+            db.add_tags_to_images(&[id], &["draft".to_string()])
+                .await
+                .unwrap();
+            db.update_image(
+                id,
+                UpdateImage {
+                    file_path: Some(format!("{}/b.jpg", dir)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            db.toggle_favorite(id).await.unwrap();
+
+            let changes = changes_for(&db, id).await;
+            let operations: Vec<ChangeKind> = changes.iter().map(|c| c.operation).collect();
+            assert_eq!(
+                operations,
+                [
+                    ChangeKind::Update,
+                    ChangeKind::Update,
+                    ChangeKind::Tag,
+                    ChangeKind::Add
+                ]
+            );
+            assert!(changes.windows(2).all(|pair| pair[0].id > pair[1].id));
+            assert_eq!(changes[3].image.file_path, format!("{}/{}", dir, original));
+
+            // Changes to an image are undone newest first
+            let err = db.undo_change(changes[3].id).await.unwrap_err();
+            assert!(err.to_string().contains("newer changes"), "{}", err);
+
+            db.undo_change(changes[0].id).await.unwrap();
+            db.undo_change(changes[1].id).await.unwrap();
+            let record = &db.get_path_records(&[id]).await.unwrap()[0];
+            assert_eq!(record.file_path, format!("{}/{}", dir, original));
+            let found = db
+                .search_images(SearchQuery {
+                    filename_pattern: Some(original.clone()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            let image = found.images.iter().find(|image| image.id == id).unwrap();
+            assert!(!image.favorite);
+            assert_eq!(image.filename, original);
+
+            db.undo_change(changes[2].id).await.unwrap();
+            assert!(db.get_image_tags(id).await.unwrap().is_empty());
+
+            // Undoing the add removes the image again
+            db.undo_change(changes[3].id).await.unwrap();
+            assert!(db.get_path_records(&[id]).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_saved_search_round_trip() {
+        let date: DateTime<Utc> = "2024-01-02T03:04:05Z".parse().unwrap();
+        let full = SearchQuery {
+            group_name: Some("g".to_string()),
+            subgroup_name: Some("s".to_string()),
+            tags: Some(vec!["a".to_string()]),
+            tag_mode: Some(TagMode::All),
+            exclude_tags: Some(vec!["b".to_string()]),
+            filename_pattern: Some("img".to_string()),
+            filename_fts: Some("beach".to_string()),
+            input_formats: Some(vec!["png".to_string()]),
+            min_width: Some(1),
+            min_height: Some(2),
+            min_file_size: Some(3),
+            max_file_size: Some(4),
+            date_added_after: Some(date),
+            date_added_before: Some(date),
+            min_rating: Some(4),
+            favorites_only: Some(true),
+            camera_model: Some("R5".to_string()),
+            date_taken_after: Some(date),
+            date_taken_before: Some(date),
+            collection: Some("Prints".to_string()),
+            media_type: Some(MediaType::Video),
+            min_duration: Some(1.5),
+            max_duration: Some(60.0),
+            sort_by: Some(SortBy::Area),
+            descending: Some(false),
+            limit: Some(10),
+            offset: Some(20),
+            page: Some(2),
+        };
+
+        for db in test_databases().await {
+            let name = format!("search-{}", uuid::Uuid::new_v4());
+            db.save_search(&name, &SearchQuery::default())
+                .await
+                .unwrap();
+            let saved = db.save_search(&name, &full).await.unwrap();
+            assert_eq!(
+                serde_json::to_value(&saved.query).unwrap(),
+                serde_json::to_value(&full).unwrap()
+            );
+            let loaded = db.get_saved_search(&name).await.unwrap();
+            assert_eq!(
+                serde_json::to_value(&loaded.query).unwrap(),
+                serde_json::to_value(&full).unwrap()
+            );
+            assert_eq!(loaded.id, saved.id);
+            assert!(db
+                .list_saved_searches()
+                .await
+                .unwrap()
+                .iter()
+                .any(|s| s.name == name));
+
+            // Saved searches run through the normal search path
+            let group = format!("saved-{}", uuid::Uuid::new_v4());
+            let path = format!("/{}/a.png", group);
+            db.add_image(&path, "a.png", None, None, Some(&group), None, None)
+                .await
+                .unwrap();
+            let album = format!("album-{}", group);
+            let query = SearchQuery {
+                group_name: Some(group.clone()),
+                ..Default::default()
+            };
+            db.save_search(&album, &query).await.unwrap();
+            let stored = db.get_saved_search(&album).await.unwrap().query;
+            assert_eq!(db.search_images(stored).await.unwrap().total_count, 1);
+
+            db.delete_saved_search(&name).await.unwrap();
+            assert!(db.get_saved_search(&name).await.is_err());
+            assert!(db.delete_saved_search(&name).await.is_err());
+            assert!(db.save_search(" ", &query).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_thumbnail_invalidation() {
+        for db in test_databases().await {
+            let path = format!("/thumbs-{}/a.png", uuid::Uuid::new_v4());
+            let id = db
+                .add_image(&path, "a.png", None, None, None, None, None)
+                .await
+                .unwrap();
+
+            db.put_thumbnail(id, 128, "jpeg", b"first").await.unwrap();
+            db.put_thumbnail(id, 128, "jpeg", b"second").await.unwrap();
+            let thumb = db.get_thumbnail(id, 128).await.unwrap().unwrap();
+            assert_eq!(thumb.bytes, b"second");
+            assert!(db.get_thumbnail(id, 64).await.unwrap().is_none());
+
+            let update = UpdateImage {
+                width: Some(10),
+                ..Default::default()
+            };
+            db.update_image(id, update).await.unwrap();
+            assert!(db.get_thumbnails(&[id], 128).await.unwrap().is_empty());
+
+            // The stale row is only dropped by pruning
+            assert!(db.prune_thumbnails(i64::MAX).await.unwrap() >= 1);
+            db.put_thumbnail(id, 128, "jpeg", b"third").await.unwrap();
+            assert!(db.get_thumbnail(id, 128).await.unwrap().is_some());
+        }
+    }
+
+    /// Runs against in-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_bulk_tag_add_remove() {
+        for db in test_databases().await {
+            let dir = format!("/bulk-{}", uuid::Uuid::new_v4());
+            let mut ids = Vec::new();
+            for i in 0..3 {
+                let path = format!("{}/{}.png", dir, i);
+                ids.push(
+                    db.add_image(&path, "x.png", None, None, None, None, None)
+                        .await
+                        .unwrap(),
+                );
+            }
+            let tag = |t: &str| format!("{}-{}", dir, t);
+            let tags = vec![tag("a"), tag("b")];
+
+            assert_eq!(db.add_tags_to_images(&ids, &tags).await.unwrap(), 6);
+            // Re-adding is a no-op
+            assert_eq!(db.add_tags_to_images(&ids, &tags).await.unwrap(), 0);
+            assert_eq!(
+                db.remove_tags_from_images(&ids[..2], &[tag("a")])
+                    .await
+                    .unwrap(),
+                2
+            );
+
+            let by_image = db.get_tags_for_images(&ids).await.unwrap();
+            assert_eq!(by_image[&ids[0]], [tag("b")]);
+            assert_eq!(by_image[&ids[2]], [tag("a"), tag("b")]);
+
+            // A missing image violates the foreign key and rolls back the new tag too
+            let bad = db.add_tags_to_images(&[ids[0], -1], &[tag("c")]).await;
+            assert!(bad.is_err());
+            assert_eq!(
+                db.get_tags_for_images(&ids[..1]).await.unwrap()[&ids[0]],
+                [tag("b")]
+            );
+            assert!(!db.get_all_tags().await.unwrap().contains(&tag("c")));
+
+            db.set_image_tags(ids[0], vec![tag("d"), tag("d")])
+                .await
+                .unwrap();
+            assert_eq!(db.get_image_tags(ids[0]).await.unwrap(), [tag("d")]);
+        }
+    }
+
+    /// Runs against in-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_tag_rename_and_merge() {
+        for db in test_databases().await {
+            let dir = format!("/tags-{}", uuid::Uuid::new_v4());
+            let tag = |t: &str| format!("{}-{}", dir, t);
+            let add = |name: &'static str, tags: Vec<String>| {
+                let path = format!("{}/{}", dir, name);
+                let db = &db;
+                async move {
+                    db.add_image(&path, name, None, None, None, None, Some(tags))
+                        .await
+                        .unwrap()
+                }
+            };
+
+            // `a` carries both the typo and the target, so the merge overlaps
+            let a = add("a.png", vec![tag("landscpe"), tag("landscape")]).await;
+            let b = add("b.png", vec![tag("Landscape")]).await;
+            let c = add("c.png", vec![tag("landscpe")]).await;
+
+            let merged = db
+                .merge_tags(&[tag("landscpe"), tag("Landscape")], &tag("landscape"))
+                .await
+                .unwrap();
+            assert_eq!(merged, 2);
+            let by_image = db.get_tags_for_images(&[a, b, c]).await.unwrap();
+            for id in [a, b, c] {
+                assert_eq!(by_image[&id], [tag("landscape")]);
+            }
+
+            db.rename_tag(&tag("landscape"), &tag("scenery"))
+                .await
+                .unwrap();
+            assert_eq!(db.get_image_tags(a).await.unwrap(), [tag("scenery")]);
+            assert!(db.rename_tag(&tag("missing"), &tag("x")).await.is_err());
+
+            // Renaming into an existing tag merges instead of violating the primary key
+            db.add_tags_to_images(&[a], &[tag("sky")]).await.unwrap();
+            db.rename_tag(&tag("sky"), &tag("scenery")).await.unwrap();
+            assert_eq!(db.get_image_tags(a).await.unwrap(), [tag("scenery")]);
+
+            db.add_tags_to_images(&[a], &[tag("tmp")]).await.unwrap();
+            db.delete_tag(&tag("tmp")).await.unwrap();
+            assert_eq!(db.get_image_tags(a).await.unwrap(), [tag("scenery")]);
+            assert!(!db
+                .get_unused_tags()
+                .await
+                .unwrap()
+                .contains(&tag("scenery")));
+        }
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+    }
+
+    /// Runs against in-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_search_tags_and_filename_fts() {
+        for db in test_databases().await {
+            let dir = format!("fts{}", uuid::Uuid::new_v4().simple());
+            let tags: Vec<String> = ["Land", "landscape", "landmark", "island"]
+                .iter()
+                .map(|t| format!("{}{}", dir, t))
+                .collect();
+            let id = db
+                .add_image(
+                    &format!("/{}/sunset_beach_2024.png", dir),
+                    "sunset_beach_2024.png",
+                    None,
+                    None,
+                    Some(&dir),
+                    None,
+                    Some(tags),
+                )
+                .await
+                .unwrap();
+
+            let found = db.search_tags(&format!("{}LAND", dir), 10).await.unwrap();
+            assert_eq!(found.len(), 3);
+            assert!(found.iter().all(|t| !t.ends_with("island")));
+            assert_eq!(
+                db.search_tags(&format!("{}land", dir), 2)
+                    .await
+                    .unwrap()
+                    .len(),
+                2
+            );
+            assert!(db
+                .search_tags(&format!("{}%", dir), 10)
+                .await
+                .unwrap()
+                .is_empty());
+
+            let mut fts = query(None, None, None);
+            fts.group_name = Some(dir.clone());
+            fts.filename_fts = Some("sunset".to_string());
+            let page = db.search_images(fts.clone()).await.unwrap();
+            assert_eq!(page.images.iter().map(|i| i.id).collect::<Vec<_>>(), [id]);
+
+            fts.filename_fts = Some("mountain".to_string());
+            assert_eq!(db.search_images(fts).await.unwrap().total_count, 0);
+        }
+    }
+
+    #[test]
+    fn test_sort_by_parsing() {
+        let parsed: SearchQuery = serde_json::from_value(
+            serde_json::json!({"sort_by": "width*height", "descending": false}),
+        )
+        .unwrap();
+        assert_eq!(parsed.sort_by, Some(SortBy::Area));
+        assert_eq!(
+            parsed.order_by_sql(),
+            " ORDER BY (CAST(i.width AS BIGINT) * CAST(i.height AS BIGINT)) ASC NULLS LAST, i.id ASC"
+        );
+        assert_eq!(
+            query(None, None, None).order_by_sql(),
+            " ORDER BY i.date_added DESC NULLS LAST, i.id DESC"
+        );
+        assert!(
+            serde_json::from_value::<SearchQuery>(serde_json::json!({"sort_by": "id; DROP"}))
+                .is_err()
+        );
+    }
+
+    /// Runs against in-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_search_filters_and_sort() {
+        for db in test_databases().await {
+            let group = format!("filters-{}", uuid::Uuid::new_v4());
+            let now = Utc::now();
+
+            // (name, width, height, file_size, days ago)
+            let rows = [
+                ("small.png", 800, 600, 1_000, 1),
+                ("wide.png", 4000, 1000, 5_000, 3),
+                ("big.png", 3500, 3000, 9_000, 40),
+            ];
+            for (name, w, h, size, days) in rows {
+                let id = db
+                    .add_image(
+                        &format!("/{}/{}", group, name),
+                        name,
+                        Some(w),
+                        Some(h),
+                        Some(&group),
+                        None,
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                set_size_and_date(&db, id, size, now - chrono::Duration::days(days)).await;
+            }
+
+            let names = |q: SearchQuery| {
+                let db = &db;
+                async move {
+                    db.search_images(q)
+                        .await
+                        .unwrap()
+                        .images
+                        .into_iter()
+                        .map(|i| i.filename)
+                        .collect::<Vec<_>>()
+                }
+            };
+            let base = SearchQuery {
+                group_name: Some(group.clone()),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                names(base.clone()).await,
+                ["small.png", "wide.png", "big.png"]
+            );
+            let q = SearchQuery {
+                min_width: Some(3000),
+                ..base.clone()
+            };
+            assert_eq!(names(q).await, ["wide.png", "big.png"]);
+            let q = SearchQuery {
+                min_height: Some(2000),
+                ..base.clone()
+            };
+            assert_eq!(names(q).await, ["big.png"]);
+            let q = SearchQuery {
+                min_file_size: Some(2_000),
+                max_file_size: Some(6_000),
+                ..base.clone()
+            };
+            assert_eq!(names(q).await, ["wide.png"]);
+            let q = SearchQuery {
+                date_added_after: Some(now - chrono::Duration::days(30)),
+                ..base.clone()
+            };
+            assert_eq!(names(q).await, ["small.png", "wide.png"]);
+            let q = SearchQuery {
+                date_added_before: Some(now - chrono::Duration::days(2)),
+                ..base.clone()
+            };
+            assert_eq!(names(q).await, ["wide.png", "big.png"]);
+            let q = SearchQuery {
+                sort_by: Some(SortBy::Filename),
+                descending: Some(false),
+                ..base.clone()
+            };
+            assert_eq!(names(q).await, ["big.png", "small.png", "wide.png"]);
+
+            // Wider than 3000px, added in the last 30 days, largest first
+            let q = SearchQuery {
+                min_width: Some(3000),
+                date_added_after: Some(now - chrono::Duration::days(30)),
+                sort_by: Some(SortBy::Area),
+                ..base.clone()
+            };
+            assert_eq!(names(q).await, ["wide.png"]);
+            let q = SearchQuery {
+                sort_by: Some(SortBy::Area),
+                ..base
+            };
+            assert_eq!(names(q).await, ["big.png", "wide.png", "small.png"]);
+        }
+    }
+
+    /// Runs against in-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_tag_modes() {
+        for db in test_databases().await {
+            let group = format!("tagmode-{}", uuid::Uuid::new_v4());
+            let images: [(&str, &[&str]); 4] = [
+                ("both.png", &["beach", "sunset"]),
+                ("beach.png", &["beach"]),
+                ("sunset.png", &["sunset", "people"]),
+                ("all.png", &["beach", "sunset", "people"]),
+            ];
+            for (name, tags) in images {
+                let tags = tags.iter().map(|t| t.to_string()).collect();
+                db.add_image(
+                    &format!("/{}/{}", group, name),
+                    name,
+                    None,
+                    None,
+                    Some(&group),
+                    None,
+                    Some(tags),
+                )
+                .await
+                .unwrap();
+            }
+
+            let search = |tags: &[&str], mode: TagMode, exclude: &[&str]| {
+                let query = SearchQuery {
+                    group_name: Some(group.clone()),
+                    tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                    tag_mode: Some(mode),
+                    exclude_tags: Some(exclude.iter().map(|t| t.to_string()).collect()),
+                    sort_by: Some(SortBy::Filename),
+                    descending: Some(false),
+                    ..Default::default()
+                };
+                let db = &db;
+                async move {
+                    let page = db.search_images(query).await.unwrap();
+                    let names: Vec<String> = page.images.into_iter().map(|i| i.filename).collect();
+                    assert_eq!(names.len() as i64, page.total_count);
+                    names
+                }
+            };
+
+            let any = search(&["beach", "sunset"], TagMode::Any, &[]).await;
+            assert_eq!(any, ["all.png", "beach.png", "both.png", "sunset.png"]);
+            let all = search(&["beach", "sunset", "beach"], TagMode::All, &[]).await;
+            assert_eq!(all, ["all.png", "both.png"]);
+            let all_but = search(&["beach", "sunset"], TagMode::All, &["people"]).await;
+            assert_eq!(all_but, ["both.png"]);
+            let any_but = search(&["sunset"], TagMode::Any, &["beach"]).await;
+            assert_eq!(any_but, ["sunset.png"]);
+            let only_exclude = search(&[], TagMode::Any, &["sunset"]).await;
+            assert_eq!(only_exclude, ["beach.png"]);
+        }
+    }
+
+    fn new_image(path: &str) -> NewImage {
+        NewImage {
+            file_path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_new_images() {
+        let mut errors = Vec::new();
+        let valid = validate_new_images(
+            vec![
+                new_image("/a/one.png"),
+                new_image(""),
+                new_image("/a/one.png"),
+                new_image("/"),
+            ],
+            &mut errors,
+        );
+        assert_eq!(valid.len(), 1);
+        assert_eq!(valid[0].1.filename.as_deref(), Some("one.png"));
+        let failed: Vec<usize> = errors.iter().map(|e| e.index).collect();
+        assert_eq!(failed, [1, 2, 3]);
+    }
+
+    /// Image ids of a collection's first page, in collection order
+    async fn collection_ids(db: &Db, name: &str) -> Vec<i32> {
+        db.get_collection_images(name, None, None)
+            .await
+            .unwrap()
+            .images
+            .iter()
+            .map(|image| image.id)
+            .collect()
+    }
+
+    /// Inserts a row the way an older version would have, without path keys
+    async fn insert_legacy_row(db: &Db, path: &str, modified: DateTime<Utc>) -> i32 {
+        let sql = "INSERT INTO images (file_path, filename, date_added, date_modified) \
+                   VALUES ($1, 'a.jpg', $2, $2) RETURNING id";
+        match db {
+            Db::Postgres(store) => sqlx::query_scalar(sql)
+                .bind(path)
+                .bind(modified)
+                .fetch_one(store.pool())
+                .await
+                .unwrap(),
+            Db::Sqlite(store) => sqlx::query_scalar(sql)
+                .bind(path)
+                .bind(modified)
+                .fetch_one(store.pool())
+                .await
+                .unwrap(),
+        }
+    }
+
+    async fn normalize_path_keys(db: &Db) -> u64 {
+        match db {
+            Db::Postgres(store) => store.normalize_path_keys().await.unwrap(),
+            Db::Sqlite(store) => store.normalize_path_keys().await.unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_paths_are_merged() {
+        for db in test_databases().await {
+            let dir = format!("/dedupe-{}", uuid::Uuid::new_v4());
+            let days_ago = |days| Utc::now() - chrono::Duration::days(days);
+            let old = insert_legacy_row(&db, &format!("{}/a.jpg", dir), days_ago(3)).await;
+            let newest = insert_legacy_row(&db, &format!("{}//a.jpg", dir), days_ago(1)).await;
+            let middle = insert_legacy_row(&db, &format!("{}/x/../a.jpg", dir), days_ago(2)).await;
+            db.add_tags_to_images(&[old], &["old".to_string()])
+                .await
+                .unwrap();
+            db.add_tags_to_images(&[middle, newest], &["shared".to_string()])
+                .await
+                .unwrap();
+
+            assert_eq!(normalize_path_keys(&db).await, 2);
+            let remaining = db
+                .search_images(SearchQuery {
+                    filename_pattern: Some("A.JPG".to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .images
+                .into_iter()
+                .filter(|image| image.file_path.starts_with(&dir))
+                .collect::<Vec<_>>();
+            assert_eq!(remaining.len(), 1);
+            assert_eq!(remaining[0].id, newest);
+            assert_eq!(remaining[0].tags, ["old", "shared"]);
+
+            // Later spellings of the same path update the surviving row
+            let id = db
+                .add_image(
+                    &format!("{}/./a.jpg", dir),
+                    "a.jpg",
+                    Some(10),
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(id, newest);
+            assert_eq!(normalize_path_keys(&db).await, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relink_images_rewrites_prefix() {
+        for db in test_databases().await {
+            let root = format!("/relink-{}", uuid::Uuid::new_v4());
+            let mut ids = Vec::new();
+            for path in [
+                "old/a.jpg",
+                "old/sub/b.jpg",
+                "old_100%/c.jpg",
+                "older/d.jpg",
+            ] {
+                let path = format!("{}/{}", root, path);
+                let filename = path.rsplit('/').next().unwrap().to_string();
+                ids.push(
+                    db.add_image(&path, &filename, None, None, None, None, None)
+                        .await
+                        .unwrap(),
+                );
+            }
+
+            let moved = db
+                .relink_images(&format!("{}/old/", root), &format!("{}/new", root))
+                .await
+                .unwrap();
+            assert_eq!(moved, 2);
+
+            // A prefix naming a single file renames it
+            let moved = db
+                .relink_images(
+                    &format!("{}/old_100%/c.jpg", root),
+                    &format!("{}/renamed/C.jpg", root),
+                )
+                .await
+                .unwrap();
+            assert_eq!(moved, 1);
+
+            let mut records = db.get_path_records(&ids).await.unwrap();
+            records.sort_by_key(|r| r.id);
+            let paths: Vec<String> = records
+                .iter()
+                .map(|r| r.file_path.trim_start_matches(&root).to_string())
+                .collect();
+            assert_eq!(
+                paths,
+                [
+                    "/new/a.jpg",
+                    "/new/sub/b.jpg",
+                    "/renamed/C.jpg",
+                    "/older/d.jpg"
+                ]
+            );
+
+            let renamed = db
+                .search_images(SearchQuery {
+                    filename_pattern: Some("c.jpg".to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .images;
+            assert!(renamed
+                .iter()
+                .any(|image| image.id == ids[2] && image.filename == "C.jpg"));
+            assert_eq!(
+                db.relink_images(&format!("{}/missing", root), "/elsewhere")
+                    .await
+                    .unwrap(),
+                0
+            );
+        }
+    }
+
+    /// Runs against in-memory SQLite, plus PostgreSQL when TEST_DATABASE_URL is set
+    #[tokio::test]
+    async fn test_batch_add_images() {
+        for db in test_databases().await {
+            let dir = format!("/batch-{}", uuid::Uuid::new_v4());
+            let existing = db
+                .add_image(
+                    &format!("{}/old.png", dir),
+                    "old.png",
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+
+            let mut images: Vec<NewImage> = (0..5)
+                .map(|i| NewImage {
+                    group_name: Some(dir.clone()),
+                    subgroup_name: Some("sub".to_string()),
+                    ..new_image(&format!("{}/{}.png", dir, i))
+                })
+                .collect();
+            images.push(new_image(&format!("{}/old.png", dir)));
+            images.push(new_image(""));
+            if let Db::Postgres(_) = db {
+                // PostgreSQL rejects NUL bytes, failing the chunk and forcing the row-by-row retry
+                images.push(new_image(&format!("{}/bad\0.png", dir)));
+            }
+
+            let result = db.batch_add_images(images).await.unwrap();
+            assert_eq!(result.inserted.len(), 5);
+            assert_eq!(result.updated, [existing]);
+            assert_eq!(result.errors[0].index, 6);
+            if let Db::Postgres(_) = db {
+                assert_eq!(result.errors.len(), 2);
+                assert_eq!(result.errors[1].index, 7);
+            } else {
+                assert_eq!(result.errors.len(), 1);
+            }
+
+            let query = SearchQuery {
+                group_name: Some(dir.clone()),
+                ..Default::default()
+            };
+            assert_eq!(db.search_images(query).await.unwrap().total_count, 5);
+            assert_eq!(db.get_subgroups_for_group(&dir).await.unwrap(), ["sub"]);
+        }
+    }
+
+    #[test]
+    fn test_page_bounds() {
+        assert_eq!(query(None, None, None).page_bounds(), (100, 0));
+        assert_eq!(query(Some(50), None, Some(3)).page_bounds(), (50, 150));
+        assert_eq!(query(Some(50), Some(7), Some(3)).page_bounds(), (50, 7));
+        assert_eq!(query(Some(5000), None, None).page_bounds(), (1000, 0));
+        assert_eq!(query(Some(0), Some(-4), None).page_bounds(), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_detailed_statistics() {
+        // Exact totals need a database of our own, so SQLite only
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let now = Utc::now();
+
+        // (path, size, days ago, group, subgroup, tags)
+        let rows: [(_, _, _, _, _, &[&str]); 4] = [
+            (
+                "/stats/a.JPG",
+                Some(100),
+                0,
+                Some("Trips"),
+                Some("Rome"),
+                &["sky", "sea"],
+            ),
+            ("/stats/b.jpg", Some(200), 60, Some("Trips"), None, &["sky"]),
+            ("/stats/c.png", Some(50), 400, None, None, &[]),
+            ("/stats/README", None, 0, None, None, &["sky"]),
+        ];
+        for (path, size, days, group, subgroup, tags) in rows {
+            let name = path.rsplit('/').next().unwrap();
+            let tags = tags.iter().map(|t| t.to_string()).collect();
+            let id = db
+                .add_image(path, name, None, None, group, subgroup, Some(tags))
+                .await
+                .unwrap();
+            if let Some(size) = size {
+                set_size_and_date(&db, id, size, now - chrono::Duration::days(days)).await;
+            }
+        }
+
+        let stats = db.get_detailed_statistics(1).await.unwrap();
+        assert_eq!(stats.totals.total_images, 4);
+        assert_eq!(stats.total_file_size, 350);
+        assert_eq!(stats.untagged_images, 1);
+        assert_eq!(stats.ungrouped_images, 2);
+
+        assert_eq!(stats.groups.len(), 1);
+        assert_eq!(stats.groups[0].name, "Trips");
+        assert_eq!(stats.groups[0].image_count, 2);
+        let rome: Vec<(&str, i64)> = stats.groups[0]
+            .subgroups
+            .iter()
+            .map(|s| (s.name.as_str(), s.image_count))
+            .collect();
+        assert_eq!(rome, [("Rome", 1)]);
+
+        let extensions: Vec<(&str, i64, i64)> = stats
+            .extensions
+            .iter()
+            .map(|e| (e.extension.as_str(), e.image_count, e.total_file_size))
+            .collect();
+        assert_eq!(extensions, [("jpg", 2, 300), ("", 1, 0), ("png", 1, 50)]);
+
+        assert_eq!(
+            stats.top_tags,
+            [TagUsage {
+                name: "sky".into(),
+                image_count: 3
+            }]
+        );
+
+        // c.png is more than a year old and falls outside the window
+        let months = &stats.added_per_month;
+        assert_eq!(months.len(), 12);
+        assert_eq!(months[11].month, now.format("%Y-%m").to_string());
+        assert_eq!(months[11].image_count, 2);
+        let two_months_ago = (now - chrono::Duration::days(60))
+            .format("%Y-%m")
+            .to_string();
+        let earlier = months.iter().find(|m| m.month == two_months_ago).unwrap();
+        assert_eq!(earlier.image_count, 1);
+        assert_eq!(months.iter().map(|m| m.image_count).sum::<i64>(), 3);
+    }
 
-        Ok(true)
+    #[test]
+    fn test_recent_months_cross_year_boundary() {
+        let now = "2026-02-10T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let months: Vec<String> = recent_months(now, 4)
+            .iter()
+            .map(|d| d.format("%Y-%m").to_string())
+            .collect();
+        assert_eq!(months, ["2025-11", "2025-12", "2026-01", "2026-02"]);
     }
 }
@@ -0,0 +1,110 @@
+//! Change log of image writes, and undo
+
+use super::{Db, ExportedImage, MAX_PAGE_SIZE};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// Kind of write recorded in `audit_log`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Add,
+    Update,
+    Delete,
+    /// Tags added, removed or replaced
+    Tag,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Add => "add",
+            ChangeKind::Update => "update",
+            ChangeKind::Delete => "delete",
+            ChangeKind::Tag => "tag",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        Ok(match value {
+            "add" => ChangeKind::Add,
+            "update" => ChangeKind::Update,
+            "delete" => ChangeKind::Delete,
+            "tag" => ChangeKind::Tag,
+            other => anyhow::bail!("Unknown change kind '{}'", other),
+        })
+    }
+}
+
+/// One `audit_log` entry. `image` is the image as it was before the change,
+/// or as it was created for adds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeLogEntry {
+    pub id: i64,
+    pub operation: ChangeKind,
+    pub image_id: i32,
+    pub image: ExportedImage,
+    pub created_at: DateTime<Utc>,
+    pub undone_at: Option<DateTime<Utc>>,
+}
+
+/// `audit_log` row with the payload still serialized
+#[derive(FromRow)]
+pub struct AuditLogRow {
+    id: i64,
+    operation: String,
+    image_id: i32,
+    payload: String,
+    created_at: DateTime<Utc>,
+    undone_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<AuditLogRow> for ChangeLogEntry {
+    type Error = anyhow::Error;
+
+    fn try_from(row: AuditLogRow) -> Result<Self> {
+        let image = serde_json::from_str(&row.payload)
+            .with_context(|| format!("Change {} has an unreadable payload", row.id))?;
+        Ok(ChangeLogEntry {
+            id: row.id,
+            operation: ChangeKind::parse(&row.operation)?,
+            image_id: row.image_id,
+            image,
+            created_at: row.created_at,
+            undone_at: row.undone_at,
+        })
+    }
+}
+
+impl Db {
+    /// The newest `limit` changes, newest first
+    pub async fn get_recent_changes(&self, limit: i64) -> Result<Vec<ChangeLogEntry>> {
+        dispatch!(self.get_recent_changes(limit.clamp(1, MAX_PAGE_SIZE)))?
+            .into_iter()
+            .map(ChangeLogEntry::try_from)
+            .collect()
+    }
+
+    /// Reverse a logged change: an add deletes the image, a delete restores it
+    /// with its id and tags, and an update or tag edit puts back the logged
+    /// columns and tags. Changes to one image are undone newest first.
+    /// Embeddings and thumbnails of deleted images are not restored.
+    pub async fn undo_change(&self, log_id: i64) -> Result<ChangeLogEntry> {
+        let entry: ChangeLogEntry = dispatch!(self.get_change(log_id))?
+            .with_context(|| format!("Change {} not found", log_id))?
+            .try_into()?;
+
+        if entry.operation != ChangeKind::Add {
+            if let Some(group) = &entry.image.group_name {
+                dispatch!(self.ensure_groups(std::slice::from_ref(group)))?;
+                if let Some(subgroup) = &entry.image.subgroup_name {
+                    dispatch!(self.ensure_subgroups(&[(group.clone(), subgroup.clone())]))?;
+                }
+            }
+        }
+
+        dispatch!(self.undo_change(&entry))
+    }
+}
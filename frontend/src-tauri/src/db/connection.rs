@@ -0,0 +1,207 @@
+//! Pool settings, connection retries and the connectivity heartbeat
+
+use super::Db;
+use anyhow::Result;
+use std::future::Future;
+use std::time::Duration;
+
+/// Pool and retry settings, read from the environment by `from_env`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbConfig {
+    /// Largest number of pooled PostgreSQL connections (`DATABASE_MAX_CONNECTIONS`)
+    pub max_connections: u32,
+    /// How long one connection attempt may take (`DATABASE_CONNECT_TIMEOUT_SECS`)
+    pub connect_timeout: Duration,
+    /// Connection attempts before giving up, at least 1 (`DATABASE_CONNECT_ATTEMPTS`)
+    pub connect_attempts: u32,
+    /// Wait after the first failed attempt; doubles after each further failure
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts
+    pub max_backoff: Duration,
+    /// Interval of the connectivity check (`DATABASE_HEARTBEAT_SECS`)
+    pub heartbeat_interval: Duration,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        DbConfig {
+            max_connections: 5,
+            connect_timeout: Duration::from_secs(10),
+            connect_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            heartbeat_interval: Duration::from_secs(15),
+        }
+    }
+}
+
+impl DbConfig {
+    /// Defaults overridden by any of the `DATABASE_*` variables that parse
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            let value = std::env::var(name).ok()?;
+            let parsed = value.trim().parse().ok();
+            if parsed.is_none() {
+                log::warn!("Ignoring invalid {}={:?}", name, value);
+            }
+            parsed
+        }
+
+        let defaults = DbConfig::default();
+        DbConfig {
+            max_connections: var("DATABASE_MAX_CONNECTIONS")
+                .filter(|&n| n > 0)
+                .unwrap_or(defaults.max_connections),
+            connect_timeout: var("DATABASE_CONNECT_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.connect_timeout),
+            connect_attempts: var("DATABASE_CONNECT_ATTEMPTS").unwrap_or(defaults.connect_attempts),
+            heartbeat_interval: var("DATABASE_HEARTBEAT_SECS")
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.heartbeat_interval),
+            ..defaults
+        }
+    }
+
+    /// Wait before retrying after `failures` failed attempts
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Runs `attempt` until it succeeds or `config.connect_attempts` attempts
+/// have failed, sleeping with exponential backoff in between. Returns the
+/// last error.
+pub async fn retry_with_backoff<T, F, Fut>(config: &DbConfig, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let attempts = config.connect_attempts.max(1);
+    let mut failures = 0;
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                failures += 1;
+                if failures >= attempts {
+                    return Err(e);
+                }
+                let delay = config.backoff(failures);
+                log::warn!(
+                    "Database connection attempt {} of {} failed ({:#}); retrying in {:?}",
+                    failures,
+                    attempts,
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+impl Db {
+    /// Wait, retrying with backoff, until the database answers again. Pools
+    /// drop broken connections as they're acquired, so once the server is
+    /// back a fresh connection is all that's needed.
+    pub async fn reconnect(&self, config: &DbConfig) -> Result<()> {
+        retry_with_backoff(config, || async {
+            self.test_connection().await.map(|_| ())
+        })
+        .await
+    }
+
+    /// Check connectivity every `interval`, calling `on_change` with the new
+    /// state (and the error, when lost) whenever it flips. Runs until the
+    /// returned future is dropped.
+    pub async fn run_heartbeat<F>(&self, interval: Duration, mut on_change: F)
+    where
+        F: FnMut(bool, Option<String>),
+    {
+        let mut connected = true;
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let result = self.test_connection().await;
+            if result.is_ok() != connected {
+                connected = result.is_ok();
+                on_change(connected, result.err().map(|e| format!("{:#}", e)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Instant;
+
+    fn fast_config(attempts: u32) -> DbConfig {
+        DbConfig {
+            connect_timeout: Duration::from_millis(300),
+            connect_attempts: attempts,
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(50),
+            ..DbConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        let config = DbConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            ..DbConfig::default()
+        };
+        let delays: Vec<u128> = (1..=5).map(|n| config.backoff(n).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 350, 350, 350]);
+        assert_eq!(config.backoff(u32::MAX).as_millis(), 350);
+    }
+
+    #[tokio::test]
+    async fn test_retry_succeeds_after_failures() {
+        let calls = AtomicU32::new(0);
+        let value = retry_with_backoff(&fast_config(5), || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => anyhow::bail!("connection refused"),
+                n => Ok(n),
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(value, 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_returns_last_error() {
+        let calls = AtomicU32::new(0);
+        let started = Instant::now();
+        let err = retry_with_backoff(&fast_config(3), || async {
+            let n = calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(anyhow::anyhow!("attempt {} failed", n))
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "attempt 2 failed");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        // Two waits: 20ms then 40ms
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[tokio::test]
+    async fn test_connect_gives_up_on_unreachable_server() {
+        // Nothing listens on port 1; each attempt is refused straight away
+        let started = Instant::now();
+        let result = Db::connect("postgres://postgres@127.0.0.1:1/none", &fast_config(3)).await;
+        assert!(result.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+}
@@ -0,0 +1,624 @@
+//! Portable export and import of the image library, for backups and for
+//! moving between the SQLite and PostgreSQL backends
+
+use super::{CaptureMetadata, Db, MediaType};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::io::{BufRead, Write};
+
+/// Records per database page on export and per transaction on import;
+/// progress is reported after each
+pub const TRANSFER_CHUNK_SIZE: usize = 500;
+
+/// Row-level problems kept in an `ImportReport`; later ones are only counted
+const MAX_REPORTED_ISSUES: usize = 1000;
+
+/// Separator between tag names in the CSV `tags` column
+const CSV_TAG_SEPARATOR: char = '|';
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One JSON object per line; lossless
+    #[default]
+    JsonLines,
+    /// Flat rows for spreadsheets, with tags joined by `|`
+    Csv,
+}
+
+/// What `import_library` does with a record whose `file_path` already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing image untouched
+    #[default]
+    Skip,
+    /// Replace the existing image's columns and tags with the imported ones
+    Overwrite,
+}
+
+/// One image as written by `export_library`. Database ids and embeddings are
+/// left out; images are matched by `file_path` on import.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ExportedImage {
+    pub file_path: String,
+    /// Derived from `file_path` on import when empty
+    #[serde(default)]
+    pub filename: String,
+    pub file_size: Option<i64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub group_name: Option<String>,
+    pub subgroup_name: Option<String>,
+    #[serde(default = "Utc::now")]
+    pub date_added: DateTime<Utc>,
+    pub date_modified: Option<DateTime<Utc>>,
+    pub rating: Option<i16>,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default)]
+    pub view_count: i32,
+    pub last_viewed: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub capture: CaptureMetadata,
+    pub content_hash: Option<String>,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub media_type: MediaType,
+    #[serde(default)]
+    pub duration_secs: Option<f64>,
+    #[serde(default)]
+    pub video_codec: Option<String>,
+    #[serde(default)]
+    #[sqlx(json)]
+    pub tags: Vec<String>,
+}
+
+/// `ExportedImage` flattened into CSV columns
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvImage {
+    file_path: String,
+    #[serde(default)]
+    filename: String,
+    file_size: Option<i64>,
+    width: Option<i32>,
+    height: Option<i32>,
+    group_name: Option<String>,
+    subgroup_name: Option<String>,
+    date_added: Option<DateTime<Utc>>,
+    date_modified: Option<DateTime<Utc>>,
+    rating: Option<i16>,
+    #[serde(default)]
+    favorite: bool,
+    #[serde(default)]
+    view_count: i32,
+    last_viewed: Option<DateTime<Utc>>,
+    camera_make: Option<String>,
+    camera_model: Option<String>,
+    lens: Option<String>,
+    focal_length: Option<f64>,
+    iso: Option<i32>,
+    aperture: Option<f64>,
+    shutter: Option<String>,
+    date_taken: Option<DateTime<Utc>>,
+    gps_lat: Option<f64>,
+    gps_lon: Option<f64>,
+    content_hash: Option<String>,
+    #[serde(default)]
+    source_url: Option<String>,
+    #[serde(default)]
+    media_type: MediaType,
+    #[serde(default)]
+    duration_secs: Option<f64>,
+    #[serde(default)]
+    video_codec: Option<String>,
+    #[serde(default)]
+    tags: String,
+}
+
+impl From<ExportedImage> for CsvImage {
+    fn from(image: ExportedImage) -> Self {
+        let capture = image.capture;
+        CsvImage {
+            file_path: image.file_path,
+            filename: image.filename,
+            file_size: image.file_size,
+            width: image.width,
+            height: image.height,
+            group_name: image.group_name,
+            subgroup_name: image.subgroup_name,
+            date_added: Some(image.date_added),
+            date_modified: image.date_modified,
+            rating: image.rating,
+            favorite: image.favorite,
+            view_count: image.view_count,
+            last_viewed: image.last_viewed,
+            camera_make: capture.camera_make,
+            camera_model: capture.camera_model,
+            lens: capture.lens,
+            focal_length: capture.focal_length,
+            iso: capture.iso,
+            aperture: capture.aperture,
+            shutter: capture.shutter,
+            date_taken: capture.date_taken,
+            gps_lat: capture.gps_lat,
+            gps_lon: capture.gps_lon,
+            content_hash: image.content_hash,
+            source_url: image.source_url,
+            media_type: image.media_type,
+            duration_secs: image.duration_secs,
+            video_codec: image.video_codec,
+            tags: image.tags.join(&CSV_TAG_SEPARATOR.to_string()),
+        }
+    }
+}
+
+impl From<CsvImage> for ExportedImage {
+    fn from(row: CsvImage) -> Self {
+        ExportedImage {
+            file_path: row.file_path,
+            filename: row.filename,
+            file_size: row.file_size,
+            width: row.width,
+            height: row.height,
+            group_name: row.group_name,
+            subgroup_name: row.subgroup_name,
+            date_added: row.date_added.unwrap_or_else(Utc::now),
+            date_modified: row.date_modified,
+            rating: row.rating,
+            favorite: row.favorite,
+            view_count: row.view_count,
+            last_viewed: row.last_viewed,
+            capture: CaptureMetadata {
+                camera_make: row.camera_make,
+                camera_model: row.camera_model,
+                lens: row.lens,
+                focal_length: row.focal_length,
+                iso: row.iso,
+                aperture: row.aperture,
+                shutter: row.shutter,
+                date_taken: row.date_taken,
+                gps_lat: row.gps_lat,
+                gps_lon: row.gps_lon,
+            },
+            content_hash: row.content_hash,
+            source_url: row.source_url,
+            media_type: row.media_type,
+            duration_secs: row.duration_secs,
+            video_codec: row.video_codec,
+            tags: row
+                .tags
+                .split(CSV_TAG_SEPARATOR)
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// Outcome of `import_library`. `conflicts` lists the paths that already
+/// existed (skipped or overwritten, depending on the policy).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub inserted: u64,
+    pub updated: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub conflicts: Vec<String>,
+    pub errors: Vec<ImportError>,
+}
+
+/// A record that could not be parsed or written; `line` is 1-based
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportError {
+    pub line: u64,
+    pub file_path: Option<String>,
+    pub error: String,
+}
+
+impl ImportReport {
+    fn fail(&mut self, line: u64, file_path: Option<String>, error: String) {
+        self.failed += 1;
+        if self.errors.len() < MAX_REPORTED_ISSUES {
+            self.errors.push(ImportError {
+                line,
+                file_path,
+                error,
+            });
+        }
+    }
+
+    /// Counts a backend outcome: `Some(true)` inserted, `Some(false)`
+    /// overwritten, `None` skipped
+    fn record(&mut self, file_path: &str, outcome: Option<bool>) {
+        match outcome {
+            Some(true) => self.inserted += 1,
+            Some(false) => self.updated += 1,
+            None => self.skipped += 1,
+        }
+        if outcome != Some(true) && self.conflicts.len() < MAX_REPORTED_ISSUES {
+            self.conflicts.push(file_path.to_string());
+        }
+    }
+}
+
+/// Parses `reader` into records paired with their line number
+fn read_records<'a, R: BufRead + Send + 'a>(
+    reader: R,
+    format: ExportFormat,
+) -> Result<Box<dyn Iterator<Item = (u64, Result<ExportedImage>)> + Send + 'a>> {
+    match format {
+        ExportFormat::JsonLines => Ok(Box::new(
+            reader
+                .lines()
+                .enumerate()
+                .map(|(index, line)| (index as u64 + 1, line))
+                .filter(|(_, line)| !matches!(line, Ok(text) if text.trim().is_empty()))
+                .map(|(line, text)| {
+                    let record = text
+                        .map_err(anyhow::Error::from)
+                        .and_then(|text| Ok(serde_json::from_str(&text)?));
+                    (line, record)
+                }),
+        )),
+        ExportFormat::Csv => {
+            let mut csv = csv::Reader::from_reader(reader);
+            let headers = csv.headers().context("Failed to read CSV header")?.clone();
+            Ok(Box::new(csv.into_records().map(move |row| match row {
+                Ok(row) => {
+                    let line = row.position().map_or(0, |p| p.line());
+                    let record = row
+                        .deserialize::<CsvImage>(Some(&headers))
+                        .map(ExportedImage::from)
+                        .map_err(anyhow::Error::from);
+                    (line, record)
+                }
+                Err(e) => {
+                    let line = e.position().map_or(0, |p| p.line());
+                    (line, Err(e.into()))
+                }
+            })))
+        }
+    }
+}
+
+/// Destination of `export_library` in either format
+enum ExportSink<W: Write> {
+    JsonLines(std::io::BufWriter<W>),
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> ExportSink<W> {
+    fn new(writer: W, format: ExportFormat) -> Self {
+        match format {
+            ExportFormat::JsonLines => ExportSink::JsonLines(std::io::BufWriter::new(writer)),
+            ExportFormat::Csv => ExportSink::Csv(Box::new(csv::Writer::from_writer(writer))),
+        }
+    }
+
+    fn write(&mut self, image: ExportedImage) -> Result<()> {
+        match self {
+            ExportSink::JsonLines(out) => {
+                serde_json::to_writer(&mut *out, &image)?;
+                out.write_all(b"\n")?;
+            }
+            ExportSink::Csv(out) => out.serialize(CsvImage::from(image))?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            ExportSink::JsonLines(mut out) => out.flush()?,
+            ExportSink::Csv(mut out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Rejects records that can't be imported, normalizes the path and fills in
+/// a missing filename
+fn validate_import(mut image: ExportedImage) -> Result<ExportedImage> {
+    if image.file_path.trim().is_empty() {
+        anyhow::bail!("file_path is empty");
+    }
+    image.file_path = crate::paths::normalize_path(&image.file_path);
+    if let Some(rating) = image.rating.filter(|r| !(1..=5).contains(r)) {
+        anyhow::bail!("Rating must be between 1 and 5, got {}", rating);
+    }
+    if image.filename.is_empty() {
+        image.filename = std::path::Path::new(&image.file_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("file_path has no file name")?
+            .to_string();
+    }
+    Ok(image)
+}
+
+impl Db {
+    /// Write every image, with its group, subgroup, tags and capture metadata,
+    /// to `writer` in path order. `on_progress` receives the running count
+    /// after each chunk. Returns the number of images written.
+    pub async fn export_library<W: Write + Send>(
+        &self,
+        writer: W,
+        format: ExportFormat,
+        mut on_progress: impl FnMut(u64) + Send,
+    ) -> Result<u64> {
+        let mut sink = ExportSink::new(writer, format);
+        let mut after: Option<String> = None;
+        let mut written = 0;
+
+        loop {
+            let page = dispatch!(self.export_page(after.as_deref(), TRANSFER_CHUNK_SIZE as i64))?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(last.file_path.clone());
+            let full = page.len() == TRANSFER_CHUNK_SIZE;
+
+            written += page.len() as u64;
+            for image in page {
+                sink.write(image)?;
+            }
+            on_progress(written);
+
+            if !full {
+                break;
+            }
+        }
+
+        sink.finish()?;
+        Ok(written)
+    }
+
+    /// Recreate images from an `export_library` file, creating groups,
+    /// subgroups and tags as needed and matching existing images by
+    /// `file_path`. Bad records are reported rather than aborting the import.
+    /// `on_progress` receives the number of records read after each chunk.
+    pub async fn import_library<R: BufRead + Send>(
+        &self,
+        reader: R,
+        format: ExportFormat,
+        policy: ConflictPolicy,
+        mut on_progress: impl FnMut(u64) + Send,
+    ) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let mut records = read_records(reader, format)?;
+        let mut read = 0;
+
+        loop {
+            let mut chunk = Vec::with_capacity(TRANSFER_CHUNK_SIZE);
+            for (line, record) in records.by_ref() {
+                read += 1;
+                match record.and_then(validate_import) {
+                    Ok(image) => chunk.push((line, image)),
+                    Err(e) => report.fail(line, None, format!("{:#}", e)),
+                }
+                if chunk.len() == TRANSFER_CHUNK_SIZE {
+                    break;
+                }
+            }
+            if chunk.is_empty() {
+                break;
+            }
+
+            self.import_chunk(&chunk, policy, &mut report).await;
+            on_progress(read);
+        }
+
+        Ok(report)
+    }
+
+    /// Writes one chunk in a transaction, retrying row by row if it fails so
+    /// one bad record doesn't sink the rest
+    async fn import_chunk(
+        &self,
+        chunk: &[(u64, ExportedImage)],
+        policy: ConflictPolicy,
+        report: &mut ImportReport,
+    ) {
+        let images: Vec<ExportedImage> = chunk.iter().map(|(_, image)| image.clone()).collect();
+        match self.write_import_chunk(&images, policy).await {
+            Ok(outcomes) => {
+                for (image, outcome) in images.iter().zip(outcomes) {
+                    report.record(&image.file_path, outcome);
+                }
+            }
+            Err(e) => {
+                log::warn!("Import chunk failed ({}), retrying row by row", e);
+                for (line, image) in chunk {
+                    match self
+                        .write_import_chunk(std::slice::from_ref(image), policy)
+                        .await
+                    {
+                        Ok(outcomes) => report.record(&image.file_path, outcomes[0]),
+                        Err(e) => report.fail(*line, Some(image.file_path.clone()), e.to_string()),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn write_import_chunk(
+        &self,
+        images: &[ExportedImage],
+        policy: ConflictPolicy,
+    ) -> Result<Vec<Option<bool>>> {
+        let mut groups: Vec<String> = images
+            .iter()
+            .filter_map(|image| image.group_name.clone())
+            .collect();
+        groups.sort();
+        groups.dedup();
+        let mut subgroups: Vec<(String, String)> = images
+            .iter()
+            .filter_map(|image| Some((image.group_name.clone()?, image.subgroup_name.clone()?)))
+            .collect();
+        subgroups.sort();
+        subgroups.dedup();
+
+        dispatch!(self.ensure_groups(&groups))?;
+        dispatch!(self.ensure_subgroups(&subgroups))?;
+        dispatch!(self.import_images_chunk(images, policy == ConflictPolicy::Overwrite))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{NewImage, SearchQuery};
+
+    async fn seeded_library() -> Db {
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let added = db
+            .batch_add_images(vec![
+                NewImage {
+                    file_path: "/photos/trip/beach.jpg".to_string(),
+                    file_size: Some(2048),
+                    width: Some(640),
+                    height: Some(480),
+                    group_name: Some("Trips".to_string()),
+                    subgroup_name: Some("Coast".to_string()),
+                    capture: Some(CaptureMetadata {
+                        camera_model: Some("X-T4".to_string()),
+                        aperture: Some(2.8),
+                        shutter: Some("1/250".to_string()),
+                        gps_lat: Some(48.858333),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                NewImage {
+                    file_path: "/photos/misc/cat, sleeping.png".to_string(),
+                    capture: Some(CaptureMetadata::default()),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+        let (beach, cat) = (added.inserted[0], added.inserted[1]);
+
+        db.add_tags_to_images(&[beach], &["sea".to_string(), "summer".to_string()])
+            .await
+            .unwrap();
+        db.add_tags_to_images(&[cat], &["pets".to_string()])
+            .await
+            .unwrap();
+        db.set_rating(beach, Some(4)).await.unwrap();
+        db.toggle_favorite(cat).await.unwrap();
+        db.record_view(cat).await.unwrap();
+        db
+    }
+
+    async fn export_to_vec(db: &Db, format: ExportFormat) -> Vec<u8> {
+        let mut out = Vec::new();
+        db.export_library(&mut out, format, |_| {}).await.unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let source = seeded_library().await;
+        let expected = export_to_vec(&source, ExportFormat::JsonLines).await;
+
+        for format in [ExportFormat::JsonLines, ExportFormat::Csv] {
+            let exported = export_to_vec(&source, format).await;
+            let target = Db::new("sqlite::memory:").await.unwrap();
+            let mut progress = Vec::new();
+            let report = target
+                .import_library(exported.as_slice(), format, ConflictPolicy::Skip, |n| {
+                    progress.push(n)
+                })
+                .await
+                .unwrap();
+
+            assert_eq!(report.inserted, 2, "{:?}: {:?}", format, report);
+            assert_eq!(progress, [2]);
+            assert_eq!(
+                String::from_utf8(export_to_vec(&target, ExportFormat::JsonLines).await).unwrap(),
+                String::from_utf8(expected.clone()).unwrap(),
+                "{:?} round trip",
+                format
+            );
+            assert_eq!(target.get_all_groups().await.unwrap(), ["Trips"]);
+            assert_eq!(
+                target.get_subgroups_for_group("Trips").await.unwrap(),
+                ["Coast"]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_conflicts_and_bad_records() {
+        let db = seeded_library().await;
+        let exported = export_to_vec(&db, ExportFormat::JsonLines).await;
+
+        let report = db
+            .import_library(
+                exported.as_slice(),
+                ExportFormat::JsonLines,
+                ConflictPolicy::Skip,
+                |_| {},
+            )
+            .await
+            .unwrap();
+        assert_eq!((report.inserted, report.skipped), (0, 2));
+        assert_eq!(report.conflicts.len(), 2);
+
+        // Overwrite restores the exported tags and rating
+        let beach = db
+            .search_images(SearchQuery::default())
+            .await
+            .unwrap()
+            .images
+            .into_iter()
+            .find(|image| image.filename == "beach.jpg")
+            .unwrap();
+        db.set_image_tags(beach.id, vec!["edited".to_string()])
+            .await
+            .unwrap();
+        db.set_rating(beach.id, None).await.unwrap();
+        let report = db
+            .import_library(
+                exported.as_slice(),
+                ExportFormat::JsonLines,
+                ConflictPolicy::Overwrite,
+                |_| {},
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.updated, 2);
+        assert_eq!(
+            db.get_image_tags(beach.id).await.unwrap(),
+            ["sea", "summer"]
+        );
+        assert_eq!(export_to_vec(&db, ExportFormat::JsonLines).await, exported);
+
+        let input = concat!(
+            "{\"file_path\": \"/new/one.jpg\"}\n",
+            "\n",
+            "not json\n",
+            "{\"file_path\": \"/new/two.jpg\", \"rating\": 9}\n",
+        );
+        let report = db
+            .import_library(
+                input.as_bytes(),
+                ExportFormat::JsonLines,
+                ConflictPolicy::Skip,
+                |_| {},
+            )
+            .await
+            .unwrap();
+        assert_eq!((report.inserted, report.failed), (1, 2));
+        let lines: Vec<u64> = report.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [3, 4]);
+    }
+}
@@ -0,0 +1,2491 @@
+use super::{
+    check_embedding_dim, collection_order, escape_like, prefix_like_pattern, retry_with_backoff,
+    AuditLogRow, CaptureMetadata, ChangeKind, ChangeLogEntry, Collection, DatabaseStats, DbConfig,
+    ExportedImage, ExtensionStats, ImageRecord, LibraryTotals, NewImage, PathMove, PathRecord,
+    SavedSearchRow, SearchQuery, SearchResults, TagMode, TagUsage, Thumbnail, UpdateImage,
+    MAX_PAGE_SIZE,
+};
+use crate::paths;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use pgvector::Vector;
+use sqlx::postgres::{PgConnection, PgPool, PgPoolOptions};
+use sqlx::{Postgres, QueryBuilder};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// PostgreSQL storage, with pgvector similarity search
+pub struct PgStore {
+    pool: Arc<PgPool>,
+}
+
+/// Folds the `sources` groups into `target` (created if missing): subgroups
+/// move across, merging with same-named ones already under `target`, and
+/// images follow. Returns how many source groups were removed.
+async fn merge_groups_into(
+    conn: &mut PgConnection,
+    sources: &[String],
+    target: &str,
+) -> Result<u64> {
+    let sources: Vec<String> = sources.iter().filter(|s| *s != target).cloned().collect();
+    if sources.is_empty() {
+        return Ok(0);
+    }
+
+    let target_id = sqlx::query_scalar::<_, i32>(
+        r#"
+        INSERT INTO groups (name) VALUES ($1)
+        ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id
+        "#,
+    )
+    .bind(target)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO subgroups (name, group_id)
+        SELECT DISTINCT s.name, $1
+        FROM subgroups s
+        JOIN groups g ON g.id = s.group_id
+        WHERE g.name = ANY($2)
+        ON CONFLICT (name, group_id) DO NOTHING
+        "#,
+    )
+    .bind(target_id)
+    .bind(&sources)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("UPDATE images SET group_name = $1 WHERE group_name = ANY($2)")
+        .bind(target)
+        .bind(&sources)
+        .execute(&mut *conn)
+        .await?;
+
+    // Source subgroups go with their group via ON DELETE CASCADE
+    let result = sqlx::query("DELETE FROM groups WHERE name = ANY($1)")
+        .bind(&sources)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Subgroup counterpart of `merge_groups_into`, scoped to `group`
+async fn merge_subgroups_into(
+    conn: &mut PgConnection,
+    group: &str,
+    sources: &[String],
+    target: &str,
+) -> Result<u64> {
+    let group_id = sqlx::query_scalar::<_, i32>("SELECT id FROM groups WHERE name = $1")
+        .bind(group)
+        .fetch_optional(&mut *conn)
+        .await?
+        .with_context(|| format!("Group '{}' not found", group))?;
+
+    let sources: Vec<String> = sources.iter().filter(|s| *s != target).cloned().collect();
+    if sources.is_empty() {
+        return Ok(0);
+    }
+
+    sqlx::query(
+        "INSERT INTO subgroups (name, group_id) VALUES ($1, $2) ON CONFLICT (name, group_id) DO NOTHING",
+    )
+    .bind(target)
+    .bind(group_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "UPDATE images SET subgroup_name = $1 WHERE group_name = $2 AND subgroup_name = ANY($3)",
+    )
+    .bind(target)
+    .bind(group)
+    .bind(&sources)
+    .execute(&mut *conn)
+    .await?;
+
+    let result = sqlx::query("DELETE FROM subgroups WHERE group_id = $1 AND name = ANY($2)")
+        .bind(group_id)
+        .bind(&sources)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+impl PgStore {
+    /// Connect and run the PostgreSQL migrations
+    pub async fn new(database_url: &str, config: &DbConfig) -> Result<Self> {
+        let options = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(config.connect_timeout)
+            // Pings idle connections before handing them out, so the ones
+            // broken by a server restart are replaced instead of failing
+            .test_before_acquire(true);
+        let pool = retry_with_backoff(config, || {
+            let options = options.clone();
+            async move {
+                options
+                    .connect(database_url)
+                    .await
+                    .context("Failed to connect to PostgreSQL database")
+            }
+        })
+        .await?;
+
+        // Run migrations if available
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .context("Failed to run database migrations")?;
+
+        let store = Self {
+            pool: Arc::new(pool),
+        };
+        let merged = store
+            .normalize_path_keys()
+            .await
+            .context("Failed to normalize stored image paths")?;
+        if merged > 0 {
+            log::info!("Merged {} duplicate image rows by normalized path", merged);
+        }
+
+        Ok(store)
+    }
+
+    /// Fills in `path_key`/`filename_key` for rows that predate them (or were
+    /// written by an older version), merges rows that share a `path_key` into
+    /// the most recently modified one, keeping the union of their tags, and
+    /// makes sure the unique index on `path_key` exists. Returns the number of
+    /// duplicate rows removed.
+    pub async fn normalize_path_keys(&self) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        // Serializes app instances starting against the same database
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('images.path_key'))")
+            .execute(&mut *tx)
+            .await?;
+
+        let rows = sqlx::query_as::<_, (i32, String, String)>(
+            "SELECT id, file_path, filename FROM images WHERE path_key IS NULL OR filename_key IS NULL",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if !rows.is_empty() {
+            // New keys may collide until the duplicates are merged below
+            sqlx::query("DROP INDEX IF EXISTS idx_images_path_key")
+                .execute(&mut *tx)
+                .await?;
+
+            let ids: Vec<i32> = rows.iter().map(|(id, _, _)| *id).collect();
+            let path_keys: Vec<String> = rows
+                .iter()
+                .map(|(_, path, _)| paths::path_key(path))
+                .collect();
+            let filename_keys: Vec<String> = rows
+                .iter()
+                .map(|(_, _, filename)| paths::filename_key(filename))
+                .collect();
+
+            sqlx::query(
+                r#"
+                UPDATE images SET path_key = u.path_key, filename_key = u.filename_key
+                FROM unnest($1::int[], $2::text[], $3::text[]) AS u(id, path_key, filename_key)
+                WHERE images.id = u.id
+                "#,
+            )
+            .bind(&ids)
+            .bind(&path_keys)
+            .bind(&filename_keys)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // (duplicate, keeper) pairs; the keeper is the newest row for its key
+        let duplicates = sqlx::query_as::<_, (i32, i32)>(
+            r#"
+            WITH ranked AS (
+                SELECT id, FIRST_VALUE(id) OVER (
+                    PARTITION BY path_key
+                    ORDER BY COALESCE(date_modified, date_added) DESC, id DESC
+                ) AS keeper
+                FROM images
+                WHERE path_key IN (SELECT path_key FROM images GROUP BY path_key HAVING COUNT(*) > 1)
+            )
+            SELECT id, keeper FROM ranked WHERE id <> keeper
+            "#,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if !duplicates.is_empty() {
+            let (duplicate_ids, keeper_ids): (Vec<i32>, Vec<i32>) =
+                duplicates.iter().copied().unzip();
+
+            sqlx::query(
+                r#"
+                INSERT INTO image_tags (image_id, tag_id)
+                SELECT d.keeper, it.tag_id
+                FROM unnest($1::int[], $2::int[]) AS d(id, keeper)
+                JOIN image_tags it ON it.image_id = d.id
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(&duplicate_ids)
+            .bind(&keeper_ids)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query("DELETE FROM images WHERE id = ANY($1)")
+                .bind(&duplicate_ids)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_images_path_key ON images(path_key)")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(duplicates.len() as u64)
+    }
+
+    /// Initialize database and ensure pgvector extension exists
+    pub async fn init_schema(&self) -> Result<()> {
+        sqlx::query("CREATE EXTENSION IF NOT EXISTS vector")
+            .execute(&*self.pool)
+            .await
+            .context("Failed to create pgvector extension")?;
+
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+/// Appends the WHERE clause for `query`, shared by the page and count queries.
+/// Returns whether a WHERE clause was started, so callers can append with AND.
+fn push_search_filters(builder: &mut QueryBuilder<'_, Postgres>, query: &SearchQuery) -> bool {
+    let mut has_condition = false;
+    let mut next_condition = |builder: &mut QueryBuilder<'_, Postgres>| {
+        builder.push(if has_condition { " AND " } else { " WHERE " });
+        has_condition = true;
+    };
+
+    if let Some(group) = &query.group_name {
+        next_condition(builder);
+        builder.push("i.group_name ILIKE ");
+        builder.push_bind(format!("%{}%", group));
+    }
+
+    if let Some(subgroup) = &query.subgroup_name {
+        next_condition(builder);
+        builder.push("i.subgroup_name ILIKE ");
+        builder.push_bind(format!("%{}%", subgroup));
+    }
+
+    if let Some(pattern) = &query.filename_pattern {
+        next_condition(builder);
+        builder.push("i.filename_key LIKE ");
+        builder.push_bind(format!("%{}%", paths::filename_key(pattern)));
+    }
+
+    if let Some(term) = query
+        .filename_fts
+        .as_deref()
+        .filter(|t| !t.trim().is_empty())
+    {
+        next_condition(builder);
+        builder.push_bind(term.trim().to_string());
+        builder.push(" <% i.filename");
+    }
+
+    if let Some(min_width) = query.min_width {
+        next_condition(builder);
+        builder.push("i.width >= ");
+        builder.push_bind(min_width);
+    }
+
+    if let Some(min_height) = query.min_height {
+        next_condition(builder);
+        builder.push("i.height >= ");
+        builder.push_bind(min_height);
+    }
+
+    if let Some(min_size) = query.min_file_size {
+        next_condition(builder);
+        builder.push("i.file_size >= ");
+        builder.push_bind(min_size);
+    }
+
+    if let Some(max_size) = query.max_file_size {
+        next_condition(builder);
+        builder.push("i.file_size <= ");
+        builder.push_bind(max_size);
+    }
+
+    if let Some(after) = query.date_added_after {
+        next_condition(builder);
+        builder.push("i.date_added >= ");
+        builder.push_bind(after);
+    }
+
+    if let Some(before) = query.date_added_before {
+        next_condition(builder);
+        builder.push("i.date_added < ");
+        builder.push_bind(before);
+    }
+
+    if let Some(min_rating) = query.min_rating {
+        next_condition(builder);
+        builder.push("i.rating >= ");
+        builder.push_bind(min_rating);
+    }
+
+    if query.favorites_only.unwrap_or(false) {
+        next_condition(builder);
+        builder.push("i.favorite");
+    }
+
+    if let Some(model) = &query.camera_model {
+        next_condition(builder);
+        builder.push("i.camera_model ILIKE ");
+        builder.push_bind(format!("%{}%", model));
+    }
+
+    if let Some(after) = query.date_taken_after {
+        next_condition(builder);
+        builder.push("i.date_taken >= ");
+        builder.push_bind(after);
+    }
+
+    if let Some(before) = query.date_taken_before {
+        next_condition(builder);
+        builder.push("i.date_taken < ");
+        builder.push_bind(before);
+    }
+
+    // Tag filters are correlated subqueries rather than joins, so each image
+    // appears once without needing DISTINCT
+    if let Some(collection) = &query.collection {
+        next_condition(builder);
+        builder.push(
+            "EXISTS (SELECT 1 FROM collection_images ci JOIN collections c ON ci.collection_id = c.id \
+             WHERE ci.image_id = i.id AND c.name = ",
+        );
+        builder.push_bind(collection.clone());
+        builder.push(")");
+    }
+
+    if let Some(media_type) = query.media_type {
+        next_condition(builder);
+        builder.push("i.media_type = ");
+        builder.push_bind(media_type.as_str());
+    }
+
+    // Images have no duration, so either bound leaves them out
+    if let Some(min_duration) = query.min_duration {
+        next_condition(builder);
+        builder.push("i.duration_secs >= ");
+        builder.push_bind(min_duration);
+    }
+
+    if let Some(max_duration) = query.max_duration {
+        next_condition(builder);
+        builder.push("i.duration_secs <= ");
+        builder.push_bind(max_duration);
+    }
+
+    if let Some(tags) = query.tags.as_ref().filter(|t| !t.is_empty()) {
+        next_condition(builder);
+        match query.tag_mode.unwrap_or_default() {
+            TagMode::Any => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM image_tags it JOIN tags t ON it.tag_id = t.id \
+                     WHERE it.image_id = i.id AND t.name = ANY(",
+                );
+                builder.push_bind(tags.clone());
+                builder.push("))");
+            }
+            TagMode::All => {
+                let mut wanted = tags.clone();
+                wanted.sort();
+                wanted.dedup();
+                builder.push(
+                    "(SELECT COUNT(DISTINCT t.id) FROM image_tags it JOIN tags t ON it.tag_id = t.id \
+                     WHERE it.image_id = i.id AND t.name = ANY(",
+                );
+                let count = wanted.len() as i64;
+                builder.push_bind(wanted);
+                builder.push(")) = ");
+                builder.push_bind(count);
+            }
+        }
+    }
+
+    if let Some(excluded) = query.exclude_tags.as_ref().filter(|t| !t.is_empty()) {
+        next_condition(builder);
+        builder.push(
+            "NOT EXISTS (SELECT 1 FROM image_tags it JOIN tags t ON it.tag_id = t.id \
+             WHERE it.image_id = i.id AND t.name = ANY(",
+        );
+        builder.push_bind(excluded.clone());
+        builder.push("))");
+    }
+
+    if let Some(formats) = query.input_formats.as_ref().filter(|f| !f.is_empty()) {
+        next_condition(builder);
+        builder.push("(");
+        let mut separated = builder.separated(" OR ");
+        for format in formats {
+            let clean_ext = paths::filename_key(format.trim_start_matches('.'));
+            separated.push("i.filename_key LIKE ");
+            separated.push_bind_unseparated(format!("%.{}", clean_ext));
+        }
+        builder.push(")");
+    }
+
+    has_condition
+}
+
+/// Creates any missing `tags` and links every tag to every image in set-based
+/// statements. Returns the number of new image/tag links.
+async fn link_tags(conn: &mut PgConnection, image_ids: &[i32], tags: &[String]) -> Result<u64> {
+    if image_ids.is_empty() || tags.is_empty() {
+        return Ok(0);
+    }
+
+    sqlx::query(
+        "INSERT INTO tags (name) SELECT DISTINCT unnest($1::text[]) ON CONFLICT (name) DO NOTHING",
+    )
+    .bind(tags)
+    .execute(&mut *conn)
+    .await?;
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO image_tags (image_id, tag_id)
+        SELECT i.id, t.id
+        FROM (SELECT DISTINCT unnest($1::int[]) AS id) i
+        CROSS JOIN tags t
+        WHERE t.name = ANY($2)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(image_ids)
+    .bind(tags)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Applies path moves in one set-based UPDATE. Returns the number of rows moved.
+async fn rewrite_paths(conn: &mut PgConnection, moves: &[PathMove]) -> Result<u64> {
+    if moves.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<i32> = moves.iter().map(|m| m.id).collect();
+    let file_paths: Vec<&str> = moves.iter().map(|m| m.file_path.as_str()).collect();
+    let filenames: Vec<&str> = moves.iter().map(|m| m.filename.as_str()).collect();
+    let path_keys: Vec<&str> = moves.iter().map(|m| m.path_key.as_str()).collect();
+    let filename_keys: Vec<&str> = moves.iter().map(|m| m.filename_key.as_str()).collect();
+
+    let result = sqlx::query(
+        r#"
+        UPDATE images SET
+            file_path = m.file_path,
+            filename = m.filename,
+            path_key = m.path_key,
+            filename_key = m.filename_key,
+            date_modified = $6
+        FROM unnest($1::int[], $2::text[], $3::text[], $4::text[], $5::text[])
+            AS m(id, file_path, filename, path_key, filename_key)
+        WHERE images.id = m.id
+        "#,
+    )
+    .bind(&ids)
+    .bind(&file_paths)
+    .bind(&filenames)
+    .bind(&path_keys)
+    .bind(&filename_keys)
+    .bind(Utc::now())
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Id of the collection called `name`, or a not-found error
+async fn collection_id(conn: &mut PgConnection, name: &str) -> Result<i32> {
+    sqlx::query_scalar::<_, i32>("SELECT id FROM collections WHERE name = $1")
+        .bind(name)
+        .fetch_optional(&mut *conn)
+        .await?
+        .with_context(|| format!("Collection '{}' not found", name))
+}
+
+/// Row `i` of `images` with its tags, as a JSON `ExportedImage`
+const IMAGE_SNAPSHOT_JSON: &str = r#"jsonb_build_object(
+    'file_path', i.file_path, 'filename', i.filename, 'file_size', i.file_size,
+    'width', i.width, 'height', i.height,
+    'group_name', i.group_name, 'subgroup_name', i.subgroup_name,
+    'date_added', i.date_added, 'date_modified', i.date_modified,
+    'rating', i.rating, 'favorite', i.favorite,
+    'view_count', i.view_count, 'last_viewed', i.last_viewed,
+    'camera_make', i.camera_make, 'camera_model', i.camera_model, 'lens', i.lens,
+    'focal_length', i.focal_length, 'iso', i.iso, 'aperture', i.aperture,
+    'shutter', i.shutter, 'date_taken', i.date_taken,
+    'gps_lat', i.gps_lat, 'gps_lon', i.gps_lon, 'content_hash', i.content_hash,
+    'source_url', i.source_url, 'media_type', i.media_type,
+    'duration_secs', i.duration_secs, 'video_codec', i.video_codec,
+    'tags', COALESCE(
+        (SELECT jsonb_agg(t.name ORDER BY t.name)
+         FROM image_tags it JOIN tags t ON t.id = it.tag_id
+         WHERE it.image_id = i.id),
+        '[]'::jsonb
+    )
+)"#;
+
+/// Records each of `image_ids` in `audit_log` as it is now, within the
+/// caller's transaction; ids with no image are skipped
+async fn log_changes(conn: &mut PgConnection, kind: ChangeKind, image_ids: &[i32]) -> Result<()> {
+    if image_ids.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(&format!(
+        "INSERT INTO audit_log (operation, image_id, payload, created_at) \
+         SELECT $1, i.id, {}, $2 FROM images i WHERE i.id = ANY($3) ORDER BY i.id",
+        IMAGE_SNAPSHOT_JSON
+    ))
+    .bind(kind.as_str())
+    .bind(Utc::now())
+    .bind(image_ids)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Replaces every tag of an image with `tags`
+async fn replace_tags(conn: &mut PgConnection, image_id: i32, tags: &[String]) -> Result<()> {
+    sqlx::query("DELETE FROM image_tags WHERE image_id = $1")
+        .bind(image_id)
+        .execute(&mut *conn)
+        .await?;
+    link_tags(conn, &[image_id], tags).await?;
+    Ok(())
+}
+
+/// Writes a logged snapshot back as image `image_id`, recreating the row if
+/// it was deleted
+async fn restore_image(
+    conn: &mut PgConnection,
+    image_id: i32,
+    image: &ExportedImage,
+) -> Result<()> {
+    let taken_by =
+        sqlx::query_scalar::<_, i32>("SELECT id FROM images WHERE path_key = $1 AND id <> $2")
+            .bind(paths::path_key(&image.file_path))
+            .bind(image_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+    if let Some(other) = taken_by {
+        anyhow::bail!("'{}' now belongs to image {}", image.file_path, other);
+    }
+
+    let capture = &image.capture;
+    sqlx::query(
+        r#"
+        INSERT INTO images
+        (id, file_path, filename, file_size, width, height, group_name, subgroup_name,
+         date_added, date_modified, rating, favorite, view_count, last_viewed, camera_make,
+         camera_model, lens, focal_length, iso, aperture, shutter, date_taken, gps_lat, gps_lon,
+         content_hash, source_url, media_type, duration_secs, video_codec, path_key,
+         filename_key)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
+                $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31)
+        ON CONFLICT (id) DO UPDATE SET
+            file_path = EXCLUDED.file_path,
+            filename = EXCLUDED.filename,
+            file_size = EXCLUDED.file_size,
+            width = EXCLUDED.width,
+            height = EXCLUDED.height,
+            group_name = EXCLUDED.group_name,
+            subgroup_name = EXCLUDED.subgroup_name,
+            date_added = EXCLUDED.date_added,
+            date_modified = EXCLUDED.date_modified,
+            rating = EXCLUDED.rating,
+            favorite = EXCLUDED.favorite,
+            view_count = EXCLUDED.view_count,
+            last_viewed = EXCLUDED.last_viewed,
+            camera_make = EXCLUDED.camera_make,
+            camera_model = EXCLUDED.camera_model,
+            lens = EXCLUDED.lens,
+            focal_length = EXCLUDED.focal_length,
+            iso = EXCLUDED.iso,
+            aperture = EXCLUDED.aperture,
+            shutter = EXCLUDED.shutter,
+            date_taken = EXCLUDED.date_taken,
+            gps_lat = EXCLUDED.gps_lat,
+            gps_lon = EXCLUDED.gps_lon,
+            content_hash = EXCLUDED.content_hash,
+            source_url = EXCLUDED.source_url,
+            media_type = EXCLUDED.media_type,
+            duration_secs = EXCLUDED.duration_secs,
+            video_codec = EXCLUDED.video_codec,
+            path_key = EXCLUDED.path_key,
+            filename_key = EXCLUDED.filename_key
+        "#,
+    )
+    .bind(image_id)
+    .bind(&image.file_path)
+    .bind(&image.filename)
+    .bind(image.file_size)
+    .bind(image.width)
+    .bind(image.height)
+    .bind(&image.group_name)
+    .bind(&image.subgroup_name)
+    .bind(image.date_added)
+    .bind(image.date_modified)
+    .bind(image.rating)
+    .bind(image.favorite)
+    .bind(image.view_count)
+    .bind(image.last_viewed)
+    .bind(&capture.camera_make)
+    .bind(&capture.camera_model)
+    .bind(&capture.lens)
+    .bind(capture.focal_length)
+    .bind(capture.iso)
+    .bind(capture.aperture)
+    .bind(&capture.shutter)
+    .bind(capture.date_taken)
+    .bind(capture.gps_lat)
+    .bind(capture.gps_lon)
+    .bind(&image.content_hash)
+    .bind(&image.source_url)
+    .bind(image.media_type.as_str())
+    .bind(image.duration_secs)
+    .bind(&image.video_codec)
+    .bind(paths::path_key(&image.file_path))
+    .bind(paths::filename_key(&image.filename))
+    .execute(&mut *conn)
+    .await?;
+
+    replace_tags(conn, image_id, &image.tags).await
+}
+
+/// Re-points image_tags from `sources` to `target` and deletes the sources.
+/// Images that already carry `target` keep a single link thanks to ON CONFLICT.
+async fn merge_tags_into(conn: &mut PgConnection, sources: &[String], target: &str) -> Result<u64> {
+    let target_id = sqlx::query_scalar::<_, i32>(
+        r#"
+        INSERT INTO tags (name) VALUES ($1)
+        ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id
+        "#,
+    )
+    .bind(target)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO image_tags (image_id, tag_id)
+        SELECT it.image_id, $1
+        FROM image_tags it
+        JOIN tags t ON t.id = it.tag_id
+        WHERE t.name = ANY($2) AND t.id <> $1
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(target_id)
+    .bind(sources)
+    .execute(&mut *conn)
+    .await?;
+
+    let result = sqlx::query("DELETE FROM tags WHERE name = ANY($1) AND id <> $2")
+        .bind(sources)
+        .bind(target_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+impl PgStore {
+    /// Search for images based on various filters
+    pub async fn search_images(&self, query: SearchQuery) -> Result<SearchResults> {
+        let (limit, offset) = query.page_bounds();
+
+        let mut count_builder = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM images i");
+        push_search_filters(&mut count_builder, &query);
+        let total_count = count_builder
+            .build_query_scalar::<i64>()
+            .fetch_one(&*self.pool)
+            .await?;
+
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT i.* FROM images i");
+        push_search_filters(&mut builder, &query);
+        builder.push(query.order_by_sql());
+        builder.push(" LIMIT ");
+        builder.push_bind(limit);
+        builder.push(" OFFSET ");
+        builder.push_bind(offset);
+
+        let mut images = builder
+            .build_query_as::<ImageRecord>()
+            .fetch_all(&*self.pool)
+            .await?;
+
+        // Fetch tags for the whole page in one query
+        let ids: Vec<i32> = images.iter().map(|image| image.id).collect();
+        let mut tags_by_image = self.get_tags_for_images(&ids).await?;
+        for image in &mut images {
+            image.tags = tags_by_image.remove(&image.id).unwrap_or_default();
+        }
+
+        Ok(SearchResults {
+            images,
+            total_count,
+            limit,
+            offset,
+        })
+    }
+
+    /// Store (or replace) the embedding for an image
+    pub async fn upsert_embedding(&self, image_id: i32, embedding: Vec<f32>) -> Result<()> {
+        check_embedding_dim(&embedding)?;
+
+        let result = sqlx::query("UPDATE images SET embedding = $1 WHERE id = $2")
+            .bind(Vector::from(embedding))
+            .bind(image_id)
+            .execute(&*self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Image {} not found", image_id);
+        }
+
+        Ok(())
+    }
+
+    /// Find the images closest to `embedding` by cosine distance, optionally
+    /// narrowed by the usual search filters (their paging fields are ignored)
+    pub async fn search_similar(
+        &self,
+        embedding: Vec<f32>,
+        limit: i64,
+        filters: Option<SearchQuery>,
+    ) -> Result<Vec<ImageRecord>> {
+        check_embedding_dim(&embedding)?;
+        let limit = limit.clamp(1, MAX_PAGE_SIZE);
+        let embedding = Vector::from(embedding);
+
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT i.*, (i.embedding <=> ");
+        builder.push_bind(embedding.clone());
+        builder.push(")::real AS distance FROM images i");
+        let has_condition = match &filters {
+            Some(filters) => push_search_filters(&mut builder, filters),
+            None => false,
+        };
+        builder.push(if has_condition { " AND " } else { " WHERE " });
+        builder.push("i.embedding IS NOT NULL ORDER BY i.embedding <=> ");
+        builder.push_bind(embedding);
+        builder.push(" LIMIT ");
+        builder.push_bind(limit);
+
+        let mut images = builder
+            .build_query_as::<ImageRecord>()
+            .fetch_all(&*self.pool)
+            .await?;
+
+        let ids: Vec<i32> = images.iter().map(|image| image.id).collect();
+        let mut tags_by_image = self.get_tags_for_images(&ids).await?;
+        for image in &mut images {
+            image.tags = tags_by_image.remove(&image.id).unwrap_or_default();
+        }
+
+        Ok(images)
+    }
+
+    /// Get tags for several images at once, keyed by image ID
+    pub async fn get_tags_for_images(
+        &self,
+        image_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<String>>> {
+        let mut tags_by_image: HashMap<i32, Vec<String>> = HashMap::new();
+        if image_ids.is_empty() {
+            return Ok(tags_by_image);
+        }
+
+        let rows = sqlx::query_as::<_, (i32, String)>(
+            r#"
+            SELECT it.image_id, t.name FROM image_tags it
+            JOIN tags t ON t.id = it.tag_id
+            WHERE it.image_id = ANY($1)
+            ORDER BY t.name
+            "#,
+        )
+        .bind(image_ids)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        for (image_id, name) in rows {
+            tags_by_image.entry(image_id).or_default().push(name);
+        }
+
+        Ok(tags_by_image)
+    }
+
+    /// Get all tags for a specific image
+    /// File path of an image already stored with each of `hashes`; the oldest
+    /// copy when there are several
+    pub async fn paths_by_content_hash(
+        &self,
+        hashes: &[String],
+    ) -> Result<HashMap<String, String>> {
+        if hashes.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let rows = sqlx::query_as::<_, (String, String)>(
+            r#"
+            SELECT DISTINCT ON (content_hash) content_hash, file_path FROM images
+            WHERE content_hash = ANY($1)
+            ORDER BY content_hash, id
+            "#,
+        )
+        .bind(hashes)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    pub async fn get_image_tags(&self, image_id: i32) -> Result<Vec<String>> {
+        let tags = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT t.name FROM tags t
+            JOIN image_tags it ON t.id = it.tag_id
+            WHERE it.image_id = $1
+            ORDER BY t.name
+            "#,
+        )
+        .bind(image_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    /// Get all unique tags from the database
+    pub async fn get_all_tags(&self) -> Result<Vec<String>> {
+        let tags = sqlx::query_scalar::<_, String>("SELECT name FROM tags ORDER BY name")
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(tags)
+    }
+
+    /// Case-insensitive prefix search over tag names, for autocomplete
+    pub async fn search_tags(&self, prefix: &str, limit: i64) -> Result<Vec<String>> {
+        let tags = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT name FROM tags
+            WHERE lower(name) LIKE lower($1) || '%' ESCAPE '\'
+            ORDER BY lower(name), name
+            LIMIT $2
+            "#,
+        )
+        .bind(escape_like(prefix))
+        .bind(limit.clamp(1, MAX_PAGE_SIZE))
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    /// Get tags that aren't attached to any image
+    pub async fn get_unused_tags(&self) -> Result<Vec<String>> {
+        let tags = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT t.name FROM tags t
+            WHERE NOT EXISTS (SELECT 1 FROM image_tags it WHERE it.tag_id = t.id)
+            ORDER BY t.name
+            "#,
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    /// Delete every tag that isn't attached to any image; returns how many were removed
+    pub async fn delete_unused_tags(&self) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM tags t WHERE NOT EXISTS (SELECT 1 FROM image_tags it WHERE it.tag_id = t.id)",
+        )
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Rename a tag. If `new_name` already exists the two are merged.
+    pub async fn rename_tag(&self, old_name: &str, new_name: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let exists =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM tags WHERE name = $1)")
+                .bind(old_name)
+                .fetch_one(&mut *tx)
+                .await?;
+        if !exists {
+            anyhow::bail!("Tag '{}' not found", old_name);
+        }
+
+        let target_exists =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM tags WHERE name = $1)")
+                .bind(new_name)
+                .fetch_one(&mut *tx)
+                .await?;
+
+        if target_exists {
+            merge_tags_into(&mut tx, &[old_name.to_string()], new_name).await?;
+        } else {
+            sqlx::query("UPDATE tags SET name = $1 WHERE name = $2")
+                .bind(new_name)
+                .bind(old_name)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Merge `sources` into `target` (created if missing); returns how many source tags were removed
+    pub async fn merge_tags(&self, sources: &[String], target: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let merged = merge_tags_into(&mut tx, sources, target).await?;
+        tx.commit().await?;
+        Ok(merged)
+    }
+
+    /// Delete a tag and detach it from every image
+    pub async fn delete_tag(&self, name: &str) -> Result<()> {
+        // image_tags rows go with it via ON DELETE CASCADE
+        let result = sqlx::query("DELETE FROM tags WHERE name = $1")
+            .bind(name)
+            .execute(&*self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Tag '{}' not found", name);
+        }
+
+        Ok(())
+    }
+
+    /// Rename a group, updating the images that reference it. If `new_name`
+    /// already exists the two are merged.
+    pub async fn rename_group(&self, old_name: &str, new_name: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let exists =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM groups WHERE name = $1)")
+                .bind(old_name)
+                .fetch_one(&mut *tx)
+                .await?;
+        if !exists {
+            anyhow::bail!("Group '{}' not found", old_name);
+        }
+
+        let target_exists =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM groups WHERE name = $1)")
+                .bind(new_name)
+                .fetch_one(&mut *tx)
+                .await?;
+
+        if target_exists {
+            merge_groups_into(&mut tx, &[old_name.to_string()], new_name).await?;
+        } else {
+            sqlx::query("UPDATE groups SET name = $1 WHERE name = $2")
+                .bind(new_name)
+                .bind(old_name)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE images SET group_name = $1 WHERE group_name = $2")
+                .bind(new_name)
+                .bind(old_name)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Merge `sources` into `target` (created if missing); returns how many source groups were removed
+    pub async fn merge_groups(&self, sources: &[String], target: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let merged = merge_groups_into(&mut tx, sources, target).await?;
+        tx.commit().await?;
+        Ok(merged)
+    }
+
+    /// Delete a group, moving its images into `reassign_to` or leaving them
+    /// ungrouped. Returns how many images were affected.
+    pub async fn delete_group(&self, name: &str, reassign_to: Option<&str>) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let image_count =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM images WHERE group_name = $1")
+                .bind(name)
+                .fetch_one(&mut *tx)
+                .await?;
+
+        let removed = match reassign_to {
+            Some(target) => merge_groups_into(&mut tx, &[name.to_string()], target).await?,
+            None => {
+                sqlx::query(
+                    "UPDATE images SET group_name = NULL, subgroup_name = NULL WHERE group_name = $1",
+                )
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query("DELETE FROM groups WHERE name = $1")
+                    .bind(name)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected()
+            }
+        };
+        if removed == 0 {
+            anyhow::bail!("Group '{}' not found", name);
+        }
+
+        tx.commit().await?;
+        Ok(image_count as u64)
+    }
+
+    /// Rename a subgroup within `group`. If `new_name` already exists there
+    /// the two are merged.
+    pub async fn rename_subgroup(&self, group: &str, old_name: &str, new_name: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let exists = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM subgroups s JOIN groups g ON g.id = s.group_id
+                WHERE g.name = $1 AND s.name = $2
+            )
+            "#,
+        )
+        .bind(group)
+        .bind(old_name)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !exists {
+            anyhow::bail!("Subgroup '{}' not found in group '{}'", old_name, group);
+        }
+
+        merge_subgroups_into(&mut tx, group, &[old_name.to_string()], new_name).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Merge subgroups of `group` into `target`; returns how many source subgroups were removed
+    pub async fn merge_subgroups(
+        &self,
+        group: &str,
+        sources: &[String],
+        target: &str,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let merged = merge_subgroups_into(&mut tx, group, sources, target).await?;
+        tx.commit().await?;
+        Ok(merged)
+    }
+
+    /// Delete a subgroup, moving its images into `reassign_to` or clearing
+    /// their subgroup. Returns how many images were affected.
+    pub async fn delete_subgroup(
+        &self,
+        group: &str,
+        name: &str,
+        reassign_to: Option<&str>,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let image_count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM images WHERE group_name = $1 AND subgroup_name = $2",
+        )
+        .bind(group)
+        .bind(name)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let removed = match reassign_to {
+            Some(target) => {
+                merge_subgroups_into(&mut tx, group, &[name.to_string()], target).await?
+            }
+            None => {
+                sqlx::query(
+                    "UPDATE images SET subgroup_name = NULL WHERE group_name = $1 AND subgroup_name = $2",
+                )
+                .bind(group)
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query(
+                    r#"
+                    DELETE FROM subgroups
+                    WHERE name = $2 AND group_id = (SELECT id FROM groups WHERE name = $1)
+                    "#,
+                )
+                .bind(group)
+                .bind(name)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+            }
+        };
+        if removed == 0 {
+            anyhow::bail!("Subgroup '{}' not found in group '{}'", name, group);
+        }
+
+        tx.commit().await?;
+        Ok(image_count as u64)
+    }
+
+    /// Image count per group, including empty groups
+    pub async fn group_counts(&self) -> Result<Vec<(String, i64)>> {
+        let counts = sqlx::query_as::<_, (String, i64)>(
+            r#"
+            SELECT g.name, COUNT(i.id)
+            FROM groups g
+            LEFT JOIN images i ON i.group_name = g.name
+            GROUP BY g.name
+            ORDER BY g.name
+            "#,
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Image count per (group, subgroup), including empty subgroups
+    pub async fn subgroup_counts(&self) -> Result<Vec<(String, String, i64)>> {
+        let counts = sqlx::query_as::<_, (String, String, i64)>(
+            r#"
+            SELECT g.name, s.name, COUNT(i.id)
+            FROM subgroups s
+            JOIN groups g ON g.id = s.group_id
+            LEFT JOIN images i ON i.group_name = g.name AND i.subgroup_name = s.name
+            GROUP BY g.name, s.name
+            ORDER BY g.name, s.name
+            "#,
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Get all groups
+    pub async fn get_all_groups(&self) -> Result<Vec<String>> {
+        let groups = sqlx::query_scalar::<_, String>("SELECT name FROM groups ORDER BY name")
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(groups)
+    }
+
+    /// Get all subgroups for a specific group
+    pub async fn get_subgroups_for_group(&self, group_name: &str) -> Result<Vec<String>> {
+        let subgroups = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT s.name FROM subgroups s
+            JOIN groups g ON s.group_id = g.id
+            WHERE g.name = $1
+            ORDER BY s.name
+            "#,
+        )
+        .bind(group_name)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(subgroups)
+    }
+
+    /// Add a new image to the database
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_image(
+        &self,
+        file_path: &str,
+        filename: &str,
+        width: Option<i32>,
+        height: Option<i32>,
+        group_name: Option<&str>,
+        subgroup_name: Option<&str>,
+        tags: Option<Vec<String>>,
+    ) -> Result<i32> {
+        // Ensure group exists if provided
+        if let Some(group) = group_name {
+            self.ensure_group_exists(group).await?;
+        }
+
+        // Ensure subgroup exists if provided
+        if let (Some(group), Some(subgroup)) = (group_name, subgroup_name) {
+            self.ensure_subgroup_exists(subgroup, group).await?;
+        }
+
+        let now = Utc::now();
+        let path_key = paths::path_key(file_path);
+        let mut tx = self.pool.begin().await?;
+
+        // Re-adding a known path updates it, so log it as an update
+        let existing = sqlx::query_scalar::<_, i32>("SELECT id FROM images WHERE path_key = $1")
+            .bind(&path_key)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if let Some(id) = existing {
+            log_changes(&mut tx, ChangeKind::Update, &[id]).await?;
+        }
+
+        let image_id = sqlx::query_scalar::<_, i32>(
+            r#"
+            INSERT INTO images
+            (file_path, filename, file_size, width, height, group_name, subgroup_name, date_added, date_modified,
+             path_key, filename_key)
+            VALUES ($1, $2, 0, $3, $4, $5, $6, $7, $7, $8, $9)
+            ON CONFLICT (path_key) DO UPDATE SET
+                width = EXCLUDED.width,
+                height = EXCLUDED.height,
+                group_name = EXCLUDED.group_name,
+                subgroup_name = EXCLUDED.subgroup_name,
+                date_modified = $7
+            RETURNING id
+            "#,
+        )
+        .bind(file_path)
+        .bind(filename)
+        .bind(width)
+        .bind(height)
+        .bind(group_name)
+        .bind(subgroup_name)
+        .bind(now)
+        .bind(&path_key)
+        .bind(paths::filename_key(filename))
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(tag_list) = tags {
+            replace_tags(&mut tx, image_id, &tag_list).await?;
+        }
+        if existing.is_none() {
+            log_changes(&mut tx, ChangeKind::Add, &[image_id]).await?;
+        }
+
+        tx.commit().await?;
+        Ok(image_id)
+    }
+
+    /// Set tags for an image (replaces existing tags)
+    pub async fn set_image_tags(&self, image_id: i32, tags: Vec<String>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        log_changes(&mut tx, ChangeKind::Tag, &[image_id]).await?;
+        replace_tags(&mut tx, image_id, &tags).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Add tags to many images at once; returns the number of new image/tag links
+    pub async fn add_tags_to_images(&self, image_ids: &[i32], tags: &[String]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        log_changes(&mut tx, ChangeKind::Tag, image_ids).await?;
+        let added = link_tags(&mut tx, image_ids, tags).await?;
+        tx.commit().await?;
+        Ok(added)
+    }
+
+    /// Remove tags from many images at once; returns the number of links removed
+    pub async fn remove_tags_from_images(&self, image_ids: &[i32], tags: &[String]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        log_changes(&mut tx, ChangeKind::Tag, image_ids).await?;
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM image_tags it
+            USING tags t
+            WHERE it.tag_id = t.id AND it.image_id = ANY($1) AND t.name = ANY($2)
+            "#,
+        )
+        .bind(image_ids)
+        .bind(tags)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Create any of `names` that don't exist yet, in one statement
+    pub async fn ensure_groups(&self, names: &[String]) -> Result<()> {
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query(
+            "INSERT INTO groups (name) SELECT unnest($1::text[]) ON CONFLICT (name) DO NOTHING",
+        )
+        .bind(names)
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Create any missing (group, subgroup) pairs; the groups must already exist
+    pub async fn ensure_subgroups(&self, pairs: &[(String, String)]) -> Result<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let (groups, subgroups): (Vec<String>, Vec<String>) = pairs.iter().cloned().unzip();
+
+        sqlx::query(
+            r#"
+            INSERT INTO subgroups (name, group_id)
+            SELECT p.subgroup, g.id
+            FROM unnest($1::text[], $2::text[]) AS p(group_name, subgroup)
+            JOIN groups g ON g.name = p.group_name
+            ON CONFLICT (name, group_id) DO NOTHING
+            "#,
+        )
+        .bind(groups)
+        .bind(subgroups)
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Upsert `images` with one multi-row INSERT inside a transaction. Returns
+    /// `(id, inserted)` per image, `inserted` being false for the conflict path.
+    pub async fn insert_images_chunk(&self, images: &[NewImage]) -> Result<Vec<(i32, bool)>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = images
+            .iter()
+            .map(|i| paths::path_key(&i.file_path))
+            .collect();
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        let existing: HashMap<String, i32> = sqlx::query_as::<_, (String, i32)>(
+            "SELECT path_key, id FROM images WHERE path_key = ANY($1)",
+        )
+        .bind(&keys)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+        let updated: Vec<i32> = existing.values().copied().collect();
+        log_changes(&mut tx, ChangeKind::Update, &updated).await?;
+
+        let mut builder = QueryBuilder::<Postgres>::new(
+            "INSERT INTO images \
+             (file_path, filename, file_size, width, height, group_name, subgroup_name, date_added, date_modified, \
+              camera_make, camera_model, lens, focal_length, iso, aperture, shutter, date_taken, gps_lat, gps_lon, \
+              path_key, filename_key, content_hash, source_url, media_type, duration_secs, video_codec) ",
+        );
+        let no_capture = CaptureMetadata::default();
+        builder.push_values(images.iter().zip(&keys), |mut row, (image, key)| {
+            let capture = image.capture.as_ref().unwrap_or(&no_capture);
+            row.push_bind(&image.file_path)
+                .push_bind(image.filename.as_deref().unwrap_or_default())
+                .push_bind(image.file_size.unwrap_or(0))
+                .push_bind(image.width)
+                .push_bind(image.height)
+                .push_bind(&image.group_name)
+                .push_bind(&image.subgroup_name)
+                .push_bind(now)
+                .push_bind(now)
+                .push_bind(&capture.camera_make)
+                .push_bind(&capture.camera_model)
+                .push_bind(&capture.lens)
+                .push_bind(capture.focal_length)
+                .push_bind(capture.iso)
+                .push_bind(capture.aperture)
+                .push_bind(&capture.shutter)
+                .push_bind(capture.date_taken)
+                .push_bind(capture.gps_lat)
+                .push_bind(capture.gps_lon)
+                .push_bind(key)
+                .push_bind(paths::filename_key(
+                    image.filename.as_deref().unwrap_or_default(),
+                ))
+                .push_bind(&image.content_hash)
+                .push_bind(&image.source_url)
+                .push_bind(image.media_type.as_str())
+                .push_bind(image.duration_secs)
+                .push_bind(&image.video_codec);
+        });
+        builder.push(
+            " ON CONFLICT (path_key) DO UPDATE SET \
+             width = EXCLUDED.width, \
+             height = EXCLUDED.height, \
+             group_name = EXCLUDED.group_name, \
+             subgroup_name = EXCLUDED.subgroup_name, \
+             date_modified = EXCLUDED.date_modified, \
+             camera_make = EXCLUDED.camera_make, \
+             camera_model = EXCLUDED.camera_model, \
+             lens = EXCLUDED.lens, \
+             focal_length = EXCLUDED.focal_length, \
+             iso = EXCLUDED.iso, \
+             aperture = EXCLUDED.aperture, \
+             shutter = EXCLUDED.shutter, \
+             date_taken = EXCLUDED.date_taken, \
+             gps_lat = EXCLUDED.gps_lat, \
+             gps_lon = EXCLUDED.gps_lon, \
+             content_hash = COALESCE(EXCLUDED.content_hash, images.content_hash), \
+             source_url = COALESCE(EXCLUDED.source_url, images.source_url), \
+             media_type = EXCLUDED.media_type, \
+             duration_secs = EXCLUDED.duration_secs, \
+             video_codec = EXCLUDED.video_codec \
+             RETURNING path_key, id",
+        );
+
+        let ids: HashMap<String, i32> = builder
+            .build_query_as::<(String, i32)>()
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .collect();
+        let added: Vec<i32> = ids
+            .iter()
+            .filter(|(key, _)| !existing.contains_key(*key))
+            .map(|(_, id)| *id)
+            .collect();
+        log_changes(&mut tx, ChangeKind::Add, &added).await?;
+
+        tx.commit().await?;
+
+        Ok(keys
+            .iter()
+            .filter_map(|key| Some((*ids.get(key)?, !existing.contains_key(key))))
+            .collect())
+    }
+
+    /// One page of `export_library`: images after `after` in path order, with
+    /// their tags
+    pub async fn export_page(&self, after: Option<&str>, limit: i64) -> Result<Vec<ExportedImage>> {
+        let images = sqlx::query_as::<_, ExportedImage>(
+            r#"
+            SELECT i.file_path, i.filename, i.file_size, i.width, i.height, i.group_name,
+                   i.subgroup_name, i.date_added, i.date_modified, i.rating, i.favorite,
+                   i.view_count, i.last_viewed, i.camera_make, i.camera_model, i.lens,
+                   i.focal_length, i.iso, i.aperture, i.shutter, i.date_taken, i.gps_lat,
+                   i.gps_lon, i.content_hash, i.source_url, i.media_type, i.duration_secs,
+                   i.video_codec,
+                   COALESCE(
+                       (SELECT json_agg(t.name ORDER BY t.name) FROM image_tags it
+                        JOIN tags t ON t.id = it.tag_id WHERE it.image_id = i.id),
+                       '[]'::json
+                   ) AS tags
+            FROM images i
+            WHERE ($1::text IS NULL OR i.file_path > $1)
+            ORDER BY i.file_path
+            LIMIT $2
+            "#,
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(images)
+    }
+
+    /// Writes imported images in one transaction. Existing paths are skipped
+    /// unless `overwrite`, in which case their columns and tags are replaced.
+    /// Returns, per input, `Some(true)` if inserted, `Some(false)` if
+    /// overwritten and `None` if skipped.
+    pub async fn import_images_chunk(
+        &self,
+        images: &[ExportedImage],
+        overwrite: bool,
+    ) -> Result<Vec<Option<bool>>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = images
+            .iter()
+            .map(|i| paths::path_key(&i.file_path))
+            .collect();
+        let mut tx = self.pool.begin().await?;
+
+        let existing: std::collections::HashSet<String> =
+            sqlx::query_scalar::<_, String>("SELECT path_key FROM images WHERE path_key = ANY($1)")
+                .bind(&keys)
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .collect();
+
+        let outcomes = keys
+            .iter()
+            .map(|key| {
+                if !existing.contains(key) {
+                    Some(true)
+                } else if overwrite {
+                    Some(false)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let written: Vec<(&ExportedImage, &String)> = images
+            .iter()
+            .zip(&keys)
+            .filter(|(_, key)| overwrite || !existing.contains(*key))
+            .collect();
+        if written.is_empty() {
+            return Ok(outcomes);
+        }
+
+        let mut builder = QueryBuilder::<Postgres>::new(
+            "INSERT INTO images \
+             (file_path, filename, file_size, width, height, group_name, subgroup_name, date_added, date_modified, \
+              rating, favorite, view_count, last_viewed, \
+              camera_make, camera_model, lens, focal_length, iso, aperture, shutter, date_taken, gps_lat, gps_lon, \
+              path_key, filename_key, content_hash, source_url, media_type, duration_secs, video_codec) ",
+        );
+        builder.push_values(&written, |mut row, (image, key)| {
+            let capture = &image.capture;
+            row.push_bind(&image.file_path)
+                .push_bind(&image.filename)
+                .push_bind(image.file_size)
+                .push_bind(image.width)
+                .push_bind(image.height)
+                .push_bind(&image.group_name)
+                .push_bind(&image.subgroup_name)
+                .push_bind(image.date_added)
+                .push_bind(image.date_modified)
+                .push_bind(image.rating)
+                .push_bind(image.favorite)
+                .push_bind(image.view_count)
+                .push_bind(image.last_viewed)
+                .push_bind(&capture.camera_make)
+                .push_bind(&capture.camera_model)
+                .push_bind(&capture.lens)
+                .push_bind(capture.focal_length)
+                .push_bind(capture.iso)
+                .push_bind(capture.aperture)
+                .push_bind(&capture.shutter)
+                .push_bind(capture.date_taken)
+                .push_bind(capture.gps_lat)
+                .push_bind(capture.gps_lon)
+                .push_bind(key)
+                .push_bind(paths::filename_key(&image.filename))
+                .push_bind(&image.content_hash)
+                .push_bind(&image.source_url)
+                .push_bind(image.media_type.as_str())
+                .push_bind(image.duration_secs)
+                .push_bind(&image.video_codec);
+        });
+        builder.push(
+            " ON CONFLICT (path_key) DO UPDATE SET \
+             filename = EXCLUDED.filename, \
+             filename_key = EXCLUDED.filename_key, \
+             file_size = EXCLUDED.file_size, \
+             width = EXCLUDED.width, \
+             height = EXCLUDED.height, \
+             group_name = EXCLUDED.group_name, \
+             subgroup_name = EXCLUDED.subgroup_name, \
+             date_added = EXCLUDED.date_added, \
+             date_modified = EXCLUDED.date_modified, \
+             rating = EXCLUDED.rating, \
+             favorite = EXCLUDED.favorite, \
+             view_count = EXCLUDED.view_count, \
+             last_viewed = EXCLUDED.last_viewed, \
+             camera_make = EXCLUDED.camera_make, \
+             camera_model = EXCLUDED.camera_model, \
+             lens = EXCLUDED.lens, \
+             focal_length = EXCLUDED.focal_length, \
+             iso = EXCLUDED.iso, \
+             aperture = EXCLUDED.aperture, \
+             shutter = EXCLUDED.shutter, \
+             date_taken = EXCLUDED.date_taken, \
+             gps_lat = EXCLUDED.gps_lat, \
+             gps_lon = EXCLUDED.gps_lon, \
+             content_hash = EXCLUDED.content_hash, \
+             source_url = EXCLUDED.source_url, \
+             media_type = EXCLUDED.media_type, \
+             duration_secs = EXCLUDED.duration_secs, \
+             video_codec = EXCLUDED.video_codec \
+             RETURNING path_key, id",
+        );
+
+        let ids: HashMap<String, i32> = builder
+            .build_query_as::<(String, i32)>()
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .collect();
+
+        // Imported tags replace whatever an overwritten image carried
+        let written_ids: Vec<i32> = ids.values().copied().collect();
+        sqlx::query("DELETE FROM image_tags WHERE image_id = ANY($1)")
+            .bind(&written_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        let (tag_ids, tag_names): (Vec<i32>, Vec<&str>) = written
+            .iter()
+            .flat_map(|(image, key)| {
+                let id = ids[*key];
+                image.tags.iter().map(move |tag| (id, tag.as_str()))
+            })
+            .unzip();
+        sqlx::query(
+            "INSERT INTO tags (name) SELECT DISTINCT unnest($1::text[]) ON CONFLICT (name) DO NOTHING",
+        )
+        .bind(&tag_names)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO image_tags (image_id, tag_id)
+            SELECT p.image_id, t.id
+            FROM unnest($1::int[], $2::text[]) AS p(image_id, name)
+            JOIN tags t ON t.name = p.name
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(&tag_ids)
+        .bind(&tag_names)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(outcomes)
+    }
+
+    async fn ensure_group_exists(&self, name: &str) -> Result<()> {
+        sqlx::query("INSERT INTO groups (name) VALUES ($1) ON CONFLICT (name) DO NOTHING")
+            .bind(name)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Ensure a subgroup exists for a given group
+    async fn ensure_subgroup_exists(&self, subgroup_name: &str, group_name: &str) -> Result<()> {
+        // Get group ID
+        let group_id = sqlx::query_scalar::<_, i32>("SELECT id FROM groups WHERE name = $1")
+            .bind(group_name)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO subgroups (name, group_id) VALUES ($1, $2) ON CONFLICT (name, group_id) DO NOTHING",
+        )
+        .bind(subgroup_name)
+        .bind(group_id)
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Apply a partial update to an image and return the updated record
+    pub async fn update_image(&self, image_id: i32, update: UpdateImage) -> Result<ImageRecord> {
+        if let Some(group) = &update.group_name {
+            self.ensure_group_exists(group).await?;
+        }
+        if let (Some(group), Some(subgroup)) = (&update.group_name, &update.subgroup_name) {
+            self.ensure_subgroup_exists(subgroup, group).await?;
+        }
+
+        let mut builder = QueryBuilder::<Postgres>::new("UPDATE images SET date_modified = ");
+        builder.push_bind(Utc::now());
+
+        if let Some(file_path) = update.file_path {
+            let filename = std::path::Path::new(&file_path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .context("file_path has no file name")?
+                .to_string();
+            let path_key = paths::path_key(&file_path);
+            let filename_key = paths::filename_key(&filename);
+            builder.push(", file_path = ");
+            builder.push_bind(file_path);
+            builder.push(", filename = ");
+            builder.push_bind(filename);
+            builder.push(", path_key = ");
+            builder.push_bind(path_key);
+            builder.push(", filename_key = ");
+            builder.push_bind(filename_key);
+        }
+        if let Some(group) = update.group_name {
+            builder.push(", group_name = ");
+            builder.push_bind(group);
+        }
+        if let Some(subgroup) = update.subgroup_name {
+            builder.push(", subgroup_name = ");
+            builder.push_bind(subgroup);
+        }
+        if let Some(width) = update.width {
+            builder.push(", width = ");
+            builder.push_bind(width);
+        }
+        if let Some(height) = update.height {
+            builder.push(", height = ");
+            builder.push_bind(height);
+        }
+
+        builder.push(" WHERE id = ");
+        builder.push_bind(image_id);
+        builder.push(" RETURNING *");
+
+        let mut tx = self.pool.begin().await?;
+        log_changes(&mut tx, ChangeKind::Update, &[image_id]).await?;
+        let mut image = builder
+            .build_query_as::<ImageRecord>()
+            .fetch_optional(&mut *tx)
+            .await?
+            .with_context(|| format!("Image {} not found", image_id))?;
+        tx.commit().await?;
+
+        image.tags = self.get_image_tags(image.id).await?;
+        Ok(image)
+    }
+
+    pub async fn get_path_records(&self, image_ids: &[i32]) -> Result<Vec<PathRecord>> {
+        let records = sqlx::query_as::<_, PathRecord>(
+            "SELECT id, file_path, file_size, width, height, content_hash FROM images WHERE id = ANY($1)",
+        )
+        .bind(image_ids)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(records)
+    }
+
+    /// Upsert a thumbnail, stamping it with the image's current `date_modified`
+    pub async fn put_thumbnail(
+        &self,
+        image_id: i32,
+        size: i32,
+        format: &str,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO thumbnails (image_id, size, format, bytes, byte_size, source_modified, generated_at)
+            SELECT id, $2, $3, $4, $5, date_modified, $6 FROM images WHERE id = $1
+            ON CONFLICT (image_id, size) DO UPDATE SET
+                format = EXCLUDED.format,
+                bytes = EXCLUDED.bytes,
+                byte_size = EXCLUDED.byte_size,
+                source_modified = EXCLUDED.source_modified,
+                generated_at = EXCLUDED.generated_at
+            "#,
+        )
+        .bind(image_id)
+        .bind(size)
+        .bind(format)
+        .bind(bytes)
+        .bind(bytes.len() as i32)
+        .bind(Utc::now())
+        .execute(&*self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Image {} not found", image_id);
+        }
+
+        Ok(())
+    }
+
+    /// Thumbnails are only returned while their `source_modified` still
+    /// matches the image
+    pub async fn get_thumbnail(&self, image_id: i32, size: i32) -> Result<Option<Thumbnail>> {
+        Ok(self.get_thumbnails(&[image_id], size).await?.pop())
+    }
+
+    pub async fn get_thumbnails(&self, image_ids: &[i32], size: i32) -> Result<Vec<Thumbnail>> {
+        let thumbnails = sqlx::query_as::<_, Thumbnail>(
+            r#"
+            SELECT t.image_id, t.size, t.format, t.bytes, t.generated_at
+            FROM thumbnails t
+            JOIN images i ON i.id = t.image_id
+            WHERE t.image_id = ANY($1) AND t.size = $2
+              AND t.source_modified IS NOT DISTINCT FROM i.date_modified
+            "#,
+        )
+        .bind(image_ids)
+        .bind(size)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(thumbnails)
+    }
+
+    pub async fn prune_thumbnails(&self, max_total_bytes: i64) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let stale = sqlx::query(
+            r#"
+            DELETE FROM thumbnails t USING images i
+            WHERE i.id = t.image_id AND t.source_modified IS DISTINCT FROM i.date_modified
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        // Keep the newest thumbnails whose running total fits the budget
+        let evicted = sqlx::query(
+            r#"
+            DELETE FROM thumbnails WHERE (image_id, size) IN (
+                SELECT image_id, size FROM (
+                    SELECT image_id, size,
+                           SUM(byte_size) OVER (ORDER BY generated_at DESC, image_id DESC, size) AS running
+                    FROM thumbnails
+                ) ranked
+                WHERE running > $1
+            )
+            "#,
+        )
+        .bind(max_total_bytes)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+
+        Ok(stale + evicted)
+    }
+
+    pub async fn save_search(&self, name: &str, query: &str) -> Result<SavedSearchRow> {
+        let now = Utc::now();
+        let row = sqlx::query_as::<_, SavedSearchRow>(
+            r#"
+            INSERT INTO saved_searches (name, query, created_at, updated_at)
+            VALUES ($1, $2::jsonb, $3, $3)
+            ON CONFLICT (name) DO UPDATE SET
+                query = EXCLUDED.query,
+                updated_at = EXCLUDED.updated_at
+            RETURNING id, name, query::text AS query, created_at, updated_at
+            "#,
+        )
+        .bind(name)
+        .bind(query)
+        .bind(now)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    pub async fn list_saved_searches(&self) -> Result<Vec<SavedSearchRow>> {
+        let rows = sqlx::query_as::<_, SavedSearchRow>(
+            "SELECT id, name, query::text AS query, created_at, updated_at FROM saved_searches ORDER BY name",
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn get_saved_search(&self, name: &str) -> Result<Option<SavedSearchRow>> {
+        let row = sqlx::query_as::<_, SavedSearchRow>(
+            "SELECT id, name, query::text AS query, created_at, updated_at FROM saved_searches WHERE name = $1",
+        )
+        .bind(name)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    pub async fn delete_saved_search(&self, name: &str) -> Result<()> {
+        let result = sqlx::query("DELETE FROM saved_searches WHERE name = $1")
+            .bind(name)
+            .execute(&*self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Saved search '{}' not found", name);
+        }
+
+        Ok(())
+    }
+
+    /// Insert a collection; `None` if the name is taken
+    pub async fn create_collection(
+        &self,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<Option<Collection>> {
+        let collection = sqlx::query_as::<_, Collection>(
+            r#"
+            INSERT INTO collections (name, description, created_at, updated_at)
+            VALUES ($1, $2, $3, $3)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id, name, description, 0::bigint AS image_count, created_at, updated_at
+            "#,
+        )
+        .bind(name)
+        .bind(description)
+        .bind(Utc::now())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(collection)
+    }
+
+    /// Rename a collection
+    pub async fn rename_collection(&self, old_name: &str, new_name: &str) -> Result<()> {
+        let result =
+            sqlx::query("UPDATE collections SET name = $1, updated_at = $2 WHERE name = $3")
+                .bind(new_name)
+                .bind(Utc::now())
+                .bind(old_name)
+                .execute(&*self.pool)
+                .await
+                .with_context(|| format!("Collection '{}' already exists", new_name))?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Collection '{}' not found", old_name);
+        }
+
+        Ok(())
+    }
+
+    /// Delete a collection and its membership rows
+    pub async fn delete_collection(&self, name: &str) -> Result<()> {
+        let result = sqlx::query("DELETE FROM collections WHERE name = $1")
+            .bind(name)
+            .execute(&*self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Collection '{}' not found", name);
+        }
+
+        Ok(())
+    }
+
+    /// All collections with image counts, by name
+    pub async fn list_collections_with_counts(&self) -> Result<Vec<Collection>> {
+        let collections = sqlx::query_as::<_, Collection>(
+            r#"
+            SELECT c.id, c.name, c.description, COUNT(ci.image_id) AS image_count,
+                   c.created_at, c.updated_at
+            FROM collections c
+            LEFT JOIN collection_images ci ON ci.collection_id = c.id
+            GROUP BY c.id
+            ORDER BY c.name
+            "#,
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(collections)
+    }
+
+    /// Append images to a collection, skipping members and unknown ids
+    pub async fn add_to_collection(&self, name: &str, image_ids: &[i32]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let collection = collection_id(&mut tx, name).await?;
+        let now = Utc::now();
+
+        // The first occurrence of a repeated id decides its place
+        let result = sqlx::query(
+            r#"
+            WITH wanted AS (
+                SELECT w.image_id, MIN(w.ord) AS ord
+                FROM unnest($2::int[]) WITH ORDINALITY AS w(image_id, ord)
+                GROUP BY w.image_id
+            ),
+            last AS (
+                SELECT COALESCE(MAX(position), -1) AS position
+                FROM collection_images WHERE collection_id = $1
+            )
+            INSERT INTO collection_images (collection_id, image_id, position, added_at)
+            SELECT $1, w.image_id, last.position + (ROW_NUMBER() OVER (ORDER BY w.ord))::int, $3
+            FROM wanted w
+            JOIN images i ON i.id = w.image_id
+            CROSS JOIN last
+            WHERE NOT EXISTS (
+                SELECT 1 FROM collection_images ci
+                WHERE ci.collection_id = $1 AND ci.image_id = w.image_id
+            )
+            "#,
+        )
+        .bind(collection)
+        .bind(image_ids)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            sqlx::query("UPDATE collections SET updated_at = $1 WHERE id = $2")
+                .bind(now)
+                .bind(collection)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Remove images from a collection
+    pub async fn remove_from_collection(&self, name: &str, image_ids: &[i32]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let collection = collection_id(&mut tx, name).await?;
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM collection_images
+            WHERE collection_id = $1 AND image_id = ANY($2)
+            "#,
+        )
+        .bind(collection)
+        .bind(image_ids)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            sqlx::query("UPDATE collections SET updated_at = $1 WHERE id = $2")
+                .bind(Utc::now())
+                .bind(collection)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Rewrite a collection's positions to `collection_order`
+    pub async fn reorder_collection(&self, name: &str, image_ids: &[i32]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let collection = collection_id(&mut tx, name).await?;
+
+        let members = sqlx::query_scalar::<_, i32>(
+            "SELECT image_id FROM collection_images WHERE collection_id = $1 ORDER BY position, image_id",
+        )
+        .bind(collection)
+        .fetch_all(&mut *tx)
+        .await?;
+        let order = collection_order(name, &members, image_ids)?;
+
+        sqlx::query(
+            r#"
+            UPDATE collection_images SET position = m.ord - 1
+            FROM unnest($2::int[]) WITH ORDINALITY AS m(image_id, ord)
+            WHERE collection_id = $1 AND collection_images.image_id = m.image_id
+            "#,
+        )
+        .bind(collection)
+        .bind(&order)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE collections SET updated_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(collection)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// One page of a collection's images in position order
+    pub async fn get_collection_images(
+        &self,
+        name: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<SearchResults> {
+        let mut conn = self.pool.acquire().await?;
+        let collection = collection_id(&mut conn, name).await?;
+
+        let total_count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM collection_images WHERE collection_id = $1",
+        )
+        .bind(collection)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let mut images = sqlx::query_as::<_, ImageRecord>(
+            r#"
+            SELECT i.* FROM collection_images ci
+            JOIN images i ON i.id = ci.image_id
+            WHERE ci.collection_id = $1
+            ORDER BY ci.position, ci.image_id
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(collection)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&mut *conn)
+        .await?;
+        drop(conn);
+
+        let ids: Vec<i32> = images.iter().map(|image| image.id).collect();
+        let mut tags_by_image = self.get_tags_for_images(&ids).await?;
+        for image in &mut images {
+            image.tags = tags_by_image.remove(&image.id).unwrap_or_default();
+        }
+
+        Ok(SearchResults {
+            images,
+            total_count,
+            limit,
+            offset,
+        })
+    }
+
+    pub async fn get_capture_metadata(&self, image_id: i32) -> Result<CaptureMetadata> {
+        sqlx::query_as::<_, CaptureMetadata>(
+            r#"
+            SELECT camera_make, camera_model, lens, focal_length, iso, aperture, shutter,
+                   date_taken, gps_lat, gps_lon
+            FROM images WHERE id = $1
+            "#,
+        )
+        .bind(image_id)
+        .fetch_optional(&*self.pool)
+        .await?
+        .with_context(|| format!("Image {} not found", image_id))
+    }
+
+    pub fn get_all_paths(&self) -> BoxStream<'_, Result<PathRecord>> {
+        sqlx::query_as::<_, PathRecord>(
+            "SELECT id, file_path, file_size, width, height, content_hash FROM images ORDER BY id",
+        )
+        .fetch(&*self.pool)
+        .map_err(anyhow::Error::from)
+        .boxed()
+    }
+
+    pub async fn set_rating(&self, image_id: i32, rating: Option<i16>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        log_changes(&mut tx, ChangeKind::Update, &[image_id]).await?;
+        let result = sqlx::query("UPDATE images SET rating = $2 WHERE id = $1")
+            .bind(image_id)
+            .bind(rating)
+            .execute(&mut *tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Image {} not found", image_id);
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn toggle_favorite(&self, image_id: i32) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        log_changes(&mut tx, ChangeKind::Update, &[image_id]).await?;
+        let favorite = sqlx::query_scalar::<_, bool>(
+            "UPDATE images SET favorite = NOT favorite WHERE id = $1 RETURNING favorite",
+        )
+        .bind(image_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .with_context(|| format!("Image {} not found", image_id))?;
+
+        tx.commit().await?;
+        Ok(favorite)
+    }
+
+    /// Increments in a single UPDATE so concurrent views are never lost
+    pub async fn record_view(&self, image_id: i32) -> Result<i32> {
+        sqlx::query_scalar::<_, i32>(
+            "UPDATE images SET view_count = view_count + 1, last_viewed = $2 \
+             WHERE id = $1 RETURNING view_count",
+        )
+        .bind(image_id)
+        .bind(Utc::now())
+        .fetch_optional(&*self.pool)
+        .await?
+        .with_context(|| format!("Image {} not found", image_id))
+    }
+
+    /// Delete an image by ID
+    /// Moves the images at or under `old_prefix` to `new_prefix`
+    pub async fn relink_prefix(&self, old_prefix: &str, new_prefix: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query_as::<_, (i32, String)>(
+            r"SELECT id, file_path FROM images WHERE file_path = $1 OR file_path LIKE $2 ESCAPE '\'",
+        )
+        .bind(old_prefix)
+        .bind(prefix_like_pattern(old_prefix))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let moves: Vec<PathMove> = rows
+            .into_iter()
+            .filter_map(|(id, path)| {
+                PathMove::new(id, paths::rebase_path(&path, old_prefix, new_prefix)?)
+            })
+            .collect();
+        let moved = rewrite_paths(&mut tx, &moves).await?;
+
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    pub async fn set_paths(&self, moves: &[PathMove]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let moved = rewrite_paths(&mut tx, moves).await?;
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    pub async fn delete_image(&self, image_id: i32) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        log_changes(&mut tx, ChangeKind::Delete, &[image_id]).await?;
+        sqlx::query("DELETE FROM images WHERE id = $1")
+            .bind(image_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The newest `limit` audit log rows
+    pub async fn get_recent_changes(&self, limit: i64) -> Result<Vec<AuditLogRow>> {
+        let rows = sqlx::query_as::<_, AuditLogRow>(
+            "SELECT id, operation, image_id, payload::text AS payload, created_at, undone_at \
+             FROM audit_log ORDER BY id DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn get_change(&self, log_id: i64) -> Result<Option<AuditLogRow>> {
+        let row = sqlx::query_as::<_, AuditLogRow>(
+            "SELECT id, operation, image_id, payload::text AS payload, created_at, undone_at \
+             FROM audit_log WHERE id = $1",
+        )
+        .bind(log_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    /// Reverse a logged change; see `Db::undo_change`
+    pub async fn undo_change(&self, entry: &ChangeLogEntry) -> Result<ChangeLogEntry> {
+        let mut tx = self.pool.begin().await?;
+
+        let undone_at = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            "SELECT undone_at FROM audit_log WHERE id = $1 FOR UPDATE",
+        )
+        .bind(entry.id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .with_context(|| format!("Change {} not found", entry.id))?;
+        if undone_at.is_some() {
+            anyhow::bail!("Change {} was already undone", entry.id);
+        }
+
+        let has_newer = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM audit_log \
+             WHERE image_id = $1 AND id > $2 AND undone_at IS NULL)",
+        )
+        .bind(entry.image_id)
+        .bind(entry.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if has_newer {
+            anyhow::bail!(
+                "Image {} has newer changes; undo those first",
+                entry.image_id
+            );
+        }
+
+        let exists =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM images WHERE id = $1)")
+                .bind(entry.image_id)
+                .fetch_one(&mut *tx)
+                .await?;
+        match entry.operation {
+            ChangeKind::Delete if exists => {
+                anyhow::bail!("Image {} already exists", entry.image_id)
+            }
+            _ if !exists && entry.operation != ChangeKind::Delete => {
+                anyhow::bail!("Image {} not found", entry.image_id)
+            }
+            ChangeKind::Add => {
+                sqlx::query("DELETE FROM images WHERE id = $1")
+                    .bind(entry.image_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            ChangeKind::Delete | ChangeKind::Update | ChangeKind::Tag => {
+                restore_image(&mut tx, entry.image_id, &entry.image).await?
+            }
+        }
+
+        let now = Utc::now();
+        sqlx::query("UPDATE audit_log SET undone_at = $1 WHERE id = $2")
+            .bind(now)
+            .bind(entry.id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(ChangeLogEntry {
+            undone_at: Some(now),
+            ..entry.clone()
+        })
+    }
+
+    /// Get database statistics
+    pub async fn get_statistics(&self) -> Result<DatabaseStats> {
+        let total_images = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM images")
+            .fetch_one(&*self.pool)
+            .await?;
+
+        let total_tags = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM tags")
+            .fetch_one(&*self.pool)
+            .await?;
+
+        let total_groups = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM groups")
+            .fetch_one(&*self.pool)
+            .await?;
+
+        let total_subgroups = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM subgroups")
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(DatabaseStats {
+            total_images,
+            total_tags,
+            total_groups,
+            total_subgroups,
+        })
+    }
+
+    /// Total file size and the number of untagged and ungrouped images
+    pub async fn library_totals(&self) -> Result<LibraryTotals> {
+        let totals = sqlx::query_as::<_, LibraryTotals>(
+            r#"
+            SELECT
+                COALESCE(SUM(file_size), 0)::BIGINT AS total_file_size,
+                COUNT(*) FILTER (
+                    WHERE NOT EXISTS (SELECT 1 FROM image_tags it WHERE it.image_id = i.id)
+                ) AS untagged_images,
+                COUNT(*) FILTER (
+                    WHERE group_name IS NULL OR group_name = ''
+                ) AS ungrouped_images
+            FROM images i
+            "#,
+        )
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(totals)
+    }
+
+    /// Image count and total size per lower-cased file extension
+    pub async fn extension_stats(&self) -> Result<Vec<ExtensionStats>> {
+        let stats = sqlx::query_as::<_, ExtensionStats>(
+            r#"
+            SELECT extension, COUNT(*) AS image_count,
+                   COALESCE(SUM(file_size), 0)::BIGINT AS total_file_size
+            FROM (
+                SELECT file_size,
+                       COALESCE(substring(filename_key FROM '\.([^.]*)$'), '') AS extension
+                FROM images
+            ) named
+            GROUP BY extension
+            ORDER BY image_count DESC, extension
+            "#,
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(stats)
+    }
+
+    /// The `limit` most used tags with their image counts
+    pub async fn tag_usage(&self, limit: i64) -> Result<Vec<TagUsage>> {
+        let usage = sqlx::query_as::<_, TagUsage>(
+            r#"
+            SELECT t.name, COUNT(*) AS image_count
+            FROM tags t
+            JOIN image_tags it ON it.tag_id = t.id
+            GROUP BY t.name
+            ORDER BY image_count DESC, t.name
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(usage)
+    }
+
+    /// Images added per UTC month (`YYYY-MM`) since `since`
+    pub async fn monthly_additions(&self, since: DateTime<Utc>) -> Result<Vec<(String, i64)>> {
+        let counts = sqlx::query_as::<_, (String, i64)>(
+            r#"
+            SELECT to_char(date_added AT TIME ZONE 'UTC', 'YYYY-MM') AS month, COUNT(*)
+            FROM images
+            WHERE date_added >= $1
+            GROUP BY month
+            ORDER BY month
+            "#,
+        )
+        .bind(since)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Test database connection
+    pub async fn test_connection(&self) -> Result<bool> {
+        sqlx::query("SELECT 1")
+            .execute(&*self.pool)
+            .await
+            .context("Database connection test failed")?;
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,236 @@
+use crate::db::CaptureMetadata;
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use exif::{Exif, In, Tag, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Reads the EXIF block of a JPEG, TIFF, PNG, WebP or HEIF file. Returns
+/// `None` for unreadable files and images without EXIF.
+pub fn read_exif(path: &Path) -> Option<Exif> {
+    let file = File::open(path).ok()?;
+    exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()
+}
+
+/// Capture metadata for `path`; all fields are `None` when there is no EXIF
+pub fn read_capture_metadata(path: &Path) -> CaptureMetadata {
+    read_exif(path)
+        .map(|exif| capture_metadata(&exif))
+        .unwrap_or_default()
+}
+
+pub fn capture_metadata(exif: &Exif) -> CaptureMetadata {
+    CaptureMetadata {
+        camera_make: ascii(exif, Tag::Make),
+        camera_model: ascii(exif, Tag::Model),
+        lens: ascii(exif, Tag::LensModel),
+        focal_length: rational(exif, Tag::FocalLength, 0),
+        iso: exif
+            .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0))
+            .map(|iso| iso as i32),
+        aperture: rational(exif, Tag::FNumber, 0),
+        shutter: exif
+            .get_field(Tag::ExposureTime, In::PRIMARY)
+            .and_then(|f| match &f.value {
+                Value::Rational(v) => v.first().map(|r| format_shutter(r.num, r.denom)),
+                _ => None,
+            }),
+        date_taken: date_taken(exif),
+        gps_lat: gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S'),
+        gps_lon: gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W'),
+    }
+}
+
+/// EXIF orientation, 1 (upright) to 8
+pub fn orientation(exif: &Exif) -> Option<u32> {
+    exif.get_field(Tag::Orientation, In::PRIMARY)?
+        .value
+        .get_uint(0)
+        .filter(|o| (1..=8).contains(o))
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(parts) => {
+            let text = String::from_utf8_lossy(parts.first()?);
+            let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            (!text.is_empty()).then(|| text.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn rational(exif: &Exif, tag: Tag, index: usize) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) => v.get(index).filter(|r| r.denom != 0).map(|r| r.to_f64()),
+        _ => None,
+    }
+}
+
+/// Exposure time as photographers write it: "1/250" below a second, "2.5" above
+fn format_shutter(num: u32, denom: u32) -> String {
+    if num == 0 || denom == 0 {
+        return "0".to_string();
+    }
+    if num < denom {
+        format!("1/{}", (denom as f64 / num as f64).round())
+    } else {
+        let seconds = num as f64 / denom as f64;
+        format!("{}", (seconds * 10.0).round() / 10.0)
+    }
+}
+
+/// DateTimeOriginal, shifted to UTC using OffsetTimeOriginal when present
+/// (cameras without it are assumed to record UTC)
+fn date_taken(exif: &Exif) -> Option<DateTime<Utc>> {
+    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?;
+    let Value::Ascii(parts) = &field.value else {
+        return None;
+    };
+    let mut stamp = exif::DateTime::from_ascii(parts.first()?).ok()?;
+    if let Some(Value::Ascii(offset)) = exif
+        .get_field(Tag::OffsetTimeOriginal, In::PRIMARY)
+        .map(|f| &f.value)
+    {
+        if let Some(offset) = offset.first() {
+            let _ = stamp.parse_offset(offset);
+        }
+    }
+
+    let local = NaiveDate::from_ymd_opt(stamp.year as i32, stamp.month as u32, stamp.day as u32)?
+        .and_hms_opt(stamp.hour as u32, stamp.minute as u32, stamp.second as u32)?;
+    let offset = FixedOffset::east_opt(i32::from(stamp.offset.unwrap_or(0)) * 60)?;
+    Some(
+        offset
+            .from_local_datetime(&local)
+            .single()?
+            .with_timezone(&Utc),
+    )
+}
+
+/// Degrees/minutes/seconds to signed decimal degrees
+fn gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+    let degrees = rational(exif, tag, 0)?;
+    let minutes = rational(exif, tag, 1).unwrap_or(0.0);
+    let seconds = rational(exif, tag, 2).unwrap_or(0.0);
+    let value = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    let negative = matches!(
+        exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value),
+        Some(Value::Ascii(parts)) if parts.first().and_then(|p| p.first()) == Some(&negative_ref)
+    );
+    Some(if negative { -value } else { value })
+}
+
+/// Builds a JPEG carrying the given EXIF fields, for tests elsewhere in the crate
+#[cfg(test)]
+pub fn jpeg_with_exif(fields: &[exif::Field]) -> Vec<u8> {
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    writer.write(&mut tiff, false).unwrap();
+    let tiff = tiff.into_inner();
+
+    let mut jpeg = Vec::new();
+    image::RgbImage::new(8, 8)
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+
+    // APP1 segment right after SOI
+    let mut out = jpeg[..2].to_vec();
+    out.extend_from_slice(&[0xFF, 0xE1]);
+    out.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    out.extend_from_slice(b"Exif\0\0");
+    out.extend_from_slice(&tiff);
+    out.extend_from_slice(&jpeg[2..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::{Field, Rational};
+    use tempfile::tempdir;
+
+    fn ascii_field(tag: Tag, text: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+        }
+    }
+
+    fn rational_field(tag: Tag, values: &[(u32, u32)]) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(values.iter().map(|&r| Rational::from(r)).collect()),
+        }
+    }
+
+    #[test]
+    fn test_read_capture_metadata() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("tagged.jpg");
+        let fields = vec![
+            ascii_field(Tag::Make, "Fujifilm"),
+            ascii_field(Tag::Model, "X-T4"),
+            ascii_field(Tag::DateTimeOriginal, "2024:05:17 14:30:00"),
+            ascii_field(Tag::OffsetTimeOriginal, "+02:00"),
+            rational_field(Tag::FNumber, &[(28, 10)]),
+            rational_field(Tag::ExposureTime, &[(1, 250)]),
+            rational_field(Tag::GPSLatitude, &[(48, 1), (51, 1), (30, 1)]),
+            ascii_field(Tag::GPSLatitudeRef, "N"),
+            rational_field(Tag::GPSLongitude, &[(2, 1), (21, 1), (0, 1)]),
+            ascii_field(Tag::GPSLongitudeRef, "W"),
+            Field {
+                tag: Tag::PhotographicSensitivity,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![400]),
+            },
+        ];
+        std::fs::write(&path, jpeg_with_exif(&fields)).unwrap();
+
+        let meta = read_capture_metadata(&path);
+        assert_eq!(meta.camera_make.as_deref(), Some("Fujifilm"));
+        assert_eq!(meta.camera_model.as_deref(), Some("X-T4"));
+        assert_eq!(meta.iso, Some(400));
+        assert_eq!(meta.aperture, Some(2.8));
+        assert_eq!(meta.shutter.as_deref(), Some("1/250"));
+        assert_eq!(
+            meta.date_taken.unwrap().to_rfc3339(),
+            "2024-05-17T12:30:00+00:00"
+        );
+        assert!((meta.gps_lat.unwrap() - 48.858333).abs() < 1e-5);
+        assert!((meta.gps_lon.unwrap() + 2.35).abs() < 1e-9);
+        assert_eq!(meta.lens, None);
+    }
+
+    #[test]
+    fn test_missing_exif_is_empty() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("plain.png");
+        image::RgbImage::new(4, 4).save(&path).unwrap();
+        assert_eq!(read_capture_metadata(&path), CaptureMetadata::default());
+        assert_eq!(
+            read_capture_metadata(Path::new("/no/such/file.jpg")),
+            CaptureMetadata::default()
+        );
+    }
+
+    #[test]
+    fn test_format_shutter() {
+        assert_eq!(format_shutter(1, 250), "1/250");
+        assert_eq!(format_shutter(10, 4000), "1/400");
+        assert_eq!(format_shutter(5, 2), "2.5");
+        assert_eq!(format_shutter(30, 1), "30");
+    }
+}
@@ -0,0 +1,613 @@
+//! Exporting a selection of images to a folder or a zip archive, converted,
+//! scaled down and stripped of metadata on the way as asked
+
+use crate::db::Db;
+use crate::paths::{numbered_filename, sanitize_filename};
+use crate::tasks::{CancellationToken, TaskError};
+use anyhow::{Context, Result};
+use base::core::image_converter::image_format;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// JPEG quality when `ExportOptions::quality` isn't set
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// An image to export: a database id, or a path that need not be in the
+/// database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExportSource {
+    Id(i32),
+    Path(String),
+}
+
+/// What `export_images` does to each image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// Output format such as `jpeg` or `png`; each image keeps its own if unset
+    pub format: Option<String>,
+    /// JPEG quality from 1 to 100; other formats ignore it
+    pub quality: Option<u8>,
+    /// Longest edge in pixels; larger images are scaled down to it
+    pub max_dimension: Option<u32>,
+    /// Leave EXIF and other metadata out, turning images upright first so
+    /// they don't lose their orientation with it
+    pub strip_metadata: bool,
+    /// Output name without extension. `{stem}` is the source's file name
+    /// without extension and `{index}` its 1-based position in the export,
+    /// zero-padded to a common width.
+    pub name_template: String,
+    /// Write one `<archive_name>.zip` into the destination instead of files
+    pub as_archive: bool,
+    pub archive_name: String,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: None,
+            quality: None,
+            max_dimension: None,
+            strip_metadata: false,
+            name_template: "{stem}".to_string(),
+            as_archive: false,
+            archive_name: "export".to_string(),
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Whether an image of `format` can be exported as its original bytes
+    fn keeps_original(&self, format: Option<ImageFormat>) -> bool {
+        self.max_dimension.is_none()
+            && self.quality.is_none()
+            && !self.strip_metadata
+            && match &self.format {
+                None => true,
+                Some(name) => image_format(name).ok() == format,
+            }
+    }
+}
+
+/// `done` of `total` images handled; `source` is the one just finished
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportProgress {
+    pub done: usize,
+    pub total: usize,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedFile {
+    /// Database id, for images exported by id
+    pub id: Option<i32>,
+    pub source: String,
+    /// The written file, or the entry name within the archive
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportFailure {
+    pub id: Option<i32>,
+    /// The image's path, or its id if that didn't resolve
+    pub source: String,
+    pub error: String,
+}
+
+/// Outcome of `export_images`, each list in input order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub exported: Vec<ExportedFile>,
+    pub failed: Vec<ExportFailure>,
+    /// The archive written, with `as_archive`
+    pub archive: Option<String>,
+}
+
+/// Looks up the paths of the images given by id; unknown ids come back as
+/// errors
+pub async fn resolve_sources(
+    db: &Db,
+    sources: Vec<ExportSource>,
+) -> Result<Vec<(Option<i32>, Result<String, String>)>> {
+    let ids: Vec<i32> = sources
+        .iter()
+        .filter_map(|source| match source {
+            ExportSource::Id(id) => Some(*id),
+            ExportSource::Path(_) => None,
+        })
+        .collect();
+    let paths: HashMap<i32, String> = if ids.is_empty() {
+        HashMap::new()
+    } else {
+        db.get_path_records(&ids)
+            .await?
+            .into_iter()
+            .map(|record| (record.id, record.file_path))
+            .collect()
+    };
+    Ok(sources
+        .into_iter()
+        .map(|source| match source {
+            ExportSource::Id(id) => (
+                Some(id),
+                paths
+                    .get(&id)
+                    .cloned()
+                    .ok_or_else(|| format!("No image with id {}", id)),
+            ),
+            ExportSource::Path(path) => (None, Ok(path)),
+        })
+        .collect())
+}
+
+/// Exports the images of `sources` (as returned by `resolve_sources`) into
+/// `dest_dir`, one at a time, reporting after each. Existing files are never
+/// overwritten: clashing names get a number. If the export is cancelled,
+/// what it wrote so far is deleted again.
+pub fn export_images(
+    sources: Vec<(Option<i32>, Result<String, String>)>,
+    dest_dir: &Path,
+    options: &ExportOptions,
+    cancel: &CancellationToken,
+    on_progress: impl Fn(ExportProgress),
+) -> Result<ExportManifest, TaskError> {
+    if let Some(name) = &options.format {
+        image_format(name)?;
+    }
+    std::fs::create_dir_all(dest_dir)
+        .with_context(|| format!("Failed to create {}", dest_dir.display()))?;
+
+    let mut target = if options.as_archive {
+        let filename = format!("{}.zip", sanitize_filename(&options.archive_name));
+        let (file, path) = create_free_file(dest_dir, &filename)
+            .with_context(|| format!("Failed to create {} in {}", filename, dest_dir.display()))?;
+        Target::Archive {
+            writer: Box::new(ZipWriter::new(file)),
+            path,
+            entries: HashSet::new(),
+        }
+    } else {
+        Target::Folder {
+            dir: dest_dir.to_path_buf(),
+            written: Vec::new(),
+        }
+    };
+
+    let total = sources.len();
+    let width = total.to_string().len();
+    let mut manifest = ExportManifest::default();
+    for (index, (id, source)) in sources.into_iter().enumerate() {
+        if cancel.is_cancelled() {
+            target.discard();
+            return Err(TaskError::Cancelled);
+        }
+        let label = match &source {
+            Ok(path) => path.clone(),
+            Err(_) => id.map(|id| id.to_string()).unwrap_or_default(),
+        };
+        let result = source.and_then(|path| {
+            let stem = Path::new(&path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let name = options
+                .name_template
+                .replace("{stem}", &stem)
+                .replace("{index}", &format!("{:0width$}", index + 1));
+            export_one(&path, &name, options)
+                .and_then(|(bytes, extension)| target.write(&name, &extension, &bytes))
+                .map_err(|e| format!("{:#}", e))
+        });
+        match result {
+            Ok(path) => manifest.exported.push(ExportedFile {
+                id,
+                source: label.clone(),
+                path,
+            }),
+            Err(error) => manifest.failed.push(ExportFailure {
+                id,
+                source: label.clone(),
+                error,
+            }),
+        }
+        on_progress(ExportProgress {
+            done: index + 1,
+            total,
+            source: label,
+        });
+    }
+
+    if let Target::Archive { writer, path, .. } = target {
+        let finished = writer.finish().context("Failed to finish the archive");
+        if let Err(e) = finished {
+            let _ = std::fs::remove_file(&path);
+            return Err(e.into());
+        }
+        manifest.archive = Some(path.to_string_lossy().to_string());
+    }
+    Ok(manifest)
+}
+
+/// Where exported images go
+enum Target {
+    Folder {
+        dir: PathBuf,
+        written: Vec<PathBuf>,
+    },
+    Archive {
+        writer: Box<ZipWriter<File>>,
+        path: PathBuf,
+        entries: HashSet<String>,
+    },
+}
+
+impl Target {
+    /// Store `bytes` as `name.extension`, numbering the name if it's taken;
+    /// returns the file or entry written
+    fn write(&mut self, name: &str, extension: &str, bytes: &[u8]) -> Result<String> {
+        let filename = format!("{}.{}", sanitize_filename(name), extension);
+        match self {
+            Target::Folder { dir, written } => {
+                let (mut file, path) = create_free_file(dir, &filename)
+                    .with_context(|| format!("Failed to create {}", filename))?;
+                if let Err(e) = file.write_all(bytes) {
+                    let _ = std::fs::remove_file(&path);
+                    return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+                }
+                written.push(path.clone());
+                Ok(path.to_string_lossy().to_string())
+            }
+            Target::Archive {
+                writer, entries, ..
+            } => {
+                let entry = (0..)
+                    .map(|n| numbered_filename(&filename, n))
+                    .find(|entry| !entries.contains(entry))
+                    .expect("some numbered name is free");
+                // Images are compressed already
+                let options =
+                    SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+                writer.start_file(entry.as_str(), options)?;
+                writer.write_all(bytes)?;
+                entries.insert(entry.clone());
+                Ok(entry)
+            }
+        }
+    }
+
+    /// Delete everything written so far
+    fn discard(self) {
+        let paths = match self {
+            Target::Folder { written, .. } => written,
+            Target::Archive { writer, path, .. } => {
+                drop(writer);
+                vec![path]
+            }
+        };
+        for path in paths {
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("Failed to remove exported {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// The exported bytes of the image at `path` and their file extension
+fn export_one(path: &str, name: &str, options: &ExportOptions) -> Result<(Vec<u8>, String)> {
+    let reader = ImageReader::open(path)
+        .with_context(|| format!("Failed to open {}", path))?
+        .with_guessed_format()
+        .with_context(|| format!("Failed to identify format of {}", path))?;
+    let source_format = reader.format();
+
+    if options.keeps_original(source_format) {
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        let extension = Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .or_else(|| source_format.map(|f| f.extensions_str()[0].to_string()))
+            .unwrap_or_else(|| "img".to_string());
+        return Ok((bytes, extension));
+    }
+
+    let format = match &options.format {
+        Some(name) => image_format(name)?,
+        None => source_format.with_context(|| format!("Unknown format of {}", path))?,
+    };
+    let mut decoder = reader
+        .into_decoder()
+        .with_context(|| format!("Failed to decode {}", path))?;
+    let exif = if options.strip_metadata {
+        None
+    } else {
+        decoder.exif_metadata().ok().flatten()
+    };
+    let orientation = decoder.orientation().ok();
+    let mut img = DynamicImage::from_decoder(decoder)
+        .with_context(|| format!("Failed to decode {}", path))?;
+    if options.strip_metadata {
+        if let Some(orientation) = orientation {
+            img.apply_orientation(orientation);
+        }
+    }
+    if let Some(max) = options.max_dimension {
+        if img.width().max(img.height()) > max {
+            img = img.resize(max, max, FilterType::Lanczos3);
+        }
+    }
+
+    let bytes = encode(&img, format, options.quality, exif)
+        .with_context(|| format!("Failed to encode {} as {:?}", name, format))?;
+    Ok((bytes, format.extensions_str()[0].to_string()))
+}
+
+/// `img` encoded as `format`, carrying `exif` where the format can hold it
+fn encode(
+    img: &DynamicImage,
+    format: ImageFormat,
+    quality: Option<u8>,
+    exif: Option<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    fn with_exif(
+        img: &DynamicImage,
+        mut encoder: impl ImageEncoder,
+        exif: Option<Vec<u8>>,
+    ) -> image::ImageResult<()> {
+        if let Some(exif) = exif {
+            // Formats without EXIF support just go without
+            let _ = encoder.set_exif_metadata(exif);
+        }
+        img.write_with_encoder(encoder)
+    }
+
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let quality = quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+            with_exif(
+                img,
+                JpegEncoder::new_with_quality(&mut bytes, quality),
+                exif,
+            )?
+        }
+        ImageFormat::Png => with_exif(img, PngEncoder::new(&mut bytes), exif)?,
+        ImageFormat::WebP => with_exif(img, WebPEncoder::new_lossless(&mut bytes), exif)?,
+        _ => img.write_to(&mut Cursor::new(&mut bytes), format)?,
+    }
+    Ok(bytes)
+}
+
+/// Create `dir/filename`, or `dir/filename (n)` for the first n whose file
+/// doesn't exist yet
+fn create_free_file(dir: &Path, filename: &str) -> std::io::Result<(File, PathBuf)> {
+    let mut attempt = 0;
+    loop {
+        let candidate = dir.join(numbered_filename(filename, attempt));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use std::cell::RefCell;
+    use tempfile::tempdir;
+
+    fn write_png(path: &Path, w: u32, h: u32) -> String {
+        RgbImage::from_pixel(w, h, Rgb([0, 128, 255]))
+            .save(path)
+            .unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn paths(paths: &[&str]) -> Vec<(Option<i32>, Result<String, String>)> {
+        paths.iter().map(|p| (None, Ok(p.to_string()))).collect()
+    }
+
+    #[test]
+    fn test_export_to_archive() {
+        let dir = tempdir().unwrap();
+        let wide = write_png(&dir.path().join("wide.png"), 400, 200);
+        let small = write_png(&dir.path().join("small.png"), 30, 60);
+        let out = dir.path().join("out");
+        // An archive of the same name is already there
+        std::fs::create_dir_all(&out).unwrap();
+        std::fs::write(out.join("for-print.zip"), "keep me").unwrap();
+
+        let options = ExportOptions {
+            format: Some("jpeg".to_string()),
+            quality: Some(80),
+            max_dimension: Some(100),
+            strip_metadata: true,
+            name_template: "print-{index}".to_string(),
+            as_archive: true,
+            archive_name: "for-print".to_string(),
+        };
+        let manifest = export_images(
+            paths(&[&wide, &small]),
+            &out,
+            &options,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .unwrap();
+
+        assert!(manifest.failed.is_empty());
+        let names: Vec<&str> = manifest.exported.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(names, ["print-1.jpg", "print-2.jpg"]);
+        let archive = manifest.archive.unwrap();
+        assert!(archive.ends_with("for-print (1).zip"));
+        assert_eq!(
+            std::fs::read(out.join("for-print.zip")).unwrap(),
+            b"keep me"
+        );
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let mut dims = Vec::new();
+        for name in names {
+            let mut entry = zip.by_name(name).unwrap();
+            let mut bytes = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut bytes).unwrap();
+            let img = image::load_from_memory(&bytes).unwrap();
+            assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Jpeg);
+            dims.push((img.width(), img.height()));
+        }
+        // Only images larger than the limit are scaled
+        assert_eq!(dims, [(100, 50), (30, 60)]);
+    }
+
+    #[tokio::test]
+    async fn test_export_resolves_ids() {
+        let dir = tempdir().unwrap();
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let tracked = write_png(&dir.path().join("tracked.png"), 10, 10);
+        let loose = write_png(&dir.path().join("loose.png"), 10, 10);
+        let id = db
+            .add_image(
+                &tracked,
+                "tracked.png",
+                Some(10),
+                Some(10),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let sources = vec![
+            ExportSource::Id(id),
+            ExportSource::Id(id + 100),
+            ExportSource::Path(loose.clone()),
+        ];
+        let resolved = resolve_sources(&db, sources).await.unwrap();
+        assert_eq!(resolved[0].0, Some(id));
+        assert!(resolved[0].1.as_ref().unwrap().ends_with("tracked.png"));
+        assert_eq!(resolved[1].1, Err(format!("No image with id {}", id + 100)));
+        assert_eq!(resolved[2], (None, Ok(loose.clone())));
+
+        let out = dir.path().join("out");
+        let manifest = export_images(
+            resolved,
+            &out,
+            &ExportOptions::default(),
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(manifest.exported.len(), 2);
+        assert_eq!(manifest.exported[0].id, Some(id));
+        assert_eq!(manifest.failed[0].id, Some(id + 100));
+        assert_eq!(manifest.failed[0].source, (id + 100).to_string());
+        assert!(manifest.archive.is_none());
+        // Without conversions, the original bytes are copied
+        assert_eq!(
+            std::fs::read(&manifest.exported[1].path).unwrap(),
+            std::fs::read(&loose).unwrap()
+        );
+
+        // The untagged form the webview sends
+        let sources: Vec<ExportSource> = serde_json::from_str(r#"[3, "/a.png"]"#).unwrap();
+        assert_eq!(
+            sources,
+            [
+                ExportSource::Id(3),
+                ExportSource::Path("/a.png".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_reports_per_file_errors() {
+        let dir = tempdir().unwrap();
+        let good = write_png(&dir.path().join("good.png"), 20, 20);
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, "not an image").unwrap();
+        let broken = broken.to_string_lossy().to_string();
+        let missing = dir.path().join("missing.png").to_string_lossy().to_string();
+        let out = dir.path().join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        std::fs::write(out.join("good.webp"), "existing").unwrap();
+
+        let options = ExportOptions {
+            format: Some("webp".to_string()),
+            ..ExportOptions::default()
+        };
+        let progress = RefCell::new(Vec::new());
+        let manifest = export_images(
+            paths(&[&broken, &good, &missing]),
+            &out,
+            &options,
+            &CancellationToken::new(),
+            |p| progress.borrow_mut().push((p.done, p.total, p.source)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            progress.into_inner(),
+            [
+                (1, 3, broken.clone()),
+                (2, 3, good.clone()),
+                (3, 3, missing.clone())
+            ]
+        );
+        let failed: Vec<&str> = manifest.failed.iter().map(|f| f.source.as_str()).collect();
+        assert_eq!(failed, [broken.as_str(), missing.as_str()]);
+        assert!(manifest.failed[1].error.contains("Failed to open"));
+        // The existing file is left alone
+        assert_eq!(manifest.exported.len(), 1);
+        assert!(manifest.exported[0].path.ends_with("good (1).webp"));
+        assert_eq!(std::fs::read(out.join("good.webp")).unwrap(), b"existing");
+
+        // An unknown format fails the whole export up front
+        let options = ExportOptions {
+            format: Some("heic".to_string()),
+            ..ExportOptions::default()
+        };
+        assert!(export_images(
+            paths(&[&good]),
+            &out,
+            &options,
+            &CancellationToken::new(),
+            |_| {}
+        )
+        .is_err());
+
+        // Cancelling removes what was written
+        let cancel = CancellationToken::new();
+        let result = export_images(
+            paths(&[&good, &good]),
+            &dir.path().join("cancelled"),
+            &ExportOptions::default(),
+            &cancel,
+            |_| cancel.cancel(),
+        );
+        assert!(matches!(result, Err(TaskError::Cancelled)));
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("cancelled"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Exact-duplicate and perceptual-similarity finders over a directory, with
+//! progress reporting and cancellation for the desktop app
+
+use crate::library::{collect_files, compute_sha256};
+use crate::tasks::{self, CancellationToken, Cancelled};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Files processed between progress reports and cancellation checks
+pub const FINDER_CHUNK: usize = 64;
+
+/// Default largest Hamming distance (of 64 bits) for two images to count
+/// as similar
+pub const DEFAULT_SIMILARITY_THRESHOLD: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FinderPhase {
+    Scanning,
+    Hashing,
+    Grouping,
+}
+
+/// `done` of `total` files through `phase`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinderProgress {
+    pub phase: FinderPhase,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// A file in a result group, with what the review screen shows about it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoundFile {
+    pub path: String,
+    pub file_size: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl FoundFile {
+    fn probe(path: &str) -> Self {
+        let file_size = std::fs::metadata(path).ok().map(|meta| meta.len());
+        let (width, height) = image::image_dimensions(path)
+            .map(|(w, h)| (Some(w), Some(h)))
+            .unwrap_or((None, None));
+        FoundFile {
+            path: path.to_string(),
+            file_size,
+            width,
+            height,
+        }
+    }
+}
+
+/// Files that are identical (`key` is their SHA-256) or look alike (`key`
+/// is `group_<n>`), sorted by path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageGroup {
+    pub key: String,
+    pub files: Vec<FoundFile>,
+}
+
+/// Groups of byte-identical files under `directory`. Only files sharing a
+/// size are hashed.
+pub fn find_duplicates(
+    directory: &str,
+    extensions: &[String],
+    recursive: bool,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(FinderProgress),
+) -> Result<Vec<ImageGroup>, Cancelled> {
+    let paths = scan(directory, extensions, recursive, cancel, &mut on_progress)?;
+
+    let mut by_size: HashMap<u64, Vec<String>> = HashMap::new();
+    for path in paths {
+        if let Ok(meta) = std::fs::metadata(&path) {
+            by_size.entry(meta.len()).or_default().push(path);
+        }
+    }
+    let candidates: Vec<String> = by_size
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .flatten()
+        .collect();
+
+    let hashes = map_in_chunks(&candidates, cancel, &mut on_progress, |path| {
+        compute_sha256(Path::new(path))
+    })?;
+
+    on_progress(progress(FinderPhase::Grouping, 0, 1));
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, hash) in candidates.into_iter().zip(hashes) {
+        if let Some(hash) = hash {
+            groups.entry(hash).or_default().push(path);
+        }
+    }
+    let groups = finish(groups.into_iter().collect());
+    on_progress(progress(FinderPhase::Grouping, 1, 1));
+    Ok(groups)
+}
+
+/// Groups of images under `directory` whose average hashes differ in at most
+/// `threshold` bits. Each image joins the first group it's close enough to.
+pub fn find_similar(
+    directory: &str,
+    extensions: &[String],
+    recursive: bool,
+    threshold: u32,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(FinderProgress),
+) -> Result<Vec<ImageGroup>, Cancelled> {
+    let paths = scan(directory, extensions, recursive, cancel, &mut on_progress)?;
+    let hashes = map_in_chunks(&paths, cancel, &mut on_progress, |path| {
+        average_hash(Path::new(path))
+    })?;
+    let hashed: Vec<(String, u64)> = paths
+        .into_iter()
+        .zip(hashes)
+        .filter_map(|(path, hash)| Some((path, hash?)))
+        .collect();
+
+    let total = hashed.len();
+    let mut visited = vec![false; total];
+    let mut groups = Vec::new();
+    for i in 0..total {
+        if i % FINDER_CHUNK == 0 {
+            cancel.check()?;
+            on_progress(progress(FinderPhase::Grouping, i, total));
+        }
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+        let mut group = vec![hashed[i].0.clone()];
+        for j in i + 1..total {
+            if !visited[j] && hamming_distance(hashed[i].1, hashed[j].1) <= threshold {
+                visited[j] = true;
+                group.push(hashed[j].0.clone());
+            }
+        }
+        if group.len() > 1 {
+            groups.push((format!("group_{}", groups.len()), group));
+        }
+    }
+    on_progress(progress(FinderPhase::Grouping, total, total));
+    Ok(finish(groups))
+}
+
+/// 64-bit average hash: the image shrunk to 8x8 greyscale, one bit per
+/// pixel brighter than the mean
+pub fn average_hash(path: &Path) -> Option<u64> {
+    let small = image::open(path)
+        .ok()?
+        .resize_exact(8, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let mean = small.pixels().map(|p| p[0] as u32).sum::<u32>() / 64;
+    Some(
+        small
+            .pixels()
+            .enumerate()
+            .filter(|(_, p)| p[0] as u32 > mean)
+            .fold(0u64, |hash, (i, _)| hash | 1 << i),
+    )
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+fn progress(phase: FinderPhase, done: usize, total: usize) -> FinderProgress {
+    FinderProgress { phase, done, total }
+}
+
+fn scan(
+    directory: &str,
+    extensions: &[String],
+    recursive: bool,
+    cancel: &CancellationToken,
+    on_progress: &mut impl FnMut(FinderProgress),
+) -> Result<Vec<String>, Cancelled> {
+    on_progress(progress(FinderPhase::Scanning, 0, 0));
+    let paths = collect_files(directory, extensions, recursive);
+    cancel.check()?;
+    on_progress(progress(FinderPhase::Scanning, paths.len(), paths.len()));
+    Ok(paths)
+}
+
+/// Applies `f` to every path across the available cores, reporting
+/// `Hashing` progress and checking for cancellation per chunk
+fn map_in_chunks<R: Send>(
+    paths: &[String],
+    cancel: &CancellationToken,
+    on_progress: &mut impl FnMut(FinderProgress),
+    f: impl Fn(&String) -> R + Sync,
+) -> Result<Vec<R>, Cancelled> {
+    tasks::map_in_chunks(
+        paths,
+        FINDER_CHUNK,
+        cancel,
+        |done, total| on_progress(progress(FinderPhase::Hashing, done, total)),
+        f,
+    )
+}
+
+/// Sorts files and groups by path and probes each file's size and dimensions
+fn finish(groups: Vec<(String, Vec<String>)>) -> Vec<ImageGroup> {
+    let mut groups: Vec<ImageGroup> = groups
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(key, mut paths)| {
+            paths.sort();
+            ImageGroup {
+                key,
+                files: paths.iter().map(|path| FoundFile::probe(path)).collect(),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.files[0].path.cmp(&b.files[0].path));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    fn solid(path: &Path, color: [u8; 3]) {
+        RgbImage::from_pixel(40, 30, Rgb(color)).save(path).unwrap();
+    }
+
+    /// Left half white, right half black; or top/bottom when `horizontal`
+    fn split(path: &Path, horizontal: bool) -> RgbImage {
+        let img = RgbImage::from_fn(64, 64, |x, y| {
+            let white = if horizontal { y < 32 } else { x < 32 };
+            Rgb(if white { [255; 3] } else { [0; 3] })
+        });
+        img.save(path).unwrap();
+        img
+    }
+
+    fn png() -> Vec<String> {
+        vec!["png".to_string()]
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let dir = tempdir().unwrap();
+        solid(&dir.path().join("a.png"), [255, 0, 0]);
+        solid(&dir.path().join("b.png"), [255, 0, 0]);
+        solid(&dir.path().join("unique.png"), [0, 255, 0]);
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        solid(&dir.path().join("nested").join("c.png"), [255, 0, 0]);
+
+        let root = dir.path().to_string_lossy().to_string();
+        let mut reports = Vec::new();
+        let groups = find_duplicates(&root, &png(), true, &CancellationToken::new(), |p| {
+            reports.push(p)
+        })
+        .unwrap();
+
+        assert_eq!(groups.len(), 1);
+        let names: Vec<&str> = groups[0]
+            .files
+            .iter()
+            .map(|f| f.path.rsplit(['/', '\\']).next().unwrap())
+            .collect();
+        assert_eq!(names, ["a.png", "b.png", "c.png"]);
+        assert_eq!(groups[0].key.len(), 64);
+        let file = &groups[0].files[0];
+        assert_eq!((file.width, file.height), (Some(40), Some(30)));
+        assert!(file.file_size.unwrap() > 0);
+
+        assert_eq!(reports[0].phase, FinderPhase::Scanning);
+        assert!(reports.contains(&progress(FinderPhase::Hashing, 4, 4)));
+        assert_eq!(reports.last(), Some(&progress(FinderPhase::Grouping, 1, 1)));
+
+        // Without recursion the nested copy is left out
+        let flat =
+            find_duplicates(&root, &png(), false, &CancellationToken::new(), |_| {}).unwrap();
+        assert_eq!(flat[0].files.len(), 2);
+    }
+
+    #[test]
+    fn test_find_similar() {
+        let dir = tempdir().unwrap();
+        let base = split(&dir.path().join("base.png"), false);
+        let mut tweaked = base.clone();
+        tweaked.put_pixel(0, 0, Rgb([128; 3]));
+        tweaked.save(dir.path().join("similar.png")).unwrap();
+        split(&dir.path().join("different.png"), true);
+
+        let a = average_hash(&dir.path().join("base.png")).unwrap();
+        let b = average_hash(&dir.path().join("different.png")).unwrap();
+        assert!(hamming_distance(a, b) > DEFAULT_SIMILARITY_THRESHOLD);
+
+        let root = dir.path().to_string_lossy().to_string();
+        let groups = find_similar(
+            &root,
+            &png(),
+            true,
+            DEFAULT_SIMILARITY_THRESHOLD,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].key, "group_0");
+        let names: Vec<&str> = groups[0]
+            .files
+            .iter()
+            .map(|f| f.path.rsplit(['/', '\\']).next().unwrap())
+            .collect();
+        assert_eq!(names, ["base.png", "similar.png"]);
+    }
+
+    #[test]
+    fn test_cancelled_finder_stops() {
+        let dir = tempdir().unwrap();
+        for i in 0..3 {
+            solid(&dir.path().join(format!("{}.png", i)), [9, 9, 9]);
+        }
+        let root = dir.path().to_string_lossy().to_string();
+
+        let token = CancellationToken::new();
+        let mut hashed = 0;
+        let result = find_duplicates(&root, &png(), true, &token, |p| {
+            // Cancel as soon as scanning finishes
+            if p.phase == FinderPhase::Scanning && p.total > 0 {
+                token.cancel();
+            }
+            if p.phase == FinderPhase::Hashing {
+                hashed = p.done;
+            }
+        });
+        assert_eq!(result, Err(Cancelled));
+        assert_eq!(hashed, 0);
+    }
+}
@@ -1,10 +1,25 @@
 mod auth_commands;
 mod benchmark_commands;
+mod converter;
 mod core_commands;
 mod database_commands;
 mod db;
+mod exif_reader;
+mod export;
+mod finder;
+mod library;
+mod merger;
+mod metadata;
+mod paths;
+mod settings;
+mod tasks;
+mod thumbnails;
+mod vault;
 mod video_commands;
+mod videos;
+mod wallpaper;
 mod wallpaper_commands;
+mod web_commands;
 
 use std::env;
 use tauri::Manager;
@@ -13,38 +28,108 @@ use tauri::Manager;
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
+        .manage(auth_commands::AuthState::default())
+        .manage(tasks::TaskManager::default())
         .invoke_handler(tauri::generate_handler![
             // Wallpaper commands
             wallpaper_commands::set_wallpaper,
             wallpaper_commands::get_monitors,
             wallpaper_commands::update_slideshow_config,
             wallpaper_commands::toggle_slideshow_daemon,
+            wallpaper_commands::set_wallpaper_with_history,
+            wallpaper_commands::get_wallpaper_history,
+            wallpaper_commands::reapply_wallpaper,
             // Core file commands
             core_commands::scan_files,
             core_commands::convert_image_batch,
+            core_commands::apply_watermark_batch,
             core_commands::delete_files,
             core_commands::delete_directory,
             core_commands::merge_images,
+            core_commands::generate_thumbnails,
+            core_commands::clear_thumbnail_cache,
+            core_commands::find_duplicate_images,
+            core_commands::find_similar_images,
+            core_commands::cancel_task,
+            core_commands::list_active_tasks,
+            core_commands::get_image_metadata,
+            core_commands::get_image_metadata_batch,
+            // Crawlers and cloud sync
+            web_commands::run_board_crawler,
+            web_commands::run_cloud_sync,
             // Authentication commands
             auth_commands::authenticate_user,
             auth_commands::create_user_account,
             auth_commands::load_user_settings,
             auth_commands::save_user_settings,
+            auth_commands::reset_settings,
             auth_commands::update_master_password,
             // Video processing commands
             video_commands::extract_video_clip,
             video_commands::extract_video_frames,
             video_commands::get_video_metadata,
+            video_commands::detect_hw_encoders,
             // Database commands
             database_commands::search_images,
+            database_commands::save_search,
+            database_commands::list_saved_searches,
+            database_commands::delete_saved_search,
+            database_commands::run_saved_search,
+            database_commands::create_collection,
+            database_commands::rename_collection,
+            database_commands::delete_collection,
+            database_commands::list_collections,
+            database_commands::add_to_collection,
+            database_commands::remove_from_collection,
+            database_commands::reorder_collection,
+            database_commands::get_collection_images,
+            database_commands::search_similar_images,
+            database_commands::set_image_embedding,
             database_commands::get_all_tags,
+            database_commands::search_tags,
+            database_commands::get_unused_tags,
+            database_commands::delete_unused_tags,
+            database_commands::rename_tag,
+            database_commands::merge_tags,
+            database_commands::delete_tag,
             database_commands::get_all_groups,
             database_commands::get_subgroups_for_group,
+            database_commands::get_group_stats,
+            database_commands::rename_group,
+            database_commands::merge_groups,
+            database_commands::delete_group,
+            database_commands::rename_subgroup,
+            database_commands::merge_subgroups,
+            database_commands::delete_subgroup,
             database_commands::add_image_to_database,
+            database_commands::update_image_record,
+            database_commands::add_tags_to_images,
+            database_commands::remove_tags_from_images,
+            database_commands::get_capture_metadata,
+            database_commands::set_image_rating,
+            database_commands::toggle_image_favorite,
+            database_commands::record_image_view,
             database_commands::delete_image_from_database,
+            database_commands::get_recent_changes,
+            database_commands::undo_change,
             database_commands::get_database_stats,
+            database_commands::get_detailed_database_stats,
             database_commands::test_database_connection,
+            database_commands::database_reconnect,
             database_commands::batch_add_images,
+            database_commands::get_image_thumbnails,
+            database_commands::audit_library,
+            database_commands::remove_missing_records,
+            database_commands::import_untracked,
+            database_commands::import_files,
+            database_commands::import_crawled_directory,
+            database_commands::index_directory,
+            database_commands::import_videos,
+            database_commands::export_images,
+            database_commands::export_library,
+            database_commands::import_library,
+            database_commands::relink_images,
+            database_commands::relink_by_content,
             // Benchmark analytics
             benchmark_commands::load_benchmark_reports
         ])
@@ -58,33 +143,43 @@ pub fn run() {
                 )?;
             }
 
-            // Initialize database connection in the background
-            let app_handle = app.handle().clone();
-            tauri::async_runtime::spawn(async move {
-                // Load DATABASE_URL from environment or .env file
-                dotenv::dotenv().ok();
+            // Load DATABASE_URL from environment or .env file; without one,
+            // fall back to a SQLite file in the app data directory
+            dotenv::dotenv().ok();
 
-                let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
-                    log::warn!("DATABASE_URL not found, using default");
-                    "postgresql://localhost/image_toolkit".to_string()
-                });
-
-                match db::Db::new(&database_url).await {
-                    Ok(db_instance) => {
-                        // Initialize schema (ensure pgvector extension)
-                        if let Err(e) = db_instance.init_schema().await {
-                            log::error!("Failed to initialize database schema: {}", e);
-                        } else {
-                            log::info!("Database connected successfully");
+            let database_url = match env::var("DATABASE_URL") {
+                Ok(url) => url,
+                Err(_) => match app.path().app_data_dir() {
+                    Ok(dir) => {
+                        if let Err(e) = std::fs::create_dir_all(&dir) {
+                            log::error!("Failed to create app data directory: {}", e);
                         }
-
-                        app_handle.manage(db_instance);
+                        let path = dir.join("image_toolkit.db");
+                        log::info!("DATABASE_URL not set, using SQLite at {}", path.display());
+                        format!("sqlite://{}", path.display())
                     }
                     Err(e) => {
-                        log::error!("Failed to connect to database: {}", e);
-                        log::warn!("Running without database support");
-                        // Continue without database - some features will be unavailable
+                        log::warn!(
+                            "DATABASE_URL not set and no app data dir ({}), using ./image_toolkit.db",
+                            e
+                        );
+                        "sqlite://image_toolkit.db".to_string()
                     }
+                },
+            };
+            app.manage(database_commands::DatabaseSettings::new(
+                database_url,
+                db::DbConfig::from_env(),
+            ));
+
+            // Connect in the background so a slow database doesn't hold up
+            // the window; until it's up, database commands fail and
+            // `database_reconnect` can be used to try again
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = database_commands::open_database(app_handle).await {
+                    log::error!("{}", e);
+                    log::warn!("Running without database support");
                 }
             });
 
@@ -0,0 +1,1753 @@
+use crate::db::{BatchAddResult, BatchItemError, Db, NewImage, PathRecord};
+use crate::paths::{numbered_filename, sanitize_filename};
+use crate::tasks::{self, CancellationToken, TaskError};
+use anyhow::{anyhow, bail, Context, Result};
+use base::core::file_system::ScanFilter;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use walkdir::WalkDir;
+
+/// Extensions picked up by `scan_files` and the library audit by default
+pub const DEFAULT_IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "bmp"];
+
+/// Walks `directory` and returns the sorted paths of files whose extension is
+/// in `extensions` (compared case-insensitively, leading dots ignored)
+pub fn collect_files(directory: &str, extensions: &[String], recursive: bool) -> Vec<String> {
+    collect_files_matching(directory, extensions, recursive, &ScanFilter::default())
+}
+
+/// `collect_files`, leaving out files outside `filter`'s size and time
+/// bounds. With `max_results`, the walk (in file name order) stops once that
+/// many files are found.
+pub fn collect_files_matching(
+    directory: &str,
+    extensions: &[String],
+    recursive: bool,
+    filter: &ScanFilter,
+) -> Vec<String> {
+    let exts: Vec<String> = extensions
+        .iter()
+        .map(|e| e.trim_start_matches('.').to_lowercase())
+        .collect();
+
+    let mut set = HashSet::new();
+    let walker = if recursive {
+        WalkDir::new(directory)
+    } else {
+        WalkDir::new(directory).max_depth(1)
+    };
+
+    for entry in walker
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if filter.max_results.is_some_and(|max| set.len() >= max) {
+            break;
+        }
+        if entry.file_type().is_file() {
+            if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
+                let ext_lower = ext.to_lowercase();
+                if exts.contains(&ext_lower) && filter.matches_entry(&entry) {
+                    set.insert(entry.path().to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+
+    let mut out: Vec<String> = set.into_iter().collect();
+    out.sort();
+    out
+}
+
+/// Hex SHA-256 of the file's contents, or `None` if it can't be read
+pub fn compute_sha256(path: &Path) -> Option<String> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 65536];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(_) => return None,
+        }
+    }
+
+    Some(hex::encode(hasher.finalize()))
+}
+
+/// Where the database and the filesystem disagree
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditReport {
+    /// Records whose file no longer exists
+    pub missing_files: Vec<PathRecord>,
+    /// Image files under the audited roots with no record
+    pub untracked_files: Vec<String>,
+    /// Records whose stored size or dimensions differ from the file
+    pub mismatched: Vec<MetadataMismatch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataMismatch {
+    pub record: PathRecord,
+    pub actual_file_size: i64,
+    pub actual_width: Option<i32>,
+    pub actual_height: Option<i32>,
+}
+
+/// Size and (if the header decodes) dimensions of an image file
+struct FileFacts {
+    size: i64,
+    dimensions: Option<(i32, i32)>,
+}
+
+fn file_facts(path: &Path) -> Option<FileFacts> {
+    let metadata = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let dimensions = image::image_dimensions(path)
+        .ok()
+        .map(|(w, h)| (w as i32, h as i32));
+    Some(FileFacts {
+        size: metadata.len() as i64,
+        dimensions,
+    })
+}
+
+/// Compares a record with its file. Unknown stored values (NULL, or a zero
+/// file size) and undecodable headers are not treated as mismatches.
+fn check_record(record: PathRecord, facts: &FileFacts) -> Option<MetadataMismatch> {
+    let size_differs = matches!(record.file_size, Some(size) if size > 0 && size != facts.size);
+    let dims_differ = match facts.dimensions {
+        Some((w, h)) => {
+            record.width.is_some_and(|sw| sw != w) || record.height.is_some_and(|sh| sh != h)
+        }
+        None => false,
+    };
+
+    (size_differs || dims_differ).then(|| MetadataMismatch {
+        record,
+        actual_file_size: facts.size,
+        actual_width: facts.dimensions.map(|(w, _)| w),
+        actual_height: facts.dimensions.map(|(_, h)| h),
+    })
+}
+
+/// Cross-references every record against the filesystem, and every image
+/// file under `roots` against the records
+pub async fn audit_library(db: &Db, roots: &[String]) -> Result<AuditReport> {
+    // A missing root is usually an unmounted drive; auditing it would flag
+    // every record under it as missing
+    for root in roots {
+        if !Path::new(root).is_dir() {
+            bail!("Library root '{}' is not a directory", root);
+        }
+    }
+
+    let records: Vec<PathRecord> = db.get_all_paths().try_collect().await?;
+    let roots = roots.to_vec();
+
+    tokio::task::spawn_blocking(move || {
+        let extensions: Vec<String> = DEFAULT_IMAGE_EXTENSIONS.map(String::from).to_vec();
+        let tracked: HashSet<String> = records.iter().map(|r| r.file_path.clone()).collect();
+        let mut report = AuditReport::default();
+
+        for record in records {
+            match file_facts(Path::new(&record.file_path)) {
+                None => report.missing_files.push(record),
+                Some(facts) => report.mismatched.extend(check_record(record, &facts)),
+            }
+        }
+
+        let mut untracked = HashSet::new();
+        for root in &roots {
+            untracked.extend(
+                collect_files(root, &extensions, true)
+                    .into_iter()
+                    .filter(|path| !tracked.contains(path)),
+            );
+        }
+        report.untracked_files = untracked.into_iter().collect();
+        report.untracked_files.sort();
+
+        report
+    })
+    .await
+    .context("Library audit task failed")
+}
+
+/// Deletes the records in `ids` whose files are still missing; ids whose file
+/// has reappeared are left alone. Returns the number of records removed.
+pub async fn remove_missing_records(db: &Db, ids: &[i32]) -> Result<u64> {
+    let wanted: HashSet<i32> = ids.iter().copied().collect();
+    let candidates: Vec<PathRecord> = db
+        .get_all_paths()
+        .try_filter(|record| std::future::ready(wanted.contains(&record.id)))
+        .try_collect()
+        .await?;
+
+    let mut removed = 0;
+    for record in candidates {
+        if !Path::new(&record.file_path).exists() {
+            db.delete_image(record.id).await?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Adds `paths` to the database with their size and dimensions read from
+/// disk, via `batch_add_images`. Unreadable files are reported as item errors.
+pub async fn import_untracked(
+    db: &Db,
+    paths: Vec<String>,
+    group: Option<String>,
+) -> Result<BatchAddResult> {
+    let (images, unreadable) = tokio::task::spawn_blocking(move || {
+        let mut images = Vec::with_capacity(paths.len());
+        let mut unreadable = Vec::new();
+
+        for (index, file_path) in paths.into_iter().enumerate() {
+            match file_facts(Path::new(&file_path)) {
+                Some(facts) => images.push((
+                    index,
+                    NewImage {
+                        file_path,
+                        file_size: Some(facts.size),
+                        width: facts.dimensions.map(|(w, _)| w),
+                        height: facts.dimensions.map(|(_, h)| h),
+                        group_name: group.clone(),
+                        ..Default::default()
+                    },
+                )),
+                None => unreadable.push(BatchItemError {
+                    index,
+                    file_path,
+                    error: "File not found".to_string(),
+                }),
+            }
+        }
+
+        (images, unreadable)
+    })
+    .await
+    .context("Import task failed")?;
+
+    // Error indices from the batch refer to `images`; map them back to `paths`
+    let original: HashMap<usize, usize> = images
+        .iter()
+        .enumerate()
+        .map(|(position, (index, _))| (position, *index))
+        .collect();
+    let mut result = db
+        .batch_add_images(images.into_iter().map(|(_, image)| image).collect())
+        .await?;
+    for error in &mut result.errors {
+        error.index = original[&error.index];
+    }
+    result.errors.extend(unreadable);
+    result.errors.sort_by_key(|e| e.index);
+
+    Ok(result)
+}
+
+/// Files hashed between progress reports and cancellation checks during
+/// `import_files`
+pub const IMPORT_CHUNK: usize = 32;
+
+/// How `import_files` lays out and filters what it copies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Copy into `YYYY/MM` folders under the destination, by capture date
+    /// or else modification time
+    pub date_subfolders: bool,
+    /// Leave out files whose content is already in the library (or earlier
+    /// in the same import)
+    pub skip_duplicates: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            date_subfolders: false,
+            skip_duplicates: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportPhase {
+    Hashing,
+    Copying,
+    Registering,
+}
+
+/// `done` of `total` files through `phase`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportProgress {
+    pub phase: ImportPhase,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportedFile {
+    pub source: String,
+    /// Where the copy was registered
+    pub path: String,
+}
+
+/// A file left out because its content is already at `existing_path`: a
+/// library file, or an earlier file of the same import
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedDuplicate {
+    pub source: String,
+    pub existing_path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportFailure {
+    pub source: String,
+    pub error: String,
+}
+
+/// Outcome of `import_files`, each list in input order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub imported: Vec<ImportedFile>,
+    pub duplicates: Vec<SkippedDuplicate>,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// Copies `paths` into `dest_dir` under sanitized, collision-free names and
+/// registers the copies in `group`/`subgroup` with one `batch_add_images`.
+/// Copies whose registration fails are deleted again, as are all copies if
+/// the import is cancelled, so the library and the folder stay in step.
+#[allow(clippy::too_many_arguments)]
+pub async fn import_files(
+    db: &Db,
+    paths: Vec<String>,
+    dest_dir: &str,
+    group: Option<String>,
+    subgroup: Option<String>,
+    options: ImportOptions,
+    cancel: &CancellationToken,
+    on_progress: impl Fn(ImportProgress) + Send + Sync + 'static,
+) -> Result<ImportSummary, TaskError> {
+    let on_progress = Arc::new(on_progress);
+    let progress = |phase, done, total| ImportProgress { phase, done, total };
+    let mut summary = ImportSummary::default();
+
+    let (token, report) = (cancel.clone(), on_progress.clone());
+    let hashed = tokio::task::spawn_blocking(move || {
+        let hashes = tasks::map_in_chunks(
+            &paths,
+            IMPORT_CHUNK,
+            &token,
+            |done, total| report(progress(ImportPhase::Hashing, done, total)),
+            |source| match std::fs::metadata(source) {
+                Ok(meta) if meta.is_file() => {
+                    compute_sha256(Path::new(source)).ok_or("Failed to read file")
+                }
+                _ => Err("File not found"),
+            },
+        )?;
+        Ok::<_, TaskError>(paths.into_iter().zip(hashes).collect::<Vec<_>>())
+    })
+    .await
+    .context("Import task failed")??;
+
+    let hashes: Vec<String> = hashed
+        .iter()
+        .filter_map(|(_, hash)| hash.as_ref().ok().cloned())
+        .collect();
+    let existing = if options.skip_duplicates {
+        db.paths_by_content_hash(&hashes).await?
+    } else {
+        HashMap::new()
+    };
+    let mut first_seen: HashMap<String, String> = HashMap::new();
+    let mut to_copy = Vec::new();
+    for (source, hash) in hashed {
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(error) => {
+                summary.failed.push(ImportFailure {
+                    source,
+                    error: error.to_string(),
+                });
+                continue;
+            }
+        };
+        if options.skip_duplicates {
+            if let Some(existing_path) = existing.get(&hash).or(first_seen.get(&hash)) {
+                summary.duplicates.push(SkippedDuplicate {
+                    source,
+                    existing_path: existing_path.clone(),
+                });
+                continue;
+            }
+            first_seen.insert(hash.clone(), source.clone());
+        }
+        to_copy.push((source, hash));
+    }
+
+    let (token, report) = (cancel.clone(), on_progress.clone());
+    let dest_dir = PathBuf::from(dest_dir);
+    let copied = tokio::task::spawn_blocking(move || {
+        let total = to_copy.len();
+        let mut copied: Vec<(String, NewImage)> = Vec::with_capacity(total);
+        let mut failed = Vec::new();
+        report(progress(ImportPhase::Copying, 0, total));
+        for (done, (source, hash)) in to_copy.into_iter().enumerate() {
+            if token.is_cancelled() {
+                remove_copies(copied.iter().map(|(_, image)| &image.file_path));
+                return Err(TaskError::Cancelled);
+            }
+            match copy_into_library(&source, &dest_dir, &options, hash) {
+                Ok(mut image) => {
+                    image.group_name = group.clone();
+                    image.subgroup_name = subgroup.clone();
+                    copied.push((source, image));
+                }
+                Err(e) => failed.push(ImportFailure {
+                    source,
+                    error: format!("{:#}", e),
+                }),
+            }
+            report(progress(ImportPhase::Copying, done + 1, total));
+        }
+        Ok((copied, failed))
+    })
+    .await
+    .context("Import task failed")?;
+    let (copied, failed) = copied?;
+    summary.failed.extend(failed);
+
+    if cancel.is_cancelled() {
+        remove_copies(copied.iter().map(|(_, image)| &image.file_path));
+        return Err(TaskError::Cancelled);
+    }
+    on_progress(progress(ImportPhase::Registering, 0, 1));
+    let images: Vec<NewImage> = copied.iter().map(|(_, image)| image.clone()).collect();
+    let result = match db.batch_add_images(images).await {
+        Ok(result) => result,
+        Err(e) => {
+            remove_copies(copied.iter().map(|(_, image)| &image.file_path));
+            return Err(e.context("Failed to register imported files").into());
+        }
+    };
+    let errors: HashMap<usize, String> = result
+        .errors
+        .into_iter()
+        .map(|e| (e.index, e.error))
+        .collect();
+    for (index, (source, image)) in copied.into_iter().enumerate() {
+        match errors.get(&index) {
+            Some(error) => {
+                remove_copies([&image.file_path]);
+                summary.failed.push(ImportFailure {
+                    source,
+                    error: error.clone(),
+                });
+            }
+            None => summary.imported.push(ImportedFile {
+                source,
+                path: image.file_path,
+            }),
+        }
+    }
+    on_progress(progress(ImportPhase::Registering, 1, 1));
+    Ok(summary)
+}
+
+/// Copy `source` into the library folder chosen by `options` and describe
+/// the copy for `batch_add_images`
+fn copy_into_library(
+    source: &str,
+    dest_dir: &Path,
+    options: &ImportOptions,
+    content_hash: String,
+) -> Result<NewImage> {
+    let source_path = Path::new(source);
+    let capture = crate::exif_reader::read_capture_metadata(source_path);
+    let mut dir = dest_dir.to_path_buf();
+    if options.date_subfolders {
+        let date = capture.date_taken.or_else(|| {
+            std::fs::metadata(source_path)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .map(DateTime::<Utc>::from)
+        });
+        if let Some(date) = date {
+            dir = dir
+                .join(date.format("%Y").to_string())
+                .join(date.format("%m").to_string());
+        }
+    }
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let filename = source_path
+        .file_name()
+        .map(|name| sanitize_filename(&name.to_string_lossy()))
+        .unwrap_or_else(|| sanitize_filename(""));
+    let copy = copy_to_free_name(source_path, &dir, &filename)
+        .with_context(|| format!("Failed to copy {}", source))?;
+    let facts = file_facts(&copy);
+    Ok(NewImage {
+        file_path: copy.to_string_lossy().to_string(),
+        file_size: facts.as_ref().map(|f| f.size),
+        width: facts.as_ref().and_then(|f| f.dimensions).map(|(w, _)| w),
+        height: facts.as_ref().and_then(|f| f.dimensions).map(|(_, h)| h),
+        capture: Some(capture),
+        content_hash: Some(content_hash),
+        ..Default::default()
+    })
+}
+
+/// Copy `source` to `dir/filename`, or `dir/filename (n)` for the first n
+/// whose file doesn't exist yet. Names are claimed with `create_new`, so
+/// concurrent imports can't overwrite each other.
+fn copy_to_free_name(source: &Path, dir: &Path, filename: &str) -> std::io::Result<PathBuf> {
+    let mut input = File::open(source)?;
+    let mut attempt = 0;
+    loop {
+        let candidate = dir.join(numbered_filename(filename, attempt));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(mut output) => {
+                return match std::io::copy(&mut input, &mut output) {
+                    Ok(_) => Ok(candidate),
+                    Err(e) => {
+                        let _ = std::fs::remove_file(&candidate);
+                        Err(e)
+                    }
+                };
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn remove_copies<'a>(paths: impl IntoIterator<Item = &'a String>) {
+    for path in paths {
+        if let Err(e) = std::fs::remove_file(path) {
+            log::warn!("Failed to remove imported copy {}: {}", path, e);
+        }
+    }
+}
+
+/// Outcome of `relink_by_content`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RelinkReport {
+    /// Records pointed at a file found under the searched directory
+    pub relinked: u64,
+    /// Records whose file is still missing
+    pub still_missing: u64,
+}
+
+/// Finds moved files for records whose file is missing, by hashing the image
+/// files under `directory` and matching them against the stored content
+/// hashes. Records imported before hashes were stored can't be matched.
+pub async fn relink_by_content(db: &Db, directory: &str) -> Result<RelinkReport> {
+    if !Path::new(directory).is_dir() {
+        bail!("'{}' is not a directory", directory);
+    }
+
+    let records: Vec<PathRecord> = db.get_all_paths().try_collect().await?;
+    let directory = directory.to_string();
+
+    let (moves, still_missing) = tokio::task::spawn_blocking(move || {
+        let tracked: HashSet<String> = records
+            .iter()
+            .map(|r| crate::paths::path_key(&r.file_path))
+            .collect();
+        let missing: Vec<PathRecord> = records
+            .into_iter()
+            .filter(|r| !Path::new(&r.file_path).exists())
+            .collect();
+
+        let mut by_hash: HashMap<&str, Vec<&PathRecord>> = HashMap::new();
+        for record in &missing {
+            if let Some(hash) = &record.content_hash {
+                by_hash.entry(hash).or_default().push(record);
+            }
+        }
+        // Only hash files whose size matches a missing record, unless some
+        // record's size is unknown
+        let sizes: Option<HashSet<i64>> = by_hash
+            .values()
+            .flatten()
+            .map(|r| r.file_size.filter(|&size| size > 0))
+            .collect();
+
+        let extensions: Vec<String> = DEFAULT_IMAGE_EXTENSIONS.map(String::from).to_vec();
+        let mut moves = Vec::new();
+        for candidate in collect_files(&directory, &extensions, true) {
+            if by_hash.is_empty() {
+                break;
+            }
+            if tracked.contains(&crate::paths::path_key(&candidate)) {
+                continue;
+            }
+            let path = Path::new(&candidate);
+            if let Some(sizes) = &sizes {
+                let size = std::fs::metadata(path).map(|m| m.len() as i64);
+                if !size.is_ok_and(|size| sizes.contains(&size)) {
+                    continue;
+                }
+            }
+
+            let Some(hash) = compute_sha256(path) else {
+                continue;
+            };
+            // Each file takes the place of one record; identical copies can
+            // satisfy several
+            if let Some(waiting) = by_hash.get_mut(hash.as_str()) {
+                let record = waiting.remove(0);
+                if waiting.is_empty() {
+                    by_hash.remove(hash.as_str());
+                }
+                moves.push((record.id, candidate));
+            }
+        }
+
+        let still_missing = (missing.len() - moves.len()) as u64;
+        (moves, still_missing)
+    })
+    .await
+    .context("Relink task failed")?;
+
+    let relinked = db.set_image_paths(moves).await?;
+    Ok(RelinkReport {
+        relinked,
+        still_missing,
+    })
+}
+
+/// Tags, dimensions and source of a board post, from the `.json` sidecar the
+/// board crawlers save next to each download
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PostMetadata {
+    pub tags: Vec<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub source_url: Option<String>,
+}
+
+/// Reads a sidecar from any board the crawlers support. Tags come from
+/// Danbooru's `tag_string`, or from `tags` as a space-separated string
+/// (Gelbooru, Moebooru), an array of names (Derpibooru) or of tag objects
+/// (Sankaku), or e621's object of per-category arrays.
+pub fn post_metadata(post: &Value) -> PostMetadata {
+    let mut tags = Vec::new();
+    if let Some(value) = post.get("tag_string").or_else(|| post.get("tags")) {
+        collect_tags(value, &mut tags);
+    }
+    tags.sort();
+    tags.dedup();
+
+    let dimension = |pointers: [&str; 3]| {
+        pointers
+            .iter()
+            .find_map(|pointer| post.pointer(pointer)?.as_i64())
+            .filter(|&n| n > 0)
+            .map(|n| n as i32)
+    };
+    let source_url = ["/source", "/source_url", "/sources/0"]
+        .iter()
+        .filter_map(|pointer| post.pointer(pointer)?.as_str())
+        .map(str::trim)
+        .find(|source| !source.is_empty())
+        .map(String::from);
+
+    PostMetadata {
+        tags,
+        width: dimension(["/image_width", "/width", "/file/width"]),
+        height: dimension(["/image_height", "/height", "/file/height"]),
+        source_url,
+    }
+}
+
+fn collect_tags(value: &Value, tags: &mut Vec<String>) {
+    match value {
+        Value::String(list) => tags.extend(list.split_whitespace().map(String::from)),
+        Value::Array(items) => {
+            for item in items {
+                let name = match item {
+                    Value::String(name) => Some(name.as_str()),
+                    tag => tag.get("name").and_then(|v| v.as_str()),
+                };
+                tags.extend(
+                    name.map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(String::from),
+                );
+            }
+        }
+        Value::Object(categories) => {
+            for list in categories.values() {
+                collect_tags(list, tags);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Outcome of `import_crawled_directory`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CrawlImportSummary {
+    pub inserted: usize,
+    /// Images already in the library, whose record was refreshed
+    pub updated: usize,
+    /// Images without a readable sidecar
+    pub skipped: usize,
+    pub errors: Vec<BatchItemError>,
+}
+
+/// Adds the images a board crawler saved under `directory` to the library
+/// in `group`/`subgroup`, tagged from their sidecars. Dimensions are read
+/// from the files when their headers decode, since the crawlers sometimes
+/// save a sample rather than the original the sidecar describes. Images
+/// already in the library keep the tags they had and gain the board's.
+pub async fn import_crawled_directory(
+    db: &Db,
+    directory: &str,
+    group: Option<String>,
+    subgroup: Option<String>,
+    cancel: &CancellationToken,
+    on_progress: impl Fn(usize, usize) + Send + 'static,
+) -> Result<CrawlImportSummary, TaskError> {
+    if !Path::new(directory).is_dir() {
+        return Err(anyhow!("'{}' is not a directory", directory).into());
+    }
+
+    let directory = directory.to_string();
+    let token = cancel.clone();
+    let read = tokio::task::spawn_blocking(move || {
+        let extensions: Vec<String> = DEFAULT_IMAGE_EXTENSIONS.map(String::from).to_vec();
+        let files = collect_files(&directory, &extensions, true);
+        let posts = tasks::map_in_chunks(&files, IMPORT_CHUNK, &token, on_progress, |file| {
+            let path = Path::new(file);
+            let sidecar = std::fs::read_to_string(path.with_extension("json")).ok()?;
+            let metadata = post_metadata(&serde_json::from_str(&sidecar).ok()?);
+            let facts = file_facts(path)?;
+            let (width, height) = match facts.dimensions {
+                Some((width, height)) => (Some(width), Some(height)),
+                None => (metadata.width, metadata.height),
+            };
+            let image = NewImage {
+                file_path: crate::paths::normalize_path(file),
+                file_size: Some(facts.size),
+                width,
+                height,
+                group_name: group.clone(),
+                subgroup_name: subgroup.clone(),
+                source_url: metadata.source_url,
+                ..Default::default()
+            };
+            Some((image, metadata.tags))
+        })?;
+        Ok::<_, TaskError>(posts)
+    })
+    .await
+    .context("Import task failed")??;
+
+    let mut summary = CrawlImportSummary {
+        skipped: read.iter().filter(|post| post.is_none()).count(),
+        ..Default::default()
+    };
+    let (images, tags): (Vec<NewImage>, Vec<Vec<String>>) = read.into_iter().flatten().unzip();
+    let tags: HashMap<String, Vec<String>> = images
+        .iter()
+        .zip(tags)
+        .map(|(image, tags)| (crate::paths::path_key(&image.file_path), tags))
+        .collect();
+
+    let result = db.batch_add_images(images).await?;
+    summary.inserted = result.inserted.len();
+    summary.updated = result.updated.len();
+    summary.errors = result.errors;
+
+    // Posts with the same tags are tagged together
+    let ids: Vec<i32> = result.inserted.into_iter().chain(result.updated).collect();
+    let mut by_tags: HashMap<&[String], Vec<i32>> = HashMap::new();
+    for record in db.get_path_records(&ids).await? {
+        if let Some(tags) = tags.get(&crate::paths::path_key(&record.file_path)) {
+            if !tags.is_empty() {
+                by_tags.entry(tags).or_default().push(record.id);
+            }
+        }
+    }
+    for (tags, ids) in by_tags {
+        db.add_tags_to_images(&ids, tags).await?;
+    }
+
+    Ok(summary)
+}
+
+/// Files walked, probed and added to the database at a time by
+/// `index_directory`
+pub const INDEX_CHUNK: usize = 1000;
+
+/// How `index_directory` files images by the folders they sit in under the
+/// root
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupMapping {
+    /// Leave them out of groups
+    #[default]
+    #[serde(rename = "none")]
+    Ungrouped,
+    /// The first folder names the group
+    Group,
+    /// The first folder names the group and the second the subgroup
+    GroupAndSubgroup,
+}
+
+impl GroupMapping {
+    /// Group and subgroup of the file at `relative` under the root. Folders
+    /// the file isn't deep enough to have are left `None`.
+    fn names(self, relative: &Path) -> (Option<String>, Option<String>) {
+        let mut folders = relative
+            .parent()
+            .into_iter()
+            .flat_map(|parent| parent.components())
+            .map(|c| c.as_os_str().to_string_lossy().to_string());
+        match self {
+            GroupMapping::Ungrouped => (None, None),
+            GroupMapping::Group => (folders.next(), None),
+            GroupMapping::GroupAndSubgroup => (folders.next(), folders.next()),
+        }
+    }
+}
+
+/// What `index_directory` picks up and how it files it
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// Compared case-insensitively, leading dots ignored
+    pub extensions: Vec<String>,
+    pub group_mapping: GroupMapping,
+    pub chunk_size: usize,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            extensions: DEFAULT_IMAGE_EXTENSIONS.map(String::from).to_vec(),
+            group_mapping: GroupMapping::default(),
+            chunk_size: INDEX_CHUNK,
+        }
+    }
+}
+
+/// Where an `index_directory` run got to, saved after every chunk so an
+/// interrupted run can carry on from there
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexCheckpoint {
+    pub root: String,
+    pub extensions: Vec<String>,
+    pub group_mapping: GroupMapping,
+    /// Files handled so far, in walk order
+    pub done: usize,
+    /// The last of them, to tell whether the tree still walks the same way
+    pub last_path: Option<String>,
+    pub inserted: usize,
+    pub updated: usize,
+    pub failed: usize,
+}
+
+impl IndexCheckpoint {
+    /// The checkpoint at `path`, if there is a readable one
+    pub fn load(path: &Path) -> Option<Self> {
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Written to a temporary file first, so a crash mid-write leaves the
+    /// previous checkpoint in place
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_string(self)?)?;
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    fn same_run(&self, other: &IndexCheckpoint) -> bool {
+        self.root == other.root
+            && self.extensions == other.extensions
+            && self.group_mapping == other.group_mapping
+    }
+}
+
+/// How far `index_directory` has got, counting what earlier, interrupted
+/// runs added
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexProgress {
+    pub done: usize,
+    pub total: usize,
+    pub inserted: usize,
+    pub updated: usize,
+    pub failed: usize,
+    /// From this run's pace so far; `None` until a chunk is in
+    pub eta_secs: Option<u64>,
+}
+
+/// Outcome of `index_directory`. The counts include earlier, interrupted
+/// runs; `errors` only this one's.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexSummary {
+    pub total: usize,
+    /// Files an earlier run had already handled
+    pub resumed_from: usize,
+    pub inserted: usize,
+    pub updated: usize,
+    pub failed: usize,
+    /// Indexed by position in the walk
+    pub errors: Vec<BatchItemError>,
+}
+
+/// Files under `root` with one of `extensions` (already lowercased), in a
+/// fixed walk order
+fn indexable_files<'a>(
+    root: &Path,
+    extensions: &'a [String],
+) -> impl Iterator<Item = PathBuf> + 'a {
+    WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(move |path| {
+            path.extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|ext| extensions.contains(&ext.to_lowercase()))
+        })
+}
+
+/// A walked file, with its record or why it has none
+type IndexedFile = (usize, String, Result<NewImage, String>);
+
+fn index_file(root: &Path, path: &Path, mapping: GroupMapping) -> Result<NewImage, String> {
+    let facts = file_facts(path).ok_or("File not found")?;
+    let (group_name, subgroup_name) = mapping.names(path.strip_prefix(root).unwrap_or(path));
+    Ok(NewImage {
+        file_path: crate::paths::normalize_path(&path.to_string_lossy()),
+        file_size: Some(facts.size),
+        width: facts.dimensions.map(|(w, _)| w),
+        height: facts.dimensions.map(|(_, h)| h),
+        group_name,
+        subgroup_name,
+        ..Default::default()
+    })
+}
+
+/// Adds every image under `root` to the library, `chunk_size` files at a
+/// time: the walk, the dimension probing and the insert all go by chunk, so
+/// a tree of hundreds of thousands of files is never held at once. Each
+/// chunk is committed and then recorded in the checkpoint at
+/// `checkpoint_path`; a cancelled or crashed run keeps what it committed
+/// and, run again with the same options, carries on after it. The
+/// checkpoint is removed once the whole tree is in.
+pub async fn index_directory(
+    db: &Db,
+    root: &str,
+    options: IndexOptions,
+    checkpoint_path: &Path,
+    cancel: &CancellationToken,
+    on_progress: impl Fn(IndexProgress) + Send + Sync,
+) -> Result<IndexSummary, TaskError> {
+    if !Path::new(root).is_dir() {
+        return Err(anyhow!("'{}' is not a directory", root).into());
+    }
+    let extensions: Vec<String> = options
+        .extensions
+        .iter()
+        .map(|e| e.trim_start_matches('.').to_lowercase())
+        .collect();
+    let fresh = IndexCheckpoint {
+        root: root.to_string(),
+        extensions: extensions.clone(),
+        group_mapping: options.group_mapping,
+        ..Default::default()
+    };
+    let saved = IndexCheckpoint::load(checkpoint_path).filter(|saved| saved.same_run(&fresh));
+
+    // Counting first gives the progress a total, and tells whether the
+    // checkpoint's last file is still where it was in the walk
+    let (root_path, exts, token) = (PathBuf::from(root), extensions.clone(), cancel.clone());
+    let (total, resumed) = tokio::task::spawn_blocking(move || {
+        let mut total = 0;
+        let mut lines_up = false;
+        for path in indexable_files(&root_path, &exts) {
+            if total % INDEX_CHUNK == 0 {
+                token.check()?;
+            }
+            total += 1;
+            if let Some(saved) = &saved {
+                lines_up |= total == saved.done
+                    && saved.last_path.as_deref() == Some(&*path.to_string_lossy());
+            }
+        }
+        Ok::<_, TaskError>((total, saved.filter(|_| lines_up)))
+    })
+    .await
+    .context("Index task failed")??;
+
+    let mut state = resumed.unwrap_or(fresh);
+    let resumed_from = state.done;
+
+    let (sender, mut receiver) = tokio::sync::mpsc::channel::<Vec<IndexedFile>>(1);
+    let (root_path, token) = (PathBuf::from(root), cancel.clone());
+    let chunk_size = options.chunk_size.max(1);
+    let mapping = options.group_mapping;
+    // Walks and probes the next chunk while the last one is being inserted
+    let producer = tokio::task::spawn_blocking(move || {
+        let mut files = indexable_files(&root_path, &extensions)
+            .enumerate()
+            .skip(resumed_from)
+            .peekable();
+        while files.peek().is_some() {
+            let chunk: Vec<(usize, PathBuf)> = files.by_ref().take(chunk_size).collect();
+            let indexed = tasks::map_in_chunks(
+                &chunk,
+                IMPORT_CHUNK,
+                &token,
+                |_, _| {},
+                |(index, path)| {
+                    (
+                        *index,
+                        path.to_string_lossy().to_string(),
+                        index_file(&root_path, path, mapping),
+                    )
+                },
+            )?;
+            if sender.blocking_send(indexed).is_err() {
+                // The inserting side stopped
+                break;
+            }
+        }
+        Ok::<_, TaskError>(())
+    });
+
+    let started = std::time::Instant::now();
+    let report = |state: &IndexCheckpoint| {
+        let handled = state.done - resumed_from;
+        let eta_secs = (handled > 0).then(|| {
+            let per_file = started.elapsed().as_secs_f64() / handled as f64;
+            (per_file * total.saturating_sub(state.done) as f64).round() as u64
+        });
+        on_progress(IndexProgress {
+            done: state.done,
+            total,
+            inserted: state.inserted,
+            updated: state.updated,
+            failed: state.failed,
+            eta_secs,
+        });
+    };
+    report(&state);
+
+    let mut errors = Vec::new();
+    while let Some(chunk) = receiver.recv().await {
+        let count = chunk.len();
+        let last_path = chunk.last().map(|(_, path, _)| path.clone());
+        let failed_before = errors.len();
+        let mut images = Vec::with_capacity(count);
+        let mut positions = Vec::with_capacity(count);
+        for (index, file_path, indexed) in chunk {
+            match indexed {
+                Ok(image) => {
+                    positions.push(index);
+                    images.push(image);
+                }
+                Err(error) => errors.push(BatchItemError {
+                    index,
+                    file_path,
+                    error,
+                }),
+            }
+        }
+
+        let result = db.batch_add_images(images).await?;
+        // Error indices from the batch refer to this chunk's images
+        errors.extend(result.errors.into_iter().map(|mut error| {
+            error.index = positions[error.index];
+            error
+        }));
+        state.done += count;
+        state.inserted += result.inserted.len();
+        state.updated += result.updated.len();
+        state.failed += errors.len() - failed_before;
+        state.last_path = last_path;
+        state
+            .save(checkpoint_path)
+            .context("Failed to save the index checkpoint")?;
+        report(&state);
+
+        if cancel.is_cancelled() {
+            break;
+        }
+    }
+    drop(receiver);
+    producer.await.context("Index task failed")??;
+    cancel.check()?;
+
+    let _ = std::fs::remove_file(checkpoint_path);
+    errors.sort_by_key(|e| e.index);
+    Ok(IndexSummary {
+        total,
+        resumed_from,
+        inserted: state.inserted,
+        updated: state.updated,
+        failed: state.failed,
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn write_png(path: &Path, width: u32, height: u32) {
+        image::RgbImage::new(width, height).save(path).unwrap();
+    }
+
+    #[test]
+    fn test_collect_files_filters_extensions() {
+        let temp = tempdir().unwrap();
+        std::fs::create_dir(temp.path().join("nested")).unwrap();
+        std::fs::write(temp.path().join("a.PNG"), b"").unwrap();
+        std::fs::write(temp.path().join("notes.txt"), b"").unwrap();
+        std::fs::write(temp.path().join("nested/b.jpg"), b"").unwrap();
+
+        let root = temp.path().to_str().unwrap();
+        let exts = vec![".png".to_string(), "jpg".to_string()];
+        assert_eq!(collect_files(root, &exts, true).len(), 2);
+        assert_eq!(collect_files(root, &exts, false).len(), 1);
+    }
+
+    #[test]
+    fn test_collect_files_matching() {
+        let temp = tempdir().unwrap();
+        std::fs::write(temp.path().join("a.png"), [0u8; 10]).unwrap();
+        std::fs::write(temp.path().join("b.png"), [0u8; 500]).unwrap();
+        std::fs::write(temp.path().join("c.png"), [0u8; 500]).unwrap();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(86_400 * 30);
+        File::options()
+            .write(true)
+            .open(temp.path().join("c.png"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let root = temp.path().to_str().unwrap();
+        let exts = vec!["png".to_string()];
+        let names = |filter: ScanFilter| -> Vec<String> {
+            collect_files_matching(root, &exts, true, &filter)
+                .iter()
+                .map(|p| {
+                    Path::new(p)
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect()
+        };
+        let week_ago = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64()
+            - 86_400.0 * 7.0;
+
+        let big = ScanFilter {
+            min_size_bytes: Some(100),
+            ..ScanFilter::default()
+        };
+        assert_eq!(names(big), ["b.png", "c.png"]);
+        let small = ScanFilter {
+            max_size_bytes: Some(100),
+            ..ScanFilter::default()
+        };
+        assert_eq!(names(small), ["a.png"]);
+        let recent = ScanFilter {
+            modified_after: Some(week_ago),
+            ..ScanFilter::default()
+        };
+        assert_eq!(names(recent), ["a.png", "b.png"]);
+        let older = ScanFilter {
+            modified_before: Some(week_ago),
+            ..ScanFilter::default()
+        };
+        assert_eq!(names(older), ["c.png"]);
+        let first_two = ScanFilter {
+            max_results: Some(2),
+            ..ScanFilter::default()
+        };
+        assert_eq!(names(first_two), ["a.png", "b.png"]);
+    }
+
+    #[tokio::test]
+    async fn test_audit_and_repair() {
+        let temp = tempdir().unwrap();
+        let path = |name: &str| temp.path().join(name).to_string_lossy().to_string();
+        write_png(temp.path().join("good.png").as_path(), 4, 3);
+        write_png(temp.path().join("resized.png").as_path(), 8, 8);
+        write_png(temp.path().join("new.png").as_path(), 2, 5);
+
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let good_size = std::fs::metadata(path("good.png")).unwrap().len() as i64;
+        let added = db
+            .batch_add_images(vec![
+                NewImage {
+                    file_path: path("good.png"),
+                    file_size: Some(good_size),
+                    width: Some(4),
+                    height: Some(3),
+                    ..Default::default()
+                },
+                NewImage {
+                    file_path: path("resized.png"),
+                    width: Some(4),
+                    height: Some(4),
+                    ..Default::default()
+                },
+                NewImage {
+                    file_path: path("gone.png"),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+        let [good, resized, gone] = added.inserted[..] else {
+            panic!("expected three inserts, got {:?}", added);
+        };
+
+        let roots = vec![temp.path().to_string_lossy().to_string()];
+        let report = audit_library(&db, &roots).await.unwrap();
+        let missing: Vec<i32> = report.missing_files.iter().map(|r| r.id).collect();
+        assert_eq!(missing, [gone]);
+        assert_eq!(report.untracked_files, [path("new.png")]);
+        assert_eq!(report.mismatched.len(), 1);
+        assert_eq!(report.mismatched[0].record.id, resized);
+        assert_eq!(report.mismatched[0].actual_width, Some(8));
+
+        // Only records whose files are actually missing get removed
+        assert_eq!(remove_missing_records(&db, &[gone, good]).await.unwrap(), 1);
+
+        let result = import_untracked(
+            &db,
+            vec![path("new.png"), path("vanished.png")],
+            Some("Imported".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.inserted.len(), 1);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].index, 1);
+
+        let report = audit_library(&db, &roots).await.unwrap();
+        assert!(report.missing_files.is_empty());
+        assert!(report.untracked_files.is_empty());
+        assert_eq!(report.mismatched.len(), 1);
+        assert_eq!(db.get_all_groups().await.unwrap(), ["Imported"]);
+    }
+
+    #[tokio::test]
+    async fn test_relink_by_content() {
+        let temp = tempdir().unwrap();
+        let old_dir = temp.path().join("old");
+        let new_dir = temp.path().join("moved");
+        std::fs::create_dir(&old_dir).unwrap();
+        std::fs::create_dir_all(new_dir.join("nested")).unwrap();
+        write_png(&old_dir.join("a.png"), 4, 3);
+        write_png(&old_dir.join("b.png"), 5, 5);
+        write_png(&old_dir.join("c.png"), 6, 2);
+
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let images = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|name| NewImage {
+                file_path: old_dir.join(name).to_string_lossy().to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let added = db.batch_add_images(images).await.unwrap();
+        let [a, b, _c] = added.inserted[..] else {
+            panic!("expected three inserts, got {:?}", added);
+        };
+
+        // a is moved and renamed, b is moved, c is deleted
+        std::fs::rename(old_dir.join("a.png"), new_dir.join("nested/renamed.png")).unwrap();
+        std::fs::rename(old_dir.join("b.png"), new_dir.join("b.png")).unwrap();
+        std::fs::remove_file(old_dir.join("c.png")).unwrap();
+        write_png(&new_dir.join("unrelated.png"), 7, 7);
+
+        let report = relink_by_content(&db, &new_dir.to_string_lossy())
+            .await
+            .unwrap();
+        assert_eq!(report.relinked, 2);
+        assert_eq!(report.still_missing, 1);
+
+        let records = db.get_path_records(&[a, b]).await.unwrap();
+        for record in records {
+            assert!(Path::new(&record.file_path).exists(), "{:?}", record);
+            assert!(record.file_path.starts_with(&*new_dir.to_string_lossy()));
+        }
+
+        // Nothing left to match
+        let report = relink_by_content(&db, &new_dir.to_string_lossy())
+            .await
+            .unwrap();
+        assert_eq!(report.relinked, 0);
+        assert_eq!(report.still_missing, 1);
+    }
+
+    /// Runs `import_files` into `dest`, collecting the phases reported
+    async fn run_import(
+        db: &Db,
+        paths: Vec<String>,
+        dest: &Path,
+        options: ImportOptions,
+    ) -> (ImportSummary, Vec<ImportPhase>) {
+        let phases = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = phases.clone();
+        let summary = import_files(
+            db,
+            paths,
+            &dest.to_string_lossy(),
+            Some("Dropped".to_string()),
+            Some("Batch".to_string()),
+            options,
+            &CancellationToken::new(),
+            move |p| seen.lock().unwrap().push(p.phase),
+        )
+        .await
+        .unwrap();
+        let mut phases = phases.lock().unwrap().clone();
+        phases.dedup();
+        (summary, phases)
+    }
+
+    #[tokio::test]
+    async fn test_import_files() {
+        let temp = tempdir().unwrap();
+        let (drop_a, drop_b) = (temp.path().join("a"), temp.path().join("b"));
+        let library = temp.path().join("library");
+        for dir in [&drop_a, &drop_b, &library] {
+            std::fs::create_dir(dir).unwrap();
+        }
+        // Same name, different content; and a name that needs cleaning
+        write_png(&drop_a.join("photo.png"), 4, 3);
+        write_png(&drop_b.join("photo.png"), 6, 2);
+        write_png(&drop_a.join("what?.png"), 2, 2);
+        let source = |dir: &Path, name: &str| dir.join(name).to_string_lossy().to_string();
+        let paths = vec![
+            source(&drop_a, "photo.png"),
+            source(&drop_b, "photo.png"),
+            source(&drop_a, "what?.png"),
+            source(&drop_a, "gone.png"),
+        ];
+
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let (summary, phases) = run_import(&db, paths.clone(), &library, Default::default()).await;
+        assert_eq!(
+            phases,
+            [
+                ImportPhase::Hashing,
+                ImportPhase::Copying,
+                ImportPhase::Registering
+            ]
+        );
+        let names: Vec<String> = summary
+            .imported
+            .iter()
+            .map(|f| {
+                Path::new(&f.path)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(names, ["photo.png", "photo (1).png", "what_.png"]);
+        assert_eq!(summary.imported[1].source, paths[1]);
+        assert!(summary.duplicates.is_empty());
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].source, paths[3]);
+        assert!(
+            Path::new(&paths[0]).exists(),
+            "sources are copied, not moved"
+        );
+
+        let records = db.get_all_paths().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(records.len(), 3);
+        let second = records
+            .iter()
+            .find(|r| r.file_path.ends_with("photo (1).png"))
+            .unwrap();
+        assert_eq!((second.width, second.height), (Some(6), Some(2)));
+        assert_eq!(second.content_hash, compute_sha256(Path::new(&paths[1])));
+        assert_eq!(db.get_all_groups().await.unwrap(), ["Dropped"]);
+
+        // Dated folders come from the modification time without EXIF
+        let dated = temp.path().join("dated");
+        let options = ImportOptions {
+            date_subfolders: true,
+            skip_duplicates: false,
+        };
+        let (summary, _) = run_import(&db, paths[..1].to_vec(), &dated, options).await;
+        let modified: DateTime<Utc> = std::fs::metadata(&paths[0])
+            .unwrap()
+            .modified()
+            .unwrap()
+            .into();
+        let expected = dated
+            .join(modified.format("%Y").to_string())
+            .join(modified.format("%m").to_string())
+            .join("photo.png");
+        assert_eq!(Path::new(&summary.imported[0].path), expected);
+    }
+
+    #[tokio::test]
+    async fn test_import_skips_duplicates() {
+        let temp = tempdir().unwrap();
+        let library = temp.path().join("library");
+        let incoming = temp.path().join("incoming");
+        std::fs::create_dir_all(&library).unwrap();
+        std::fs::create_dir_all(&incoming).unwrap();
+        write_png(&library.join("kept.png"), 5, 5);
+        write_png(&incoming.join("copy.png"), 5, 5);
+        write_png(&incoming.join("new.png"), 3, 1);
+        std::fs::copy(incoming.join("new.png"), incoming.join("new again.png")).unwrap();
+
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        import_untracked(
+            &db,
+            vec![library.join("kept.png").to_string_lossy().to_string()],
+            None,
+        )
+        .await
+        .unwrap();
+        let kept = db.get_all_paths().try_collect::<Vec<_>>().await.unwrap()[0]
+            .file_path
+            .clone();
+
+        let paths: Vec<String> = ["copy.png", "new.png", "new again.png"]
+            .iter()
+            .map(|name| incoming.join(name).to_string_lossy().to_string())
+            .collect();
+        let (summary, _) = run_import(&db, paths.clone(), &library, Default::default()).await;
+        assert_eq!(summary.imported.len(), 1);
+        assert_eq!(summary.imported[0].source, paths[1]);
+        assert_eq!(
+            summary.duplicates,
+            [
+                SkippedDuplicate {
+                    source: paths[0].clone(),
+                    existing_path: kept,
+                },
+                SkippedDuplicate {
+                    source: paths[2].clone(),
+                    existing_path: paths[1].clone(),
+                },
+            ]
+        );
+        assert!(summary.failed.is_empty());
+        assert_eq!(std::fs::read_dir(&library).unwrap().count(), 2);
+
+        // With skipping off, the same content is imported again
+        let options = ImportOptions {
+            skip_duplicates: false,
+            ..Default::default()
+        };
+        let (summary, _) = run_import(&db, paths[..1].to_vec(), &library, options).await;
+        assert_eq!(summary.imported.len(), 1);
+        assert!(summary.duplicates.is_empty());
+    }
+
+    #[test]
+    fn test_post_metadata() {
+        let danbooru = json!({
+            "id": 7412093,
+            "image_width": 1920,
+            "image_height": 1080,
+            "tag_string": "1girl scenery  sky",
+            "source": "https://twitter.com/someartist/status/1785000000000000000",
+            "file_url": "https://cdn.donmai.us/original/ab/cd/abcd.jpg"
+        });
+        assert_eq!(
+            post_metadata(&danbooru),
+            PostMetadata {
+                tags: vec!["1girl".into(), "scenery".into(), "sky".into()],
+                width: Some(1920),
+                height: Some(1080),
+                source_url: Some(
+                    "https://twitter.com/someartist/status/1785000000000000000".into()
+                ),
+            }
+        );
+
+        let gelbooru = json!({"tags": "sky cloud sky", "width": 800, "height": 600, "source": ""});
+        let metadata = post_metadata(&gelbooru);
+        assert_eq!(metadata.tags, ["cloud", "sky"]);
+        assert_eq!(metadata.source_url, None);
+
+        let sankaku = json!({
+            "width": 1200,
+            "height": 900,
+            "source": "pixiv #1234",
+            "tags": [{"id": 1, "name": "original", "type": 0}, {"id": 2, "name": "rain"}]
+        });
+        assert_eq!(post_metadata(&sankaku).tags, ["original", "rain"]);
+
+        let e621 = json!({
+            "file": {"width": 2480, "height": 3508},
+            "tags": {"general": ["wolf", "snow"], "species": ["canine"], "artist": []},
+            "sources": ["https://www.furaffinity.net/view/52411234/"]
+        });
+        let metadata = post_metadata(&e621);
+        assert_eq!(metadata.tags, ["canine", "snow", "wolf"]);
+        assert_eq!((metadata.width, metadata.height), (Some(2480), Some(3508)));
+        assert_eq!(
+            metadata.source_url.as_deref(),
+            Some("https://www.furaffinity.net/view/52411234/")
+        );
+
+        let derpibooru = json!({
+            "tags": ["safe", "artist:some artist"],
+            "source_url": "https://example.com/post/1"
+        });
+        let metadata = post_metadata(&derpibooru);
+        assert_eq!(metadata.tags, ["artist:some artist", "safe"]);
+        assert_eq!(
+            metadata.source_url.as_deref(),
+            Some("https://example.com/post/1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_crawled_directory() {
+        let temp = tempdir().unwrap();
+        let crawl = temp.path().join("crawl");
+        std::fs::create_dir(&crawl).unwrap();
+        // A sample saved in place of the 1920x1080 original
+        write_png(&crawl.join("1_aa.png"), 4, 3);
+        std::fs::write(
+            crawl.join("1_aa.json"),
+            json!({"image_width": 1920, "image_height": 1080, "tag_string": "sky cloud",
+                   "source": "https://example.com/1"})
+            .to_string(),
+        )
+        .unwrap();
+        write_png(&crawl.join("2_bb.png"), 2, 2);
+        std::fs::write(crawl.join("2_bb.json"), json!({"tags": "sky"}).to_string()).unwrap();
+        write_png(&crawl.join("3_cc.png"), 2, 2);
+        std::fs::write(crawl.join("4_dd.png"), b"not a png").unwrap();
+        std::fs::write(
+            crawl.join("4_dd.json"),
+            json!({"width": 50, "height": 40}).to_string(),
+        )
+        .unwrap();
+
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let cancel = CancellationToken::new();
+        let import = || {
+            import_crawled_directory(
+                &db,
+                crawl.to_str().unwrap(),
+                Some("Crawled".to_string()),
+                None,
+                &cancel,
+                |_, _| {},
+            )
+        };
+        let summary = import().await.unwrap();
+        assert_eq!(
+            (summary.inserted, summary.updated, summary.skipped),
+            (3, 0, 1)
+        );
+        assert!(summary.errors.is_empty());
+
+        let records = db.search_images(Default::default()).await.unwrap().images;
+        let record = |name: &str| records.iter().find(|r| r.filename == name).unwrap();
+        let first = record("1_aa.png");
+        assert_eq!((first.width, first.height), (Some(4), Some(3)));
+        assert_eq!(first.tags, ["cloud", "sky"]);
+        assert_eq!(first.source_url.as_deref(), Some("https://example.com/1"));
+        assert_eq!(first.group_name.as_deref(), Some("Crawled"));
+        assert_eq!(record("2_bb.png").tags, ["sky"]);
+        // Unreadable files fall back to the sidecar's dimensions
+        let broken = record("4_dd.png");
+        assert_eq!((broken.width, broken.height), (Some(50), Some(40)));
+        assert!(broken.tags.is_empty());
+
+        // Importing again updates the records and keeps tags added since
+        db.add_tags_to_images(&[first.id], &["favorite".to_string()])
+            .await
+            .unwrap();
+        let summary = import().await.unwrap();
+        assert_eq!((summary.inserted, summary.updated), (0, 3));
+        assert_eq!(
+            db.get_image_tags(first.id).await.unwrap(),
+            ["cloud", "favorite", "sky"]
+        );
+    }
+
+    #[test]
+    fn test_group_mapping_names() {
+        let path = Path::new("Cats/Tabby/kitten/1.png");
+        assert_eq!(GroupMapping::Ungrouped.names(path), (None, None));
+        assert_eq!(
+            GroupMapping::Group.names(path),
+            (Some("Cats".to_string()), None)
+        );
+        assert_eq!(
+            GroupMapping::GroupAndSubgroup.names(path),
+            (Some("Cats".to_string()), Some("Tabby".to_string()))
+        );
+        assert_eq!(
+            GroupMapping::GroupAndSubgroup.names(Path::new("Dogs/1.png")),
+            (Some("Dogs".to_string()), None)
+        );
+        assert_eq!(
+            GroupMapping::GroupAndSubgroup.names(Path::new("1.png")),
+            (None, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_directory_resumes() {
+        let temp = tempdir().unwrap();
+        let root = temp.path().join("library");
+        for (folder, count) in [("", 1), ("Cats", 2), ("Cats/Tabby", 3), ("Dogs/Pug", 4)] {
+            std::fs::create_dir_all(root.join(folder)).unwrap();
+            for i in 0..count {
+                write_png(&root.join(folder).join(format!("{}.png", i)), 2 + i, 2);
+            }
+        }
+        std::fs::write(root.join("Cats/notes.txt"), b"").unwrap();
+        std::fs::write(root.join("Dogs/Pug/broken.png"), b"not a png").unwrap();
+        let root = root.to_str().unwrap();
+        let checkpoint = temp.path().join("checkpoints/library.json");
+        let options = || IndexOptions {
+            group_mapping: GroupMapping::GroupAndSubgroup,
+            chunk_size: 3,
+            ..Default::default()
+        };
+
+        // Stopped once the second chunk is in, as a crash after it would
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let cancel = CancellationToken::new();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (stop, record) = (cancel.clone(), seen.clone());
+        let interrupted = index_directory(&db, root, options(), &checkpoint, &cancel, move |p| {
+            record.lock().unwrap().push(p);
+            if p.done >= 6 {
+                stop.cancel();
+            }
+        })
+        .await;
+        assert!(matches!(interrupted, Err(TaskError::Cancelled)));
+        let seen = seen.lock().unwrap().clone();
+        let done: Vec<(usize, usize)> = seen.iter().map(|p| (p.done, p.total)).collect();
+        assert_eq!(done, [(0, 11), (3, 11), (6, 11)]);
+        assert_eq!(seen[0].eta_secs, None);
+        assert!(seen[2].eta_secs.is_some());
+
+        let saved = IndexCheckpoint::load(&checkpoint).unwrap();
+        assert_eq!((saved.done, saved.inserted), (6, 6));
+        let records = db.get_all_paths().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(records.len(), 6);
+
+        let summary = index_directory(
+            &db,
+            root,
+            options(),
+            &checkpoint,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            (
+                summary.total,
+                summary.resumed_from,
+                summary.inserted,
+                summary.updated
+            ),
+            (11, 6, 11, 0)
+        );
+        assert!(summary.errors.is_empty());
+        assert!(!checkpoint.exists());
+
+        let records = db.search_images(Default::default()).await.unwrap().images;
+        assert_eq!(records.len(), 11);
+        let groups = |suffix: &str| {
+            let record = records
+                .iter()
+                .find(|r| Path::new(&r.file_path).ends_with(suffix))
+                .unwrap();
+            (
+                record.group_name.as_deref(),
+                record.subgroup_name.as_deref(),
+            )
+        };
+        assert_eq!(groups("library/0.png"), (None, None));
+        assert_eq!(groups("Cats/1.png"), (Some("Cats"), None));
+        assert_eq!(groups("Cats/Tabby/2.png"), (Some("Cats"), Some("Tabby")));
+        assert_eq!(groups("Dogs/Pug/broken.png"), (Some("Dogs"), Some("Pug")));
+        assert_eq!(db.get_all_groups().await.unwrap(), ["Cats", "Dogs"]);
+
+        // A checkpoint that no longer lines up with the tree is started over
+        IndexCheckpoint {
+            done: 3,
+            last_path: Some("/moved/away.png".to_string()),
+            ..saved
+        }
+        .save(&checkpoint)
+        .unwrap();
+        let summary = index_directory(
+            &db,
+            root,
+            options(),
+            &checkpoint,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            (summary.resumed_from, summary.inserted, summary.updated),
+            (0, 0, 11)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_rejects_missing_root() {
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let roots = vec!["/definitely/not/mounted".to_string()];
+        assert!(audit_library(&db, &roots).await.is_err());
+    }
+}
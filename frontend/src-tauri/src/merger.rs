@@ -0,0 +1,376 @@
+//! Merging images side by side, stacked or in a grid, ported from the base
+//! library's `image_merger`. Canvas sizes come from image headers, then
+//! images are decoded and drawn one at a time, so only one is in memory.
+
+use crate::tasks::{CancellationToken, TaskError};
+use anyhow::{anyhow, Context, Result};
+use base::core::image_converter::{image_format, load_image, save_image};
+use base::core::image_merger::parse_background;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeDirection {
+    #[default]
+    Horizontal,
+    Vertical,
+    Grid,
+}
+
+/// Placement across the merge direction: `top` is also `left` and
+/// `bottom` also `right`; `stretch` resizes images to the tallest (or
+/// widest). Grid cells always center.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeAlign {
+    #[serde(alias = "left")]
+    Top,
+    #[default]
+    Center,
+    #[serde(alias = "right")]
+    Bottom,
+    #[serde(alias = "squish")]
+    Stretch,
+}
+
+/// The merge tab's settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MergeConfig {
+    pub direction: MergeDirection,
+    pub spacing: u32,
+    pub align_mode: MergeAlign,
+    /// Grid shape; a missing side is sized to fit all images
+    pub grid_rows: Option<u32>,
+    pub grid_cols: Option<u32>,
+    /// `#RRGGBB`, `#RRGGBBAA` or `transparent`; white if unset
+    pub background: Option<String>,
+    /// Format such as `png` or `jpeg`; the output's extension if unset
+    pub output_format: Option<String>,
+    /// JPEG quality from 1 to 100
+    pub quality: Option<u8>,
+}
+
+impl MergeConfig {
+    /// `(rows, cols)` of the grid for `count` images
+    fn grid(&self, count: u32) -> (u32, u32) {
+        let count = count.max(1);
+        match (
+            self.grid_rows.filter(|&r| r > 0),
+            self.grid_cols.filter(|&c| c > 0),
+        ) {
+            (Some(rows), Some(cols)) => (rows, cols),
+            (Some(rows), None) => (rows, count.div_ceil(rows)),
+            (None, Some(cols)) => (count.div_ceil(cols), cols),
+            (None, None) => {
+                let cols = (count as f64).sqrt().ceil() as u32;
+                (count.div_ceil(cols), cols)
+            }
+        }
+    }
+}
+
+/// Offset of an `len`-long image along a `span`-long slot
+fn align_offset(align: MergeAlign, span: u32, len: u32) -> u32 {
+    match align {
+        MergeAlign::Top | MergeAlign::Stretch => 0,
+        MergeAlign::Center => span.saturating_sub(len) / 2,
+        MergeAlign::Bottom => span.saturating_sub(len),
+    }
+}
+
+/// Merge `paths` into `output` on the config's background, saved in its
+/// output format or else the one `output`'s extension names. Unreadable
+/// images are skipped; it's an error if none can be read. Reports
+/// `(drawn, total)` after each image.
+pub fn merge_images(
+    paths: &[String],
+    output: &str,
+    config: &MergeConfig,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Result<(), TaskError> {
+    if paths.len() < 2 {
+        return Err(anyhow!("Select at least 2 images to merge").into());
+    }
+    let format = match (
+        &config.output_format,
+        Path::new(output).extension().and_then(|ext| ext.to_str()),
+    ) {
+        (Some(name), _) => name.as_str(),
+        (None, Some(ext)) => ext,
+        (None, None) => "png",
+    };
+    image_format(format)?;
+    let background = match &config.background {
+        Some(spec) => parse_background(spec)?,
+        None => Rgba([255, 255, 255, 255]),
+    };
+
+    // Pass 1: headers only
+    let sized: Vec<(&String, (u32, u32))> = paths
+        .iter()
+        .filter_map(|path| match image::image_dimensions(path) {
+            Ok(dims) => Some((path, dims)),
+            Err(e) => {
+                log::warn!("Skipping {} in merge: {}", path, e);
+                None
+            }
+        })
+        .collect();
+    if sized.is_empty() {
+        return Err(anyhow!("None of the images could be read").into());
+    }
+    let count = sized.len() as u32;
+    let spacing = config.spacing;
+    let max_w = sized.iter().map(|(_, (w, _))| *w).max().unwrap_or(0);
+    let max_h = sized.iter().map(|(_, (_, h))| *h).max().unwrap_or(0);
+    let (rows, cols) = config.grid(count);
+
+    let stretched = |w: u32, h: u32, horizontal: bool| match (config.align_mode, horizontal) {
+        // Keep the other side, as the base library did
+        (MergeAlign::Stretch, true) => (w, max_h),
+        (MergeAlign::Stretch, false) => (max_w, h),
+        _ => (w, h),
+    };
+    let (canvas_w, canvas_h) = match config.direction {
+        MergeDirection::Horizontal => (
+            sized
+                .iter()
+                .map(|(_, (w, h))| stretched(*w, *h, true).0)
+                .sum::<u32>()
+                + spacing * (count - 1),
+            max_h,
+        ),
+        MergeDirection::Vertical => (
+            max_w,
+            sized
+                .iter()
+                .map(|(_, (w, h))| stretched(*w, *h, false).1)
+                .sum::<u32>()
+                + spacing * (count - 1),
+        ),
+        MergeDirection::Grid => (
+            cols * max_w + spacing * (cols - 1),
+            rows * max_h + spacing * (rows - 1),
+        ),
+    };
+    let mut canvas = RgbaImage::from_pixel(canvas_w, canvas_h, background);
+
+    // Pass 2: decode and draw one image at a time
+    let total = sized.len();
+    let mut cursor = 0u32;
+    let mut drawn = 0;
+    for (index, (path, _)) in sized.iter().enumerate() {
+        cancel.check()?;
+        let index = index as u32;
+        if config.direction == MergeDirection::Grid && index / cols >= rows {
+            break;
+        }
+        let img = match load_image(path, false) {
+            Ok(img) => img,
+            Err(e) => {
+                log::warn!("Skipping {} in merge: {:#}", path, e);
+                continue;
+            }
+        };
+        let horizontal = config.direction == MergeDirection::Horizontal;
+        let (w, h) = stretched(img.width(), img.height(), horizontal);
+        let img = if config.direction != MergeDirection::Grid && (w, h) != img.dimensions() {
+            img.resize_exact(w, h, FilterType::Lanczos3)
+        } else {
+            img
+        };
+
+        let (w, h) = (img.width(), img.height());
+        let (x, y) = match config.direction {
+            MergeDirection::Horizontal => (cursor, align_offset(config.align_mode, max_h, h)),
+            MergeDirection::Vertical => (align_offset(config.align_mode, max_w, w), cursor),
+            MergeDirection::Grid => (
+                index % cols * (max_w + spacing) + (max_w.saturating_sub(w)) / 2,
+                index / cols * (max_h + spacing) + (max_h.saturating_sub(h)) / 2,
+            ),
+        };
+        image::imageops::overlay(&mut canvas, &img, x as i64, y as i64);
+        cursor += spacing + if horizontal { w } else { h };
+        drawn += 1;
+        on_progress(index as usize + 1, total);
+    }
+    if drawn == 0 {
+        return Err(anyhow!("None of the images could be read").into());
+    }
+
+    if let Some(parent) = Path::new(output).parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    save_image(
+        &DynamicImage::ImageRgba8(canvas),
+        output,
+        format,
+        config.quality,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, ImageReader, Rgb, RgbImage};
+    use tempfile::{tempdir, TempDir};
+
+    /// Paths of a 100x100 red and a 50x50 green image
+    fn fixtures(dir: &TempDir) -> Vec<String> {
+        [("1.png", 100, [255, 0, 0]), ("2.png", 50, [0, 255, 0])]
+            .into_iter()
+            .map(|(name, side, color)| {
+                let path = dir.path().join(name);
+                RgbImage::from_pixel(side, side, Rgb(color))
+                    .save(&path)
+                    .unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect()
+    }
+
+    fn merge(paths: &[String], config: MergeConfig, out: &Path) -> image::RgbaImage {
+        merge_images(
+            paths,
+            &out.to_string_lossy(),
+            &config,
+            &CancellationToken::new(),
+            |_, _| {},
+        )
+        .unwrap();
+        load_image(&out.to_string_lossy(), false)
+            .unwrap()
+            .to_rgba8()
+    }
+
+    #[test]
+    fn test_merge_directions() {
+        let dir = tempdir().unwrap();
+        let paths = fixtures(&dir);
+        let out = dir.path().join("out.png");
+
+        let horizontal = merge(&paths, MergeConfig::default(), &out);
+        assert_eq!(horizontal.dimensions(), (150, 100));
+        // Centered: white above the small image, green in the middle
+        assert_eq!(horizontal.get_pixel(120, 10), &Rgba([255, 255, 255, 255]));
+        assert_eq!(horizontal.get_pixel(120, 50), &Rgba([0, 255, 0, 255]));
+
+        let config: MergeConfig = serde_json::from_value(serde_json::json!({
+            "direction": "vertical",
+            "spacing": 10,
+            "alignMode": "right",
+            "gridRows": 2,
+            "gridCols": 2,
+            "duration": 500,
+        }))
+        .unwrap();
+        let vertical = merge(&paths, config, &out);
+        assert_eq!(vertical.dimensions(), (100, 160));
+        assert_eq!(vertical.get_pixel(99, 159), &Rgba([0, 255, 0, 255]));
+
+        let stretch = MergeConfig {
+            align_mode: MergeAlign::Stretch,
+            ..MergeConfig::default()
+        };
+        assert_eq!(merge(&paths, stretch, &out).dimensions(), (150, 100));
+    }
+
+    #[test]
+    fn test_merge_grid() {
+        let dir = tempdir().unwrap();
+        let mut paths = fixtures(&dir);
+        paths.push(paths[1].clone());
+        let config = MergeConfig {
+            direction: MergeDirection::Grid,
+            spacing: 4,
+            ..MergeConfig::default()
+        };
+        assert_eq!(config.grid(3), (2, 2));
+        let grid = merge(&paths, config, &dir.path().join("grid.jpg"));
+        assert_eq!(grid.dimensions(), (204, 204));
+    }
+
+    #[test]
+    fn test_merge_background_and_format() {
+        let dir = tempdir().unwrap();
+        let paths = fixtures(&dir);
+
+        let config: MergeConfig = serde_json::from_value(serde_json::json!({
+            "spacing": 10,
+            "background": "transparent",
+        }))
+        .unwrap();
+        let sheet = merge(&paths, config, &dir.path().join("sheet.png"));
+        assert_eq!(sheet.get_pixel(105, 50), &Rgba([0, 0, 0, 0]));
+        assert_eq!(sheet.get_pixel(130, 50), &Rgba([0, 255, 0, 255]));
+
+        // JPEG by request, whatever the extension, at the quality asked for
+        let collage = |quality| {
+            let out = dir.path().join(format!("collage_{}.out", quality));
+            let config = MergeConfig {
+                background: Some("#202020".into()),
+                output_format: Some("jpeg".into()),
+                quality: Some(quality),
+                ..MergeConfig::default()
+            };
+            let img = merge(&paths, config, &out);
+            let format = ImageReader::open(&out)
+                .unwrap()
+                .with_guessed_format()
+                .unwrap()
+                .format();
+            assert_eq!(format, Some(ImageFormat::Jpeg));
+            (img, std::fs::metadata(&out).unwrap().len())
+        };
+        let (img, high) = collage(90);
+        assert!(img.get_pixel(120, 5).0[..3]
+            .iter()
+            .all(|&c| c.abs_diff(0x20) <= 3));
+        let (_, low) = collage(10);
+        assert!(low < high);
+
+        let invalid = MergeConfig {
+            background: Some("purple".into()),
+            ..MergeConfig::default()
+        };
+        let out = dir.path().join("invalid.png");
+        let result = merge_images(
+            &paths,
+            &out.to_string_lossy(),
+            &invalid,
+            &CancellationToken::new(),
+            |_, _| {},
+        );
+        assert!(format!("{}", result.unwrap_err()).contains("Invalid background color 'purple'"));
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        let dir = tempdir().unwrap();
+        let paths = fixtures(&dir);
+        let out = dir.path().join("out.png").to_string_lossy().to_string();
+        let run = |paths: &[String], cancel: &CancellationToken| {
+            merge_images(paths, &out, &MergeConfig::default(), cancel, |_, _| {})
+        };
+
+        assert!(matches!(
+            run(&paths[..1], &CancellationToken::new()),
+            Err(TaskError::Failed(_))
+        ));
+        let missing = vec!["/nope/a.png".to_string(), "/nope/b.png".to_string()];
+        assert!(run(&missing, &CancellationToken::new()).is_err());
+
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        assert!(matches!(run(&paths, &cancelled), Err(TaskError::Cancelled)));
+        assert!(!Path::new(&out).exists());
+    }
+}
@@ -0,0 +1,172 @@
+//! What the info panel shows about a file on disk, whether or not it is in
+//! the database: format and dimensions from the header, file info and EXIF
+//! highlights
+
+use crate::db::CaptureMetadata;
+use crate::exif_reader;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use image::ImageReader;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageMetadata {
+    pub path: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub created: Option<DateTime<Utc>>,
+    pub modified: Option<DateTime<Utc>>,
+    /// Detected from the content, e.g. "jpg"; `None` for non-images
+    pub format: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// EXIF orientation, 1 (upright) to 8
+    pub orientation: Option<u32>,
+    pub capture: CaptureMetadata,
+}
+
+/// One file of `read_metadata_batch`: its metadata, or why it couldn't be read
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataResult {
+    pub path: String,
+    pub metadata: Option<ImageMetadata>,
+    pub error: Option<String>,
+}
+
+/// Metadata for `path`. Fails only if the file can't be found or read;
+/// files that aren't images come back with just their file info.
+pub fn read_metadata(path: &Path) -> Result<ImageMetadata> {
+    let meta =
+        std::fs::metadata(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if !meta.is_file() {
+        anyhow::bail!("{} is not a file", path.display());
+    }
+
+    let reader = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .ok();
+    let format = reader
+        .as_ref()
+        .and_then(|reader| reader.format())
+        .and_then(|format| format.extensions_str().first())
+        .map(|ext| ext.to_string());
+    let (width, height) = reader
+        .and_then(|reader| reader.into_dimensions().ok())
+        .map(|(w, h)| (Some(w), Some(h)))
+        .unwrap_or((None, None));
+
+    let exif = exif_reader::read_exif(path);
+    Ok(ImageMetadata {
+        path: path.to_string_lossy().to_string(),
+        file_name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        file_size: meta.len(),
+        created: meta.created().ok().map(DateTime::<Utc>::from),
+        modified: meta.modified().ok().map(DateTime::<Utc>::from),
+        format,
+        width,
+        height,
+        orientation: exif.as_ref().and_then(exif_reader::orientation),
+        capture: exif
+            .as_ref()
+            .map(exif_reader::capture_metadata)
+            .unwrap_or_default(),
+    })
+}
+
+/// `read_metadata` for each path, in order
+pub fn read_metadata_batch(paths: &[String]) -> Vec<MetadataResult> {
+    paths
+        .iter()
+        .map(|path| match read_metadata(Path::new(path)) {
+            Ok(metadata) => MetadataResult {
+                path: path.clone(),
+                metadata: Some(metadata),
+                error: None,
+            },
+            Err(e) => MetadataResult {
+                path: path.clone(),
+                metadata: None,
+                error: Some(format!("{:#}", e)),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::{Field, In, Tag, Value};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_exif_tagged_jpeg() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("photo.jpeg");
+        let fields = [
+            Field {
+                tag: Tag::Model,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"X-T4".to_vec()]),
+            },
+            Field {
+                tag: Tag::DateTimeOriginal,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"2023:01:02 03:04:05".to_vec()]),
+            },
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            },
+        ];
+        std::fs::write(&path, exif_reader::jpeg_with_exif(&fields)).unwrap();
+
+        let meta = read_metadata(&path).unwrap();
+        assert_eq!(meta.file_name, "photo.jpeg");
+        assert_eq!(meta.format.as_deref(), Some("jpg"));
+        assert_eq!((meta.width, meta.height), (Some(8), Some(8)));
+        assert_eq!(meta.orientation, Some(6));
+        assert_eq!(meta.capture.camera_model.as_deref(), Some("X-T4"));
+        assert_eq!(
+            meta.capture.date_taken.unwrap().to_rfc3339(),
+            "2023-01-02T03:04:05+00:00"
+        );
+        assert_eq!(meta.file_size, std::fs::metadata(&path).unwrap().len());
+        assert!(meta.modified.is_some());
+    }
+
+    #[test]
+    fn test_plain_files() {
+        let temp = tempdir().unwrap();
+        // The extension lies; the format comes from the content
+        let png = temp.path().join("plain.jpg");
+        image::RgbImage::new(12, 5)
+            .save_with_format(&png, image::ImageFormat::Png)
+            .unwrap();
+        let text = temp.path().join("notes.txt");
+        std::fs::write(&text, "not an image").unwrap();
+
+        let meta = read_metadata(&png).unwrap();
+        assert_eq!(meta.format.as_deref(), Some("png"));
+        assert_eq!((meta.width, meta.height), (Some(12), Some(5)));
+        assert_eq!(meta.orientation, None);
+        assert_eq!(meta.capture, CaptureMetadata::default());
+
+        let meta = read_metadata(&text).unwrap();
+        assert_eq!(meta.format, None);
+        assert_eq!((meta.width, meta.height), (None, None));
+        assert_eq!(meta.file_size, 12);
+
+        assert!(read_metadata(temp.path()).is_err());
+        let paths = [png, temp.path().join("missing.png")].map(|p| p.to_string_lossy().to_string());
+        let batch = read_metadata_batch(&paths);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].metadata.as_ref().unwrap().width, Some(12));
+        assert!(batch[1].metadata.is_none());
+        assert!(batch[1].error.as_ref().unwrap().contains("missing.png"));
+    }
+}
@@ -0,0 +1,306 @@
+//! Path normalization, so one file reached through differently spelled paths
+//! (`C:\Photos\a.JPG` and `c:/photos/a.jpg` on Windows) maps to one image row
+
+use std::path::Path;
+
+/// Path syntax and case sensitivity of a platform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle {
+    /// `/`-separated and case-sensitive (Linux and other Unixes)
+    Unix,
+    /// `/`-separated and case-insensitive (macOS's default filesystems)
+    MacOs,
+    /// `\`-separated with drive letters or UNC shares, case-insensitive
+    Windows,
+}
+
+impl PathStyle {
+    /// The style of the platform we're running on
+    pub const fn native() -> Self {
+        if cfg!(windows) {
+            PathStyle::Windows
+        } else if cfg!(target_os = "macos") {
+            PathStyle::MacOs
+        } else {
+            PathStyle::Unix
+        }
+    }
+
+    pub fn separator(self) -> char {
+        match self {
+            PathStyle::Windows => '\\',
+            PathStyle::Unix | PathStyle::MacOs => '/',
+        }
+    }
+
+    fn is_case_insensitive(self) -> bool {
+        !matches!(self, PathStyle::Unix)
+    }
+}
+
+/// The path to store for `path`: canonical (symlinks and `..` resolved) when
+/// the file exists, otherwise lexically normalized
+pub fn normalize_path(path: &str) -> String {
+    match std::fs::canonicalize(Path::new(path)) {
+        Ok(canonical) => strip_verbatim_prefix(&canonical.to_string_lossy()),
+        Err(_) => lexical_normalize(path, PathStyle::native()),
+    }
+}
+
+/// Key identifying the file at `path` on this platform; stored in
+/// `images.path_key`, which is unique
+pub fn path_key(path: &str) -> String {
+    path_key_for(path, PathStyle::native())
+}
+
+pub fn path_key_for(path: &str, style: PathStyle) -> String {
+    let normalized = lexical_normalize(path, style);
+    if style.is_case_insensitive() {
+        normalized.to_lowercase()
+    } else {
+        normalized
+    }
+}
+
+/// Case-folded filename stored in `images.filename_key`, so filename search
+/// is case-insensitive (beyond ASCII) on every backend
+pub fn filename_key(filename: &str) -> String {
+    filename.to_lowercase()
+}
+
+/// Moves `path` from under `old_prefix` to under `new_prefix`; `None` when
+/// `path` isn't `old_prefix` itself or inside it. Prefixes are compared by
+/// whole components, so `/photos` doesn't match `/photos2/a.jpg`.
+pub fn rebase_path(path: &str, old_prefix: &str, new_prefix: &str) -> Option<String> {
+    let is_separator = |c: char| c == '/' || c == PathStyle::native().separator();
+    let rest = path.strip_prefix(old_prefix)?;
+    if rest.is_empty() {
+        return Some(new_prefix.to_string());
+    }
+    if !rest.starts_with(is_separator) && !old_prefix.ends_with(is_separator) {
+        return None;
+    }
+
+    Some(format!(
+        "{}{}{}",
+        new_prefix.trim_end_matches(is_separator),
+        PathStyle::native().separator(),
+        rest.trim_start_matches(is_separator)
+    ))
+}
+
+/// Replaces characters that are invalid in file names on common platforms
+/// and trims the result to a sane length. Never returns an empty string.
+pub fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = cleaned.trim().trim_matches('.');
+    let truncated: String = trimmed.chars().take(150).collect();
+    if truncated.is_empty() {
+        "image".to_string()
+    } else {
+        truncated
+    }
+}
+
+/// `filename` with " (n)" before the extension, for the n-th attempt at a
+/// free name; attempt 0 is `filename` itself
+pub fn numbered_filename(filename: &str, n: usize) -> String {
+    if n == 0 {
+        return filename.to_string();
+    }
+    match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{} ({}).{}", stem, n, ext),
+        _ => format!("{} ({})", filename, n),
+    }
+}
+
+/// Windows `canonicalize` returns `\\?\C:\...` and `\\?\UNC\server\...`
+fn strip_verbatim_prefix(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", rest)
+    } else if let Some(rest) = path.strip_prefix(r"\\?\") {
+        rest.to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+/// Normalizes separators, drops `.` and empty components and resolves `..`
+/// against the preceding component, without touching the filesystem. Drive
+/// letters are upper-cased; other casing is preserved.
+pub fn lexical_normalize(path: &str, style: PathStyle) -> String {
+    let sep = style.separator();
+    let (prefix, rest) = match style {
+        PathStyle::Windows => split_windows_prefix(path),
+        PathStyle::Unix | PathStyle::MacOs => (String::new(), path.to_string()),
+    };
+
+    let absolute = rest.starts_with(sep);
+    let mut components: Vec<&str> = Vec::new();
+    for component in rest.split(sep) {
+        match component {
+            "" | "." => {}
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                // `..` above the root stays at the root
+                _ if absolute => {}
+                _ => components.push(".."),
+            },
+            _ => components.push(component),
+        }
+    }
+
+    let mut out = prefix;
+    if absolute {
+        out.push(sep);
+    }
+    out.push_str(&components.join(&sep.to_string()));
+    if out.is_empty() {
+        out.push('.');
+    }
+    out
+}
+
+/// Splits a Windows path into its prefix (`C:` or `\\server\share`) and the
+/// remainder, with `/` already turned into `\`
+fn split_windows_prefix(path: &str) -> (String, String) {
+    let path = strip_verbatim_prefix(&path.replace('/', "\\"));
+
+    if let Some(unc) = path.strip_prefix(r"\\") {
+        let mut parts = unc.splitn(3, '\\');
+        let server = parts.next().unwrap_or_default();
+        let share = parts.next().unwrap_or_default();
+        let rest = parts.next().map(|r| format!("\\{}", r)).unwrap_or_default();
+        return (format!(r"\\{}\{}", server, share), rest);
+    }
+
+    let bytes = path.as_bytes();
+    if bytes.len() >= 2 && bytes[1] == b':' && bytes[0].is_ascii_alphabetic() {
+        let drive = (bytes[0] as char).to_ascii_uppercase();
+        return (format!("{}:", drive), path[2..].to_string());
+    }
+
+    (String::new(), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_and_number_filenames() {
+        assert_eq!(sanitize_filename("a/b:c*?.jpg"), "a_b_c__.jpg");
+        assert_eq!(sanitize_filename("  ..  "), "image");
+        assert_eq!(sanitize_filename(&"x".repeat(300)).len(), 150);
+        assert_eq!(numbered_filename("cat.jpg", 0), "cat.jpg");
+        assert_eq!(numbered_filename("cat.tar.gz", 2), "cat.tar (2).gz");
+        assert_eq!(numbered_filename("README", 1), "README (1)");
+        assert_eq!(numbered_filename(".hidden", 1), ".hidden (1)");
+    }
+
+    #[test]
+    fn test_windows_paths() {
+        let style = PathStyle::Windows;
+        assert_eq!(
+            lexical_normalize(r"c:/Photos//2024/./a.JPG", style),
+            r"C:\Photos\2024\a.JPG"
+        );
+        assert_eq!(
+            lexical_normalize(r"C:\Photos\raw\..\a.jpg", style),
+            r"C:\Photos\a.jpg"
+        );
+        assert_eq!(lexical_normalize(r"C:\..\a.jpg", style), r"C:\a.jpg");
+        assert_eq!(
+            lexical_normalize(r"\\?\C:\Photos\a.jpg", style),
+            r"C:\Photos\a.jpg"
+        );
+        assert_eq!(
+            lexical_normalize(r"//NAS/share/dir/../a.jpg", style),
+            r"\\NAS\share\a.jpg"
+        );
+        assert_eq!(
+            lexical_normalize(r"\\?\UNC\NAS\share\a.jpg", style),
+            r"\\NAS\share\a.jpg"
+        );
+        assert_eq!(lexical_normalize(r"photos\..\..\a.jpg", style), r"..\a.jpg");
+
+        assert_eq!(
+            path_key_for(r"C:\Photos\a.JPG", style),
+            path_key_for(r"c:/photos/A.jpg", style)
+        );
+        assert_ne!(
+            path_key_for(r"C:\Photos\a.jpg", style),
+            path_key_for(r"D:\Photos\a.jpg", style)
+        );
+    }
+
+    #[test]
+    fn test_unix_paths() {
+        let style = PathStyle::Unix;
+        assert_eq!(
+            lexical_normalize("/home//me/./pics/../a.jpg", style),
+            "/home/me/a.jpg"
+        );
+        assert_eq!(lexical_normalize("/../a.jpg", style), "/a.jpg");
+        assert_eq!(lexical_normalize("pics/", style), "pics");
+        assert_eq!(lexical_normalize("./", style), ".");
+        // Backslashes are ordinary filename characters here
+        assert_eq!(lexical_normalize(r"/pics/a\b.jpg", style), r"/pics/a\b.jpg");
+
+        // Case matters on Linux but not on macOS
+        assert_ne!(
+            path_key_for("/pics/a.JPG", style),
+            path_key_for("/pics/a.jpg", style)
+        );
+        assert_eq!(
+            path_key_for("/Pics/a.JPG", PathStyle::MacOs),
+            path_key_for("/pics//a.jpg", PathStyle::MacOs)
+        );
+    }
+
+    #[test]
+    fn test_rebase_path() {
+        let sep = PathStyle::native().separator();
+        assert_eq!(
+            rebase_path("/old/a/b.jpg", "/old", "/new"),
+            Some(format!("/new{}a/b.jpg", sep))
+        );
+        assert_eq!(
+            rebase_path("/old/b.jpg", "/old/b.jpg", "/new/c.jpg").as_deref(),
+            Some("/new/c.jpg")
+        );
+        assert_eq!(rebase_path("/older/b.jpg", "/old", "/new"), None);
+        assert_eq!(
+            rebase_path("/old/b.jpg", "/", "/mnt/"),
+            Some(format!("/mnt{}old/b.jpg", sep))
+        );
+    }
+
+    #[test]
+    fn test_normalize_path_canonicalizes_existing_files() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(temp.path().join("sub")).unwrap();
+        let file = temp.path().join("a.jpg");
+        std::fs::write(&file, b"").unwrap();
+        let canonical = std::fs::canonicalize(&file).unwrap();
+
+        let roundabout = temp.path().join("sub").join("..").join("a.jpg");
+        assert_eq!(
+            normalize_path(&roundabout.to_string_lossy()),
+            strip_verbatim_prefix(&canonical.to_string_lossy())
+        );
+        assert_eq!(
+            normalize_path("/no/such/dir/../a.jpg"),
+            lexical_normalize("/no/such/dir/../a.jpg", PathStyle::native())
+        );
+    }
+}
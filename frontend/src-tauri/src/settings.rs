@@ -0,0 +1,270 @@
+//! User settings: one versioned document, checked against `SettingsData` on
+//! every load and save, kept in the signed-in account's vault or, without an
+//! account, in a file in the app config directory
+
+use crate::vault::{self, Vault};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Version of the settings structure this build writes
+pub const SETTINGS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettingsData {
+    pub version: u32,
+    pub theme: Theme,
+    pub tab_configurations: HashMap<String, HashMap<String, Value>>,
+    pub system_preference_profiles: HashMap<String, Value>,
+    pub active_tab_configs: HashMap<String, String>,
+    /// Fields this version doesn't know, kept so saving here doesn't drop
+    /// what a newer version stored
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Default for SettingsData {
+    fn default() -> Self {
+        SettingsData {
+            version: SETTINGS_VERSION,
+            theme: Theme::Dark,
+            tab_configurations: HashMap::new(),
+            system_preference_profiles: HashMap::new(),
+            active_tab_configs: HashMap::new(),
+            extra: Map::new(),
+        }
+    }
+}
+
+impl SettingsData {
+    /// Parse stored settings, upgrading older structures first. Returns the
+    /// settings and whether an upgrade happened.
+    pub fn migrate(mut raw: Map<String, Value>) -> Result<(SettingsData, bool)> {
+        let version = raw.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+        if version == 0 {
+            // Before versioning: as the Python app left them, with the theme
+            // in whatever case its settings window saved it
+            let theme = match raw.get("theme").and_then(Value::as_str) {
+                Some(theme) if theme.eq_ignore_ascii_case("light") => "light",
+                _ => "dark",
+            };
+            raw.insert("theme".into(), theme.into());
+            raw.insert("version".into(), 1.into());
+        }
+        let settings =
+            serde_json::from_value(Value::Object(raw)).context("Settings are not valid")?;
+        Ok((settings, version < SETTINGS_VERSION))
+    }
+
+    fn to_map(&self) -> Result<Map<String, Value>> {
+        match serde_json::to_value(self)? {
+            Value::Object(map) => Ok(map),
+            _ => unreachable!("settings serialize to an object"),
+        }
+    }
+}
+
+/// Where settings are kept
+pub enum SettingsStore<'a> {
+    /// A JSON file, for use without a signed-in account
+    File(PathBuf),
+    /// An unlocked account's vault; its `account_name` entry isn't a setting
+    Vault(&'a mut Vault),
+}
+
+impl SettingsStore<'_> {
+    /// The stored settings, or the defaults if there are none yet. Settings
+    /// from before versioning are upgraded and written back.
+    pub fn load(&mut self) -> Result<SettingsData> {
+        let Some(raw) = self.read()? else {
+            return Ok(SettingsData::default());
+        };
+        let (settings, migrated) = SettingsData::migrate(raw)?;
+        if migrated {
+            self.write(&settings)?;
+        }
+        Ok(settings)
+    }
+
+    /// Store `settings`. Unknown fields already stored are kept unless
+    /// `settings` sets them too; returns what was stored.
+    pub fn save(&mut self, mut settings: SettingsData) -> Result<SettingsData> {
+        let stored = self.load()?;
+        settings.version = settings.version.max(stored.version);
+        for (key, value) in stored.extra {
+            settings.extra.entry(key).or_insert(value);
+        }
+        self.write(&settings)?;
+        Ok(settings)
+    }
+
+    /// Put every setting this version knows back to its default
+    pub fn reset(&mut self) -> Result<SettingsData> {
+        self.save(SettingsData::default())
+    }
+
+    fn read(&self) -> Result<Option<Map<String, Value>>> {
+        match self {
+            SettingsStore::File(path) => {
+                let content = match std::fs::read_to_string(path) {
+                    Ok(content) => content,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Failed to read {}", path.display()))
+                    }
+                };
+                serde_json::from_str(&content)
+                    .map(Some)
+                    .with_context(|| format!("Failed to parse {}", path.display()))
+            }
+            SettingsStore::Vault(vault) => {
+                let mut data = vault.data().clone();
+                data.remove(vault::ACCOUNT_KEY);
+                Ok(Some(data))
+            }
+        }
+    }
+
+    fn write(&mut self, settings: &SettingsData) -> Result<()> {
+        let map = settings.to_map()?;
+        match self {
+            SettingsStore::File(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create {}", parent.display()))?;
+                }
+                let json = serde_json::to_vec_pretty(&map)?;
+                vault::write_atomically(path, &json)
+                    .with_context(|| format!("Failed to write {}", path.display()))
+            }
+            SettingsStore::Vault(vault) => vault.update(map).context("Failed to save the vault"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::KdfParams;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn object(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_quotes_and_newlines_round_trip() {
+        let dir = tempdir().unwrap();
+        let mut store = SettingsStore::File(dir.path().join("settings.json"));
+        assert_eq!(store.load().unwrap(), SettingsData::default());
+
+        let tricky = "it's a \"quoted\"\nmulti-line 'value' \\ with $HOME and `ticks`";
+        let mut settings = SettingsData {
+            theme: Theme::Light,
+            ..SettingsData::default()
+        };
+        settings
+            .tab_configurations
+            .entry("convert".into())
+            .or_default()
+            .insert(tricky.into(), json!({ "output_dir": tricky }));
+        settings
+            .active_tab_configs
+            .insert("convert".into(), tricky.into());
+        store.save(settings.clone()).unwrap();
+
+        assert_eq!(store.load().unwrap(), settings);
+        let reopened = SettingsStore::File(dir.path().join("settings.json"))
+            .load()
+            .unwrap();
+        assert_eq!(reopened.active_tab_configs["convert"], tricky);
+    }
+
+    #[test]
+    fn test_atomic_write_keeps_unknown_fields() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config").join("settings.json");
+        // As a newer version might have left it
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            json!({ "version": 7, "theme": "light", "gallery": { "columns": 6 } }).to_string(),
+        )
+        .unwrap();
+
+        let mut store = SettingsStore::File(path.clone());
+        let mut settings = store.load().unwrap();
+        assert_eq!(settings.extra["gallery"], json!({ "columns": 6 }));
+        settings.theme = Theme::Dark;
+        settings.extra.clear();
+        store.save(settings).unwrap();
+
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["theme"], "dark");
+        assert_eq!(saved["version"], 7);
+        assert_eq!(saved["gallery"], json!({ "columns": 6 }));
+        let leftovers: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(leftovers, ["settings.json"]);
+
+        // Reset clears what this version knows, not what it doesn't
+        let reset = store.reset().unwrap();
+        assert_eq!(reset.theme, Theme::Dark);
+        assert_eq!(reset.extra["gallery"], json!({ "columns": 6 }));
+
+        // Invalid settings are refused rather than silently replaced
+        std::fs::write(
+            &path,
+            json!({ "version": 1, "theme": "purple" }).to_string(),
+        )
+        .unwrap();
+        assert!(store.load().is_err());
+        assert!(serde_json::from_value::<SettingsData>(json!({ "theme": 3 })).is_err());
+    }
+
+    #[test]
+    fn test_migration_from_unversioned_vault() {
+        let dir = tempdir().unwrap();
+        let kdf = KdfParams {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let legacy = object(json!({
+            "theme": "Light",
+            "system_preference_profiles": { "work": { "theme": "dark" } },
+            "window_geometry": [10, 20],
+        }));
+        let mut vault = Vault::create_with(dir.path(), "alice", "pw", kdf, legacy).unwrap();
+
+        let settings = SettingsStore::Vault(&mut vault).load().unwrap();
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.theme, Theme::Light);
+        assert_eq!(settings.system_preference_profiles["work"]["theme"], "dark");
+        assert_eq!(settings.extra["window_geometry"], json!([10, 20]));
+        assert!(!settings.extra.contains_key("account_name"));
+
+        // Written back on first load, and the account survives it
+        let reopened = Vault::open(dir.path(), "alice", "pw").unwrap();
+        assert_eq!(reopened.data()["version"], SETTINGS_VERSION);
+        assert_eq!(reopened.data()["theme"], "light");
+        assert_eq!(reopened.data()["account_name"], "alice");
+
+        let (_, migrated) = SettingsData::migrate(settings.to_map().unwrap()).unwrap();
+        assert!(!migrated);
+    }
+}
@@ -0,0 +1,545 @@
+//! Long-running commands as tasks: cancellation, uniform progress events and
+//! the list of what is running, keyed by the task id the frontend tracks them
+//! under
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Returned by work that stopped because its task was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Task cancelled")]
+pub struct Cancelled;
+
+/// Why task work stopped early
+#[derive(Debug, thiserror::Error)]
+pub enum TaskError {
+    #[error("Task cancelled")]
+    Cancelled,
+    #[error("{0:#}")]
+    Failed(#[from] anyhow::Error),
+}
+
+impl From<Cancelled> for TaskError {
+    fn from(_: Cancelled) -> Self {
+        TaskError::Cancelled
+    }
+}
+
+/// Shared flag checked by blocking work between units of progress
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// `Err(Cancelled)` once cancelled, for use with `?`
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Payload of `task-progress` events, in the shape the task store expects
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskProgress {
+    pub task_id: String,
+    pub progress: u32,
+    pub message: String,
+    pub status: String,
+}
+
+/// Payload of `task-complete` events, sent when a task succeeds or fails
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskComplete {
+    pub task_id: String,
+    pub success: bool,
+    pub message: String,
+}
+
+/// Payload of `task-cancelled` events
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskCancelled {
+    pub task_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum TaskEvent {
+    Progress(TaskProgress),
+    Complete(TaskComplete),
+    Cancelled(TaskCancelled),
+}
+
+impl TaskEvent {
+    /// Name the event is emitted under
+    pub fn name(&self) -> &'static str {
+        match self {
+            TaskEvent::Progress(_) => "task-progress",
+            TaskEvent::Complete(_) => "task-complete",
+            TaskEvent::Cancelled(_) => "task-cancelled",
+        }
+    }
+}
+
+/// Where task events go: the webview in the app, a recorder in tests
+pub trait TaskEvents: Send + Sync {
+    fn send(&self, event: TaskEvent);
+}
+
+/// A running task as `list_active_tasks` reports it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskInfo {
+    pub id: String,
+    pub kind: String,
+    pub metadata: Value,
+    pub started_at: DateTime<Utc>,
+    pub cancelled: bool,
+}
+
+struct TaskEntry {
+    kind: String,
+    metadata: Value,
+    started_at: DateTime<Utc>,
+    token: CancellationToken,
+}
+
+/// The tasks currently running. Entries are removed as their tasks finish.
+#[derive(Default)]
+pub struct TaskManager {
+    tasks: Mutex<HashMap<String, TaskEntry>>,
+}
+
+impl TaskManager {
+    /// Register a task of `kind` under a fresh id; it stays listed and
+    /// cancellable until the returned guard is dropped
+    pub fn start_task(&self, kind: &str, metadata: Value) -> RunningTask<'_> {
+        let task_id = uuid::Uuid::new_v4().to_string();
+        self.start_task_as(&task_id, kind, metadata)
+            .expect("fresh task ids are unique")
+    }
+
+    /// Like `start_task`, under an id chosen by the caller. Fails if a task
+    /// with that id is already running.
+    pub fn start_task_as(
+        &self,
+        task_id: &str,
+        kind: &str,
+        metadata: Value,
+    ) -> Result<RunningTask<'_>, String> {
+        let mut tasks = self.tasks.lock().unwrap();
+        if tasks.contains_key(task_id) {
+            return Err(format!("Task '{}' is already running", task_id));
+        }
+        let token = CancellationToken::new();
+        tasks.insert(
+            task_id.to_string(),
+            TaskEntry {
+                kind: kind.to_string(),
+                metadata,
+                started_at: Utc::now(),
+                token: token.clone(),
+            },
+        );
+        Ok(RunningTask {
+            manager: self,
+            task_id: task_id.to_string(),
+            token,
+        })
+    }
+
+    /// `start_task_as` when the caller sent a task id, else `start_task`
+    pub fn start_task_for(
+        &self,
+        task_id: Option<String>,
+        kind: &str,
+        metadata: Value,
+    ) -> Result<RunningTask<'_>, String> {
+        match task_id {
+            Some(task_id) => self.start_task_as(&task_id, kind, metadata),
+            None => Ok(self.start_task(kind, metadata)),
+        }
+    }
+
+    /// Ask a running task to stop; `false` if no such task is running
+    pub fn cancel(&self, task_id: &str) -> bool {
+        match self.tasks.lock().unwrap().get(task_id) {
+            Some(entry) => {
+                entry.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_running(&self, task_id: &str) -> bool {
+        self.tasks.lock().unwrap().contains_key(task_id)
+    }
+
+    /// Running tasks, oldest first
+    pub fn list_active(&self) -> Vec<TaskInfo> {
+        let mut active: Vec<TaskInfo> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| TaskInfo {
+                id: id.clone(),
+                kind: entry.kind.clone(),
+                metadata: entry.metadata.clone(),
+                started_at: entry.started_at,
+                cancelled: entry.token.is_cancelled(),
+            })
+            .collect();
+        active.sort_by(|a, b| a.started_at.cmp(&b.started_at).then(a.id.cmp(&b.id)));
+        active
+    }
+}
+
+/// A registered task; unregisters it when dropped
+pub struct RunningTask<'a> {
+    manager: &'a TaskManager,
+    task_id: String,
+    token: CancellationToken,
+}
+
+impl RunningTask<'_> {
+    pub fn id(&self) -> &str {
+        &self.task_id
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Run blocking `work` on the blocking pool. `events` gets a
+    /// `task-progress` as the task starts, whatever the work reports, then
+    /// `task-complete` or, if it was cancelled, `task-cancelled`. Errors are
+    /// returned as messages.
+    pub async fn run<T, F>(self, events: impl TaskEvents + 'static, work: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&TaskContext) -> Result<T, TaskError> + Send + 'static,
+    {
+        self.run_async(events, |ctx| async move {
+            tokio::task::spawn_blocking(move || work(&ctx))
+                .await
+                .unwrap_or_else(|e| Err(anyhow::anyhow!("Task panicked: {}", e).into()))
+        })
+        .await
+    }
+
+    /// `run` for work that mixes async calls with its blocking parts
+    pub async fn run_async<T, F, Fut>(
+        self,
+        events: impl TaskEvents + 'static,
+        work: F,
+    ) -> Result<T, String>
+    where
+        F: FnOnce(TaskContext) -> Fut,
+        Fut: Future<Output = Result<T, TaskError>>,
+    {
+        let ctx = TaskContext {
+            task_id: self.task_id.clone(),
+            token: self.token(),
+            events: Arc::new(events),
+        };
+        ctx.progress(0, "Started");
+
+        let events = ctx.events.clone();
+        let outcome = work(ctx).await;
+
+        let task_id = self.task_id.clone();
+        match &outcome {
+            Ok(_) => events.send(TaskEvent::Complete(TaskComplete {
+                task_id,
+                success: true,
+                message: "Finished".to_string(),
+            })),
+            Err(TaskError::Cancelled) => {
+                events.send(TaskEvent::Cancelled(TaskCancelled { task_id }))
+            }
+            Err(e) => events.send(TaskEvent::Complete(TaskComplete {
+                task_id,
+                success: false,
+                message: e.to_string(),
+            })),
+        }
+        outcome.map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for RunningTask<'_> {
+    fn drop(&mut self) {
+        self.manager.tasks.lock().unwrap().remove(&self.task_id);
+    }
+}
+
+/// Handed to a task's work: its cancellation token and a progress channel
+#[derive(Clone)]
+pub struct TaskContext {
+    task_id: String,
+    token: CancellationToken,
+    events: Arc<dyn TaskEvents>,
+}
+
+impl TaskContext {
+    pub fn task_id(&self) -> &str {
+        &self.task_id
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Send a `task-progress` event at `percent`
+    pub fn progress(&self, percent: u32, message: impl Into<String>) {
+        self.events.send(TaskEvent::Progress(TaskProgress {
+            task_id: self.task_id.clone(),
+            progress: percent.min(100),
+            message: message.into(),
+            status: "running".to_string(),
+        }));
+    }
+
+    /// `progress` for `done` of `total` items
+    pub fn step(&self, done: usize, total: usize, message: impl Into<String>) {
+        self.progress((done * 100 / total.max(1)) as u32, message);
+    }
+}
+
+/// Applies `f` to every item across the available cores, `chunk` items at a
+/// time, checking for cancellation and reporting `(done, total)` per chunk
+pub fn map_in_chunks<T: Sync, R: Send>(
+    items: &[T],
+    chunk: usize,
+    cancel: &CancellationToken,
+    mut on_chunk: impl FnMut(usize, usize),
+    f: impl Fn(&T) -> R + Sync,
+) -> Result<Vec<R>, Cancelled> {
+    let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let mut results = Vec::with_capacity(items.len());
+    on_chunk(0, items.len());
+
+    for chunk in items.chunks(chunk.max(1)) {
+        cancel.check()?;
+        let per_worker = chunk.len().div_ceil(workers).max(1);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = chunk
+                .chunks(per_worker)
+                .map(|part| scope.spawn(|| part.iter().map(&f).collect::<Vec<_>>()))
+                .collect();
+            for handle in handles {
+                results.extend(handle.join().expect("task worker panicked"));
+            }
+        });
+        on_chunk(results.len(), items.len());
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Keeps every event sent, for assertions
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<TaskEvent>>>);
+
+    impl TaskEvents for Recorder {
+        fn send(&self, event: TaskEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl Recorder {
+        fn names(&self) -> Vec<&'static str> {
+            self.0.lock().unwrap().iter().map(TaskEvent::name).collect()
+        }
+
+        fn last(&self) -> TaskEvent {
+            self.0.lock().unwrap().last().cloned().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_manager_lifecycle() {
+        let manager = TaskManager::default();
+        assert!(!manager.cancel("scan"));
+
+        let task = manager
+            .start_task_as("scan", "finder", serde_json::json!({"directory": "/pics"}))
+            .unwrap();
+        assert!(manager.is_running("scan"));
+        assert!(manager
+            .start_task_as("scan", "finder", Value::Null)
+            .is_err());
+        assert!(manager
+            .start_task_for(Some("scan".to_string()), "finder", Value::Null)
+            .is_err());
+        let other = manager
+            .start_task_for(None, "conversion", Value::Null)
+            .unwrap();
+        assert_ne!(other.id(), "scan");
+
+        let active = manager.list_active();
+        assert_eq!(active.len(), 2);
+        assert_eq!(active[0].id, "scan");
+        assert_eq!(active[0].kind, "finder");
+        assert_eq!(active[0].metadata["directory"], "/pics");
+        assert_eq!(active[1].kind, "conversion");
+        assert!(active.iter().all(|info| !info.cancelled));
+
+        let token = task.token();
+        assert_eq!(token.check(), Ok(()));
+        assert!(manager.cancel("scan"));
+        assert!(token.is_cancelled());
+        assert_eq!(token.check(), Err(Cancelled));
+        assert!(manager.list_active()[0].cancelled);
+
+        drop(task);
+        assert!(!manager.is_running("scan"));
+        assert!(!manager.cancel("scan"));
+        assert_eq!(manager.list_active().len(), 1);
+        drop(other);
+        assert!(manager.list_active().is_empty());
+
+        // The id can be reused, with a fresh token
+        let again = manager
+            .start_task_as("scan", "finder", Value::Null)
+            .unwrap();
+        assert!(!again.token().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.png"] {
+            image::RgbImage::new(8, 8)
+                .save(dir.path().join(name))
+                .unwrap();
+        }
+        let root = dir.path().to_string_lossy().to_string();
+
+        let manager = TaskManager::default();
+        let events = Recorder::default();
+        let task = manager
+            .start_task_as("dupes", "finder", Value::Null)
+            .unwrap();
+        let groups = task
+            .run(events.clone(), move |ctx| {
+                let groups = crate::finder::find_duplicates(
+                    &root,
+                    &["png".to_string()],
+                    true,
+                    ctx.token(),
+                    |_| {},
+                )?;
+                ctx.progress(100, "Grouped");
+                Ok(groups)
+            })
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].files.len(), 2);
+        assert!(!manager.is_running("dupes"));
+
+        assert_eq!(
+            events.names(),
+            ["task-progress", "task-progress", "task-complete"]
+        );
+        assert_eq!(
+            events.last(),
+            TaskEvent::Complete(TaskComplete {
+                task_id: "dupes".to_string(),
+                success: true,
+                message: "Finished".to_string(),
+            })
+        );
+
+        let failing = manager
+            .start_task_as("dupes", "finder", Value::Null)
+            .unwrap();
+        let result: Result<(), String> = failing
+            .run(events.clone(), |_| {
+                Err(anyhow::anyhow!("disk on fire").into())
+            })
+            .await;
+        assert_eq!(result, Err("disk on fire".to_string()));
+        assert!(matches!(
+            events.last(),
+            TaskEvent::Complete(TaskComplete { success: false, .. })
+        ));
+        assert!(manager.list_active().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_slow_task() {
+        let manager = TaskManager::default();
+        let events = Recorder::default();
+        let task = manager.start_task("slow", Value::Null);
+        let task_id = task.id().to_string();
+
+        let slow = task.run(events.clone(), |ctx| {
+            for i in 0..1000 {
+                ctx.token().check()?;
+                ctx.step(i, 1000, "Sleeping");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Ok("finished")
+        });
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            assert!(manager.cancel(&task_id));
+        };
+
+        let started = Instant::now();
+        let (result, ()) = tokio::join!(slow, cancel);
+        assert_eq!(result, Err("Task cancelled".to_string()));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(!manager.is_running(&task_id));
+        assert_eq!(
+            events.last(),
+            TaskEvent::Cancelled(TaskCancelled { task_id })
+        );
+        assert!(!events.names().contains(&"task-complete"));
+    }
+
+    #[test]
+    fn test_map_in_chunks() {
+        let items: Vec<usize> = (0..10).collect();
+        let mut reports = Vec::new();
+        let token = CancellationToken::new();
+        let doubled = map_in_chunks(&items, 4, &token, |d, t| reports.push((d, t)), |n| n * 2);
+        assert_eq!(doubled, Ok((0..10).map(|n| n * 2).collect()));
+        assert_eq!(reports, [(0, 10), (4, 10), (8, 10), (10, 10)]);
+
+        token.cancel();
+        assert_eq!(
+            map_in_chunks(&items, 4, &token, |_, _| {}, |n| *n),
+            Err(Cancelled)
+        );
+    }
+}
@@ -0,0 +1,524 @@
+use crate::db::Db;
+use crate::videos;
+use anyhow::{Context, Result};
+use base::core::video_converter::extract_thumbnail_frame;
+use base64::Engine;
+use image::{imageops::FilterType, ImageFormat};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Bounds for the requested longest edge, in pixels
+pub const MIN_THUMBNAIL_EDGE: u32 = 32;
+pub const MAX_THUMBNAIL_EDGE: u32 = 1024;
+
+/// Total bytes the database thumbnail cache may hold before the oldest
+/// entries are evicted
+pub const MAX_THUMBNAIL_CACHE_BYTES: i64 = 256 * 1024 * 1024;
+
+/// Paths rendered between progress reports by `generate_disk_thumbnails`
+pub const DISK_THUMBNAIL_CHUNK: usize = 32;
+
+/// Encoding used for generated thumbnails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    #[default]
+    Jpeg,
+    Webp,
+}
+
+impl ThumbnailFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpeg",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+
+    /// File extension of the cached files
+    pub fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            ThumbnailFormat::Jpeg => ImageFormat::Jpeg,
+            ThumbnailFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Decodes `path` and encodes a copy whose longest edge is at most `max_edge`.
+/// Videos are read through the same ffmpeg frame grab as
+/// `extract_video_thumbnails_batch`.
+pub fn render_thumbnail(path: &Path, max_edge: u32, format: ThumbnailFormat) -> Result<Vec<u8>> {
+    let img = if videos::is_video_path(path) {
+        extract_thumbnail_frame(path, videos::process_limit())
+            .with_context(|| format!("Failed to read a frame of {}", path.display()))?
+    } else {
+        image::open(path).with_context(|| format!("Failed to open {}", path.display()))?
+    };
+    let thumb = img.resize(max_edge, max_edge, FilterType::Triangle);
+
+    // JPEG has no alpha channel
+    let thumb = match format {
+        ThumbnailFormat::Jpeg => image::DynamicImage::ImageRgb8(thumb.to_rgb8()),
+        ThumbnailFormat::Webp => thumb,
+    };
+
+    let mut bytes = Vec::new();
+    thumb
+        .write_to(&mut Cursor::new(&mut bytes), format.image_format())
+        .context("Failed to encode thumbnail")?;
+    Ok(bytes)
+}
+
+/// Renders thumbnails for `paths` across the available cores. Results are in
+/// the same order as `paths`.
+pub fn thumbnail_batch_core(
+    paths: &[String],
+    max_edge: u32,
+    format: ThumbnailFormat,
+) -> Vec<Result<Vec<u8>>> {
+    let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk_size = paths.len().div_ceil(workers).max(1);
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| render_thumbnail(Path::new(path), max_edge, format))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("thumbnail worker panicked"))
+            .collect()
+    })
+}
+
+/// A thumbnail returned to the frontend as base64
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThumbnailData {
+    pub image_id: i32,
+    pub format: Option<String>,
+    pub data: Option<String>,
+    /// Whether the thumbnail came from the cache rather than being generated
+    pub cached: bool,
+    pub error: Option<String>,
+}
+
+/// Serves thumbnails for `image_ids` from the database cache, generating and
+/// storing any that are missing or stale. Results follow the order of `image_ids`.
+pub async fn get_cached_thumbnails(
+    db: &Db,
+    image_ids: &[i32],
+    size: u32,
+) -> Result<Vec<ThumbnailData>> {
+    let size = size.clamp(MIN_THUMBNAIL_EDGE, MAX_THUMBNAIL_EDGE);
+    let format = ThumbnailFormat::default();
+    let encoder = base64::engine::general_purpose::STANDARD;
+
+    let mut results: HashMap<i32, ThumbnailData> = db
+        .get_thumbnails(image_ids, size as i32)
+        .await?
+        .into_iter()
+        .map(|thumb| {
+            let data = ThumbnailData {
+                image_id: thumb.image_id,
+                format: Some(thumb.format),
+                data: Some(encoder.encode(&thumb.bytes)),
+                cached: true,
+                error: None,
+            };
+            (thumb.image_id, data)
+        })
+        .collect();
+
+    let misses: Vec<i32> = image_ids
+        .iter()
+        .copied()
+        .filter(|id| !results.contains_key(id))
+        .collect();
+
+    if !misses.is_empty() {
+        let records = db.get_path_records(&misses).await?;
+        let paths: Vec<String> = records.iter().map(|r| r.file_path.clone()).collect();
+        let rendered =
+            tokio::task::spawn_blocking(move || thumbnail_batch_core(&paths, size, format))
+                .await
+                .context("Thumbnail task failed")?;
+
+        for (record, outcome) in records.iter().zip(rendered) {
+            let data = match outcome {
+                Ok(bytes) => {
+                    // Oversized thumbnails are still served, just not cached
+                    if let Err(e) = db
+                        .put_thumbnail(record.id, size as i32, format.as_str(), &bytes)
+                        .await
+                    {
+                        log::warn!("Not caching thumbnail for image {}: {}", record.id, e);
+                    }
+                    ThumbnailData {
+                        image_id: record.id,
+                        format: Some(format.as_str().to_string()),
+                        data: Some(encoder.encode(&bytes)),
+                        cached: false,
+                        error: None,
+                    }
+                }
+                Err(e) => ThumbnailData {
+                    image_id: record.id,
+                    format: None,
+                    data: None,
+                    cached: false,
+                    error: Some(format!("{:#}", e)),
+                },
+            };
+            results.insert(record.id, data);
+        }
+
+        db.prune_thumbnails(MAX_THUMBNAIL_CACHE_BYTES).await?;
+    }
+
+    Ok(image_ids
+        .iter()
+        .map(|id| {
+            results.remove(id).unwrap_or_else(|| ThumbnailData {
+                image_id: *id,
+                format: None,
+                data: None,
+                cached: false,
+                error: Some(format!("Image {} not found", id)),
+            })
+        })
+        .collect())
+}
+
+/// A thumbnail in the on-disk cache, for the webview to load through the
+/// asset protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskThumbnail {
+    pub source: String,
+    /// Path of the cached file
+    pub thumbnail: Option<String>,
+    /// Whether the file was already in the cache
+    pub cached: bool,
+    pub error: Option<String>,
+}
+
+/// File name of `path`'s thumbnail in the disk cache. It hashes the path
+/// with the file's modification time, the edge and the format, so editing
+/// the file (or asking for another size) maps to a new entry.
+pub fn disk_cache_name(path: &Path, max_edge: u32, format: ThumbnailFormat) -> Result<String> {
+    let modified = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+
+    let mut hasher = Sha256::new();
+    hasher.update(crate::paths::path_key(&path.to_string_lossy()).as_bytes());
+    hasher.update(nanos.to_le_bytes());
+    hasher.update(max_edge.to_le_bytes());
+    hasher.update(format.as_str().as_bytes());
+    let digest = hasher.finalize();
+    Ok(format!(
+        "{}.{}",
+        hex::encode(&digest[..16]),
+        format.extension()
+    ))
+}
+
+/// Renders thumbnails for `paths` into `cache_dir`, reusing cached files, in
+/// chunks of `DISK_THUMBNAIL_CHUNK`. `on_chunk(done, total)` is called after
+/// each chunk. Results follow the order of `paths`.
+pub fn generate_disk_thumbnails(
+    cache_dir: &Path,
+    paths: &[String],
+    max_edge: u32,
+    format: ThumbnailFormat,
+    mut on_chunk: impl FnMut(usize, usize),
+) -> Result<Vec<DiskThumbnail>> {
+    let max_edge = max_edge.clamp(MIN_THUMBNAIL_EDGE, MAX_THUMBNAIL_EDGE);
+    std::fs::create_dir_all(cache_dir)
+        .with_context(|| format!("Failed to create {}", cache_dir.display()))?;
+
+    let mut results = Vec::with_capacity(paths.len());
+    for chunk in paths.chunks(DISK_THUMBNAIL_CHUNK) {
+        // (index into results, source, cache file) of each thumbnail to render
+        let mut misses = Vec::new();
+        for source in chunk {
+            let mut entry = DiskThumbnail {
+                source: source.clone(),
+                thumbnail: None,
+                cached: false,
+                error: None,
+            };
+            match disk_cache_name(Path::new(source), max_edge, format) {
+                Ok(name) => {
+                    let target = cache_dir.join(name);
+                    if target.is_file() {
+                        entry.thumbnail = Some(target.to_string_lossy().to_string());
+                        entry.cached = true;
+                    } else {
+                        misses.push((results.len(), source.clone(), target));
+                    }
+                }
+                Err(e) => entry.error = Some(format!("{:#}", e)),
+            }
+            results.push(entry);
+        }
+
+        let sources: Vec<String> = misses.iter().map(|(_, source, _)| source.clone()).collect();
+        let rendered = thumbnail_batch_core(&sources, max_edge, format);
+        for ((index, _, target), outcome) in misses.into_iter().zip(rendered) {
+            match outcome.and_then(|bytes| write_cache_file(&target, &bytes)) {
+                Ok(()) => results[index].thumbnail = Some(target.to_string_lossy().to_string()),
+                Err(e) => results[index].error = Some(format!("{:#}", e)),
+            }
+        }
+        on_chunk(results.len(), paths.len());
+    }
+
+    Ok(results)
+}
+
+/// Writes through a temp file so the webview never loads a partial image
+fn write_cache_file(target: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = target.with_extension("tmp");
+    std::fs::write(&tmp, bytes).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, target).with_context(|| format!("Failed to write {}", target.display()))
+}
+
+/// Deletes every file in the disk cache; returns how many were removed
+pub fn clear_disk_cache(cache_dir: &Path) -> Result<usize> {
+    let entries = match std::fs::read_dir(cache_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", cache_dir.display())),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{NewImage, UpdateImage};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render_thumbnail_fits_edge() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("wide.png");
+        image::RgbaImage::new(200, 100).save(&path).unwrap();
+
+        for format in [ThumbnailFormat::Jpeg, ThumbnailFormat::Webp] {
+            let bytes = render_thumbnail(&path, 50, format).unwrap();
+            let thumb = image::load_from_memory(&bytes).unwrap();
+            assert_eq!((thumb.width(), thumb.height()), (50, 25));
+        }
+
+        let results = thumbnail_batch_core(
+            &[
+                path.to_string_lossy().to_string(),
+                "/missing.png".to_string(),
+            ],
+            50,
+            ThumbnailFormat::Jpeg,
+        );
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_thumbnail_cache_cycle() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("photo.png");
+        image::RgbImage::new(300, 200).save(&path).unwrap();
+
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let added = db
+            .batch_add_images(vec![
+                NewImage {
+                    file_path: path.to_string_lossy().to_string(),
+                    ..Default::default()
+                },
+                NewImage {
+                    file_path: temp.path().join("gone.png").to_string_lossy().to_string(),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+        let (id, gone) = (added.inserted[0], added.inserted[1]);
+
+        // Miss: generated and stored
+        let first = get_cached_thumbnails(&db, &[id, gone], 64).await.unwrap();
+        assert!(!first[0].cached && first[0].data.is_some());
+        assert!(first[1].error.is_some());
+        let stored = db.get_thumbnail(id, 64).await.unwrap().unwrap();
+
+        // Hit: served from the table
+        let second = get_cached_thumbnails(&db, &[id], 64).await.unwrap();
+        assert!(second[0].cached);
+        assert_eq!(second[0].data, first[0].data);
+
+        // Changing the record invalidates the thumbnail
+        db.update_image(
+            id,
+            UpdateImage {
+                group_name: Some("Edited".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(db.get_thumbnail(id, 64).await.unwrap().is_none());
+        let third = get_cached_thumbnails(&db, &[id], 64).await.unwrap();
+        assert!(!third[0].cached);
+        let regenerated = db.get_thumbnail(id, 64).await.unwrap().unwrap();
+        assert!(regenerated.generated_at >= stored.generated_at);
+    }
+
+    #[tokio::test]
+    async fn test_put_thumbnail_limits_and_pruning() {
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let added = db
+            .batch_add_images(
+                (0..3)
+                    .map(|i| NewImage {
+                        file_path: format!("/thumbs/{}.png", i),
+                        ..Default::default()
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let oversized = vec![0u8; crate::db::MAX_THUMBNAIL_BYTES + 1];
+        assert!(db
+            .put_thumbnail(added.inserted[0], 64, "jpeg", &oversized)
+            .await
+            .is_err());
+        assert!(db.put_thumbnail(9999, 64, "jpeg", b"x").await.is_err());
+
+        for id in &added.inserted {
+            db.put_thumbnail(*id, 64, "jpeg", &[0u8; 100])
+                .await
+                .unwrap();
+        }
+        // Room for two: the oldest entry goes
+        assert_eq!(db.prune_thumbnails(250).await.unwrap(), 1);
+        assert_eq!(
+            db.get_thumbnails(&added.inserted, 64).await.unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_disk_cache_name_keys() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("a.png");
+        image::RgbImage::new(10, 10).save(&path).unwrap();
+        let other = temp.path().join("b.png");
+        std::fs::copy(&path, &other).unwrap();
+
+        let jpeg = ThumbnailFormat::Jpeg;
+        let name = disk_cache_name(&path, 64, jpeg).unwrap();
+        assert!(name.ends_with(".jpg"));
+        assert_eq!(disk_cache_name(&path, 64, jpeg).unwrap(), name);
+        assert_ne!(disk_cache_name(&other, 64, jpeg).unwrap(), name);
+        assert_ne!(disk_cache_name(&path, 128, jpeg).unwrap(), name);
+        let webp = disk_cache_name(&path, 64, ThumbnailFormat::Webp).unwrap();
+        assert!(webp.ends_with(".webp"));
+
+        // A new modification time is a new key
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_000_000))
+            .unwrap();
+        assert_ne!(disk_cache_name(&path, 64, jpeg).unwrap(), name);
+
+        assert!(disk_cache_name(&temp.path().join("missing.png"), 64, jpeg).is_err());
+    }
+
+    #[test]
+    fn test_disk_thumbnails_cache_and_invalidate() {
+        let temp = tempdir().unwrap();
+        let cache = temp.path().join("cache");
+        let paths: Vec<String> = (0..DISK_THUMBNAIL_CHUNK + 3)
+            .map(|i| {
+                let path = temp.path().join(format!("{}.png", i));
+                image::RgbImage::new(120, 80).save(&path).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .chain(["/missing.png".to_string()])
+            .collect();
+
+        let mut reports = Vec::new();
+        let first =
+            generate_disk_thumbnails(&cache, &paths, 64, ThumbnailFormat::Jpeg, |done, total| {
+                reports.push((done, total))
+            })
+            .unwrap();
+        let total = paths.len();
+        assert_eq!(reports, [(DISK_THUMBNAIL_CHUNK, total), (total, total)]);
+        assert_eq!(first.len(), total);
+        assert!(first[..total - 1]
+            .iter()
+            .all(|t| !t.cached && t.error.is_none()));
+        assert_eq!(first[3].source, paths[3]);
+        let thumb = image::open(first[0].thumbnail.as_ref().unwrap()).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (64, 43));
+        assert!(first[total - 1].thumbnail.is_none() && first[total - 1].error.is_some());
+
+        // Second run is served from the cache
+        let second =
+            generate_disk_thumbnails(&cache, &paths[..2], 64, ThumbnailFormat::Jpeg, |_, _| {})
+                .unwrap();
+        assert!(second.iter().all(|t| t.cached));
+        assert_eq!(second[0].thumbnail, first[0].thumbnail);
+
+        // Rewriting the source invalidates its entry
+        image::RgbImage::new(40, 40).save(&paths[0]).unwrap();
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&paths[0])
+            .unwrap();
+        file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(5))
+            .unwrap();
+        let third =
+            generate_disk_thumbnails(&cache, &paths[..1], 64, ThumbnailFormat::Jpeg, |_, _| {})
+                .unwrap();
+        assert!(!third[0].cached);
+        assert_ne!(third[0].thumbnail, first[0].thumbnail);
+
+        assert_eq!(clear_disk_cache(&cache).unwrap(), DISK_THUMBNAIL_CHUNK + 4);
+        assert_eq!(clear_disk_cache(&cache).unwrap(), 0);
+        assert_eq!(clear_disk_cache(&temp.path().join("nowhere")).unwrap(), 0);
+    }
+}
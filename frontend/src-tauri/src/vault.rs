@@ -0,0 +1,476 @@
+//! Password-protected settings vault: one file per account holding the
+//! account's settings JSON, encrypted with AES-256-GCM under a key derived
+//! from the master password with Argon2id
+
+pub mod legacy;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::password_hash::rand_core::RngCore;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+const FORMAT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// Key in the vault contents naming the account it belongs to
+pub(crate) const ACCOUNT_KEY: &str = "account_name";
+
+#[derive(Debug, thiserror::Error)]
+pub enum VaultError {
+    #[error("Account '{0}' does not exist")]
+    NotFound(String),
+    #[error("Account '{0}' already exists")]
+    AlreadyExists(String),
+    #[error("Invalid account name '{0}'")]
+    InvalidAccountName(String),
+    #[error("Invalid password")]
+    WrongPassword,
+    #[error("Account name mismatch")]
+    AccountMismatch,
+    #[error("Vault file is corrupted: {0}")]
+    Corrupt(String),
+    #[error("Key derivation failed: {0}")]
+    Kdf(String),
+    #[error("Could not import the old vault: {0}")]
+    Legacy(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Argon2id cost settings, stored in the vault so they can be raised for
+/// new vaults without breaking old ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory in KiB
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// The OWASP-recommended minimum: 19 MiB, two passes, one lane
+    fn default() -> Self {
+        KdfParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// The on-disk layout; everything but the ciphertext is public
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    kdf: KdfParams,
+    /// Base64 Argon2 salt
+    salt: String,
+    /// Base64 96-bit GCM nonce, fresh for every save
+    nonce: String,
+    /// Base64 ciphertext followed by the GCM tag
+    ciphertext: String,
+}
+
+/// An unlocked vault. The derived key is kept (zeroed on drop) so settings
+/// can be saved again without asking for the password.
+pub struct Vault {
+    path: PathBuf,
+    account_name: String,
+    kdf: KdfParams,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; KEY_LEN]>,
+    data: Map<String, Value>,
+}
+
+/// File holding `account_name`'s vault inside `dir`. Characters other than
+/// letters, digits, `-`, `_` and `.` are dropped, as the old Python vault did.
+pub fn vault_path(dir: &Path, account_name: &str) -> Result<PathBuf, VaultError> {
+    let safe = safe_account_name(account_name);
+    if safe.is_empty() || safe.chars().all(|c| c == '.') {
+        return Err(VaultError::InvalidAccountName(account_name.to_string()));
+    }
+    Ok(dir.join(format!("{}.vault", safe)))
+}
+
+pub(crate) fn safe_account_name(account_name: &str) -> String {
+    account_name
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect()
+}
+
+impl Vault {
+    /// Create the vault for a new account holding just its name
+    pub fn create(dir: &Path, account_name: &str, password: &str) -> Result<Vault, VaultError> {
+        Self::create_with(
+            dir,
+            account_name,
+            password,
+            KdfParams::default(),
+            Map::new(),
+        )
+    }
+
+    /// Create a vault with explicit KDF costs and initial contents
+    pub fn create_with(
+        dir: &Path,
+        account_name: &str,
+        password: &str,
+        kdf: KdfParams,
+        mut data: Map<String, Value>,
+    ) -> Result<Vault, VaultError> {
+        let path = vault_path(dir, account_name)?;
+        if path.exists() {
+            return Err(VaultError::AlreadyExists(account_name.to_string()));
+        }
+
+        data.insert(ACCOUNT_KEY.into(), Value::String(account_name.to_string()));
+        let salt = random_salt();
+        let vault = Vault {
+            key: derive_key(password, &salt, kdf)?,
+            path,
+            account_name: account_name.to_string(),
+            kdf,
+            salt,
+            data,
+        };
+        std::fs::create_dir_all(dir)?;
+        vault.save()?;
+        Ok(vault)
+    }
+
+    /// Unlock `account_name`'s vault in `dir`
+    pub fn open(dir: &Path, account_name: &str, password: &str) -> Result<Vault, VaultError> {
+        let path = vault_path(dir, account_name)?;
+        let raw = match std::fs::read(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(VaultError::NotFound(account_name.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let file: VaultFile =
+            serde_json::from_slice(&raw).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+        if file.version != FORMAT_VERSION {
+            return Err(VaultError::Corrupt(format!(
+                "unsupported version {}",
+                file.version
+            )));
+        }
+        let salt: [u8; SALT_LEN] = decode(&file.salt)?
+            .try_into()
+            .map_err(|_| VaultError::Corrupt("bad salt length".into()))?;
+        let nonce = decode(&file.nonce)?;
+        if nonce.len() != 12 {
+            return Err(VaultError::Corrupt("bad nonce length".into()));
+        }
+
+        let key = derive_key(password, &salt, file.kdf)?;
+        // The tag check fails for a wrong key and for tampered files alike;
+        // with an intact file the former is by far the likelier
+        let plaintext = Zeroizing::new(
+            cipher(&key)
+                .decrypt(
+                    Nonce::from_slice(&nonce),
+                    decode(&file.ciphertext)?.as_slice(),
+                )
+                .map_err(|_| VaultError::WrongPassword)?,
+        );
+        let data: Map<String, Value> =
+            serde_json::from_slice(&plaintext).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+
+        if data.get(ACCOUNT_KEY).and_then(Value::as_str) != Some(account_name) {
+            return Err(VaultError::AccountMismatch);
+        }
+
+        Ok(Vault {
+            path,
+            account_name: account_name.to_string(),
+            kdf: file.kdf,
+            salt,
+            key,
+            data,
+        })
+    }
+
+    pub fn account_name(&self) -> &str {
+        &self.account_name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Everything stored in the vault, `account_name` included
+    pub fn data(&self) -> &Map<String, Value> {
+        &self.data
+    }
+
+    /// Merge `values` into the contents (top-level keys are replaced) and
+    /// save. The account name can't be changed this way.
+    pub fn update(&mut self, values: Map<String, Value>) -> Result<(), VaultError> {
+        for (key, value) in values {
+            if key != ACCOUNT_KEY {
+                self.data.insert(key, value);
+            }
+        }
+        self.save()
+    }
+
+    /// Re-encrypt the contents under a key derived from `new_password`, with
+    /// a fresh salt. The old file is only replaced once the new one is
+    /// fully written.
+    pub fn change_password(&mut self, new_password: &str) -> Result<(), VaultError> {
+        let salt = random_salt();
+        let key = derive_key(new_password, &salt, self.kdf)?;
+        self.write(&key, &salt)?;
+        self.key = key;
+        self.salt = salt;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), VaultError> {
+        self.write(&self.key, &self.salt)
+    }
+
+    fn write(&self, key: &[u8; KEY_LEN], salt: &[u8; SALT_LEN]) -> Result<(), VaultError> {
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(&self.data).map_err(|e| VaultError::Corrupt(e.to_string()))?,
+        );
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher(key)
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| VaultError::Corrupt("encryption failed".into()))?;
+
+        let file = VaultFile {
+            version: FORMAT_VERSION,
+            kdf: self.kdf,
+            salt: BASE64.encode(salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        let json =
+            serde_json::to_vec_pretty(&file).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+        write_atomically(&self.path, &json)?;
+        Ok(())
+    }
+}
+
+fn random_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+fn derive_key(
+    password: &str,
+    salt: &[u8],
+    kdf: KdfParams,
+) -> Result<Zeroizing<[u8; KEY_LEN]>, VaultError> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+        .map_err(|e| VaultError::Kdf(e.to_string()))?;
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, key.as_mut_slice())
+        .map_err(|e| VaultError::Kdf(e.to_string()))?;
+    Ok(key)
+}
+
+fn cipher(key: &[u8; KEY_LEN]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, VaultError> {
+    BASE64
+        .decode(value)
+        .map_err(|e| VaultError::Corrupt(e.to_string()))
+}
+
+/// Write to a sibling temp file readable only by the owner, then rename it
+/// over `path`
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("vault.tmp");
+    {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        std::io::Write::write_all(&mut file, bytes)?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Cheap costs so the tests don't spend seconds in Argon2
+    const FAST: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn create(dir: &Path, account: &str, password: &str) -> Vault {
+        Vault::create_with(dir, account, password, FAST, Map::new()).unwrap()
+    }
+
+    fn settings(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_create_then_open() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut vault = create(temp.path(), "alice", "hunter2");
+        vault
+            .update(settings(json!({"theme": "light", "profiles": {"a": 1}})))
+            .unwrap();
+
+        let opened = Vault::open(temp.path(), "alice", "hunter2").unwrap();
+        assert_eq!(opened.account_name(), "alice");
+        assert_eq!(opened.data()["theme"], "light");
+        assert_eq!(opened.data()["profiles"], json!({"a": 1}));
+        assert_eq!(opened.data()["account_name"], "alice");
+
+        // The file doesn't give the settings away
+        let raw = std::fs::read_to_string(opened.path()).unwrap();
+        assert!(!raw.contains("light"));
+        assert!(!raw.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_wrong_password_and_unknown_account() {
+        let temp = tempfile::tempdir().unwrap();
+        create(temp.path(), "alice", "hunter2");
+
+        assert!(matches!(
+            Vault::open(temp.path(), "alice", "hunter3"),
+            Err(VaultError::WrongPassword)
+        ));
+        assert!(matches!(
+            Vault::open(temp.path(), "alice", ""),
+            Err(VaultError::WrongPassword)
+        ));
+        assert!(matches!(
+            Vault::open(temp.path(), "bob", "hunter2"),
+            Err(VaultError::NotFound(_))
+        ));
+        assert!(matches!(
+            Vault::create_with(temp.path(), "alice", "other", FAST, Map::new()),
+            Err(VaultError::AlreadyExists(_))
+        ));
+    }
+
+    #[test]
+    fn test_special_characters_in_passwords() {
+        let temp = tempfile::tempdir().unwrap();
+        let passwords = [
+            r#"it's "quoted""#,
+            r"back\slash\\ and \n escapes",
+            "'); import os; os.system('x') #",
+            "{}{{}} %s $HOME `cmd`",
+            "pässwörd ✓ 密码 🔑",
+            "  leading and trailing spaces  ",
+            "line\nbreak\ttab\0nul",
+        ];
+        for (i, password) in passwords.iter().enumerate() {
+            let account = format!("user{}", i);
+            create(temp.path(), &account, password);
+            assert!(Vault::open(temp.path(), &account, password).is_ok());
+            assert!(matches!(
+                Vault::open(temp.path(), &account, &format!("{}x", password)),
+                Err(VaultError::WrongPassword)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_change_password_keeps_settings() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut vault = create(temp.path(), "alice", "old");
+        vault.update(settings(json!({"theme": "light"}))).unwrap();
+        let old_salt = vault.salt;
+
+        vault.change_password("new \"pass\"").unwrap();
+        assert_ne!(vault.salt, old_salt);
+        // The unlocked vault keeps working with the new key
+        vault.update(settings(json!({"theme": "dark"}))).unwrap();
+
+        assert!(matches!(
+            Vault::open(temp.path(), "alice", "old"),
+            Err(VaultError::WrongPassword)
+        ));
+        let reopened = Vault::open(temp.path(), "alice", "new \"pass\"").unwrap();
+        assert_eq!(reopened.data()["theme"], "dark");
+        assert!(!temp.path().join("alice.vault.tmp").exists());
+    }
+
+    #[test]
+    fn test_update_cannot_rename_account() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut vault = create(temp.path(), "alice", "pw");
+        vault
+            .update(settings(json!({"account_name": "mallory", "theme": "x"})))
+            .unwrap();
+        let reopened = Vault::open(temp.path(), "alice", "pw").unwrap();
+        assert_eq!(reopened.data()["account_name"], "alice");
+        assert_eq!(reopened.data()["theme"], "x");
+    }
+
+    #[test]
+    fn test_account_names_map_to_files() {
+        let dir = Path::new("/vaults");
+        assert_eq!(
+            vault_path(dir, "Jane Doe/../x").unwrap(),
+            dir.join("JaneDoe..x.vault")
+        );
+        assert!(matches!(
+            vault_path(dir, "../"),
+            Err(VaultError::InvalidAccountName(_))
+        ));
+        assert!(matches!(
+            vault_path(dir, " / "),
+            Err(VaultError::InvalidAccountName(_))
+        ));
+
+        // Two names that sanitize alike share a file, so the second can
+        // neither be created nor opened with the first one's password
+        let temp = tempfile::tempdir().unwrap();
+        create(temp.path(), "ab", "pw");
+        assert!(matches!(
+            Vault::open(temp.path(), "a b", "pw"),
+            Err(VaultError::AccountMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_file_is_rejected() {
+        let temp = tempfile::tempdir().unwrap();
+        let vault = create(temp.path(), "alice", "pw");
+        let mut file: VaultFile =
+            serde_json::from_slice(&std::fs::read(vault.path()).unwrap()).unwrap();
+        let mut ciphertext = BASE64.decode(&file.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        file.ciphertext = BASE64.encode(ciphertext);
+        std::fs::write(vault.path(), serde_json::to_vec(&file).unwrap()).unwrap();
+        assert!(Vault::open(temp.path(), "alice", "pw").is_err());
+
+        std::fs::write(vault.path(), b"not json").unwrap();
+        assert!(matches!(
+            Vault::open(temp.path(), "alice", "pw"),
+            Err(VaultError::Corrupt(_))
+        ));
+    }
+}
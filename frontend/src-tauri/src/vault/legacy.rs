@@ -0,0 +1,263 @@
+//! One-time import of vaults written by the Python `VaultManager`: a PKCS#12
+//! keystore holding an AES key, the AES-GCM vault that key encrypts and a
+//! pepper file, all in `~/.image-toolkit/secrets`
+
+use super::{safe_account_name, KdfParams, Vault, VaultError};
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Reads the vault through the Python backend and prints its JSON after a
+/// marker (the JVM writes its own chatter to stdout). The request, password
+/// included, arrives on stdin.
+const DECRYPT_SCRIPT: &str = r#"
+import json
+import sys
+
+request = json.load(sys.stdin)
+sys.path.insert(0, request["root"])
+_stdout = sys.stdout
+sys.stdout = sys.stderr
+
+import backend.src.constants as udef
+from backend.src.core.vault_manager import VaultManager
+
+udef.update_cryptographic_values(request["account_name"])
+vm = VaultManager(udef.JAR_FILE)
+vm.load_keystore(udef.KEYSTORE_FILE, request["password"])
+vm.get_secret_key(udef.KEY_ALIAS, request["password"])
+vm.init_vault(udef.VAULT_FILE)
+data = vm.load_data()
+
+sys.stdout = _stdout
+print("RESULT: " + json.dumps(json.loads(data)), flush=True)
+"#;
+
+/// Where `VaultManager` kept an account's files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyFiles {
+    pub keystore: PathBuf,
+    pub vault: PathBuf,
+    pub pepper: PathBuf,
+}
+
+impl LegacyFiles {
+    pub fn locate(secrets_dir: &Path, account_name: &str) -> Self {
+        let suffix = safe_account_name(account_name);
+        let name = |stem: &str, ext: &str| {
+            if suffix.is_empty() {
+                secrets_dir.join(format!("{}.{}", stem, ext))
+            } else {
+                secrets_dir.join(format!("{}-{}.{}", stem, suffix, ext))
+            }
+        };
+        LegacyFiles {
+            keystore: name("my_keystore", "p12"),
+            vault: name("my_secure_data", "vault"),
+            pepper: name("pepper", "txt"),
+        }
+    }
+
+    pub fn exist(&self) -> bool {
+        self.keystore.is_file() && self.vault.is_file() && self.pepper.is_file()
+    }
+}
+
+/// Whether `password` matches the salted, peppered SHA-256 that
+/// `VaultManager` stored next to the settings
+pub fn password_matches(data: &Map<String, Value>, password: &str, pepper: &str) -> bool {
+    let (Some(hash), Some(salt)) = (
+        data.get("hashed_password").and_then(Value::as_str),
+        data.get("salt").and_then(Value::as_str),
+    ) else {
+        return false;
+    };
+    let digest = Sha256::digest(format!("{}{}{}", password, salt, pepper).as_bytes());
+    hex::encode(digest).eq_ignore_ascii_case(hash)
+}
+
+/// Decrypt the legacy vault with the Python backend checked out at
+/// `backend_root`; only the JVM can read the keystore holding its key
+pub fn decrypt_with_backend(
+    backend_root: &Path,
+    account_name: &str,
+    password: &str,
+) -> Result<Map<String, Value>> {
+    let mut child = Command::new("python3")
+        .arg("-c")
+        .arg(DECRYPT_SCRIPT)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute Python")?;
+
+    let request = serde_json::json!({
+        "root": backend_root,
+        "account_name": account_name,
+        "password": password,
+    });
+    child
+        .stdin
+        .take()
+        .context("Python stdin unavailable")?
+        .write_all(request.to_string().as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.is_empty() {
+        log::info!("Python stderr: {}", stderr);
+    }
+    if !output.status.success() {
+        let reason = stderr.lines().last().unwrap_or("unknown error");
+        bail!("{}", reason);
+    }
+
+    let json = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("RESULT: "))
+        .context("No result marker found in backend output")?;
+    serde_json::from_str(json).context("Failed to parse the old vault's contents")
+}
+
+/// Create a native vault in `dir` from the legacy one, read with `decrypt`,
+/// after checking `password` against the legacy hash. The hash and salt are
+/// dropped; the legacy files are left where they are.
+pub fn import<F>(
+    dir: &Path,
+    account_name: &str,
+    password: &str,
+    files: &LegacyFiles,
+    kdf: KdfParams,
+    decrypt: F,
+) -> Result<Vault, VaultError>
+where
+    F: FnOnce() -> Result<Map<String, Value>>,
+{
+    let pepper = std::fs::read_to_string(&files.pepper)?;
+    let mut data = decrypt().map_err(|e| VaultError::Legacy(format!("{:#}", e)))?;
+
+    if data.get("account_name").and_then(Value::as_str) != Some(account_name) {
+        return Err(VaultError::AccountMismatch);
+    }
+    if !password_matches(&data, password, pepper.trim()) {
+        return Err(VaultError::WrongPassword);
+    }
+
+    data.remove("hashed_password");
+    data.remove("salt");
+    let vault = Vault::create_with(dir, account_name, password, kdf, data)?;
+    log::info!(
+        "Imported the old vault for '{}' into {}",
+        account_name,
+        vault.path().display()
+    );
+    Ok(vault)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FAST: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    /// Contents as `VaultManager.save_account_credentials` and later
+    /// settings saves left them
+    fn legacy_data(account: &str, password: &str, pepper: &str) -> Map<String, Value> {
+        let salt = "00112233445566778899aabbccddeeff";
+        let hash = hex::encode(Sha256::digest(format!("{}{}{}", password, salt, pepper)));
+        json!({
+            "account_name": account,
+            "hashed_password": hash,
+            "salt": salt,
+            "theme": "light",
+            "system_preference_profiles": {"work": {}},
+        })
+        .as_object()
+        .unwrap()
+        .clone()
+    }
+
+    fn legacy_files(secrets: &Path, account: &str, pepper: &str) -> LegacyFiles {
+        let files = LegacyFiles::locate(secrets, account);
+        std::fs::write(&files.keystore, b"").unwrap();
+        std::fs::write(&files.vault, b"").unwrap();
+        std::fs::write(&files.pepper, format!("{}\n", pepper)).unwrap();
+        files
+    }
+
+    #[test]
+    fn test_locate_matches_python_names() {
+        let dir = Path::new("/secrets");
+        let files = LegacyFiles::locate(dir, "Jane Doe!");
+        assert_eq!(files.keystore, dir.join("my_keystore-JaneDoe.p12"));
+        assert_eq!(files.vault, dir.join("my_secure_data-JaneDoe.vault"));
+        assert_eq!(files.pepper, dir.join("pepper-JaneDoe.txt"));
+        assert_eq!(
+            LegacyFiles::locate(dir, "!!").vault,
+            dir.join("my_secure_data.vault")
+        );
+    }
+
+    #[test]
+    fn test_import_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let (secrets, vaults) = (temp.path().join("secrets"), temp.path().join("vaults"));
+        std::fs::create_dir(&secrets).unwrap();
+        let password = r#"o'ld "pa\ss""#;
+        let pepper = "c0ffee";
+        let files = legacy_files(&secrets, "alice", pepper);
+        assert!(files.exist());
+
+        let data = legacy_data("alice", password, pepper);
+        let vault = import(&vaults, "alice", password, &files, FAST, || Ok(data)).unwrap();
+        assert_eq!(vault.data()["theme"], "light");
+        assert!(!vault.data().contains_key("hashed_password"));
+        assert!(!vault.data().contains_key("salt"));
+
+        let opened = Vault::open(&vaults, "alice", password).unwrap();
+        assert_eq!(
+            opened.data()["system_preference_profiles"],
+            json!({"work": {}})
+        );
+        assert!(files.vault.exists());
+
+        // Once imported, a second import finds the native vault in the way
+        let again = import(&vaults, "alice", password, &files, FAST, || {
+            Ok(legacy_data("alice", password, pepper))
+        });
+        assert!(matches!(again, Err(VaultError::AlreadyExists(_))));
+    }
+
+    #[test]
+    fn test_import_rejects_wrong_password_and_account() {
+        let temp = tempfile::tempdir().unwrap();
+        let files = legacy_files(temp.path(), "alice", "pepper");
+        let vaults = temp.path().join("vaults");
+
+        let wrong = import(&vaults, "alice", "guess", &files, FAST, || {
+            Ok(legacy_data("alice", "right", "pepper"))
+        });
+        assert!(matches!(wrong, Err(VaultError::WrongPassword)));
+
+        let other = import(&vaults, "alice", "right", &files, FAST, || {
+            Ok(legacy_data("bob", "right", "pepper"))
+        });
+        assert!(matches!(other, Err(VaultError::AccountMismatch)));
+
+        let failed = import(&vaults, "alice", "right", &files, FAST, || {
+            bail!("keystore password was incorrect")
+        });
+        assert!(matches!(failed, Err(VaultError::Legacy(_))));
+        assert!(!vaults.join("alice.vault").exists());
+    }
+}
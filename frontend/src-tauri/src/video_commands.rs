@@ -1,5 +1,13 @@
+use crate::tasks::TaskManager;
+use crate::videos;
+use base::core::video_converter::{
+    detect_hw_encoders_core, encoder_args, extract_video_frames_core, ffmpeg_command,
+    resolve_encoder, run_ffmpeg, SOFTWARE_ENCODER,
+};
 use serde::{Deserialize, Serialize};
-use tauri::Emitter;
+use serde_json::json;
+use std::path::Path;
+use tauri::{Emitter, State};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct VideoExtractionParams {
@@ -12,6 +20,9 @@ pub struct VideoExtractionParams {
     pub mute_audio: bool,
     pub use_ffmpeg: bool,
     pub speed: f64,
+    /// `auto` or one of `VIDEO_ENCODERS`; libx264 when not given
+    #[serde(default)]
+    pub encoder: Option<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -52,13 +63,22 @@ async fn extract_with_ffmpeg(
     params: VideoExtractionParams,
     task_id: String,
 ) -> Result<String, String> {
-    use std::process::Command;
-
     let t_start = params.start_ms as f64 / 1000.0;
     let t_end = params.end_ms as f64 / 1000.0;
     let duration = t_end - t_start;
+    let encoder = match params.encoder.as_deref() {
+        // Probing may test-encode with each hardware encoder
+        Some(requested) => {
+            let requested = requested.to_string();
+            tauri::async_runtime::spawn_blocking(move || resolve_encoder(&requested))
+                .await
+                .map_err(|e| format!("FFmpeg error: {}", e))?
+                .map_err(|e| e.to_string())?
+        }
+        None => SOFTWARE_ENCODER.to_string(),
+    };
 
-    let mut cmd = Command::new("ffmpeg");
+    let mut cmd = ffmpeg_command();
     cmd.args(&[
         "-y",
         "-ss",
@@ -82,12 +102,9 @@ async fn extract_with_ffmpeg(
         filters.push(format!("setpts={}*PTS", pts_mult));
     }
 
-    if !filters.is_empty() {
-        cmd.args(&["-vf", &filters.join(",")]);
-    }
-
     // Codec settings
-    cmd.args(&["-c:v", "libx264", "-movflags", "+faststart"]);
+    cmd.args(encoder_args(&encoder, &filters));
+    cmd.args(&["-movflags", "+faststart"]);
 
     // Audio handling
     if params.mute_audio {
@@ -119,33 +136,49 @@ async fn extract_with_ffmpeg(
 
     cmd.arg(&params.output_path);
 
-    // Emit progress
-    let _ = app.emit(
-        "task-progress",
-        VideoExtractionProgress {
-            task_id: task_id.clone(),
-            progress: 30,
-            message: "Running FFmpeg...".to_string(),
-            status: "running".to_string(),
-        },
-    );
-
-    // Execute command
-    let output = cmd.output().map_err(|e| format!("FFmpeg error: {}", e))?;
-
-    if output.status.success() {
-        let _ = app.emit(
-            "task-complete",
-            serde_json::json!({
-                "taskId": task_id,
-                "success": true,
-                "message": "Video extraction completed"
-            }),
-        );
-        Ok(params.output_path)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("FFmpeg failed: {}", error))
+    // The clip's length once sped up or slowed down
+    let output_duration = duration / params.speed.max(0.001);
+    let progress_app = app.clone();
+    let progress_task_id = task_id.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        run_ffmpeg(
+            cmd,
+            Some(output_duration),
+            || false,
+            |progress| {
+                let percent = progress.fraction.unwrap_or(0.0) * 100.0;
+                let message = match progress.eta_seconds {
+                    Some(eta) => format!("Running FFmpeg... {:.0}s left", eta),
+                    None => "Running FFmpeg...".to_string(),
+                };
+                let _ = progress_app.emit(
+                    "task-progress",
+                    VideoExtractionProgress {
+                        task_id: progress_task_id.clone(),
+                        progress: percent as u32,
+                        message,
+                        status: "running".to_string(),
+                    },
+                );
+            },
+        )
+    })
+    .await
+    .map_err(|e| format!("FFmpeg error: {}", e))?;
+
+    match result {
+        Ok(_) => {
+            let _ = app.emit(
+                "task-complete",
+                serde_json::json!({
+                    "taskId": task_id,
+                    "success": true,
+                    "message": "Video extraction completed"
+                }),
+            );
+            Ok(params.output_path)
+        }
+        Err(e) => Err(format!("FFmpeg failed: {:#}", e)),
     }
 }
 
@@ -225,99 +258,51 @@ except Exception as e:
     }
 }
 
-/// Extract frames from video at specific intervals
+/// Write a frame of `video_path` every `interval_ms` into `output_dir` as
+/// numbered PNGs and return their paths. Runs as a `frame_extraction` task
+/// that `cancel_task` can stop, with progress from ffmpeg's own reports.
 #[tauri::command]
 pub async fn extract_video_frames(
     app: tauri::AppHandle,
+    manager: State<'_, TaskManager>,
     video_path: String,
     output_dir: String,
-    interval_ms: i64,
-    task_id: String,
+    interval_ms: u64,
+    task_id: Option<String>,
 ) -> Result<Vec<String>, String> {
-    // Use the Rust-based frame extraction from the base crate
-    let _ = app.emit(
-        "task-progress",
-        VideoExtractionProgress {
-            task_id: task_id.clone(),
-            progress: 0,
-            message: "Starting frame extraction...".to_string(),
-            status: "running".to_string(),
-        },
-    );
-
-    // Call Python backend with base module
-    let python_script = format!(
-        r#"
-import sys
-import json
-sys.path.insert(0, '../../')
-import base
-
-frames = base.extract_video_frames("{}", "{}", {})
-print(json.dumps({{"frames": frames}}))
-"#,
-        video_path, output_dir, interval_ms
-    );
-
-    let output = std::process::Command::new("python")
-        .arg("-c")
-        .arg(&python_script)
-        .output()
-        .map_err(|e| format!("Failed to run Python: {}", e))?;
-
-    if output.status.success() {
-        let result_str = String::from_utf8_lossy(&output.stdout);
-        let result: serde_json::Value = serde_json::from_str(&result_str)
-            .map_err(|e| format!("Failed to parse result: {}", e))?;
-
-        let frames = result["frames"]
-            .as_array()
-            .ok_or("Invalid frames data")?
-            .iter()
-            .filter_map(|v| v.as_str().map(String::from))
-            .collect();
-
-        let _ = app.emit(
-            "task-complete",
-            serde_json::json!({
-                "taskId": task_id,
-                "success": true,
-                "message": "Frame extraction completed"
-            }),
+    let metadata = json!({ "videoPath": video_path, "intervalMs": interval_ms });
+    let task = manager.start_task_for(task_id, "frame_extraction", metadata)?;
+    task.run(app, move |ctx| {
+        let frames = extract_video_frames_core(
+            Path::new(&video_path),
+            Path::new(&output_dir),
+            interval_ms,
+            || ctx.token().is_cancelled(),
+            |done| ctx.progress((done * 100.0) as u32, "Extracting frames"),
         );
+        // A cancelled extraction fails; report it as cancelled instead
+        ctx.token().check()?;
+        Ok(frames?
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect())
+    })
+    .await
+    .map_err(|e| format!("Failed to extract frames: {}", e))
+}
 
-        Ok(frames)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Frame extraction failed: {}", error))
-    }
+/// The hardware video encoders usable here, for the encoder choice of
+/// `extract_video_clip`; detected once per run of the app
+#[tauri::command]
+pub async fn detect_hw_encoders() -> Result<Vec<String>, String> {
+    tauri::async_runtime::spawn_blocking(|| detect_hw_encoders_core().to_vec())
+        .await
+        .map_err(|e| format!("Failed to detect encoders: {}", e))
 }
 
 /// Get video metadata (duration, dimensions, codec)
 #[tauri::command]
 pub fn get_video_metadata(video_path: String) -> Result<serde_json::Value, String> {
-    use std::process::Command;
-
-    let output = Command::new("ffprobe")
-        .args(&[
-            "-v",
-            "quiet",
-            "-print_format",
-            "json",
-            "-show_format",
-            "-show_streams",
-            &video_path,
-        ])
-        .output()
-        .map_err(|e| format!("FFprobe error: {}", e))?;
-
-    if output.status.success() {
-        let result_str = String::from_utf8_lossy(&output.stdout);
-        let metadata: serde_json::Value = serde_json::from_str(&result_str)
-            .map_err(|e| format!("Failed to parse metadata: {}", e))?;
-        Ok(metadata)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to get video metadata: {}", error))
-    }
+    videos::ffprobe_json(Path::new(&video_path))
+        .map_err(|e| format!("Failed to get video metadata: {:#}", e))
 }
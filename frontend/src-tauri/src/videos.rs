@@ -0,0 +1,524 @@
+use crate::db::{BatchItemError, Db, MediaType, NewImage};
+use crate::tasks::{self, CancellationToken, TaskError};
+use crate::thumbnails::{self, DiskThumbnail, ThumbnailFormat};
+use anyhow::{bail, Context, Result};
+use base::core::video_converter::ProcessLimit;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, OnceLock};
+
+/// Extensions registered as videos, and whose thumbnails are taken from a
+/// frame rather than decoded as an image
+pub const VIDEO_EXTENSIONS: [&str; 7] = ["mp4", "mkv", "avi", "mov", "webm", "flv", "m4v"];
+
+/// Longest edge of the posters `import_videos` writes; the default size of
+/// `generate_thumbnails`, so the frontend finds them cached
+pub const POSTER_EDGE: u32 = 256;
+
+/// Videos probed between progress reports and cancellation checks during
+/// `import_videos`
+pub const PROBE_CHUNK: usize = 16;
+
+/// Whether `path` has one of `VIDEO_EXTENSIONS`, in any case
+pub fn is_video_path(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Caps the ffprobe and ffmpeg children started for the library, however
+/// many thumbnail or import workers ask at once
+pub fn process_limit() -> &'static ProcessLimit {
+    static LIMIT: OnceLock<ProcessLimit> = OnceLock::new();
+    LIMIT.get_or_init(|| ProcessLimit::new(ProcessLimit::default_max()))
+}
+
+/// ffprobe's report on the file at `path`: its container under `format` and
+/// every stream under `streams`
+pub fn ffprobe_json(path: &Path) -> Result<Value> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-print_format", "json"])
+        .args(["-show_format", "-show_streams"])
+        .arg(path)
+        .output()
+        .context("Failed to execute ffprobe")?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout).context("Failed to parse ffprobe output")
+}
+
+/// What the library records about a video
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    pub duration_secs: Option<f64>,
+    /// Size as displayed, so portrait phone videos are taller than wide
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub codec: Option<String>,
+}
+
+impl VideoMetadata {
+    /// Reads an `ffprobe_json` report. The first video stream that isn't
+    /// embedded cover art gives the size and codec; the container's duration
+    /// is preferred to the stream's.
+    pub fn from_ffprobe(report: &Value) -> Result<Self> {
+        let stream = report["streams"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|s| s["codec_type"] == "video" && s["disposition"]["attached_pic"] != 1)
+            .context("No video stream")?;
+
+        // ffprobe writes durations as decimal strings
+        let seconds = |value: &Value| {
+            value
+                .as_str()
+                .and_then(|d| d.parse::<f64>().ok())
+                .filter(|d| d.is_finite() && *d > 0.0)
+        };
+        let dimension = |key: &str| {
+            stream[key]
+                .as_i64()
+                .and_then(|v| i32::try_from(v).ok())
+                .filter(|v| *v > 0)
+        };
+        let (mut width, mut height) = (dimension("width"), dimension("height"));
+        if rotation(stream) % 180 != 0 {
+            std::mem::swap(&mut width, &mut height);
+        }
+
+        Ok(VideoMetadata {
+            duration_secs: seconds(&report["format"]["duration"])
+                .or_else(|| seconds(&stream["duration"])),
+            width,
+            height,
+            codec: stream["codec_name"].as_str().map(String::from),
+        })
+    }
+}
+
+/// Rotation of `stream` in degrees, from its display matrix or the older
+/// `rotate` tag
+fn rotation(stream: &Value) -> i64 {
+    stream["side_data_list"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|data| data["rotation"].as_f64())
+        .or_else(|| stream["tags"]["rotate"].as_str()?.parse().ok())
+        .map_or(0, |degrees| degrees.round() as i64)
+}
+
+/// Probes the video at `path`, holding a slot of `process_limit` meanwhile
+pub fn probe_video(path: &Path) -> Result<VideoMetadata> {
+    let _slot = process_limit().acquire();
+    VideoMetadata::from_ffprobe(&ffprobe_json(path)?)
+}
+
+/// The record to add for the video at `path`
+pub fn new_video(path: &str, group: Option<String>, metadata: VideoMetadata) -> NewImage {
+    NewImage {
+        file_path: crate::paths::normalize_path(path),
+        file_size: std::fs::metadata(path).ok().map(|meta| meta.len() as i64),
+        width: metadata.width,
+        height: metadata.height,
+        group_name: group,
+        media_type: MediaType::Video,
+        duration_secs: metadata.duration_secs,
+        video_codec: metadata.codec,
+        ..Default::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoImportPhase {
+    Probing,
+    Registering,
+    Posters,
+}
+
+/// `done` of `total` videos through `phase`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VideoImportProgress {
+    pub phase: VideoImportPhase,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Outcome of `import_videos`. `errors` index into the imported paths.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VideoImportSummary {
+    pub inserted: Vec<i32>,
+    /// Videos already in the library, whose record was refreshed
+    pub updated: Vec<i32>,
+    pub errors: Vec<BatchItemError>,
+    /// Poster of each video added or refreshed, in the order of the paths
+    pub posters: Vec<DiskThumbnail>,
+}
+
+/// Adds the videos at `paths` to the library in `group`, with the duration,
+/// size and codec ffprobe reports, then writes a poster of each into the
+/// thumbnail cache at `cache_dir`. Files without a video extension, or that
+/// ffprobe can't read, are reported as errors and left out.
+pub async fn import_videos(
+    db: &Db,
+    paths: Vec<String>,
+    group: Option<String>,
+    cache_dir: &Path,
+    cancel: &CancellationToken,
+    on_progress: impl Fn(VideoImportProgress) + Send + Sync + 'static,
+) -> Result<VideoImportSummary, TaskError> {
+    let on_progress = Arc::new(on_progress);
+    let (token, report) = (cancel.clone(), on_progress.clone());
+    let probed = tokio::task::spawn_blocking(move || {
+        let progress = |done, total| {
+            report(VideoImportProgress {
+                phase: VideoImportPhase::Probing,
+                done,
+                total,
+            })
+        };
+        let probes = tasks::map_in_chunks(&paths, PROBE_CHUNK, &token, progress, |path| {
+            let path = Path::new(path);
+            if !is_video_path(path) {
+                bail!("Not a video file");
+            }
+            probe_video(path)
+        })?;
+        Ok::<_, TaskError>(paths.into_iter().zip(probes).collect::<Vec<_>>())
+    })
+    .await
+    .context("Import task failed")??;
+
+    let mut summary = VideoImportSummary::default();
+    // Index into `paths` of each probed video
+    let mut indices = Vec::new();
+    let mut videos = Vec::new();
+    for (index, (path, probe)) in probed.into_iter().enumerate() {
+        match probe {
+            Ok(metadata) => {
+                indices.push(index);
+                videos.push(new_video(&path, group.clone(), metadata));
+            }
+            Err(e) => summary.errors.push(BatchItemError {
+                index,
+                file_path: path,
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+
+    on_progress(VideoImportProgress {
+        phase: VideoImportPhase::Registering,
+        done: 0,
+        total: videos.len(),
+    });
+    let sources: Vec<String> = videos.iter().map(|v| v.file_path.clone()).collect();
+    let result = db.batch_add_images(videos).await?;
+    let failed: HashSet<usize> = result.errors.iter().map(|e| e.index).collect();
+    summary
+        .errors
+        .extend(result.errors.into_iter().map(|e| BatchItemError {
+            index: indices[e.index],
+            ..e
+        }));
+    summary.errors.sort_by_key(|e| e.index);
+    summary.inserted = result.inserted;
+    summary.updated = result.updated;
+
+    cancel.check()?;
+    let added: Vec<String> = sources
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !failed.contains(index))
+        .map(|(_, source)| source)
+        .collect();
+    let cache_dir = cache_dir.to_path_buf();
+    summary.posters = tokio::task::spawn_blocking(move || {
+        let progress = |done, total| {
+            on_progress(VideoImportProgress {
+                phase: VideoImportPhase::Posters,
+                done,
+                total,
+            })
+        };
+        thumbnails::generate_disk_thumbnails(
+            &cache_dir,
+            &added,
+            POSTER_EDGE,
+            ThumbnailFormat::default(),
+            progress,
+        )
+    })
+    .await
+    .context("Poster task failed")??;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ConflictPolicy, ExportFormat, SearchQuery, SortBy};
+    use base::core::video_converter::ffmpeg_available;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_video_metadata_from_ffprobe() {
+        let report = json!({
+            "streams": [
+                {
+                    "codec_type": "video", "codec_name": "mjpeg",
+                    "width": 600, "height": 600, "disposition": { "attached_pic": 1 }
+                },
+                {
+                    "codec_type": "video", "codec_name": "h264",
+                    "width": 1920, "height": 1080, "duration": "12.000000",
+                    "disposition": { "attached_pic": 0 },
+                    "side_data_list": [{ "side_data_type": "Display Matrix", "rotation": -90 }]
+                },
+                { "codec_type": "audio", "codec_name": "aac" }
+            ],
+            "format": { "duration": "12.512000" }
+        });
+        assert_eq!(
+            VideoMetadata::from_ffprobe(&report).unwrap(),
+            VideoMetadata {
+                duration_secs: Some(12.512),
+                width: Some(1080),
+                height: Some(1920),
+                codec: Some("h264".to_string()),
+            }
+        );
+
+        // The stream's duration stands in for a container without one, and
+        // the old rotate tag is still read
+        let report = json!({
+            "streams": [{
+                "codec_type": "video", "codec_name": "vp9", "width": 640, "height": 360,
+                "duration": "3.5", "tags": { "rotate": "180" }
+            }],
+            "format": { "duration": "N/A" }
+        });
+        let metadata = VideoMetadata::from_ffprobe(&report).unwrap();
+        assert_eq!(metadata.duration_secs, Some(3.5));
+        assert_eq!((metadata.width, metadata.height), (Some(640), Some(360)));
+
+        let audio_only = json!({ "streams": [{ "codec_type": "audio" }], "format": {} });
+        assert!(VideoMetadata::from_ffprobe(&audio_only).is_err());
+    }
+
+    #[test]
+    fn test_is_video_path() {
+        assert!(is_video_path(Path::new("/clips/a.MP4")));
+        assert!(is_video_path(Path::new("b.webm")));
+        assert!(!is_video_path(Path::new("c.png")));
+        assert!(!is_video_path(Path::new("mp4")));
+    }
+
+    fn video(path: &str, duration: f64) -> NewImage {
+        NewImage {
+            file_path: path.to_string(),
+            width: Some(1280),
+            height: Some(720),
+            media_type: MediaType::Video,
+            duration_secs: Some(duration),
+            video_codec: Some("h264".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_by_media_type_and_duration() {
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        db.batch_add_images(vec![
+            NewImage {
+                file_path: "/library/photo.png".to_string(),
+                ..Default::default()
+            },
+            video("/library/short.mp4", 4.0),
+            video("/library/long.mkv", 95.5),
+        ])
+        .await
+        .unwrap();
+
+        let search = |query: SearchQuery| {
+            let db = &db;
+            async move {
+                let results = db.search_images(query).await.unwrap();
+                let mut names: Vec<String> =
+                    results.images.into_iter().map(|i| i.filename).collect();
+                names.sort();
+                names
+            }
+        };
+
+        let videos = db
+            .search_images(SearchQuery {
+                media_type: Some(MediaType::Video),
+                sort_by: Some(SortBy::Duration),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .images;
+        assert_eq!(videos.len(), 2);
+        assert_eq!(videos[0].filename, "long.mkv");
+        assert_eq!(videos[0].media_type, MediaType::Video);
+        assert_eq!(videos[0].duration_secs, Some(95.5));
+        assert_eq!(videos[0].video_codec.as_deref(), Some("h264"));
+
+        let images = search(SearchQuery {
+            media_type: Some(MediaType::Image),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(images, ["photo.png"]);
+
+        // Images have no duration, so a bound leaves them out
+        let long = search(SearchQuery {
+            min_duration: Some(10.0),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(long, ["long.mkv"]);
+        let short = search(SearchQuery {
+            max_duration: Some(10.0),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(short, ["short.mp4"]);
+        let between = search(SearchQuery {
+            min_duration: Some(1.0),
+            max_duration: Some(100.0),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(between, ["long.mkv", "short.mp4"]);
+
+        // Exports carry the video columns in both formats
+        for format in [ExportFormat::JsonLines, ExportFormat::Csv] {
+            let mut exported = Vec::new();
+            db.export_library(&mut exported, format, |_| {})
+                .await
+                .unwrap();
+            let target = Db::new("sqlite::memory:").await.unwrap();
+            target
+                .import_library(exported.as_slice(), format, ConflictPolicy::Skip, |_| {})
+                .await
+                .unwrap();
+            let videos = target
+                .search_images(SearchQuery {
+                    media_type: Some(MediaType::Video),
+                    min_duration: Some(10.0),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .images;
+            assert_eq!(videos.len(), 1, "{:?}", format);
+            assert_eq!(videos[0].video_codec.as_deref(), Some("h264"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_videos() {
+        let temp = tempdir().unwrap();
+        let cache = temp.path().join("cache");
+        let db = Db::new("sqlite::memory:").await.unwrap();
+        let photo = temp.path().join("photo.png");
+        image::RgbImage::new(10, 10).save(&photo).unwrap();
+
+        let clip = temp.path().join("clip.mp4");
+        let mut paths = vec![
+            photo.to_string_lossy().to_string(),
+            temp.path()
+                .join("missing.mp4")
+                .to_string_lossy()
+                .to_string(),
+        ];
+        if ffmpeg_available() {
+            let status = Command::new("ffmpeg")
+                .args(["-v", "error", "-f", "lavfi"])
+                .args(["-i", "testsrc=duration=2:size=160x120:rate=10"])
+                .arg(&clip)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            paths.push(clip.to_string_lossy().to_string());
+        }
+
+        let phases = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = phases.clone();
+        let summary = import_videos(
+            &db,
+            paths.clone(),
+            Some("Clips".to_string()),
+            &cache,
+            &CancellationToken::new(),
+            move |progress| seen.lock().unwrap().push(progress.phase),
+        )
+        .await
+        .unwrap();
+
+        // Neither the image nor the missing file is registered
+        let failed: Vec<usize> = summary.errors.iter().map(|e| e.index).collect();
+        assert_eq!(failed, [0, 1]);
+        assert!(summary.errors[0].error.contains("Not a video"));
+        assert!(phases.lock().unwrap().contains(&VideoImportPhase::Probing));
+
+        if !ffmpeg_available() {
+            assert!(summary.inserted.is_empty() && summary.posters.is_empty());
+            return;
+        }
+
+        assert_eq!(summary.inserted.len(), 1);
+        let record = db
+            .search_images(SearchQuery::default())
+            .await
+            .unwrap()
+            .images
+            .remove(0);
+        assert_eq!(record.id, summary.inserted[0]);
+        assert_eq!(record.media_type, MediaType::Video);
+        assert_eq!(record.group_name.as_deref(), Some("Clips"));
+        assert_eq!((record.width, record.height), (Some(160), Some(120)));
+        assert!((record.duration_secs.unwrap() - 2.0).abs() < 0.5);
+        assert!(record.video_codec.is_some());
+
+        // The poster is where `generate_thumbnails` will look for it
+        let poster = &summary.posters[0];
+        assert!(poster.error.is_none(), "{:?}", poster.error);
+        let thumb = image::open(poster.thumbnail.as_ref().unwrap()).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (POSTER_EDGE, 192));
+        let again = thumbnails::generate_disk_thumbnails(
+            &cache,
+            &paths[2..],
+            POSTER_EDGE,
+            ThumbnailFormat::Jpeg,
+            |_, _| {},
+        )
+        .unwrap();
+        assert!(again[0].cached);
+
+        // Found by the new filters
+        let found = db
+            .search_images(SearchQuery {
+                media_type: Some(MediaType::Video),
+                min_duration: Some(1.0),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(found.images.len(), 1);
+    }
+}
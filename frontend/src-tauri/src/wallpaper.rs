@@ -0,0 +1,487 @@
+//! Setting wallpapers per monitor, and the history of what was set so the
+//! wallpaper tab can re-apply an earlier choice
+
+use anyhow::{anyhow, bail, Context, Result};
+use base::core::wallpaper::{
+    is_known_style, is_slideshow_daemon, is_wlroots_session, set_wallpaper_native_core,
+    set_wallpaper_wlroots_core, WallpaperFit, FIT_STYLES, GNOME_STYLES, KDE_STYLES,
+    VIDEO_STYLE_PREFIX,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Entries kept in the history file; older ones are dropped
+pub const MAX_HISTORY: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorInfo {
+    /// Key for `path_map`: the monitor's geometry, which, unlike its name,
+    /// survives the connector being renamed
+    pub id: String,
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl MonitorInfo {
+    pub fn new(name: Option<&str>, width: u32, height: u32, x: i32, y: i32) -> Self {
+        MonitorInfo {
+            id: format!("{}x{}+{}+{}", width, height, x, y),
+            name: name.unwrap_or("Unknown").to_string(),
+            width,
+            height,
+            x,
+            y,
+        }
+    }
+}
+
+/// What was set on one monitor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorWallpaper {
+    pub monitor_id: String,
+    pub monitor_name: String,
+    pub path: String,
+}
+
+/// One successful application: every monitor it covered and the style used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WallpaperHistoryEntry {
+    pub monitors: Vec<MonitorWallpaper>,
+    pub style: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+/// Whatever actually changes the desktop background
+pub trait WallpaperCore {
+    /// Set each monitor id in `path_map` to its image, scaled as `style` says
+    fn apply(&self, path_map: &HashMap<String, String>, style: &str) -> Result<()>;
+}
+
+/// The platform's wallpaper setting: per monitor on Windows, macOS and
+/// wlroots compositors (swaybg or hyprpaper), and otherwise on Linux only
+/// GNOME, which takes one image for all monitors
+pub struct DesktopWallpaper;
+
+impl WallpaperCore for DesktopWallpaper {
+    fn apply(&self, path_map: &HashMap<String, String>, style: &str) -> Result<()> {
+        if cfg!(any(target_os = "windows", target_os = "macos")) {
+            return set_wallpaper_native_core(path_map, WallpaperFit::from_style(style))
+                .map_err(|e| anyhow!(e));
+        }
+        if is_wlroots_session() {
+            let fit = WallpaperFit::from_style(style);
+            return set_wallpaper_wlroots_core(path_map, |_| fit).map_err(|e| anyhow!(e));
+        }
+        let desktop_env = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        if !desktop_env.contains("GNOME") {
+            bail!("Wallpaper setting not fully implemented for this desktop environment");
+        }
+        let path = path_map
+            .values()
+            .next()
+            .ok_or_else(|| anyhow!("No images assigned to monitors"))?;
+        let file_uri = format!(
+            "file://{}",
+            Path::new(path)
+                .canonicalize()
+                .with_context(|| format!("Failed to resolve {}", path))?
+                .display()
+        );
+
+        Command::new("gsettings")
+            .args([
+                "set",
+                "org.gnome.desktop.background",
+                "picture-uri",
+                &file_uri,
+            ])
+            .output()
+            .context("Failed to run gsettings")?;
+        let _ = Command::new("gsettings")
+            .args([
+                "set",
+                "org.gnome.desktop.background",
+                "picture-uri-dark",
+                &file_uri,
+            ])
+            .output();
+        Ok(())
+    }
+}
+
+/// The history file, newest entry first
+pub struct WallpaperHistory {
+    path: PathBuf,
+    capacity: usize,
+}
+
+impl WallpaperHistory {
+    pub fn new(path: PathBuf) -> Self {
+        Self::with_capacity(path, MAX_HISTORY)
+    }
+
+    pub fn with_capacity(path: PathBuf, capacity: usize) -> Self {
+        WallpaperHistory { path, capacity }
+    }
+
+    /// Entries, newest first. A missing or unreadable file is an empty
+    /// history; losing it must not stop wallpapers from being set.
+    pub fn load(&self) -> Vec<WallpaperHistoryEntry> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(_) => return Vec::new(),
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            log::warn!(
+                "Ignoring unreadable wallpaper history {}: {}",
+                self.path.display(),
+                e
+            );
+            Vec::new()
+        })
+    }
+
+    /// Put `entry` first, dropping the oldest entries past the capacity
+    pub fn record(&self, entry: WallpaperHistoryEntry) -> Result<()> {
+        let mut entries = self.load();
+        entries.insert(0, entry);
+        entries.truncate(self.capacity);
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        // Through a temp file, so a crash mid-write keeps the old history
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&entries)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// Check a slideshow daemon config before it's written. The daemon falls back
+/// to its default for a style it doesn't know, so a misspelled entry in
+/// `monitor_styles` is rejected here instead.
+pub fn validate_slideshow_config(config: &serde_json::Value) -> Result<()> {
+    let Some(monitor_styles) = config.get("monitor_styles") else {
+        return Ok(());
+    };
+    let monitor_styles = monitor_styles
+        .as_object()
+        .ok_or_else(|| anyhow!("monitor_styles must map monitor ids to style names"))?;
+    for (monitor_id, style) in monitor_styles {
+        let style = style
+            .as_str()
+            .ok_or_else(|| anyhow!("Style for monitor {} must be a string", monitor_id))?;
+        if is_known_style(style) {
+            continue;
+        }
+        if let Some(known) = KDE_STYLES.iter().find(|s| s.eq_ignore_ascii_case(style)) {
+            bail!(
+                "Unknown style '{}' for monitor {}; did you mean '{}'?",
+                style,
+                monitor_id,
+                known
+            );
+        }
+        bail!(
+            "Unknown style '{}' for monitor {}; expected one of {}, {} (KDE), {} (GNOME) or {}<mode>",
+            style,
+            monitor_id,
+            FIT_STYLES.join(", "),
+            KDE_STYLES.join(", "),
+            GNOME_STYLES.join(", "),
+            VIDEO_STYLE_PREFIX
+        );
+    }
+    Ok(())
+}
+
+/// The slideshow daemon binary: installed next to this app's executable, or
+/// else looked up on `PATH`
+pub fn slideshow_daemon_binary() -> PathBuf {
+    let name = format!("slideshow_daemon{}", std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name(&name))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// PID of the slideshow daemon recorded in `pid_path`, if that process is
+/// still running and is the daemon rather than something that reused its PID
+pub fn running_slideshow_daemon(pid_path: &Path) -> Option<u32> {
+    let pid = std::fs::read_to_string(pid_path)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    is_slideshow_daemon(pid).then_some(pid)
+}
+
+/// Stop the daemon recorded in `pid_path`, if one is running, and remove the
+/// pid file it doesn't get to remove itself. Returns the PID that was stopped.
+pub fn stop_slideshow_daemon(pid_path: &Path) -> Result<Option<u32>> {
+    let Some(pid) = running_slideshow_daemon(pid_path) else {
+        return Ok(None);
+    };
+    let status = Command::new("kill")
+        .arg(pid.to_string())
+        .status()
+        .context("Failed to run kill")?;
+    if !status.success() {
+        bail!("Failed to stop the slideshow daemon (PID {})", pid);
+    }
+    let _ = std::fs::remove_file(pid_path);
+    Ok(Some(pid))
+}
+
+/// Apply `path_map` (monitor id to image) and, once that worked, record it
+pub fn set_with_history(
+    core: &dyn WallpaperCore,
+    history: &WallpaperHistory,
+    monitors: &[MonitorInfo],
+    path_map: &HashMap<String, String>,
+    style: &str,
+) -> Result<WallpaperHistoryEntry> {
+    if path_map.is_empty() {
+        bail!("No images assigned to monitors");
+    }
+    core.apply(path_map, style)?;
+
+    let mut applied: Vec<MonitorWallpaper> = path_map
+        .iter()
+        .map(|(id, path)| MonitorWallpaper {
+            monitor_id: id.clone(),
+            monitor_name: monitors
+                .iter()
+                .find(|m| &m.id == id)
+                .map(|m| m.name.clone())
+                .unwrap_or_else(|| id.clone()),
+            path: path.clone(),
+        })
+        .collect();
+    applied.sort_by(|a, b| a.monitor_id.cmp(&b.monitor_id));
+    let entry = WallpaperHistoryEntry {
+        monitors: applied,
+        style: style.to_string(),
+        applied_at: Utc::now(),
+    };
+    if let Err(e) = history.record(entry.clone()) {
+        log::warn!("Wallpaper set but not recorded: {:#}", e);
+    }
+    Ok(entry)
+}
+
+/// Set the `index`th history entry again (0 is the latest), recording it as
+/// a new entry. Monitors are matched by id, then by name; ones no longer
+/// connected are skipped.
+pub fn reapply(
+    core: &dyn WallpaperCore,
+    history: &WallpaperHistory,
+    monitors: &[MonitorInfo],
+    index: usize,
+) -> Result<WallpaperHistoryEntry> {
+    let entry = history
+        .load()
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| anyhow!("No wallpaper history entry {}", index))?;
+
+    let path_map: HashMap<String, String> = entry
+        .monitors
+        .iter()
+        .filter_map(|applied| {
+            let monitor = monitors
+                .iter()
+                .find(|m| m.id == applied.monitor_id)
+                .or_else(|| monitors.iter().find(|m| m.name == applied.monitor_name))?;
+            Some((monitor.id.clone(), applied.path.clone()))
+        })
+        .collect();
+    if path_map.is_empty() {
+        bail!(
+            "None of the monitors in history entry {} are connected",
+            index
+        );
+    }
+    set_with_history(core, history, monitors, &path_map, &entry.style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use tempfile::tempdir;
+
+    #[derive(Default)]
+    struct MockCore {
+        applied: RefCell<Vec<(HashMap<String, String>, String)>>,
+        fail: bool,
+    }
+
+    impl WallpaperCore for MockCore {
+        fn apply(&self, path_map: &HashMap<String, String>, style: &str) -> Result<()> {
+            if self.fail {
+                bail!("No desktop");
+            }
+            self.applied
+                .borrow_mut()
+                .push((path_map.clone(), style.to_string()));
+            Ok(())
+        }
+    }
+
+    fn paths(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(id, path)| (id.to_string(), path.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_history_rotation() {
+        let dir = tempdir().unwrap();
+        let history = WallpaperHistory::with_capacity(dir.path().join("history.json"), 3);
+        let monitors = [MonitorInfo::new(Some("DP-1"), 1920, 1080, 0, 0)];
+        assert_eq!(monitors[0].id, "1920x1080+0+0");
+        assert!(history.load().is_empty());
+
+        let core = MockCore::default();
+        for i in 0..5 {
+            let map = paths(&[("1920x1080+0+0", &format!("/img/{}.png", i))]);
+            set_with_history(&core, &history, &monitors, &map, "Fill").unwrap();
+        }
+        let entries = history.load();
+        let newest: Vec<&str> = entries
+            .iter()
+            .map(|e| e.monitors[0].path.as_str())
+            .collect();
+        assert_eq!(newest, ["/img/4.png", "/img/3.png", "/img/2.png"]);
+        assert_eq!(entries[0].monitors[0].monitor_name, "DP-1");
+        assert_eq!(entries[0].style, "Fill");
+
+        // A failed application isn't recorded
+        let failing = MockCore {
+            fail: true,
+            ..MockCore::default()
+        };
+        let map = paths(&[("1920x1080+0+0", "/img/x.png")]);
+        assert!(set_with_history(&failing, &history, &monitors, &map, "Fill").is_err());
+        assert_eq!(history.load().len(), 3);
+        assert_eq!(history.load()[0].monitors[0].path, "/img/4.png");
+
+        // A corrupt file starts a fresh history rather than failing
+        std::fs::write(dir.path().join("history.json"), "{oops").unwrap();
+        assert!(history.load().is_empty());
+        set_with_history(&core, &history, &monitors, &map, "Fit").unwrap();
+        assert_eq!(history.load().len(), 1);
+    }
+
+    #[test]
+    fn test_reapply() {
+        let dir = tempdir().unwrap();
+        let history = WallpaperHistory::new(dir.path().join("history.json"));
+        let core = MockCore::default();
+        let before = [
+            MonitorInfo::new(Some("DP-1"), 2560, 1440, 0, 0),
+            MonitorInfo::new(Some("HDMI-1"), 1920, 1080, 2560, 0),
+        ];
+        let map = paths(&[
+            ("2560x1440+0+0", "/img/left.png"),
+            ("1920x1080+2560+0", "/img/right.png"),
+        ]);
+        set_with_history(&core, &history, &before, &map, "Span").unwrap();
+        let single = paths(&[("2560x1440+0+0", "/img/other.png")]);
+        set_with_history(&core, &history, &before, &single, "Fill").unwrap();
+
+        // The left one renamed, the right one resized
+        let after = [
+            MonitorInfo::new(Some("DP-2"), 2560, 1440, 0, 0),
+            MonitorInfo::new(Some("HDMI-1"), 3840, 2160, 2560, 0),
+        ];
+        let entry = reapply(&core, &history, &after, 1).unwrap();
+        assert_eq!(entry.style, "Span");
+        let (applied, style) = core.applied.borrow().last().cloned().unwrap();
+        assert_eq!(style, "Span");
+        assert_eq!(
+            applied,
+            paths(&[
+                ("2560x1440+0+0", "/img/left.png"),
+                ("3840x2160+2560+0", "/img/right.png"),
+            ])
+        );
+
+        // Re-applying records a new entry on top
+        let entries = history.load();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].monitors[0].monitor_name, "DP-2");
+
+        assert!(reapply(&core, &history, &after, 10).is_err());
+        let unplugged = [MonitorInfo::new(Some("eDP-1"), 1280, 800, 0, 0)];
+        assert!(reapply(&core, &history, &unplugged, 0).is_err());
+    }
+
+    #[test]
+    fn test_validate_slideshow_config() {
+        assert!(validate_slideshow_config(&serde_json::json!({"style": "Fill"})).is_ok());
+        let config = serde_json::json!({
+            "monitor_styles": {
+                "0": "Fill",
+                "1": "Centered",
+                "2": "zoom",
+                "3": "SmartVideoWallpaper::Keep Proportions",
+            }
+        });
+        assert!(validate_slideshow_config(&config).is_ok());
+
+        let err =
+            validate_slideshow_config(&serde_json::json!({"monitor_styles": {"1": "Centred"}}))
+                .unwrap_err()
+                .to_string();
+        assert!(err.contains("'Centred' for monitor 1"), "{}", err);
+        assert!(err.contains("Centered"), "{}", err);
+        let err = validate_slideshow_config(
+            &serde_json::json!({"monitor_styles": {"1": "center tiled"}}),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("did you mean 'Center Tiled'"), "{}", err);
+        assert!(
+            validate_slideshow_config(&serde_json::json!({"monitor_styles": ["Fill"]})).is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_stop_slideshow_daemon() {
+        let dir = tempdir().unwrap();
+        let pid_path = dir.path().join(".slideshow.pid");
+        assert_eq!(running_slideshow_daemon(&pid_path), None);
+
+        // A PID that's alive but isn't the daemon is left alone
+        std::fs::write(&pid_path, std::process::id().to_string()).unwrap();
+        assert_eq!(running_slideshow_daemon(&pid_path), None);
+        assert_eq!(stop_slideshow_daemon(&pid_path).unwrap(), None);
+
+        // Something named like the daemon, standing in for it
+        let daemon = dir.path().join("slideshow_daemon");
+        std::fs::copy("/bin/sleep", &daemon).unwrap();
+        let mut child = Command::new(&daemon).arg("30").spawn().unwrap();
+        std::fs::write(&pid_path, child.id().to_string()).unwrap();
+        assert_eq!(running_slideshow_daemon(&pid_path), Some(child.id()));
+
+        assert_eq!(stop_slideshow_daemon(&pid_path).unwrap(), Some(child.id()));
+        assert!(!child.wait().unwrap().success());
+        assert!(!pid_path.exists());
+    }
+}
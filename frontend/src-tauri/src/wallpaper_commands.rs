@@ -1,61 +1,82 @@
-use serde::Serialize;
+use crate::wallpaper::{
+    self, DesktopWallpaper, MonitorInfo, WallpaperCore, WallpaperHistory, WallpaperHistoryEntry,
+};
 use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::process::Command;
 use tauri::Manager;
 
-#[derive(Serialize)]
-pub struct MonitorInfo {
-    pub name: String,
-    pub width: u32,
-    pub height: u32,
-    pub x: i32,
-    pub y: i32,
-}
-
-fn get_slideshow_config_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
-    let config_dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+/// Where the slideshow daemon keeps its config and pid file
+fn get_slideshow_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let config_dir = app
+        .path()
+        .home_dir()
+        .map_err(|e| e.to_string())?
+        .join(".image-toolkit");
 
     // Ensure the config directory exists
     std::fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
 
-    Ok(config_dir.join("slideshow_config.json"))
+    Ok(config_dir)
 }
 
+/// The file the slideshow daemon reads, and polls for changes while it runs
+fn get_slideshow_config_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    Ok(get_slideshow_dir(app)?.join(".slideshow_config.json"))
+}
+
+fn wallpaper_history(app: &tauri::AppHandle) -> Result<WallpaperHistory, String> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+    Ok(WallpaperHistory::new(
+        data_dir.join("wallpaper_history.json"),
+    ))
+}
+
+/// Connected monitors; each one's `id` is the key to use in `path_map`
 #[tauri::command]
 pub fn get_monitors(app: tauri::AppHandle) -> Result<Vec<MonitorInfo>, String> {
     let monitors = app.available_monitors().map_err(|e| e.to_string())?;
-    let mut info = Vec::new();
-    for monitor in monitors {
-        let size = monitor.size();
-        let pos = monitor.position();
-        info.push(MonitorInfo {
-            name: monitor
-                .name()
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| "Unknown".to_string()),
-            width: size.width,
-            height: size.height,
-            x: pos.x,
-            y: pos.y,
-        });
-    }
-    Ok(info)
+    Ok(monitors
+        .iter()
+        .map(|monitor| {
+            let size = monitor.size();
+            let pos = monitor.position();
+            MonitorInfo::new(
+                monitor.name().map(|s| s.as_str()),
+                size.width,
+                size.height,
+                pos.x,
+                pos.y,
+            )
+        })
+        .collect())
 }
 
+/// Write the slideshow daemon's config, after checking its `monitor_styles`
 #[tauri::command]
 pub fn update_slideshow_config(
     app: tauri::AppHandle,
     config: serde_json::Value,
 ) -> Result<(), String> {
+    wallpaper::validate_slideshow_config(&config).map_err(|e| e.to_string())?;
     let path = get_slideshow_config_path(&app)?;
     let content = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
     std::fs::write(path, content).map_err(|e| e.to_string())?;
     Ok(())
 }
 
+/// Start or stop the slideshow. Stopping kills the running daemon, and
+/// starting leaves one that's already running alone. With `advance_now` the
+/// running daemon is asked to show the next wallpapers right away instead.
 #[tauri::command]
-pub fn toggle_slideshow_daemon(app: tauri::AppHandle, running: bool) -> Result<(), String> {
+pub fn toggle_slideshow_daemon(
+    app: tauri::AppHandle,
+    running: bool,
+    advance_now: Option<bool>,
+) -> Result<(), String> {
     // 1. Update config file 'running' field
     let path = get_slideshow_config_path(&app)?;
     let mut config: serde_json::Value = if path.exists() {
@@ -65,18 +86,22 @@ pub fn toggle_slideshow_daemon(app: tauri::AppHandle, running: bool) -> Result<(
         serde_json::json!({})
     };
     config["running"] = serde_json::json!(running);
+    let advance_now = running && advance_now.unwrap_or(false);
+    if advance_now {
+        config["advance_now"] = serde_json::json!(true);
+    }
     let content = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
     std::fs::write(&path, content).map_err(|e| e.to_string())?;
 
-    // 2. Start process if running
-    if running {
-        // We assume 'python' is in path and we are in project root or can find main.py
-        // In a real app, we'd use sidecars or properly bundled python.
-        Command::new("python")
-            .arg("main.py")
-            .arg("slideshow")
+    // 2. Start or stop the process
+    let pid_path = get_slideshow_dir(&app)?.join(".slideshow.pid");
+    if !running {
+        wallpaper::stop_slideshow_daemon(&pid_path).map_err(|e| format!("{:#}", e))?;
+    } else if !advance_now && wallpaper::running_slideshow_daemon(&pid_path).is_none() {
+        let binary = wallpaper::slideshow_daemon_binary();
+        Command::new(&binary)
             .spawn()
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| format!("Failed to start {}: {}", binary.display(), e))?;
     }
     Ok(())
 }
@@ -85,47 +110,58 @@ pub fn toggle_slideshow_daemon(app: tauri::AppHandle, running: bool) -> Result<(
 pub fn set_wallpaper(
     path_map: HashMap<String, String>,
     _monitors: Vec<usize>,
-    _style: String,
+    style: String,
 ) -> Result<(), String> {
-    // TODO: Implement wallpaper setting once base library is refactored
-    log::warn!("Wallpaper setting not yet fully implemented in Tauri backend");
-
-    // Check desktop environment
-    let desktop_env = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
-
-    if desktop_env.contains("GNOME") {
-        // Try to set via gsettings for GNOME
-        if let Some(path) = path_map.values().next() {
-            let file_uri = format!(
-                "file://{}",
-                Path::new(path)
-                    .canonicalize()
-                    .map_err(|e| e.to_string())?
-                    .display()
-            );
-
-            let _ = Command::new("gsettings")
-                .args(&[
-                    "set",
-                    "org.gnome.desktop.background",
-                    "picture-uri",
-                    &file_uri,
-                ])
-                .output()
-                .map_err(|e| e.to_string())?;
+    DesktopWallpaper
+        .apply(&path_map, &style)
+        .map_err(|e| format!("Failed to set wallpaper: {:#}", e))
+}
 
-            let _ = Command::new("gsettings")
-                .args(&[
-                    "set",
-                    "org.gnome.desktop.background",
-                    "picture-uri-dark",
-                    &file_uri,
-                ])
-                .output();
+/// Set each monitor id in `path_map` to its image and record it in the
+/// wallpaper history
+#[tauri::command]
+pub fn set_wallpaper_with_history(
+    app: tauri::AppHandle,
+    path_map: HashMap<String, String>,
+    style: String,
+) -> Result<WallpaperHistoryEntry, String> {
+    let monitors = get_monitors(app.clone())?;
+    wallpaper::set_with_history(
+        &DesktopWallpaper,
+        &wallpaper_history(&app)?,
+        &monitors,
+        &path_map,
+        &style,
+    )
+    .map_err(|e| format!("Failed to set wallpaper: {:#}", e))
+}
 
-            return Ok(());
-        }
+/// Wallpapers set so far, newest first, at most `limit` of them
+#[tauri::command]
+pub fn get_wallpaper_history(
+    app: tauri::AppHandle,
+    limit: Option<usize>,
+) -> Result<Vec<WallpaperHistoryEntry>, String> {
+    let mut entries = wallpaper_history(&app)?.load();
+    if let Some(limit) = limit {
+        entries.truncate(limit);
     }
+    Ok(entries)
+}
 
-    Err("Wallpaper setting not fully implemented for this desktop environment".to_string())
+/// Set the wallpapers of history entry `history_index` (0 is the latest)
+/// again, on whichever of its monitors are still connected
+#[tauri::command]
+pub fn reapply_wallpaper(
+    app: tauri::AppHandle,
+    history_index: usize,
+) -> Result<WallpaperHistoryEntry, String> {
+    let monitors = get_monitors(app.clone())?;
+    wallpaper::reapply(
+        &DesktopWallpaper,
+        &wallpaper_history(&app)?,
+        &monitors,
+        history_index,
+    )
+    .map_err(|e| format!("Failed to re-apply wallpaper: {:#}", e))
 }
@@ -0,0 +1,117 @@
+use crate::tasks::{TaskContext, TaskManager};
+use base::web::{
+    self,
+    cloud::{oauth::RefreshedToken, sync::SyncStats},
+    progress::ProgressSink,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, State};
+
+/// Payload of `web-status`, `web-error`, `web-image-saved` and
+/// `web-token-refreshed` events; for `web-image-saved` the message is the
+/// saved file's path, for `web-token-refreshed` the new tokens as JSON
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebTaskMessage {
+    pub task_id: String,
+    pub message: String,
+}
+
+/// Forwards a crawl or sync's reports to the webview, tagged with the task
+/// id, and stops it when the task is cancelled
+struct TaskSink<'a> {
+    app: AppHandle,
+    ctx: &'a TaskContext,
+}
+
+impl TaskSink<'_> {
+    fn emit(&self, event: &str, message: &str) {
+        let _ = self.app.emit(
+            event,
+            WebTaskMessage {
+                task_id: self.ctx.task_id().to_string(),
+                message: message.to_string(),
+            },
+        );
+    }
+}
+
+impl ProgressSink for TaskSink<'_> {
+    fn on_status(&self, message: &str) {
+        self.emit("web-status", message);
+    }
+
+    fn on_error(&self, message: &str) {
+        self.emit("web-error", message);
+    }
+
+    fn on_image_saved(&self, path: &str) {
+        self.emit("web-image-saved", path);
+    }
+
+    fn on_transfer_progress(&self, name: &str, sent: u64, total: u64) {
+        let percent = (sent * 100 / total.max(1)) as u32;
+        self.ctx.progress(percent, format!("Uploading {}", name));
+    }
+
+    fn on_token_refreshed(&self, token: &RefreshedToken) {
+        if let Ok(json) = serde_json::to_string(token) {
+            self.emit("web-token-refreshed", &json);
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.ctx.token().is_cancelled()
+    }
+}
+
+/// Crawl `crawler_name` (danbooru, gelbooru, sankaku, e621, derpibooru or
+/// generic) with the crawler tab's `config` and return how many images were
+/// downloaded. Runs as a `board_crawl` task that `cancel_task` can stop.
+#[tauri::command]
+pub async fn run_board_crawler(
+    app: AppHandle,
+    manager: State<'_, TaskManager>,
+    crawler_name: String,
+    config: Value,
+    task_id: Option<String>,
+) -> Result<u32, String> {
+    let metadata = json!({ "crawler": crawler_name, "tags": config.get("tags") });
+    let task = manager.start_task_for(task_id, "board_crawl", metadata)?;
+    let sink_app = app.clone();
+    task.run(app, move |ctx| {
+        let sink = TaskSink { app: sink_app, ctx };
+        let downloaded = web::crawl_board(&crawler_name, &config, &sink)?;
+        ctx.token().check()?;
+        Ok(downloaded)
+    })
+    .await
+    .map_err(|e| format!("Failed to run crawler: {}", e))
+}
+
+/// Sync `config`'s local folder with `provider` (dropbox, google_drive,
+/// one_drive or local; base is built without sftp) and return what was
+/// transferred. Runs as a `cloud_sync` task that `cancel_task` can stop.
+#[tauri::command]
+pub async fn run_cloud_sync(
+    app: AppHandle,
+    manager: State<'_, TaskManager>,
+    provider: String,
+    config: Value,
+    task_id: Option<String>,
+) -> Result<SyncStats, String> {
+    // Not the whole config: it holds the provider's credentials
+    let metadata = json!({ "provider": provider, "localPath": config.get("local_path") });
+    let task = manager.start_task_for(task_id, "cloud_sync", metadata)?;
+    let sink_app = app.clone();
+    task.run(app, move |ctx| {
+        let sink = TaskSink { app: sink_app, ctx };
+        let stats = web::sync_cloud(&provider, &config, &sink);
+        // A cancelled sync fails; report it as cancelled instead
+        ctx.token().check()?;
+        Ok(stats?)
+    })
+    .await
+    .map_err(|e| format!("Failed to sync with cloud: {}", e))
+}
@@ -122,11 +122,22 @@ export const SettingsDialog: React.FC<SettingsDialogProps> = ({ onClose }) => {
     }
   };
 
-  const handleResetSettings = () => {
-    setSelectedTheme('dark');
-    setNewPassword('');
-    setConfirmPassword('');
-    setMessage({ type: 'success', text: 'Settings reset to defaults' });
+  const handleResetSettings = async () => {
+    try {
+      const settings = await invoke<any>('reset_settings', {
+        accountName: vault?.accountName,
+      });
+      setSelectedTheme(settings.theme);
+      setTheme(settings.theme);
+      setTabConfigurations(settings.tab_configurations || {});
+      setProfiles(settings.system_preference_profiles || {});
+      setNewPassword('');
+      setConfirmPassword('');
+      setMessage({ type: 'success', text: 'Settings reset to defaults' });
+    } catch (err: any) {
+      console.error('Reset error:', err);
+      setMessage({ type: 'error', text: err.message || String(err) || 'Failed to reset settings' });
+    }
   };
 
   const handleSaveProfile = () => {
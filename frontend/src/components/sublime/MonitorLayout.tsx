@@ -2,7 +2,9 @@ import React, { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { MonitorDropWidget } from "./MonitorDropWidget";
 
-interface MonitorInfo {
+export interface MonitorInfo {
+  /** Stable key for path maps, from the monitor's geometry */
+  id: string;
   name: string;
   width: number;
   height: number;
@@ -12,10 +14,12 @@ interface MonitorInfo {
 
 interface MonitorLayoutProps {
   onMonitorImagesChange: (pathMap: Record<string, string>) => void;
+  onMonitorsLoaded?: (monitors: MonitorInfo[]) => void;
 }
 
 export const MonitorLayout: React.FC<MonitorLayoutProps> = ({
   onMonitorImagesChange,
+  onMonitorsLoaded,
 }) => {
   const [monitors, setMonitors] = useState<MonitorInfo[]>([]);
   const [pathMap, setPathMap] = useState<Record<string, string>>({});
@@ -25,6 +29,7 @@ export const MonitorLayout: React.FC<MonitorLayoutProps> = ({
       try {
         const result: MonitorInfo[] = await invoke("get_monitors");
         setMonitors(result);
+        onMonitorsLoaded?.(result);
       } catch (err) {
         console.error("Failed to fetch monitors:", err);
       }
@@ -47,11 +52,11 @@ export const MonitorLayout: React.FC<MonitorLayoutProps> = ({
 
   return (
     <div className="flex flex-wrap gap-6 justify-center p-6 bg-gray-100 dark:bg-gray-900/30 rounded-xl border border-gray-200 dark:border-gray-800">
-      {monitors.map((m, idx) => (
-        <div key={idx} className="flex flex-col items-center">
+      {monitors.map((m) => (
+        <div key={m.id} className="flex flex-col items-center">
           <MonitorDropWidget
-            monitor={{ id: idx.toString(), name: m.name }}
-            monitorId={idx.toString()}
+            monitor={{ id: m.id, name: m.name }}
+            monitorId={m.id}
             onImageDropped={handleImageDropped}
             onDoubleClicked={() => {}}
             onClearRequested={handleClear}
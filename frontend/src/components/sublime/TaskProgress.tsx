@@ -15,7 +15,9 @@ export const TaskProgress: React.FC = () => {
   const isDark = preferences.theme === 'dark';
   const taskArray = Array.from(tasks.values());
   const activeTasks = taskArray.filter((t) => t.status === 'running');
-  const completedTasks = taskArray.filter((t) => t.status === 'completed' || t.status === 'failed');
+  const completedTasks = taskArray.filter(
+    (t) => t.status === 'completed' || t.status === 'failed' || t.status === 'cancelled',
+  );
 
   // Don't show panel if no tasks
   if (taskArray.length === 0) {
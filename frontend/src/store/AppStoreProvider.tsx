@@ -176,10 +176,21 @@ export const AppStoreProvider: React.FC<AppStoreProviderProps> = ({ children })
       });
     });
 
+    // Listen for tasks stopped with cancel_task
+    const unlistenCancelled = listen<any>('task-cancelled', (event) => {
+      const { taskId } = event.payload;
+      updateTask(taskId, {
+        status: 'cancelled',
+        message: 'Cancelled',
+        endTime: Date.now(),
+      });
+    });
+
     // Cleanup listeners on unmount
     return () => {
       unlistenProgress.then((fn) => fn());
       unlistenComplete.then((fn) => fn());
+      unlistenCancelled.then((fn) => fn());
     };
   }, [updateTask]);
 
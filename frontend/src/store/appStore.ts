@@ -15,7 +15,7 @@ import { createContext, useContext } from 'react';
 export interface BackgroundTask {
   id: string;
   type: 'conversion' | 'merge' | 'video_extraction' | 'database_scan' | 'crawl' | 'sync';
-  status: 'pending' | 'running' | 'completed' | 'failed';
+  status: 'pending' | 'running' | 'completed' | 'failed' | 'cancelled';
   progress: number; // 0-100
   message: string;
   startTime: number;
@@ -1,9 +1,13 @@
 import React, { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import { MonitorLayout } from "../../components/sublime/MonitorLayout";
+import {
+  MonitorLayout,
+  MonitorInfo,
+} from "../../components/sublime/MonitorLayout";
 import {
   Play,
   Square,
+  SkipForward,
   Settings2,
   Clock,
   Monitor as MonitorIcon,
@@ -13,6 +17,7 @@ import { WallpaperGallery } from "../../components/sublime/WallpaperGallery";
 
 const WallpaperTab: React.FC = () => {
   const [monitorPaths, setMonitorPaths] = useState<Record<string, string>>({});
+  const [monitors, setMonitors] = useState<MonitorInfo[]>([]);
   const [style, setStyle] = useState("Fill");
   const [interval, setIntervalValue] = useState(300);
   const [isRunning, setIsRunning] = useState(false);
@@ -42,9 +47,8 @@ const WallpaperTab: React.FC = () => {
     }
     try {
       setStatus("Setting wallpaper...");
-      await invoke("set_wallpaper", {
+      await invoke("set_wallpaper_with_history", {
         pathMap: monitorPaths,
-        monitors: Object.keys(monitorPaths).map((k) => parseInt(k)),
         style,
       });
       setStatus("Wallpaper set successfully!");
@@ -76,7 +80,17 @@ const WallpaperTab: React.FC = () => {
           {} as Record<string, string[]>,
         ),
         current_paths: monitorPaths,
-        monitor_geometries: {}, // Could be populated if we want precise layout
+        // Lets the daemon match monitor ids to desktops by position
+        monitor_geometries: monitors.reduce(
+          (acc, m) => {
+            acc[m.id] = { x: m.x, y: m.y, width: m.width, height: m.height };
+            return acc;
+          },
+          {} as Record<
+            string,
+            { x: number; y: number; width: number; height: number }
+          >,
+        ),
       };
 
       await invoke("update_slideshow_config", { config });
@@ -95,6 +109,20 @@ const WallpaperTab: React.FC = () => {
     }
   };
 
+  const handleNextWallpaper = async () => {
+    try {
+      await invoke("toggle_slideshow_daemon", {
+        running: true,
+        advanceNow: true,
+      });
+      setCountdown(interval);
+      setStatus("Showing the next wallpaper");
+    } catch (err) {
+      console.error(err);
+      setStatus(`Error: ${err}`);
+    }
+  };
+
   return (
     <div className="flex flex-col h-full bg-gray-50 dark:bg-gray-900 overflow-hidden">
       {/* Header */}
@@ -117,7 +145,10 @@ const WallpaperTab: React.FC = () => {
           <div className="flex items-center gap-2 mb-4 text-sm font-semibold text-gray-500 uppercase tracking-wider">
             Monitor Discovery & Assignment
           </div>
-          <MonitorLayout onMonitorImagesChange={handleMonitorImagesChange} />
+          <MonitorLayout
+            onMonitorImagesChange={handleMonitorImagesChange}
+            onMonitorsLoaded={setMonitors}
+          />
           <div className="mt-2 flex items-start gap-2 text-xs text-gray-400">
             <Info size={14} className="mt-0.5 flex-shrink-0" />
             <p>
@@ -213,6 +244,15 @@ const WallpaperTab: React.FC = () => {
                   </>
                 )}
               </button>
+
+              {isRunning && (
+                <button
+                  onClick={handleNextWallpaper}
+                  className="w-full py-2.5 rounded-lg font-bold text-violet-600 dark:text-violet-300 border border-violet-300 dark:border-violet-700 hover:bg-violet-50 dark:hover:bg-violet-900/30 transition flex items-center justify-center gap-2"
+                >
+                  <SkipForward size={18} /> Next Wallpaper
+                </button>
+              )}
             </div>
           </div>
         </div>
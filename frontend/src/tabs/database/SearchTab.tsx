@@ -115,26 +115,33 @@ const SearchTab = forwardRef<SearchTabHandle, SearchTabProps>(
 
       try {
         // Call Tauri backend to search database
-        const results = await invoke<any[]>("search_images", {
-          query: {
-            group_name: group || null,
-            subgroup_name: subgroup || null,
-            filename_pattern: filename || null,
-            input_formats: formats.size > 0 ? Array.from(formats) : null,
-            tags: selectedTags.size > 0 ? Array.from(selectedTags) : null,
-            limit: 500, // Fetch more results for better UX
+        const results = await invoke<{ images: any[]; total_count: number }>(
+          "search_images",
+          {
+            query: {
+              group_name: group || null,
+              subgroup_name: subgroup || null,
+              filename_pattern: filename || null,
+              input_formats: formats.size > 0 ? Array.from(formats) : null,
+              tags: selectedTags.size > 0 ? Array.from(selectedTags) : null,
+              limit: 500, // Fetch more results for better UX
+            },
           },
-        });
+        );
 
         // Convert database results to GalleryItem format
-        const galleryItems: GalleryItem[] = results.map((img) => ({
+        const galleryItems: GalleryItem[] = results.images.map((img) => ({
           path: img.file_path,
           thumbnail: `file://${img.file_path}`, // Use actual image path
           isVideo: false,
         }));
 
         found.actions.setGalleryItems(galleryItems);
-        showModal(`Found ${galleryItems.length} images`, "success", 2000);
+        showModal(
+          `Found ${results.total_count} images (showing ${galleryItems.length})`,
+          "success",
+          2000,
+        );
       } catch (err: any) {
         console.error("Search error:", err);
         showModal(err.message || "Search failed", "error");
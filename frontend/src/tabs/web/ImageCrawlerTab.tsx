@@ -63,6 +63,8 @@ const ImageCrawlerTab = forwardRef((props: ImageCrawlerTabProps, ref) => {
           <option>Image Board Crawler (Danbooru API)</option>
           <option>Image Board Crawler (Gelbooru API)</option>
           <option>Image Board Crawler (Sankaku Complex API)</option>
+          <option>Image Board Crawler (e621 API)</option>
+          <option>Image Board Crawler (Derpibooru API)</option>
         </select>
       </div>
 
@@ -3,5 +3,7 @@ from PySide6.QtCore import QObject, Signal
 
 class CloudDriveSyncWorkerSignals(QObject):
     status_update = Signal(str)
+    # file name, fraction of it uploaded
+    transfer_progress = Signal(str, float)
     # success, message, is_dry_run
     sync_finished = Signal(bool, str, bool)
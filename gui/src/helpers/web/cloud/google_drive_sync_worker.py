@@ -38,6 +38,10 @@ class GoogleDriveSyncWorker(QRunnable):
             timestamp = time.strftime("[%H:%M:%S]")
             self.signals.status_update.emit(f"{timestamp} {message}")
 
+    def _progress(self, name: str, sent: int, total: int):
+        if self._is_running:
+            self.signals.transfer_progress.emit(name, sent / total if total else 1.0)
+
     def run(self):
         self.signals.status_update.emit("\n" + "=" * 50)
         self._log("--- Google Drive Sync Initiated ---")
@@ -55,6 +59,7 @@ class GoogleDriveSyncWorker(QRunnable):
                 "drive_destination_folder_name": self.remote_path,
                 "dry_run": self.dry_run,
                 "logger": self._log,
+                "progress": self._progress,
                 "action_local_orphans": self.action_local,
                 "action_remote_orphans": self.action_remote,
             }
@@ -33,6 +33,10 @@ class OneDriveSyncWorker(QRunnable):
             timestamp = time.strftime("[%H:%M:%S]")
             self.signals.status_update.emit(f"{timestamp} {message}")
 
+    def _progress(self, name: str, sent: int, total: int):
+        if self._is_running:
+            self.signals.transfer_progress.emit(name, sent / total if total else 1.0)
+
     def run(self):
         self.signals.status_update.emit("\n" + "=" * 50)
         self._log("--- OneDrive Sync Initiated ---")
@@ -51,6 +55,7 @@ class OneDriveSyncWorker(QRunnable):
                 client_id=client_id,
                 dry_run=self.dry_run,
                 logger=self._log,
+                progress=self._progress,
                 action_local_orphans=self.action_local,
                 action_remote_orphans=self.action_remote,
             )
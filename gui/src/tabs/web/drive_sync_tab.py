@@ -538,6 +538,7 @@ class DriveSyncTab(QWidget):
             self.current_worker = OneDriveSyncWorker(**common_args)
 
         self.current_worker.signals.status_update.connect(self.handle_status_update) # pyrefly: ignore [missing-attribute]
+        self.current_worker.signals.transfer_progress.connect(self.handle_transfer_progress) # pyrefly: ignore [missing-attribute]
         self.current_worker.signals.sync_finished.connect(self.handle_sync_finished) # pyrefly: ignore [missing-attribute]
 
         QThreadPool.globalInstance().start(self.current_worker) # pyrefly: ignore [no-matching-overload]
@@ -616,6 +617,11 @@ class DriveSyncTab(QWidget):
         self._log_text += msg + "\n"
         self.qml_log_changed.emit()
 
+    @Slot(str, float)
+    def handle_transfer_progress(self, name: str, fraction: float):
+        self._progress_value = fraction
+        self.qml_progress_changed.emit()
+
     # ------------------------------------------------------------------ #
     #                           FINISHED                               #
     # ------------------------------------------------------------------ #
@@ -1,3 +1,5 @@
+import json
+
 from PySide6.QtCore import Property, QPoint, Qt, Signal, Slot
 from PySide6.QtGui import QAction
 from PySide6.QtWidgets import (
@@ -128,6 +130,11 @@ class WebRequestsTab(QWidget):
                 "Print Response Headers",
                 "Print Response Content (Text)",
                 "Save Response Content (Binary)",
+                "Assert Status Equals",
+                "Assert Body Contains",
+                "Assert JSON Path Equals",
+                "Extract JSON Path to Variable",
+                "Extract Regex to Variable",
             ]
         )
         self.action_param_input = QLineEdit()
@@ -349,10 +356,36 @@ class WebRequestsTab(QWidget):
         self.run_button.show()
         self.cancel_button.hide()
         self.progress_bar.hide()
-        self.status_label.setText(message)
 
-        if "cancelled" not in message.lower() and "Error" not in message:
+        # A finished run reports back as JSON; anything else is plain text
+        try:
+            report = json.loads(message)
+        except ValueError:
+            report = None
+        if not isinstance(report, dict):
+            self.status_label.setText(message)
+            if "cancelled" not in message.lower() and "Error" not in message:
+                QMessageBox.information(self, "Success", "All requests finished!")
+            return
+
+        summary = (
+            f"{report.get('succeeded', 0)} passed, {report.get('failed', 0)} failed"
+        )
+        if report.get("status") == "cancelled":
+            self.status_label.setText(f"Cancelled ({summary}).")
+            return
+        self.status_label.setText(f"All requests finished ({summary}).")
+        if report.get("passed"):
             QMessageBox.information(self, "Success", "All requests finished!")
+        else:
+            failures = [
+                f"Request {r.get('request')}: {'; '.join(r.get('errors', []))}"
+                for r in report.get("requests", [])
+                if not r.get("passed")
+            ]
+            QMessageBox.warning(
+                self, "Requests failed", f"{summary}.\n\n" + "\n".join(failures)
+            )
 
     # --- Config Management ---
 